@@ -0,0 +1,156 @@
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use chrono::{Duration, Timelike, Utc};
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use rocket::http::{ContentType, Cookie, Header, Status};
+use rocket::local::blocking::{Client, LocalRequest};
+use rocket::serde::json;
+use rocket_db_pools::Database;
+use rocket_sqlx::csrf::{CSRF_COOKIE, CSRF_HEADER};
+use rocket_sqlx::{api, clock, db, error, events, handlers, util::*};
+
+/// Posts the benchmark user starts with, so that list pages are read from a realistically sized
+/// table.
+const SEEDED_POSTS: usize = 10_000;
+const PAGE_SIZE: usize = 50;
+const BULK_SIZE: usize = 100;
+const CSRF_TOKEN: &str = "bench-csrf-token";
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Builds an instance on a fresh in-memory database with the response cache off, so that every
+/// request reaches the query layer.
+fn client_build() -> Client {
+    unsafe {
+        env::set_var("DATABASE_URL", "sqlite::memory:");
+        env::set_var("ROCKET_DATABASES", "{sqlx={url=\"sqlite::memory:\"}}");
+        env::set_var("ROCKET_PROFILE", "release");
+        env::set_var("ROCKET_LOG_LEVEL", "off");
+        env::set_var("ROCKET_SECRET_KEY", "5Z4RZccfO6oVLQj86VXLxCaX/xyGq5wixH4hWsLve0s=");
+        env::set_var("DKIM_KEY_PRIVATE", "bench_key");
+        env::set_var("DKIM_KEY_PUBLIC", "bench_public_key");
+        env::set_var("EMAIL_FROM", "bench@example.com");
+        env::set_var("RESPONSE_CACHE_TTL_SECS", "0");
+    }
+    env_get(); // asserts all are there
+
+    let url = format!(
+        "sqlite:bench-{}?mode=memory&cache=shared",
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    );
+    let figment = rocket::Config::figment()
+        .merge(("databases.sqlx.url", url))
+        // An in-memory database is dropped when its last connection closes
+        .merge(("databases.sqlx.min_connections", 1));
+
+    let rocket = rocket::custom(figment)
+        .register("/", error::catchers())
+        .attach(api::stage())
+        .attach(clock::stage())
+        .attach(db::stage())
+        .attach(events::stage())
+        .attach(handlers::posts::stage());
+    Client::tracked(rocket).expect("valid rocket instance")
+}
+
+fn user_seed(client: &Client) -> i64 {
+    let pool = (**db::Db::fetch(client.rocket()).expect("database pool")).clone();
+    rocket::tokio::runtime::Runtime::new().unwrap().block_on(async move {
+        sqlx::query("INSERT INTO users (email) VALUES ('bench@example.com')")
+            .execute(&pool)
+            .await
+            .expect("insert user")
+            .last_insert_rowid()
+    })
+}
+
+fn authed<'c>(request: LocalRequest<'c>, user_id: i64) -> LocalRequest<'c> {
+    request
+        .private_cookie(auth_cookie(user_id))
+        .cookie(Cookie::new(CSRF_COOKIE, CSRF_TOKEN))
+        .header(Header::new(CSRF_HEADER, CSRF_TOKEN))
+}
+
+/// `count` new posts, each updated a second before the previous one.
+fn posts_new(count: usize) -> json::Value {
+    let now = Utc::now().with_nanosecond(0).unwrap();
+    let posts = (0..count)
+        .map(|i| {
+            let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+            let at = (now - Duration::seconds(i as i64)).to_rfc3339();
+            json::json!({
+                "id": format!("bench-{}", id),
+                "content": format!("Benchmark post {}", id),
+                "variant": "note",
+                "createdAt": at,
+                "updatedAt": at,
+            })
+        })
+        .collect();
+    json::Value::Array(posts)
+}
+
+fn upsert_many(client: &Client, user_id: i64, posts: &json::Value) {
+    let response = authed(client.post("/api/posts/upsert-many"), user_id)
+        .header(ContentType::JSON)
+        .body(posts.to_string())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
+
+fn posts(c: &mut Criterion) {
+    let client = client_build();
+    let user_id = user_seed(&client);
+    for _ in 0..SEEDED_POSTS / 500 {
+        upsert_many(&client, user_id, &posts_new(500));
+    }
+
+    let list = |uri: &str| {
+        let response = authed(client.get(uri), user_id).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json::<json::Value>().expect("list response")
+    };
+    // A cursor halfway through the seeded posts, so that half of them are newer
+    let middle = list(&format!("/api/posts?limit={}", SEEDED_POSTS / 2));
+    let after = middle["items"].as_array().unwrap().last().unwrap()["updatedAt"].clone();
+    let after = after.as_str().unwrap().to_owned();
+
+    let mut group = c.benchmark_group("list");
+    group.throughput(Throughput::Elements(PAGE_SIZE as u64));
+    let first = format!("/api/posts?limit={}", PAGE_SIZE);
+    group.bench_function("first_page", |b| b.iter(|| list(&first)));
+    let deep = format!("/api/posts?limit={}&after={}", PAGE_SIZE, after);
+    group.bench_function("updated_after", |b| b.iter(|| list(&deep)));
+    let manual = format!("/api/posts?limit={}&sort=manual", PAGE_SIZE);
+    group.bench_function("manual_sort", |b| b.iter(|| list(&manual)));
+    group.finish();
+
+    let mut group = c.benchmark_group("upsert");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("single", |b| {
+        b.iter_batched(
+            || posts_new(1)[0].clone(),
+            |post| {
+                let response = authed(client.post("/api/posts"), user_id)
+                    .header(ContentType::JSON)
+                    .body(post.to_string())
+                    .dispatch();
+                assert_eq!(response.status(), Status::Created);
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.throughput(Throughput::Elements(BULK_SIZE as u64));
+    group.bench_function("bulk", |b| {
+        b.iter_batched(
+            || posts_new(BULK_SIZE),
+            |posts| upsert_many(&client, user_id, &posts),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, posts);
+criterion_main!(benches);
@@ -0,0 +1,12 @@
+CREATE TABLE sessions (
+  id TEXT PRIMARY KEY NOT NULL,
+  user_id INTEGER NOT NULL,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  expires_at DATETIME NOT NULL,
+  revoked_at DATETIME,
+  ip TEXT,
+  user_agent TEXT,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_sessions_user_id ON sessions (user_id);
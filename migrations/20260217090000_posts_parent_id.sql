@@ -0,0 +1,3 @@
+ALTER TABLE posts ADD COLUMN parent_id TEXT REFERENCES posts(id) ON DELETE CASCADE;
+
+CREATE INDEX idx_posts_parent_id ON posts (parent_id);
@@ -0,0 +1,3 @@
+ALTER TABLE posts ADD COLUMN position REAL;
+
+CREATE INDEX idx_posts_user_id_position ON posts (user_id, position);
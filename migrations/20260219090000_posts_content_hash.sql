@@ -0,0 +1,3 @@
+ALTER TABLE posts ADD COLUMN content_hash TEXT;
+
+CREATE INDEX idx_posts_user_id_content_hash ON posts (user_id, content_hash);
@@ -0,0 +1,54 @@
+-- Server-assigned sync sequence: every write to a post (or its favorite) takes the next number
+-- from sync_counter, and deletes leave a tombstone, so that clients can pull changes incrementally
+-- without trusting client clocks.
+CREATE TABLE sync_counter (
+  id INTEGER PRIMARY KEY CHECK (id = 1),
+  seq INTEGER NOT NULL
+);
+
+ALTER TABLE posts ADD COLUMN seq INTEGER NOT NULL DEFAULT 0;
+UPDATE posts SET seq = rowid;
+INSERT INTO sync_counter (id, seq) VALUES (1, COALESCE((SELECT MAX(seq) FROM posts), 0));
+
+CREATE INDEX idx_posts_user_id_seq ON posts (user_id, seq);
+
+CREATE TABLE post_tombstones (
+  post_id TEXT NOT NULL,
+  user_id INTEGER NOT NULL,
+  seq INTEGER NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_post_tombstones_user_id_seq ON post_tombstones (user_id, seq);
+
+CREATE TRIGGER posts_seq_insert AFTER INSERT ON posts
+BEGIN
+  UPDATE sync_counter SET seq = seq + 1;
+  UPDATE posts SET seq = (SELECT seq FROM sync_counter) WHERE id = NEW.id;
+  DELETE FROM post_tombstones WHERE post_id = NEW.id AND user_id = NEW.user_id;
+END;
+
+-- The guard skips the trigger's own update of seq
+CREATE TRIGGER posts_seq_update AFTER UPDATE ON posts WHEN NEW.seq = OLD.seq
+BEGIN
+  UPDATE sync_counter SET seq = seq + 1;
+  UPDATE posts SET seq = (SELECT seq FROM sync_counter) WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER posts_seq_delete AFTER DELETE ON posts
+BEGIN
+  UPDATE sync_counter SET seq = seq + 1;
+  INSERT INTO post_tombstones (post_id, user_id, seq) VALUES (OLD.id, OLD.user_id, (SELECT seq FROM sync_counter));
+END;
+
+CREATE TRIGGER post_reactions_seq_insert AFTER INSERT ON post_reactions
+BEGIN
+  UPDATE sync_counter SET seq = seq + 1;
+  UPDATE posts SET seq = (SELECT seq FROM sync_counter) WHERE id = NEW.post_id;
+END;
+
+CREATE TRIGGER post_reactions_seq_delete AFTER DELETE ON post_reactions
+BEGIN
+  UPDATE sync_counter SET seq = seq + 1;
+  UPDATE posts SET seq = (SELECT seq FROM sync_counter) WHERE id = OLD.post_id;
+END;
@@ -0,0 +1,22 @@
+CREATE TABLE organizations (
+  id TEXT PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE TABLE organization_members (
+  org_id TEXT NOT NULL,
+  user_id INTEGER NOT NULL,
+  role TEXT NOT NULL CHECK (role IN ('owner', 'admin', 'member')),
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  PRIMARY KEY (org_id, user_id),
+  FOREIGN KEY (org_id) REFERENCES organizations(id) ON DELETE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_organization_members_user_id ON organization_members (user_id);
+
+-- Posts stay owned by their author and are shared read-only with the members of `org_id`
+ALTER TABLE posts ADD COLUMN org_id TEXT REFERENCES organizations(id) ON DELETE SET NULL;
+
+CREATE INDEX idx_posts_org_id_updated_at ON posts (org_id, updated_at);
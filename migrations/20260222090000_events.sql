@@ -0,0 +1,19 @@
+-- Transactional outbox: post changes are recorded in the same transaction as the change itself
+-- and delivered to consumers by the dispatcher, so a crash between commit and publish loses nothing
+CREATE TABLE events (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  user_id INTEGER NOT NULL,
+  kind TEXT NOT NULL CHECK (kind IN ('upserted', 'deleted', 'cleared')),
+  post_id TEXT,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_events_created_at ON events (created_at);
+
+-- The last event each consumer has acknowledged
+CREATE TABLE event_cursors (
+  consumer TEXT PRIMARY KEY NOT NULL,
+  event_id INTEGER NOT NULL,
+  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
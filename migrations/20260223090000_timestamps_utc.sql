@@ -0,0 +1,45 @@
+-- Timestamps are now written as RFC 3339 with an explicit UTC offset (2024-01-31T12:00:00+00:00)
+-- rather than as naive SQLite datetimes (2024-01-31 12:00:00). Rewrite the existing values, so that
+-- comparing and sorting them as text keeps working. Every insert now sets its timestamps, the
+-- CURRENT_TIMESTAMP defaults are only a fallback for rows written by hand.
+
+-- A format change is not a change to the post, so don't bump its sync sequence
+DROP TRIGGER posts_seq_update;
+
+UPDATE posts SET created_at = replace(created_at, ' ', 'T') || '+00:00' WHERE created_at NOT LIKE '%+00:00';
+UPDATE posts SET updated_at = replace(updated_at, ' ', 'T') || '+00:00' WHERE updated_at NOT LIKE '%+00:00';
+
+CREATE TRIGGER posts_seq_update AFTER UPDATE ON posts WHEN NEW.seq = OLD.seq
+BEGIN
+  UPDATE sync_counter SET seq = seq + 1;
+  UPDATE posts SET seq = (SELECT seq FROM sync_counter) WHERE id = NEW.id;
+END;
+
+UPDATE users SET created_at = replace(created_at, ' ', 'T') || '+00:00' WHERE created_at NOT LIKE '%+00:00';
+UPDATE users SET code_created_at = replace(code_created_at, ' ', 'T') || '+00:00'
+  WHERE code_created_at NOT LIKE '%+00:00';
+UPDATE users SET email_verified_at = replace(email_verified_at, ' ', 'T') || '+00:00'
+  WHERE email_verified_at NOT LIKE '%+00:00';
+UPDATE users SET disabled_at = replace(disabled_at, ' ', 'T') || '+00:00' WHERE disabled_at NOT LIKE '%+00:00';
+
+UPDATE user_preferences SET updated_at = replace(updated_at, ' ', 'T') || '+00:00'
+  WHERE updated_at NOT LIKE '%+00:00';
+
+UPDATE sessions SET created_at = replace(created_at, ' ', 'T') || '+00:00' WHERE created_at NOT LIKE '%+00:00';
+UPDATE sessions SET expires_at = replace(expires_at, ' ', 'T') || '+00:00' WHERE expires_at NOT LIKE '%+00:00';
+UPDATE sessions SET revoked_at = replace(revoked_at, ' ', 'T') || '+00:00' WHERE revoked_at NOT LIKE '%+00:00';
+
+UPDATE post_reactions SET created_at = replace(created_at, ' ', 'T') || '+00:00'
+  WHERE created_at NOT LIKE '%+00:00';
+
+UPDATE comments SET created_at = replace(created_at, ' ', 'T') || '+00:00' WHERE created_at NOT LIKE '%+00:00';
+UPDATE comments SET updated_at = replace(updated_at, ' ', 'T') || '+00:00' WHERE updated_at NOT LIKE '%+00:00';
+
+UPDATE organizations SET created_at = replace(created_at, ' ', 'T') || '+00:00'
+  WHERE created_at NOT LIKE '%+00:00';
+UPDATE organization_members SET created_at = replace(created_at, ' ', 'T') || '+00:00'
+  WHERE created_at NOT LIKE '%+00:00';
+
+UPDATE events SET created_at = replace(created_at, ' ', 'T') || '+00:00' WHERE created_at NOT LIKE '%+00:00';
+UPDATE event_cursors SET updated_at = replace(updated_at, ' ', 'T') || '+00:00'
+  WHERE updated_at NOT LIKE '%+00:00';
@@ -0,0 +1,18 @@
+-- Hybrid logical clock stamp of the last write (see `Hlc`), which conflict resolution compares
+-- instead of updated_at. Existing posts get the stamp of their updated_at, with an empty node, so
+-- that they keep their order against writes that only carry an updated_at.
+ALTER TABLE posts ADD COLUMN version TEXT NOT NULL DEFAULT '';
+
+-- Stamping a post is not a change to it, so don't bump its sync sequence
+DROP TRIGGER posts_seq_update;
+
+UPDATE posts SET version = printf(
+  '%015d-00000-',
+  CAST(strftime('%s', updated_at) AS INTEGER) * 1000 + CAST(substr(strftime('%f', updated_at), 4) AS INTEGER)
+);
+
+CREATE TRIGGER posts_seq_update AFTER UPDATE ON posts WHEN NEW.seq = OLD.seq
+BEGIN
+  UPDATE sync_counter SET seq = seq + 1;
+  UPDATE posts SET seq = (SELECT seq FROM sync_counter) WHERE id = NEW.id;
+END;
@@ -0,0 +1,25 @@
+-- The content of the last few versions of each post, as the base of three-way merges of
+-- conflicting edits. Kept by triggers, so that every write path records them.
+CREATE TABLE post_revisions (
+  post_id TEXT NOT NULL,
+  version TEXT NOT NULL,
+  content TEXT NOT NULL,
+  PRIMARY KEY (post_id, version),
+  FOREIGN KEY (post_id) REFERENCES posts(id) ON DELETE CASCADE
+);
+
+INSERT INTO post_revisions (post_id, version, content) SELECT id, version, content FROM posts;
+
+CREATE TRIGGER post_revisions_insert AFTER INSERT ON posts
+BEGIN
+  INSERT OR IGNORE INTO post_revisions (post_id, version, content) VALUES (NEW.id, NEW.version, NEW.content);
+END;
+
+-- Only the 20 latest revisions are kept
+CREATE TRIGGER post_revisions_update AFTER UPDATE OF version ON posts WHEN NEW.version <> OLD.version
+BEGIN
+  INSERT OR IGNORE INTO post_revisions (post_id, version, content) VALUES (NEW.id, NEW.version, NEW.content);
+  DELETE FROM post_revisions WHERE post_id = NEW.id AND version NOT IN (
+    SELECT version FROM post_revisions WHERE post_id = NEW.id ORDER BY version DESC LIMIT 20
+  );
+END;
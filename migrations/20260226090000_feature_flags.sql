@@ -0,0 +1,17 @@
+-- Runtime switches for experimental features. A flag is on for everyone when enabled, else for
+-- the share of users given by rollout_percent; per-user rows override both.
+CREATE TABLE feature_flags (
+  name TEXT PRIMARY KEY NOT NULL,
+  enabled INTEGER NOT NULL DEFAULT 0,
+  rollout_percent INTEGER NOT NULL DEFAULT 0 CHECK (rollout_percent BETWEEN 0 AND 100),
+  updated_at DATETIME NOT NULL
+);
+
+CREATE TABLE feature_flag_users (
+  name TEXT NOT NULL,
+  user_id INTEGER NOT NULL,
+  enabled INTEGER NOT NULL,
+  PRIMARY KEY (name, user_id),
+  FOREIGN KEY (name) REFERENCES feature_flags(name) ON DELETE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
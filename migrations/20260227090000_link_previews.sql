@@ -0,0 +1,33 @@
+-- OpenGraph metadata of the URLs found in posts, fetched in the background. A URL is fetched once
+-- however many posts link to it; failed fetches are kept so that they are not retried in a loop.
+CREATE TABLE link_previews (
+  url TEXT PRIMARY KEY NOT NULL,
+  status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'ok', 'failed')),
+  title TEXT,
+  description TEXT,
+  image_url TEXT,
+  site_name TEXT,
+  fetched_at DATETIME
+);
+
+CREATE INDEX idx_link_previews_status ON link_previews (status);
+
+-- The URLs each post links to, in the order they appear
+CREATE TABLE post_links (
+  post_id TEXT NOT NULL,
+  url TEXT NOT NULL,
+  position INTEGER NOT NULL,
+  PRIMARY KEY (post_id, url),
+  FOREIGN KEY (post_id) REFERENCES posts(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_post_links_url ON post_links (url);
+
+-- The posts seq the scanner has read up to. Contents may be encrypted, so URLs are extracted by
+-- the app rather than by triggers.
+CREATE TABLE link_scan_cursor (
+  id INTEGER PRIMARY KEY CHECK (id = 1),
+  seq INTEGER NOT NULL
+);
+
+INSERT INTO link_scan_cursor (id, seq) VALUES (1, 0);
@@ -0,0 +1,32 @@
+-- Reminders: a post is due once its remind_at has passed. Deliveries are recorded apart from the
+-- post, so that sending one is not a write clients have to sync.
+ALTER TABLE posts ADD COLUMN remind_at DATETIME;
+
+CREATE INDEX idx_posts_remind_at ON posts (remind_at) WHERE remind_at IS NOT NULL;
+
+-- The last reminder delivered for each post. A snoozed post is due again once its new remind_at
+-- passes, as it no longer matches the delivered one.
+CREATE TABLE reminder_deliveries (
+  post_id TEXT PRIMARY KEY NOT NULL,
+  remind_at DATETIME NOT NULL,
+  delivered_at DATETIME NOT NULL,
+  FOREIGN KEY (post_id) REFERENCES posts(id) ON DELETE CASCADE
+);
+
+-- Events gain the 'reminded' kind. SQLite can't alter a CHECK constraint, so the table is rebuilt.
+CREATE TABLE events_new (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  user_id INTEGER NOT NULL,
+  kind TEXT NOT NULL CHECK (kind IN ('upserted', 'deleted', 'cleared', 'reminded')),
+  post_id TEXT,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+INSERT INTO events_new (id, user_id, kind, post_id, created_at)
+SELECT id, user_id, kind, post_id, created_at FROM events;
+
+DROP TABLE events;
+ALTER TABLE events_new RENAME TO events;
+
+CREATE INDEX idx_events_created_at ON events (created_at);
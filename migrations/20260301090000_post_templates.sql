@@ -0,0 +1,22 @@
+-- Recurring posts: each template is instantiated into a new post whenever its cron schedule
+-- (in UTC) comes due. next_run_at is NULL while the template is disabled or its schedule never
+-- matches again.
+CREATE TABLE post_templates (
+  id TEXT PRIMARY KEY NOT NULL,
+  user_id INTEGER NOT NULL,
+  name TEXT NOT NULL,
+  content TEXT NOT NULL,
+  variant TEXT NOT NULL,
+  parent_id TEXT,
+  schedule TEXT NOT NULL,
+  enabled INTEGER NOT NULL DEFAULT 1,
+  next_run_at DATETIME,
+  last_run_at DATETIME,
+  created_at DATETIME NOT NULL,
+  updated_at DATETIME NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+  FOREIGN KEY (parent_id) REFERENCES posts(id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_post_templates_user_id ON post_templates (user_id);
+CREATE INDEX idx_post_templates_next_run_at ON post_templates (next_run_at) WHERE next_run_at IS NOT NULL;
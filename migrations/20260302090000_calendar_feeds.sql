@@ -0,0 +1,8 @@
+-- Each user may have one secret token giving read-only access to the iCalendar feed of their
+-- dated posts. Only its SHA-256 digest is stored.
+CREATE TABLE calendar_feeds (
+  user_id INTEGER PRIMARY KEY NOT NULL,
+  token_hash TEXT NOT NULL UNIQUE,
+  created_at DATETIME NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,19 @@
+-- Public share links: anyone holding a post's token may read it. Each user sharing posts also
+-- gets an Atom feed of them, under an ID of its own so that it doesn't reveal the user's.
+CREATE TABLE post_shares (
+  token TEXT PRIMARY KEY NOT NULL,
+  post_id TEXT NOT NULL UNIQUE,
+  user_id INTEGER NOT NULL,
+  created_at DATETIME NOT NULL,
+  FOREIGN KEY (post_id) REFERENCES posts(id) ON DELETE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_post_shares_user_id ON post_shares (user_id);
+
+CREATE TABLE share_feeds (
+  user_id INTEGER PRIMARY KEY NOT NULL,
+  id TEXT NOT NULL UNIQUE,
+  created_at DATETIME NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,3 @@
+-- Tombstone of an account merged into another: it is disabled and points at the account that
+-- took over its data.
+ALTER TABLE users ADD COLUMN merged_into INTEGER REFERENCES users(id);
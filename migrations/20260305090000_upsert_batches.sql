@@ -0,0 +1,13 @@
+-- Batches of `upsert-many` already applied, so that a retried batch is answered with the response
+-- it first got instead of being written again
+CREATE TABLE upsert_batches (
+  user_id INTEGER NOT NULL,
+  batch_id TEXT NOT NULL,
+  -- SHA-256 of the posts and options, to refuse a batch ID reused for other posts
+  digest TEXT NOT NULL,
+  status INTEGER NOT NULL,
+  response TEXT NOT NULL,
+  created_at DATETIME NOT NULL,
+  PRIMARY KEY (user_id, batch_id),
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,41 @@
+-- Attachment contents, stored once per blob and shared by every attachment with the same bytes.
+-- `key` is the SHA-256 of the bytes, prefixed with the owner's ID unless blobs are deduplicated
+-- across users. The reference count is kept by the triggers below, so that attachments deleted
+-- along with their post or user are counted too.
+CREATE TABLE blobs (
+  key TEXT PRIMARY KEY NOT NULL,
+  sha256 TEXT NOT NULL,
+  size INTEGER NOT NULL,
+  ref_count INTEGER NOT NULL DEFAULT 0,
+  created_at DATETIME NOT NULL,
+  -- When the last reference went away; the GC job removes the blob after a grace period
+  unreferenced_at DATETIME
+);
+
+CREATE INDEX idx_blobs_unreferenced_at ON blobs (unreferenced_at) WHERE ref_count = 0;
+
+CREATE TABLE attachments (
+  id TEXT PRIMARY KEY NOT NULL,
+  post_id TEXT NOT NULL,
+  user_id INTEGER NOT NULL,
+  blob_key TEXT NOT NULL,
+  filename TEXT NOT NULL,
+  content_type TEXT NOT NULL,
+  created_at DATETIME NOT NULL,
+  FOREIGN KEY (post_id) REFERENCES posts(id) ON DELETE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+  FOREIGN KEY (blob_key) REFERENCES blobs(key)
+);
+
+CREATE INDEX idx_attachments_post_id ON attachments (post_id);
+CREATE INDEX idx_attachments_blob_key ON attachments (blob_key);
+
+CREATE TRIGGER attachments_blob_ref AFTER INSERT ON attachments BEGIN
+  UPDATE blobs SET ref_count = ref_count + 1, unreferenced_at = NULL WHERE key = NEW.blob_key;
+END;
+
+CREATE TRIGGER attachments_blob_unref AFTER DELETE ON attachments BEGIN
+  UPDATE blobs SET ref_count = ref_count - 1,
+    unreferenced_at = CASE WHEN ref_count = 1 THEN strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now') ELSE unreferenced_at END
+  WHERE key = OLD.blob_key;
+END;
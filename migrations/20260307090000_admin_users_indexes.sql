@@ -0,0 +1,4 @@
+-- Per-user aggregates of the admin user list: post counts and last activity come from the index
+-- alone, attachment sizes from the user's attachments only.
+CREATE INDEX idx_posts_user_id_updated_at ON posts (user_id, updated_at);
+CREATE INDEX idx_attachments_user_id ON attachments (user_id);
@@ -0,0 +1,4 @@
+-- Sessions opened by support staff to act as the user. The row is the audit record of the
+-- impersonation: who opened it, why, and when it expired or was stopped.
+ALTER TABLE sessions ADD COLUMN impersonator TEXT;
+ALTER TABLE sessions ADD COLUMN impersonation_reason TEXT;
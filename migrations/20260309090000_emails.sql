@@ -0,0 +1,20 @@
+-- Every email the app sends, with the outcome of its delivery.
+CREATE TABLE emails (
+  id TEXT PRIMARY KEY NOT NULL,
+  recipient TEXT NOT NULL,
+  template TEXT NOT NULL,
+  -- sent, failed or suppressed
+  status TEXT NOT NULL,
+  provider_message_id TEXT,
+  error TEXT,
+  created_at DATETIME NOT NULL
+);
+
+CREATE INDEX idx_emails_status_created_at ON emails (status, created_at);
+
+-- Addresses that hard-bounced: emails to them are recorded as suppressed instead of sent.
+CREATE TABLE email_suppressions (
+  email TEXT PRIMARY KEY NOT NULL,
+  reason TEXT NOT NULL,
+  created_at DATETIME NOT NULL
+);
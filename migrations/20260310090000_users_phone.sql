@@ -0,0 +1,9 @@
+-- Phone numbers login codes can be texted to, when the user picks SMS as their channel. A number
+-- is only used once verified with a code texted to it, tracked like login codes.
+ALTER TABLE users ADD COLUMN phone TEXT;
+ALTER TABLE users ADD COLUMN phone_verified_at DATETIME;
+ALTER TABLE users ADD COLUMN phone_code_hash TEXT;
+ALTER TABLE users ADD COLUMN phone_code_attempts INTEGER;
+ALTER TABLE users ADD COLUMN phone_code_created_at DATETIME;
+-- email or sms
+ALTER TABLE users ADD COLUMN otp_channel TEXT NOT NULL DEFAULT 'email';
@@ -0,0 +1,6 @@
+-- How each session was signed in: email_code, sms_code or impersonation
+ALTER TABLE sessions ADD COLUMN auth_method TEXT NOT NULL DEFAULT 'email_code';
+UPDATE sessions SET auth_method = 'impersonation' WHERE impersonator IS NOT NULL;
+
+-- Channel the pending login code was sent through, email or sms, recorded on the session it opens
+ALTER TABLE users ADD COLUMN code_channel TEXT NOT NULL DEFAULT 'email';
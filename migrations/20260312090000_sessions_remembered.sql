@@ -0,0 +1,3 @@
+-- Whether the user asked to be remembered at login, which sets how far sessions are extended
+ALTER TABLE sessions ADD COLUMN remembered BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE sessions SET remembered = julianday(expires_at) - julianday(created_at) > 1.5;
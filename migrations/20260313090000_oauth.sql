@@ -0,0 +1,43 @@
+-- First-party apps, such as the mobile app, that may get tokens through OAuth2 (authorization code
+-- with PKCE). Clients are public: they have no secret, so every grant must prove its PKCE verifier.
+-- `redirect_uris` and `scopes` are space-separated; `scopes` are the most a client may ask for.
+CREATE TABLE oauth_clients (
+  id TEXT PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL,
+  redirect_uris TEXT NOT NULL,
+  scopes TEXT NOT NULL,
+  created_at DATETIME NOT NULL,
+  revoked_at DATETIME
+);
+
+-- Authorization codes waiting to be exchanged for tokens, by the SHA-256 digest of the code. They
+-- are single use and short-lived.
+CREATE TABLE oauth_codes (
+  code_hash TEXT PRIMARY KEY NOT NULL,
+  client_id TEXT NOT NULL,
+  user_id INTEGER NOT NULL,
+  redirect_uri TEXT NOT NULL,
+  scope TEXT NOT NULL,
+  code_challenge TEXT NOT NULL,
+  expires_at DATETIME NOT NULL,
+  FOREIGN KEY (client_id) REFERENCES oauth_clients(id) ON DELETE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- A grant of a client by a user: its current access and refresh tokens, both stored as SHA-256
+-- digests. Refreshing replaces both tokens of the row.
+CREATE TABLE oauth_tokens (
+  id TEXT PRIMARY KEY NOT NULL,
+  client_id TEXT NOT NULL,
+  user_id INTEGER NOT NULL,
+  scope TEXT NOT NULL,
+  access_hash TEXT NOT NULL UNIQUE,
+  access_expires_at DATETIME NOT NULL,
+  refresh_hash TEXT NOT NULL UNIQUE,
+  refresh_expires_at DATETIME NOT NULL,
+  created_at DATETIME NOT NULL,
+  revoked_at DATETIME,
+  FOREIGN KEY (client_id) REFERENCES oauth_clients(id) ON DELETE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+CREATE INDEX idx_oauth_tokens_user_id ON oauth_tokens (user_id);
@@ -0,0 +1,8 @@
+-- The content of posts whose content is a JSON object or array, for `?json_path=` filters. It is
+-- derived from `content`, so it is NULL for plain text and for encrypted content alike. CASE is
+-- evaluated lazily, unlike AND, so `json_type` never sees malformed JSON.
+ALTER TABLE posts ADD COLUMN content_json TEXT GENERATED ALWAYS AS (
+  CASE WHEN json_valid(content) THEN
+    CASE WHEN json_type(content) IN ('object', 'array') THEN json(content) END
+  END
+) VIRTUAL;
@@ -0,0 +1,5 @@
+-- The title and excerpt of posts, derived from their content on write for list views. NULL for
+-- encrypted content, whose title and excerpt are derived on read instead, and for posts written
+-- before these columns, until `admin posts rehash` fills them in.
+ALTER TABLE posts ADD COLUMN title TEXT;
+ALTER TABLE posts ADD COLUMN excerpt TEXT;
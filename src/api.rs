@@ -0,0 +1,277 @@
+use rocket::fairing::AdHoc;
+use rocket::http::{ContentType, Header, Method, Status};
+use rocket::route::{self, Handler, Route};
+use rocket::serde::{Serialize, json};
+use rocket::{Build, Data, Request, Rocket};
+use std::io::Cursor;
+use std::sync::OnceLock;
+
+use crate::client_info::TrustedProxies;
+use crate::error::ApiError;
+use crate::maintenance::maintenance_routes;
+use crate::metrics::metrics;
+use crate::panics::panic_routes;
+use crate::payload::msgpack_handler;
+use crate::ratelimit::rate_limit_routes;
+use crate::util::*;
+
+/// Path prefix of the current API version.
+pub const API_V1: &str = "/api/v1";
+/// Path prefix of the unversioned API, kept as a deprecated alias of `/api/v1` with the old bodies.
+pub const API_LEGACY: &str = "/api";
+
+/// The `POST` routes that only read, taking their input as a body too large for a query string.
+/// Relative to the API prefix.
+const READS_BY_POST: [&str; 1] = ["/posts/fetch-many"];
+
+/// Whether a request only reads, so that it is rate limited as a read, served during maintenance
+/// and open to tokens with read scopes. `path` is relative to the API prefix.
+pub fn request_reads(method: Method, path: &str) -> bool {
+    matches!(method, Method::Get | Method::Head | Method::Options)
+        || (method == Method::Post && READS_BY_POST.contains(&path))
+}
+
+/// The body of every `/api/v1` response.
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Envelope {
+    pub data: json::Value,
+    /// An `ErrorBody`.
+    pub error: Option<json::Value>,
+    pub meta: Option<json::Value>,
+}
+
+impl Envelope {
+    /// Wraps a legacy handler body. A list page (`items` + `hasMore`) becomes `data` with the
+    /// pagination and any other fields in `meta`, a bare `{"message": "success"}` carries no data,
+    /// and error bodies become `error`.
+    pub fn from_legacy(status: Status, body: json::Value) -> Self {
+        if status.class().is_success() {
+            return match body {
+                json::Value::Object(mut map) if map.contains_key("items") && map.contains_key("hasMore") => {
+                    let has_more = map.remove("hasMore").unwrap_or_default();
+                    let data = map.remove("items").unwrap_or_default();
+                    map.insert("pagination".into(), json::json!({ "hasMore": has_more }));
+                    Self {
+                        data,
+                        error: None,
+                        meta: Some(json::Value::Object(map)),
+                    }
+                }
+                json::Value::Object(map) if map.len() == 1 && map.contains_key("message") => {
+                    Self::data(json::Value::Null)
+                }
+                body => Self::data(body),
+            };
+        }
+
+        Self::error(body)
+    }
+
+    pub fn data(data: json::Value) -> Self {
+        Self {
+            data,
+            error: None,
+            meta: None,
+        }
+    }
+
+    pub fn error(error: json::Value) -> Self {
+        Self {
+            data: json::Value::Null,
+            error: Some(error),
+            meta: None,
+        }
+    }
+}
+
+/// The outcome of one item of a batch request answered with `207 Multi-Status`.
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BatchItem {
+    pub id: String,
+    pub status: u16,
+    /// The `code` and `message` of the item's error.
+    pub error: Option<json::Value>,
+}
+
+impl BatchItem {
+    pub fn ok(id: impl Into<String>, status: Status) -> Self {
+        Self {
+            id: id.into(),
+            status: status.code,
+            error: None,
+        }
+    }
+
+    pub fn error(id: impl Into<String>, error: ApiError) -> Self {
+        Self {
+            id: id.into(),
+            status: error.status.code,
+            error: Some(json::json!({ "code": error.code, "message": error.message })),
+        }
+    }
+}
+
+/// Handler wrapper that rewrites JSON responses of the inner handler into an `Envelope`.
+#[derive(Clone)]
+struct EnvelopeHandler {
+    inner: Box<dyn Handler>,
+}
+
+#[rocket::async_trait]
+impl Handler for EnvelopeHandler {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        let mut response = match self.inner.handle(request, data).await {
+            route::Outcome::Success(response) => response,
+            outcome => return outcome,
+        };
+        if response.content_type() != Some(ContentType::JSON) {
+            return route::Outcome::Success(response);
+        }
+
+        let body = response.body_mut().to_string().await.unwrap_or_default();
+        let body = json::from_str(&body).unwrap_or(json::Value::Null);
+        let envelope = Envelope::from_legacy(response.status(), body);
+        let envelope = json::to_string(&envelope).expect("envelope serializes");
+        response.set_sized_body(envelope.len(), Cursor::new(envelope));
+        route::Outcome::Success(response)
+    }
+}
+
+/// How a deprecated route is announced to its clients.
+#[derive(Debug, Clone, Default)]
+pub struct Deprecation {
+    /// When the route is due to be removed, sent as the `Sunset` header.
+    pub sunset: Option<DateTime<Utc>>,
+    /// The route replacing it, sent as a `successor-version` link.
+    pub successor: Option<String>,
+}
+
+/// Returns the deprecation of the unversioned API, due to be removed at `API_LEGACY_SUNSET`
+/// (an RFC 3339 date) when set.
+pub fn legacy_deprecation() -> &'static Deprecation {
+    static DEPRECATION: OnceLock<Deprecation> = OnceLock::new();
+    DEPRECATION.get_or_init(|| Deprecation {
+        sunset: env_parse_opt("API_LEGACY_SUNSET"),
+        successor: None,
+    })
+}
+
+/// Handler wrapper that flags responses as deprecated and counts them, so that the clients still
+/// calling a route can be tracked down before it is removed. The innermost wrapper wins, so a
+/// route deprecated on its own keeps its dates when it is also served by the unversioned API.
+#[derive(Clone)]
+struct DeprecatedHandler {
+    inner: Box<dyn Handler>,
+    deprecation: Deprecation,
+    /// Whether this is the unversioned alias of a `/api/v1` route, its successor.
+    legacy: bool,
+}
+
+#[rocket::async_trait]
+impl Handler for DeprecatedHandler {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        let mut outcome = self.inner.handle(request, data).await;
+        let route::Outcome::Success(response) = &mut outcome else {
+            return outcome;
+        };
+        if response.headers().contains("Deprecation") {
+            return outcome;
+        }
+
+        let successor = match self.legacy {
+            true => Some(format!(
+                "{}{}",
+                API_V1,
+                request.uri().path().as_str().trim_start_matches(API_LEGACY)
+            )),
+            false => self.deprecation.successor.clone(),
+        };
+        response.set_header(Header::new("Deprecation", "true"));
+        if let Some(successor) = successor {
+            response.set_header(Header::new(
+                "Link",
+                format!("<{}>; rel=\"successor-version\"", successor),
+            ));
+        }
+        if let Some(sunset) = self.deprecation.sunset {
+            response.set_header(Header::new(
+                "Sunset",
+                sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+            ));
+        }
+        let name = request
+            .route()
+            .and_then(|route| route.name.as_deref())
+            .unwrap_or("unknown");
+        metrics().counter_inc(
+            "deprecated_requests_total",
+            "Requests served by deprecated routes.",
+            &[("route", name), ("api", if self.legacy { "legacy" } else { "v1" })],
+        );
+        outcome
+    }
+}
+
+/// Marks `routes` as deprecated: their responses carry the `Deprecation` header, and `Sunset` and
+/// a `successor-version` link when given, and their use is counted in `deprecated_requests_total`.
+pub fn deprecated_routes(routes: Vec<Route>, deprecation: Deprecation) -> Vec<Route> {
+    routes
+        .into_iter()
+        .map(|mut route| {
+            route.handler = Box::new(DeprecatedHandler {
+                inner: route.handler,
+                deprecation: deprecation.clone(),
+                legacy: false,
+            });
+            route
+        })
+        .collect()
+}
+
+/// Mounts `routes` at `/api/v1{base}`, wrapped in the response envelope, and at the deprecated
+/// `/api{base}` with the legacy bodies. Handler panics are turned into JSON 500s, requests over the
+/// caller's rate limit into 429s, and writes in maintenance mode into 503s.
+///
+/// Responses are sent as MessagePack to clients that prefer it.
+pub fn api_mount(rocket: Rocket<Build>, base: &str, routes: Vec<Route>) -> Rocket<Build> {
+    let routes = rate_limit_routes(maintenance_routes(panic_routes(routes)));
+    let wrap = |wrapper: fn(Box<dyn Handler>) -> Box<dyn Handler>| {
+        routes
+            .iter()
+            .cloned()
+            .map(|mut route| {
+                route.handler = wrapper(route.handler);
+                route
+            })
+            .collect::<Vec<_>>()
+    };
+    let v1 = wrap(|inner| msgpack_handler(Box::new(EnvelopeHandler { inner })));
+    let legacy = wrap(|inner| {
+        msgpack_handler(Box::new(DeprecatedHandler {
+            inner,
+            deprecation: legacy_deprecation().clone(),
+            legacy: true,
+        }))
+    });
+
+    rocket
+        .mount(format!("{}{}", API_V1, base), v1)
+        .mount(format!("{}{}", API_LEGACY, base), legacy)
+}
+
+/// Errors raised before a `/api/v1` handler runs (failed guards, unknown routes) still use the
+/// envelope.
+#[catch(default)]
+fn v1_default(status: Status, request: &Request) -> (Status, json::Value) {
+    let error = ApiError::caught(status, request).body(request);
+    (status, json::json!(Envelope::error(json::json!(error))))
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("API stage", |rocket| async {
+        let rocket = manage_default(rocket, |_| TrustedProxies::from_env());
+        rocket.register(API_V1, catchers![v1_default])
+    })
+}
@@ -0,0 +1,143 @@
+use rocket::fairing::AdHoc;
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rust_embed::RustEmbed;
+use std::borrow::Cow;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+/// The built single-page app, compiled into the binary. Build it into `web/dist` before building
+/// with the `embed` feature; next to each file, `<file>.br` and `<file>.gz` are served in its place
+/// to clients that accept them.
+#[derive(RustEmbed)]
+#[folder = "web/dist/"]
+struct Assets;
+
+/// Precompressed variants, most preferred first: file suffix and `Content-Encoding`.
+const ENCODINGS: &[(&str, &str)] = &[(".br", "br"), (".gz", "gzip")];
+
+/// The request headers that decide which variant of an asset to send.
+pub struct AssetRequest<'r> {
+    accept_encoding: &'r str,
+    if_none_match: Option<&'r str>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AssetRequest<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(AssetRequest {
+            accept_encoding: request.headers().get_one("Accept-Encoding").unwrap_or_default(),
+            if_none_match: request.headers().get_one("If-None-Match"),
+        })
+    }
+}
+
+/// An embedded file with the headers to serve it with, or `304 Not Modified`.
+pub struct Asset {
+    body: Option<Cow<'static, [u8]>>,
+    content_type: ContentType,
+    encoding: Option<&'static str>,
+    cache_control: &'static str,
+    etag: String,
+}
+
+impl<'r> Responder<'r, 'static> for Asset {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response
+            .header(self.content_type)
+            .header(Header::new("Cache-Control", self.cache_control))
+            .header(Header::new("ETag", self.etag))
+            .header(Header::new("Vary", "Accept-Encoding"));
+        if let Some(encoding) = self.encoding {
+            response.header(Header::new("Content-Encoding", encoding));
+        }
+        match self.body {
+            Some(body) => response.sized_body(body.len(), Cursor::new(body)),
+            None => response.status(Status::NotModified),
+        };
+        response.ok()
+    }
+}
+
+/// Returns the `Cache-Control` of an asset. Bundlers put content-hashed files under `assets/`, so
+/// those never change; `index.html` points at the current ones and must always be revalidated.
+pub fn asset_cache_control(path: &str) -> &'static str {
+    if path.starts_with("assets/") {
+        "public, max-age=31536000, immutable"
+    } else if path == "index.html" {
+        "no-cache"
+    } else {
+        "public, max-age=3600"
+    }
+}
+
+/// Returns the embedded path to serve for a request path: the file itself, or `index.html` for
+/// the app's client-side routes, which have no file extension.
+pub fn asset_resolve(path: &str, exists: impl Fn(&str) -> bool) -> Option<String> {
+    let path = match path {
+        "" => "index.html",
+        path => path,
+    };
+    if exists(path) {
+        return Some(path.to_string());
+    }
+    let last = path.rsplit('/').next().unwrap_or_default();
+    (!last.contains('.') && exists("index.html")).then(|| "index.html".to_string())
+}
+
+/// Serves the embedded app. Ranked after every other route, so that it only answers paths no API
+/// route claims.
+#[get("/<path..>", rank = 100)]
+fn asset(path: PathBuf, request: AssetRequest<'_>) -> Option<Asset> {
+    let path = path.to_str()?;
+    if path == "api" || path.starts_with("api/") {
+        return None;
+    }
+    let path = asset_resolve(path, |path| Assets::get(path).is_some())?;
+
+    let file = Assets::get(&path)?;
+    let content_type = path
+        .rsplit_once('.')
+        .and_then(|(_, extension)| ContentType::from_extension(extension))
+        .unwrap_or(ContentType::Binary);
+    let accepted = |encoding: &str| {
+        request
+            .accept_encoding
+            .split(',')
+            .any(|accepted| accepted.split(';').next().unwrap_or_default().trim() == encoding)
+    };
+    let (body, encoding) = ENCODINGS
+        .iter()
+        .filter(|(_, encoding)| accepted(encoding))
+        .find_map(|(suffix, encoding)| Some((Assets::get(&format!("{}{}", path, suffix))?.data, Some(*encoding))))
+        .unwrap_or((file.data, None));
+
+    // Each encoding is a different representation, so it gets its own tag
+    let hash = file
+        .metadata
+        .sha256_hash()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    let etag = format!(
+        "\"{}{}\"",
+        hash,
+        encoding.map(|encoding| format!("-{}", encoding)).unwrap_or_default()
+    );
+    let modified = request.if_none_match != Some(etag.as_str());
+    Some(Asset {
+        body: modified.then_some(body),
+        content_type,
+        encoding,
+        cache_control: asset_cache_control(&path),
+        etag,
+    })
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Assets stage", |rocket| async { rocket.mount("/", routes![asset]) })
+}
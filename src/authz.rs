@@ -0,0 +1,392 @@
+use chrono::TimeDelta;
+use nanoid::nanoid;
+use rocket::Request;
+use rocket::http::{self, Method};
+use rocket::request::{self, FromRequest};
+use rocket::serde::{Serialize, json};
+use sha2::{Digest, Sha256};
+use tracing::Instrument;
+
+use crate::api::{API_LEGACY, API_V1, request_reads};
+use crate::clock::Timekeeper;
+use crate::db::*;
+use crate::error::{ApiError, ErrorCode, guard_error_set};
+use crate::oauth::oauth_access_lookup;
+use crate::util::*;
+
+/// Prefix of personal access tokens, which tells them apart from OAuth access tokens.
+pub const API_TOKEN_PREFIX: &str = "pat_";
+/// Longest lifetime of a personal access token that expires.
+pub const API_TOKEN_TTL_MAX_DAYS: i64 = 365;
+
+/// The resource each base path of the API belongs to. A scope grants `read` (safe methods) or
+/// `write` (the others) access to one resource, e.g. `posts:read` or `account:write`.
+pub const SCOPE_RESOURCES: &[(&str, &str)] = &[
+    ("attachments", "attachments"),
+    ("calendar", "calendar"),
+    ("comments", "comments"),
+    ("orgs", "orgs"),
+    ("posts", "posts"),
+    ("session", "account"),
+    ("shares", "shares"),
+    ("templates", "templates"),
+    ("users", "account"),
+];
+
+/// Whether `scope` is the `read` or `write` scope of one of the `SCOPE_RESOURCES`.
+pub fn scope_is_valid(scope: &str) -> bool {
+    matches!(
+        scope.split_once(':'),
+        Some((resource, "read" | "write")) if SCOPE_RESOURCES.iter().any(|(_, r)| *r == resource)
+    )
+}
+
+/// Parses space-separated scopes, sorted and without duplicates. `None` when there are none or one
+/// is unknown.
+pub fn scopes_parse(text: &str) -> Option<Vec<String>> {
+    let mut scopes = text.split_whitespace().map(str::to_owned).collect::<Vec<_>>();
+    scopes.sort();
+    scopes.dedup();
+    match !scopes.is_empty() && scopes.iter().all(|scope| scope_is_valid(scope)) {
+        true => Some(scopes),
+        false => None,
+    }
+}
+
+/// The scope a token needs for a request to `path`. `None` outside the API paths of
+/// `SCOPE_RESOURCES`, which only accept sessions.
+pub fn scope_required(method: Method, path: &str) -> Option<String> {
+    let path = path.strip_prefix(API_V1).or_else(|| path.strip_prefix(API_LEGACY))?;
+    let base = path.trim_start_matches('/').split('/').next()?;
+    let (_, resource) = SCOPE_RESOURCES.iter().find(|(b, _)| *b == base)?;
+    let access = match request_reads(method, path) {
+        true => "read",
+        false => "write",
+    };
+    Some(format!("{}:{}", resource, access))
+}
+
+fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// What a request was authenticated with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credential {
+    /// The session cookie of a signed-in user, which may do anything.
+    Session,
+    /// An access token issued to an OAuth client, see `crate::oauth`.
+    OAuth { client_id: String },
+    /// A personal access token.
+    ApiToken { id: String },
+}
+
+/// A bearer token's user, credential and scopes.
+#[derive(Debug, Clone)]
+pub struct TokenGrant {
+    pub user_id: i64,
+    pub credential: Credential,
+    pub scopes: Vec<String>,
+}
+
+/// `TokenGrant` lookup cached on the request. `Err` when the database could not be queried.
+struct TokenGrantCache(Result<Option<TokenGrant>, ()>);
+
+/// Looks up the grant of the token in the request's `Authorization: Bearer` header, once per
+/// request. `Ok(None)` when there is no such header or the token is unknown, expired or revoked.
+async fn token_grant<'r>(request: &'r Request<'_>) -> &'r Result<Option<TokenGrant>, ()> {
+    let cache = request
+        .local_cache_async(async {
+            let Some(token) = request
+                .headers()
+                .get_one("Authorization")
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .map(str::trim)
+            else {
+                return TokenGrantCache(Ok(None));
+            };
+            let Some(db) = Db::fetch(request.rocket()) else {
+                return TokenGrantCache(Err(()));
+            };
+            let now = match request.rocket().state::<Timekeeper>() {
+                Some(clock) => clock.now(),
+                None => Utc::now(),
+            };
+            let grant = match token.starts_with(API_TOKEN_PREFIX) {
+                true => api_token_lookup(db, token, now).await,
+                false => oauth_access_lookup(db, token, now).await,
+            };
+            TokenGrantCache(grant.map_err(|e| tracing::error!("auth:token-lookup-error: {}", e)))
+        })
+        .await;
+    &cache.0
+}
+
+/// The `sessions` row the request's session cookie names, while the session is active.
+#[derive(Debug, Clone)]
+pub struct SessionRecord {
+    pub id: String,
+    pub user_id: i64,
+    pub expires_at: DateTime<Utc>,
+    pub remembered: bool,
+    /// The support staff member acting as the user, for impersonations.
+    pub impersonator: Option<String>,
+}
+
+/// `SessionRecord` lookup cached on the request. `Err` when the database could not be queried.
+struct SessionRecordCache(Result<Option<SessionRecord>, ()>);
+
+/// Looks up the session `session_id` unless it was revoked or expired by `now`.
+pub(crate) async fn session_active(
+    db: &sqlx::SqlitePool,
+    session_id: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<SessionRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        SessionRecord,
+        "SELECT id, user_id, expires_at AS \"expires_at: DateTime<Utc>\", remembered AS \"remembered: bool\", \
+        impersonator FROM sessions WHERE id = ? AND revoked_at IS NULL AND expires_at > ?",
+        session_id,
+        now
+    )
+    .fetch_optional(db)
+    .instrument(query_span("sessions.auth"))
+    .await
+}
+
+/// Looks up the session named by the request's session cookie, once per request. `Ok(None)`
+/// without the cookie, or once the session was revoked or expired, whatever the cookies' max-age.
+pub async fn session_record<'r>(request: &'r Request<'_>) -> &'r Result<Option<SessionRecord>, ()> {
+    let cache = request
+        .local_cache_async(async {
+            let Some(session_id) = request
+                .cookies()
+                .get_private(SESSION_COOKIE)
+                .map(|cookie| cookie.value().to_owned())
+            else {
+                return SessionRecordCache(Ok(None));
+            };
+            let Some(db) = Db::fetch(request.rocket()) else {
+                return SessionRecordCache(Err(()));
+            };
+            let now = match request.rocket().state::<Timekeeper>() {
+                Some(clock) => clock.now(),
+                None => Utc::now(),
+            };
+            let session = session_active(db, &session_id, now)
+                .await
+                .map_err(|e| tracing::error!("auth:session-lookup-error: {}", e));
+            SessionRecordCache(session)
+        })
+        .await;
+    &cache.0
+}
+
+/// Request guard for the active session of the request, see `session_record`. Forwards without one.
+#[rocket::async_trait]
+impl<'r> request::FromRequest<'r> for SessionRecord {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match session_record(request).await {
+            Ok(Some(session)) => request::Outcome::Success(session.clone()),
+            Ok(None) => request::Outcome::Forward(http::Status::Unauthorized),
+            Err(()) => request::Outcome::Error((http::Status::ServiceUnavailable, "database unavailable")),
+        }
+    }
+}
+
+/// The session `session_record` found for a request that looked it up, e.g. in response fairings.
+pub fn session_record_cached<'r>(request: &'r Request<'_>) -> Option<&'r SessionRecord> {
+    request
+        .local_cache(|| SessionRecordCache(Ok(None)))
+        .0
+        .as_ref()
+        .ok()
+        .and_then(Option::as_ref)
+}
+
+/// The credential of a request and what it allows. Every `UserCtx` is resolved through it, so
+/// session cookies are refused with 401 once their session was revoked or expired, and tokens with
+/// 403 by any route outside their scopes and with 401 by routes that only accept sessions. Handlers
+/// that must tell sessions from tokens, e.g. to stop tokens from minting tokens, take it next to
+/// `UserCtx`.
+#[derive(Debug, Clone)]
+pub struct Authz {
+    pub user_id: i64,
+    pub credential: Credential,
+    /// The scopes of a token; `None` for sessions.
+    pub scopes: Option<Vec<String>>,
+}
+
+impl Authz {
+    pub fn allows(&self, scope: &str) -> bool {
+        self.scopes
+            .as_ref()
+            .is_none_or(|scopes| scopes.iter().any(|s| s == scope))
+    }
+
+    /// Fails with 403 unless the request was made with a session.
+    pub fn session_required(&self) -> Result<(), ApiError> {
+        match self.credential {
+            Credential::Session => Ok(()),
+            _ => Err(ApiError::new(
+                http::Status::Forbidden,
+                ErrorCode::Forbidden,
+                "This requires a signed-in session, not a token",
+            )),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authz {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let cookie = request
+            .cookies()
+            .get_private("user_id")
+            .and_then(|cookie| cookie.value().parse().ok());
+        if let Some(user_id) = cookie {
+            // The cookie is only as good as its session, which may have been revoked or expired since it was set
+            return match session_record(request).await {
+                Ok(Some(session)) if session.user_id == user_id => request::Outcome::Success(Authz {
+                    user_id,
+                    credential: Credential::Session,
+                    scopes: None,
+                }),
+                Ok(_) => request::Outcome::Error((http::Status::Unauthorized, "session ended")),
+                Err(()) => request::Outcome::Error((http::Status::ServiceUnavailable, "database unavailable")),
+            };
+        }
+
+        let grant = match token_grant(request).await {
+            Ok(Some(grant)) => grant.clone(),
+            Ok(None) => return request::Outcome::Forward(http::Status::Unauthorized),
+            Err(()) => return request::Outcome::Error((http::Status::ServiceUnavailable, "database unavailable")),
+        };
+        let authz = Authz {
+            user_id: grant.user_id,
+            credential: grant.credential,
+            scopes: Some(grant.scopes),
+        };
+        let Some(scope) = scope_required(request.method(), request.uri().path().as_str()) else {
+            return request::Outcome::Error((http::Status::Unauthorized, "tokens are not accepted here"));
+        };
+        if !authz.allows(&scope) {
+            let message = format!("The token lacks the {} scope", scope);
+            guard_error_set(
+                request,
+                ApiError::new(http::Status::Forbidden, ErrorCode::Forbidden, message)
+                    .details(json::json!({ "scope": scope })),
+            );
+            return request::Outcome::Error((http::Status::Forbidden, "insufficient scope"));
+        }
+        request::Outcome::Success(authz)
+    }
+}
+
+/// A personal access token, without its secret.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    /// `None` for tokens that last until deleted.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Creates a personal access token `id` of the user at `now`, returning it with its secret, which is
+/// only stored as a digest. The caller validates `scopes`.
+pub async fn api_token_create(
+    db: &mut sqlx::SqliteConnection,
+    user_id: i64,
+    name: &str,
+    scopes: &[String],
+    ttl_days: Option<i64>,
+    id: String,
+    now: DateTime<Utc>,
+) -> Result<(ApiToken, String), sqlx::Error> {
+    let secret = format!("{}{}", API_TOKEN_PREFIX, nanoid!(40));
+    let token = ApiToken {
+        id,
+        name: name.to_owned(),
+        scopes: scopes.to_vec(),
+        created_at: now,
+        expires_at: ttl_days.map(|days| now + TimeDelta::days(days)),
+    };
+    let (hash, scopes) = (token_hash(&secret), token.scopes.join(" "));
+    sqlx::query!(
+        "INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, created_at, expires_at) \
+        VALUES (?, ?, ?, ?, ?, ?, ?)",
+        token.id,
+        user_id,
+        token.name,
+        hash,
+        scopes,
+        token.created_at,
+        token.expires_at
+    )
+    .execute(db)
+    .instrument(query_span("api_tokens.insert"))
+    .await?;
+    Ok((token, secret))
+}
+
+/// Lists the personal access tokens of the user, expired ones included, newest first.
+pub async fn api_tokens_list(db: &mut sqlx::SqliteConnection, user_id: i64) -> Result<Vec<ApiToken>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT id, name, scopes, created_at AS \"created_at: DateTime<Utc>\", \
+        expires_at AS \"expires_at: DateTime<Utc>\" FROM api_tokens WHERE user_id = ? ORDER BY created_at DESC",
+        user_id
+    )
+    .fetch_all(db)
+    .instrument(query_span("api_tokens.list"))
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| ApiToken {
+            id: row.id,
+            name: row.name,
+            scopes: row.scopes.split_whitespace().map(str::to_owned).collect(),
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+        })
+        .collect())
+}
+
+/// Deletes a personal access token of the user. Returns `false` when the user has no such token.
+pub async fn api_token_delete(db: &mut sqlx::SqliteConnection, user_id: i64, id: &str) -> Result<bool, sqlx::Error> {
+    let deleted = sqlx::query!("DELETE FROM api_tokens WHERE id = ? AND user_id = ?", id, user_id)
+        .execute(db)
+        .instrument(query_span("api_tokens.delete"))
+        .await?
+        .rows_affected();
+    Ok(deleted > 0)
+}
+
+/// Returns the grant of a personal access token that has not expired by `now`.
+async fn api_token_lookup(
+    db: &sqlx::SqlitePool,
+    token: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<TokenGrant>, sqlx::Error> {
+    let hash = token_hash(token);
+    let row = sqlx::query!(
+        "SELECT id, user_id, scopes FROM api_tokens WHERE token_hash = ? AND (expires_at IS NULL OR expires_at > ?)",
+        hash,
+        now
+    )
+    .fetch_optional(db)
+    .instrument(query_span("api_tokens.by_hash"))
+    .await?;
+    Ok(row.map(|row| TokenGrant {
+        user_id: row.user_id,
+        credential: Credential::ApiToken { id: row.id },
+        scopes: row.scopes.split_whitespace().map(str::to_owned).collect(),
+    }))
+}
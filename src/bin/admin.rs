@@ -0,0 +1,328 @@
+//! Administration tasks against the app database, so that operators don't have to write SQL
+//! against the live SQLite file. Connects to `DATABASE_URL` (loaded from `.env` when present).
+//!
+//! Run `cargo run --bin admin -- help` for the list of commands.
+
+use std::env;
+use std::process::ExitCode;
+
+use rocket_sqlx::crypto::content_cipher;
+use rocket_sqlx::db::{MIGRATOR, Post, User, sqlx};
+use rocket_sqlx::handlers::admin::{user_reactivate, user_suspend, users_merge};
+use rocket_sqlx::scope::StoredContent;
+use rocket_sqlx::util::*;
+
+const USAGE: &str = "\
+Usage: admin <command>
+
+Commands:
+  users create <email>        Create a user
+  users disable <email>       Suspend a user: block sign-ins and revoke all sessions
+  users enable <email>        Allow a disabled user to sign in again
+  users reset-code <email>    Issue a fresh login code and print it
+  users merge <from> <into>   Move everything of the user <from> to <into> and disable <from>
+  posts list <email> [limit]  List a user's most recently updated posts (default 20)
+  posts delete <id>           Delete a post
+  posts rotate-key            Re-encrypt post and comment contents with the active CONTENT_KEY
+  posts rehash                Recompute post content hashes used by ?dedupe=true, titles and excerpts
+  migrate                     Apply pending database migrations
+  backup <path>               Write a consistent copy of the database to <path>";
+
+type AdminResult = Result<(), String>;
+
+async fn user_by_email(pool: &sqlx::SqlitePool, email: &str) -> Result<User, String> {
+    sqlx::query_as!(
+        User,
+        "SELECT id, created_at AS \"created_at: DateTime<Utc>\", email, code_hash, code_attempts, \
+        code_created_at AS \"code_created_at: DateTime<Utc>\", \
+        email_verified_at AS \"email_verified_at: DateTime<Utc>\", \
+        disabled_at AS \"disabled_at: DateTime<Utc>\" FROM users WHERE email = ?",
+        email
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("no user with email {}", email))
+}
+
+async fn users_create(pool: &sqlx::SqlitePool, email: &str) -> AdminResult {
+    if !email_is_valid(email) {
+        return Err(format!("invalid email: {}", email));
+    }
+    let now = Utc::now();
+    let id = sqlx::query!("INSERT INTO users (email, created_at) VALUES (?, ?)", email, now)
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .last_insert_rowid();
+    println!("created user {} ({})", id, email);
+    Ok(())
+}
+
+async fn users_disable(pool: &sqlx::SqlitePool, email: &str) -> AdminResult {
+    let user = user_by_email(pool, email).await?;
+    let mut db = pool.acquire().await.map_err(|e| e.to_string())?;
+    let revoked = user_suspend(&mut db, user.id, Utc::now())
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("no user with email {}", email))?;
+    println!("disabled user {} ({}), revoked {} session(s)", user.id, email, revoked);
+    Ok(())
+}
+
+async fn users_enable(pool: &sqlx::SqlitePool, email: &str) -> AdminResult {
+    let user = user_by_email(pool, email).await?;
+    let mut db = pool.acquire().await.map_err(|e| e.to_string())?;
+    user_reactivate(&mut db, user.id).await.map_err(|e| e.to_string())?;
+    println!("enabled user {} ({})", user.id, email);
+    Ok(())
+}
+
+async fn users_merge_cmd(pool: &sqlx::SqlitePool, source: &str, target: &str) -> AdminResult {
+    let source = user_by_email(pool, source).await?;
+    let target = user_by_email(pool, target).await?;
+    let mut db = pool.acquire().await.map_err(|e| e.to_string())?;
+    let counts = users_merge(&mut db, source.id, target.id, Utc::now())
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "users must be two distinct accounts that were not merged".to_string())?;
+    println!(
+        "merged user {} ({}) into {} ({}): moved {} post(s), {} session(s), {} preference(s)",
+        source.id, source.email, target.id, target.email, counts.posts, counts.sessions, counts.preferences
+    );
+    Ok(())
+}
+
+async fn users_reset_code(pool: &sqlx::SqlitePool, email: &str) -> AdminResult {
+    let user = user_by_email(pool, email).await?;
+    if user.disabled_at.is_some() {
+        return Err(format!("user {} is disabled, enable it first", email));
+    }
+
+    let code = code_gen();
+    let code_hash = hash_code(&code).await.map_err(|e| format!("{:?}", e))?;
+    let now = Utc::now();
+    sqlx::query!(
+        "UPDATE users SET code_attempts = 0, code_created_at = ?, code_hash = ? WHERE id = ?",
+        now,
+        code_hash,
+        user.id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    println!("login code for {}: {} (expires in 10 minutes)", email, code);
+    Ok(())
+}
+
+async fn posts_list(pool: &sqlx::SqlitePool, email: &str, limit: i64) -> AdminResult {
+    let user = user_by_email(pool, email).await?;
+    let posts = sqlx::query_as!(
+        Post,
+        "SELECT p.id, p.parent_id, p.content, p.created_at AS \"created_at: DateTime<Utc>\", \
+        p.updated_at AS \"updated_at: DateTime<Utc>\", p.version, p.user_id, p.variant, p.position, p.seq, p.org_id, \
+        p.remind_at AS \"remind_at: DateTime<Utc>\", p.title, p.excerpt, r.post_id IS NOT NULL AS \"favorited!: bool\" FROM posts p \
+        LEFT JOIN post_reactions r ON r.post_id = p.id AND r.user_id = p.user_id AND r.kind = 'favorite' \
+        WHERE p.user_id = ? ORDER BY p.updated_at DESC LIMIT ?",
+        user.id,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    for post in posts {
+        let post = post.content_decrypt();
+        let preview = post.content.chars().take(60).collect::<String>().replace('\n', " ");
+        println!(
+            "{}\t{}\t{}\t{}",
+            post.id,
+            post.updated_at.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true),
+            post.variant,
+            preview
+        );
+    }
+    Ok(())
+}
+
+async fn posts_delete(pool: &sqlx::SqlitePool, id: &str) -> AdminResult {
+    let deleted = sqlx::query!("DELETE FROM posts WHERE id = ?", id)
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(format!("no post with id {}", id));
+    }
+    println!("deleted post {}", id);
+    Ok(())
+}
+
+/// Rows processed per batch by `posts rotate-key` and `posts rehash`.
+const ROTATE_BATCH: i64 = 500;
+
+/// Tables with a `content` column encrypted by `ContentCipher`.
+const ENCRYPTED_TABLES: [&str; 2] = ["posts", "comments"];
+
+/// Re-encrypts every row of `table` whose content is not encrypted with the active key (or, with
+/// encryption disabled, decrypts it back to plain text). Safe to run while the server is writing: a
+/// row changed in between is left alone, as it was then written with the active key.
+async fn content_rotate_key(pool: &sqlx::SqlitePool, table: &str) -> Result<u64, String> {
+    let cipher = content_cipher();
+    let select = format!("SELECT id, content FROM {} WHERE id > ? ORDER BY id LIMIT ?", table);
+    let update = format!("UPDATE {} SET content = ? WHERE id = ? AND content = ?", table);
+    let mut after = String::new();
+    let mut rotated = 0;
+    loop {
+        let rows = sqlx::query_as::<_, (String, String)>(&select)
+            .bind(&after)
+            .bind(ROTATE_BATCH)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        let Some((last, _)) = rows.last() else {
+            break;
+        };
+        after = last.clone();
+
+        let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+        for (id, stored) in rows.iter().filter(|(_, stored)| cipher.needs_rotation(stored)) {
+            let plaintext = cipher.decrypt(stored).map_err(|e| format!("{} {}: {}", table, id, e))?;
+            rotated += sqlx::query(&update)
+                .bind(cipher.encrypt(&plaintext))
+                .bind(id)
+                .bind(stored)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?
+                .rows_affected();
+        }
+        tx.commit().await.map_err(|e| e.to_string())?;
+    }
+    Ok(rotated)
+}
+
+/// Re-encrypts posts and their comments with the active key, after which retired keys can be
+/// dropped from `CONTENT_KEYS_OLD`. Content hashes depend on the key, so they are recomputed too.
+async fn posts_rotate_key(pool: &sqlx::SqlitePool) -> AdminResult {
+    for table in ENCRYPTED_TABLES {
+        let rotated = content_rotate_key(pool, table).await?;
+        println!("rotated {} row(s) of {}", rotated, table);
+    }
+    posts_rehash(pool).await
+}
+
+/// Recomputes the duplicate detection hash of every post, which is keyed with the active content
+/// key and missing on posts written before hashes were introduced, along with the stored title and
+/// excerpt, which are missing on older posts and must not be kept once contents are encrypted.
+async fn posts_rehash(pool: &sqlx::SqlitePool) -> AdminResult {
+    let cipher = content_cipher();
+    let mut after = String::new();
+    let mut rehashed = 0;
+    loop {
+        let rows = sqlx::query!(
+            "SELECT id, content, content_hash, title, excerpt FROM posts WHERE id > ? ORDER BY id LIMIT ?",
+            after,
+            ROTATE_BATCH
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+        let Some(last) = rows.last() else {
+            break;
+        };
+        after = last.id.clone();
+
+        let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+        for row in rows {
+            let plaintext = cipher
+                .decrypt(&row.content)
+                .map_err(|e| format!("posts {}: {}", row.id, e))?;
+            let stored = StoredContent::of(&plaintext);
+            if row.content_hash.as_ref() == Some(&stored.content_hash)
+                && row.title == stored.title
+                && row.excerpt == stored.excerpt
+            {
+                continue;
+            }
+            // Skip rows whose content changed in between, they were hashed on write
+            rehashed += sqlx::query!(
+                "UPDATE posts SET content_hash = ?, title = ?, excerpt = ? WHERE id = ? AND content = ?",
+                stored.content_hash,
+                stored.title,
+                stored.excerpt,
+                row.id,
+                row.content
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?
+            .rows_affected();
+        }
+        tx.commit().await.map_err(|e| e.to_string())?;
+    }
+    println!("rehashed {} post(s)", rehashed);
+    Ok(())
+}
+
+async fn migrate(pool: &sqlx::SqlitePool) -> AdminResult {
+    MIGRATOR.run(pool).await.map_err(|e| e.to_string())?;
+    println!("migrations applied");
+    Ok(())
+}
+
+/// Copies the database with `VACUUM INTO`, which is safe to run while the server is writing.
+async fn backup(pool: &sqlx::SqlitePool, path: &str) -> AdminResult {
+    sqlx::query("VACUUM INTO ?")
+        .bind(path)
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    println!("backup written to {}", path);
+    Ok(())
+}
+
+async fn run(args: &[&str]) -> AdminResult {
+    if matches!(args, [] | ["help" | "-h" | "--help"]) {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    let url = env::var("DATABASE_URL").map_err(|_| "DATABASE_URL must be set".to_string())?;
+    let pool = sqlx::SqlitePool::connect(&url).await.map_err(|e| e.to_string())?;
+
+    match args {
+        ["users", "create", email] => users_create(&pool, email).await,
+        ["users", "disable", email] => users_disable(&pool, email).await,
+        ["users", "enable", email] => users_enable(&pool, email).await,
+        ["users", "reset-code", email] => users_reset_code(&pool, email).await,
+        ["users", "merge", source, target] => users_merge_cmd(&pool, source, target).await,
+        ["posts", "list", email] => posts_list(&pool, email, 20).await,
+        ["posts", "list", email, limit] => {
+            let limit = limit.parse().map_err(|_| format!("invalid limit: {}", limit))?;
+            posts_list(&pool, email, limit).await
+        }
+        ["posts", "delete", id] => posts_delete(&pool, id).await,
+        ["posts", "rotate-key"] => posts_rotate_key(&pool).await,
+        ["posts", "rehash"] => posts_rehash(&pool).await,
+        ["migrate"] => migrate(&pool).await,
+        ["backup", path] => backup(&pool, path).await,
+        _ => Err(format!("unknown command\n\n{}", USAGE)),
+    }
+}
+
+#[rocket::main]
+async fn main() -> ExitCode {
+    let _ = dotenv::dotenv();
+
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    match run(&args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,421 @@
+//! Load generator for a running instance: seeds synthetic users and posts, then replays a mix of
+//! post requests from concurrent clients and reports latency percentiles per request kind.
+//!
+//! Users are created directly in the database at `DATABASE_URL` (loaded from `.env` when present)
+//! and sign in through the login endpoint, so it must run where the SQLite file is reachable.
+//!
+//! Run `cargo run --release --bin loadgen -- --help` for the options.
+
+use std::collections::HashMap;
+use std::env;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use rand::Rng;
+use rand::seq::IndexedRandom;
+use reqwest::header::{COOKIE, HeaderMap, HeaderName, HeaderValue, SET_COOKIE};
+use rocket::serde::json;
+
+use rocket_sqlx::api::API_V1;
+use rocket_sqlx::db::{id_gen, sqlx};
+use rocket_sqlx::util::*;
+
+const USAGE: &str = "\
+Usage: loadgen [options]
+
+Options:
+  --url <url>           Base URL of the instance (default http://127.0.0.1:8000)
+  --users <n>           Users to seed (default 10)
+  --posts <n>           Posts to seed per user (default 100)
+  --concurrency <n>     Concurrent clients (default 50)
+  --duration <secs>     How long to replay requests (default 30)
+  --mix <kind=weight>   Request mix, comma-separated (default list=50,read=30,create=10,update=8,delete=2)";
+
+/// Posts per `upsert-many` request while seeding.
+const SEED_BATCH: usize = 500;
+
+struct Options {
+    url: String,
+    users: usize,
+    posts: usize,
+    concurrency: usize,
+    duration: Duration,
+    mix: Vec<(Kind, u32)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Kind {
+    /// A page of the newest posts.
+    List,
+    /// One post by ID.
+    Read,
+    /// A new post.
+    Create,
+    /// New content for an existing post.
+    Update,
+    /// Deletes a post.
+    Delete,
+}
+
+impl Kind {
+    fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "list" => Some(Self::List),
+            "read" => Some(Self::Read),
+            "create" => Some(Self::Create),
+            "update" => Some(Self::Update),
+            "delete" => Some(Self::Delete),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::List => "list",
+            Self::Read => "read",
+            Self::Create => "create",
+            Self::Update => "update",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+fn options_parse(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        url: "http://127.0.0.1:8000".into(),
+        users: 10,
+        posts: 100,
+        concurrency: 50,
+        duration: Duration::from_secs(30),
+        mix: mix_parse("list=50,read=30,create=10,update=8,delete=2")?,
+    };
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", flag));
+        let number = |value: &String| value.parse().map_err(|_| format!("invalid {}: {}", flag, value));
+        match flag.as_str() {
+            "--url" => options.url = value()?.trim_end_matches('/').to_owned(),
+            "--users" => options.users = number(value()?)?,
+            "--posts" => options.posts = number(value()?)?,
+            "--concurrency" => options.concurrency = number(value()?)?,
+            "--duration" => options.duration = Duration::from_secs(number(value()?)? as u64),
+            "--mix" => options.mix = mix_parse(value()?)?,
+            _ => return Err(format!("unknown option: {}", flag)),
+        }
+    }
+    if options.users == 0 || options.concurrency == 0 {
+        return Err("--users and --concurrency must be at least 1".into());
+    }
+    Ok(options)
+}
+
+fn mix_parse(mix: &str) -> Result<Vec<(Kind, u32)>, String> {
+    let mix = mix
+        .split(',')
+        .map(|entry| {
+            let (kind, weight) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("invalid mix entry: {}", entry))?;
+            let kind = Kind::parse(kind.trim()).ok_or_else(|| format!("unknown request kind: {}", kind))?;
+            let weight = weight
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid weight: {}", weight))?;
+            Ok((kind, weight))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    match mix.iter().any(|(_, weight)| *weight > 0) {
+        true => Ok(mix),
+        false => Err("the mix needs a positive weight".into()),
+    }
+}
+
+/// A signed-in user: the cookies and CSRF token of their session, and the IDs of their posts.
+struct Session {
+    headers: HeaderMap,
+    post_ids: Vec<String>,
+}
+
+/// Creates a user with a fresh login code, then signs in with it.
+async fn session_seed(
+    http: &reqwest::Client,
+    pool: &sqlx::SqlitePool,
+    url: &str,
+    email: &str,
+) -> Result<Session, String> {
+    let code = code_gen();
+    let code_hash = hash_code(&code).await.map_err(|e| format!("{:?}", e))?;
+    let now = Utc::now();
+    sqlx::query(
+        "INSERT INTO users (email, code_attempts, code_created_at, code_hash, created_at) VALUES (?, 0, ?, ?, ?) \
+        ON CONFLICT(email) DO UPDATE SET code_attempts = 0, code_created_at = excluded.code_created_at, \
+        code_hash = excluded.code_hash",
+    )
+    .bind(email)
+    .bind(now)
+    .bind(code_hash)
+    .bind(now)
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let response = http
+        .post(format!("{}{}/session/login", url, API_V1))
+        .json(&json::json!({ "email": email, "code": code }))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("login of {} failed: {}", email, response.status()));
+    }
+    // Echo the session cookies back, like a browser would
+    let cookies = response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|cookie| cookie.to_str().ok()?.split(';').next())
+        .collect::<Vec<_>>()
+        .join("; ");
+    let body = response.json::<json::Value>().await.map_err(|e| e.to_string())?;
+    let csrf_token = body["data"]["csrfToken"]
+        .as_str()
+        .ok_or("login returned no CSRF token")?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(COOKIE, HeaderValue::from_str(&cookies).map_err(|e| e.to_string())?);
+    headers.insert(
+        HeaderName::from_static("x-csrf-token"),
+        HeaderValue::from_str(csrf_token).map_err(|e| e.to_string())?,
+    );
+    Ok(Session {
+        headers,
+        post_ids: Vec::new(),
+    })
+}
+
+/// Synthetic post content of a few sentences, so that rows are about the size of real notes.
+fn content_gen() -> String {
+    const WORDS: &[&str] = &[
+        "meeting", "notes", "project", "draft", "idea", "review", "follow", "up", "with", "the", "team", "about",
+        "release", "plan", "bug", "fix", "design", "todo", "weekly", "summary",
+    ];
+    let mut rng = rand::rng();
+    let words = rng.random_range(10..80);
+    (0..words)
+        .map(|_| *WORDS.choose(&mut rng).expect("words"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn post_gen() -> json::Value {
+    let now = Utc::now().to_rfc3339();
+    json::json!({
+        "id": id_gen(),
+        "content": content_gen(),
+        "variant": "note",
+        "createdAt": now,
+        "updatedAt": now,
+    })
+}
+
+async fn posts_seed(http: &reqwest::Client, url: &str, session: &mut Session, count: usize) -> Result<(), String> {
+    let mut remaining = count;
+    while remaining > 0 {
+        let batch = (0..remaining.min(SEED_BATCH)).map(|_| post_gen()).collect::<Vec<_>>();
+        remaining -= batch.len();
+        let response = http
+            .post(format!("{}{}/posts/upsert-many", url, API_V1))
+            .headers(session.headers.clone())
+            .json(&batch)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("seeding posts failed: {}", response.status()));
+        }
+        session
+            .post_ids
+            .extend(batch.iter().map(|post| post["id"].as_str().expect("id").to_owned()));
+    }
+    Ok(())
+}
+
+/// Latencies of the requests of one kind, and how many of them failed.
+#[derive(Default)]
+struct Samples {
+    latencies: Vec<Duration>,
+    errors: u64,
+}
+
+/// Sends one request of `kind` as `session`. Returns whether it succeeded.
+async fn request_send(http: &reqwest::Client, url: &str, session: &Session, kind: Kind) -> bool {
+    let posts = format!("{}{}/posts", url, API_V1);
+    let id = || session.post_ids.choose(&mut rand::rng()).cloned();
+    let request = match kind {
+        Kind::List => http.get(format!("{}?limit=20", posts)),
+        Kind::Read => match id() {
+            Some(id) => http.get(format!("{}/{}", posts, id)),
+            None => return true,
+        },
+        Kind::Create => http.post(&posts).json(&post_gen()),
+        Kind::Update => match id() {
+            Some(id) => {
+                let body = json::json!({ "content": content_gen(), "updatedAt": Utc::now().to_rfc3339() });
+                http.put(format!("{}/{}", posts, id)).json(&body)
+            }
+            None => return true,
+        },
+        // Deleting a post another worker already deleted is a 404, which is fine
+        Kind::Delete => match id() {
+            Some(id) => http.delete(format!("{}/{}", posts, id)),
+            None => return true,
+        },
+    };
+    match request.headers(session.headers.clone()).send().await {
+        Ok(response) => {
+            let status = response.status();
+            // Read the body so that its transfer counts towards the latency
+            let _ = response.bytes().await;
+            status.is_success() || status == reqwest::StatusCode::NOT_FOUND
+        }
+        Err(_) => false,
+    }
+}
+
+/// Sends requests drawn from the mix until `deadline`.
+async fn worker(
+    http: reqwest::Client,
+    url: Arc<str>,
+    sessions: Arc<Vec<Session>>,
+    mix: Arc<Vec<(Kind, u32)>>,
+    deadline: Instant,
+) -> HashMap<Kind, Samples> {
+    let mut samples = HashMap::<Kind, Samples>::new();
+    while Instant::now() < deadline {
+        let (session, kind) = {
+            let mut rng = rand::rng();
+            let session = sessions.choose(&mut rng).expect("sessions");
+            let (kind, _) = mix.choose_weighted(&mut rng, |(_, weight)| *weight).expect("mix");
+            (session, *kind)
+        };
+        let start = Instant::now();
+        let ok = request_send(&http, &url, session, kind).await;
+        let entry = samples.entry(kind).or_default();
+        entry.latencies.push(start.elapsed());
+        if !ok {
+            entry.errors += 1;
+        }
+    }
+    samples
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len());
+    sorted[rank - 1]
+}
+
+fn report(mut samples: HashMap<Kind, Samples>, elapsed: Duration) {
+    let ms = |d: Duration| format!("{:.2}", d.as_secs_f64() * 1000.0);
+    let mut kinds = samples.keys().copied().collect::<Vec<_>>();
+    kinds.sort();
+    let mut total = 0;
+    println!("kind\trequests\terrors\treq/s\tp50 ms\tp90 ms\tp99 ms\tmax ms");
+    for kind in kinds {
+        let Samples { mut latencies, errors } = samples.remove(&kind).expect("kind");
+        latencies.sort();
+        total += latencies.len();
+        println!(
+            "{}\t{}\t{}\t{:.0}\t{}\t{}\t{}\t{}",
+            kind.as_str(),
+            latencies.len(),
+            errors,
+            latencies.len() as f64 / elapsed.as_secs_f64(),
+            ms(percentile(&latencies, 0.5)),
+            ms(percentile(&latencies, 0.9)),
+            ms(percentile(&latencies, 0.99)),
+            ms(*latencies.last().expect("latencies")),
+        );
+    }
+    println!(
+        "total\t{}\t\t{:.0} req/s over {:.1}s",
+        total,
+        total as f64 / elapsed.as_secs_f64(),
+        elapsed.as_secs_f64()
+    );
+}
+
+async fn run(args: &[String]) -> Result<(), String> {
+    if matches!(args.first().map(String::as_str), Some("help" | "-h" | "--help")) {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let options = options_parse(args)?;
+
+    let database_url = env::var("DATABASE_URL").map_err(|_| "DATABASE_URL must be set".to_string())?;
+    let pool = sqlx::SqlitePool::connect(&database_url)
+        .await
+        .map_err(|e| e.to_string())?;
+    let http = reqwest::Client::builder()
+        .pool_max_idle_per_host(options.concurrency)
+        .timeout(Duration::from_secs(30))
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    println!("seeding {} user(s) with {} post(s) each", options.users, options.posts);
+    let mut sessions = Vec::with_capacity(options.users);
+    for i in 0..options.users {
+        let email = format!("loadgen+{}@example.com", i);
+        let mut session = session_seed(&http, &pool, &options.url, &email).await?;
+        posts_seed(&http, &options.url, &mut session, options.posts).await?;
+        sessions.push(session);
+    }
+
+    println!(
+        "replaying requests from {} client(s) for {}s",
+        options.concurrency,
+        options.duration.as_secs()
+    );
+    let url = Arc::<str>::from(options.url.as_str());
+    let sessions = Arc::new(sessions);
+    let mix = Arc::new(options.mix);
+    let start = Instant::now();
+    let deadline = start + options.duration;
+    let workers = (0..options.concurrency)
+        .map(|_| {
+            rocket::tokio::spawn(worker(
+                http.clone(),
+                url.clone(),
+                sessions.clone(),
+                mix.clone(),
+                deadline,
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    let mut samples = HashMap::<Kind, Samples>::new();
+    for worker in workers {
+        for (kind, worker_samples) in worker.await.map_err(|e| e.to_string())? {
+            let entry = samples.entry(kind).or_default();
+            entry.latencies.extend(worker_samples.latencies);
+            entry.errors += worker_samples.errors;
+        }
+    }
+    report(samples, start.elapsed());
+    Ok(())
+}
+
+#[rocket::main]
+async fn main() -> ExitCode {
+    let _ = dotenv::dotenv();
+
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    match run(&args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
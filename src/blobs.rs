@@ -0,0 +1,282 @@
+use rocket::fairing::AdHoc;
+use rocket::serde::Serialize;
+use rocket::tokio::sync::Mutex;
+use rocket::tokio::{fs, time};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tracing::Instrument;
+
+use crate::db::*;
+use crate::metrics::metrics;
+use crate::util::*;
+
+/// Which attachments share a blob when their bytes are the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupeScope {
+    /// Only the same user's. Users can't learn whether someone else uploaded a file from how
+    /// quickly theirs is stored.
+    User,
+    /// Anyone's.
+    Global,
+}
+
+/// Attachments are up to `ATTACHMENT_MAX_BYTES` (10 MiB) and deduplicated per `ATTACHMENTS_DEDUPE`
+/// (`user` or `global`). Unless `ATTACHMENTS_GC` is `false`, blobs left without attachments are
+/// removed every `ATTACHMENTS_GC_INTERVAL_SECS` (3600), once unreferenced for
+/// `ATTACHMENTS_GC_GRACE_SECS` (3600).
+#[derive(Debug, Clone)]
+pub struct BlobsConfig {
+    pub max_bytes: u64,
+    pub dedupe: DedupeScope,
+    pub gc_enabled: bool,
+    pub gc_interval: Duration,
+    pub gc_grace_secs: i64,
+}
+
+impl BlobsConfig {
+    pub fn from_env() -> Self {
+        let dedupe = match config_var("ATTACHMENTS_DEDUPE").unwrap_or_default().as_str() {
+            "" | "user" => DedupeScope::User,
+            "global" => DedupeScope::Global,
+            other => panic!("ATTACHMENTS_DEDUPE has an invalid value: {}", other),
+        };
+        Self {
+            max_bytes: env_parse_or("ATTACHMENT_MAX_BYTES", 10 * 1024 * 1024),
+            dedupe,
+            gc_enabled: env_parse_or("ATTACHMENTS_GC", true),
+            gc_interval: Duration::from_secs(env_parse_or("ATTACHMENTS_GC_INTERVAL_SECS", 3600)),
+            gc_grace_secs: env_parse_or("ATTACHMENTS_GC_GRACE_SECS", 3600),
+        }
+    }
+}
+
+/// Returns the process-wide `BlobsConfig`.
+pub fn blobs_config() -> &'static BlobsConfig {
+    static CONFIG: OnceLock<BlobsConfig> = OnceLock::new();
+    CONFIG.get_or_init(BlobsConfig::from_env)
+}
+
+/// A file attached to a post.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct Attachment {
+    pub id: String,
+    pub post_id: String,
+    #[serde(skip)]
+    pub user_id: i64,
+    #[serde(skip)]
+    pub blob_key: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Why a blob could not be stored or removed.
+#[derive(Debug)]
+pub enum BlobError {
+    Db(sqlx::Error),
+    Io(io::Error),
+}
+
+impl std::fmt::Display for BlobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Db(e) => write!(f, "blob database error: {}", e),
+            Self::Io(e) => write!(f, "blob storage error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for BlobError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Db(e)
+    }
+}
+
+impl From<io::Error> for BlobError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Returns the key of the blob holding `bytes` uploaded by the user.
+pub fn blob_key(user_id: i64, bytes: &[u8], scope: DedupeScope) -> (String, String) {
+    let sha256 = format!("{:x}", Sha256::digest(bytes));
+    let key = match scope {
+        DedupeScope::User => format!("{}-{}", user_id, sha256),
+        DedupeScope::Global => sha256.clone(),
+    };
+    (key, sha256)
+}
+
+/// Managed state storing blobs as files under `ATTACHMENTS_DIR` (`data/blobs`), named after their
+/// key. Writes of blobs and their collection are serialized, so that a blob is never removed
+/// while an upload is starting to share it.
+#[derive(Clone)]
+pub struct BlobStore {
+    dir: PathBuf,
+    lock: Arc<Mutex<()>>,
+}
+
+impl BlobStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(config_var("ATTACHMENTS_DIR").unwrap_or_else(|| "data/blobs".into()))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(&key[key.len() - 2..]).join(key)
+    }
+
+    pub async fn read(&self, key: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.path(key)).await
+    }
+
+    /// Writes the blob unless it is already stored, through a temporary file so that a crash
+    /// never leaves a partial blob behind.
+    async fn write(&self, key: &str, bytes: &[u8]) -> io::Result<()> {
+        let path = self.path(key);
+        if fs::try_exists(&path).await? {
+            return Ok(());
+        }
+        fs::create_dir_all(path.parent().expect("blob paths have a parent")).await?;
+        let temporary = path.with_extension(format!("tmp-{}", id_gen()));
+        fs::write(&temporary, bytes).await?;
+        fs::rename(&temporary, &path).await
+    }
+
+    async fn remove(&self, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(key)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Attaches `bytes` to the post, storing them unless a blob with the same key already exists.
+/// `attachment.blob_key` must be the `blob_key` of the bytes.
+pub async fn attachment_create(
+    db: &mut sqlx::SqliteConnection,
+    store: &BlobStore,
+    attachment: &Attachment,
+    sha256: &str,
+    bytes: &[u8],
+) -> Result<(), BlobError> {
+    let _guard = store.lock.lock().await;
+    let mut tx = sqlx::Connection::begin(db).await?;
+    let size = bytes.len() as i64;
+    sqlx::query!(
+        "INSERT INTO blobs (key, sha256, size, created_at) VALUES (?, ?, ?, ?) ON CONFLICT (key) DO NOTHING",
+        attachment.blob_key,
+        sha256,
+        size,
+        attachment.created_at
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("blobs.insert"))
+    .await?;
+    sqlx::query!(
+        "INSERT INTO attachments (id, post_id, user_id, blob_key, filename, content_type, created_at) \
+        VALUES (?, ?, ?, ?, ?, ?, ?)",
+        attachment.id,
+        attachment.post_id,
+        attachment.user_id,
+        attachment.blob_key,
+        attachment.filename,
+        attachment.content_type,
+        attachment.created_at
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("attachments.insert"))
+    .await?;
+
+    // Written before committing, so that a stored attachment always has its bytes
+    store.write(&attachment.blob_key, bytes).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Removes up to `batch` blobs without attachments for longer than the grace period, rows and
+/// files. Returns how many were removed.
+pub async fn blobs_gc(
+    db: &mut sqlx::SqliteConnection,
+    store: &BlobStore,
+    grace_secs: i64,
+    batch: i64,
+) -> Result<u64, BlobError> {
+    let _guard = store.lock.lock().await;
+    let cutoff = Utc::now() - chrono::TimeDelta::seconds(grace_secs);
+    let keys = sqlx::query_scalar!(
+        "SELECT key FROM blobs WHERE ref_count = 0 AND unreferenced_at <= ? LIMIT ?",
+        cutoff,
+        batch
+    )
+    .fetch_all(&mut *db)
+    .instrument(query_span("blobs.unreferenced"))
+    .await?;
+
+    let mut removed = 0;
+    for key in keys {
+        let deleted = sqlx::query!("DELETE FROM blobs WHERE key = ? AND ref_count = 0", key)
+            .execute(&mut *db)
+            .instrument(query_span("blobs.delete"))
+            .await?
+            .rows_affected();
+        if deleted > 0 {
+            store.remove(&key).await?;
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        metrics().counter_add(
+            "blobs_collected_total",
+            "Unreferenced attachment blobs removed.",
+            &[],
+            removed,
+        );
+    }
+    Ok(removed)
+}
+
+/// Rows looked at per round of the GC job.
+const GC_BATCH: i64 = 500;
+
+async fn gc(pool: sqlx::SqlitePool, store: BlobStore, config: &'static BlobsConfig) {
+    loop {
+        time::sleep(config.gc_interval).await;
+        let collected = match pool.acquire().await {
+            Ok(mut db) => blobs_gc(&mut db, &store, config.gc_grace_secs, GC_BATCH).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = collected {
+            tracing::warn!("blob GC round failed: {}", e);
+        }
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Blobs stage", |rocket| async {
+        let rocket = manage_default(rocket, |_| BlobStore::from_env());
+        rocket.attach(AdHoc::on_liftoff("Blob GC", |rocket| {
+            Box::pin(async move {
+                let config = blobs_config();
+                let Some(db) = Db::fetch(rocket).filter(|_| config.gc_enabled) else {
+                    return;
+                };
+                let store = rocket.state::<BlobStore>().expect("blob store is managed").clone();
+                rocket::tokio::spawn(gc((**db).clone(), store, config));
+            })
+        }))
+    })
+}
@@ -0,0 +1,82 @@
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use rocket::futures::StreamExt;
+use rocket::serde::json;
+use rocket::tokio::sync::OnceCell;
+use rocket::tokio::time;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::config_var;
+use crate::events::{Event, EventSink, event_broadcast};
+
+/// Carries outbox events between instances over the Redis pub/sub channel
+/// `REDIS_EVENTS_CHANNEL` ("post-changes") of `REDIS_URL`. Each instance's dispatcher publishes
+/// its events, and every instance, itself included, relays what it receives to its own subscribers.
+#[derive(Clone)]
+pub struct RedisBus {
+    client: redis::Client,
+    channel: String,
+    publisher: Arc<OnceCell<ConnectionManager>>,
+}
+
+impl RedisBus {
+    /// Returns `None` when `REDIS_URL` is unset or empty.
+    pub fn from_env() -> Option<Self> {
+        let url = config_var("REDIS_URL").filter(|url| !url.is_empty())?;
+        Some(Self {
+            client: redis::Client::open(url).expect("invalid REDIS_URL"),
+            channel: config_var("REDIS_EVENTS_CHANNEL").unwrap_or_else(|| "post-changes".into()),
+            publisher: Arc::new(OnceCell::new()),
+        })
+    }
+
+    /// Relays the channel to the subscribers of this process forever, resubscribing after errors.
+    /// Events published while the subscription is down are not replayed.
+    pub async fn forward(self) {
+        loop {
+            if let Err(e) = self.subscribe().await {
+                tracing::warn!("redis event bus subscription failed: {}", e);
+            }
+            time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    async fn subscribe(&self) -> redis::RedisResult<()> {
+        let mut pubsub = self.client.get_async_pubsub().await?;
+        pubsub.subscribe(&self.channel).await?;
+        let mut messages = pubsub.on_message();
+        while let Some(message) = messages.next().await {
+            let payload: String = message.get_payload()?;
+            match json::from_str::<Event>(&payload) {
+                Ok(event) => event_broadcast(&event),
+                Err(e) => tracing::warn!("ignoring malformed event on the redis bus: {}", e),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[rocket::async_trait]
+impl EventSink for RedisBus {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    async fn deliver(&self, events: &[Event]) -> Result<(), String> {
+        let publisher = self
+            .publisher
+            .get_or_try_init(|| self.client.get_connection_manager())
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut publisher = publisher.clone();
+        for event in events {
+            let payload = json::to_string(event).map_err(|e| e.to_string())?;
+            let _: i64 = publisher
+                .publish(&self.channel, payload)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use moka::future::Cache;
+use rocket::serde::json;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::metrics::metrics;
+use crate::util::*;
+
+/// Responses of the posts `read` and `list` endpoints are cached per user and query for
+/// `RESPONSE_CACHE_TTL_SECS` (30), up to `RESPONSE_CACHE_MAX_ENTRIES` (10000) of them. Either set
+/// to 0 disables the cache.
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    pub ttl: Duration,
+    pub max_entries: u64,
+}
+
+impl CacheConfig {
+    pub fn from_env() -> Self {
+        Self {
+            ttl: Duration::from_secs(env_parse_or("RESPONSE_CACHE_TTL_SECS", 30)),
+            max_entries: env_parse_or("RESPONSE_CACHE_MAX_ENTRIES", 10_000),
+        }
+    }
+}
+
+/// Returns the process-wide `CacheConfig`.
+pub fn cache_config() -> &'static CacheConfig {
+    static CONFIG: OnceLock<CacheConfig> = OnceLock::new();
+    CONFIG.get_or_init(CacheConfig::from_env)
+}
+
+/// JSON response bodies keyed by user and request. `Scope` drops a user's entries whenever it
+/// writes their posts, and the events dispatcher drops them again once the write is committed, so
+/// that a read racing the transaction cannot keep the old rows cached until they expire. Only reads
+/// of the user's own posts are cached; organization posts and shared links are read uncached, so
+/// writes by other users never leave an entry stale. Writes outside `Scope` that move or hide a
+/// user's posts (shares, admin suspends and merges) drop the entries of every user involved.
+pub struct ResponseCache {
+    entries: Option<Cache<(i64, String), json::Value>>,
+}
+
+impl ResponseCache {
+    pub fn new(config: &CacheConfig) -> Self {
+        let enabled = !config.ttl.is_zero() && config.max_entries > 0;
+        Self {
+            entries: enabled.then(|| {
+                Cache::builder()
+                    .max_capacity(config.max_entries)
+                    .time_to_live(config.ttl)
+                    .support_invalidation_closures()
+                    .build()
+            }),
+        }
+    }
+
+    /// Returns the cached response of `endpoint` to `key`, counting the hit or miss.
+    pub async fn get(&self, user_id: i64, endpoint: &'static str, key: &str) -> Option<json::Value> {
+        let entries = self.entries.as_ref()?;
+        let cached = entries.get(&(user_id, key.to_owned())).await;
+        let (name, help) = match cached {
+            Some(_) => ("response_cache_hits_total", "Responses served from the cache."),
+            None => ("response_cache_misses_total", "Cacheable responses not in the cache."),
+        };
+        metrics().counter_inc(name, help, &[("endpoint", endpoint)]);
+        cached
+    }
+
+    pub async fn insert(&self, user_id: i64, key: String, response: json::Value) {
+        if let Some(entries) = &self.entries {
+            entries.insert((user_id, key), response).await;
+        }
+    }
+
+    /// Drops every response cached for the user.
+    pub fn invalidate_user(&self, user_id: i64) {
+        if let Some(entries) = &self.entries {
+            entries
+                .invalidate_entries_if(move |(cached_user_id, _), _| *cached_user_id == user_id)
+                .expect("invalidation closures are enabled");
+        }
+    }
+}
+
+/// Returns the process-wide `ResponseCache`.
+pub fn response_cache() -> &'static ResponseCache {
+    static CACHE: OnceLock<ResponseCache> = OnceLock::new();
+    CACHE.get_or_init(|| ResponseCache::new(cache_config()))
+}
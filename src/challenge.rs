@@ -0,0 +1,101 @@
+use rocket::serde::Deserialize;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use crate::config::config_var;
+
+/// Verifies an anti-bot challenge token (CAPTCHA) submitted by a client.
+#[rocket::async_trait]
+pub trait ChallengeVerifier: Send + Sync {
+    /// Returns `Ok(true)` if the token is valid, `Ok(false)` if it was rejected, and `Err` if the
+    /// provider could not be reached.
+    async fn verify(&self, token: &str, remote_ip: Option<&str>) -> Result<bool, String>;
+}
+
+/// Managed state holding the challenge verifier guarding open endpoints such as `send-code`.
+/// When no verifier is configured, challenges are not required.
+#[derive(Clone, Default)]
+pub struct ChallengeGate(pub Option<Arc<dyn ChallengeVerifier>>);
+
+impl ChallengeGate {
+    /// Builds the gate from `CAPTCHA_PROVIDER` (`turnstile` or `hcaptcha`) and `CAPTCHA_SECRET`.
+    pub fn from_env() -> Self {
+        let provider = config_var("CAPTCHA_PROVIDER").unwrap_or_default();
+        let secret = || config_var("CAPTCHA_SECRET").expect("CAPTCHA_SECRET must be set when CAPTCHA_PROVIDER is");
+        let verifier: Option<Arc<dyn ChallengeVerifier>> = match provider.as_str() {
+            "" => None,
+            "turnstile" => Some(Arc::new(Turnstile { secret: secret() })),
+            "hcaptcha" => Some(Arc::new(HCaptcha { secret: secret() })),
+            other => panic!("CAPTCHA_PROVIDER has an invalid value: {}", other),
+        };
+        Self(verifier)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct SiteVerifyResponse {
+    success: bool,
+}
+
+/// Returns the shared HTTP client used to call challenge providers.
+fn http_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .expect("failed to build HTTP client")
+    })
+}
+
+/// Calls a `siteverify` endpoint, the protocol shared by Turnstile, hCaptcha and reCAPTCHA.
+async fn siteverify(url: &str, secret: &str, token: &str, remote_ip: Option<&str>) -> Result<bool, String> {
+    let mut form = vec![("secret", secret), ("response", token)];
+    if let Some(ip) = remote_ip {
+        form.push(("remoteip", ip));
+    }
+
+    let response = http_client()
+        .post(url)
+        .form(&form)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .error_for_status()
+        .map_err(|e| e.to_string())?
+        .json::<SiteVerifyResponse>()
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(response.success)
+}
+
+/// Cloudflare Turnstile verifier.
+pub struct Turnstile {
+    pub secret: String,
+}
+
+#[rocket::async_trait]
+impl ChallengeVerifier for Turnstile {
+    async fn verify(&self, token: &str, remote_ip: Option<&str>) -> Result<bool, String> {
+        siteverify(
+            "https://challenges.cloudflare.com/turnstile/v0/siteverify",
+            &self.secret,
+            token,
+            remote_ip,
+        )
+        .await
+    }
+}
+
+/// hCaptcha verifier.
+pub struct HCaptcha {
+    pub secret: String,
+}
+
+#[rocket::async_trait]
+impl ChallengeVerifier for HCaptcha {
+    async fn verify(&self, token: &str, remote_ip: Option<&str>) -> Result<bool, String> {
+        siteverify("https://api.hcaptcha.com/siteverify", &self.secret, token, remote_ip).await
+    }
+}
@@ -0,0 +1,172 @@
+use rocket::tokio::task_local;
+use std::borrow::Cow;
+use std::future::Future;
+use std::sync::{Arc, OnceLock};
+
+use crate::db::sqlx;
+use crate::util::*;
+
+/// A failure the database layer can be made to fail with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// `SQLITE_BUSY`, as when another connection holds the write lock.
+    Busy,
+    /// No connection could be acquired in time.
+    Timeout,
+    /// The connection went away mid-operation.
+    Dropped,
+}
+
+impl Fault {
+    /// The error sqlx reports for such a failure.
+    pub fn error(self) -> sqlx::Error {
+        match self {
+            Self::Busy => sqlx::Error::Database(Box::new(InjectedBusy)),
+            Self::Timeout => sqlx::Error::PoolTimedOut,
+            Self::Dropped => sqlx::Error::Io(std::io::Error::new(
+                std::io::ErrorKind::ConnectionReset,
+                "injected fault: connection dropped",
+            )),
+        }
+    }
+}
+
+/// The `SQLITE_BUSY` database error of `Fault::Busy`, which can't be built as a real `SqliteError`.
+#[derive(Debug)]
+struct InjectedBusy;
+
+impl std::fmt::Display for InjectedBusy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "(code: 5) database is locked")
+    }
+}
+
+impl std::error::Error for InjectedBusy {}
+
+impl sqlx::error::DatabaseError for InjectedBusy {
+    fn message(&self) -> &str {
+        "database is locked"
+    }
+
+    fn code(&self) -> Option<Cow<'_, str>> {
+        Some(Cow::Borrowed("5"))
+    }
+
+    fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+        self
+    }
+
+    fn kind(&self) -> sqlx::error::ErrorKind {
+        sqlx::error::ErrorKind::Other
+    }
+}
+
+/// Odds, in `0.0..=1.0`, that a database operation fails with each `Fault`, from
+/// `DB_FAULT_BUSY_RATE`, `DB_FAULT_TIMEOUT_RATE` and `DB_FAULT_DROP_RATE` (all 0). For trying out
+/// how clients and the retry and transaction logic cope with a flaky database; only debug builds
+/// honor them.
+#[derive(Debug, Clone, Default)]
+pub struct FaultConfig {
+    pub busy_rate: f64,
+    pub timeout_rate: f64,
+    pub drop_rate: f64,
+}
+
+impl FaultConfig {
+    pub fn from_env() -> Self {
+        let config = Self {
+            busy_rate: env_parse_or("DB_FAULT_BUSY_RATE", 0.0),
+            timeout_rate: env_parse_or("DB_FAULT_TIMEOUT_RATE", 0.0),
+            drop_rate: env_parse_or("DB_FAULT_DROP_RATE", 0.0),
+        };
+        if !config.is_active() {
+            return config;
+        }
+        if !cfg!(debug_assertions) {
+            tracing::warn!("DB_FAULT_* settings are ignored outside debug builds");
+            return Self::default();
+        }
+        tracing::warn!(
+            busy_rate = config.busy_rate,
+            timeout_rate = config.timeout_rate,
+            drop_rate = config.drop_rate,
+            "injecting database faults"
+        );
+        config
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.busy_rate > 0.0 || self.timeout_rate > 0.0 || self.drop_rate > 0.0
+    }
+}
+
+/// Fails database operations at random, per its `FaultConfig`.
+#[derive(Debug, Default)]
+pub struct FaultInjector {
+    config: FaultConfig,
+}
+
+impl FaultInjector {
+    pub fn new(config: FaultConfig) -> Self {
+        Self { config }
+    }
+
+    /// Draws whether the next operation fails, and how.
+    pub fn roll(&self) -> Option<Fault> {
+        if !self.config.is_active() {
+            return None;
+        }
+        let draw = rand::random::<f64>();
+        let busy = self.config.busy_rate;
+        let timeout = busy + self.config.timeout_rate;
+        let dropped = timeout + self.config.drop_rate;
+        if draw < busy {
+            Some(Fault::Busy)
+        } else if draw < timeout {
+            Some(Fault::Timeout)
+        } else if draw < dropped {
+            Some(Fault::Dropped)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the process-wide `FaultInjector`, configured from the environment.
+pub fn fault_injector() -> &'static FaultInjector {
+    static INJECTOR: OnceLock<FaultInjector> = OnceLock::new();
+    INJECTOR.get_or_init(|| FaultInjector::new(FaultConfig::from_env()))
+}
+
+task_local! {
+    static SCOPED: Arc<FaultInjector>;
+}
+
+/// Runs `future` with `injector` in place of the process-wide one, so that a test can fail its own
+/// operations without touching others running alongside.
+pub async fn faults_scoped<F: Future>(injector: Arc<FaultInjector>, future: F) -> F::Output {
+    SCOPED.scope(injector, future).await
+}
+
+/// A point where the database layer may fail on purpose. `Ok` unless the injector in effect draws a
+/// fault, which is logged with `op`.
+pub fn fault_point(op: &'static str) -> Result<(), sqlx::Error> {
+    let fault = SCOPED
+        .try_with(|injector| injector.roll())
+        .unwrap_or_else(|_| fault_injector().roll());
+    match fault {
+        Some(fault) => {
+            tracing::debug!(op, ?fault, "injected database fault");
+            Err(fault.error())
+        }
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,231 @@
+use ipnet::IpNet;
+use rocket::Request;
+use rocket::request::{self, FromRequest};
+use rocket::serde::Serialize;
+use std::net::IpAddr;
+
+use crate::util::*;
+
+/// Browser, OS and device class parsed from a `User-Agent` header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct UserAgentInfo {
+    pub browser: Option<String>,
+    pub os: Option<String>,
+    /// One of `desktop`, `mobile`, `tablet` or `bot`.
+    pub device: &'static str,
+}
+
+/// Returns the major version following `token` (e.g. `Firefox/`) in the user agent, if any.
+fn token_version(ua: &str, token: &str) -> Option<String> {
+    let start = ua.find(token)? + token.len();
+    let version: String = ua[start..].chars().take_while(|c| c.is_ascii_digit()).collect();
+    (!version.is_empty()).then_some(version)
+}
+
+/// Formats a browser name with its major version when one can be found.
+fn browser_name(ua: &str, name: &str, token: &str) -> String {
+    match token_version(ua, token) {
+        Some(version) => format!("{} {}", name, version),
+        None => name.to_string(),
+    }
+}
+
+/// Parses the parts of a user agent that are useful for recognizing a session. This is a heuristic
+/// covering the mainstream browsers, not a full user-agent database.
+pub fn user_agent_parse(ua: &str) -> UserAgentInfo {
+    let lower = ua.to_ascii_lowercase();
+
+    let device = if ["bot", "crawler", "spider"].iter().any(|t| lower.contains(t)) {
+        "bot"
+    } else if lower.contains("ipad")
+        || lower.contains("tablet")
+        || (lower.contains("android") && !lower.contains("mobile"))
+    {
+        "tablet"
+    } else if lower.contains("mobile") || lower.contains("iphone") {
+        "mobile"
+    } else {
+        "desktop"
+    };
+
+    let os = if ua.contains("Windows") {
+        Some("Windows")
+    } else if ua.contains("iPhone") || ua.contains("iPad") {
+        Some("iOS")
+    } else if ua.contains("Mac OS X") || ua.contains("Macintosh") {
+        Some("macOS")
+    } else if ua.contains("Android") {
+        Some("Android")
+    } else if ua.contains("CrOS") {
+        Some("ChromeOS")
+    } else if ua.contains("Linux") {
+        Some("Linux")
+    } else {
+        None
+    };
+
+    // Order matters: Edge and Opera also claim to be Chrome, and Chrome claims to be Safari
+    let browser = if ua.contains("Edg/") {
+        Some(browser_name(ua, "Edge", "Edg/"))
+    } else if ua.contains("OPR/") {
+        Some(browser_name(ua, "Opera", "OPR/"))
+    } else if ua.contains("Firefox/") {
+        Some(browser_name(ua, "Firefox", "Firefox/"))
+    } else if ua.contains("Chrome/") {
+        Some(browser_name(ua, "Chrome", "Chrome/"))
+    } else if ua.contains("Safari/") && ua.contains("Version/") {
+        Some(browser_name(ua, "Safari", "Version/"))
+    } else {
+        None
+    };
+
+    UserAgentInfo {
+        browser,
+        os: os.map(str::to_string),
+        device,
+    }
+}
+
+/// Resolves an IP address to a coarse "City, Country" location using the MaxMind-format database
+/// at `GEOIP_DB_PATH`. Returns `None` when the `geoip` feature is disabled, no database is
+/// configured, or the address is unknown (e.g. private ranges).
+#[cfg(feature = "geoip")]
+pub fn ip_locate(ip: &str) -> Option<String> {
+    use std::sync::OnceLock;
+
+    static READER: OnceLock<Option<maxminddb::Reader<Vec<u8>>>> = OnceLock::new();
+    let reader = READER.get_or_init(|| {
+        let path = config_var("GEOIP_DB_PATH")?;
+        maxminddb::Reader::open_readfile(&path)
+            .inspect_err(|e| tracing::warn!("failed to open GeoIP database {}: {}", path, e))
+            .ok()
+    });
+
+    let ip: std::net::IpAddr = ip.parse().ok()?;
+    let city: maxminddb::geoip2::City = reader.as_ref()?.lookup(ip).ok()?;
+    let city_name = city.city.and_then(|c| c.names).and_then(|n| n.get("en").copied());
+    let country = city.country.and_then(|c| c.iso_code);
+    match (city_name, country) {
+        (Some(city_name), Some(country)) => Some(format!("{}, {}", city_name, country)),
+        (None, Some(country)) => Some(country.to_string()),
+        _ => None,
+    }
+}
+
+/// Resolves an IP address to a coarse location. Always `None` without the `geoip` feature.
+#[cfg(not(feature = "geoip"))]
+pub fn ip_locate(_ip: &str) -> Option<String> {
+    None
+}
+
+/// Proxies in front of the app, from `TRUSTED_PROXIES`: comma-separated addresses or CIDR ranges
+/// (e.g. `127.0.0.1,10.0.0.0/8`). The `X-Forwarded-For` and `Forwarded` headers are only believed
+/// on requests coming from one of them, as anyone else could forge them.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(pub Vec<IpNet>);
+
+impl TrustedProxies {
+    pub fn from_env() -> Self {
+        let proxies = env_list("TRUSTED_PROXIES")
+            .into_iter()
+            .map(|item| {
+                item.parse::<IpNet>()
+                    .or_else(|_| item.parse::<IpAddr>().map(IpNet::from))
+                    .unwrap_or_else(|_| panic!("TRUSTED_PROXIES has an invalid entry: {}", item))
+            })
+            .collect();
+        Self(proxies)
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|net| net.contains(&ip.to_canonical()))
+    }
+}
+
+/// Parses a node of a `Forwarded` `for=` parameter or an `X-Forwarded-For` item: an address,
+/// possibly quoted, bracketed or with a port. Obfuscated and `unknown` nodes give `None`.
+fn forwarded_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    let (host, _port) = node
+        .rsplit_once(':')
+        .filter(|(host, _)| !host.contains(':'))
+        .unwrap_or((node, ""));
+    host.parse().ok()
+}
+
+/// Returns the chain of addresses a request was forwarded for, client first, from the `for=`
+/// parameters of `Forwarded` or else from `X-Forwarded-For`. Unparseable nodes are kept as `None`,
+/// so that the chain still lines up with the proxies that added it.
+pub fn forwarded_for(forwarded: Option<&str>, x_forwarded_for: Option<&str>) -> Vec<Option<IpAddr>> {
+    if let Some(forwarded) = forwarded {
+        let chain = forwarded
+            .split(',')
+            .filter_map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                    .map(|(_, value)| forwarded_node(value))
+            })
+            .collect::<Vec<_>>();
+        if !chain.is_empty() {
+            return chain;
+        }
+    }
+    x_forwarded_for
+        .map(|header| header.split(',').map(forwarded_node).collect())
+        .unwrap_or_default()
+}
+
+/// Resolves the client address from the connection's `peer` and the forwarded `chain`: walking
+/// back from the peer, the first hop that isn't a trusted proxy. A hop that can't be parsed ends
+/// the walk at the proxy that reported it.
+pub fn client_ip_resolve(peer: IpAddr, chain: &[Option<IpAddr>], proxies: &TrustedProxies) -> IpAddr {
+    let mut client = peer.to_canonical();
+    for hop in chain.iter().rev() {
+        match hop {
+            Some(hop) if proxies.contains(client) => client = hop.to_canonical(),
+            _ => break,
+        }
+    }
+    client
+}
+
+/// The caller's IP address, resolved once per request through the trusted proxies. `None` when the
+/// connection's peer is unknown (e.g. local test clients).
+///
+/// Rocket's own `Request::client_ip` believes the `X-Real-IP` header from anyone, so use this
+/// instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl ClientIp {
+    /// Returns the IP address of this request's client, resolving it on first use.
+    pub fn of(request: &Request<'_>) -> Option<IpAddr> {
+        request
+            .local_cache(|| {
+                let ip = request.remote().map(|peer| {
+                    let proxies = request.rocket().state::<TrustedProxies>();
+                    let headers = request.headers();
+                    let chain = forwarded_for(headers.get_one("Forwarded"), headers.get_one("X-Forwarded-For"));
+                    client_ip_resolve(peer.ip(), &chain, proxies.unwrap_or(&TrustedProxies::default()))
+                });
+                ClientIp(ip)
+            })
+            .0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientIp {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(ClientIp(ClientIp::of(request)))
+    }
+}
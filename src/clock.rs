@@ -0,0 +1,172 @@
+use chrono::{SubsecRound, TimeDelta};
+use rocket::State;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::json;
+use std::sync::{Arc, OnceLock};
+
+use crate::api::api_mount;
+use crate::error::ApiError;
+use crate::hlc::{Hlc, hlc_clock};
+use crate::util::*;
+
+/// What to do with a client-supplied timestamp too far in the future.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkewPolicy {
+    /// Refuse the write with a validation error.
+    Reject,
+    /// Replace the timestamp with the server time.
+    Clamp,
+}
+
+/// How finely stored timestamps resolve, from `TIMESTAMP_PRECISION`: `s`, `ms` (default) or `us`.
+/// Edits within the same unit can't be ordered by last-write-wins, so the finer the better, as long
+/// as clients send timestamps that fine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampPrecision {
+    Seconds,
+    Millis,
+    Micros,
+}
+
+impl TimestampPrecision {
+    /// Drops the digits finer than the precision.
+    pub fn truncate(self, at: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Self::Seconds => at.trunc_subsecs(0),
+            Self::Millis => at.trunc_subsecs(3),
+            Self::Micros => at.trunc_subsecs(6),
+        }
+    }
+}
+
+/// Guards last-write-wins conflict resolution against client clocks running ahead: a post stamped
+/// a year ahead would otherwise win every later conflict. Timestamps more than
+/// `CLOCK_SKEW_MAX_SECS` (300) past the server time are handled per `CLOCK_SKEW_POLICY`, `reject`
+/// (default) or `clamp`.
+///
+/// Timestamps are truncated to `precision` before they are stored. Values stored at a coarser
+/// precision before it was raised still compare correctly: `2024-01-31T12:00:00+00:00` sorts
+/// before `2024-01-31T12:00:00.250+00:00`, since sqlx only writes the fraction when it is non-zero.
+#[derive(Debug, Clone)]
+pub struct ClockConfig {
+    pub max_skew: TimeDelta,
+    pub policy: SkewPolicy,
+    pub precision: TimestampPrecision,
+}
+
+impl ClockConfig {
+    pub fn from_env() -> Self {
+        let policy = match config_var("CLOCK_SKEW_POLICY").unwrap_or_default().as_str() {
+            "" | "reject" => SkewPolicy::Reject,
+            "clamp" => SkewPolicy::Clamp,
+            other => panic!("CLOCK_SKEW_POLICY has an invalid value: {}", other),
+        };
+        let precision = match config_var("TIMESTAMP_PRECISION").unwrap_or_default().as_str() {
+            "s" => TimestampPrecision::Seconds,
+            "" | "ms" => TimestampPrecision::Millis,
+            "us" => TimestampPrecision::Micros,
+            other => panic!("TIMESTAMP_PRECISION has an invalid value: {}", other),
+        };
+        Self {
+            max_skew: TimeDelta::seconds(env_parse_or("CLOCK_SKEW_MAX_SECS", 300)),
+            policy,
+            precision,
+        }
+    }
+
+    /// Returns the server time at the stored precision.
+    pub fn now(&self) -> DateTime<Utc> {
+        self.precision.truncate(Utc::now())
+    }
+
+    /// Returns the timestamp to store for a client-supplied `timestamp`, or why it was refused.
+    pub fn accept(&self, timestamp: DateTime<Utc>, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+        if timestamp <= now + self.max_skew {
+            return Ok(self.precision.truncate(timestamp));
+        }
+        match self.policy {
+            SkewPolicy::Reject => Err(format!(
+                "Timestamp {} is ahead of the server time {}, check the device clock",
+                timestamp.to_rfc3339(),
+                now.to_rfc3339()
+            )),
+            SkewPolicy::Clamp => Ok(now),
+        }
+    }
+}
+
+/// Returns the process-wide `ClockConfig`.
+pub fn clock_config() -> &'static ClockConfig {
+    static CONFIG: OnceLock<ClockConfig> = OnceLock::new();
+    CONFIG.get_or_init(ClockConfig::from_env)
+}
+
+/// A source of the current time. Handlers read it from `Timekeeper` rather than calling
+/// `Utc::now()`, so that tests can stop and move time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Managed state holding the clock handlers read the time from.
+#[derive(Clone)]
+pub struct Timekeeper(pub Arc<dyn Clock>);
+
+impl Timekeeper {
+    pub fn now(&self) -> DateTime<Utc> {
+        self.0.now()
+    }
+
+    /// Returns the time at the stored precision, see `ClockConfig`.
+    pub fn now_stored(&self) -> DateTime<Utc> {
+        clock_config().precision.truncate(self.0.now())
+    }
+}
+
+/// Returns the server time, so that clients can measure and correct their clock offset.
+#[get("/")]
+fn index(clock: &State<Timekeeper>) -> (Status, json::Value) {
+    let now = clock.now();
+    (
+        Status::Ok,
+        json::json!({
+            "now": now.to_rfc3339(),
+            "nowMs": now.timestamp_millis(),
+            "maxSkewSecs": clock_config().max_skew.num_seconds(),
+        }),
+    )
+}
+
+/// Returns a new stamp of the server's hybrid logical clock, for clients that version their writes
+/// with one. A client passes its latest stamp as `?version=` and moves its own clock up to the
+/// returned stamp, which is greater than both, so that its next writes sort after everything the
+/// server has seen.
+#[get("/clock?<version>")]
+fn sync_clock(
+    _user: UserCtx,
+    clock: &State<Timekeeper>,
+    version: Option<String>,
+) -> Result<(Status, json::Value), ApiError> {
+    if let Some(version) = version {
+        let version = version.parse::<Hlc>().map_err(ApiError::validation)?;
+        hlc_clock().accept(version, clock.now()).map_err(ApiError::validation)?;
+    }
+    Ok((Status::Ok, json::json!({ "version": hlc_clock().now() })))
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Clock stage", |rocket| async {
+        let rocket = manage_default(rocket, |_| Timekeeper(Arc::new(SystemClock)));
+        let rocket = api_mount(rocket, "/time", routes![index]);
+        api_mount(rocket, "/sync", routes![sync_clock])
+    })
+}
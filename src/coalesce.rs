@@ -0,0 +1,174 @@
+use rocket::fairing::AdHoc;
+use rocket::tokio::time::{self, Duration};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::clock::Timekeeper;
+use crate::db::*;
+use crate::events::{event_record, events_wake};
+use crate::handlers::posts::PostChangeKind;
+use crate::metrics::metrics;
+use crate::retry::write_retry;
+use crate::scope::{Scope, StoredContent, WriteStamp};
+use crate::util::*;
+
+/// A content update of a post waiting in the `UpdateCoalescer`, its content already encrypted.
+#[derive(Debug, Clone)]
+pub struct PendingUpdate {
+    pub content: StoredContent,
+    pub position: Option<f64>,
+    pub stamp: WriteStamp,
+}
+
+/// Write-behind for `PUT /api/posts/<id>`. Clients syncing as the user types send the same post
+/// several times a second; with `POST_UPDATE_COALESCE_MS` set (0, off by default), their updates
+/// are queued per user and post and only the newest of each is written, every that many
+/// milliseconds, in one transaction.
+///
+/// Updates still win by `version`: a queued update only replaces an older one, and the write itself
+/// only lands over an older stored version. Reads may lag the queued updates by up to the interval.
+#[derive(Debug, Clone, Default)]
+pub struct UpdateCoalescer {
+    interval: Option<Duration>,
+    pending: Arc<Mutex<HashMap<(i64, String), PendingUpdate>>>,
+}
+
+impl UpdateCoalescer {
+    pub fn new(interval: Option<Duration>) -> Self {
+        Self {
+            interval,
+            pending: Arc::default(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let ms: u64 = env_parse_or("POST_UPDATE_COALESCE_MS", 0);
+        Self::new((ms > 0).then(|| Duration::from_millis(ms)))
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.interval.is_some()
+    }
+
+    /// Queues an update of the user's post, unless one with a newer or the same version is queued
+    /// already. Returns whether it was queued.
+    pub fn queue(&self, user_id: i64, id: &str, update: PendingUpdate) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        match pending.get_mut(&(user_id, id.to_owned())) {
+            Some(queued) if queued.stamp.version >= update.stamp.version => false,
+            Some(queued) => {
+                *queued = update;
+                metrics().counter_inc(
+                    "posts_updates_coalesced_total",
+                    "Post updates replaced by a newer one before they were written.",
+                    &[],
+                );
+                true
+            }
+            None => {
+                pending.insert((user_id, id.to_owned()), update);
+                true
+            }
+        }
+    }
+
+    pub fn pending_len(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    /// Writes the queued updates at `now`, returning how many landed. On failure they are queued
+    /// again, behind any newer update queued meanwhile.
+    pub async fn flush(&self, db: &mut sqlx::SqliteConnection, now: DateTime<Utc>) -> Result<usize, sqlx::Error> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if pending.is_empty() {
+            return Ok(0);
+        }
+        let result = write_retry!("posts.update_coalesced", async {
+            let mut tx = tx_begin(&mut *db).await?;
+            let mut applied = 0;
+            for ((user_id, id), update) in &pending {
+                let updated = Scope::user(*user_id)
+                    .post_update(&mut tx, id, &update.content, update.position, &update.stamp)
+                    .await?;
+                if updated {
+                    event_record(&mut tx, *user_id, PostChangeKind::Upserted, Some(id), now).await?;
+                    applied += 1;
+                }
+            }
+            tx_commit(tx).await?;
+            Ok(applied)
+        });
+
+        match result {
+            Ok(applied) => {
+                if applied > 0 {
+                    events_wake();
+                }
+                Ok(applied)
+            }
+            Err(e) => {
+                for ((user_id, id), update) in pending {
+                    self.queue(user_id, &id, update);
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Flushes the coalescer every interval, forever.
+async fn flusher(pool: sqlx::SqlitePool, clock: Timekeeper, coalescer: UpdateCoalescer, interval: Duration) {
+    loop {
+        time::sleep(interval).await;
+        let flushed = match pool.acquire().await {
+            Ok(mut db) => coalescer.flush(&mut db, clock.now()).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = flushed {
+            tracing::warn!("coalesced post updates failed to write: {}", e);
+        }
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Post update coalescing", |rocket| async {
+        let rocket = manage_default(rocket, |_| UpdateCoalescer::from_env());
+        rocket
+            .attach(AdHoc::on_liftoff("Post update flusher", |rocket| {
+                Box::pin(async move {
+                    let (Some(db), Some(clock), Some(coalescer)) = (
+                        Db::fetch(rocket),
+                        rocket.state::<Timekeeper>(),
+                        rocket.state::<UpdateCoalescer>(),
+                    ) else {
+                        return;
+                    };
+                    if let Some(interval) = coalescer.interval {
+                        rocket::tokio::spawn(flusher((**db).clone(), clock.clone(), coalescer.clone(), interval));
+                    }
+                })
+            }))
+            .attach(AdHoc::on_shutdown("Post update flush", |rocket| {
+                Box::pin(async move {
+                    let (Some(db), Some(clock), Some(coalescer)) = (
+                        Db::fetch(rocket),
+                        rocket.state::<Timekeeper>(),
+                        rocket.state::<UpdateCoalescer>(),
+                    ) else {
+                        return;
+                    };
+                    let flushed = match db.acquire().await {
+                        Ok(mut db) => coalescer.flush(&mut db, clock.now()).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = flushed {
+                        tracing::error!(
+                            pending = coalescer.pending_len(),
+                            "coalesced post updates lost at shutdown: {}",
+                            e
+                        );
+                    }
+                })
+            }))
+    })
+}
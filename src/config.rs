@@ -0,0 +1,324 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use rocket::figment::value::Value;
+use rocket::{Phase, Rocket};
+use std::env;
+use std::fmt;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::email::pem_decode;
+use crate::handlers::posts::ForeignPostPolicy;
+use crate::util::{app_mode, env_parse_or, manage_default};
+
+/// The settings in `Rocket.toml` (or the file at `ROCKET_CONFIG`): its `[default]` table merged with
+/// the table of the active profile (`[debug]`, `[release]` or the `ROCKET_PROFILE`). Settings there
+/// keep their environment variable names, lowercased:
+///
+/// ```toml
+/// [default]
+/// post_content_max_bytes = 65536
+/// trusted_proxies = ["10.0.0.0/8"]
+///
+/// [release]
+/// app_url = "https://notes.example.com"
+/// rate_limit_writes = 60
+/// ```
+///
+/// The file is read again by `config_file_reload`.
+fn config_file() -> Arc<Figment> {
+    config_file_lock().read().expect("config file lock poisoned").clone()
+}
+
+fn config_file_lock() -> &'static RwLock<Arc<Figment>> {
+    static FIGMENT: OnceLock<RwLock<Arc<Figment>>> = OnceLock::new();
+    FIGMENT.get_or_init(|| RwLock::new(Arc::new(rocket::Config::figment())))
+}
+
+/// Reads the config file again, for the settings that are reloaded at runtime (see
+/// `reload::Reloadable`). A file that no longer parses is an error and the previous one is kept.
+pub fn config_file_reload() -> Result<(), String> {
+    let figment = rocket::Config::figment();
+    figment
+        .extract::<rocket::figment::value::Dict>()
+        .map_err(|e| format!("the config file is invalid: {}", e))?;
+    *config_file_lock().write().expect("config file lock poisoned") = Arc::new(figment);
+    Ok(())
+}
+
+/// The raw value of a setting, from the first of these that has it:
+///
+/// - the environment variable `name`
+/// - the file at the path in `<name>_FILE`, as Docker and Kubernetes mount secrets, without its
+///   trailing newline
+/// - with the `secrets` feature, the secrets loaded from Vault or SOPS at startup
+/// - its lowercased key in `config_file`
+///
+/// Lists are joined with commas. Panics when a `_FILE` can't be read.
+pub fn config_var(name: &str) -> Option<String> {
+    config_lookup(name).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// `config_var`, returning the error of a `_FILE` that can't be read.
+pub fn config_lookup(name: &str) -> Result<Option<String>, String> {
+    if let Ok(value) = env::var(name) {
+        return Ok(Some(value));
+    }
+    if let Ok(path) = env::var(format!("{}_FILE", name)) {
+        let value =
+            std::fs::read_to_string(&path).map_err(|e| format!("{}_FILE: failed to read {}: {}", name, path, e))?;
+        let value = value.strip_suffix('\n').unwrap_or(&value);
+        return Ok(Some(value.strip_suffix('\r').unwrap_or(value).to_string()));
+    }
+    #[cfg(feature = "secrets")]
+    if let Some(value) = crate::secrets::secret_get(name) {
+        return Ok(Some(value.to_string()));
+    }
+    let Ok(value) = config_file().find_value(&name.to_ascii_lowercase()) else {
+        return Ok(None);
+    };
+    Ok(value_string(&value))
+}
+
+fn value_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(_, value) => Some(value.clone()),
+        Value::Char(_, value) => Some(value.to_string()),
+        Value::Bool(_, value) => Some(value.to_string()),
+        Value::Num(..) => match value.deserialize::<i64>() {
+            Ok(value) => Some(value.to_string()),
+            Err(_) => value.deserialize::<f64>().ok().map(|value| value.to_string()),
+        },
+        Value::Array(_, items) => items
+            .iter()
+            .map(value_string)
+            .collect::<Option<Vec<_>>>()
+            .map(|items| items.join(",")),
+        Value::Dict(..) | Value::Empty(..) => None,
+    }
+}
+
+/// The typed settings of the app that handlers, guards and fairings share, read through
+/// `config_var`. Managed by `stage`; the process-wide copy is `app_config`. Module-specific settings
+/// (pool tuning, schedulers, rate limits...) are read by their modules' configs the same way.
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    /// Public base URL of the app, used to build links in emails (`APP_URL`).
+    pub app_url: String,
+    pub auth: AuthConfig,
+    pub limits: LimitsConfig,
+}
+
+/// Who gets in and for how long.
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    /// `SESSION_SLIDING`, see `session_sliding`.
+    pub session_sliding: bool,
+    /// `AUTH_USER_CHECK`, see `auth_user_check`.
+    pub auth_user_check: bool,
+    /// `ADMIN_TOKEN`, see `AdminToken`.
+    pub admin_token: Option<String>,
+    /// `IMPERSONATION_TTL_MINS`, see `impersonation_ttl_mins`.
+    pub impersonation_ttl_mins: i64,
+    /// `JWT_TTL_SECS`, see `token_ttl`.
+    pub jwt_ttl_secs: i64,
+    /// `POST_ACCESS_POLICY`, see `ForeignPostPolicy`.
+    pub post_access_policy: ForeignPostPolicy,
+}
+
+/// Sizes and retention of what clients send.
+#[derive(Debug, Clone)]
+pub struct LimitsConfig {
+    /// `POST_CONTENT_MAX_BYTES`, see `post_content_max`.
+    pub post_content_max_bytes: usize,
+    /// `UPSERT_BATCH_RETENTION_SECS`: how long upsert-many remembers a `batchId`.
+    pub upsert_batch_retention_secs: i64,
+}
+
+impl AppConfig {
+    /// Reads the settings. Panics on a setting that is set but cannot be parsed.
+    pub fn load() -> Self {
+        let post_access_policy = match config_var("POST_ACCESS_POLICY").unwrap_or_default().as_str() {
+            "" | "hide" => ForeignPostPolicy::Hide,
+            "forbid" => ForeignPostPolicy::Forbid,
+            other => panic!("POST_ACCESS_POLICY has an invalid value: {}", other),
+        };
+        Self {
+            app_url: config_var("APP_URL")
+                .unwrap_or_else(|| "http://127.0.0.1:8000".into())
+                .trim_end_matches('/')
+                .to_string(),
+            auth: AuthConfig {
+                session_sliding: env_parse_or("SESSION_SLIDING", true),
+                auth_user_check: env_parse_or("AUTH_USER_CHECK", true),
+                admin_token: config_var("ADMIN_TOKEN").filter(|token| !token.is_empty()),
+                impersonation_ttl_mins: env_parse_or("IMPERSONATION_TTL_MINS", 30),
+                jwt_ttl_secs: env_parse_or("JWT_TTL_SECS", 300),
+                post_access_policy,
+            },
+            limits: LimitsConfig {
+                post_content_max_bytes: env_parse_or("POST_CONTENT_MAX_BYTES", 1024 * 1024),
+                upsert_batch_retention_secs: env_parse_or("UPSERT_BATCH_RETENTION_SECS", 24 * 3600),
+            },
+        }
+    }
+}
+
+/// Returns the process-wide `AppConfig`.
+pub fn app_config() -> &'static AppConfig {
+    static CONFIG: OnceLock<AppConfig> = OnceLock::new();
+    CONFIG.get_or_init(AppConfig::load)
+}
+
+/// The `AppConfig` managed by `rocket`, or the process-wide one when none is.
+pub fn app_config_managed<P: Phase>(rocket: &Rocket<P>) -> &AppConfig {
+    match rocket.state::<AppConfig>() {
+        Some(config) => config,
+        None => app_config(),
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("App config", |rocket| async {
+        manage_default(rocket, |_| app_config().clone())
+    })
+}
+
+/// Struct to hold required environment variables.
+#[derive(Debug)]
+pub struct EnvVars {
+    pub database_url: String,
+    pub rocket_databases: String,
+    pub dkim_key_public: String,
+    pub dkim_key_private: String,
+    pub rocket_secret_key: String,
+}
+
+impl EnvVars {
+    /// Reads and validates the required settings, collecting every missing or invalid
+    /// one into the report rather than stopping at the first. The DKIM keys must be PEM outside
+    /// debug mode only, where sending is simulated.
+    pub fn from_env() -> Result<Self, ConfigReport> {
+        let mut report = ConfigReport::default();
+        let mut required = |name: &'static str| match config_lookup(name) {
+            Ok(Some(value)) if !value.trim().is_empty() => value,
+            Ok(Some(_)) => {
+                report.problem(name, "is empty");
+                String::new()
+            }
+            Ok(None) => {
+                report.problem(name, "must be set (or its path in a _FILE variable)");
+                String::new()
+            }
+            Err(message) => {
+                report.problem(name, message);
+                String::new()
+            }
+        };
+        let vars = Self {
+            database_url: required("DATABASE_URL"),
+            rocket_databases: required("ROCKET_DATABASES"),
+            dkim_key_public: required("DKIM_KEY_PUBLIC").replace("\\n", "\n"),
+            dkim_key_private: required("DKIM_KEY_PRIVATE").replace("\\n", "\n"),
+            rocket_secret_key: required("ROCKET_SECRET_KEY"),
+        };
+
+        if !vars.database_url.is_empty() && !vars.database_url.starts_with("sqlite:") {
+            report.problem("DATABASE_URL", "must be a sqlite: URL, e.g. sqlite://db.sqlite");
+        }
+        match rocket_databases_check(&vars.rocket_databases) {
+            Err(message) if !vars.rocket_databases.is_empty() => report.problem("ROCKET_DATABASES", message),
+            _ => {}
+        }
+        match secret_key_check(&vars.rocket_secret_key) {
+            Err(message) if !vars.rocket_secret_key.is_empty() => report.problem("ROCKET_SECRET_KEY", message),
+            _ => {}
+        }
+        if app_mode() != "debug" {
+            if !vars.dkim_key_private.is_empty() && pem_decode(&vars.dkim_key_private, "PRIVATE KEY").is_none() {
+                report.problem(
+                    "DKIM_KEY_PRIVATE",
+                    "is not a PKCS#8 PEM private key (see `just keygen-dkim`)",
+                );
+            }
+            if !vars.dkim_key_public.is_empty() && pem_decode(&vars.dkim_key_public, "PUBLIC KEY").is_none() {
+                report.problem("DKIM_KEY_PUBLIC", "is not a PEM public key (see `just keygen-dkim`)");
+            }
+        }
+
+        match report.problems.is_empty() {
+            true => Ok(vars),
+            false => Err(report),
+        }
+    }
+}
+
+/// `ROCKET_DATABASES` must hold the `sqlx` database with a SQLite file or in-memory URL.
+pub fn rocket_databases_check(value: &str) -> Result<(), String> {
+    let value = match value.parse::<Value>() {
+        Ok(value) if value.as_dict().is_some() => value,
+        _ => return Err("must be a table, e.g. {sqlx={url=\"db.sqlite\"}}".into()),
+    };
+    let Some(url) = value.find_ref("sqlx.url").and_then(|url| url.as_str()) else {
+        return Err("must set sqlx.url, e.g. {sqlx={url=\"db.sqlite\"}}".into());
+    };
+    match url.split_once("://") {
+        Some(("sqlite", _)) | None => Ok(()),
+        Some((scheme, _)) => Err(format!("sqlx.url has the scheme {}, only sqlite is supported", scheme)),
+    }
+}
+
+/// Rocket and the gRPC cookie key take a base64 key of 32 or 64 bytes.
+pub fn secret_key_check(value: &str) -> Result<(), String> {
+    match BASE64.decode(value.trim()) {
+        Ok(key) if key.len() == 32 || key.len() == 64 => Ok(()),
+        Ok(key) => Err(format!(
+            "must be 32 or 64 bytes, found {}; generate one with `openssl rand -base64 32`",
+            key.len()
+        )),
+        Err(_) => Err("must be base64; generate one with `openssl rand -base64 32`".into()),
+    }
+}
+
+/// Every missing or invalid environment variable found at startup, printed as one report.
+#[derive(Debug, Default)]
+pub struct ConfigReport {
+    pub problems: Vec<(&'static str, String)>,
+}
+
+impl ConfigReport {
+    pub fn problem(&mut self, name: &'static str, message: impl Into<String>) {
+        self.problems.push((name, message.into()));
+    }
+}
+
+impl fmt::Display for ConfigReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Invalid configuration ({} problems):", self.problems.len())?;
+        for (name, message) in &self.problems {
+            writeln!(f, "  {}: {}", name, message)?;
+        }
+        write!(f, "See .env.example for every setting.")
+    }
+}
+
+/// Returns the validated required environment variables. Panics with the report when they are
+/// invalid; `main` checks them with `env_check` first to exit cleanly instead.
+pub fn env_get() -> &'static EnvVars {
+    ENV_VARS.get_or_init(|| EnvVars::from_env().unwrap_or_else(|report| panic!("{}", report)))
+}
+
+static ENV_VARS: OnceLock<EnvVars> = OnceLock::new();
+
+/// Validates the required environment variables, printing the report and exiting with status 1
+/// when any is missing or invalid.
+pub fn env_check() -> &'static EnvVars {
+    match EnvVars::from_env() {
+        Ok(vars) => ENV_VARS.get_or_init(|| vars),
+        Err(report) => {
+            eprintln!("{}", report);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use chrono::{Datelike, DurationRound, TimeDelta, Timelike};
+
+use crate::util::*;
+
+/// A five-field cron schedule (`minute hour day-of-month month day-of-week`), evaluated in UTC.
+///
+/// Fields take `*`, numbers, ranges (`1-5`), lists (`1,15`) and steps (`*/15`, `9-17/2`). Days of
+/// the week run from 0 (Sunday) to 6, 7 being Sunday too. As in cron, when both days are
+/// restricted a time matches either. `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly`
+/// stand for the usual schedules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    days_any: bool,
+    weekdays_any: bool,
+}
+
+/// Parses one field into a bit set of the values it matches.
+fn field_parse(field: &str, name: &str, min: u32, max: u32) -> Result<u64, String> {
+    let invalid = || format!("invalid {} field `{}`", name, field);
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|step| *step > 0).ok_or_else(invalid)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (
+                    start.parse().map_err(|_| invalid())?,
+                    end.parse().map_err(|_| invalid())?,
+                ),
+                // `5/15` runs from 5 to the end, like `5-59/15`
+                None if part.contains('/') => (range.parse().map_err(|_| invalid())?, max),
+                None => {
+                    let value = range.parse().map_err(|_| invalid())?;
+                    (value, value)
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expression => expression,
+        };
+        let fields = expression.split_whitespace().collect::<Vec<_>>();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("expected 5 fields, got {}", fields.len()));
+        };
+        let mut weekdays = field_parse(weekday, "day-of-week", 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            minutes: field_parse(minute, "minute", 0, 59)?,
+            hours: field_parse(hour, "hour", 0, 23)?,
+            days: field_parse(day, "day-of-month", 1, 31)?,
+            months: field_parse(month, "month", 1, 12)?,
+            weekdays,
+            days_any: day == "*",
+            weekdays_any: weekday == "*",
+        })
+    }
+
+    fn day_matches(&self, at: DateTime<Utc>) -> bool {
+        let day = self.days & (1 << at.day()) != 0;
+        let weekday = self.weekdays & (1 << at.weekday().num_days_from_sunday()) != 0;
+        match (self.days_any, self.weekdays_any) {
+            (false, false) => day || weekday,
+            (true, false) => weekday,
+            _ => day,
+        }
+    }
+
+    /// Returns the first time strictly after `after` that the schedule matches, or `None` when it
+    /// matches nothing within the next five years (e.g. February 30th).
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let limit = after + TimeDelta::days(5 * 366);
+        let mut at = after.duration_trunc(TimeDelta::minutes(1)).ok()? + TimeDelta::minutes(1);
+        while at < limit {
+            let midnight = at.duration_trunc(TimeDelta::days(1)).ok()?;
+            if self.months & (1 << at.month()) == 0 {
+                let (year, month) = match at.month() {
+                    12 => (at.year() + 1, 1),
+                    month => (at.year(), month + 1),
+                };
+                at = midnight.with_day(1)?.with_year(year)?.with_month(month)?;
+            } else if !self.day_matches(at) {
+                at = midnight + TimeDelta::days(1);
+            } else if self.hours & (1 << at.hour()) == 0 {
+                at = at.duration_trunc(TimeDelta::hours(1)).ok()? + TimeDelta::hours(1);
+            } else if self.minutes & (1 << at.minute()) == 0 {
+                at += TimeDelta::minutes(1);
+            } else {
+                return Some(at);
+            }
+        }
+        None
+    }
+}
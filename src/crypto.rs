@@ -0,0 +1,156 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::config::config_var;
+
+/// Prefix of encrypted values: `enc:v1:<key id>:<base64 of nonce followed by ciphertext>`.
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CryptoError {
+    /// The value was encrypted with a key that is not configured.
+    UnknownKey(String),
+    /// The value is not a well-formed ciphertext or fails authentication.
+    Corrupt,
+}
+
+impl std::fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownKey(id) => write!(f, "content encrypted with unknown key {}", id),
+            Self::Corrupt => write!(f, "content ciphertext is corrupt"),
+        }
+    }
+}
+
+/// Encrypts post and comment content at rest with AES-256-GCM.
+///
+/// Keys are loaded from the environment as `<id>:<base64 32-byte key>` pairs: `CONTENT_KEY` is the
+/// key new writes use and `CONTENT_KEYS_OLD` (comma-separated) lists retired keys that can still
+/// decrypt. Without `CONTENT_KEY` content is stored in plain text. Plain text values are always
+/// readable, so encryption can be turned on for an existing database and old rows migrated with
+/// `admin posts rotate-key`.
+pub struct ContentCipher {
+    active: Option<String>,
+    /// Raw bytes of the active key, which keys `content_hash`.
+    hash_key: Option<Vec<u8>>,
+    keys: HashMap<String, Aes256Gcm>,
+}
+
+fn key_parse(entry: &str) -> (String, Vec<u8>) {
+    let (id, key) = entry
+        .split_once(':')
+        .unwrap_or_else(|| panic!("content key must be <id>:<base64 key>, got {}", entry));
+    let key = BASE64
+        .decode(key.trim())
+        .ok()
+        .filter(|key| key.len() == 32)
+        .unwrap_or_else(|| panic!("content key {} must be 32 bytes of base64", id));
+    (id.trim().to_string(), key)
+}
+
+fn cipher_new(key: &[u8]) -> Aes256Gcm {
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+}
+
+impl ContentCipher {
+    pub fn new(active: Option<&str>, old: &[&str]) -> Self {
+        let mut keys = old
+            .iter()
+            .map(|entry| {
+                let (id, key) = key_parse(entry);
+                (id, cipher_new(&key))
+            })
+            .collect::<HashMap<_, _>>();
+        let (active, hash_key) = match active.map(key_parse) {
+            Some((id, key)) => {
+                keys.insert(id.clone(), cipher_new(&key));
+                (Some(id), Some(key))
+            }
+            None => (None, None),
+        };
+        Self { active, hash_key, keys }
+    }
+
+    pub fn from_env() -> Self {
+        let active = config_var("CONTENT_KEY");
+        // Not `env_list`, which lowercases and would corrupt the base64 keys
+        let old = config_var("CONTENT_KEYS_OLD").unwrap_or_default();
+        let old = old.split(',').map(str::trim).filter(|e| !e.is_empty()).collect::<Vec<_>>();
+        Self::new(active.as_deref(), &old)
+    }
+
+    /// Whether new writes are encrypted.
+    pub fn encrypts(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// Returns the value to store for `plaintext`: encrypted with the active key, or unchanged when
+    /// encryption is disabled.
+    pub fn encrypt(&self, plaintext: &str) -> String {
+        let Some(id) = &self.active else {
+            return plaintext.to_string();
+        };
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self.keys[id]
+            .encrypt(&nonce, plaintext.as_bytes())
+            .expect("AES-GCM encryption failed");
+
+        let mut payload = nonce.to_vec();
+        payload.extend_from_slice(&ciphertext);
+        format!("{}{}:{}", ENCRYPTED_PREFIX, id, BASE64.encode(payload))
+    }
+
+    /// Returns the plain text of a stored value.
+    pub fn decrypt(&self, stored: &str) -> Result<String, CryptoError> {
+        let Some(rest) = stored.strip_prefix(ENCRYPTED_PREFIX) else {
+            return Ok(stored.to_string());
+        };
+        let (id, payload) = rest.split_once(':').ok_or(CryptoError::Corrupt)?;
+        let cipher = self.keys.get(id).ok_or_else(|| CryptoError::UnknownKey(id.to_string()))?;
+        let payload = BASE64.decode(payload).map_err(|_| CryptoError::Corrupt)?;
+        if payload.len() < NONCE_LEN {
+            return Err(CryptoError::Corrupt);
+        }
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| CryptoError::Corrupt)?;
+        String::from_utf8(plaintext).map_err(|_| CryptoError::Corrupt)
+    }
+
+    /// Returns a hex digest identifying `plaintext`, for duplicate detection. With encryption
+    /// enabled it is an HMAC keyed with the active key, so that it does not reveal the content;
+    /// digests stored before a key change must then be recomputed with `admin posts rehash`.
+    pub fn content_hash(&self, plaintext: &str) -> String {
+        match &self.hash_key {
+            Some(key) => {
+                let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+                mac.update(plaintext.as_bytes());
+                format!("{:x}", mac.finalize().into_bytes())
+            }
+            None => format!("{:x}", Sha256::digest(plaintext.as_bytes())),
+        }
+    }
+
+    /// Whether a stored value is not yet encrypted with the active key.
+    pub fn needs_rotation(&self, stored: &str) -> bool {
+        match &self.active {
+            Some(id) => !stored.starts_with(&format!("{}{}:", ENCRYPTED_PREFIX, id)),
+            None => stored.starts_with(ENCRYPTED_PREFIX),
+        }
+    }
+}
+
+/// Returns the process-wide `ContentCipher`.
+pub fn content_cipher() -> &'static ContentCipher {
+    static CIPHER: OnceLock<ContentCipher> = OnceLock::new();
+    CIPHER.get_or_init(ContentCipher::from_env)
+}
@@ -0,0 +1,65 @@
+use nanoid::nanoid;
+use rocket::Request;
+use rocket::http::{self, Method, Status};
+use rocket::request::{self, FromRequest};
+
+/// Name of the (JS-readable) cookie holding the double-submit token.
+pub const CSRF_COOKIE: &str = "csrf_token";
+/// Header mutating requests must echo the cookie value in.
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Generates a fresh random CSRF token.
+pub fn csrf_token_gen() -> String {
+    nanoid!(32)
+}
+
+/// Builds the double-submit cookie. It is deliberately not http-only so that browser clients can
+/// read it and copy it into the `X-CSRF-Token` header, and it lives as long as the auth cookie.
+pub fn csrf_cookie(token: String, max_age: rocket::time::Duration) -> http::Cookie<'static> {
+    http::Cookie::build((CSRF_COOKIE, token))
+        .http_only(false)
+        .same_site(http::SameSite::Strict)
+        .max_age(max_age)
+        .build()
+}
+
+/// Compares two tokens without short-circuiting on the first mismatched byte.
+pub(crate) fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Request guard that rejects cookie-authenticated mutations which don't carry a `X-CSRF-Token`
+/// header matching the `csrf_token` cookie.
+///
+/// Safe methods pass through, and so do requests carrying an `Authorization: Bearer` header: a
+/// browser will not attach that header to a cross-site request without a CORS preflight, so such
+/// requests cannot be forged from another origin.
+pub struct CsrfVerified;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CsrfVerified {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        if matches!(request.method(), Method::Get | Method::Head | Method::Options) {
+            return request::Outcome::Success(CsrfVerified);
+        }
+
+        let bearer = request
+            .headers()
+            .get_one("Authorization")
+            .is_some_and(|value| value.starts_with("Bearer "));
+        if bearer {
+            return request::Outcome::Success(CsrfVerified);
+        }
+
+        let cookie = request.cookies().get(CSRF_COOKIE).map(|c| c.value().to_owned());
+        let header = request.headers().get_one(CSRF_HEADER);
+        match (cookie, header) {
+            (Some(cookie), Some(header)) if !cookie.is_empty() && tokens_match(&cookie, header) => {
+                request::Outcome::Success(CsrfVerified)
+            }
+            _ => request::Outcome::Error((Status::Forbidden, "csrf token missing or invalid")),
+        }
+    }
+}
@@ -1,10 +1,22 @@
 use rocket::fairing::{self, AdHoc};
+use rocket::futures::future::BoxFuture;
 use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::time::{self, Duration, Instant};
 use rocket::{Build, Rocket};
+use std::future::Future;
+use std::sync::{Arc, OnceLock};
+use tracing::Instrument;
 
 use nanoid::nanoid;
 pub use rocket_db_pools::{Connection, Database, sqlx};
 
+use crate::chaos::fault_point;
+use crate::crypto::content_cipher;
+use crate::hlc::Hlc;
+use crate::metrics::metrics;
+use crate::replication;
+use crate::scope::{PostFilter, PostWrite, Scope, StoredContent, WriteStamp};
+use crate::summary::summary_of;
 use crate::util::*;
 
 #[derive(Database)]
@@ -12,26 +24,62 @@ use crate::util::*;
 pub struct Db(sqlx::SqlitePool);
 
 /// A generic database table that can hold multiple types of data, distinguished by the `variant` field.
-#[derive(Debug, Clone, Deserialize, Serialize)]
+///
+/// Columns a query leaves out, as with `?fields=`, are read as their defaults.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, sqlx::FromRow)]
 #[serde(rename_all = "camelCase")]
 #[serde(crate = "rocket::serde")]
+#[sqlx(default)]
 pub struct Post {
     pub id: String,
+    /// The folder/notebook post this post is nested under.
+    pub parent_id: Option<String>,
     pub content: String,
-    #[serde(
-        serialize_with = "NaiveDateTime::serializer",
-        deserialize_with = "NaiveDateTime::deserializer"
-    )]
-    pub created_at: NaiveDateTime,
-    #[serde(
-        serialize_with = "NaiveDateTime::serializer",
-        deserialize_with = "NaiveDateTime::deserializer"
-    )]
-    pub updated_at: NaiveDateTime,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Hybrid logical clock stamp of the last write, which decides conflicts, see `Hlc`.
+    #[serde(default)]
+    pub version: String,
     #[serde(skip)]
     #[allow(dead_code)]
     pub user_id: i64,
     pub variant: String,
+    /// Manual sort key, ascending. Unpositioned posts sort last.
+    pub position: Option<f64>,
+    /// Server-assigned sequence number of the last write, for `?since_seq=` sync.
+    #[serde(default)]
+    pub seq: i64,
+    /// The organization the post is shared with, whose members can read it.
+    #[serde(default)]
+    pub org_id: Option<String>,
+    /// When to remind the user of the post, see `reminders`.
+    #[serde(default)]
+    pub remind_at: Option<DateTime<Utc>>,
+    /// Whether the user starred the post, from `post_reactions`.
+    #[serde(default)]
+    pub favorited: bool,
+    /// The first line of the content, for list views, see `summary_of`.
+    #[serde(default)]
+    pub title: Option<String>,
+    /// The text after the title, cut short, for list views.
+    #[serde(default)]
+    pub excerpt: Option<String>,
+}
+
+impl Post {
+    /// Replaces the stored (possibly encrypted) content with its plain text, and derives the title
+    /// and excerpt when they are not stored: with encryption on, or for posts written before them.
+    pub fn content_decrypt(mut self) -> Self {
+        self.content = content_cipher()
+            .decrypt(&self.content)
+            .expect("Failed to decrypt post content");
+        if self.title.is_none() || self.excerpt.is_none() {
+            let (title, excerpt) = summary_of(&self.content);
+            self.title = Some(title);
+            self.excerpt = Some(excerpt);
+        }
+        self
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -39,19 +87,164 @@ pub struct Post {
 #[serde(crate = "rocket::serde")]
 pub struct User {
     pub id: i64,
-    #[serde(
-        serialize_with = "NaiveDateTime::serializer",
-        deserialize_with = "NaiveDateTime::deserializer"
-    )]
-    pub created_at: NaiveDateTime,
+    pub created_at: DateTime<Utc>,
     pub email: String,
     pub code_hash: Option<String>,
     pub code_attempts: Option<i64>,
-    #[serde(
-        serialize_with = "NaiveDateTime::serializer_option",
-        deserialize_with = "NaiveDateTime::deserializer_option"
-    )]
-    pub code_created_at: Option<NaiveDateTime>,
+    pub code_created_at: Option<DateTime<Utc>>,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub disabled_at: Option<DateTime<Utc>>,
+}
+
+/// A comment on a post. `parent_id` points to the comment it replies to, for threads.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct Comment {
+    pub id: String,
+    pub post_id: String,
+    pub parent_id: Option<String>,
+    #[serde(skip)]
+    #[allow(dead_code)]
+    pub user_id: i64,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Comment {
+    /// Replaces the stored (possibly encrypted) content with its plain text.
+    pub fn content_decrypt(mut self) -> Self {
+        self.content = content_cipher()
+            .decrypt(&self.content)
+            .expect("Failed to decrypt comment content");
+        self
+    }
+}
+
+/// A shared workspace. Members are listed in `organization_members` with their role.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct Organization {
+    pub id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A login session. The `session_id` private cookie references it so sessions can be listed and
+/// revoked server-side.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct Session {
+    pub id: String,
+    #[serde(skip)]
+    #[allow(dead_code)]
+    pub user_id: i64,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// Coarse location resolved from `ip` at login, when geolocation is enabled.
+    pub location: Option<String>,
+    /// How the session was signed in: `email_code`, `sms_code` or `impersonation`.
+    pub auth_method: String,
+}
+
+/// Returns a span to instrument a single database query with. Statement logs emitted by sqlx while
+/// the query runs are recorded inside it, tagged with the query name.
+pub fn query_span(name: &'static str) -> tracing::Span {
+    tracing::debug_span!("db_query", query = name)
+}
+
+/// The error of a statement that ran past `DB_STATEMENT_TIMEOUT_MS`.
+#[derive(Debug)]
+pub struct StatementTimeout {
+    pub query: &'static str,
+    pub after: Duration,
+}
+
+impl std::fmt::Display for StatementTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} timed out after {}ms", self.query, self.after.as_millis())
+    }
+}
+
+impl std::error::Error for StatementTimeout {}
+
+/// Whether the error is a `StatementTimeout`.
+pub fn is_statement_timeout(e: &sqlx::Error) -> bool {
+    matches!(e, sqlx::Error::Io(e) if e.get_ref().is_some_and(|e| e.is::<StatementTimeout>()))
+}
+
+/// Runs a statement instrumented with `query_span(name)`, failing it with a `StatementTimeout`
+/// once it runs longer than `deadline`. The caller gets its connection back then, but SQLite can't
+/// be interrupted from here: the statement runs to its end on the connection's worker, and the
+/// connection's next statement waits for it.
+pub async fn statement_run<T, F>(name: &'static str, deadline: Option<Duration>, query: F) -> Result<T, sqlx::Error>
+where
+    F: Future<Output = Result<T, sqlx::Error>>,
+{
+    let query = query.instrument(query_span(name));
+    let Some(deadline) = deadline else {
+        return query.await;
+    };
+    match time::timeout(deadline, query).await {
+        Ok(result) => result,
+        Err(_) => {
+            metrics().counter_inc(
+                "db_statement_timeouts_total",
+                "Statements that ran past DB_STATEMENT_TIMEOUT_MS, by query.",
+                &[("query", name)],
+            );
+            tracing::warn!(
+                query = name,
+                deadline_ms = deadline.as_millis() as u64,
+                "statement timed out"
+            );
+            let timeout = StatementTimeout {
+                query: name,
+                after: deadline,
+            };
+            Err(sqlx::Error::Io(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                timeout,
+            )))
+        }
+    }
+}
+
+/// The statements of the repo layer (`Scope`), which are written
+/// `query.fetch_all(db).statement("posts.list")` in place of instrumenting them with `query_span`:
+/// see `statement_run`, with the deadline of `DB_STATEMENT_TIMEOUT_MS`.
+pub trait Statement<'a, T: Send + 'a>: Future<Output = Result<T, sqlx::Error>> + Send + Sized + 'a {
+    fn statement(self, name: &'static str) -> BoxFuture<'a, Result<T, sqlx::Error>> {
+        Box::pin(statement_run(name, db_pool_config().statement_timeout, self))
+    }
+}
+
+impl<'a, T: Send + 'a, F> Statement<'a, T> for F where F: Future<Output = Result<T, sqlx::Error>> + Send + 'a {}
+
+/// Begins a transaction, or a savepoint inside one, after setting the connection's
+/// `DB_BUSY_TIMEOUT_MS`. Debug builds may fail it on purpose, see `chaos`.
+pub async fn tx_begin(db: &mut sqlx::SqliteConnection) -> Result<sqlx::Transaction<'_, sqlx::Sqlite>, sqlx::Error> {
+    fault_point("begin")?;
+    // Connections are opened by rocket_db_pools, so the pragma can't be set once at connect time
+    if let Some(busy_timeout) = db_pool_config().busy_timeout {
+        sqlx::query(&format!("PRAGMA busy_timeout = {}", busy_timeout.as_millis()))
+            .execute(&mut *db)
+            .await?;
+    }
+    sqlx::Connection::begin(db).await
+}
+
+/// Commits a transaction begun with `tx_begin`. A fault injected here drops the transaction
+/// instead, rolling it back as a failed commit would.
+pub async fn tx_commit(tx: sqlx::Transaction<'_, sqlx::Sqlite>) -> Result<(), sqlx::Error> {
+    fault_point("commit")?;
+    tx.commit().await
 }
 
 /// Generates a unique ID using the `nanoid` crate with a custom alphabet and length.
@@ -67,13 +260,83 @@ pub fn id_gen() -> String {
     nanoid!(21, &ALPHABET)
 }
 
+/// A source of IDs for new records. Handlers take them from `IdSource` rather than calling
+/// `id_gen()`, so that tests can predict them.
+pub trait IdGenerator: Send + Sync {
+    fn id(&self) -> String;
+}
+
+/// Random IDs from `id_gen`.
+pub struct NanoIds;
+
+impl IdGenerator for NanoIds {
+    fn id(&self) -> String {
+        id_gen()
+    }
+}
+
+/// Managed state holding the generator handlers take IDs from.
+#[derive(Clone)]
+pub struct IdSource(pub Arc<dyn IdGenerator>);
+
+impl IdSource {
+    pub fn id(&self) -> String {
+        self.0.id()
+    }
+}
+
+/// The embedded migrations, shared by the server and the admin CLI.
+pub static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!();
+
+/// Largest post content accepted, in bytes, from `POST_CONTENT_MAX_BYTES` (1 MiB).
+pub fn post_content_max() -> usize {
+    app_config().limits.post_content_max_bytes
+}
+
+/// Warns about the posts stored before `post_content_max` was enforced with a larger content,
+/// largest first, so that they can be trimmed. Encrypted contents are measured as stored, about a
+/// third over their plain text.
+pub async fn posts_oversized_report(db: &sqlx::SqlitePool, max: usize) -> Result<u64, sqlx::Error> {
+    let max = max as i64;
+    let oversized = sqlx::query!(
+        "SELECT id, user_id, length(CAST(content AS BLOB)) AS \"size!: i64\" FROM posts \
+        WHERE length(CAST(content AS BLOB)) > ? ORDER BY 3 DESC",
+        max
+    )
+    .fetch_all(db)
+    .instrument(query_span("posts.oversized"))
+    .await?;
+    for post in oversized.iter().take(20) {
+        tracing::warn!(
+            post_id = %post.id,
+            user_id = post.user_id,
+            size = post.size,
+            max,
+            "post content over the size limit"
+        );
+    }
+    if !oversized.is_empty() {
+        tracing::warn!(
+            count = oversized.len(),
+            max,
+            "posts with a content over the size limit; they can be read but not saved again as is"
+        );
+    }
+    Ok(oversized.len() as u64)
+}
+
 /// Runs database migrations using SQLx when the Rocket application is launched.
 async fn migrations_run(rocket: Rocket<Build>) -> fairing::Result {
     match Db::fetch(&rocket) {
-        Some(db) => match sqlx::migrate!().run(&**db).await {
-            Ok(_) => Ok(rocket),
+        Some(db) => match MIGRATOR.run(&**db).await {
+            Ok(_) => {
+                if let Err(e) = posts_oversized_report(db, post_content_max()).await {
+                    tracing::warn!("Failed to look up oversized posts: {}", e);
+                }
+                Ok(rocket)
+            }
             Err(e) => {
-                error!("Failed to initialize SQLx database: {}", e);
+                tracing::error!("Failed to initialize SQLx database: {}", e);
                 Err(rocket)
             }
         },
@@ -81,10 +344,220 @@ async fn migrations_run(rocket: Rocket<Build>) -> fairing::Result {
     }
 }
 
+/// Connection pool settings, merged over `ROCKET_DATABASES` so they can be tuned without rewriting
+/// the whole database table. Unset fields keep the rocket_db_pools defaults.
+///
+/// - `DB_POOL_MAX_CONNECTIONS` / `DB_POOL_MIN_CONNECTIONS`: pool size bounds
+/// - `DB_POOL_ACQUIRE_TIMEOUT_SECS`: how long a request waits for a connection before failing
+/// - `DB_POOL_IDLE_TIMEOUT_SECS`: idle connections are closed after this long
+///
+/// rocket_db_pools does not expose sqlx's `max_lifetime`, so long-lived connections are recycled
+/// through the idle timeout instead.
+///
+/// - `DB_STATEMENT_CACHE_CAPACITY`: prepared statements kept per connection (sqlx's default 100)
+/// - `DB_STATEMENT_TIMEOUT_MS`: how long a statement of the repo layer may run before failing (10s,
+///   0 for no limit), see `Statement`
+/// - `DB_BUSY_TIMEOUT_MS`: how long SQLite waits on the lock of another connection before answering
+///   `SQLITE_BUSY` (sqlx's default 5s), set on write transactions by `tx_begin`
+///
+/// The watchdog probes the pool every `DB_POOL_WATCHDOG_INTERVAL_SECS` (15s) and warns when
+/// acquiring a connection takes longer than `DB_POOL_ACQUIRE_WARN_MS` (250ms).
+///
+/// At liftoff, the hot post queries are prepared on `DB_WARMUP_CONNECTIONS` (2) connections, so
+/// the first requests after a deploy don't pay for opening connections and planning queries.
+#[derive(Debug, Clone)]
+pub struct DbPoolConfig {
+    pub max_connections: Option<u32>,
+    pub min_connections: Option<u32>,
+    pub acquire_timeout_secs: Option<u64>,
+    pub idle_timeout_secs: Option<u64>,
+    pub statement_cache_capacity: Option<usize>,
+    pub statement_timeout: Option<Duration>,
+    pub busy_timeout: Option<Duration>,
+    pub watchdog_interval: Duration,
+    pub acquire_warn: Duration,
+    pub warmup_connections: u32,
+}
+
+impl DbPoolConfig {
+    pub fn from_env() -> Self {
+        Self {
+            max_connections: env_parse_opt("DB_POOL_MAX_CONNECTIONS"),
+            min_connections: env_parse_opt("DB_POOL_MIN_CONNECTIONS"),
+            acquire_timeout_secs: env_parse_opt("DB_POOL_ACQUIRE_TIMEOUT_SECS"),
+            idle_timeout_secs: env_parse_opt("DB_POOL_IDLE_TIMEOUT_SECS"),
+            statement_cache_capacity: env_parse_opt("DB_STATEMENT_CACHE_CAPACITY"),
+            statement_timeout: Some(Duration::from_millis(env_parse_or("DB_STATEMENT_TIMEOUT_MS", 10_000)))
+                .filter(|timeout| !timeout.is_zero()),
+            busy_timeout: env_parse_opt("DB_BUSY_TIMEOUT_MS").map(Duration::from_millis),
+            watchdog_interval: Duration::from_secs(env_parse_or("DB_POOL_WATCHDOG_INTERVAL_SECS", 15)),
+            acquire_warn: Duration::from_millis(env_parse_or("DB_POOL_ACQUIRE_WARN_MS", 250)),
+            warmup_connections: env_parse_or("DB_WARMUP_CONNECTIONS", 2),
+        }
+    }
+
+    /// Overlays the configured pool settings on the `databases.sqlx` table of the figment.
+    pub fn merge_into(&self, mut figment: rocket::figment::Figment) -> rocket::figment::Figment {
+        if let Some(max) = self.max_connections {
+            figment = figment.merge(("databases.sqlx.max_connections", max));
+        }
+        if let Some(min) = self.min_connections {
+            figment = figment.merge(("databases.sqlx.min_connections", min));
+        }
+        if let Some(secs) = self.acquire_timeout_secs {
+            figment = figment.merge(("databases.sqlx.connect_timeout", secs));
+        }
+        if let Some(secs) = self.idle_timeout_secs {
+            figment = figment.merge(("databases.sqlx.idle_timeout", secs));
+        }
+        if let Some(capacity) = self.statement_cache_capacity {
+            figment = figment.merge(("databases.sqlx.statement_cache_capacity", capacity));
+        }
+        figment
+    }
+}
+
+/// Returns the process-wide `DbPoolConfig`.
+pub fn db_pool_config() -> &'static DbPoolConfig {
+    static CONFIG: OnceLock<DbPoolConfig> = OnceLock::new();
+    CONFIG.get_or_init(DbPoolConfig::from_env)
+}
+
+/// Acquires and releases one connection, recording the pool size, idle count and acquisition
+/// latency in the metrics registry. Returns whether a connection could be acquired.
+pub async fn pool_probe(pool: &sqlx::SqlitePool, config: &DbPoolConfig) -> bool {
+    let start = Instant::now();
+    // Release the connection straight away so it is counted as idle below
+    let result = pool.acquire().await.map(drop);
+    let wait = start.elapsed();
+
+    let m = metrics();
+    m.gauge_set("db_pool_size", "Open connections in the pool.", &[], pool.size() as f64);
+    m.gauge_set(
+        "db_pool_idle",
+        "Idle connections in the pool.",
+        &[],
+        pool.num_idle() as f64,
+    );
+    m.histogram_observe(
+        "db_pool_acquire_seconds",
+        "Time the watchdog waited to acquire a connection.",
+        &[],
+        wait.as_secs_f64(),
+    );
+
+    match result {
+        Ok(()) => {
+            if wait > config.acquire_warn {
+                tracing::warn!(wait_ms = wait.as_millis() as u64, "db pool acquisition is slow");
+            }
+            true
+        }
+        Err(e) => {
+            m.counter_inc("db_pool_acquire_errors_total", "Failed connection acquisitions.", &[]);
+            tracing::error!(wait_ms = wait.as_millis() as u64, "db pool acquisition failed: {}", e);
+            false
+        }
+    }
+}
+
+/// Prepares the hot post queries (list, read, update, single-post upsert) on up to `connections`
+/// connections at once, filling their statement caches. The upsert is rolled back. Returns how
+/// many connections were warmed.
+pub async fn pool_warm_up(pool: &sqlx::SqlitePool, connections: u32) -> Result<usize, sqlx::Error> {
+    let connections = connections.min(pool.options().get_max_connections());
+    let mut held = Vec::new();
+    for _ in 0..connections {
+        held.push(pool.acquire().await?);
+    }
+    for conn in &mut held {
+        statements_prepare(conn).await?;
+    }
+    Ok(held.len())
+}
+
+async fn statements_prepare(conn: &mut sqlx::SqliteConnection) -> Result<(), sqlx::Error> {
+    // No user has ID 0, so the reads come back empty
+    let scope = Scope::user(0);
+    let now = Utc::now();
+    let manual = PostFilter {
+        manual: true,
+        ..PostFilter::default()
+    };
+    scope.posts_list(conn, &PostFilter::default(), None, 10).await?;
+    scope.posts_list(conn, &manual, Some(now), 11).await?;
+    scope.post_read(conn, "warm-up").await?;
+    let stamp = WriteStamp {
+        updated_at: now,
+        version: Hlc::from_wall(now),
+    };
+    scope
+        .post_update(conn, "warm-up", &StoredContent::of(""), None, &stamp)
+        .await?;
+
+    let mut tx = sqlx::Connection::begin(conn).await?;
+    // The user does not exist, which is only checked at a commit that never comes
+    sqlx::query("PRAGMA defer_foreign_keys = ON").execute(&mut *tx).await?;
+    let post = PostWrite {
+        id: "warm-up",
+        parent_id: None,
+        content: StoredContent::of(""),
+        created_at: now,
+        updated_at: now,
+        version: Hlc::from_wall(now),
+        variant: "note",
+        position: None,
+        org_id: None,
+        remind_at: None,
+    };
+    scope.posts_upsert(&mut tx, std::slice::from_ref(&post)).await?;
+    tx.rollback().await
+}
+
+/// Probes the pool forever, reporting when it becomes unhealthy and when it recovers.
+async fn pool_watchdog(pool: sqlx::SqlitePool, config: &'static DbPoolConfig) {
+    let mut interval = time::interval(config.watchdog_interval);
+    let mut healthy = true;
+    loop {
+        interval.tick().await;
+        let ok = pool_probe(&pool, config).await;
+        metrics().gauge_set(
+            "db_pool_healthy",
+            "Whether the last watchdog probe acquired a connection.",
+            &[],
+            if ok { 1.0 } else { 0.0 },
+        );
+        if ok && !healthy {
+            tracing::info!("db pool recovered");
+        }
+        healthy = ok;
+    }
+}
+
 pub fn stage() -> AdHoc {
     AdHoc::on_ignite("SQLx Stage", |rocket| async {
+        let figment = db_pool_config().merge_into(rocket.figment().clone());
+        let rocket = manage_default(rocket, |_| IdSource(Arc::new(NanoIds)));
         rocket
+            .configure(figment)
+            .attach(replication::restore_stage())
             .attach(Db::init())
             .attach(AdHoc::try_on_ignite("SQLx Migrations", migrations_run))
+            .attach(AdHoc::on_liftoff("SQLx Pool Watchdog", |rocket| {
+                Box::pin(async move {
+                    if let Some(db) = Db::fetch(rocket) {
+                        let start = Instant::now();
+                        match pool_warm_up(db, db_pool_config().warmup_connections).await {
+                            Ok(warmed) => tracing::info!(
+                                connections = warmed,
+                                elapsed_ms = start.elapsed().as_millis() as u64,
+                                "db statements warmed up"
+                            ),
+                            Err(e) => tracing::warn!("db warm-up failed: {}", e),
+                        }
+                        rocket::tokio::spawn(pool_watchdog((**db).clone(), db_pool_config()));
+                    }
+                })
+            }))
     })
 }
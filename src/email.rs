@@ -0,0 +1,630 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use mail_struct::Mail;
+use regex::Regex;
+use rocket::fairing::AdHoc;
+use rocket::serde::Serialize;
+use smtp_send::Send as Smtp;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use tracing::Instrument;
+
+use crate::db::{id_gen, query_span, sqlx};
+use crate::metrics::metrics;
+use crate::util::*;
+
+/// The emails the app sends, named in `emails.template`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailTemplate {
+    LoginCode,
+    NewDevice,
+    Reminder,
+    SelfCheck,
+}
+
+impl EmailTemplate {
+    pub const ALL: [Self; 4] = [Self::LoginCode, Self::NewDevice, Self::Reminder, Self::SelfCheck];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::LoginCode => "login_code",
+            Self::NewDevice => "new_device",
+            Self::Reminder => "reminder",
+            Self::SelfCheck => "self_check",
+        }
+    }
+
+    /// The subject, after the prefix, unless `EMAIL_SUBJECT_<TEMPLATE>` says otherwise.
+    fn subject_default(self) -> &'static str {
+        match self {
+            Self::LoginCode => "Your login code",
+            Self::NewDevice => "New sign-in to your account",
+            Self::Reminder => "Reminder: {title}",
+            Self::SelfCheck => "Email self-check",
+        }
+    }
+}
+
+/// Who the app's emails come from and how they are titled: `EMAIL_FROM` (`codes@example.com`),
+/// `EMAIL_FROM_NAME` and `EMAIL_REPLY_TO` (unset), and subjects made of `EMAIL_SUBJECT_PREFIX`
+/// (`[ROCKET]`) and `EMAIL_SUBJECT_<TEMPLATE>`, e.g. `EMAIL_SUBJECT_REMINDER`, where `{title}` is
+/// the first line of the post. Signatures are made for `DKIM_DOMAIN` (the domain of `EMAIL_FROM`)
+/// under `DKIM_SELECTOR` (`default`), whose public key is published at
+/// `<selector>._domainkey.<domain>`.
+#[derive(Debug, Clone)]
+pub struct EmailIdentity {
+    pub from: String,
+    pub from_name: Option<String>,
+    pub reply_to: Option<String>,
+    pub subject_prefix: String,
+    pub subjects: Vec<(EmailTemplate, String)>,
+    pub dkim_domain: String,
+    pub dkim_selector: String,
+}
+
+impl EmailIdentity {
+    /// Reads the identity from the environment, returning why it is invalid.
+    pub fn from_env() -> Result<Self, String> {
+        let optional = |name: &str| config_var(name).filter(|value| !value.trim().is_empty());
+        let from = optional("EMAIL_FROM").unwrap_or_else(|| "codes@example.com".into());
+        let from_domain = from.rsplit_once('@').map(|(_, domain)| domain.to_ascii_lowercase());
+        let identity = Self {
+            from_name: optional("EMAIL_FROM_NAME"),
+            reply_to: optional("EMAIL_REPLY_TO"),
+            subject_prefix: config_var("EMAIL_SUBJECT_PREFIX").unwrap_or_else(|| "[ROCKET]".into()),
+            subjects: EmailTemplate::ALL
+                .into_iter()
+                .map(|template| {
+                    let name = format!("EMAIL_SUBJECT_{}", template.as_str().to_ascii_uppercase());
+                    let subject = optional(&name).unwrap_or_else(|| template.subject_default().into());
+                    (template, subject)
+                })
+                .collect(),
+            dkim_domain: optional("DKIM_DOMAIN").or(from_domain).unwrap_or_default(),
+            dkim_selector: optional("DKIM_SELECTOR").unwrap_or_else(|| "default".into()),
+            from,
+        };
+        identity.check()?;
+        Ok(identity)
+    }
+
+    /// Checks that the addresses are valid and that no value could break out of its header.
+    pub fn check(&self) -> Result<(), String> {
+        if !email_is_valid(&self.from) {
+            return Err(format!("EMAIL_FROM is not an email address: {}", self.from));
+        }
+        if let Some(reply_to) = self.reply_to.as_deref().filter(|reply_to| !email_is_valid(reply_to)) {
+            return Err(format!("EMAIL_REPLY_TO is not an email address: {}", reply_to));
+        }
+        let header_safe = |value: &str| !value.chars().any(char::is_control);
+        let quotable = |name: &str| header_safe(name) && !name.contains(['"', '\\']);
+        if !self.from_name.as_deref().is_none_or(quotable) {
+            return Err("EMAIL_FROM_NAME must be a single line without quotes or backslashes".into());
+        }
+        if !header_safe(&self.subject_prefix) {
+            return Err("EMAIL_SUBJECT_PREFIX must be a single line".into());
+        }
+        if let Some((template, _)) = self.subjects.iter().find(|(_, subject)| !header_safe(subject)) {
+            return Err(format!(
+                "EMAIL_SUBJECT_{} must be a single line",
+                template.as_str().to_ascii_uppercase()
+            ));
+        }
+        let dns_label = |label: &str| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        };
+        if !self.dkim_selector.split('.').all(dns_label) {
+            return Err(format!("DKIM_SELECTOR is not a DNS name: {}", self.dkim_selector));
+        }
+        if !self.dkim_domain.contains('.') || !self.dkim_domain.split('.').all(dns_label) {
+            return Err(format!("DKIM_DOMAIN is not a domain name: {}", self.dkim_domain));
+        }
+        Ok(())
+    }
+
+    /// Returns the subject of `template`, with `{title}` replaced by `title`.
+    pub fn subject(&self, template: EmailTemplate, title: &str) -> String {
+        let subject = self
+            .subjects
+            .iter()
+            .find(|(t, _)| *t == template)
+            .map_or(template.subject_default(), |(_, subject)| subject.as_str())
+            .replace("{title}", title);
+        match self.subject_prefix.trim() {
+            "" => subject,
+            prefix => format!("{} {}", prefix, subject),
+        }
+    }
+
+    /// Returns the email of `template` to `to`, from this identity.
+    pub fn email(&self, template: EmailTemplate, to: &str, title: &str, body: &str) -> Email {
+        Email {
+            from: self.from.clone(),
+            from_name: self.from_name.clone(),
+            reply_to: self.reply_to.clone(),
+            to: to.into(),
+            subject: self.subject(template, title),
+            body: body.into(),
+        }
+    }
+}
+
+/// Returns the process-wide `EmailIdentity`. The email stage refuses to start with an invalid one.
+pub fn email_identity() -> &'static EmailIdentity {
+    static IDENTITY: OnceLock<EmailIdentity> = OnceLock::new();
+    IDENTITY.get_or_init(|| EmailIdentity::from_env().unwrap_or_else(|e| panic!("{}", e)))
+}
+
+/// A plain-text email.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Email {
+    pub from: String,
+    /// Display name shown for `from`.
+    pub from_name: Option<String>,
+    pub reply_to: Option<String>,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Why an email was not delivered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliveryError {
+    pub message: String,
+    /// The recipient's server refused the address for good (a 5xx reply): a hard bounce, after
+    /// which the address is suppressed.
+    pub permanent: bool,
+}
+
+/// Delivers emails, returning the provider's ID of the message when there is one. `Mailer` records
+/// the outcome; callers don't see it, as none can do better than the user asking again.
+#[rocket::async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<Option<String>, DeliveryError>;
+}
+
+/// Sends emails directly to the recipient's MX with DKIM signing, using the `smtp_send` crate. In
+/// debug mode, sending is only simulated by logging the email.
+pub struct SmtpSender;
+
+/// Matches the SMTP reply codes of permanent failures, e.g. `550 5.1.1 User unknown`.
+fn smtp_permanent_reply() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(r"\b5\d\d\b").expect("valid regex"))
+}
+
+#[rocket::async_trait]
+impl EmailSender for SmtpSender {
+    async fn send(&self, email: &Email) -> Result<Option<String>, DeliveryError> {
+        let Email {
+            from,
+            from_name,
+            reply_to,
+            to,
+            subject,
+            body,
+        } = email;
+        let domain = from.rsplit_once('@').map_or("localhost", |(_, domain)| domain);
+        let message_id = format!("<{}@{}>", id_gen(), domain);
+        if app_mode() == "debug" {
+            tracing::info!(from, to, subject, body, "email send simulated (debug mode)");
+            dev_outbox().push(&message_id, email);
+            return Ok(Some(message_id));
+        }
+        let sk = env_get().dkim_key_private.as_bytes().to_vec();
+
+        // Create sender with DKIM selector
+        let identity = email_identity();
+        let sender = Smtp::new(&identity.dkim_selector, &sk);
+
+        let mut headers = match from_name {
+            Some(name) => format!("From: \"{}\" <{}>\r\n", name, from),
+            None => format!("From: {}\r\n", from),
+        };
+        if let Some(reply_to) = reply_to {
+            headers.push_str(&format!("Reply-To: {}\r\n", reply_to));
+        }
+        headers.push_str(&format!("Message-ID: {}\r\nSubject: {}\r\n", message_id, subject));
+        let mut mail = Mail::new(
+            from.as_str(),
+            [to.as_str()],
+            format!("{}\r\n{}", headers, body).into_bytes(),
+        )
+        .unwrap();
+
+        let result = sender.send(&mut mail).await;
+
+        if result.error_li.is_empty() {
+            tracing::info!(to, success = result.success, "email sent");
+            Ok(Some(message_id))
+        } else {
+            tracing::warn!(
+                to,
+                success = result.success,
+                errors = result.error_li.len(),
+                "email send failed"
+            );
+            let message = format!("{:?}", result.error_li);
+            Err(DeliveryError {
+                permanent: smtp_permanent_reply().is_match(&message),
+                message,
+            })
+        }
+    }
+}
+
+/// Emails kept by `DevOutbox`.
+pub const DEV_OUTBOX_SIZE: usize = 50;
+
+/// An email whose sending was simulated in debug mode.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct DevEmail {
+    pub message_id: String,
+    pub from: String,
+    pub from_name: Option<String>,
+    pub reply_to: Option<String>,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+    pub sent_at: DateTime<Utc>,
+}
+
+/// The latest `DEV_OUTBOX_SIZE` emails simulated in debug mode, so that developers can read them
+/// through `GET /api/dev/emails` rather than in the logs.
+#[derive(Default)]
+pub struct DevOutbox(Mutex<VecDeque<DevEmail>>);
+
+impl DevOutbox {
+    fn push(&self, message_id: &str, email: &Email) {
+        let mut emails = self.0.lock().expect("dev outbox lock poisoned");
+        if emails.len() >= DEV_OUTBOX_SIZE {
+            emails.pop_back();
+        }
+        emails.push_front(DevEmail {
+            message_id: message_id.into(),
+            from: email.from.clone(),
+            from_name: email.from_name.clone(),
+            reply_to: email.reply_to.clone(),
+            to: email.to.clone(),
+            subject: email.subject.clone(),
+            body: email.body.clone(),
+            sent_at: Utc::now(),
+        });
+    }
+
+    /// Returns the emails to `to` (any address when `None`), newest first.
+    pub fn list(&self, to: Option<&str>) -> Vec<DevEmail> {
+        let emails = self.0.lock().expect("dev outbox lock poisoned");
+        emails
+            .iter()
+            .filter(|email| to.is_none_or(|to| email.to.eq_ignore_ascii_case(to)))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Returns the process-wide `DevOutbox`, empty outside debug mode.
+pub fn dev_outbox() -> &'static DevOutbox {
+    static OUTBOX: OnceLock<DevOutbox> = OnceLock::new();
+    OUTBOX.get_or_init(DevOutbox::default)
+}
+
+/// Managed state holding the sender of the emails the app sends, such as login codes.
+#[derive(Clone)]
+pub struct Mailer(pub Arc<dyn EmailSender>);
+
+impl Mailer {
+    pub fn from_env() -> Self {
+        Self(Arc::new(SmtpSender))
+    }
+
+    /// Sends `template` to `to` from the `EmailIdentity`, and records it in `emails` along with the
+    /// outcome. `title` fills in the subject, see `EmailIdentity::subject`. Emails to suppressed
+    /// addresses are recorded without being sent, and a hard bounce suppresses the address.
+    pub async fn send(
+        &self,
+        db: &mut sqlx::SqliteConnection,
+        template: EmailTemplate,
+        to: &str,
+        title: &str,
+        body: &str,
+    ) {
+        let email = email_identity().email(template, to, title, body);
+        let template = template.as_str();
+        let recipient = email.to.to_lowercase();
+        let suppressed = sqlx::query_scalar!("SELECT reason FROM email_suppressions WHERE email = ?", recipient)
+            .fetch_optional(&mut *db)
+            .instrument(query_span("email_suppressions.get"))
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("email:suppression-lookup-failed: {}", e);
+                None
+            });
+
+        let (status, message_id, error) = match suppressed {
+            Some(reason) => {
+                tracing::info!(template, "email:suppressed");
+                (EMAIL_SUPPRESSED, None, Some(reason))
+            }
+            None => match self.0.send(&email).await {
+                Ok(message_id) => (EMAIL_SENT, message_id, None),
+                Err(e) => {
+                    if e.permanent {
+                        email_suppress(&mut *db, &recipient, &e.message).await;
+                    }
+                    (EMAIL_FAILED, None, Some(e.message))
+                }
+            },
+        };
+        metrics().counter_inc("emails_total", "Emails sent, by outcome.", &[("status", status)]);
+
+        let id = id_gen();
+        let now = Utc::now();
+        let recorded = sqlx::query!(
+            "INSERT INTO emails (id, recipient, template, status, provider_message_id, error, created_at) \
+            VALUES (?, ?, ?, ?, ?, ?, ?)",
+            id,
+            recipient,
+            template,
+            status,
+            message_id,
+            error,
+            now
+        )
+        .execute(&mut *db)
+        .instrument(query_span("emails.insert"))
+        .await;
+        if let Err(e) = recorded {
+            tracing::error!("email:record-failed: {}", e);
+        }
+    }
+}
+
+/// `status` of an email in `emails`.
+pub const EMAIL_SENT: &str = "sent";
+pub const EMAIL_FAILED: &str = "failed";
+/// Not sent, as the address is suppressed.
+pub const EMAIL_SUPPRESSED: &str = "suppressed";
+
+/// Stops sending emails to the address, for `reason`.
+async fn email_suppress(db: &mut sqlx::SqliteConnection, email: &str, reason: &str) {
+    let now = Utc::now();
+    let suppressed = sqlx::query!(
+        "INSERT OR IGNORE INTO email_suppressions (email, reason, created_at) VALUES (?, ?, ?)",
+        email,
+        reason,
+        now
+    )
+    .execute(db)
+    .instrument(query_span("email_suppressions.insert"))
+    .await;
+    match suppressed {
+        Ok(_) => tracing::warn!("email:suppressed-after-hard-bounce"),
+        Err(e) => tracing::error!("email:suppress-failed: {}", e),
+    }
+}
+
+/// An email as recorded in `emails`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct EmailRecord {
+    pub id: String,
+    pub recipient: String,
+    pub template: String,
+    pub status: String,
+    pub provider_message_id: Option<String>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Lists the latest `limit` emails that failed or were suppressed, newest first.
+pub async fn emails_failed(db: &mut sqlx::SqliteConnection, limit: i64) -> Result<Vec<EmailRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        EmailRecord,
+        "SELECT id, recipient, template, status, provider_message_id, error, \
+        created_at AS \"created_at: DateTime<Utc>\" FROM emails WHERE status IN (?, ?) \
+        ORDER BY created_at DESC, id LIMIT ?",
+        EMAIL_FAILED,
+        EMAIL_SUPPRESSED,
+        limit
+    )
+    .fetch_all(db)
+    .instrument(query_span("emails.failed"))
+    .await
+}
+
+/// Lets emails be sent to the address again, e.g. once the user fixed their mailbox. Returns
+/// whether it was suppressed.
+pub async fn email_unsuppress(db: &mut sqlx::SqliteConnection, email: &str) -> Result<bool, sqlx::Error> {
+    let email = email.to_lowercase();
+    let deleted = sqlx::query!("DELETE FROM email_suppressions WHERE email = ?", email)
+        .execute(db)
+        .instrument(query_span("email_suppressions.delete"))
+        .await?
+        .rows_affected();
+    Ok(deleted > 0)
+}
+
+/// Whether emails can be delivered, as found by `email_self_check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmailHealth {
+    /// The check has not run, or was skipped.
+    Unknown,
+    Ok,
+    /// Delivery is known to fail, for the given reason.
+    Broken(String),
+}
+
+/// Managed state holding the instance's `EmailHealth`, which `send-code` and `/readyz` report.
+#[derive(Debug, Clone)]
+pub struct EmailStatus(Arc<RwLock<EmailHealth>>);
+
+impl EmailStatus {
+    pub fn new(health: EmailHealth) -> Self {
+        Self(Arc::new(RwLock::new(health)))
+    }
+
+    pub fn get(&self) -> EmailHealth {
+        self.0.read().expect("email status lock poisoned").clone()
+    }
+
+    pub fn set(&self, health: EmailHealth) {
+        *self.0.write().expect("email status lock poisoned") = health;
+    }
+}
+
+/// The startup self-check of email delivery, unless `EMAIL_SELF_CHECK` is `false`. It always checks
+/// that the DKIM keys parse; with `DKIM_DOMAIN` set, that the public key is the one published in
+/// DNS for the `EmailIdentity`; with `EMAIL_SELF_CHECK_TO`, that a test email to that address goes
+/// through.
+#[derive(Debug, Clone)]
+pub struct EmailCheckConfig {
+    pub enabled: bool,
+    pub dkim_record: bool,
+    pub test_to: Option<String>,
+}
+
+impl EmailCheckConfig {
+    pub fn from_env() -> Self {
+        let optional = |name| config_var(name).filter(|value: &String| !value.is_empty());
+        Self {
+            enabled: env_parse_or("EMAIL_SELF_CHECK", true),
+            dkim_record: optional("DKIM_DOMAIN").is_some(),
+            test_to: optional("EMAIL_SELF_CHECK_TO"),
+        }
+    }
+}
+
+/// Returns the DER bytes of the PEM block labeled `label`, e.g. `PUBLIC KEY`.
+pub(crate) fn pem_decode(pem: &str, label: &str) -> Option<Vec<u8>> {
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let body = pem.trim().strip_prefix(begin.as_str())?.strip_suffix(end.as_str())?;
+    let body = body.split_whitespace().collect::<String>();
+    // Keys are DER sequences
+    BASE64.decode(body).ok().filter(|der| der.first() == Some(&0x30))
+}
+
+/// Checks that the keys are a PKCS#8 private key and a public key, as made by `just keygen-dkim`.
+/// Returns the public key as published in the `p=` tag of the DKIM record.
+pub fn dkim_keys_check(private: &str, public: &str) -> Result<String, String> {
+    pem_decode(private, "PRIVATE KEY").ok_or("DKIM_KEY_PRIVATE is not a PKCS#8 PEM private key")?;
+    let public = pem_decode(public, "PUBLIC KEY").ok_or("DKIM_KEY_PUBLIC is not a PEM public key")?;
+    Ok(BASE64.encode(public))
+}
+
+/// Checks that the DKIM record of `domain` publishes `public_key`. DNS failures other than a missing
+/// record are logged and let through, as they say nothing about delivery.
+async fn dkim_record_check(domain: &str, selector: &str, public_key: &str) -> Result<(), String> {
+    use hickory_resolver::TokioAsyncResolver;
+    use hickory_resolver::error::ResolveErrorKind;
+
+    let name = format!("{}._domainkey.{}.", selector, domain);
+    let resolver = match TokioAsyncResolver::tokio_from_system_conf() {
+        Ok(resolver) => resolver,
+        Err(e) => {
+            tracing::warn!("DKIM record check skipped, failed to build DNS resolver: {}", e);
+            return Ok(());
+        }
+    };
+    let lookup = match resolver.txt_lookup(name.as_str()).await {
+        Ok(lookup) => lookup,
+        Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+            return Err(format!("no DKIM record at {}", name));
+        }
+        Err(e) => {
+            tracing::warn!("DKIM record check skipped, lookup of {} failed: {}", name, e);
+            return Ok(());
+        }
+    };
+    let published = lookup.iter().any(|txt| {
+        let record = txt
+            .txt_data()
+            .iter()
+            .map(|part| String::from_utf8_lossy(part))
+            .collect::<String>();
+        record
+            .split(';')
+            .filter_map(|tag| tag.trim().strip_prefix("p="))
+            .any(|key| key.split_whitespace().collect::<String>() == public_key)
+    });
+    match published {
+        true => Ok(()),
+        false => Err(format!("the DKIM record at {} doesn't match DKIM_KEY_PUBLIC", name)),
+    }
+}
+
+/// Checks that emails can be delivered, see `EmailCheckConfig`. Skipped in debug mode, where
+/// sending is simulated.
+pub async fn email_self_check(config: &EmailCheckConfig) -> EmailHealth {
+    if !config.enabled || app_mode() == "debug" {
+        return EmailHealth::Unknown;
+    }
+    let env = env_get();
+    let public_key = match dkim_keys_check(&env.dkim_key_private, &env.dkim_key_public) {
+        Ok(public_key) => public_key,
+        Err(reason) => return EmailHealth::Broken(reason),
+    };
+    let identity = email_identity();
+    let record = match config.dkim_record {
+        true => dkim_record_check(&identity.dkim_domain, &identity.dkim_selector, &public_key).await,
+        false => Ok(()),
+    };
+    if let Err(reason) = record {
+        return EmailHealth::Broken(reason);
+    }
+    if let Some(to) = &config.test_to {
+        let email = email_identity().email(
+            EmailTemplate::SelfCheck,
+            to,
+            "",
+            "This email checks that the app can deliver emails. No action is needed.",
+        );
+        if let Err(e) = SmtpSender.send(&email).await {
+            return EmailHealth::Broken(e.message);
+        }
+    }
+    EmailHealth::Ok
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Email stage", |rocket| async {
+        if let Err(e) = EmailIdentity::from_env() {
+            tracing::error!("Invalid email identity: {}", e);
+            return Err(rocket);
+        }
+        let rocket = manage_default(rocket, |_| EmailStatus::new(EmailHealth::Unknown));
+        Ok(rocket.attach(AdHoc::on_liftoff("Email self-check", |rocket| {
+            Box::pin(async move {
+                let status = rocket.state::<EmailStatus>().expect("email status is managed").clone();
+                // In the background, as a test email can take a while
+                rocket::tokio::spawn(async move {
+                    let health = email_self_check(&EmailCheckConfig::from_env()).await;
+                    match &health {
+                        EmailHealth::Broken(reason) => tracing::error!("email delivery is broken: {}", reason),
+                        EmailHealth::Ok => tracing::info!("email self-check passed"),
+                        EmailHealth::Unknown => {}
+                    }
+                    let healthy = !matches!(health, EmailHealth::Broken(_));
+                    metrics().gauge_set(
+                        "email_healthy",
+                        "Whether the email self-check passed, or was skipped.",
+                        &[],
+                        if healthy { 1.0 } else { 0.0 },
+                    );
+                    // A skipped check leaves the status as managed
+                    if !matches!(health, EmailHealth::Unknown) {
+                        status.set(health);
+                    }
+                });
+            })
+        })))
+    })
+}
@@ -0,0 +1,182 @@
+use rocket::http::{Header, Status};
+use rocket::response::{self, Responder};
+use rocket::serde::{Serialize, json};
+use rocket::{Catcher, Request};
+
+use crate::telemetry::RequestId;
+
+/// Machine-readable error codes, serialized in snake case, that clients can branch on instead of
+/// parsing messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    /// The resource exists but belongs to another user, see `ForeignPostPolicy::Forbid`.
+    NotOwner,
+    /// The account was suspended by an administrator.
+    AccountDisabled,
+    NotFound,
+    /// A concurrent edit could not be merged with the request's.
+    Conflict,
+    ValidationFailed,
+    /// The request, or one of its fields, is over a size limit given in the details.
+    PayloadTooLarge,
+    RateLimited,
+    /// A CAPTCHA token is required but was not sent.
+    CaptchaRequired,
+    /// The CAPTCHA provider rejected the token.
+    CaptchaFailed,
+    Unavailable,
+    /// The server is in maintenance mode and refuses changes for now; reads still work.
+    Maintenance,
+    /// Emails, such as login codes, can't be delivered until the server's email setup is fixed.
+    EmailUnavailable,
+    Timeout,
+    Internal,
+}
+
+impl ErrorCode {
+    /// The code and default message used for a bare status, e.g. by the catchers.
+    pub fn from_status(status: Status) -> (Self, &'static str) {
+        match status.code {
+            401 => (Self::Unauthorized, "Unauthorized"),
+            403 => (Self::Forbidden, "Forbidden"),
+            404 => (Self::NotFound, "Not found"),
+            409 => (Self::Conflict, "Conflict"),
+            413 => (Self::PayloadTooLarge, "Payload too large"),
+            422 => (Self::ValidationFailed, "Inputs are invalid"),
+            429 => (Self::RateLimited, "Too many requests"),
+            503 => (Self::Unavailable, "Service unavailable"),
+            504 => (Self::Timeout, "Request timed out"),
+            400..=499 => (Self::BadRequest, "Bad request"),
+            _ => (Self::Internal, "Internal Server Error"),
+        }
+    }
+}
+
+/// The body of every error response, from handlers and catchers alike.
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct ErrorBody {
+    pub code: ErrorCode,
+    pub message: String,
+    pub details: Option<json::Value>,
+    pub request_id: String,
+}
+
+/// An error response. Rendered as an `ErrorBody` carrying the request's ID.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub status: Status,
+    pub code: ErrorCode,
+    pub message: String,
+    pub details: Option<json::Value>,
+    /// Seconds to send in a `Retry-After` header, for 429 and 503 responses.
+    pub retry_after: Option<u64>,
+}
+
+impl ApiError {
+    pub fn new(status: Status, code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+            details: None,
+            retry_after: None,
+        }
+    }
+
+    /// An error with the default code and message for `status`.
+    pub fn from_status(status: Status) -> Self {
+        let (code, message) = ErrorCode::from_status(status);
+        Self::new(status, code, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(Status::Unauthorized, ErrorCode::Unauthorized, message)
+    }
+
+    pub fn account_disabled() -> Self {
+        Self::new(
+            Status::Unauthorized,
+            ErrorCode::AccountDisabled,
+            "This account is suspended",
+        )
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(Status::NotFound, ErrorCode::NotFound, message)
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new(Status::Conflict, ErrorCode::Conflict, message)
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        Self::new(Status::UnprocessableEntity, ErrorCode::ValidationFailed, message)
+    }
+
+    pub fn internal() -> Self {
+        Self::from_status(Status::InternalServerError)
+    }
+
+    pub fn details(mut self, details: json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    /// The error for a status raised before a handler ran: the one a request guard recorded with
+    /// `guard_error_set` for that status, or else the default one.
+    pub fn caught(status: Status, request: &Request<'_>) -> Self {
+        match &request.local_cache(|| GuardError(None)).0 {
+            Some(error) if error.status == status => error.clone(),
+            _ => Self::from_status(status),
+        }
+    }
+
+    /// Renders the body for the given request.
+    pub fn body(&self, request: &Request<'_>) -> ErrorBody {
+        ErrorBody {
+            code: self.code,
+            message: self.message.clone(),
+            details: self.details.clone(),
+            request_id: RequestId::of(request).to_owned(),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = (self.status, json::Json(self.body(request))).respond_to(request)?;
+        if let Some(seconds) = self.retry_after {
+            response.set_header(Header::new("Retry-After", seconds.to_string()));
+        }
+        Ok(response)
+    }
+}
+
+/// A specific error recorded by a failing request guard for the catchers.
+struct GuardError(Option<ApiError>);
+
+/// Records the error the catchers should answer with when a request guard fails with its status,
+/// as guards can otherwise only report a bare status. The first recorded error of a request wins.
+pub fn guard_error_set(request: &Request<'_>, error: ApiError) {
+    request.local_cache(|| GuardError(Some(error)));
+}
+
+#[catch(default)]
+fn default_catcher(status: Status, request: &Request) -> ApiError {
+    ApiError::caught(status, request)
+}
+
+/// Catchers rendering every unhandled error status as an `ErrorBody`.
+pub fn catchers() -> Vec<Catcher> {
+    catchers![default_catcher]
+}
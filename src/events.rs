@@ -0,0 +1,342 @@
+use chrono::TimeDelta;
+use hmac::{Hmac, Mac};
+use rocket::fairing::AdHoc;
+use rocket::serde::{Deserialize, Serialize, json};
+use rocket::tokio::sync::Notify;
+use rocket::tokio::time;
+use sha2::Sha256;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::Instrument;
+
+use crate::cache::response_cache;
+use crate::clock::Timekeeper;
+use crate::db::*;
+use crate::handlers::posts::{PostChange, PostChangeKind, post_changes};
+use crate::metrics::metrics;
+use crate::util::*;
+
+/// Header carrying the `sha256=<hex>` HMAC of a webhook body, when `WEBHOOK_SECRET` is set.
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+/// The outbox dispatcher wakes after every commit that recorded events, and otherwise polls every
+/// `EVENTS_POLL_INTERVAL_MS` (1000). It hands consumers up to `EVENTS_BATCH` (100) events at a
+/// time. Events every consumer has acknowledged are pruned after `EVENTS_RETENTION_HOURS` (72).
+///
+/// Besides live subscribers in this process, events are POSTed to `WEBHOOK_URL` when set.
+#[derive(Debug, Clone)]
+pub struct EventsConfig {
+    pub poll_interval: Duration,
+    pub batch: i64,
+    pub retention: TimeDelta,
+    pub webhook_url: Option<String>,
+    pub webhook_secret: Option<String>,
+}
+
+impl EventsConfig {
+    pub fn from_env() -> Self {
+        let non_empty = |name| config_var(name).filter(|value| !value.is_empty());
+        Self {
+            poll_interval: Duration::from_millis(env_parse_or("EVENTS_POLL_INTERVAL_MS", 1000)),
+            batch: env_parse_or("EVENTS_BATCH", 100),
+            retention: TimeDelta::hours(env_parse_or("EVENTS_RETENTION_HOURS", 72)),
+            webhook_url: non_empty("WEBHOOK_URL"),
+            webhook_secret: non_empty("WEBHOOK_SECRET"),
+        }
+    }
+}
+
+/// Returns the process-wide `EventsConfig`.
+pub fn events_config() -> &'static EventsConfig {
+    static CONFIG: OnceLock<EventsConfig> = OnceLock::new();
+    CONFIG.get_or_init(EventsConfig::from_env)
+}
+
+/// A post change read back from the outbox. `id` increases with every event, so consumers can
+/// drop the duplicates at-least-once delivery may produce.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Event {
+    pub id: i64,
+    pub user_id: i64,
+    pub kind: String,
+    pub post_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Records a post change made at `now` in the outbox. Call it in the transaction that makes the
+/// change, so that the event is stored if and only if the change is, and `events_wake` once it is
+/// committed.
+pub async fn event_record(
+    db: &mut sqlx::SqliteConnection,
+    user_id: i64,
+    kind: PostChangeKind,
+    post_id: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    let kind = kind.as_str();
+    sqlx::query!(
+        "INSERT INTO events (user_id, kind, post_id, created_at) VALUES (?, ?, ?, ?)",
+        user_id,
+        kind,
+        post_id,
+        now
+    )
+    .execute(db)
+    .instrument(query_span("events.insert"))
+    .await?;
+    Ok(())
+}
+
+fn events_notify() -> &'static Notify {
+    static NOTIFY: OnceLock<Notify> = OnceLock::new();
+    NOTIFY.get_or_init(Notify::new)
+}
+
+/// Tells the dispatcher that new events were committed, rather than waiting for its next poll.
+pub fn events_wake() {
+    events_notify().notify_one();
+}
+
+/// A destination of outbox events. Delivery is at least once: a batch is offered again until
+/// `deliver` succeeds, and only then does the consumer's cursor move past it.
+#[rocket::async_trait]
+pub trait EventSink: Send + Sync {
+    /// The consumer's name in `event_cursors`.
+    fn name(&self) -> &'static str;
+    async fn deliver(&self, events: &[Event]) -> Result<(), String>;
+}
+
+/// Publishes events to the subscribers of this process (GraphQL subscriptions, gRPC pulls).
+pub struct BroadcastSink;
+
+#[rocket::async_trait]
+impl EventSink for BroadcastSink {
+    fn name(&self) -> &'static str {
+        "broadcast"
+    }
+
+    async fn deliver(&self, events: &[Event]) -> Result<(), String> {
+        events.iter().for_each(event_broadcast);
+        Ok(())
+    }
+}
+
+/// Sends an event to the subscribers of this process, and drops the responses cached for its user
+/// now that the change is committed. Having no subscribers is not an error.
+pub fn event_broadcast(event: &Event) {
+    response_cache().invalidate_user(event.user_id);
+    if let Some(kind) = PostChangeKind::parse(&event.kind) {
+        let _ = post_changes().send(PostChange {
+            user_id: event.user_id,
+            kind,
+            id: event.post_id.clone(),
+        });
+    }
+}
+
+/// POSTs `{ "events": [...] }` to a URL, signed with HMAC-SHA256 of the body when a secret is set.
+/// Any response but 2xx is a failed delivery.
+pub struct WebhookSink {
+    pub url: String,
+    pub secret: Option<String>,
+}
+
+/// Returns the `sha256=<hex>` signature of a webhook body.
+pub fn webhook_signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+fn http_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("failed to build HTTP client")
+    })
+}
+
+#[rocket::async_trait]
+impl EventSink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn deliver(&self, events: &[Event]) -> Result<(), String> {
+        let body = json::to_string(&json::json!({ "events": events }))
+            .map_err(|e| e.to_string())?
+            .into_bytes();
+        let mut request = http_client().post(&self.url).header("Content-Type", "application/json");
+        if let Some(secret) = &self.secret {
+            request = request.header(WEBHOOK_SIGNATURE_HEADER, webhook_signature(secret, &body));
+        }
+        let response = request.body(body).send().await.map_err(|e| e.to_string())?;
+        match response.status().is_success() {
+            true => Ok(()),
+            false => Err(format!("webhook responded {}", response.status())),
+        }
+    }
+}
+
+/// Returns the consumer's cursor. A new consumer starts after the latest event instead of
+/// replaying the whole outbox.
+async fn cursor_get(db: &mut sqlx::SqliteConnection, consumer: &str, now: DateTime<Utc>) -> Result<i64, sqlx::Error> {
+    sqlx::query!(
+        "INSERT OR IGNORE INTO event_cursors (consumer, event_id, updated_at) \
+        SELECT ?, COALESCE(MAX(id), 0), ? FROM events",
+        consumer,
+        now
+    )
+    .execute(&mut *db)
+    .instrument(query_span("event_cursors.insert"))
+    .await?;
+    sqlx::query_scalar!("SELECT event_id FROM event_cursors WHERE consumer = ?", consumer)
+        .fetch_one(db)
+        .instrument(query_span("event_cursors.read"))
+        .await
+}
+
+/// Hands the sink the events after its cursor, then moves the cursor past them at `now`. Returns
+/// how many events were delivered; a failed delivery leaves the cursor where it was.
+pub async fn events_dispatch(
+    db: &mut sqlx::SqliteConnection,
+    sink: &dyn EventSink,
+    batch: i64,
+    now: DateTime<Utc>,
+) -> Result<usize, String> {
+    let consumer = sink.name();
+    let cursor = cursor_get(&mut *db, consumer, now).await.map_err(|e| e.to_string())?;
+    let events = sqlx::query_as!(
+        Event,
+        "SELECT id, user_id, kind, post_id, created_at AS \"created_at: DateTime<Utc>\" FROM events \
+        WHERE id > ? ORDER BY id LIMIT ?",
+        cursor,
+        batch
+    )
+    .fetch_all(&mut *db)
+    .instrument(query_span("events.after"))
+    .await
+    .map_err(|e| e.to_string())?;
+    let Some(last) = events.last() else {
+        return Ok(0);
+    };
+
+    let labels = [("consumer", consumer)];
+    if let Err(e) = sink.deliver(&events).await {
+        metrics().counter_inc("events_delivery_failures_total", "Failed outbox deliveries.", &labels);
+        return Err(e);
+    }
+    sqlx::query!(
+        "UPDATE event_cursors SET event_id = ?, updated_at = ? WHERE consumer = ?",
+        last.id,
+        now,
+        consumer
+    )
+    .execute(&mut *db)
+    .instrument(query_span("event_cursors.update"))
+    .await
+    .map_err(|e| e.to_string())?;
+    metrics().counter_add(
+        "events_delivered_total",
+        "Outbox events delivered.",
+        &labels,
+        events.len() as u64,
+    );
+    Ok(events.len())
+}
+
+/// Deletes events older than the retention at `now` that every consumer has acknowledged. Events
+/// a consumer is stuck on are kept, however old.
+pub async fn events_prune(
+    db: &mut sqlx::SqliteConnection,
+    retention: TimeDelta,
+    now: DateTime<Utc>,
+) -> Result<u64, sqlx::Error> {
+    let before = now - retention;
+    let result = sqlx::query!(
+        "DELETE FROM events WHERE created_at < ? AND id <= (SELECT COALESCE(MIN(event_id), 0) FROM event_cursors)",
+        before
+    )
+    .execute(db)
+    .instrument(query_span("events.prune"))
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Delivers the outbox to every sink forever. A full batch is followed by the next one straight
+/// away; a failing sink is retried on the next round without holding up the others.
+async fn dispatcher(
+    pool: sqlx::SqlitePool,
+    clock: Timekeeper,
+    sinks: Vec<Box<dyn EventSink>>,
+    config: &'static EventsConfig,
+) {
+    let mut pruned_at = time::Instant::now();
+    loop {
+        for sink in &sinks {
+            loop {
+                let mut db = match pool.acquire().await {
+                    Ok(db) => db,
+                    Err(e) => {
+                        tracing::error!("events dispatcher failed to acquire a connection: {}", e);
+                        break;
+                    }
+                };
+                match events_dispatch(&mut db, sink.as_ref(), config.batch, clock.now()).await {
+                    Ok(delivered) if delivered as i64 == config.batch => continue,
+                    Ok(_) => break,
+                    Err(e) => {
+                        tracing::warn!(consumer = sink.name(), "event delivery failed: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        if pruned_at.elapsed() > Duration::from_secs(3600) {
+            pruned_at = time::Instant::now();
+            let pruned = match pool.acquire().await {
+                Ok(mut db) => events_prune(&mut db, config.retention, clock.now()).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = pruned {
+                tracing::warn!("failed to prune events: {}", e);
+            }
+        }
+
+        let _ = time::timeout(config.poll_interval, events_notify().notified()).await;
+    }
+}
+
+/// The sink that reaches live subscribers: those of every instance through Redis when `REDIS_URL`
+/// is set (with the `redis` feature), otherwise those of this process.
+fn live_sink() -> Box<dyn EventSink> {
+    #[cfg(feature = "redis")]
+    if let Some(bus) = crate::bus::RedisBus::from_env() {
+        rocket::tokio::spawn(bus.clone().forward());
+        return Box::new(bus);
+    }
+    Box::new(BroadcastSink)
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_liftoff("Events dispatcher", |rocket| {
+        Box::pin(async move {
+            let (Some(db), Some(clock)) = (Db::fetch(rocket), rocket.state::<Timekeeper>()) else {
+                return;
+            };
+            let config = events_config();
+            let mut sinks: Vec<Box<dyn EventSink>> = vec![live_sink()];
+            if let Some(url) = &config.webhook_url {
+                sinks.push(Box::new(WebhookSink {
+                    url: url.clone(),
+                    secret: config.webhook_secret.clone(),
+                }));
+            }
+            rocket::tokio::spawn(dispatcher((**db).clone(), clock.clone(), sinks, config));
+        })
+    })
+}
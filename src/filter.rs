@@ -0,0 +1,554 @@
+use chrono::{DateTime, NaiveDate};
+use rocket::serde::json;
+
+use crate::db::sqlx::{QueryBuilder, Sqlite};
+use crate::util::*;
+
+/// Maximum length of a filter expression.
+const FILTER_MAX_LEN: usize = 1024;
+/// Maximum number of comparisons in a filter expression, which bounds the generated SQL.
+const FILTER_MAX_TERMS: usize = 20;
+/// Maximum nesting of parentheses and `not`, which bounds the parser's recursion.
+const FILTER_MAX_DEPTH: usize = 8;
+
+/// A post field that can be filtered on, named as in the JSON of a post.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Id,
+    ParentId,
+    Variant,
+    CreatedAt,
+    UpdatedAt,
+    Position,
+    OrgId,
+    Favorited,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldType {
+    Text,
+    Timestamp,
+    Number,
+    Bool,
+}
+
+impl Field {
+    const ALL: [Self; 8] = [
+        Self::Id,
+        Self::ParentId,
+        Self::Variant,
+        Self::CreatedAt,
+        Self::UpdatedAt,
+        Self::Position,
+        Self::OrgId,
+        Self::Favorited,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::ParentId => "parentId",
+            Self::Variant => "variant",
+            Self::CreatedAt => "createdAt",
+            Self::UpdatedAt => "updatedAt",
+            Self::Position => "position",
+            Self::OrgId => "orgId",
+            Self::Favorited => "favorited",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|field| field.name() == name)
+    }
+
+    /// The SQL the field compiles to. Never contains user input.
+    fn column(self) -> &'static str {
+        match self {
+            Self::Id => "p.id",
+            Self::ParentId => "p.parent_id",
+            Self::Variant => "p.variant",
+            Self::CreatedAt => "p.created_at",
+            Self::UpdatedAt => "p.updated_at",
+            Self::Position => "p.position",
+            Self::OrgId => "p.org_id",
+            Self::Favorited => "(r.post_id IS NOT NULL)",
+        }
+    }
+
+    fn field_type(self) -> FieldType {
+        match self {
+            Self::Id | Self::ParentId | Self::Variant | Self::OrgId => FieldType::Text,
+            Self::CreatedAt | Self::UpdatedAt => FieldType::Timestamp,
+            Self::Position => FieldType::Number,
+            Self::Favorited => FieldType::Bool,
+        }
+    }
+
+    fn nullable(self) -> bool {
+        matches!(self, Self::ParentId | Self::Position | Self::OrgId)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl Op {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "eq" => Some(Self::Eq),
+            "ne" => Some(Self::Ne),
+            "gt" => Some(Self::Gt),
+            "ge" => Some(Self::Ge),
+            "lt" => Some(Self::Lt),
+            "le" => Some(Self::Le),
+            _ => None,
+        }
+    }
+
+    /// `IS` and `IS NOT` so that comparing with `null`, or a nullable field, behaves as expected.
+    fn sql(self) -> &'static str {
+        match self {
+            Self::Eq => " IS ",
+            Self::Ne => " IS NOT ",
+            Self::Gt => " > ",
+            Self::Ge => " >= ",
+            Self::Lt => " < ",
+            Self::Le => " <= ",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Null,
+    Text(String),
+    Timestamp(DateTime<Utc>),
+    Number(f64),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Compare { field: Field, op: Op, value: Value },
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+}
+
+/// A filter on posts, such as `variant eq "todo" and (updatedAt gt "2024-01-01" or favorited eq true)`.
+///
+/// Comparisons are `<field> <op> <value>` with the operators `eq`, `ne`, `gt`, `ge`, `lt` and `le`,
+/// combined with `and`, `or`, `not` and parentheses. Values are double-quoted strings, numbers,
+/// `true`, `false` and `null`; timestamps are RFC 3339 strings or plain dates. Fields and values
+/// are checked while parsing, and values are always bound as parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterExpr {
+    root: Node,
+}
+
+impl FilterExpr {
+    /// Parses an expression, describing the first problem found otherwise.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        if input.len() > FILTER_MAX_LEN {
+            return Err(format!("filter must be at most {} characters", FILTER_MAX_LEN));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            pos: 0,
+            terms: 0,
+            depth: 0,
+        };
+        let root = parser.or_expr()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(Self { root }),
+            Some(token) => Err(format!("Unexpected {} in filter", token.describe())),
+        }
+    }
+
+    /// Appends the expression as a parenthesized SQL condition over `posts p` and the favorite
+    /// reaction `r`.
+    pub fn push_sql(&self, builder: &mut QueryBuilder<'_, Sqlite>) {
+        node_push(&self.root, builder);
+    }
+}
+
+fn node_push(node: &Node, builder: &mut QueryBuilder<'_, Sqlite>) {
+    match node {
+        Node::Compare { field, op, value } => {
+            builder.push("(").push(field.column()).push(op.sql());
+            match value {
+                Value::Null => builder.push("NULL"),
+                Value::Text(text) => builder.push_bind(text.clone()),
+                Value::Timestamp(at) => builder.push_bind(*at),
+                Value::Number(number) => builder.push_bind(*number),
+                Value::Bool(flag) => builder.push_bind(*flag),
+            };
+            builder.push(")");
+        }
+        Node::Not(inner) => {
+            builder.push("(NOT ");
+            node_push(inner, builder);
+            builder.push(")");
+        }
+        Node::And(left, right) => binary_push(left, " AND ", right, builder),
+        Node::Or(left, right) => binary_push(left, " OR ", right, builder),
+    }
+}
+
+fn binary_push(left: &Node, joiner: &str, right: &Node, builder: &mut QueryBuilder<'_, Sqlite>) {
+    builder.push("(");
+    node_push(left, builder);
+    builder.push(joiner);
+    node_push(right, builder);
+    builder.push(")");
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Number(f64),
+    Open,
+    Close,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Self::Word(word) => format!("'{}'", word),
+            Self::Text(text) => format!("\"{}\"", text),
+            Self::Number(number) => number.to_string(),
+            Self::Open => "'('".into(),
+            Self::Close => "')'".into(),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' => {
+                chars.next();
+                tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            }
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped @ ('"' | '\\'))) => text.push(escaped),
+                            _ => return Err("Only \\\" and \\\\ can be escaped in filter strings".into()),
+                        },
+                        Some((_, c)) => text.push(c),
+                        None => return Err("Unterminated string in filter".into()),
+                    }
+                }
+                tokens.push(Token::Text(text));
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut end = start;
+                while let Some(&(i, c)) = chars.peek() {
+                    if !(c.is_ascii_digit() || c == '.' || (c == '-' && i == start)) {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                let number = input[start..end]
+                    .parse::<f64>()
+                    .map_err(|_| format!("Invalid number '{}' in filter", &input[start..end]))?;
+                tokens.push(Token::Number(number));
+            }
+            c if c.is_ascii_alphabetic() => {
+                let mut end = start;
+                while let Some(&(i, c)) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                tokens.push(Token::Word(input[start..end].to_owned()));
+            }
+            c => return Err(format!("Unexpected '{}' in filter", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over `or > and > not > comparison`, from loosest to tightest binding.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    terms: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn keyword_eat(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.tokens.get(self.pos), Some(Token::Word(word)) if word == keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn nest(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        match self.depth > FILTER_MAX_DEPTH {
+            true => Err(format!("filter can be nested at most {} levels deep", FILTER_MAX_DEPTH)),
+            false => Ok(()),
+        }
+    }
+
+    fn or_expr(&mut self) -> Result<Node, String> {
+        let mut node = self.and_expr()?;
+        while self.keyword_eat("or") {
+            node = Node::Or(Box::new(node), Box::new(self.and_expr()?));
+        }
+        Ok(node)
+    }
+
+    fn and_expr(&mut self) -> Result<Node, String> {
+        let mut node = self.unary()?;
+        while self.keyword_eat("and") {
+            node = Node::And(Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.keyword_eat("not") {
+            self.nest()?;
+            let node = Node::Not(Box::new(self.unary()?));
+            self.depth -= 1;
+            return Ok(node);
+        }
+        if self.tokens.get(self.pos) == Some(&Token::Open) {
+            self.pos += 1;
+            self.nest()?;
+            let node = self.or_expr()?;
+            if self.next() != Some(Token::Close) {
+                return Err("Missing ')' in filter".into());
+            }
+            self.depth -= 1;
+            return Ok(node);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Node, String> {
+        self.terms += 1;
+        if self.terms > FILTER_MAX_TERMS {
+            return Err(format!("filter can have at most {} comparisons", FILTER_MAX_TERMS));
+        }
+
+        let field = match self.next() {
+            Some(Token::Word(name)) => Field::parse(&name).ok_or_else(|| format!("Unknown filter field '{}'", name))?,
+            Some(token) => return Err(format!("Expected a field, found {}", token.describe())),
+            None => return Err("Expected a field at the end of the filter".into()),
+        };
+        let op = match self.next() {
+            Some(Token::Word(name)) => Op::parse(&name).ok_or_else(|| format!("Unknown filter operator '{}'", name))?,
+            Some(token) => return Err(format!("Expected an operator, found {}", token.describe())),
+            None => return Err("Expected an operator at the end of the filter".into()),
+        };
+        let token = self
+            .next()
+            .ok_or_else(|| "Expected a value at the end of the filter".to_owned())?;
+        let value = value_parse(field, op, token)?;
+        Ok(Node::Compare { field, op, value })
+    }
+}
+
+/// Checks the value against the field's type, which also keeps `gt` and friends off booleans and
+/// `null`.
+fn value_parse(field: Field, op: Op, token: Token) -> Result<Value, String> {
+    let mismatch = |expected: &str| format!("Filter field '{}' expects {}", field.name(), expected);
+    let equality = matches!(op, Op::Eq | Op::Ne);
+    match (field.field_type(), token) {
+        (_, Token::Word(word)) if word == "null" => match field.nullable() && equality {
+            true => Ok(Value::Null),
+            false => Err(mismatch("a non-null value, and null only with eq or ne")),
+        },
+        (FieldType::Text, Token::Text(text)) => Ok(Value::Text(text)),
+        (FieldType::Timestamp, Token::Text(text)) => timestamp_parse(&text)
+            .map(Value::Timestamp)
+            .ok_or_else(|| mismatch("an RFC 3339 timestamp or a YYYY-MM-DD date")),
+        (FieldType::Number, Token::Number(number)) => Ok(Value::Number(number)),
+        (FieldType::Bool, Token::Word(word)) if equality && (word == "true" || word == "false") => {
+            Ok(Value::Bool(word == "true"))
+        }
+        (FieldType::Text | FieldType::Timestamp, _) => Err(mismatch("a string")),
+        (FieldType::Number, _) => Err(mismatch("a number")),
+        (FieldType::Bool, _) => Err(mismatch("true or false, with eq or ne")),
+    }
+}
+
+fn timestamp_parse(text: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(text)
+        .map(|at| at.to_utc())
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .map(|at| at.and_utc())
+        })
+}
+
+/// Maximum number of `?json_path=` filters on one list.
+pub const JSON_PATHS_MAX: usize = 8;
+/// Maximum number of keys and indexes in a JSON path.
+const JSON_PATH_MAX_SEGMENTS: usize = 16;
+
+/// A filter on the structured content of posts, such as `$.done == false` or
+/// `$.tags[0] == "home"`, matched against the `content_json` column that holds the content of posts
+/// whose content is a JSON object or array.
+///
+/// The path is `$` followed by `.key` and `[index]` segments; the operators are `==`, `!=`, `<`,
+/// `<=`, `>` and `>=`; the value is a JSON string, number, `true`, `false` or `null`. A comparison
+/// only matches a value of the same JSON type, so `$.done == false` skips posts whose `done` is
+/// `0` or missing, and `!=` matches every structured post that `==` does not. Ordering operators
+/// take numbers and strings only.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPathFilter {
+    path: String,
+    op: Op,
+    value: Value,
+}
+
+impl JsonPathFilter {
+    /// Parses a filter, describing the first problem found otherwise.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        if input.len() > FILTER_MAX_LEN {
+            return Err(format!("json_path must be at most {} characters", FILTER_MAX_LEN));
+        }
+        let input = input.trim();
+        let rest = input
+            .strip_prefix('$')
+            .ok_or("json_path must start with $, e.g. $.done == false")?;
+
+        let mut path = String::from("$");
+        let mut segments = 0;
+        let mut chars = rest.char_indices().peekable();
+        let rest = loop {
+            match chars.peek().copied() {
+                Some((_, '.')) => {
+                    chars.next();
+                    let mut key = String::new();
+                    while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_') {
+                        key.push(c);
+                    }
+                    if key.is_empty() {
+                        return Err("json_path keys must be letters, digits and _".into());
+                    }
+                    path.push('.');
+                    path.push_str(&key);
+                }
+                Some((_, '[')) => {
+                    chars.next();
+                    let mut index = String::new();
+                    while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                        index.push(c);
+                    }
+                    if index.is_empty() || chars.next().map(|(_, c)| c) != Some(']') {
+                        return Err("json_path indexes must be [<number>]".into());
+                    }
+                    path.push_str(&format!("[{}]", index));
+                }
+                Some((i, _)) => break &rest[i..],
+                None => break "",
+            }
+            segments += 1;
+            if segments > JSON_PATH_MAX_SEGMENTS {
+                return Err(format!(
+                    "json_path can have at most {} segments",
+                    JSON_PATH_MAX_SEGMENTS
+                ));
+            }
+        };
+
+        let rest = rest.trim_start();
+        let (op, rest) = [
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+        ]
+        .into_iter()
+        .find_map(|(symbol, op)| rest.strip_prefix(symbol).map(|rest| (op, rest)))
+        .ok_or("json_path needs an operator: ==, !=, <, <=, > or >=")?;
+
+        let value = match json::from_str::<json::Value>(rest.trim()) {
+            Ok(json::Value::Null) => Value::Null,
+            Ok(json::Value::Bool(flag)) => Value::Bool(flag),
+            Ok(json::Value::Number(number)) => {
+                Value::Number(number.as_f64().ok_or("json_path number is out of range")?)
+            }
+            Ok(json::Value::String(text)) => Value::Text(text),
+            _ => return Err("json_path value must be a JSON string, number, true, false or null".into()),
+        };
+        if matches!(value, Value::Null | Value::Bool(_)) && !matches!(op, Op::Eq | Op::Ne) {
+            return Err("json_path compares true, false and null with == or != only".into());
+        }
+        Ok(Self { path, op, value })
+    }
+
+    /// Appends the filter as a parenthesized SQL condition over `posts p`.
+    pub fn push_sql(&self, builder: &mut QueryBuilder<'_, Sqlite>) {
+        match self.op {
+            Op::Ne => {
+                builder.push("(p.content_json IS NOT NULL AND NOT COALESCE(");
+                self.match_push(Op::Eq, builder);
+                builder.push(", 0))");
+            }
+            op => self.match_push(op, builder),
+        }
+    }
+
+    fn match_push(&self, op: Op, builder: &mut QueryBuilder<'_, Sqlite>) {
+        builder.push("(json_type(p.content_json, ").push_bind(self.path.clone());
+        match &self.value {
+            Value::Null => builder.push(") = 'null'"),
+            Value::Bool(true) => builder.push(") = 'true'"),
+            Value::Bool(false) => builder.push(") = 'false'"),
+            Value::Number(number) => builder
+                .push(") IN ('integer', 'real') AND json_extract(p.content_json, ")
+                .push_bind(self.path.clone())
+                .push(")")
+                .push(op.sql())
+                .push_bind(*number),
+            Value::Text(text) => builder
+                .push(") = 'text' AND json_extract(p.content_json, ")
+                .push_bind(self.path.clone())
+                .push(")")
+                .push(op.sql())
+                .push_bind(text.clone()),
+            Value::Timestamp(_) => unreachable!("json_path values are never timestamps"),
+        };
+        builder.push(")");
+    }
+}
@@ -0,0 +1,296 @@
+use rocket::Request;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest};
+use rocket::serde::{Serialize, json};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+
+use crate::api::api_mount;
+use crate::db::*;
+use crate::error::ApiError;
+use crate::util::*;
+
+/// A runtime switch for an experimental feature.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct FeatureFlag {
+    pub name: String,
+    /// On for everyone.
+    pub enabled: bool,
+    /// Otherwise on for this share of the users, always the same ones.
+    pub rollout_percent: i64,
+    /// Per-user overrides of the above.
+    pub users: BTreeMap<i64, bool>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Returns the bucket, in `0..100`, a user falls in for a flag's rollout. Hashing the name in means
+/// each flag is tried on a different share of the users.
+pub fn flag_bucket(name: &str, user_id: i64) -> i64 {
+    let hash = Sha256::digest(format!("{}:{}", name, user_id));
+    let head = u64::from_be_bytes(hash[..8].try_into().expect("8 bytes"));
+    (head % 100) as i64
+}
+
+/// Every flag of the deployment, as last read from the database.
+#[derive(Debug, Clone, Default)]
+pub struct FlagSet(pub HashMap<String, FeatureFlag>);
+
+impl FlagSet {
+    /// Whether the flag is on for the user, or for anonymous callers when `user_id` is `None`.
+    /// Unknown flags are off.
+    pub fn enabled(&self, name: &str, user_id: Option<i64>) -> bool {
+        let Some(flag) = self.0.get(name) else {
+            return false;
+        };
+        if let Some(enabled) = user_id.and_then(|user_id| flag.users.get(&user_id)) {
+            return *enabled;
+        }
+        flag.enabled || user_id.is_some_and(|user_id| flag_bucket(name, user_id) < flag.rollout_percent)
+    }
+
+    /// Returns the names of the flags on for the user.
+    pub fn enabled_names(&self, user_id: Option<i64>) -> Vec<&str> {
+        let mut names = self
+            .0
+            .keys()
+            .filter(|name| self.enabled(name, user_id))
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// Reads every flag with its per-user overrides.
+pub async fn flags_load(db: &mut sqlx::SqliteConnection) -> Result<FlagSet, sqlx::Error> {
+    let flags = sqlx::query!(
+        "SELECT name, enabled AS \"enabled: bool\", rollout_percent, \
+        updated_at AS \"updated_at: DateTime<Utc>\" FROM feature_flags"
+    )
+    .fetch_all(&mut *db)
+    .instrument(query_span("feature_flags.list"))
+    .await?;
+    let overrides = sqlx::query!("SELECT name, user_id, enabled AS \"enabled: bool\" FROM feature_flag_users")
+        .fetch_all(&mut *db)
+        .instrument(query_span("feature_flag_users.list"))
+        .await?;
+
+    let mut set = flags
+        .into_iter()
+        .map(|flag| {
+            let name = flag.name.clone();
+            let flag = FeatureFlag {
+                name: flag.name,
+                enabled: flag.enabled,
+                rollout_percent: flag.rollout_percent,
+                users: BTreeMap::new(),
+                updated_at: flag.updated_at,
+            };
+            (name, flag)
+        })
+        .collect::<HashMap<_, _>>();
+    for row in overrides {
+        if let Some(flag) = set.get_mut(&row.name) {
+            flag.users.insert(row.user_id, row.enabled);
+        }
+    }
+    Ok(FlagSet(set))
+}
+
+/// Creates or updates a flag's deployment-wide setting at `now`.
+pub async fn flag_set(
+    db: &mut sqlx::SqliteConnection,
+    name: &str,
+    enabled: bool,
+    rollout_percent: i64,
+    now: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO feature_flags (name, enabled, rollout_percent, updated_at) VALUES (?, ?, ?, ?) \
+        ON CONFLICT (name) DO UPDATE SET enabled = excluded.enabled, rollout_percent = excluded.rollout_percent, \
+        updated_at = excluded.updated_at",
+        name,
+        enabled,
+        rollout_percent,
+        now
+    )
+    .execute(db)
+    .instrument(query_span("feature_flags.upsert"))
+    .await?;
+    Ok(())
+}
+
+/// Deletes a flag with its overrides. Returns `false` when there is no such flag.
+pub async fn flag_delete(db: &mut sqlx::SqliteConnection, name: &str) -> Result<bool, sqlx::Error> {
+    let deleted = sqlx::query!("DELETE FROM feature_flags WHERE name = ?", name)
+        .execute(db)
+        .instrument(query_span("feature_flags.delete"))
+        .await?
+        .rows_affected();
+    Ok(deleted > 0)
+}
+
+/// Overrides a flag for one user, or drops the override when `enabled` is `None`. Returns `false`
+/// when there is no such flag or user.
+pub async fn flag_user_set(
+    db: &mut sqlx::SqliteConnection,
+    name: &str,
+    user_id: i64,
+    enabled: Option<bool>,
+) -> Result<bool, sqlx::Error> {
+    let changed = match enabled {
+        Some(enabled) => sqlx::query!(
+            "INSERT INTO feature_flag_users (name, user_id, enabled) \
+            SELECT f.name, u.id, ? FROM feature_flags f, users u WHERE f.name = ? AND u.id = ? \
+            ON CONFLICT (name, user_id) DO UPDATE SET enabled = excluded.enabled",
+            enabled,
+            name,
+            user_id
+        )
+        .execute(db)
+        .instrument(query_span("feature_flag_users.upsert"))
+        .await?
+        .rows_affected(),
+        None => sqlx::query!(
+            "DELETE FROM feature_flag_users WHERE name = ? AND user_id = ?",
+            name,
+            user_id
+        )
+        .execute(db)
+        .instrument(query_span("feature_flag_users.delete"))
+        .await?
+        .rows_affected(),
+    };
+    Ok(changed > 0)
+}
+
+/// Whether a flag name is lowercase letters, digits, `_`, `-` and `.`, at most 64 of them.
+pub fn flag_name_is_valid(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || matches!(b, b'_' | b'-' | b'.'))
+}
+
+/// `FEATURE_FLAGS_REFRESH_SECS`, see `FlagCache`.
+pub fn flags_refresh() -> Duration {
+    Duration::from_secs(env_parse_or("FEATURE_FLAGS_REFRESH_SECS", 10))
+}
+
+/// The flags of a `FlagCache` and when they were read.
+type FlagSnapshot = Option<(Instant, Arc<FlagSet>)>;
+
+/// The flags, re-read from the database at most every `FEATURE_FLAGS_REFRESH_SECS` (10). Writes
+/// through the admin API take effect at once on this instance, on the others within that delay.
+/// Clones share the snapshot.
+#[derive(Clone)]
+pub struct FlagCache {
+    ttl: Arc<Mutex<Duration>>,
+    snapshot: Arc<Mutex<FlagSnapshot>>,
+}
+
+impl FlagCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl: Arc::new(Mutex::new(ttl)),
+            snapshot: Arc::default(),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        *self.ttl.lock().unwrap()
+    }
+
+    /// Changes how long snapshots are kept, e.g. on a config reload, and forgets the current one.
+    pub fn reconfigure(&self, ttl: Duration) {
+        *self.ttl.lock().unwrap() = ttl;
+        self.invalidate();
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(flags_refresh())
+    }
+
+    /// Returns the current flags, reading them again when the snapshot is stale.
+    pub async fn get(&self, db: &sqlx::SqlitePool) -> Result<Arc<FlagSet>, sqlx::Error> {
+        let cached = self.snapshot.lock().unwrap().clone();
+        let ttl = self.ttl();
+        if let Some((_, set)) = cached.filter(|(loaded_at, _)| loaded_at.elapsed() < ttl) {
+            return Ok(set);
+        }
+        let set = Arc::new(flags_load(&mut *db.acquire().await?).await?);
+        *self.snapshot.lock().unwrap() = Some((Instant::now(), set.clone()));
+        Ok(set)
+    }
+
+    /// Forgets the snapshot, so that the next request reads the flags again.
+    pub fn invalidate(&self) {
+        *self.snapshot.lock().unwrap() = None;
+    }
+}
+
+/// Request guard giving the flags as they apply to the caller, signed in or not.
+///
+/// ```ignore
+/// #[get("/experimental")]
+/// fn experimental(flags: FeatureFlags) -> Result<..., ApiError> {
+///     flags.require("sync_v2")?;
+/// ```
+pub struct FeatureFlags {
+    pub set: Arc<FlagSet>,
+    pub user_id: Option<i64>,
+}
+
+impl FeatureFlags {
+    pub fn enabled(&self, name: &str) -> bool {
+        self.set.enabled(name, self.user_id)
+    }
+
+    /// Fails with a 404 when the flag is off, as if the endpoint it gates did not exist.
+    pub fn require(&self, name: &str) -> Result<(), ApiError> {
+        match self.enabled(name) {
+            true => Ok(()),
+            false => Err(ApiError::from_status(Status::NotFound)),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for FeatureFlags {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let (Some(cache), Some(db)) = (request.rocket().state::<FlagCache>(), Db::fetch(request.rocket())) else {
+            return request::Outcome::Error((Status::ServiceUnavailable, "feature flags unavailable"));
+        };
+        let set = match cache.get(db).await {
+            Ok(set) => set,
+            Err(e) => {
+                tracing::error!("flags:load-error: {}", e);
+                return request::Outcome::Error((Status::ServiceUnavailable, "database unavailable"));
+            }
+        };
+        let user_id = request.guard::<UserCtx>().await.succeeded().map(|user| user.id);
+        request::Outcome::Success(FeatureFlags { set, user_id })
+    }
+}
+
+/// Returns the names of the flags on for the caller, so that clients can show the matching UI.
+#[get("/")]
+fn index(flags: FeatureFlags) -> json::Value {
+    json::json!({ "flags": flags.set.enabled_names(flags.user_id) })
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Feature flags stage", |rocket| async {
+        let rocket = rocket.manage(FlagCache::from_env());
+        api_mount(rocket, "/flags", routes![index])
+    })
+}
@@ -0,0 +1,264 @@
+use base64::Engine;
+use cookie::{CookieJar, Key};
+use rocket::fairing::AdHoc;
+use rocket::futures::{Stream, StreamExt, stream};
+use rocket::tokio::sync::broadcast::error::RecvError;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::authz::session_active;
+use crate::clock::clock_config;
+use crate::db::*;
+use crate::handlers::posts::*;
+use crate::maintenance::Maintenance;
+use crate::scope::Scope;
+use crate::util::*;
+
+pub mod proto {
+    tonic::include_proto!("posts_sync");
+}
+
+use proto::posts_sync_server::{PostsSync, PostsSyncServer};
+
+/// Posts pushed are upserted in batches of this size.
+const PUSH_BATCH: usize = 500;
+
+/// Builds the key Rocket uses for private cookies from `ROCKET_SECRET_KEY`, so that gRPC calls can
+/// authenticate with the same cookies as the REST API.
+pub(crate) fn cookie_key() -> Key {
+    let secret = &env_get().rocket_secret_key;
+    let raw = base64::engine::general_purpose::STANDARD
+        .decode(secret)
+        .expect("ROCKET_SECRET_KEY must be base64");
+    match raw.len() {
+        64 => Key::from(&raw),
+        _ => Key::derive_from(&raw),
+    }
+}
+
+/// Resolves the user from the private `user_id` and session cookies in the `cookie` metadata entry,
+/// with the checks of `UserCtx`: the session is active and the user neither deleted nor disabled.
+pub(crate) async fn user_id_get<T>(request: &Request<T>, key: &Key, db: &sqlx::SqlitePool) -> Result<i64, Status> {
+    let unauthenticated = || Status::unauthenticated("Unauthorized");
+    let header = request
+        .metadata()
+        .get("cookie")
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(unauthenticated)?;
+
+    let mut jar = CookieJar::new();
+    for cookie in cookie::Cookie::split_parse(header.to_owned()).flatten() {
+        jar.add_original(cookie);
+    }
+    let jar = jar.private(key);
+    let user_id: i64 = jar
+        .get("user_id")
+        .and_then(|cookie| cookie.value().parse().ok())
+        .ok_or_else(unauthenticated)?;
+    let session_id = jar.get(SESSION_COOKIE).ok_or_else(unauthenticated)?;
+
+    match session_active(db, session_id.value(), clock_config().now()).await {
+        Ok(Some(session)) if session.user_id == user_id => {}
+        Ok(_) => return Err(unauthenticated()),
+        Err(e) => return Err(internal(e)),
+    }
+    match user_is_active(db, user_id).await {
+        Ok(true) => Ok(user_id),
+        Ok(false) => Err(unauthenticated()),
+        Err(e) => Err(internal(e)),
+    }
+}
+
+fn post_to_proto(post: Post) -> proto::Post {
+    let post = post.content_decrypt();
+    proto::Post {
+        id: post.id,
+        parent_id: post.parent_id,
+        content: post.content,
+        created_at_ms: post.created_at.timestamp_millis(),
+        updated_at_ms: post.updated_at.timestamp_millis(),
+        variant: post.variant,
+        position: post.position,
+        org_id: post.org_id,
+        remind_at_ms: post.remind_at.map(|remind_at| remind_at.timestamp_millis()),
+    }
+}
+
+fn post_from_proto(post: proto::Post) -> Result<UpsertPostPayload, Status> {
+    let timestamp =
+        |ms| DateTime::from_timestamp_millis(ms).ok_or_else(|| Status::invalid_argument("timestamp is out of range"));
+    Ok(UpsertPostPayload {
+        created_at: timestamp(post.created_at_ms)?,
+        updated_at: timestamp(post.updated_at_ms)?,
+        version: None,
+        id: post.id,
+        parent_id: post.parent_id,
+        content: post.content,
+        variant: post.variant,
+        position: post.position,
+        org_id: post.org_id,
+        remind_at: post.remind_at_ms.map(timestamp).transpose()?,
+    })
+}
+
+fn upserted(post: Post) -> proto::PostChange {
+    proto::PostChange {
+        kind: proto::post_change::Kind::Upserted as i32,
+        id: post.id.clone(),
+        post: Some(post_to_proto(post)),
+    }
+}
+
+fn internal(e: sqlx::Error) -> Status {
+    tracing::error!("grpc:db-error: {}", e);
+    Status::internal("Internal Server Error")
+}
+
+fn write_error(e: PostWriteError) -> Status {
+    match e {
+        PostWriteError::Invalid(message) => Status::invalid_argument(message),
+        e @ PostWriteError::TooLarge { .. } => Status::invalid_argument(e.to_string()),
+        e @ PostWriteError::Taken(_) => Status::already_exists(e.to_string()),
+        PostWriteError::Db(e) => internal(e),
+    }
+}
+
+pub struct PostsSyncService {
+    pool: sqlx::SqlitePool,
+    key: Key,
+    maintenance: Option<Maintenance>,
+}
+
+type PullStream = Pin<Box<dyn Stream<Item = Result<proto::PostChange, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl PostsSync for PostsSyncService {
+    type PullStream = PullStream;
+
+    async fn pull(&self, request: Request<proto::PullRequest>) -> Result<Response<PullStream>, Status> {
+        let user_id = user_id_get(&request, &self.key, &self.pool).await?;
+        let since = request.into_inner().since_ms.unwrap_or(0);
+        let since = DateTime::from_timestamp_millis(since)
+            .ok_or_else(|| Status::invalid_argument("since_ms is out of range"))?;
+
+        // Subscribe before the catch-up query so that no change falls in between
+        let receiver = post_changes().subscribe();
+        let mut db = self.pool.acquire().await.map_err(internal)?;
+        let posts = Scope::user(user_id)
+            .posts_updated_since(&mut *db, since)
+            .await
+            .map_err(internal)?;
+        drop(db);
+
+        let pool = self.pool.clone();
+        let live = stream::unfold(receiver, move |mut receiver| {
+            let pool = pool.clone();
+            async move {
+                loop {
+                    let change = match receiver.recv().await {
+                        Ok(change) if change.user_id == user_id => change,
+                        Ok(_) => continue,
+                        Err(RecvError::Lagged(_)) => {
+                            let status = Status::data_loss("Fell behind the change stream, pull again");
+                            return Some((Err(status), receiver));
+                        }
+                        Err(RecvError::Closed) => return None,
+                    };
+                    let item = match change.kind {
+                        PostChangeKind::Upserted => {
+                            let id = change.id.unwrap_or_default();
+                            let post = match pool.acquire().await {
+                                Ok(mut db) => Scope::user(user_id).post_read(&mut *db, &id).await,
+                                Err(e) => Err(e),
+                            };
+                            match post {
+                                Ok(Some(post)) => Ok(upserted(post)),
+                                // Deleted again before we got to it
+                                Ok(None) => continue,
+                                Err(e) => Err(internal(e)),
+                            }
+                        }
+                        PostChangeKind::Deleted => Ok(proto::PostChange {
+                            kind: proto::post_change::Kind::Deleted as i32,
+                            id: change.id.unwrap_or_default(),
+                            post: None,
+                        }),
+                        PostChangeKind::Cleared => Ok(proto::PostChange {
+                            kind: proto::post_change::Kind::Cleared as i32,
+                            id: String::new(),
+                            post: None,
+                        }),
+                        PostChangeKind::Reminded => Ok(proto::PostChange {
+                            kind: proto::post_change::Kind::Reminded as i32,
+                            id: change.id.unwrap_or_default(),
+                            post: None,
+                        }),
+                    };
+                    return Some((item, receiver));
+                }
+            }
+        });
+
+        let stream = stream::iter(posts.into_iter().map(|post| Ok(upserted(post)))).chain(live);
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn push(&self, request: Request<Streaming<proto::Post>>) -> Result<Response<proto::PushReply>, Status> {
+        let user_id = user_id_get(&request, &self.key, &self.pool).await?;
+        if let Some(error) = self.maintenance.as_ref().and_then(Maintenance::write_refusal) {
+            return Err(Status::unavailable(error.message));
+        }
+        let mut incoming = request.into_inner();
+        let mut db = self.pool.acquire().await.map_err(internal)?;
+
+        let mut received = 0;
+        let mut batch = Vec::with_capacity(PUSH_BATCH);
+        while let Some(post) = incoming.message().await? {
+            batch.push(post_from_proto(post)?);
+            if batch.len() == PUSH_BATCH {
+                posts_upsert_many(&mut *db, user_id, &batch, clock_config().now())
+                    .await
+                    .map_err(write_error)?;
+                received += batch.len() as u64;
+                batch.clear();
+            }
+        }
+        posts_upsert_many(&mut *db, user_id, &batch, clock_config().now())
+            .await
+            .map_err(write_error)?;
+        received += batch.len() as u64;
+
+        Ok(Response::new(proto::PushReply { received }))
+    }
+}
+
+/// Serves `PostsSync` on `GRPC_ADDR` (default `127.0.0.1:50051`) once Rocket has launched, sharing
+/// its database pool.
+pub fn stage() -> AdHoc {
+    AdHoc::on_liftoff("gRPC stage", |rocket| {
+        Box::pin(async move {
+            let Some(db) = Db::fetch(rocket) else {
+                tracing::error!("grpc:no-database");
+                return;
+            };
+            let addr: SocketAddr = env_parse_or("GRPC_ADDR", SocketAddr::from(([127, 0, 0, 1], 50051)));
+            let service = PostsSyncService {
+                pool: (**db).clone(),
+                key: cookie_key(),
+                maintenance: rocket.state::<Maintenance>().cloned(),
+            };
+
+            rocket::tokio::spawn(async move {
+                tracing::info!(%addr, "grpc:listening");
+                let result = tonic::transport::Server::builder()
+                    .add_service(PostsSyncServer::new(service))
+                    .serve(addr)
+                    .await;
+                if let Err(e) = result {
+                    tracing::error!("grpc:server-error: {}", e);
+                }
+            });
+        })
+    })
+}
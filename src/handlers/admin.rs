@@ -0,0 +1,723 @@
+use rocket::fairing::AdHoc;
+use rocket::http::{CookieJar, Status};
+use rocket::request::{self, FromRequest};
+use rocket::serde::{Deserialize, Serialize, json};
+use rocket::{Request, State};
+use tracing::Instrument;
+
+use crate::api::api_mount;
+use crate::authz::scopes_parse;
+use crate::cache::response_cache;
+use crate::clock::Timekeeper;
+use crate::csrf::{csrf_cookie, csrf_token_gen, tokens_match};
+use crate::db::*;
+use crate::email::{email_unsuppress, emails_failed};
+use crate::error::ApiError;
+use crate::flags::*;
+use crate::impersonation::*;
+use crate::oauth::{client_create, client_revoke, clients_list, redirect_uri_is_valid};
+use crate::reload::Reloadable;
+use crate::timeout::timeout_routes;
+use crate::util::*;
+
+/// Bearer token granting access to the admin API, from `ADMIN_TOKEN`. Without it the admin routes
+/// answer 404.
+pub struct AdminToken(pub Option<String>);
+
+/// Request guard for the admin API: requires `Authorization: Bearer <ADMIN_TOKEN>`.
+pub struct AdminCtx;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminCtx {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let Some(AdminToken(Some(token))) = request.rocket().state::<AdminToken>() else {
+            return request::Outcome::Forward(Status::NotFound);
+        };
+        let bearer = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match bearer {
+            Some(bearer) if tokens_match(bearer, token) => request::Outcome::Success(AdminCtx),
+            _ => request::Outcome::Error((Status::Unauthorized, "invalid admin token")),
+        }
+    }
+}
+
+/// Suspends a user at `now`: blocks sign-ins, drops any pending login code and cached responses
+/// and revokes all sessions. Returns the number of revoked sessions, or `None` when there is no
+/// such user.
+pub async fn user_suspend(
+    db: &mut sqlx::SqliteConnection,
+    user_id: i64,
+    now: DateTime<Utc>,
+) -> Result<Option<u64>, sqlx::Error> {
+    let mut tx = sqlx::Connection::begin(db).await?;
+
+    let found = sqlx::query!(
+        "UPDATE users SET disabled_at = COALESCE(disabled_at, ?), code_attempts = NULL, \
+        code_created_at = NULL, code_hash = NULL WHERE id = ?",
+        now,
+        user_id
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("users.suspend"))
+    .await?
+    .rows_affected();
+    if found == 0 {
+        return Ok(None);
+    }
+    let revoked = sqlx::query!(
+        "UPDATE sessions SET revoked_at = ? WHERE user_id = ? AND revoked_at IS NULL",
+        now,
+        user_id
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("sessions.revoke_all"))
+    .await?
+    .rows_affected();
+
+    tx.commit().await?;
+    response_cache().invalidate_user(user_id);
+    Ok(Some(revoked))
+}
+
+/// Lets a suspended user sign in again. Returns `false` when there is no such user.
+pub async fn user_reactivate(db: &mut sqlx::SqliteConnection, user_id: i64) -> Result<bool, sqlx::Error> {
+    let found = sqlx::query!("UPDATE users SET disabled_at = NULL WHERE id = ?", user_id)
+        .execute(db)
+        .instrument(query_span("users.reactivate"))
+        .await?
+        .rows_affected();
+    Ok(found > 0)
+}
+
+/// What `users_merge` moved to the target account.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct MergeCounts {
+    pub posts: u64,
+    pub sessions: u64,
+    pub preferences: u64,
+}
+
+/// Merges the `source` account into `target` in one transaction, e.g. when a user signed in with
+/// another email. Posts, comments, attachments, templates, shares, reactions, organization
+/// memberships (keeping the higher role), flag overrides, feeds and preferences move over, the
+/// target's own winning on conflict. Sessions move too but are revoked, as their cookies still
+/// name the source. The source is then tombstoned: disabled, with `merged_into` set. Returns `None`
+/// when the accounts are the same, or either is missing or already merged.
+pub async fn users_merge(
+    db: &mut sqlx::SqliteConnection,
+    source: i64,
+    target: i64,
+    now: DateTime<Utc>,
+) -> Result<Option<MergeCounts>, sqlx::Error> {
+    if source == target {
+        return Ok(None);
+    }
+    let mut tx = sqlx::Connection::begin(db).await?;
+
+    let found = sqlx::query_scalar!(
+        "SELECT COUNT(*) AS \"count!: i64\" FROM users WHERE id IN (?, ?) AND merged_into IS NULL",
+        source,
+        target
+    )
+    .fetch_one(&mut *tx)
+    .instrument(query_span("users.merge_check"))
+    .await?;
+    if found != 2 {
+        return Ok(None);
+    }
+
+    let posts = sqlx::query!("UPDATE posts SET user_id = ? WHERE user_id = ?", target, source)
+        .execute(&mut *tx)
+        .instrument(query_span("posts.merge"))
+        .await?
+        .rows_affected();
+    sqlx::query!("UPDATE comments SET user_id = ? WHERE user_id = ?", target, source)
+        .execute(&mut *tx)
+        .instrument(query_span("comments.merge"))
+        .await?;
+    sqlx::query!(
+        "UPDATE post_templates SET user_id = ? WHERE user_id = ?",
+        target,
+        source
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("post_templates.merge"))
+    .await?;
+    sqlx::query!("UPDATE attachments SET user_id = ? WHERE user_id = ?", target, source)
+        .execute(&mut *tx)
+        .instrument(query_span("attachments.merge"))
+        .await?;
+    sqlx::query!("UPDATE post_shares SET user_id = ? WHERE user_id = ?", target, source)
+        .execute(&mut *tx)
+        .instrument(query_span("post_shares.merge"))
+        .await?;
+    sqlx::query!(
+        "UPDATE organization_members AS t SET role = s.role FROM organization_members s \
+        WHERE t.user_id = ? AND s.user_id = ? AND s.org_id = t.org_id \
+        AND (CASE s.role WHEN 'owner' THEN 2 WHEN 'admin' THEN 1 ELSE 0 END) \
+        > (CASE t.role WHEN 'owner' THEN 2 WHEN 'admin' THEN 1 ELSE 0 END)",
+        target,
+        source
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("organization_members.merge_roles"))
+    .await?;
+    // Rows the target already has are left behind, and go with the source's below
+    sqlx::query!(
+        "UPDATE OR IGNORE organization_members SET user_id = ? WHERE user_id = ?",
+        target,
+        source
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("organization_members.merge"))
+    .await?;
+    sqlx::query!(
+        "UPDATE OR IGNORE post_reactions SET user_id = ? WHERE user_id = ?",
+        target,
+        source
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("post_reactions.merge"))
+    .await?;
+    sqlx::query!(
+        "UPDATE OR IGNORE feature_flag_users SET user_id = ? WHERE user_id = ?",
+        target,
+        source
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("feature_flag_users.merge"))
+    .await?;
+    sqlx::query!(
+        "UPDATE OR IGNORE calendar_feeds SET user_id = ? WHERE user_id = ?",
+        target,
+        source
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("calendar_feeds.merge"))
+    .await?;
+    sqlx::query!(
+        "UPDATE OR IGNORE share_feeds SET user_id = ? WHERE user_id = ?",
+        target,
+        source
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("share_feeds.merge"))
+    .await?;
+    let preferences = sqlx::query!(
+        "UPDATE OR IGNORE user_preferences SET user_id = ? WHERE user_id = ?",
+        target,
+        source
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("user_preferences.merge"))
+    .await?
+    .rows_affected();
+    sqlx::query!("DELETE FROM organization_members WHERE user_id = ?", source)
+        .execute(&mut *tx)
+        .instrument(query_span("organization_members.merge_leftovers"))
+        .await?;
+    sqlx::query!("DELETE FROM post_reactions WHERE user_id = ?", source)
+        .execute(&mut *tx)
+        .instrument(query_span("post_reactions.merge_leftovers"))
+        .await?;
+    sqlx::query!("DELETE FROM feature_flag_users WHERE user_id = ?", source)
+        .execute(&mut *tx)
+        .instrument(query_span("feature_flag_users.merge_leftovers"))
+        .await?;
+    sqlx::query!("DELETE FROM calendar_feeds WHERE user_id = ?", source)
+        .execute(&mut *tx)
+        .instrument(query_span("calendar_feeds.merge_leftovers"))
+        .await?;
+    sqlx::query!("DELETE FROM share_feeds WHERE user_id = ?", source)
+        .execute(&mut *tx)
+        .instrument(query_span("share_feeds.merge_leftovers"))
+        .await?;
+    sqlx::query!("DELETE FROM user_preferences WHERE user_id = ?", source)
+        .execute(&mut *tx)
+        .instrument(query_span("user_preferences.merge_leftovers"))
+        .await?;
+    let sessions = sqlx::query!(
+        "UPDATE sessions SET user_id = ?, revoked_at = COALESCE(revoked_at, ?) WHERE user_id = ?",
+        target,
+        now,
+        source
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("sessions.merge"))
+    .await?
+    .rows_affected();
+    sqlx::query!(
+        "UPDATE users SET merged_into = ?, disabled_at = COALESCE(disabled_at, ?), code_attempts = NULL, \
+        code_created_at = NULL, code_hash = NULL WHERE id = ?",
+        target,
+        now,
+        source
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("users.merge_tombstone"))
+    .await?;
+
+    tx.commit().await?;
+    response_cache().invalidate_user(source);
+    response_cache().invalidate_user(target);
+    Ok(Some(MergeCounts {
+        posts,
+        sessions,
+        preferences,
+    }))
+}
+
+/// Users per page of `GET /admin/users`.
+pub const USERS_PAGE_SIZE: i64 = 50;
+
+/// A user with aggregates of their data, for operators looking for heavy or dormant accounts.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct UserSummary {
+    pub id: i64,
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+    pub disabled_at: Option<DateTime<Utc>>,
+    pub post_count: i64,
+    /// When the user last wrote a post, `None` without posts.
+    pub last_activity_at: Option<DateTime<Utc>>,
+    /// Bytes of post contents, as stored, and of attachments.
+    pub storage_bytes: i64,
+}
+
+/// Lists the users whose email contains `query`, oldest first, in pages of `USERS_PAGE_SIZE`
+/// numbered from 1. Accounts merged into another are left out. Returns the page and the number of
+/// matching users.
+pub async fn users_list(
+    db: &mut sqlx::SqliteConnection,
+    query: Option<&str>,
+    page: i64,
+) -> Result<(Vec<UserSummary>, i64), sqlx::Error> {
+    let pattern = query.map(|query| {
+        let escaped = query.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+        format!("%{}%", escaped)
+    });
+    let offset = (page - 1).saturating_mul(USERS_PAGE_SIZE);
+    // Users are paged first, so that only their posts and attachments are aggregated
+    let users = sqlx::query_as!(
+        UserSummary,
+        "WITH page AS (SELECT id, email, created_at, disabled_at FROM users \
+        WHERE merged_into IS NULL AND (? IS NULL OR email LIKE ? ESCAPE '\\') ORDER BY id LIMIT ? OFFSET ?) \
+        SELECT u.id AS \"id!\", u.email AS \"email!\", u.created_at AS \"created_at!: DateTime<Utc>\", \
+        u.disabled_at AS \"disabled_at: DateTime<Utc>\", COUNT(p.id) AS \"post_count!: i64\", \
+        MAX(p.updated_at) AS \"last_activity_at: DateTime<Utc>\", \
+        COALESCE(SUM(LENGTH(p.content)), 0) + COALESCE(a.bytes, 0) AS \"storage_bytes!: i64\" FROM page u \
+        LEFT JOIN posts p ON p.user_id = u.id \
+        LEFT JOIN (SELECT a.user_id, SUM(b.size) AS bytes FROM attachments a JOIN blobs b ON b.key = a.blob_key \
+        WHERE a.user_id IN (SELECT id FROM page) GROUP BY a.user_id) a ON a.user_id = u.id \
+        GROUP BY u.id ORDER BY u.id",
+        pattern,
+        pattern,
+        USERS_PAGE_SIZE,
+        offset
+    )
+    .fetch_all(&mut *db)
+    .instrument(query_span("users.admin_list"))
+    .await?;
+    let total = sqlx::query_scalar!(
+        "SELECT COUNT(*) AS \"count!: i64\" FROM users \
+        WHERE merged_into IS NULL AND (? IS NULL OR email LIKE ? ESCAPE '\\')",
+        pattern,
+        pattern
+    )
+    .fetch_one(&mut *db)
+    .instrument(query_span("users.admin_count"))
+    .await?;
+    Ok((users, total))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    tracing::error!("admin:db-error: {}", e);
+    ApiError::internal()
+}
+
+/// Lists users with their post count, last activity and storage used. `q` searches emails.
+#[get("/users?<q>&<page>")]
+async fn users(
+    mut db: Connection<Db>,
+    _admin: AdminCtx,
+    q: Option<&str>,
+    page: Option<i64>,
+) -> Result<(Status, json::Value), ApiError> {
+    let page = page.unwrap_or(1);
+    if page < 1 {
+        return Err(ApiError::validation("page must be 1 or more"));
+    }
+    let query = q.map(str::trim).filter(|q| !q.is_empty());
+    let (users, total) = users_list(&mut db, query, page).await.map_err(db_error)?;
+    Ok((
+        Status::Ok,
+        json::json!({
+            "items": users,
+            "page": page,
+            "total": total,
+            "hasMore": page.saturating_mul(USERS_PAGE_SIZE) < total,
+        }),
+    ))
+}
+
+#[post("/users/<id>/suspend")]
+async fn suspend(
+    mut db: Connection<Db>,
+    _admin: AdminCtx,
+    clock: &State<Timekeeper>,
+    id: i64,
+) -> Result<(Status, json::Value), ApiError> {
+    let revoked = user_suspend(&mut db, id, clock.now())
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| ApiError::not_found("User not found"))?;
+    tracing::info!("admin:suspend:{}", id);
+    Ok((Status::Ok, json::json!({ "id": id, "revokedSessions": revoked })))
+}
+
+#[post("/users/<id>/reactivate")]
+async fn reactivate(mut db: Connection<Db>, _admin: AdminCtx, id: i64) -> Result<(Status, json::Value), ApiError> {
+    if !user_reactivate(&mut db, id).await.map_err(db_error)? {
+        return Err(ApiError::not_found("User not found"));
+    }
+    tracing::info!("admin:reactivate:{}", id);
+    Ok((Status::Ok, json::json!({ "id": id })))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct MergeRequestBody {
+    pub target_id: i64,
+}
+
+/// Merges the user into `targetId`, see `users_merge`.
+#[post("/users/<id>/merge", data = "<body>")]
+async fn merge(
+    mut db: Connection<Db>,
+    _admin: AdminCtx,
+    clock: &State<Timekeeper>,
+    id: i64,
+    body: json::Json<MergeRequestBody>,
+) -> Result<(Status, json::Value), ApiError> {
+    let counts = users_merge(&mut db, id, body.target_id, clock.now())
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| ApiError::validation("Users must be two distinct accounts that were not merged"))?;
+    tracing::info!("admin:merge:{}:{}", id, body.target_id);
+    Ok((
+        Status::Ok,
+        json::json!({ "sourceId": id, "targetId": body.target_id, "moved": counts }),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ImpersonateRequestBody {
+    /// Who is impersonating, shown to clients and kept for the audit.
+    pub staff: String,
+    /// Why, e.g. a ticket reference, kept for the audit.
+    pub reason: String,
+}
+
+/// Longest `staff` and `reason` of an impersonation.
+const IMPERSONATION_FIELD_MAX_LEN: usize = 200;
+
+/// Signs the caller in as the user for `IMPERSONATION_TTL_MINS`, so that support staff can reproduce
+/// an issue. Responses to the impersonation carry an `X-Impersonated-By` header for clients to show
+/// a banner, until `POST /session/stop-impersonating`.
+#[post("/impersonate/<user_id>", data = "<body>")]
+#[allow(clippy::too_many_arguments)]
+async fn impersonate(
+    jar: &CookieJar<'_>,
+    mut db: Connection<Db>,
+    _admin: AdminCtx,
+    meta: RequestMeta,
+    clock: &State<Timekeeper>,
+    ids: &State<IdSource>,
+    user_id: i64,
+    body: json::Json<ImpersonateRequestBody>,
+) -> Result<(Status, json::Value), ApiError> {
+    let staff = body.staff.trim();
+    let reason = body.reason.trim();
+    for (name, value) in [("staff", staff), ("reason", reason)] {
+        if value.is_empty()
+            || value.chars().count() > IMPERSONATION_FIELD_MAX_LEN
+            || value.chars().any(char::is_control)
+        {
+            return Err(ApiError::validation(format!(
+                "{} must be 1 to {} characters",
+                name, IMPERSONATION_FIELD_MAX_LEN
+            )));
+        }
+    }
+
+    let now = clock.now();
+    let impersonation = impersonation_start(&mut db, user_id, staff, reason, &meta, ids.id(), now)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| ApiError::not_found("User not found"))?;
+    tracing::info!("admin:impersonate:{}:{}:{}", user_id, staff, reason);
+    for cookie in impersonation.cookies(now) {
+        jar.add_private(cookie);
+    }
+    let csrf_token = csrf_token_gen();
+    let max_age = rocket::time::Duration::minutes(impersonation_ttl_mins());
+    jar.add(csrf_cookie(csrf_token.clone(), max_age));
+    Ok((
+        Status::Ok,
+        json::json!({ "impersonation": impersonation, "csrfToken": csrf_token }),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct FlagRequestBody {
+    pub enabled: bool,
+    #[serde(default)]
+    pub rollout_percent: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct FlagUserRequestBody {
+    pub enabled: bool,
+}
+
+/// Emails listed by `GET /admin/emails/failures` unless `limit` says otherwise, and the most it can say.
+const EMAIL_FAILURES_LIMIT: i64 = 50;
+const EMAIL_FAILURES_LIMIT_MAX: i64 = 500;
+
+/// Lists the latest emails that failed or were not sent to a suppressed address.
+#[get("/emails/failures?<limit>")]
+async fn email_failures(
+    mut db: Connection<Db>,
+    _admin: AdminCtx,
+    limit: Option<i64>,
+) -> Result<(Status, json::Value), ApiError> {
+    let limit = limit.unwrap_or(EMAIL_FAILURES_LIMIT);
+    if !(1..=EMAIL_FAILURES_LIMIT_MAX).contains(&limit) {
+        return Err(ApiError::validation(format!(
+            "limit must be 1 to {}",
+            EMAIL_FAILURES_LIMIT_MAX
+        )));
+    }
+    let emails = emails_failed(&mut db, limit).await.map_err(db_error)?;
+    Ok((Status::Ok, json::json!({ "items": emails })))
+}
+
+/// Lifts the suppression of an address that hard-bounced.
+#[delete("/emails/suppressions/<email>")]
+async fn email_suppression_remove(
+    mut db: Connection<Db>,
+    _admin: AdminCtx,
+    email: &str,
+) -> Result<(Status, json::Value), ApiError> {
+    if !email_unsuppress(&mut db, email).await.map_err(db_error)? {
+        return Err(ApiError::not_found("Address is not suppressed"));
+    }
+    tracing::info!("admin:email-unsuppressed");
+    Ok((Status::Ok, json::json!({ "email": email })))
+}
+
+#[get("/flags")]
+async fn flags_list(mut db: Connection<Db>, _admin: AdminCtx) -> Result<(Status, json::Value), ApiError> {
+    let set = flags_load(&mut db).await.map_err(db_error)?;
+    let mut flags = set.0.values().collect::<Vec<_>>();
+    flags.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok((Status::Ok, json::json!({ "items": flags })))
+}
+
+#[put("/flags/<name>", data = "<body>")]
+async fn flag_put(
+    mut db: Connection<Db>,
+    cache: &State<FlagCache>,
+    _admin: AdminCtx,
+    clock: &State<Timekeeper>,
+    name: &str,
+    body: json::Json<FlagRequestBody>,
+) -> Result<(Status, json::Value), ApiError> {
+    if !flag_name_is_valid(name) {
+        return Err(ApiError::validation(
+            "Flag names are lowercase letters, digits, '_', '-' and '.'",
+        ));
+    }
+    if !(0..=100).contains(&body.rollout_percent) {
+        return Err(ApiError::validation("rolloutPercent must be between 0 and 100"));
+    }
+    flag_set(&mut db, name, body.enabled, body.rollout_percent, clock.now())
+        .await
+        .map_err(db_error)?;
+    cache.invalidate();
+    tracing::info!("admin:flag-set:{}:{}:{}", name, body.enabled, body.rollout_percent);
+    Ok((
+        Status::Ok,
+        json::json!({ "name": name, "enabled": body.enabled, "rolloutPercent": body.rollout_percent }),
+    ))
+}
+
+#[delete("/flags/<name>")]
+async fn flag_remove(
+    mut db: Connection<Db>,
+    cache: &State<FlagCache>,
+    _admin: AdminCtx,
+    name: &str,
+) -> Result<(Status, json::Value), ApiError> {
+    if !flag_delete(&mut db, name).await.map_err(db_error)? {
+        return Err(ApiError::not_found("Flag not found"));
+    }
+    cache.invalidate();
+    tracing::info!("admin:flag-delete:{}", name);
+    Ok((Status::Ok, json::json!({ "name": name })))
+}
+
+#[put("/flags/<name>/users/<user_id>", data = "<body>")]
+async fn flag_user_put(
+    mut db: Connection<Db>,
+    cache: &State<FlagCache>,
+    _admin: AdminCtx,
+    name: &str,
+    user_id: i64,
+    body: json::Json<FlagUserRequestBody>,
+) -> Result<(Status, json::Value), ApiError> {
+    if !flag_user_set(&mut db, name, user_id, Some(body.enabled))
+        .await
+        .map_err(db_error)?
+    {
+        return Err(ApiError::not_found("Flag or user not found"));
+    }
+    cache.invalidate();
+    tracing::info!("admin:flag-user-set:{}:{}:{}", name, user_id, body.enabled);
+    Ok((
+        Status::Ok,
+        json::json!({ "name": name, "userId": user_id, "enabled": body.enabled }),
+    ))
+}
+
+#[delete("/flags/<name>/users/<user_id>")]
+async fn flag_user_remove(
+    mut db: Connection<Db>,
+    cache: &State<FlagCache>,
+    _admin: AdminCtx,
+    name: &str,
+    user_id: i64,
+) -> Result<(Status, json::Value), ApiError> {
+    if !flag_user_set(&mut db, name, user_id, None).await.map_err(db_error)? {
+        return Err(ApiError::not_found("Override not found"));
+    }
+    cache.invalidate();
+    tracing::info!("admin:flag-user-delete:{}:{}", name, user_id);
+    Ok((Status::Ok, json::json!({ "name": name, "userId": user_id })))
+}
+
+/// Re-reads the runtime settings, as `SIGHUP` does, and returns them. See `Reloadable`.
+#[post("/config/reload")]
+async fn config_reload(reloadable: Reloadable, _admin: AdminCtx) -> Result<(Status, json::Value), ApiError> {
+    let settings = reloadable.reload().map_err(|e| {
+        tracing::warn!("admin:config-reload-failed: {}", e);
+        ApiError::validation(e)
+    })?;
+    tracing::info!("admin:config-reload");
+    Ok((Status::Ok, settings))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct OAuthClientRequestBody {
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    /// Space-separated, the most the client may ask for.
+    pub scopes: String,
+}
+
+/// Registers a first-party app that may get tokens through `/oauth/authorize`.
+#[post("/oauth/clients", data = "<body>")]
+async fn oauth_client_create(
+    mut db: Connection<Db>,
+    _admin: AdminCtx,
+    clock: &State<Timekeeper>,
+    ids: &State<IdSource>,
+    body: json::Json<OAuthClientRequestBody>,
+) -> Result<(Status, json::Value), ApiError> {
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > 200 {
+        return Err(ApiError::validation("name must be 1 to 200 characters"));
+    }
+    if body.redirect_uris.is_empty() || !body.redirect_uris.iter().all(|uri| redirect_uri_is_valid(uri)) {
+        return Err(ApiError::validation(
+            "redirectUris must be absolute URIs without a fragment",
+        ));
+    }
+    let scopes = scopes_parse(&body.scopes)
+        .ok_or_else(|| ApiError::validation("scopes must be <resource>:read or <resource>:write, e.g. posts:read"))?;
+    let client = client_create(&mut db, name, &body.redirect_uris, &scopes, ids.id(), clock.now())
+        .await
+        .map_err(db_error)?;
+    tracing::info!("admin:oauth-client-create:{}", client.id);
+    Ok((Status::Created, json::json!(client)))
+}
+
+#[get("/oauth/clients")]
+async fn oauth_clients_list(mut db: Connection<Db>, _admin: AdminCtx) -> Result<(Status, json::Value), ApiError> {
+    let clients = clients_list(&mut db).await.map_err(db_error)?;
+    Ok((Status::Ok, json::json!({ "items": clients })))
+}
+
+/// Revokes a client and every token it was issued.
+#[delete("/oauth/clients/<id>")]
+async fn oauth_client_revoke(
+    mut db: Connection<Db>,
+    _admin: AdminCtx,
+    clock: &State<Timekeeper>,
+    id: &str,
+) -> Result<(Status, json::Value), ApiError> {
+    if !client_revoke(&mut db, id, clock.now()).await.map_err(db_error)? {
+        return Err(ApiError::not_found("Client not found"));
+    }
+    tracing::info!("admin:oauth-client-revoke:{}", id);
+    Ok((Status::Ok, json::json!({ "id": id })))
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Admin stage", |rocket| async {
+        let rocket = manage_default(rocket, |rocket| {
+            AdminToken(app_config_managed(rocket).auth.admin_token.clone())
+        });
+        api_mount(
+            rocket,
+            "/admin",
+            timeout_routes(
+                "/admin",
+                routes![
+                    users,
+                    suspend,
+                    reactivate,
+                    merge,
+                    impersonate,
+                    email_failures,
+                    email_suppression_remove,
+                    flags_list,
+                    flag_put,
+                    flag_remove,
+                    flag_user_put,
+                    flag_user_remove,
+                    config_reload,
+                    oauth_client_create,
+                    oauth_clients_list,
+                    oauth_client_revoke
+                ],
+            ),
+        )
+    })
+}
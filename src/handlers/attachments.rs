@@ -0,0 +1,209 @@
+use rocket::data::{Data, ToByteUnit};
+use rocket::fairing::AdHoc;
+use rocket::http::{ContentType, Header, Status};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json;
+use rocket::{Request, State};
+use std::io::Cursor;
+use tracing::Instrument;
+
+use crate::api::api_mount;
+use crate::blobs::*;
+use crate::clock::Timekeeper;
+use crate::csrf::*;
+use crate::db::*;
+use crate::error::ApiError;
+use crate::handlers::posts::{ForeignPostPolicy, post_access};
+use crate::scan::{ScanVerdict, Scanner};
+use crate::timeout::timeout_routes;
+use crate::util::*;
+
+/// Maximum length of an attachment's file name.
+const FILENAME_MAX_LEN: usize = 255;
+
+async fn attachment_get(db: &mut sqlx::SqliteConnection, user_id: i64, post_id: &str, id: &str) -> Option<Attachment> {
+    sqlx::query_as!(
+        Attachment,
+        "SELECT a.id, a.post_id, a.user_id, a.blob_key, a.filename, a.content_type, b.size, \
+        a.created_at AS \"created_at: DateTime<Utc>\" FROM attachments a JOIN blobs b ON b.key = a.blob_key \
+        WHERE a.id = ? AND a.post_id = ? AND a.user_id = ?",
+        id,
+        post_id,
+        user_id
+    )
+    .fetch_optional(db)
+    .instrument(query_span("attachments.get"))
+    .await
+    .expect("Failed to fetch attachment")
+}
+
+/// Lists the attachments of a post, oldest first.
+#[get("/<id>/attachments")]
+async fn list(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    access: &State<ForeignPostPolicy>,
+    id: String,
+) -> Result<(Status, json::Value), ApiError> {
+    let attachments = sqlx::query_as!(
+        Attachment,
+        "SELECT a.id, a.post_id, a.user_id, a.blob_key, a.filename, a.content_type, b.size, \
+        a.created_at AS \"created_at: DateTime<Utc>\" FROM attachments a JOIN blobs b ON b.key = a.blob_key \
+        WHERE a.post_id = ? AND a.user_id = ? ORDER BY a.created_at, a.id",
+        id,
+        user.id
+    )
+    .fetch_all(&mut **db)
+    .instrument(query_span("attachments.list"))
+    .await
+    .expect("Failed to fetch attachments");
+    if attachments.is_empty() {
+        post_access(&mut db, **access, user.id, &id).await?;
+    }
+    Ok((Status::Ok, json::json!({ "items": attachments, "hasMore": false })))
+}
+
+/// Attaches the request body to a post as a file named `filename`, of the request's content type.
+/// The content is scanned first, and refused when flagged or when the scanner fails. Identical
+/// files are stored once, see `DedupeScope`.
+#[post("/<id>/attachments?<filename>", data = "<data>")]
+#[allow(clippy::too_many_arguments)]
+async fn create(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    access: &State<ForeignPostPolicy>,
+    _csrf: CsrfVerified,
+    store: &State<BlobStore>,
+    scanner: &State<Scanner>,
+    clock: &State<Timekeeper>,
+    ids: &State<IdSource>,
+    id: String,
+    filename: &str,
+    content_type: Option<&ContentType>,
+    data: Data<'_>,
+) -> Result<(Status, json::Value), ApiError> {
+    let filename = filename.trim();
+    if filename.is_empty()
+        || filename.chars().count() > FILENAME_MAX_LEN
+        || filename.contains(['/', '\\'])
+        || filename.chars().any(char::is_control)
+    {
+        return Err(ApiError::validation(format!(
+            "filename must be 1 to {} characters, without slashes",
+            FILENAME_MAX_LEN
+        )));
+    }
+    post_access(&mut db, **access, user.id, &id).await?;
+
+    let config = blobs_config();
+    let bytes = data
+        .open(config.max_bytes.bytes())
+        .into_bytes()
+        .await
+        .map_err(|_| ApiError::from_status(Status::BadRequest))?;
+    if !bytes.is_complete() {
+        return Err(ApiError::from_status(Status::PayloadTooLarge).details(json::json!({ "max": config.max_bytes })));
+    }
+    let bytes = bytes.into_inner();
+    match scanner.scan(&bytes).await {
+        Ok(ScanVerdict::Clean) => {}
+        Ok(ScanVerdict::Infected(signature)) => {
+            tracing::info!("attachments:flagged:{}:{}", user.id, signature);
+            return Err(ApiError::validation("The file was flagged by the content scanner"));
+        }
+        Err(_) => return Err(ApiError::from_status(Status::ServiceUnavailable)),
+    }
+
+    let (blob_key, sha256) = blob_key(user.id, &bytes, config.dedupe);
+    let attachment = Attachment {
+        id: ids.id(),
+        post_id: id,
+        user_id: user.id,
+        blob_key,
+        filename: filename.to_owned(),
+        content_type: content_type.unwrap_or(&ContentType::Binary).to_string(),
+        size: bytes.len() as i64,
+        created_at: clock.now(),
+    };
+    if let Err(e) = attachment_create(&mut db, store, &attachment, &sha256, &bytes).await {
+        tracing::error!("attachments:store-error: {}", e);
+        return Err(ApiError::internal());
+    }
+    Ok((Status::Created, json::json!(attachment)))
+}
+
+/// The content of an attachment, sent as a download so that browsers never render it inline.
+struct AttachmentFile {
+    attachment: Attachment,
+    bytes: Vec<u8>,
+}
+
+impl<'r> Responder<'r, 'static> for AttachmentFile {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let content_type = ContentType::parse_flexible(&self.attachment.content_type).unwrap_or(ContentType::Binary);
+        let filename = self.attachment.filename.replace(['"', '\\'], "_");
+        Response::build()
+            .header(content_type)
+            .header(Header::new(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", filename),
+            ))
+            .header(Header::new("X-Content-Type-Options", "nosniff"))
+            .sized_body(self.bytes.len(), Cursor::new(self.bytes))
+            .ok()
+    }
+}
+
+#[get("/<id>/attachments/<attachment_id>")]
+async fn download(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    store: &State<BlobStore>,
+    id: String,
+    attachment_id: String,
+) -> Result<AttachmentFile, ApiError> {
+    let attachment = attachment_get(&mut db, user.id, &id, &attachment_id)
+        .await
+        .ok_or_else(|| ApiError::not_found("Attachment not found"))?;
+    let bytes = store.read(&attachment.blob_key).await.map_err(|e| {
+        tracing::error!("attachments:read-error:{}: {}", attachment.id, e);
+        ApiError::internal()
+    })?;
+    Ok(AttachmentFile { attachment, bytes })
+}
+
+/// Deletes an attachment. Its blob is removed by the GC job once no attachment uses it.
+#[delete("/<id>/attachments/<attachment_id>")]
+async fn delete(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    id: String,
+    attachment_id: String,
+) -> Result<(Status, json::Value), ApiError> {
+    let deleted = sqlx::query!(
+        "DELETE FROM attachments WHERE id = ? AND post_id = ? AND user_id = ?",
+        attachment_id,
+        id,
+        user.id
+    )
+    .execute(&mut **db)
+    .instrument(query_span("attachments.delete"))
+    .await
+    .expect("Failed to delete attachment")
+    .rows_affected();
+    if deleted == 0 {
+        return Err(ApiError::not_found("Attachment not found"));
+    }
+    Ok((Status::Ok, json::json!({ "message": "success" })))
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Attachments stage", |rocket| async {
+        api_mount(
+            rocket,
+            "/posts",
+            timeout_routes("/posts", routes![list, create, download, delete]),
+        )
+    })
+}
@@ -0,0 +1,219 @@
+use chrono::{NaiveDate, NaiveTime, TimeDelta};
+use nanoid::nanoid;
+use regex::Regex;
+use rocket::State;
+use rocket::fairing::AdHoc;
+use rocket::http::{ContentType, Status};
+use rocket::serde::json;
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+use tracing::Instrument;
+
+use crate::api::{API_V1, api_mount};
+use crate::clock::Timekeeper;
+use crate::csrf::*;
+use crate::db::*;
+use crate::error::ApiError;
+use crate::scope::Scope;
+use crate::timeout::timeout_routes;
+use crate::util::*;
+
+/// Maximum number of events in a feed, the most recently updated posts winning.
+const CALENDAR_EVENTS_MAX: usize = 1000;
+/// Length of a timed event, as calendars need an end.
+const EVENT_DURATION_MINS: i64 = 30;
+
+/// When a post happens on a calendar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostDate {
+    /// At a time, from `remind_at` or a date and time in the content.
+    At(DateTime<Utc>),
+    /// All day, from a date alone in the content.
+    Day(NaiveDate),
+}
+
+/// Returns the date of a decrypted post: its reminder if it has one, otherwise the first
+/// `YYYY-MM-DD` date in its content, with an optional `HH:MM` time (UTC) after a space or `T`.
+pub fn post_date(post: &Post) -> Option<PostDate> {
+    if let Some(remind_at) = post.remind_at {
+        return Some(PostDate::At(remind_at));
+    }
+    static DATE: OnceLock<Regex> = OnceLock::new();
+    let date = DATE.get_or_init(|| Regex::new(r"\b(\d{4}-\d{2}-\d{2})(?:[T ](\d{2}:\d{2}))?\b").expect("valid regex"));
+    date.captures_iter(&post.content).find_map(|captures| {
+        let day = NaiveDate::parse_from_str(&captures[1], "%Y-%m-%d").ok()?;
+        match captures
+            .get(2)
+            .and_then(|time| NaiveTime::parse_from_str(time.as_str(), "%H:%M").ok())
+        {
+            Some(time) => Some(PostDate::At(day.and_time(time).and_utc())),
+            None => Some(PostDate::Day(day)),
+        }
+    })
+}
+
+/// Escapes a TEXT value (RFC 5545 3.3.11).
+fn ics_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+        .replace('\r', "")
+}
+
+/// Appends a content line, folded at 75 octets without splitting characters (RFC 5545 3.1).
+fn ics_line(ics: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            ics.push_str("\r\n ");
+            width = 1;
+        }
+        ics.push(c);
+        width += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+fn ics_timestamp(at: DateTime<Utc>) -> String {
+    at.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Renders decrypted posts as an iCalendar feed with an event per dated post, titled with the
+/// post's first line.
+pub fn calendar_render(posts: &[Post]) -> String {
+    let mut ics = String::new();
+    for line in [
+        "BEGIN:VCALENDAR",
+        "VERSION:2.0",
+        "PRODID:-//rocket-sqlx//posts//EN",
+        "CALSCALE:GREGORIAN",
+        "X-WR-CALNAME:Posts",
+    ] {
+        ics_line(&mut ics, line);
+    }
+    for post in posts {
+        let Some(date) = post_date(post) else {
+            continue;
+        };
+        let title = post
+            .content
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or_default();
+        let title = title.trim().chars().take(80).collect::<String>();
+        ics_line(&mut ics, "BEGIN:VEVENT");
+        ics_line(&mut ics, &format!("UID:{}@rocket-sqlx", post.id));
+        ics_line(&mut ics, &format!("DTSTAMP:{}", ics_timestamp(post.updated_at)));
+        match date {
+            PostDate::At(at) => {
+                ics_line(&mut ics, &format!("DTSTART:{}", ics_timestamp(at)));
+                let end = at + TimeDelta::minutes(EVENT_DURATION_MINS);
+                ics_line(&mut ics, &format!("DTEND:{}", ics_timestamp(end)));
+            }
+            PostDate::Day(day) => {
+                ics_line(&mut ics, &format!("DTSTART;VALUE=DATE:{}", day.format("%Y%m%d")));
+                let end = day.succ_opt().unwrap_or(day);
+                ics_line(&mut ics, &format!("DTEND;VALUE=DATE:{}", end.format("%Y%m%d")));
+            }
+        }
+        ics_line(&mut ics, &format!("SUMMARY:{}", ics_escape(&title)));
+        ics_line(&mut ics, &format!("DESCRIPTION:{}", ics_escape(&post.content)));
+        ics_line(&mut ics, &format!("URL:{}/posts/{}", app_url(), post.id));
+        ics_line(&mut ics, "END:VEVENT");
+    }
+    ics_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Serves the iCalendar feed of the dated posts of the user owning `token`. Calendar apps can't
+/// log in, so the token in the URL is the only credential; a wrong one is a 404.
+#[get("/calendar.ics?<token>")]
+async fn feed(mut db: Connection<Db>, token: &str) -> Result<(ContentType, String), ApiError> {
+    let hash = token_hash(token);
+    let user_id = sqlx::query_scalar!(
+        "SELECT f.user_id FROM calendar_feeds f JOIN users u ON u.id = f.user_id \
+        WHERE f.token_hash = ? AND u.disabled_at IS NULL",
+        hash
+    )
+    .fetch_optional(&mut **db)
+    .instrument(query_span("calendar_feeds.user_id"))
+    .await
+    .expect("Failed to fetch calendar feed")
+    .ok_or_else(|| ApiError::not_found("Calendar feed not found"))?;
+
+    let posts = Scope::user(user_id)
+        .posts_updated_since(&mut db, DateTime::UNIX_EPOCH)
+        .await
+        .expect("Failed to fetch posts")
+        .into_iter()
+        .rev()
+        .map(Post::content_decrypt)
+        .filter(|post| post_date(post).is_some())
+        .take(CALENDAR_EVENTS_MAX)
+        .collect::<Vec<_>>();
+    Ok((ContentType::Calendar, calendar_render(&posts)))
+}
+
+/// Creates the user's calendar feed token, replacing any previous one, and returns it with the
+/// feed URL. Only a digest is stored, so the token can't be shown again.
+#[post("/me/calendar-feed")]
+async fn feed_create(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+) -> (Status, json::Value) {
+    let token = nanoid!(32);
+    let hash = token_hash(&token);
+    let now = clock.now();
+    sqlx::query!(
+        "INSERT INTO calendar_feeds (user_id, token_hash, created_at) VALUES (?, ?, ?) \
+        ON CONFLICT (user_id) DO UPDATE SET token_hash = excluded.token_hash, created_at = excluded.created_at",
+        user.id,
+        hash,
+        now
+    )
+    .execute(&mut **db)
+    .instrument(query_span("calendar_feeds.upsert"))
+    .await
+    .expect("Failed to save calendar feed");
+
+    let url = format!("{}{}/posts/calendar.ics?token={}", app_url(), API_V1, token);
+    (Status::Created, json::json!({ "token": token, "url": url }))
+}
+
+/// Revokes the user's calendar feed token.
+#[delete("/me/calendar-feed")]
+async fn feed_delete(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+) -> Result<(Status, json::Value), ApiError> {
+    let deleted = sqlx::query!("DELETE FROM calendar_feeds WHERE user_id = ?", user.id)
+        .execute(&mut **db)
+        .instrument(query_span("calendar_feeds.delete"))
+        .await
+        .expect("Failed to delete calendar feed")
+        .rows_affected();
+    if deleted == 0 {
+        return Err(ApiError::not_found("Calendar feed not found"));
+    }
+    Ok((Status::Ok, json::json!({ "message": "success" })))
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Calendar stage", |rocket| async {
+        let rocket = api_mount(rocket, "/posts", timeout_routes("/posts", routes![feed]));
+        api_mount(
+            rocket,
+            "/users",
+            timeout_routes("/users", routes![feed_create, feed_delete]),
+        )
+    })
+}
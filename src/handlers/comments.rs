@@ -0,0 +1,269 @@
+use rocket::State;
+use rocket::fairing::AdHoc;
+use rocket::form::FromForm;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, json};
+use tracing::Instrument;
+
+use crate::api::api_mount;
+use crate::clock::{Timekeeper, clock_config};
+use crate::crypto::content_cipher;
+use crate::csrf::*;
+use crate::db::*;
+use crate::error::ApiError;
+use crate::handlers::posts::{ForeignPostPolicy, post_access};
+use crate::timeout::timeout_routes;
+use crate::util::*;
+
+/// Comments are scoped to the post: only its owner can read or write them.
+async fn post_check(
+    db: &mut sqlx::SqliteConnection,
+    user: &UserCtx,
+    access: &State<ForeignPostPolicy>,
+    post_id: &str,
+) -> Result<(), ApiError> {
+    post_access(db, **access, user.id, post_id).await
+}
+
+#[derive(FromForm)]
+struct QueryParams {
+    after: Option<String>,
+    limit: Option<i64>,
+}
+
+/// Lists the comments of a post, most recently updated first, paginated like `GET /api/posts`.
+#[get("/<post_id>/comments?<qp..>")]
+async fn list(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    access: &State<ForeignPostPolicy>,
+    post_id: String,
+    qp: QueryParams,
+) -> Result<(Status, json::Value), ApiError> {
+    post_check(&mut db, &user, access, &post_id).await?;
+
+    let limit = qp.limit.unwrap_or(10).min(1000);
+    let limit_plus_one = limit + 1;
+    let after = qp
+        .after
+        .map(|after| timestamp_param_parse("after", &after))
+        .transpose()?;
+
+    let comments = sqlx::query_as!(
+        Comment,
+        "SELECT id, post_id, parent_id, user_id, content, created_at AS \"created_at: DateTime<Utc>\", \
+        updated_at AS \"updated_at: DateTime<Utc>\" FROM comments \
+        WHERE post_id = ? AND user_id = ? AND (? IS NULL OR updated_at >= ?) ORDER BY updated_at DESC LIMIT ?",
+        post_id,
+        user.id,
+        after,
+        after,
+        limit_plus_one
+    )
+    .fetch(&mut **db)
+    .map_ok(Comment::content_decrypt)
+    .try_collect::<Vec<_>>()
+    .instrument(query_span("comments.list"))
+    .await
+    .expect("Failed to fetch comments");
+
+    let has_more = comments.len() as i64 > limit;
+    let comments = comments.into_iter().take(limit as usize).collect::<Vec<_>>();
+
+    Ok((
+        Status::Ok,
+        json::json!({
+            "items": comments,
+            "hasMore": has_more,
+        }),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct CreateRequestBody {
+    pub id: Option<String>,
+    /// The comment this one replies to, on the same post.
+    pub parent_id: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub content: String,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Creates a comment, or updates it when the id exists and the supplied `updatedAt` is newer (the
+/// same last-write-wins semantics as `POST /api/posts`).
+#[post("/<post_id>/comments", data = "<body>")]
+#[allow(clippy::too_many_arguments)]
+async fn create(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    access: &State<ForeignPostPolicy>,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    ids: &State<IdSource>,
+    post_id: String,
+    body: json::Json<CreateRequestBody>,
+) -> Result<(Status, json::Value), ApiError> {
+    post_check(&mut db, &user, access, &post_id).await?;
+
+    if let Some(parent_id) = &body.parent_id {
+        let parent = sqlx::query!(
+            "SELECT id FROM comments WHERE id = ? AND post_id = ?",
+            parent_id,
+            post_id
+        )
+        .fetch_optional(&mut **db)
+        .instrument(query_span("comments.parent"))
+        .await
+        .expect("Failed to fetch comment");
+        if parent.is_none() {
+            return Err(ApiError::validation("Parent comment not found on this post"));
+        }
+    }
+
+    let now = clock.now_stored();
+    let id = body.id.clone().unwrap_or_else(|| ids.id());
+    let config = clock_config();
+    let created_at = config
+        .accept(body.created_at.unwrap_or(now), now)
+        .map_err(ApiError::validation)?;
+    let updated_at = config
+        .accept(body.updated_at.unwrap_or(now), now)
+        .map_err(ApiError::validation)?;
+    let content = content_cipher().encrypt(&body.content);
+
+    sqlx::query!(
+        "INSERT INTO comments (id, post_id, parent_id, user_id, content, created_at, updated_at) \
+        VALUES (?, ?, ?, ?, ?, ?, ?) \
+        ON CONFLICT(id) DO UPDATE SET \
+        content = excluded.content, \
+        updated_at = excluded.updated_at \
+        WHERE comments.updated_at < excluded.updated_at \
+        AND comments.post_id = excluded.post_id AND comments.user_id = excluded.user_id",
+        id,
+        post_id,
+        body.parent_id,
+        user.id,
+        content,
+        created_at,
+        updated_at,
+    )
+    .execute(&mut **db)
+    .instrument(query_span("comments.upsert"))
+    .await
+    .expect("Failed to upsert comment");
+
+    Ok((Status::Created, json::json!({ "message": "success", "id": id })))
+}
+
+#[get("/<post_id>/comments/<id>")]
+async fn read(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    post_id: String,
+    id: String,
+) -> Result<(Status, json::Value), ApiError> {
+    let comment = sqlx::query_as!(
+        Comment,
+        "SELECT id, post_id, parent_id, user_id, content, created_at AS \"created_at: DateTime<Utc>\", \
+        updated_at AS \"updated_at: DateTime<Utc>\" FROM comments WHERE id = ? AND post_id = ? AND user_id = ?",
+        id,
+        post_id,
+        user.id
+    )
+    .fetch_optional(&mut **db)
+    .instrument(query_span("comments.read"))
+    .await
+    .expect("Failed to fetch comment");
+
+    match comment {
+        Some(comment) => Ok((Status::Ok, json::json!(comment.content_decrypt()))),
+        None => Err(ApiError::not_found("Comment not found")),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct UpdateRequestBody {
+    pub content: String,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[put("/<post_id>/comments/<id>", data = "<body>")]
+async fn update(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    post_id: String,
+    id: String,
+    body: json::Json<UpdateRequestBody>,
+) -> Result<(Status, json::Value), ApiError> {
+    let now = clock.now_stored();
+    let updated_at = clock_config()
+        .accept(body.updated_at.unwrap_or(now), now)
+        .map_err(ApiError::validation)?;
+    let content = content_cipher().encrypt(&body.content);
+
+    let result = sqlx::query!(
+        "UPDATE comments SET content = ?, updated_at = ? \
+        WHERE id = ? AND post_id = ? AND user_id = ? AND updated_at < ?",
+        content,
+        updated_at,
+        id,
+        post_id,
+        user.id,
+        updated_at,
+    )
+    .execute(&mut **db)
+    .instrument(query_span("comments.update"))
+    .await
+    .expect("Failed to update comment");
+
+    if result.rows_affected() == 0 {
+        return Err(ApiError::not_found(
+            "Comment not found or supplied update_at is less than existing",
+        ));
+    }
+
+    Ok((Status::Ok, json::json!({ "message": "success" })))
+}
+
+/// Deletes a comment along with its replies.
+#[delete("/<post_id>/comments/<id>")]
+async fn delete(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    post_id: String,
+    id: String,
+) -> Result<(Status, json::Value), ApiError> {
+    let result = sqlx::query!(
+        "DELETE FROM comments WHERE id = ? AND post_id = ? AND user_id = ?",
+        id,
+        post_id,
+        user.id
+    )
+    .execute(&mut **db)
+    .instrument(query_span("comments.delete"))
+    .await
+    .expect("Failed to delete comment");
+
+    if result.rows_affected() == 0 {
+        return Err(ApiError::not_found("Comment not found"));
+    }
+
+    Ok((Status::Ok, json::json!({ "message": "success" })))
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Comments stage", |rocket| async {
+        api_mount(
+            rocket,
+            "/posts",
+            timeout_routes("/posts", routes![list, create, read, update, delete]),
+        )
+    })
+}
@@ -0,0 +1,26 @@
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::json;
+
+use crate::api::api_mount;
+use crate::email::dev_outbox;
+use crate::timeout::timeout_routes;
+use crate::util::*;
+
+/// Lists the latest emails simulated in debug mode, newest first, e.g. to read login codes. `to`
+/// keeps those to one address.
+#[get("/emails?<to>")]
+async fn emails(to: Option<&str>) -> (Status, json::Value) {
+    let emails = dev_outbox().list(to.map(str::trim).filter(|to| !to.is_empty()));
+    (Status::Ok, json::json!({ "items": emails }))
+}
+
+/// Routes for developers, only mounted in debug mode.
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Dev stage", |rocket| async {
+        if app_mode() != "debug" {
+            return rocket;
+        }
+        api_mount(rocket, "/dev", timeout_routes("/dev", routes![emails]))
+    })
+}
@@ -0,0 +1,312 @@
+use async_graphql::parser::types::OperationType;
+use async_graphql::{Context, Enum, InputObject, Object, Schema, SimpleObject, Subscription, Variables};
+use async_graphql_rocket::{GraphQLRequest, GraphQLResponse};
+use rocket::State;
+use rocket::fairing::AdHoc;
+use rocket::futures::{Stream, StreamExt, stream};
+use rocket::response::stream::{Event, EventStream};
+use rocket::serde::json;
+use rocket::tokio::sync::broadcast::error::RecvError;
+
+use crate::clock::Timekeeper;
+use crate::crypto::content_cipher;
+use crate::csrf::*;
+use crate::db::*;
+use crate::error::ApiError;
+use crate::handlers::posts::*;
+use crate::maintenance::Maintenance;
+use crate::panics::panic_routes;
+use crate::ratelimit::rate_limit_routes;
+use crate::scope::Scope;
+use crate::timeout::timeout_routes;
+use crate::util::*;
+
+pub type PostsSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+#[derive(SimpleObject)]
+#[graphql(name = "Post")]
+struct PostObject {
+    id: String,
+    parent_id: Option<String>,
+    content: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    variant: String,
+    position: Option<f64>,
+    seq: i64,
+    org_id: Option<String>,
+    remind_at: Option<DateTime<Utc>>,
+    favorited: bool,
+}
+
+impl From<Post> for PostObject {
+    fn from(post: Post) -> Self {
+        Self {
+            id: post.id,
+            parent_id: post.parent_id,
+            content: content_cipher()
+                .decrypt(&post.content)
+                .expect("Failed to decrypt post content"),
+            created_at: post.created_at,
+            updated_at: post.updated_at,
+            variant: post.variant,
+            position: post.position,
+            seq: post.seq,
+            org_id: post.org_id,
+            remind_at: post.remind_at,
+            favorited: post.favorited,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+struct PostPage {
+    items: Vec<PostObject>,
+    has_more: bool,
+}
+
+#[derive(InputObject)]
+struct PostInput {
+    id: Option<String>,
+    parent_id: Option<String>,
+    created_at: Option<DateTime<Utc>>,
+    content: String,
+    updated_at: Option<DateTime<Utc>>,
+    variant: String,
+    position: Option<f64>,
+    org_id: Option<String>,
+    remind_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Enum, Copy, Clone, PartialEq, Eq)]
+#[graphql(name = "PostChangeKind", remote = "PostChangeKind")]
+enum PostChangeKindObject {
+    Upserted,
+    Deleted,
+    Cleared,
+    Reminded,
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "PostChange")]
+struct PostChangeObject {
+    kind: PostChangeKindObject,
+    /// The post affected, or null when all posts were deleted.
+    id: Option<String>,
+}
+
+/// Returns the authenticated user attached to the GraphQL request.
+fn user_id(ctx: &Context<'_>) -> async_graphql::Result<i64> {
+    Ok(ctx.data::<UserCtx>()?.id)
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Lists the user's posts, newest first. Mirrors `GET /api/posts`: `after` only returns posts
+    /// updated at or after the given time, and `hasMore` tells whether `limit` cut the list short.
+    async fn posts(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<DateTime<Utc>>,
+        variant: Option<String>,
+        #[graphql(default = 10)] limit: i64,
+    ) -> async_graphql::Result<PostPage> {
+        let user_id = user_id(ctx)?;
+        let pool = ctx.data::<sqlx::SqlitePool>()?;
+        let limit = limit.clamp(0, 1000);
+        let limit_plus_one = limit + 1;
+
+        let mut db = pool.acquire().await?;
+        let posts = Scope::user(user_id)
+            .posts_list_variant(&mut *db, after, variant.as_deref(), limit_plus_one)
+            .await?;
+
+        let has_more = posts.len() as i64 > limit;
+        let items = posts.into_iter().take(limit as usize).map(PostObject::from).collect();
+        Ok(PostPage { items, has_more })
+    }
+
+    async fn post(&self, ctx: &Context<'_>, id: String) -> async_graphql::Result<Option<PostObject>> {
+        let user_id = user_id(ctx)?;
+        let pool = ctx.data::<sqlx::SqlitePool>()?;
+        let mut db = pool.acquire().await?;
+        let post = Scope::user(user_id).post_read(&mut *db, &id).await?;
+        Ok(post.map(PostObject::from))
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Creates or updates posts with the same semantics as `POST /api/posts/upsert-many`: an
+    /// existing post is only overwritten when the supplied `updatedAt` is newer. Missing ids and
+    /// timestamps are generated like `POST /api/posts` does.
+    async fn upsert_posts(&self, ctx: &Context<'_>, posts: Vec<PostInput>) -> async_graphql::Result<bool> {
+        let user_id = user_id(ctx)?;
+        let pool = ctx.data::<sqlx::SqlitePool>()?;
+        let (clock, ids) = (ctx.data::<Timekeeper>()?, ctx.data::<IdSource>()?);
+        let now = clock.now_stored();
+
+        let posts = posts
+            .into_iter()
+            .map(|post| UpsertPostPayload {
+                id: post.id.unwrap_or_else(|| ids.id()),
+                parent_id: post.parent_id,
+                created_at: post.created_at.unwrap_or(now),
+                content: post.content,
+                updated_at: post.updated_at.unwrap_or(now),
+                version: version_default(None, post.updated_at),
+                variant: post.variant,
+                position: post.position,
+                org_id: post.org_id,
+                remind_at: post.remind_at,
+            })
+            .collect::<Vec<_>>();
+
+        let mut db = pool.acquire().await?;
+        posts_upsert_many(&mut *db, user_id, &posts, now).await?;
+        Ok(true)
+    }
+
+    /// Deletes a post, returning whether it existed. Its children move up to its parent, or are
+    /// deleted too with `cascade`.
+    async fn delete_post(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        #[graphql(default = false)] cascade: bool,
+    ) -> async_graphql::Result<bool> {
+        let user_id = user_id(ctx)?;
+        let pool = ctx.data::<sqlx::SqlitePool>()?;
+        let now = ctx.data::<Timekeeper>()?.now_stored();
+        let children = match cascade {
+            true => ChildrenOnDelete::Cascade,
+            false => ChildrenOnDelete::Reparent,
+        };
+
+        let mut db = pool.acquire().await?;
+        Ok(post_delete(&mut *db, user_id, &id, children, now).await?)
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Emits whenever one of the user's posts changes, through either API.
+    async fn post_changed(&self, ctx: &Context<'_>) -> async_graphql::Result<impl Stream<Item = PostChangeObject>> {
+        let user_id = user_id(ctx)?;
+        let receiver = post_changes().subscribe();
+        Ok(stream::unfold(receiver, move |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(change) if change.user_id == user_id => {
+                        let change = PostChangeObject {
+                            kind: change.kind.into(),
+                            id: change.id,
+                        };
+                        return Some((change, receiver));
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }))
+    }
+}
+
+/// Builds the schema served at `/api/graphql`.
+pub fn schema_build() -> PostsSchema {
+    Schema::build(QueryRoot, MutationRoot, SubscriptionRoot).finish()
+}
+
+/// Whether the document holds a mutation. Documents that don't parse are left to the executor.
+fn document_mutates(query: &str) -> bool {
+    async_graphql::parser::parse_query(query).is_ok_and(|document| {
+        document
+            .operations
+            .iter()
+            .any(|(_, operation)| operation.node.ty == OperationType::Mutation)
+    })
+}
+
+/// Executes queries and mutations. Like the REST API, it requires a CSRF token for
+/// cookie-authenticated requests, and refuses mutations in maintenance mode. Queries share the
+/// route, so the check is made here rather than by `maintenance_routes`.
+#[post("/", data = "<request>")]
+async fn execute(
+    schema: &State<PostsSchema>,
+    db: &State<Db>,
+    clock: &State<Timekeeper>,
+    ids: &State<IdSource>,
+    maintenance: &State<Maintenance>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    request: GraphQLRequest,
+) -> Result<GraphQLResponse, ApiError> {
+    if document_mutates(&request.0.query) {
+        if let Some(error) = maintenance.write_refusal() {
+            return Err(error);
+        }
+    }
+    let pool: sqlx::SqlitePool = (***db).clone();
+    Ok(request
+        .data(user)
+        .data(pool)
+        .data(clock.inner().clone())
+        .data(ids.inner().clone())
+        .execute(schema.inner())
+        .await)
+}
+
+/// Runs a subscription and streams each result as a server-sent event, since Rocket does not speak
+/// websockets. Only subscription operations are accepted so that this GET route cannot be used to
+/// run mutations without a CSRF token.
+#[get("/stream?<query>&<variables>")]
+fn subscribe(
+    schema: &State<PostsSchema>,
+    user: UserCtx,
+    query: String,
+    variables: Option<String>,
+) -> Result<EventStream![], ApiError> {
+    let invalid = |message: &str| ApiError::validation(message);
+
+    let document = async_graphql::parser::parse_query(&query).map_err(|_| invalid("query is invalid"))?;
+    let subscriptions_only = document
+        .operations
+        .iter()
+        .all(|(_, operation)| operation.node.ty == OperationType::Subscription);
+    if !subscriptions_only {
+        return Err(invalid("only subscriptions can be streamed"));
+    }
+
+    let mut request = async_graphql::Request::new(query).data(user);
+    if let Some(variables) = variables {
+        let variables = json::from_str(&variables).map_err(|_| invalid("variables are invalid"))?;
+        request = request.variables(Variables::from_json(variables));
+    }
+
+    let mut responses = schema.inner().execute_stream(request);
+    Ok(EventStream! {
+        while let Some(response) = responses.next().await {
+            yield Event::json(&response);
+        }
+    })
+}
+
+/// Mounts the GraphQL routes under the caller's rate limit. The class is picked before the body is
+/// read, so queries count as writes along with mutations, and only streamed subscriptions as reads.
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("GraphQL stage", |rocket| async {
+        rocket
+            .manage(schema_build())
+            .mount(
+                "/api/graphql",
+                rate_limit_routes(panic_routes(timeout_routes("/api/graphql", routes![execute]))),
+            )
+            .mount("/api/graphql", rate_limit_routes(panic_routes(routes![subscribe])))
+    })
+}
@@ -1,2 +1,13 @@
+pub mod admin;
+pub mod attachments;
+pub mod calendar;
+pub mod comments;
+pub mod dev;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+pub mod orgs;
 pub mod posts;
 pub mod session;
+pub mod shares;
+pub mod templates;
+pub mod users;
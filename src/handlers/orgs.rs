@@ -0,0 +1,360 @@
+use rocket::State;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize, json};
+use tracing::Instrument;
+
+use crate::api::api_mount;
+use crate::clock::Timekeeper;
+use crate::csrf::*;
+use crate::db::*;
+use crate::error::ApiError;
+use crate::scope::Scope;
+use crate::timeout::timeout_routes;
+use crate::util::*;
+
+/// Maximum length of an organization name.
+const ORG_NAME_MAX_LEN: usize = 100;
+
+/// What a member may do in an organization. Ordered by privilege.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum OrgRole {
+    /// Reads the posts shared with the organization.
+    Member,
+    /// Also adds and removes members.
+    Admin,
+    /// Also manages admins and other owners.
+    Owner,
+}
+
+impl OrgRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Member => "member",
+            Self::Admin => "admin",
+            Self::Owner => "owner",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "member" => Some(Self::Member),
+            "admin" => Some(Self::Admin),
+            "owner" => Some(Self::Owner),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the role of the user in the organization, or `None` when they are not a member.
+pub(crate) async fn org_role(
+    db: &mut sqlx::SqliteConnection,
+    org_id: &str,
+    user_id: i64,
+) -> Result<Option<OrgRole>, sqlx::Error> {
+    let role = sqlx::query_scalar!(
+        "SELECT role FROM organization_members WHERE org_id = ? AND user_id = ?",
+        org_id,
+        user_id
+    )
+    .fetch_optional(db)
+    .instrument(query_span("organization_members.role"))
+    .await?;
+    Ok(role.as_deref().and_then(OrgRole::parse))
+}
+
+/// Returns the user's role, failing with 404 when they are not a member so that organizations
+/// can't be probed.
+async fn member_check(db: &mut sqlx::SqliteConnection, org_id: &str, user_id: i64) -> Result<OrgRole, ApiError> {
+    org_role(db, org_id, user_id)
+        .await
+        .expect("Failed to fetch membership")
+        .ok_or_else(|| ApiError::not_found("Organization not found"))
+}
+
+/// Whether `actor` may give or take away `role`: admins manage plain members, owners everyone.
+fn role_manageable(actor: OrgRole, role: OrgRole) -> bool {
+    match actor {
+        OrgRole::Owner => true,
+        OrgRole::Admin => role == OrgRole::Member,
+        OrgRole::Member => false,
+    }
+}
+
+async fn owners_count(db: &mut sqlx::SqliteConnection, org_id: &str) -> i64 {
+    sqlx::query_scalar!(
+        "SELECT COUNT(*) AS \"count!: i64\" FROM organization_members WHERE org_id = ? AND role = 'owner'",
+        org_id
+    )
+    .fetch_one(db)
+    .instrument(query_span("organization_members.owners_count"))
+    .await
+    .expect("Failed to count owners")
+}
+
+/// Lists the organizations the user is a member of, with their role in each.
+#[get("/")]
+async fn list(mut db: Connection<Db>, user: UserCtx) -> (Status, json::Value) {
+    let orgs = sqlx::query!(
+        "SELECT o.id, o.name, o.created_at AS \"created_at: DateTime<Utc>\", m.role FROM organizations o \
+        JOIN organization_members m ON m.org_id = o.id WHERE m.user_id = ? ORDER BY o.name",
+        user.id
+    )
+    .fetch_all(&mut **db)
+    .instrument(query_span("organizations.list"))
+    .await
+    .expect("Failed to fetch organizations");
+
+    let items = orgs
+        .into_iter()
+        .map(|org| {
+            json::json!({
+                "id": org.id,
+                "name": org.name,
+                "createdAt": org.created_at,
+                "role": org.role,
+            })
+        })
+        .collect::<Vec<_>>();
+    (Status::Ok, json::json!({ "items": items, "hasMore": false }))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct CreateRequestBody {
+    name: String,
+}
+
+/// Creates an organization with the user as its owner.
+#[post("/", data = "<body>")]
+async fn create(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    ids: &State<IdSource>,
+    body: json::Json<CreateRequestBody>,
+) -> Result<(Status, json::Value), ApiError> {
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > ORG_NAME_MAX_LEN {
+        return Err(ApiError::validation(format!(
+            "name must be 1 to {} characters",
+            ORG_NAME_MAX_LEN
+        )));
+    }
+
+    let org = Organization {
+        id: ids.id(),
+        name: name.to_owned(),
+        created_at: clock.now(),
+    };
+    let mut tx = sqlx::Connection::begin(&mut **db)
+        .await
+        .expect("Failed to begin transaction");
+    sqlx::query!(
+        "INSERT INTO organizations (id, name, created_at) VALUES (?, ?, ?)",
+        org.id,
+        org.name,
+        org.created_at
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("organizations.insert"))
+    .await
+    .expect("Failed to insert organization");
+    let role = OrgRole::Owner.as_str();
+    sqlx::query!(
+        "INSERT INTO organization_members (org_id, user_id, role, created_at) VALUES (?, ?, ?, ?)",
+        org.id,
+        user.id,
+        role,
+        org.created_at
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("organization_members.insert"))
+    .await
+    .expect("Failed to insert organization owner");
+    tx.commit().await.expect("Failed to commit organization");
+
+    Ok((Status::Created, json::json!(org)))
+}
+
+/// Lists the members of an organization. Any member may see the others.
+#[get("/<id>/members")]
+async fn members(mut db: Connection<Db>, user: UserCtx, id: String) -> Result<(Status, json::Value), ApiError> {
+    member_check(&mut db, &id, user.id).await?;
+
+    let members = sqlx::query!(
+        "SELECT m.user_id, u.email, m.role, m.created_at AS \"created_at: DateTime<Utc>\" FROM organization_members m \
+        JOIN users u ON u.id = m.user_id WHERE m.org_id = ? ORDER BY m.created_at, m.user_id",
+        id
+    )
+    .fetch_all(&mut **db)
+    .instrument(query_span("organization_members.list"))
+    .await
+    .expect("Failed to fetch members");
+
+    let items = members
+        .into_iter()
+        .map(|member| {
+            json::json!({
+                "userId": member.user_id,
+                "email": member.email,
+                "role": member.role,
+                "createdAt": member.created_at,
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok((Status::Ok, json::json!({ "items": items, "hasMore": false })))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct MemberRequestBody {
+    email: String,
+    role: OrgRole,
+}
+
+/// Adds a user to the organization by email, or changes the role of an existing member. Admins
+/// manage plain members, owners everyone. The last owner cannot be demoted.
+#[put("/<id>/members", data = "<body>")]
+async fn member_put(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    id: String,
+    body: json::Json<MemberRequestBody>,
+) -> Result<(Status, json::Value), ApiError> {
+    let actor = member_check(&mut db, &id, user.id).await?;
+    let forbidden = || ApiError::from_status(Status::Forbidden);
+    if !role_manageable(actor, body.role) {
+        return Err(forbidden());
+    }
+
+    let member_id = sqlx::query_scalar!("SELECT id FROM users WHERE email = ?", body.email)
+        .fetch_optional(&mut **db)
+        .instrument(query_span("users.id_by_email"))
+        .await
+        .expect("Failed to fetch user")
+        .ok_or_else(|| ApiError::validation(format!("No user with email {}", body.email)))?;
+
+    let mut tx = sqlx::Connection::begin(&mut **db)
+        .await
+        .expect("Failed to begin transaction");
+    let current = org_role(&mut tx, &id, member_id)
+        .await
+        .expect("Failed to fetch membership");
+    if let Some(current) = current {
+        if !role_manageable(actor, current) {
+            return Err(forbidden());
+        }
+        if current == OrgRole::Owner && body.role != OrgRole::Owner && owners_count(&mut tx, &id).await == 1 {
+            return Err(ApiError::validation("An organization needs at least one owner"));
+        }
+    }
+
+    let role = body.role.as_str();
+    let now = clock.now();
+    sqlx::query!(
+        "INSERT INTO organization_members (org_id, user_id, role, created_at) VALUES (?, ?, ?, ?) \
+        ON CONFLICT(org_id, user_id) DO UPDATE SET role = excluded.role",
+        id,
+        member_id,
+        role,
+        now
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("organization_members.upsert"))
+    .await
+    .expect("Failed to save member");
+    tx.commit().await.expect("Failed to commit member");
+
+    let status = match current {
+        Some(_) => Status::Ok,
+        None => Status::Created,
+    };
+    Ok((status, json::json!({ "userId": member_id, "role": body.role })))
+}
+
+/// Removes a member. Members may leave on their own, otherwise the same rules as `member_put`
+/// apply. The last owner cannot leave.
+#[delete("/<id>/members/<member_id>")]
+async fn member_remove(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    id: String,
+    member_id: i64,
+) -> Result<(Status, json::Value), ApiError> {
+    let actor = member_check(&mut db, &id, user.id).await?;
+
+    let mut tx = sqlx::Connection::begin(&mut **db)
+        .await
+        .expect("Failed to begin transaction");
+    let role = org_role(&mut tx, &id, member_id)
+        .await
+        .expect("Failed to fetch membership")
+        .ok_or_else(|| ApiError::not_found("Member not found"))?;
+    if member_id != user.id && !role_manageable(actor, role) {
+        return Err(ApiError::from_status(Status::Forbidden));
+    }
+    if role == OrgRole::Owner && owners_count(&mut tx, &id).await == 1 {
+        return Err(ApiError::validation("An organization needs at least one owner"));
+    }
+
+    sqlx::query!(
+        "DELETE FROM organization_members WHERE org_id = ? AND user_id = ?",
+        id,
+        member_id
+    )
+    .execute(&mut *tx)
+    .instrument(query_span("organization_members.delete"))
+    .await
+    .expect("Failed to remove member");
+    tx.commit().await.expect("Failed to commit member removal");
+
+    Ok((Status::Ok, json::json!({ "message": "success" })))
+}
+
+/// Lists the posts shared with the organization, most recently updated first. Pages like
+/// `GET /posts`: `after` only returns posts updated at or after the given time.
+#[get("/<id>/posts?<after>&<limit>")]
+async fn posts(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    id: String,
+    after: Option<String>,
+    limit: Option<i64>,
+) -> Result<(Status, json::Value), ApiError> {
+    member_check(&mut db, &id, user.id).await?;
+
+    let limit = limit.unwrap_or(10).min(1000);
+    let limit_plus_one = limit + 1;
+    let after = after.map(|after| timestamp_param_parse("after", &after)).transpose()?;
+    // `favorited` is the viewer's own star, not the author's
+    let posts = Scope::from(&user)
+        .org_posts(&mut db, &id, after, limit_plus_one)
+        .await
+        .expect("Failed to fetch posts")
+        .into_iter()
+        .map(Post::content_decrypt)
+        .collect::<Vec<_>>();
+
+    let has_more = posts.len() as i64 > limit;
+    let posts = posts.into_iter().take(limit as usize).collect::<Vec<_>>();
+    Ok((Status::Ok, json::json!({ "items": posts, "hasMore": has_more })))
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Organizations stage", |rocket| async {
+        api_mount(
+            rocket,
+            "/orgs",
+            timeout_routes(
+                "/orgs",
+                routes![list, create, members, member_put, member_remove, posts],
+            ),
+        )
+    })
+}
@@ -1,46 +1,196 @@
-use chrono::Timelike;
+use rocket::State;
 use rocket::fairing::AdHoc;
-use rocket::form::FromForm;
+use rocket::form::{FromForm, FromFormField};
 use rocket::http::Status;
-use rocket::serde::{Deserialize, json};
+use rocket::serde::{Deserialize, Serialize, json};
+use rocket::tokio::sync::broadcast;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+use tracing::Instrument;
 
+use crate::api::{BatchItem, api_mount};
+use crate::cache::response_cache;
+use crate::clock::{Timekeeper, clock_config};
+use crate::coalesce::{PendingUpdate, UpdateCoalescer};
+use crate::crypto::content_cipher;
+use crate::csrf::*;
 use crate::db::*;
+use crate::error::{ApiError, ErrorCode};
+use crate::events::{event_record, events_wake};
+use crate::filter::{FilterExpr, JSON_PATHS_MAX, JsonPathFilter};
+use crate::handlers::orgs::org_role;
+use crate::hlc::{Hlc, hlc_clock};
+use crate::merge::text_merge;
+use crate::payload::Payload;
+use crate::previews::posts_with_previews;
+use crate::retry::{Busy, WriteError, write_error, write_retry};
+use crate::scope::*;
+use crate::timeout::timeout_routes;
 use crate::util::*;
+use crate::variants::variants;
 
-#[derive(FromForm)]
+/// What happened to a post, broadcast to live subscribers (e.g. the GraphQL `postChanged`
+/// subscription).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PostChangeKind {
+    Upserted,
+    Deleted,
+    /// All of the user's posts were deleted.
+    Cleared,
+    /// A reminder set on the post is due, see `reminders`.
+    Reminded,
+}
+
+impl PostChangeKind {
+    /// The `kind` stored in the `events` outbox.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Upserted => "upserted",
+            Self::Deleted => "deleted",
+            Self::Cleared => "cleared",
+            Self::Reminded => "reminded",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "upserted" => Some(Self::Upserted),
+            "deleted" => Some(Self::Deleted),
+            "cleared" => Some(Self::Cleared),
+            "reminded" => Some(Self::Reminded),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PostChange {
+    pub user_id: i64,
+    pub kind: PostChangeKind,
+    /// The post affected, or `None` for `Cleared`.
+    pub id: Option<String>,
+}
+
+/// Returns the process-wide channel post changes are published on. Handlers record changes in the
+/// `events` outbox instead of sending here; the events dispatcher publishes them once committed.
+pub fn post_changes() -> &'static broadcast::Sender<PostChange> {
+    static CHANNEL: OnceLock<broadcast::Sender<PostChange>> = OnceLock::new();
+    CHANNEL.get_or_init(|| broadcast::channel(1024).0)
+}
+
+#[derive(Debug, FromForm)]
 struct QueryParams {
+    /// Only list posts updated at or after this time, see `timestamp_param_parse` for the formats.
     after: Option<String>,
+    /// Only list posts updated before this time, for range queries together with `after`.
+    before: Option<String>,
+    /// Only list posts that are (`true`) or are not (`false`) favorited.
+    favorited: Option<bool>,
+    /// Only list the children of this post, or top-level posts when empty.
+    parent: Option<String>,
+    sort: Option<PostSort>,
+    /// Compound filter such as `variant eq "todo" and updatedAt gt "2024-01-01"`, see `FilterExpr`.
+    filter: Option<String>,
+    /// Filters on structured content such as `$.done == false`, see `JsonPathFilter`. Repeat the
+    /// parameter to combine them.
+    json_path: Vec<String>,
+    /// Incremental sync: only list changes after this `seq`. Other filters are ignored.
+    since_seq: Option<i64>,
     limit: Option<i64>,
+    /// Include the link previews fetched so far of each post, under `previews`.
+    previews: Option<bool>,
+    /// With `false`, leave out the `content` of each post, for list views that only show its
+    /// `title` and `excerpt`.
+    content: Option<bool>,
+    /// Only read and return these fields of each post, e.g. `id,updatedAt,excerpt`, see
+    /// `PostFields`.
+    fields: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+enum PostSort {
+    /// Most recently updated first.
+    Updated,
+    /// By the user-controlled `position`, then most recently updated first.
+    Manual,
 }
 
 #[get("/?<qp..>")]
-async fn list(mut db: Connection<Db>, user: UserCtx, qp: QueryParams) -> (Status, json::Value) {
-    // info!("list:params:limit={:?}:after={:?}", qp.limit, qp.after);
+async fn list(db: Connection<Db>, user: UserCtx, mut qp: QueryParams) -> Result<(Status, json::Value), ApiError> {
+    // Keyed by the parsed parameters, so that their order and encoding do not split the entries
+    qp.json_path.sort_unstable();
+    let key = format!("list?{:?}", qp);
+    if let Some(cached) = response_cache().get(user.id, "list", &key).await {
+        return Ok((Status::Ok, cached));
+    }
+    let (status, response) = list_fetch(db, &user, qp).await?;
+    response_cache().insert(user.id, key, response.clone()).await;
+    Ok((status, response))
+}
 
+async fn list_fetch(
+    mut db: Connection<Db>,
+    user: &UserCtx,
+    qp: QueryParams,
+) -> Result<(Status, json::Value), ApiError> {
     let limit = qp.limit.unwrap_or(10).min(1000);
-    let limit_plus_one = limit + 1;
-
-    let posts = match qp.after {
-        Some(after) => {
-            let after = NaiveDateTime::parse_from_rfc3339(after);
-            sqlx::query_as!(
-                Post,
-                "SELECT * FROM posts WHERE user_id = ? AND updated_at >= ? ORDER BY updated_at DESC LIMIT ?",
-                user.id,
-                after,
-                limit_plus_one
-            )
-            .fetch(&mut **db)
-            .try_collect::<Vec<_>>()
-            .await
-            .expect("Failed to fetch posts")
-        }
-        None => sqlx::query_as!(Post, "SELECT * FROM posts WHERE user_id = ? LIMIT ?", user.id, limit)
-            .fetch(&mut **db)
-            .try_collect::<Vec<_>>()
-            .await
-            .expect("Failed to fetch posts"),
+    if let Some(since_seq) = qp.since_seq {
+        return Ok(list_since_seq(&mut db, user.id, since_seq, limit).await);
+    }
+
+    let expr = qp
+        .filter
+        .as_deref()
+        .filter(|filter| !filter.trim().is_empty())
+        .map(FilterExpr::parse)
+        .transpose()
+        .map_err(ApiError::validation)?;
+    if qp.json_path.len() > JSON_PATHS_MAX {
+        return Err(ApiError::validation(format!(
+            "At most {} json_path filters are allowed",
+            JSON_PATHS_MAX
+        )));
+    }
+    let json_paths = qp
+        .json_path
+        .iter()
+        .map(|json_path| JsonPathFilter::parse(json_path))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(ApiError::validation)?;
+    let fields = fields_param_parse(qp.fields.as_deref())?;
+    let after = qp
+        .after
+        .map(|after| timestamp_param_parse("after", &after))
+        .transpose()?;
+    let before = qp
+        .before
+        .map(|before| timestamp_param_parse("before", &before))
+        .transpose()?;
+    if after.zip(before).is_some_and(|(after, before)| after >= before) {
+        return Err(ApiError::validation("`after` must be earlier than `before`"));
+    }
+    let filter = PostFilter {
+        favorited: qp.favorited,
+        parent: qp.parent.map(|parent| (!parent.is_empty()).then_some(parent)),
+        before,
+        manual: qp.sort == Some(PostSort::Manual),
+        expr,
+        json_paths,
     };
+    let scope = Scope::from(user);
+    let fetched = match after {
+        Some(_) => limit + 1,
+        None => limit,
+    };
+    let posts = match &fields {
+        Some(fields) => scope.posts_list_fields(&mut db, &filter, fields, after, fetched).await,
+        None => scope.posts_list(&mut db, &filter, after, fetched).await,
+    }
+    .expect("Failed to fetch posts")
+    .into_iter()
+    .map(Post::content_decrypt)
+    .collect::<Vec<_>>();
 
     let has_more = posts.len() as i64 > limit;
     let posts = if has_more {
@@ -48,12 +198,73 @@ async fn list(mut db: Connection<Db>, user: UserCtx, qp: QueryParams) -> (Status
     } else {
         posts
     };
+    let mut items = match qp.previews {
+        Some(true) => json::json!(
+            posts_with_previews(&mut db, posts)
+                .await
+                .expect("Failed to fetch link previews")
+        ),
+        _ => json::json!(posts),
+    };
+    if qp.content == Some(false) {
+        for item in items.as_array_mut().into_iter().flatten() {
+            if let Some(item) = item.as_object_mut() {
+                item.remove("content");
+            }
+        }
+    }
+    if let (Some(fields), Some(posts)) = (&fields, items.as_array_mut()) {
+        for item in posts {
+            *item = fields.pick(item.take());
+        }
+    }
+
+    Ok((
+        Status::Ok,
+        json::json!({
+            "items": items,
+            "hasMore": has_more,
+        }),
+    ))
+}
+
+/// Lists the posts written after `since_seq` in write order, with the IDs of the posts deleted in
+/// the same range in `deleted`. Clients pass the returned `seq` as the next `since_seq`, which
+/// keeps working when their clock is off, unlike `after`.
+async fn list_since_seq(db: &mut Connection<Db>, user_id: i64, since_seq: i64, limit: i64) -> (Status, json::Value) {
+    let scope = Scope::user(user_id);
+    let posts = scope
+        .posts_since_seq(db, since_seq, limit + 1)
+        .await
+        .expect("Failed to fetch posts")
+        .into_iter()
+        .map(Post::content_decrypt)
+        .collect::<Vec<_>>();
+
+    let has_more = posts.len() as i64 > limit;
+    let posts = posts.into_iter().take(limit as usize).collect::<Vec<_>>();
+    // A cut-off page ends at its last post, otherwise the client is caught up
+    let seq = match (has_more, posts.last()) {
+        (true, Some(last)) => last.seq,
+        _ => sqlx::query_scalar!("SELECT seq FROM sync_counter")
+            .fetch_one(&mut ***db)
+            .instrument(query_span("posts.sync_counter"))
+            .await
+            .expect("Failed to fetch sync counter"),
+    };
+
+    let deleted = scope
+        .posts_deleted_between(db, since_seq, seq)
+        .await
+        .expect("Failed to fetch deleted posts");
 
     (
         Status::Ok,
         json::json!({
             "items": posts,
             "hasMore": has_more,
+            "deleted": deleted,
+            "seq": seq,
         }),
     )
 }
@@ -63,125 +274,665 @@ async fn list(mut db: Connection<Db>, user: UserCtx, qp: QueryParams) -> (Status
 #[serde(crate = "rocket::serde")]
 pub struct CreateRequestBody {
     pub id: Option<String>,
+    pub parent_id: Option<String>,
     pub created_at: Option<DateTime<Utc>>,
     pub content: String,
     pub updated_at: Option<DateTime<Utc>>,
+    /// The client's clock stamp of the write, see `UpsertPostPayload::version`.
+    pub version: Option<Hlc>,
     pub variant: String,
+    pub position: Option<f64>,
+    /// Shares the post with this organization, which the user must be a member of.
+    pub org_id: Option<String>,
+    /// When to remind the user of the post.
+    pub remind_at: Option<DateTime<Utc>>,
 }
 
 #[post("/", data = "<body>")]
-async fn create(mut db: Connection<Db>, user: UserCtx, body: json::Json<CreateRequestBody>) -> (Status, json::Value) {
-    let now = Utc::now().with_nanosecond(0).unwrap();
-
-    let id = body.id.clone().unwrap_or_else(|| id_gen());
-    let created_at = body.created_at.unwrap_or_else(|| now).naive_utc();
-    let updated_at = body.updated_at.unwrap_or_else(|| now).naive_utc();
-
-    sqlx::query!(
-        "INSERT INTO posts (created_at, id, content, updated_at, user_id, variant) \
-        VALUES (?, ?, ?, ?, ?, ?) \
-        ON CONFLICT(id) DO UPDATE SET \
-        content = excluded.content, \
-        variant = excluded.variant, \
-        updated_at = excluded.updated_at \
-        WHERE posts.updated_at < excluded.updated_at AND posts.user_id = excluded.user_id",
-        created_at,
-        id,
-        body.content,
-        updated_at,
-        user.id,
-        body.variant,
-    )
-    .execute(&mut **db)
-    .await
-    .expect("Failed to upsert post");
+async fn create(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    ids: &State<IdSource>,
+    body: Payload<CreateRequestBody>,
+) -> Result<(Status, json::Value), ApiError> {
+    let now = clock.now_stored();
+    let body = body.into_inner();
+
+    // A single-post upsert-many, defaulting the ID and timestamps
+    let post = UpsertPostPayload {
+        id: body.id.unwrap_or_else(|| ids.id()),
+        parent_id: body.parent_id,
+        created_at: body.created_at.unwrap_or(now),
+        content: body.content,
+        updated_at: body.updated_at.unwrap_or(now),
+        version: version_default(body.version, body.updated_at),
+        variant: body.variant,
+        position: body.position,
+        org_id: body.org_id,
+        remind_at: body.remind_at,
+    };
+    write_retry!("posts.create", async {
+        posts_upsert_many(&mut db, user.id, std::slice::from_ref(&post), now).await
+    })?;
 
-    (Status::Created, json::json!(MESSAGE_RESPONSE_SUCCESS.clone()))
+    Ok((Status::Created, json::json!(MESSAGE_RESPONSE_SUCCESS.clone())))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 #[serde(crate = "rocket::serde")]
 pub struct UpsertPostPayload {
     pub id: String,
+    #[serde(default)]
+    pub parent_id: Option<String>,
     pub created_at: DateTime<Utc>,
     pub content: String,
     pub updated_at: DateTime<Utc>,
+    /// The client's hybrid logical clock stamp of the write, see `Hlc`. Conflicts are decided by
+    /// the stamps, so that writes from clocks out of step still apply in the order they were
+    /// made. Without one, the post is stamped with `updated_at`.
+    #[serde(default)]
+    pub version: Option<Hlc>,
     pub variant: String,
+    #[serde(default)]
+    pub position: Option<f64>,
+    #[serde(default)]
+    pub org_id: Option<String>,
+    /// When to remind the user of the post, see `reminders`. Set again by every upsert, like the
+    /// other fields.
+    #[serde(default)]
+    pub remind_at: Option<DateTime<Utc>>,
+}
+
+/// Maximum length of an `upsert-many` `batchId`.
+const BATCH_ID_MAX_LEN: usize = 100;
+
+/// How long `upsert-many` remembers a `batchId`, from `UPSERT_BATCH_RETENTION_SECS` (1 day).
+fn batch_retention() -> chrono::TimeDelta {
+    chrono::TimeDelta::seconds(app_config().limits.upsert_batch_retention_secs)
+}
+
+/// The body of `upsert-many`: the posts, or the posts with a `batchId` making retries safe.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+#[serde(crate = "rocket::serde")]
+pub enum UpsertManyBody {
+    Posts(Vec<UpsertPostPayload>),
+    #[serde(rename_all = "camelCase")]
+    Batch {
+        batch_id: String,
+        posts: Vec<UpsertPostPayload>,
+    },
 }
 
-#[post("/upsert-many", data = "<body>")]
+/// Digest of a batch and the options it was sent with.
+fn batch_digest(posts: &[UpsertPostPayload], dedupe: bool, partial: bool) -> String {
+    let batch = json::to_string(&(posts, dedupe, partial)).expect("posts serialize");
+    format!("{:x}", Sha256::digest(batch.as_bytes()))
+}
+
+/// Returns the response recorded for the user's batch, unless it expired by `now`. Fails when the
+/// batch ID was used for other posts.
+async fn batch_get(
+    db: &mut sqlx::SqliteConnection,
+    user_id: i64,
+    batch_id: &str,
+    digest: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<(Status, json::Value)>, ApiError> {
+    let since = now - batch_retention();
+    let batch = sqlx::query!(
+        "SELECT digest, status, response FROM upsert_batches WHERE user_id = ? AND batch_id = ? AND created_at > ?",
+        user_id,
+        batch_id,
+        since
+    )
+    .fetch_optional(db)
+    .instrument(query_span("upsert_batches.get"))
+    .await
+    .expect("Failed to fetch batch");
+    let Some(batch) = batch else {
+        return Ok(None);
+    };
+    if batch.digest != digest {
+        return Err(ApiError::validation(format!(
+            "Batch {} was already applied with other posts",
+            batch_id
+        )));
+    }
+    let status = Status::from_code(batch.status as u16).unwrap_or(Status::Ok);
+    let response = json::from_str(&batch.response).expect("Stored batch response is valid JSON");
+    Ok(Some((status, response)))
+}
+
+#[post("/upsert-many?<dedupe>&<partial>", data = "<body>")]
 /// Upsert multiple posts in a single request. The client must provide the full post
 /// data for each post, and the server will insert or update each post based on the ID.
-/// For updates, the server will only apply the update if the provided updated_at is
-/// greater than the existing updated_at to prevent overwriting newer data with older
-/// data.
+/// For updates, the server will only apply the update if the provided version (or
+/// updated_at) is greater than the existing one to prevent overwriting newer data with
+/// older data.
+///
+/// With `?dedupe=true`, posts whose content matches another of the user's posts are
+/// skipped, e.g. when re-running an import that generates fresh IDs. The skipped IDs
+/// are returned in `skipped`.
+///
+/// With `?partial=true`, each post is written independently and an invalid post does
+/// not fail the others: the response is a `207 Multi-Status` listing each post's
+/// status in `results`. Parents must then come before their children.
+///
+/// The posts may instead be sent as `{"batchId": ..., "posts": [...]}`. A batch is applied once:
+/// retrying it within `UPSERT_BATCH_RETENTION_SECS` returns the response it first got, without
+/// writing the posts or recording their events again, so clients can retry after losing a
+/// response. Reusing a batch ID for other posts or options is a 422.
 async fn upsert_many(
     mut db: Connection<Db>,
     user: UserCtx,
-    body: json::Json<Vec<UpsertPostPayload>>,
-) -> (Status, json::Value) {
-    if body.is_empty() {
-        return (Status::Ok, json::json!(MESSAGE_RESPONSE_SUCCESS.clone()));
-    }
-
-    let mut builder =
-        sqlx::QueryBuilder::new("INSERT INTO posts (created_at, id, content, updated_at, user_id, variant) ");
-
-    builder.push_values(body.iter(), |mut row, post| {
-        row.push_bind(post.created_at.naive_utc())
-            .push_bind(&post.id)
-            .push_bind(&post.content)
-            .push_bind(post.updated_at.naive_utc())
-            .push_bind(user.id)
-            .push_bind(&post.variant);
-    });
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    dedupe: Option<bool>,
+    partial: Option<bool>,
+    body: Payload<UpsertManyBody>,
+) -> Result<(Status, json::Value), ApiError> {
+    let now = clock.now_stored();
+    let dedupe = dedupe.unwrap_or(false);
+    let partial = partial.unwrap_or(false);
+    let (batch, posts) = match body.into_inner() {
+        UpsertManyBody::Posts(posts) => (None, posts),
+        UpsertManyBody::Batch { batch_id, posts } => {
+            if batch_id.is_empty() || batch_id.len() > BATCH_ID_MAX_LEN {
+                return Err(ApiError::validation(format!(
+                    "batchId must be 1 to {} characters",
+                    BATCH_ID_MAX_LEN
+                )));
+            }
+            let digest = batch_digest(&posts, dedupe, partial);
+            if let Some(response) = batch_get(&mut db, user.id, &batch_id, &digest, now).await? {
+                return Ok(response);
+            }
+            (Some((batch_id, digest)), posts)
+        }
+    };
+    let (posts, skipped) = match dedupe {
+        true => posts_dedupe(&mut db, user.id, posts)
+            .await
+            .expect("Failed to look up duplicate posts"),
+        false => (posts, Vec::new()),
+    };
 
-    builder.push(
-        " ON CONFLICT(id) DO UPDATE SET content = excluded.content, variant = excluded.variant, updated_at = excluded.updated_at"
-    );
-    builder.push(" WHERE posts.updated_at < excluded.updated_at AND posts.user_id = excluded.user_id");
+    let applied = write_retry!("posts.upsert_many", async {
+        let mut tx = tx_begin(&mut db).await?;
+        let response = if partial {
+            let mut results = Vec::with_capacity(posts.len());
+            for post in &posts {
+                let mut savepoint = tx_begin(&mut tx).await?;
+                match posts_upsert_many(&mut savepoint, user.id, std::slice::from_ref(post), now).await {
+                    Ok(()) => {
+                        tx_commit(savepoint).await?;
+                        results.push(BatchItem::ok(&post.id, Status::Ok));
+                    }
+                    // Retry the whole batch rather than fail the post for a lock
+                    Err(e) if e.is_busy() => return Err(e.into()),
+                    // Dropping the savepoint rolls the post back
+                    Err(e) => results.push(BatchItem::error(&post.id, e.into())),
+                }
+            }
+            (
+                Status::MultiStatus,
+                json::json!({ "results": results, "skipped": skipped }),
+            )
+        } else {
+            posts_upsert_many(&mut tx, user.id, &posts, now).await?;
+            match dedupe {
+                true => (Status::Ok, json::json!({ "message": "success", "skipped": skipped })),
+                false => (Status::Ok, json::json!(MESSAGE_RESPONSE_SUCCESS.clone())),
+            }
+        };
 
-    builder
-        .build()
-        .execute(&mut **db)
-        .await
-        .expect("Failed to upsert posts");
+        // Recorded with the posts, so that a batch that failed is applied when retried
+        if let Some((batch_id, digest)) = &batch {
+            let expired = now - batch_retention();
+            sqlx::query!(
+                "DELETE FROM upsert_batches WHERE user_id = ? AND created_at <= ?",
+                user.id,
+                expired
+            )
+            .execute(&mut *tx)
+            .instrument(query_span("upsert_batches.prune"))
+            .await?;
+            let status = response.0.code;
+            let body = response.1.to_string();
+            let recorded = sqlx::query!(
+                "INSERT INTO upsert_batches (user_id, batch_id, digest, status, response, created_at) \
+                VALUES (?, ?, ?, ?, ?, ?) ON CONFLICT (user_id, batch_id) DO NOTHING",
+                user.id,
+                batch_id,
+                digest,
+                status,
+                body,
+                now
+            )
+            .execute(&mut *tx)
+            .instrument(query_span("upsert_batches.insert"))
+            .await?
+            .rows_affected();
+            if recorded == 0 {
+                // A concurrent retry of the batch was applied first, so drop this one
+                return Ok(None);
+            }
+        }
+        tx_commit(tx).await?;
+        Ok::<_, WriteError>(Some(response))
+    })?;
+    events_wake();
+
+    match (applied, batch) {
+        (Some(response), _) => Ok(response),
+        (None, Some((batch_id, digest))) => batch_get(&mut db, user.id, &batch_id, &digest, now)
+            .await?
+            .ok_or_else(ApiError::internal),
+        (None, None) => Err(ApiError::internal()),
+    }
+}
+
+/// Splits `posts` into those to write and the IDs of those whose content already exists under
+/// another ID, either stored or earlier in `posts`.
+pub async fn posts_dedupe(
+    db: &mut sqlx::SqliteConnection,
+    user_id: i64,
+    posts: Vec<UpsertPostPayload>,
+) -> Result<(Vec<UpsertPostPayload>, Vec<String>), sqlx::Error> {
+    let cipher = content_cipher();
+    let mut seen = HashSet::new();
+    let mut kept = Vec::with_capacity(posts.len());
+    let mut skipped = Vec::new();
+
+    for post in posts {
+        let content_hash = cipher.content_hash(&post.content);
+        let duplicate = match seen.insert(content_hash.clone()) {
+            false => true,
+            true => {
+                Scope::user(user_id)
+                    .post_duplicate_exists(&mut *db, &content_hash, &post.id)
+                    .await?
+            }
+        };
+        match duplicate {
+            true => skipped.push(post.id),
+            false => kept.push(post),
+        }
+    }
+    Ok((kept, skipped))
+}
 
-    (Status::Ok, json::json!(MESSAGE_RESPONSE_SUCCESS.clone()))
+/// Why a post write was refused.
+#[derive(Debug)]
+pub enum PostWriteError {
+    /// The input is inconsistent, e.g. a missing parent or a parent cycle.
+    Invalid(String),
+    /// A content of `size` bytes is over the `max` of `post_content_max`.
+    TooLarge {
+        size: usize,
+        max: usize,
+    },
+    /// The ID is used by another user's post. IDs are unique across users, so the client must pick
+    /// another one.
+    Taken(String),
+    Db(sqlx::Error),
+}
+
+impl std::fmt::Display for PostWriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Invalid(message) => write!(f, "{}", message),
+            Self::TooLarge { size, max } => write!(f, "Content is {} bytes, over the limit of {} bytes", size, max),
+            Self::Taken(id) => write!(f, "Post ID {} is already taken", id),
+            Self::Db(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for PostWriteError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Db(e)
+    }
+}
+
+impl From<PostWriteError> for ApiError {
+    fn from(e: PostWriteError) -> Self {
+        match e {
+            PostWriteError::Invalid(message) => ApiError::validation(message),
+            PostWriteError::TooLarge { size, max } => {
+                ApiError::new(Status::PayloadTooLarge, ErrorCode::PayloadTooLarge, e.to_string())
+                    .details(json::json!({ "size": size, "maxBytes": max }))
+            }
+            PostWriteError::Taken(ref id) => ApiError::conflict(e.to_string()).details(json::json!({ "id": id })),
+            PostWriteError::Db(e) => write_error(e),
+        }
+    }
+}
+
+impl From<PostWriteError> for WriteError {
+    fn from(e: PostWriteError) -> Self {
+        match e {
+            PostWriteError::Db(e) => Self::Db(e),
+            e => Self::Api(e.into()),
+        }
+    }
+}
+
+impl Busy for PostWriteError {
+    fn is_busy(&self) -> bool {
+        matches!(self, Self::Db(e) if e.is_busy())
+    }
+}
+
+/// Checks that a post content is within `post_content_max`.
+pub fn content_size_check(content: &str) -> Result<(), PostWriteError> {
+    let max = post_content_max();
+    match content.len() > max {
+        true => Err(PostWriteError::TooLarge {
+            size: content.len(),
+            max,
+        }),
+        false => Ok(()),
+    }
+}
+
+/// Checks that every parent in `posts` (`(id, parent_id)` pairs) is one of the user's posts, stored
+/// or in `posts` itself, and that no post would become its own ancestor.
+pub async fn parents_validate(
+    db: &mut sqlx::SqliteConnection,
+    user_id: i64,
+    posts: &[(&str, Option<&str>)],
+) -> Result<(), PostWriteError> {
+    let batch = posts.iter().copied().collect::<HashMap<_, _>>();
+
+    for (id, parent_id) in posts {
+        let mut seen = HashSet::from([id.to_string()]);
+        let mut current = parent_id.map(str::to_owned);
+        while let Some(ancestor) = current {
+            if !seen.insert(ancestor.clone()) {
+                return Err(PostWriteError::Invalid(format!(
+                    "Post {} cannot be nested under its own descendant",
+                    id
+                )));
+            }
+            current = match batch.get(ancestor.as_str()) {
+                Some(parent_id) => parent_id.map(str::to_owned),
+                None => Scope::user(user_id)
+                    .post_parent_id(&mut *db, &ancestor)
+                    .await?
+                    .ok_or_else(|| PostWriteError::Invalid(format!("Parent post {} not found", ancestor)))?,
+            };
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the user is a member of each of the organizations posts are shared with.
+pub async fn orgs_validate(
+    db: &mut sqlx::SqliteConnection,
+    user_id: i64,
+    org_ids: &[String],
+) -> Result<(), PostWriteError> {
+    for org_id in org_ids {
+        if org_role(&mut *db, org_id, user_id).await?.is_none() {
+            return Err(PostWriteError::Invalid(format!("Organization {} not found", org_id)));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the client's `version`, or a new stamp of the server when the client sent neither a
+/// version nor an `updated_at`, so that the write wins like one made on the server.
+pub fn version_default(version: Option<Hlc>, updated_at: Option<DateTime<Utc>>) -> Option<Hlc> {
+    version.or_else(|| updated_at.is_none().then(|| hlc_clock().now()))
+}
+
+/// Upserts the given posts for the user, keeping whichever has the newer `version`. Timestamps
+/// past `now` are checked against the allowed clock skew. Shared by the REST, GraphQL and gRPC
+/// APIs.
+pub async fn posts_upsert_many(
+    db: &mut sqlx::SqliteConnection,
+    user_id: i64,
+    posts: &[UpsertPostPayload],
+    now: DateTime<Utc>,
+) -> Result<(), PostWriteError> {
+    if posts.is_empty() {
+        return Ok(());
+    }
+    for post in posts {
+        content_size_check(&post.content)?;
+        variants()
+            .check(&post.variant, &post.content)
+            .map_err(PostWriteError::Invalid)?;
+    }
+
+    let parents = posts
+        .iter()
+        .map(|post| (post.id.as_str(), post.parent_id.as_deref()))
+        .collect::<Vec<_>>();
+    parents_validate(&mut *db, user_id, &parents).await?;
+    let org_ids = posts
+        .iter()
+        .filter_map(|post| post.org_id.clone())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    orgs_validate(&mut *db, user_id, &org_ids).await?;
+
+    let clock = clock_config();
+    let timestamps = posts
+        .iter()
+        .map(|post| {
+            let created_at = clock.accept(post.created_at, now)?;
+            let updated_at = clock.accept(post.updated_at, now)?;
+            let version = post.version.clone().unwrap_or_else(|| Hlc::from_wall(updated_at));
+            Ok((created_at, updated_at, hlc_clock().accept(version, now)?))
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map_err(PostWriteError::Invalid)?;
+
+    let rows = posts
+        .iter()
+        .zip(timestamps)
+        .map(|(post, (created_at, updated_at, version))| PostWrite {
+            id: &post.id,
+            parent_id: post.parent_id.as_deref(),
+            content: StoredContent::of(&post.content),
+            created_at,
+            updated_at,
+            version,
+            variant: &post.variant,
+            position: post.position,
+            org_id: post.org_id.as_deref(),
+            remind_at: post.remind_at,
+        })
+        .collect::<Vec<_>>();
+    let mut tx = tx_begin(db).await?;
+    let ids = posts.iter().map(|post| post.id.as_str()).collect::<Vec<_>>();
+    let taken = Scope::user(user_id).post_ids_taken(&mut tx, &ids).await?;
+    if let Some(id) = taken.into_iter().next() {
+        return Err(PostWriteError::Taken(id));
+    }
+    Scope::user(user_id).posts_upsert(&mut tx, &rows).await?;
+    for post in posts {
+        event_record(&mut tx, user_id, PostChangeKind::Upserted, Some(&post.id), now).await?;
+    }
+    tx_commit(tx).await?;
+    events_wake();
+    Ok(())
 }
 
 #[delete("/")]
-async fn delete_all(mut db: Connection<Db>, user: UserCtx) -> (Status, json::Value) {
-    sqlx::query!("DELETE FROM posts WHERE user_id = ?", user.id)
-        .execute(&mut **db)
-        .await
-        .expect("Failed to delete posts");
-
-    (Status::Ok, json::json!({ "message": "success" }))
-}
-
-#[get("/<id>")]
-async fn read(mut db: Connection<Db>, user: UserCtx, id: String) -> (Status, json::Value) {
-    let post = sqlx::query_as!(Post, "SELECT * FROM posts WHERE id = ? AND user_id = ?", id, user.id)
-        .fetch_optional(&mut **db)
-        // .map_ok(|r| {
-        //     Post {
-        //         id: r.id,
-        //         // created_at: r.created_at,
-        //         content: r.content,
-        //         // updated_at: r.updated_at,
-        //         variant: r.variant,
-        //     }
-        //     // r
-        // })
+async fn delete_all(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+) -> Result<(Status, json::Value), ApiError> {
+    write_retry!("posts.delete_all", async {
+        let mut tx = tx_begin(&mut db).await?;
+        Scope::from(&user).posts_remove_all(&mut tx).await?;
+        event_record(&mut tx, user.id, PostChangeKind::Cleared, None, clock.now()).await?;
+        tx_commit(tx).await
+    })
+    .map_err(write_error)?;
+    events_wake();
+
+    Ok((Status::Ok, json::json!({ "message": "success" })))
+}
+
+/// Deletes the posts with the given IDs, handling their children like `DELETE /<id>`. All or
+/// nothing by default: a missing post fails the request with 404. With `?partial=true` the
+/// response is a `207 Multi-Status` listing each post's status in `results`.
+#[post("/delete-many?<children>&<partial>", data = "<body>")]
+#[allow(clippy::too_many_arguments)]
+async fn delete_many(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    access: &State<ForeignPostPolicy>,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    children: Option<ChildrenOnDelete>,
+    partial: Option<bool>,
+    body: Payload<Vec<String>>,
+) -> Result<(Status, json::Value), ApiError> {
+    let children = children.unwrap_or_default();
+    let partial = partial.unwrap_or(false);
+    let now = clock.now_stored();
+    let results = write_retry!("posts.delete_many", async {
+        let mut tx = tx_begin(&mut db).await?;
+        let mut results = Vec::with_capacity(body.len());
+        for id in body.iter() {
+            // post_delete runs in its own savepoint
+            let deleted = post_delete(&mut tx, user.id, id, children, now).await?;
+            results.push(match deleted {
+                true => BatchItem::ok(id, Status::Ok),
+                false if partial => BatchItem::error(id, post_missing(&mut tx, **access, user.id, id).await),
+                // Dropping the transaction rolls back the posts deleted so far
+                false => {
+                    let error = post_missing(&mut tx, **access, user.id, id).await;
+                    if error.status == Status::NotFound {
+                        return Err(ApiError::not_found(format!("Post {} not found", id)).into());
+                    }
+                    return Err(error.into());
+                }
+            });
+        }
+        tx_commit(tx).await?;
+        Ok::<_, WriteError>(results)
+    })?;
+
+    match partial {
+        true => Ok((Status::MultiStatus, json::json!({ "results": results }))),
+        false => Ok((Status::Ok, json::json!({ "message": "success" }))),
+    }
+}
+
+/// Most posts `fetch-many` takes at once.
+const FETCH_MANY_MAX: usize = 1000;
+
+/// A post a client has, with the `updatedAt` of its copy.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct FetchManyItem {
+    pub id: String,
+    /// Omitted when the client has no copy yet, fetching the post whatever its `updatedAt`.
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Reconciles a known set of posts: returns in `items` those whose `updatedAt` differs from the
+/// client's copy, in the order asked, and in `missing` the IDs of those the user doesn't have (any
+/// longer). A read, though a `POST` for the size of the body, see `request_reads`.
+#[post("/fetch-many", data = "<body>")]
+async fn fetch_many(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    body: Payload<Vec<FetchManyItem>>,
+) -> Result<(Status, json::Value), ApiError> {
+    if body.len() > FETCH_MANY_MAX {
+        return Err(ApiError::validation(format!(
+            "At most {} posts can be fetched at once",
+            FETCH_MANY_MAX
+        )));
+    }
+    let ids = body.iter().map(|item| item.id.as_str()).collect::<Vec<_>>();
+    let mut posts = Scope::from(&user)
+        .posts_read_many(&mut db, &ids)
         .await
-        .expect("Failed to fetch post");
+        .expect("Failed to fetch posts")
+        .into_iter()
+        .map(|post| (post.id.clone(), post))
+        .collect::<HashMap<_, _>>();
 
-    if let Some(post) = post {
-        (Status::Ok, json::json!(post))
-    } else {
-        (Status::NotFound, json::json!({ "error": "Post not found" }))
+    let mut items = Vec::new();
+    let mut missing = Vec::new();
+    let mut seen = HashSet::new();
+    for item in body.iter().filter(|item| seen.insert(item.id.as_str())) {
+        match posts.remove(&item.id) {
+            Some(post) if item.updated_at == Some(post.updated_at) => {}
+            Some(post) => items.push(post.content_decrypt()),
+            None => missing.push(item.id.clone()),
+        }
+    }
+
+    Ok((Status::Ok, json::json!({ "items": items, "missing": missing })))
+}
+
+/// Parses a `?fields=` selection, empty meaning every field.
+fn fields_param_parse(fields: Option<&str>) -> Result<Option<PostFields>, ApiError> {
+    fields
+        .filter(|fields| !fields.trim().is_empty())
+        .map(PostFields::parse)
+        .transpose()
+        .map_err(ApiError::validation)
+}
+
+/// Reads a post, with the link previews fetched so far of the links in it. With `?fields=`, only
+/// those fields are read and returned, without the previews.
+#[get("/<id>?<fields>")]
+async fn read(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    access: &State<ForeignPostPolicy>,
+    id: String,
+    fields: Option<String>,
+) -> Result<(Status, json::Value), ApiError> {
+    let fields = fields_param_parse(fields.as_deref())?;
+    let key = match &fields {
+        Some(fields) => format!("read/{}?fields={:?}", id, fields),
+        None => format!("read/{}", id),
+    };
+    if let Some(cached) = response_cache().get(user.id, "read", &key).await {
+        return Ok((Status::Ok, cached));
+    }
+    let scope = Scope::from(&user);
+    let post = match &fields {
+        Some(fields) => scope.post_read_fields(&mut db, &id, fields).await,
+        None => scope.post_read(&mut db, &id).await,
+    }
+    .expect("Failed to fetch post");
+
+    match (post, fields) {
+        (Some(post), Some(fields)) => {
+            let response = fields.pick(json::json!(post.content_decrypt()));
+            response_cache().insert(user.id, key, response.clone()).await;
+            Ok((Status::Ok, response))
+        }
+        (Some(post), None) => {
+            let response = posts_with_previews(&mut db, vec![post.content_decrypt()])
+                .await
+                .expect("Failed to fetch link previews")
+                .remove(0);
+            response_cache().insert(user.id, key, response.clone()).await;
+            Ok((Status::Ok, response))
+        }
+        (None, _) => Err(post_missing(&mut db, **access, user.id, &id).await),
     }
 }
 
@@ -191,59 +942,554 @@ async fn read(mut db: Connection<Db>, user: UserCtx, id: String) -> (Status, jso
 pub struct UpdateRequestBody {
     pub content: String,
     pub updated_at: Option<DateTime<Utc>>,
+    /// The client's clock stamp of the write, see `UpsertPostPayload::version`.
+    pub version: Option<Hlc>,
+    /// The version of the post the edit started from, the base of `?merge=true`.
+    pub base_version: Option<Hlc>,
+    /// Left unchanged when omitted.
+    pub position: Option<f64>,
 }
 
-#[put("/<id>", data = "<body>")]
+/// Merges an edit that lost to a newer version into it, diff3-style against the revision the edit
+/// started from. Returns the merged content and its stamp, or a 409 listing both versions when the
+/// edits overlap or the base revision is no longer kept.
+async fn post_merge(
+    db: &mut sqlx::SqliteConnection,
+    user: &UserCtx,
+    id: &str,
+    body: &UpdateRequestBody,
+    now: DateTime<Utc>,
+) -> Result<(String, WriteStamp), ApiError> {
+    let scope = Scope::from(user);
+    let current = scope
+        .post_read(&mut *db, id)
+        .await
+        .expect("Failed to fetch post")
+        .ok_or_else(|| ApiError::not_found("Post not found"))?
+        .content_decrypt();
+    let base = match &body.base_version {
+        Some(version) => scope
+            .post_revision(&mut *db, id, version)
+            .await
+            .expect("Failed to fetch post revision"),
+        None => None,
+    };
+    let merged = base.and_then(|base| {
+        let base = content_cipher()
+            .decrypt(&base)
+            .expect("Failed to decrypt post revision");
+        text_merge(&base, &current.content, &body.content)
+    });
+    let Some(merged) = merged else {
+        return Err(
+            ApiError::conflict("The post was edited concurrently and the edits overlap").details(json::json!({
+                "current": current,
+                "yours": { "content": body.content, "version": body.version },
+            })),
+        );
+    };
+
+    // The merge must win over the stored version, whichever clock stamped it
+    let version = match current.version.parse::<Hlc>() {
+        Ok(current) => hlc_clock().update(&current),
+        Err(_) => hlc_clock().now(),
+    };
+    let stamp = WriteStamp {
+        updated_at: now,
+        version,
+    };
+    Ok((merged, stamp))
+}
+
+/// Updates the content of a post, unless it has a newer version. With `?merge=true` and a
+/// `baseVersion`, an edit to an outdated version is merged into the newer one instead of refused
+/// when the two edits changed different lines; the response then carries the merged `content`.
+///
+/// With update coalescing on, other updates of the user's own posts are queued and written by the
+/// `UpdateCoalescer` a moment later.
+#[put("/<id>?<merge>", data = "<body>")]
+#[allow(clippy::too_many_arguments)]
 async fn update(
     mut db: Connection<Db>,
     user: UserCtx,
+    access: &State<ForeignPostPolicy>,
+    coalescer: &State<UpdateCoalescer>,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
     id: String,
-    body: json::Json<UpdateRequestBody>,
-) -> (Status, json::Value) {
-    let now = Utc::now().with_nanosecond(0).unwrap();
-    let updated_at = body.updated_at.unwrap_or_else(|| now).naive_utc();
-
-    let result = sqlx::query!(
-        "UPDATE posts SET content = ?, updated_at = ? WHERE id = ? AND user_id = ? AND updated_at < ?",
-        body.content,
-        updated_at,
-        id,
-        user.id,
+    merge: Option<bool>,
+    body: Payload<UpdateRequestBody>,
+) -> Result<(Status, json::Value), ApiError> {
+    content_size_check(&body.content)?;
+    // Only a schema constrains the content, so the variant is only looked up when there is one
+    let variant = match variants().checks_content() {
+        true => Scope::from(&user)
+            .post_variant(&mut db, &id)
+            .await
+            .expect("Failed to fetch post variant"),
+        false => None,
+    };
+    if let Some(variant) = &variant {
+        variants().check(variant, &body.content).map_err(ApiError::validation)?;
+    }
+    let now = clock.now_stored();
+    let updated_at = clock_config()
+        .accept(body.updated_at.unwrap_or(now), now)
+        .map_err(ApiError::validation)?;
+    let version = version_default(body.version.clone(), body.updated_at).unwrap_or_else(|| Hlc::from_wall(updated_at));
+    let stamp = WriteStamp {
         updated_at,
-    )
-    .execute(&mut **db)
-    .await
-    .expect("Failed to update post");
+        version: hlc_clock().accept(version, now).map_err(ApiError::validation)?,
+    };
+    let content = StoredContent::of(&body.content);
 
-    if result.rows_affected() == 0 {
-        return (
-            Status::NotFound,
-            json::json!({ "error": "Post not found or supplied update_at is less than existing" }),
-        );
+    if coalescer.enabled() && !merge.unwrap_or(false) {
+        let stored = Scope::from(&user)
+            .post_version(&mut db, &id)
+            .await
+            .expect("Failed to fetch post version");
+        // Checked here as the write would, so the client learns of a refused update right away
+        let queued = match stored {
+            Some(stored) if stored < stamp.version.to_string() => {
+                let update = PendingUpdate {
+                    content,
+                    position: body.position,
+                    stamp,
+                };
+                coalescer.queue(user.id, &id, update)
+            }
+            Some(_) => false,
+            None => return Err(post_missing(&mut db, **access, user.id, &id).await),
+        };
+        if !queued {
+            return Err(ApiError::not_found(
+                "Post not found or supplied version is less than existing",
+            ));
+        }
+        return Ok((Status::Ok, json::json!({ "message": "success" })));
+    }
+
+    let merged = write_retry!("posts.update", async {
+        let mut tx = tx_begin(&mut db).await?;
+        let updated = Scope::from(&user)
+            .post_update(&mut tx, &id, &content, body.position, &stamp)
+            .await?;
+
+        let merged = match updated {
+            true => None,
+            false if merge.unwrap_or(false) => {
+                post_access(&mut tx, **access, user.id, &id).await?;
+                let (merged, stamp) = post_merge(&mut tx, &user, &id, &body, now).await?;
+                content_size_check(&merged)?;
+                if let Some(variant) = &variant {
+                    variants().check(variant, &merged).map_err(PostWriteError::Invalid)?;
+                }
+                Scope::from(&user)
+                    .post_update(&mut tx, &id, &StoredContent::of(&merged), body.position, &stamp)
+                    .await?;
+                Some((merged, stamp.version))
+            }
+            false => {
+                post_access(&mut tx, **access, user.id, &id).await?;
+                return Err(ApiError::not_found("Post not found or supplied version is less than existing").into());
+            }
+        };
+        event_record(&mut tx, user.id, PostChangeKind::Upserted, Some(&id), now).await?;
+        tx_commit(tx).await?;
+        Ok::<_, WriteError>(merged)
+    })?;
+    events_wake();
+
+    match merged {
+        Some((content, version)) => Ok((
+            Status::Ok,
+            json::json!({ "message": "success", "content": content, "version": version }),
+        )),
+        None => Ok((Status::Ok, json::json!({ "message": "success" }))),
     }
+}
 
-    (Status::Ok, json::json!({ "message": "success" }))
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct MoveRequestBody {
+    /// The post to place this one right after.
+    pub after: Option<String>,
+    /// The post to place this one right before.
+    pub before: Option<String>,
 }
 
-#[delete("/<id>")]
-async fn delete(mut db: Connection<Db>, user: UserCtx, id: String) -> (Status, json::Value) {
-    let result = sqlx::query!("DELETE FROM posts WHERE id = ? AND user_id = ?", id, user.id)
-        .execute(&mut **db)
+async fn position_of(db: &mut sqlx::SqliteConnection, user_id: i64, id: &str) -> Result<f64, ApiError> {
+    Scope::user(user_id)
+        .post_position(db, id)
         .await
-        .expect("Failed to delete post");
+        .expect("Failed to fetch post position")
+        .ok_or_else(|| ApiError::validation(format!("Neighbor post {} not found", id)))?
+        .ok_or_else(|| ApiError::validation(format!("Neighbor post {} has no position", id)))
+}
+
+/// Returns a position between the neighbors, or `None` when floating point precision between them
+/// has run out.
+async fn position_between(
+    db: &mut sqlx::SqliteConnection,
+    user_id: i64,
+    body: &MoveRequestBody,
+) -> Result<Option<f64>, ApiError> {
+    let after = match &body.after {
+        Some(id) => Some(position_of(&mut *db, user_id, id).await?),
+        None => None,
+    };
+    let before = match &body.before {
+        Some(id) => Some(position_of(&mut *db, user_id, id).await?),
+        None => None,
+    };
+
+    let position = match (after, before) {
+        (Some(after), Some(before)) if after >= before => {
+            return Err(ApiError::validation("`after` must sort before `before`"));
+        }
+        (Some(after), Some(before)) => after + (before - after) / 2.0,
+        (Some(after), None) => after + 1.0,
+        (None, Some(before)) => before - 1.0,
+        (None, None) => Scope::user(user_id)
+            .posts_position_max(db)
+            .await
+            .expect("Failed to fetch post position")
+            .map_or(0.0, |max| max + 1.0),
+    };
+
+    let collides = Some(position) == after || Some(position) == before;
+    Ok((!collides).then_some(position))
+}
+
+/// Places a post between two others in the manual sort order (`?sort=manual`). The move counts as
+/// an edit, so it syncs to other clients like any other update. When positions get too close the
+/// user's positioned posts are first spread out again.
+#[post("/<id>/move", data = "<body>")]
+async fn move_post(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    access: &State<ForeignPostPolicy>,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    id: String,
+    body: Payload<MoveRequestBody>,
+) -> Result<(Status, json::Value), ApiError> {
+    let now = clock.now_stored();
+    let stamp = WriteStamp::at(now);
+    let position = write_retry!("posts.move", async {
+        let mut tx = tx_begin(&mut db).await?;
+        let position = match position_between(&mut tx, user.id, &body).await? {
+            Some(position) => position,
+            None => {
+                let renumbered = Scope::from(&user).posts_renumber(&mut tx, &stamp).await?;
+                for renumbered_id in renumbered {
+                    event_record(&mut tx, user.id, PostChangeKind::Upserted, Some(&renumbered_id), now).await?;
+                }
+
+                position_between(&mut tx, user.id, &body)
+                    .await?
+                    .ok_or_else(|| ApiError::conflict("The posts could not be renumbered, retry the move"))?
+            }
+        };
 
-    if result.rows_affected() == 0 {
-        return (Status::NotFound, json::json!({ "error": "Post not found" }));
+        let moved = Scope::from(&user)
+            .post_position_set(&mut tx, &id, position, &stamp)
+            .await?;
+        if !moved {
+            return Err(post_missing(&mut tx, **access, user.id, &id).await.into());
+        }
+        event_record(&mut tx, user.id, PostChangeKind::Upserted, Some(&id), now).await?;
+        tx_commit(tx).await?;
+        Ok::<_, WriteError>(position)
+    })?;
+    events_wake();
+
+    Ok((Status::Ok, json::json!({ "message": "success", "position": position })))
+}
+
+/// What happens to the children of a deleted post.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, FromFormField)]
+pub enum ChildrenOnDelete {
+    /// The children move up to the deleted post's parent.
+    #[default]
+    Reparent,
+    /// The children and all their descendants are deleted too.
+    Cascade,
+}
+
+/// Deletes a post at `now`, returning whether it existed. Shared by the REST and GraphQL APIs.
+pub async fn post_delete(
+    db: &mut sqlx::SqliteConnection,
+    user_id: i64,
+    id: &str,
+    children: ChildrenOnDelete,
+    now: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = tx_begin(db).await?;
+
+    let (reparented, deleted) = match children {
+        ChildrenOnDelete::Reparent => {
+            // Bump the version so that syncing clients pick up the move
+            let reparented = Scope::user(user_id)
+                .posts_reparent_children(&mut tx, id, &WriteStamp::at(now))
+                .await?;
+            (reparented, Vec::new())
+        }
+        ChildrenOnDelete::Cascade => {
+            // The foreign key deletes the descendants, but subscribers need their ids
+            let descendants = Scope::user(user_id).posts_descendants(&mut tx, id).await?;
+            (Vec::new(), descendants)
+        }
+    };
+
+    if !Scope::user(user_id).post_remove(&mut tx, id).await? {
+        return Ok(false);
+    }
+
+    for child in &reparented {
+        event_record(&mut tx, user_id, PostChangeKind::Upserted, Some(child), now).await?;
+    }
+    for descendant in &deleted {
+        event_record(&mut tx, user_id, PostChangeKind::Deleted, Some(descendant), now).await?;
+    }
+    event_record(&mut tx, user_id, PostChangeKind::Deleted, Some(id), now).await?;
+    tx_commit(tx).await?;
+    events_wake();
+    Ok(true)
+}
+
+/// Deletes a post. `?children=reparent` (the default) moves its children up to its parent,
+/// `?children=cascade` deletes them along with it.
+#[delete("/<id>?<children>")]
+async fn delete(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    access: &State<ForeignPostPolicy>,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    id: String,
+    children: Option<ChildrenOnDelete>,
+) -> Result<(Status, json::Value), ApiError> {
+    let children = children.unwrap_or_default();
+    let now = clock.now_stored();
+    let deleted = write_retry!("posts.delete", async {
+        post_delete(&mut db, user.id, &id, children, now).await
+    })
+    .map_err(write_error)?;
+
+    if !deleted {
+        return Err(post_missing(&mut db, **access, user.id, &id).await);
+    }
+
+    Ok((Status::Ok, json::json!({ "message": "success" })))
+}
+
+/// Reaction kind stored in `post_reactions` for starred posts.
+const REACTION_FAVORITE: &str = "favorite";
+
+/// Whether the post exists and belongs to the user.
+pub(crate) async fn post_owned(db: &mut sqlx::SqliteConnection, user_id: i64, id: &str) -> bool {
+    Scope::user(user_id)
+        .post_exists(db, id)
+        .await
+        .expect("Failed to fetch post")
+}
+
+/// How requests naming another user's post are answered, per `POST_ACCESS_POLICY`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForeignPostPolicy {
+    /// `hide`, the default: a 404 as for a missing post, so that other users' post IDs can't be
+    /// probed.
+    Hide,
+    /// `forbid`: a 403 with the `not_owner` code, for clients that tell the two cases apart.
+    Forbid,
+}
+
+/// The error of a request for a post the user doesn't have: a 404, unless the post is another
+/// user's and the policy forbids.
+pub(crate) async fn post_missing(
+    db: &mut sqlx::SqliteConnection,
+    policy: ForeignPostPolicy,
+    user_id: i64,
+    id: &str,
+) -> ApiError {
+    if policy == ForeignPostPolicy::Forbid {
+        let foreign = Scope::user(user_id)
+            .post_foreign(db, id)
+            .await
+            .expect("Failed to fetch post");
+        if foreign {
+            return ApiError::new(Status::Forbidden, ErrorCode::NotOwner, "Post belongs to another user");
+        }
+    }
+    ApiError::not_found("Post not found")
+}
+
+/// Checks that the post exists and belongs to the user, failing with `post_missing` otherwise.
+pub(crate) async fn post_access(
+    db: &mut sqlx::SqliteConnection,
+    policy: ForeignPostPolicy,
+    user_id: i64,
+    id: &str,
+) -> Result<(), ApiError> {
+    match post_owned(&mut *db, user_id, id).await {
+        true => Ok(()),
+        false => Err(post_missing(db, policy, user_id, id).await),
     }
+}
+
+/// Stars a post. Favoriting is per-user metadata, so it does not bump the post's `updated_at`.
+#[put("/<id>/favorite")]
+async fn favorite(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    access: &State<ForeignPostPolicy>,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    id: String,
+) -> Result<(Status, json::Value), ApiError> {
+    post_access(&mut db, **access, user.id, &id).await?;
+
+    let now = clock.now();
+    write_retry!("posts.favorite", async {
+        let mut tx = tx_begin(&mut db).await?;
+        Scope::from(&user)
+            .post_reaction_add(&mut tx, &id, REACTION_FAVORITE, now)
+            .await?;
+        event_record(&mut tx, user.id, PostChangeKind::Upserted, Some(&id), now).await?;
+        tx_commit(tx).await
+    })
+    .map_err(write_error)?;
+    events_wake();
+
+    Ok((Status::Ok, json::json!({ "message": "success" })))
+}
+
+#[delete("/<id>/favorite")]
+async fn unfavorite(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    access: &State<ForeignPostPolicy>,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    id: String,
+) -> Result<(Status, json::Value), ApiError> {
+    post_access(&mut db, **access, user.id, &id).await?;
+
+    write_retry!("posts.unfavorite", async {
+        let mut tx = tx_begin(&mut db).await?;
+        Scope::from(&user)
+            .post_reaction_remove(&mut tx, &id, REACTION_FAVORITE)
+            .await?;
+        event_record(&mut tx, user.id, PostChangeKind::Upserted, Some(&id), clock.now()).await?;
+        tx_commit(tx).await
+    })
+    .map_err(write_error)?;
+    events_wake();
+
+    Ok((Status::Ok, json::json!({ "message": "success" })))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SnoozeRequestBody {
+    /// Remind again at this time.
+    pub until: Option<DateTime<Utc>>,
+    /// Or this many minutes from now.
+    pub minutes: Option<i64>,
+}
+
+/// Sets or clears a post's reminder. Like a move, it counts as an edit, so it syncs to other
+/// clients and wins over their older writes.
+async fn remind_at_set(
+    db: &mut sqlx::SqliteConnection,
+    user: &UserCtx,
+    access: ForeignPostPolicy,
+    id: &str,
+    remind_at: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Result<(), ApiError> {
+    write_retry!("posts.remind_at", async {
+        let mut tx = tx_begin(&mut *db).await?;
+        let updated = Scope::from(user)
+            .post_remind_at_set(&mut tx, id, remind_at, &WriteStamp::at(now))
+            .await?;
+        if !updated {
+            return Err(post_missing(&mut tx, access, user.id, id).await.into());
+        }
+        event_record(&mut tx, user.id, PostChangeKind::Upserted, Some(id), now).await?;
+        tx_commit(tx).await?;
+        Ok::<_, WriteError>(())
+    })?;
+    events_wake();
+    Ok(())
+}
+
+/// Puts a reminder off, to `until` or for `minutes`. The post is delivered again once that time
+/// comes.
+#[post("/<id>/reminder/snooze", data = "<body>")]
+async fn reminder_snooze(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    access: &State<ForeignPostPolicy>,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    id: String,
+    body: Payload<SnoozeRequestBody>,
+) -> Result<(Status, json::Value), ApiError> {
+    let now = clock.now_stored();
+    let remind_at = match (body.until, body.minutes) {
+        (Some(until), None) if until > now => until,
+        (None, Some(minutes)) if (1..=525_600).contains(&minutes) => now + chrono::TimeDelta::minutes(minutes),
+        (Some(_), None) => return Err(ApiError::validation("`until` must be in the future")),
+        (None, Some(_)) => return Err(ApiError::validation("`minutes` must be between 1 and 525600")),
+        _ => return Err(ApiError::validation("Exactly one of `until` and `minutes` is required")),
+    };
+    remind_at_set(&mut db, &user, **access, &id, Some(remind_at), now).await?;
+
+    Ok((Status::Ok, json::json!({ "message": "success", "remindAt": remind_at })))
+}
+
+/// Dismisses a post's reminder, delivered or not.
+#[delete("/<id>/reminder")]
+async fn reminder_dismiss(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    access: &State<ForeignPostPolicy>,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    id: String,
+) -> Result<(Status, json::Value), ApiError> {
+    remind_at_set(&mut db, &user, **access, &id, None, clock.now_stored()).await?;
 
-    (Status::Ok, json::json!({ "message": "success" }))
+    Ok((Status::Ok, json::json!({ "message": "success" })))
 }
 
 pub fn stage() -> AdHoc {
     AdHoc::on_ignite("Posts stage", |rocket| async {
-        rocket.mount(
-            "/api/posts",
-            routes![list, create, upsert_many, delete_all, read, update, delete],
+        let rocket = manage_default(rocket, |rocket| app_config_managed(rocket).auth.post_access_policy);
+        api_mount(
+            rocket,
+            "/posts",
+            timeout_routes(
+                "/posts",
+                routes![
+                    list,
+                    create,
+                    upsert_many,
+                    delete_all,
+                    delete_many,
+                    fetch_many,
+                    read,
+                    update,
+                    delete,
+                    favorite,
+                    unfavorite,
+                    move_post,
+                    reminder_snooze,
+                    reminder_dismiss
+                ],
+            ),
         )
     })
 }
@@ -2,52 +2,136 @@ use chrono::{Duration, Utc};
 use rocket::fairing::AdHoc;
 use rocket::http::{CookieJar, Status};
 use rocket::serde::{Deserialize, json};
+use rocket::{Request, State};
+use tracing::{Instrument, info};
 
+use crate::api::api_mount;
+use crate::authz::{SessionRecord, session_record};
+use crate::challenge::*;
+use crate::client_info::*;
+use crate::clock::Timekeeper;
+use crate::csrf::*;
 use crate::db::*;
+use crate::email::{EmailHealth, EmailStatus, EmailTemplate, Mailer};
+use crate::error::{ApiError, ErrorCode};
+use crate::impersonation::*;
+use crate::jwt::{TokenActor, TokenClaims, token_signer, token_ttl};
+use crate::otp::{OtpChannel, OtpChannelKind};
+use crate::retry::{write_error, write_retry};
+use crate::sms::Sms;
+use crate::timeout::timeout_routes;
 use crate::util::*;
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 #[serde(crate = "rocket::serde")]
-struct SendCodeRequestBody<'r> {
+pub(crate) struct SendCodeRequestBody<'r> {
     email: &'r str,
+    /// Token from the CAPTCHA widget, required when a challenge provider is configured.
+    captcha_token: Option<&'r str>,
+    /// Honeypot: a form field hidden from humans. Bots that fill it in are silently ignored.
+    website: Option<&'r str>,
 }
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 #[serde(crate = "rocket::serde")]
-struct LoginRequestBody<'r> {
+pub(crate) struct LoginRequestBody<'r> {
     code: &'r str,
     email: &'r str,
+    /// Persist the session for weeks instead of a day.
+    #[serde(default)]
+    remember_me: bool,
 }
 
+/// The current user and session, enough for clients to render an account header from. `session`
+/// is `null` for token requests, and `impersonation` is set while support staff is acting as the
+/// user.
 #[get("/")]
-fn index(user: UserCtx) -> json::Value {
-    json::json!(user)
-}
-
-#[post("/login", data = "<body>")]
-async fn login(
+async fn index(
     jar: &CookieJar<'_>,
     mut db: Connection<Db>,
-    body: json::Json<LoginRequestBody<'_>>,
-) -> (Status, json::Value) {
-    let unauthorized = (
-        Status::Unauthorized,
-        json::json!({ "message": "invalid email or password" }),
-    );
+    user: UserCtx,
+    impersonation: Option<Impersonation>,
+) -> Result<(Status, json::Value), ApiError> {
+    let profile = sqlx::query!(
+        "SELECT email, email_verified_at AS \"email_verified_at: DateTime<Utc>\", otp_channel FROM users WHERE id = ?",
+        user.id
+    )
+    .fetch_optional(&mut **db)
+    .instrument(query_span("users.session_profile"))
+    .await
+    .expect("Failed to fetch user")
+    .ok_or_else(|| ApiError::unauthorized("user not found"))?;
 
-    if !code_is_valid(body.code) {
+    let session_id = match &impersonation {
+        Some(impersonation) => Some(impersonation.session_id.clone()),
+        None => jar.get_private(SESSION_COOKIE).map(|c| c.value().to_owned()),
+    };
+    let session = match session_id {
+        Some(session_id) => sqlx::query_as!(
+            Session,
+            "SELECT id, user_id, created_at AS \"created_at: DateTime<Utc>\", \
+            expires_at AS \"expires_at: DateTime<Utc>\", revoked_at AS \"revoked_at: DateTime<Utc>\", ip, \
+            user_agent, location, auth_method FROM sessions WHERE id = ? AND user_id = ? AND revoked_at IS NULL",
+            session_id,
+            user.id
+        )
+        .fetch_optional(&mut **db)
+        .instrument(query_span("sessions.current"))
+        .await
+        .expect("Failed to fetch session"),
+        None => None,
+    };
+    let session = session.map(|session| {
+        let mut session = json::json!(session);
+        // There is no second factor yet: codes are the only one, whatever the channel
+        session["twoFactorEnabled"] = json::json!(false);
+        session
+    });
+
+    let mut body = json::json!({
+        "id": user.id,
+        "email": profile.email,
+        "emailVerifiedAt": profile.email_verified_at,
+        "otpChannel": profile.otp_channel,
+        "session": session,
+    });
+    if let Some(impersonation) = impersonation {
+        body["impersonation"] = json::json!(impersonation);
+    }
+    Ok((Status::Ok, body))
+}
+
+/// Checks a login code sent by `send-code` to `email`, counting failed attempts, and consumes it.
+/// Receiving the code proves ownership of the email address, so the account's email is marked
+/// verified. Returns the ID of the account. Shared by login and account merging.
+pub(crate) async fn code_consume(
+    db: &mut sqlx::SqliteConnection,
+    email: &str,
+    code: &str,
+    now: DateTime<Utc>,
+) -> Result<i64, ApiError> {
+    let unauthorized = Err(ApiError::unauthorized("invalid email or password"));
+
+    if !code_is_valid(code) {
         info!("login:code-invalid");
         return unauthorized;
     }
 
-    if !email_is_valid(body.email) {
+    if !email_is_valid(email) {
         info!("login:email-invalid");
         return unauthorized;
     }
 
-    let user = sqlx::query!("SELECT * FROM users WHERE email = ?", body.email)
-        .fetch_one(&mut **db)
-        .await;
+    let user = sqlx::query!(
+        "SELECT id, email, code_hash, code_attempts, code_created_at AS \"code_created_at: DateTime<Utc>\", \
+        disabled_at FROM users WHERE email = ?",
+        email
+    )
+    .fetch_one(&mut *db)
+    .instrument(query_span("users.by_email"))
+    .await;
 
     let user = match user {
         Ok(user) => user,
@@ -67,128 +151,671 @@ async fn login(
         return unauthorized;
     }
 
-    let code_created_at = user
-        .code_created_at
-        .expect("code_created_at is unexpectedly NULL")
-        .to_datetime();
-    let ten_minutes_ago = Utc::now() - Duration::minutes(10);
+    let code_created_at = user.code_created_at.expect("code_created_at is unexpectedly NULL");
+    let ten_minutes_ago = now - Duration::minutes(10);
     if code_created_at < ten_minutes_ago {
         info!("login:expired:{}", user.id);
         return unauthorized;
     }
 
-    let code_verified = hash_code_verify(user.code_hash.as_deref().expect("unreachable"), body.code)
-        .await
-        .unwrap_or(false);
+    let verification = match hash_code_verify_rehash(user.code_hash.as_deref().expect("unreachable"), code).await {
+        Ok(verification) => verification,
+        // Don't count a shed request as a failed attempt
+        Err(HashError::Saturated) => return Err(hash_saturated()),
+        Err(HashError::Failed(_)) => HashVerification {
+            verified: false,
+            rehash: None,
+        },
+    };
 
-    if !code_verified {
+    if !verification.verified {
         let new_attempts = user.code_attempts.unwrap_or(0) + 1;
-        sqlx::query!("UPDATE users SET code_attempts = ? WHERE id = ?", new_attempts, user.id)
-            .execute(&mut **db)
-            .await
-            .expect("Failed to increment code attempts");
+        write_retry!("users.code_attempts_increment", async {
+            sqlx::query!("UPDATE users SET code_attempts = ? WHERE id = ?", new_attempts, user.id)
+                .execute(&mut *db)
+                .instrument(query_span("users.code_attempts_increment"))
+                .await
+        })
+        .map_err(write_error)?;
         info!("login:bad-code:{}", user.id);
         return unauthorized;
     }
 
+    // The code stays pending when the login is refused below, so keep its hash current
+    if let Some(rehash) = verification.rehash {
+        write_retry!("users.code_rehash", async {
+            sqlx::query!("UPDATE users SET code_hash = ? WHERE id = ?", rehash, user.id)
+                .execute(&mut *db)
+                .instrument(query_span("users.code_rehash"))
+                .await
+        })
+        .map_err(write_error)?;
+    }
+
+    // Only reveal the suspension to whoever proved they own the email address
+    if user.disabled_at.is_some() {
+        info!("login:disabled:{}", user.id);
+        return Err(ApiError::account_disabled());
+    }
+
     // clear the code_hash on the user
-    sqlx::query!(
-        "UPDATE users SET code_attempts = NULL, code_created_at = NULL, code_hash = NULL WHERE id = ?",
-        user.id
+    write_retry!("users.code_clear", async {
+        sqlx::query!(
+            "UPDATE users SET code_attempts = NULL, code_created_at = NULL, code_hash = NULL, \
+            email_verified_at = COALESCE(email_verified_at, ?) WHERE id = ?",
+            now,
+            user.id
+        )
+        .execute(&mut *db)
+        .instrument(query_span("users.code_clear"))
+        .await
+    })
+    .map_err(write_error)?;
+
+    Ok(user.id)
+}
+
+#[post("/login", data = "<body>")]
+#[allow(clippy::too_many_arguments)]
+async fn login(
+    jar: &CookieJar<'_>,
+    mut db: Connection<Db>,
+    pool: &Db,
+    mailer: &State<Mailer>,
+    clock: &State<Timekeeper>,
+    ids: &State<IdSource>,
+    meta: RequestMeta,
+    body: json::Json<LoginRequestBody<'_>>,
+) -> Result<(Status, json::Value), ApiError> {
+    let now = clock.now();
+    let user_id = code_consume(&mut db, body.email, body.code, now).await?;
+    let code_channel = sqlx::query_scalar!("SELECT code_channel FROM users WHERE id = ?", user_id)
+        .fetch_one(&mut **db)
+        .instrument(query_span("users.code_channel"))
+        .await
+        .expect("Failed to fetch code channel");
+    let auth_method = format!("{}_code", code_channel);
+
+    let seen = sqlx::query!(
+        r#"SELECT COUNT(*) AS "total!: i64", COALESCE(SUM(ip IS ? AND user_agent IS ?), 0) AS "matching!: i64"
+        FROM sessions WHERE user_id = ?"#,
+        meta.ip,
+        meta.user_agent,
+        user_id
     )
-    .execute(&mut **db)
+    .fetch_one(&mut **db)
+    .instrument(query_span("sessions.seen_count"))
     .await
-    .expect("Failed to clear user code");
+    .expect("Failed to count sessions");
+
+    let session_id = ids.id();
+    let expires_at = now + Duration::seconds(session_max_age(body.remember_me).whole_seconds());
+    let location = meta.ip.as_deref().and_then(ip_locate);
+    write_retry!("sessions.insert", async {
+        sqlx::query!(
+            "INSERT INTO sessions (id, user_id, created_at, expires_at, ip, user_agent, location, auth_method, \
+            remembered) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            session_id,
+            user_id,
+            now,
+            expires_at,
+            meta.ip,
+            meta.user_agent,
+            location,
+            auth_method,
+            body.remember_me,
+        )
+        .execute(&mut **db)
+        .instrument(query_span("sessions.insert"))
+        .await
+    })
+    .map_err(write_error)?;
+
+    // Let the user know about sign-ins from clients we haven't seen on their account before. The very
+    // first session of an account is the signup itself, so it doesn't count.
+    if seen.total > 0 && seen.matching == 0 {
+        info!("login:new-device:{}", user_id);
+        rocket::tokio::spawn(new_device_notify(
+            (**pool).clone(),
+            mailer.inner().clone(),
+            body.email.to_string(),
+            now,
+            meta,
+            location,
+        ));
+    }
+
+    // Signing in ends any impersonation made from this browser
+    jar.remove_private(IMPERSONATION_COOKIE);
+    jar.add_private(auth_cookie_with_lifetime(user_id, body.remember_me));
+    jar.add_private(session_cookie(session_id, body.remember_me));
+    let csrf_token = csrf_token_gen();
+    jar.add(csrf_cookie(csrf_token.clone(), session_max_age(body.remember_me)));
 
-    jar.add_private(auth_cookie(user.id));
+    Ok((
+        Status::Ok,
+        json::json!({ "message": "success", "csrfToken": csrf_token }),
+    ))
+}
 
-    (Status::Ok, json::json!({ "message": "success" }))
+/// Emails the user about a sign-in from a client not seen on their account before.
+async fn new_device_notify(
+    pool: sqlx::SqlitePool,
+    mailer: Mailer,
+    email: String,
+    at: DateTime<Utc>,
+    meta: RequestMeta,
+    location: Option<String>,
+) {
+    let device = meta.user_agent.as_deref().map(user_agent_parse);
+    let device = match device {
+        Some(UserAgentInfo {
+            browser: Some(browser),
+            os: Some(os),
+            ..
+        }) => format!("{} on {}", browser, os),
+        _ => meta.user_agent.clone().unwrap_or_else(|| "unknown".into()),
+    };
+    let body = format!(
+        "A new sign-in to your account was detected.\r\n\r\n\
+        Time: {}\r\n\
+        IP address: {}\r\n\
+        Approximate location: {}\r\n\
+        Device: {}\r\n\r\n\
+        If this wasn't you, review and revoke your sessions at {}/account/sessions",
+        at.to_rfc3339(),
+        meta.ip.as_deref().unwrap_or("unknown"),
+        location.as_deref().unwrap_or("unknown"),
+        device,
+        app_url(),
+    );
+    let mut db = match pool.acquire().await {
+        Ok(db) => db,
+        Err(e) => {
+            tracing::error!("login:new-device-notify-failed: {}", e);
+            return;
+        }
+    };
+    mailer.send(&mut db, EmailTemplate::NewDevice, &email, "", &body).await;
 }
 
 #[post("/logout")]
-fn logout(jar: &CookieJar<'_>) -> (Status, json::Value) {
+async fn logout(
+    jar: &CookieJar<'_>,
+    mut db: Connection<Db>,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+) -> Result<(Status, json::Value), ApiError> {
+    if let Some(session) = jar.get_private(SESSION_COOKIE) {
+        let (now, session_id) = (clock.now(), session.value());
+        write_retry!("sessions.revoke_current", async {
+            sqlx::query!(
+                "UPDATE sessions SET revoked_at = ? WHERE id = ? AND revoked_at IS NULL",
+                now,
+                session_id
+            )
+            .execute(&mut **db)
+            .instrument(query_span("sessions.revoke_current"))
+            .await
+        })
+        .map_err(write_error)?;
+    }
+
     jar.remove_private("user_id");
-    (Status::Ok, json::json!({ "message": "success" }))
+    jar.remove_private(SESSION_COOKIE);
+    jar.remove_private(IMPERSONATION_COOKIE);
+    jar.remove(CSRF_COOKIE);
+    Ok((Status::Ok, json::json!({ "message": "success" })))
+}
+
+/// Extends an active session of the user to a full lifetime from `now`, with `due_only` only when it
+/// is in the second half of its lifetime, see `session_sliding`. Impersonations keep their time
+/// limit. Returns whether the session is remembered when it was extended.
+pub(crate) async fn session_extend(
+    db: &mut sqlx::SqliteConnection,
+    session_id: &str,
+    user_id: i64,
+    now: DateTime<Utc>,
+    due_only: bool,
+) -> Result<Option<bool>, sqlx::Error> {
+    let lifetime = |remembered| Duration::seconds(session_max_age(remembered).whole_seconds());
+    let (short, long) = (lifetime(false), lifetime(true));
+    let (due_short, due_long) = match due_only {
+        true => (now + short / 2, now + long / 2),
+        false => (now + short * 2, now + long * 2),
+    };
+    let (expires_short, expires_long) = (now + short, now + long);
+    sqlx::query_scalar!(
+        "UPDATE sessions SET expires_at = CASE WHEN remembered THEN ? ELSE ? END \
+        WHERE id = ? AND user_id = ? AND revoked_at IS NULL AND impersonator IS NULL AND expires_at > ? \
+        AND expires_at < CASE WHEN remembered THEN ? ELSE ? END RETURNING remembered AS \"remembered: bool\"",
+        expires_long,
+        expires_short,
+        session_id,
+        user_id,
+        now,
+        due_long,
+        due_short
+    )
+    .fetch_optional(db)
+    .instrument(query_span("sessions.extend"))
+    .await
+}
+
+/// Reissues the auth, session and CSRF cookies of an extended session with a full lifetime.
+fn session_cookies_renew(jar: &CookieJar<'_>, user_id: i64, session_id: String, remembered: bool) {
+    jar.add_private(auth_cookie_with_lifetime(user_id, remembered));
+    jar.add_private(session_cookie(session_id, remembered));
+    if let Some(token) = jar.get(CSRF_COOKIE).map(|c| c.value().to_owned()) {
+        jar.add(csrf_cookie(token, session_max_age(remembered)));
+    }
+}
+
+/// Extends the current session to a full lifetime, for clients that would rather not rely on
+/// `session_sliding`. Fails with 401 without an active session, e.g. while impersonating.
+#[post("/refresh")]
+async fn refresh(
+    jar: &CookieJar<'_>,
+    mut db: Connection<Db>,
+    user: UserCtx,
+    session: Option<SessionRecord>,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+) -> Result<(Status, json::Value), ApiError> {
+    let unauthorized = || ApiError::unauthorized("No active session to refresh");
+    let session_id = session
+        .filter(|session| session.user_id == user.id)
+        .map(|session| session.id)
+        .ok_or_else(unauthorized)?;
+    let now = clock.now();
+    let remembered = write_retry!("sessions.extend", async {
+        session_extend(&mut db, &session_id, user.id, now, false).await
+    })
+    .map_err(write_error)?
+    .ok_or_else(unauthorized)?;
+    session_cookies_renew(jar, user.id, session_id, remembered);
+    let expires_at = now + Duration::seconds(session_max_age(remembered).whole_seconds());
+    Ok((Status::Ok, json::json!({ "expiresAt": expires_at })))
+}
+
+/// Issues a short-lived JWT of the current user for sibling services, which verify it offline
+/// against `/.well-known/jwks.json`. Tokens issued while impersonating name the staff member in
+/// `act`.
+#[post("/token")]
+async fn token(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    impersonation: Option<Impersonation>,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+) -> Result<(Status, json::Value), ApiError> {
+    let email = sqlx::query_scalar!("SELECT email FROM users WHERE id = ?", user.id)
+        .fetch_optional(&mut **db)
+        .instrument(query_span("users.token_email"))
+        .await
+        .expect("Failed to fetch user")
+        .ok_or_else(|| ApiError::unauthorized("user not found"))?;
+    let now = clock.now();
+    let expires_at = now + Duration::seconds(token_ttl());
+    let claims = TokenClaims {
+        iss: app_url().to_string(),
+        sub: user.id.to_string(),
+        email,
+        iat: now.timestamp(),
+        exp: expires_at.timestamp(),
+        act: impersonation.map(|i| TokenActor { sub: i.impersonator }),
+    };
+    let token = token_signer().sign(&claims);
+    info!("session:token:{}", user.id);
+    Ok((Status::Ok, json::json!({ "token": token, "expiresAt": expires_at })))
+}
+
+/// Marks a request whose session `session_slide` already considered.
+struct SessionSlid;
+
+/// Extends the session of a request signed in as the user once it is in the second half of its
+/// lifetime, see `session_sliding`. Run by the `UserCtx` guard, at most once per request, so that
+/// sessions only slide with requests they authenticate and only write when they are due.
+/// Impersonations keep their time limit.
+pub(crate) async fn session_slide(request: &Request<'_>, user_id: i64) {
+    request
+        .local_cache_async(async {
+            let session = match session_record(request).await {
+                Ok(Some(session)) if session.user_id == user_id && session.impersonator.is_none() => session,
+                _ => return SessionSlid,
+            };
+            let now = match request.rocket().state::<Timekeeper>() {
+                Some(clock) => clock.now(),
+                None => Utc::now(),
+            };
+            let lifetime = Duration::seconds(session_max_age(session.remembered).whole_seconds());
+            if session.expires_at - now >= lifetime / 2 {
+                return SessionSlid;
+            }
+            let Some(db) = Db::fetch(request.rocket()) else {
+                return SessionSlid;
+            };
+            let Ok(mut db) = db.acquire().await else {
+                return SessionSlid;
+            };
+            let session_id = session.id.clone();
+            match write_retry!("sessions.slide", async {
+                session_extend(&mut db, &session_id, user_id, now, true).await
+            }) {
+                Ok(Some(remembered)) => session_cookies_renew(request.cookies(), user_id, session_id, remembered),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("session:slide-failed: {}", e),
+            }
+            SessionSlid
+        })
+        .await;
+}
+
+/// Ends the impersonation the caller is in, expired or not, and signs them out of the user's
+/// account.
+#[post("/stop-impersonating")]
+async fn stop_impersonating(
+    jar: &CookieJar<'_>,
+    mut db: Connection<Db>,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+) -> Result<(Status, json::Value), ApiError> {
+    let Some(impersonation) = jar.get_private(IMPERSONATION_COOKIE) else {
+        return Err(ApiError::conflict("Not impersonating a user"));
+    };
+    let (now, session_id) = (clock.now(), impersonation.value());
+    write_retry!("sessions.impersonation_stop", async {
+        sqlx::query!(
+            "UPDATE sessions SET revoked_at = ? WHERE id = ? AND impersonator IS NOT NULL AND revoked_at IS NULL",
+            now,
+            session_id
+        )
+        .execute(&mut **db)
+        .instrument(query_span("sessions.impersonation_stop"))
+        .await
+    })
+    .map_err(write_error)?;
+    info!("impersonation:stop:{}", impersonation.value());
+
+    jar.remove_private("user_id");
+    jar.remove_private(SESSION_COOKIE);
+    jar.remove_private(IMPERSONATION_COOKIE);
+    jar.remove(CSRF_COOKIE);
+    Ok((Status::Ok, json::json!({ "message": "success" })))
+}
+
+/// Lists the active sessions of the current user, flagging the one making the request.
+#[get("/sessions")]
+async fn sessions_list(
+    jar: &CookieJar<'_>,
+    mut db: Connection<Db>,
+    user: UserCtx,
+    clock: &State<Timekeeper>,
+) -> (Status, json::Value) {
+    let now = clock.now();
+    let sessions = sqlx::query_as!(
+        Session,
+        "SELECT id, user_id, created_at AS \"created_at: DateTime<Utc>\", expires_at AS \"expires_at: DateTime<Utc>\", \
+        revoked_at AS \"revoked_at: DateTime<Utc>\", ip, user_agent, location, auth_method FROM sessions \
+        WHERE user_id = ? AND revoked_at IS NULL AND expires_at > ? ORDER BY created_at DESC",
+        user.id,
+        now
+    )
+    .fetch_all(&mut **db)
+    .instrument(query_span("sessions.list"))
+    .await
+    .expect("Failed to fetch sessions");
+
+    let current = jar.get_private(SESSION_COOKIE).map(|c| c.value().to_owned());
+    let items = sessions
+        .into_iter()
+        .map(|session| {
+            let is_current = current.as_deref() == Some(session.id.as_str());
+            let client = session.user_agent.as_deref().map(user_agent_parse);
+            let mut item = json::json!(session);
+            item["current"] = json::json!(is_current);
+            item["client"] = json::json!(client);
+            item
+        })
+        .collect::<Vec<_>>();
+
+    (Status::Ok, json::json!({ "items": items }))
+}
+
+/// Revokes one of the current user's sessions.
+#[delete("/sessions/<id>")]
+async fn sessions_revoke(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    id: &str,
+) -> Result<(Status, json::Value), ApiError> {
+    let now = clock.now();
+    let result = write_retry!("sessions.revoke", async {
+        sqlx::query!(
+            "UPDATE sessions SET revoked_at = ? WHERE id = ? AND user_id = ? AND revoked_at IS NULL",
+            now,
+            id,
+            user.id
+        )
+        .execute(&mut **db)
+        .instrument(query_span("sessions.revoke"))
+        .await
+    })
+    .map_err(write_error)?;
+
+    if result.rows_affected() == 0 {
+        return Err(ApiError::not_found("Session not found"));
+    }
+
+    Ok((Status::Ok, json::json!({ "message": "success" })))
+}
+
+/// Response for requests shed because hashing capacity is saturated.
+pub(crate) fn hash_saturated() -> ApiError {
+    ApiError::new(
+        Status::ServiceUnavailable,
+        ErrorCode::Unavailable,
+        "Server is busy, try again shortly.",
+    )
+    .retry_after(hash_retry_after_secs())
 }
 
 #[post("/send-code", data = "<body>")]
-async fn send_code(mut db: Connection<Db>, body: json::Json<SendCodeRequestBody<'_>>) -> (Status, json::Value) {
+#[allow(clippy::too_many_arguments)]
+async fn send_code(
+    mut db: Connection<Db>,
+    challenge: &State<ChallengeGate>,
+    mailer: &State<Mailer>,
+    sms: &State<Sms>,
+    email_status: &State<EmailStatus>,
+    clock: &State<Timekeeper>,
+    meta: RequestMeta,
+    body: json::Json<SendCodeRequestBody<'_>>,
+) -> Result<(Status, json::Value), ApiError> {
+    if body.website.is_some_and(|website| !website.is_empty()) {
+        info!("send-code:honeypot");
+        return Ok((Status::Ok, json::json!({ "message": "success" })));
+    }
+
     if !email_is_valid(body.email) {
-        return (Status::Unauthorized, json::json!({ "message": "invalid email" }));
+        return Err(ApiError::unauthorized("invalid email"));
     }
 
-    let code: String = (0..8)
-        .map(|_| rand::random::<u8>() % 10)
-        .map(|digit| digit.to_string())
-        .collect();
+    // Rather than answering success for a code that will never arrive
+    if let EmailHealth::Broken(reason) = email_status.get() {
+        tracing::warn!("send-code:email-broken: {}", reason);
+        return Err(ApiError::new(
+            Status::ServiceUnavailable,
+            ErrorCode::EmailUnavailable,
+            "Login codes can't be emailed right now, try again later",
+        ));
+    }
+
+    if let Some(verifier) = &challenge.0 {
+        let Some(token) = body.captcha_token.filter(|token| !token.is_empty()) else {
+            return Err(ApiError::new(
+                Status::UnprocessableEntity,
+                ErrorCode::CaptchaRequired,
+                "captchaToken is required",
+            ));
+        };
+        match verifier.verify(token, meta.ip.as_deref()).await {
+            Ok(true) => {}
+            Ok(false) => {
+                info!("send-code:captcha-rejected");
+                return Err(ApiError::new(
+                    Status::Forbidden,
+                    ErrorCode::CaptchaFailed,
+                    "captcha verification failed",
+                ));
+            }
+            Err(e) => {
+                tracing::warn!("send-code:captcha-unavailable: {}", e);
+                return Err(ApiError::new(
+                    Status::ServiceUnavailable,
+                    ErrorCode::Unavailable,
+                    "captcha verification is unavailable, try again shortly",
+                )
+                .retry_after(5));
+            }
+        }
+    }
+
+    let code = code_gen();
 
     let code_hash = match hash_code(&code).await {
         Ok(hash) => hash,
-        Err(e) => {
-            return (Status::InternalServerError, json::json!({ "error": e }));
+        Err(HashError::Saturated) => return Err(hash_saturated()),
+        Err(HashError::Failed(e)) => {
+            tracing::error!("send-code:hash-failed: {}", e);
+            return Err(ApiError::internal());
         }
     };
 
-    let user_partial = sqlx::query!("SELECT id, code_created_at FROM users WHERE email = ?", body.email)
-        .fetch_one(&mut **db)
-        .await;
+    let user_partial = sqlx::query!(
+        "SELECT id, code_created_at AS \"code_created_at: DateTime<Utc>\", disabled_at, otp_channel, \
+        CASE WHEN phone_verified_at IS NOT NULL THEN phone END AS phone FROM users WHERE email = ?",
+        body.email
+    )
+    .fetch_one(&mut **db)
+    .instrument(query_span("users.code_state_by_email"))
+    .await;
+    let now = clock.now();
 
+    // Texted instead of emailed to users who asked for it
+    let mut phone = None;
     match user_partial {
         Ok(record) => {
+            // Answer as if a code was sent so that disabled accounts can't be told apart
+            if record.disabled_at.is_some() {
+                info!("send-code:disabled:{}", record.id);
+                return Ok((Status::Ok, json::json!({ "message": "success" })));
+            }
+
             if let Some(code_created_at) = record.code_created_at {
-                let code_created_at = code_created_at.to_datetime();
-                let two_minutes_ago: chrono::DateTime<Utc> = Utc::now() - Duration::minutes(2);
+                let two_minutes_ago = now - Duration::minutes(2);
                 if code_created_at > two_minutes_ago {
-                    return (
+                    info!("send-code:rate-limited:{}", meta.ip.as_deref().unwrap_or("unknown"));
+                    return Err(ApiError::new(
                         Status::TooManyRequests,
-                        json::json!({ "message": "Wait 2 minutes after requesting a code to try again." }),
-                    );
+                        ErrorCode::RateLimited,
+                        "Wait 2 minutes after requesting a code to try again.",
+                    ));
                 }
             }
 
-            let now = NaiveDateTime::now();
-            sqlx::query!(
-                "UPDATE users SET code_attempts = 0, code_created_at = ?, code_hash = ? WHERE id = ?",
-                now,
-                code_hash,
-                record.id
-            )
-            .execute(&mut **db)
-            .await
-            .expect("Failed to update user code");
+            if OtpChannelKind::parse(&record.otp_channel) == Some(OtpChannelKind::Sms) && sms.0.is_some() {
+                phone = record.phone;
+            }
+            let channel = match phone {
+                Some(_) => OtpChannelKind::Sms,
+                None => OtpChannelKind::Email,
+            }
+            .as_str();
+            write_retry!("users.code_set", async {
+                sqlx::query!(
+                    "UPDATE users SET code_attempts = 0, code_created_at = ?, code_hash = ?, code_channel = ? WHERE id = ?",
+                    now,
+                    code_hash,
+                    channel,
+                    record.id
+                )
+                .execute(&mut **db)
+                .instrument(query_span("users.code_set"))
+                .await
+            })
+            .map_err(write_error)?;
         }
         Err(sqlx::Error::RowNotFound) => {
-            let now = NaiveDateTime::now();
-            sqlx::query!(
-                "INSERT INTO users (code_attempts, code_created_at, code_hash, email) VALUES (0, ?, ?, ?)",
-                now,
-                code_hash,
-                body.email,
-            )
-            .execute(&mut **db)
-            .await
-            .expect("Failed to insert new user");
+            if let Err(reason) = email_domain_check(body.email).await {
+                info!("send-code:domain-rejected:{}", reason);
+                return Err(ApiError::validation(reason));
+            }
+
+            write_retry!("users.insert", async {
+                sqlx::query!(
+                    "INSERT INTO users (code_attempts, code_created_at, code_hash, email, created_at) VALUES (0, ?, ?, ?, ?)",
+                    now,
+                    code_hash,
+                    body.email,
+                    now,
+                )
+                .execute(&mut **db)
+                .instrument(query_span("users.insert"))
+                .await
+            })
+            .map_err(write_error)?;
         }
         Err(e) => {
-            return (
-                Status::InternalServerError,
-                json::json!({ "error": format!("{:?}", e) }),
-            );
+            tracing::error!("send-code:user-lookup-failed: {:?}", e);
+            return Err(ApiError::internal());
         }
     }
 
-    email_send(
-        "codes@example.com",
-        body.email,
-        "[ROCKET] Your login code",
-        &format!("Your login code is: {}. It will expire in 5 minutes.", code),
-    )
-    .await;
-    (Status::Ok, json::json!({ "message": "success" }))
+    if let Some((sender, phone)) = sms.0.as_ref().zip(phone) {
+        match sender.code_send(&mut db, &phone, &code).await {
+            Ok(()) => return Ok((Status::Ok, json::json!({ "message": "success" }))),
+            // Rather than leave the user without a code
+            Err(e) => {
+                tracing::warn!("send-code:sms-failed, emailing instead: {}", e);
+                let channel = OtpChannelKind::Email.as_str();
+                write_retry!("users.code_channel_set", async {
+                    sqlx::query!("UPDATE users SET code_channel = ? WHERE email = ?", channel, body.email)
+                        .execute(&mut **db)
+                        .instrument(query_span("users.code_channel_set"))
+                        .await
+                })
+                .map_err(write_error)?;
+            }
+        }
+    }
+    let _ = mailer.code_send(&mut db, body.email, &code).await;
+    Ok((Status::Ok, json::json!({ "message": "success" })))
 }
 
 pub fn stage() -> AdHoc {
     AdHoc::on_ignite("Session stage", |rocket| async {
-        rocket.mount("/api/session", routes![index, login, logout, send_code])
+        let rocket = manage_default(rocket, |_| ChallengeGate::from_env());
+        let rocket = manage_default(rocket, |_| Mailer::from_env());
+        api_mount(
+            rocket,
+            "/session",
+            timeout_routes(
+                "/session",
+                routes![
+                    index,
+                    login,
+                    logout,
+                    refresh,
+                    token,
+                    stop_impersonating,
+                    send_code,
+                    sessions_list,
+                    sessions_revoke
+                ],
+            ),
+        )
     })
 }
@@ -0,0 +1,412 @@
+use rocket::State;
+use rocket::fairing::AdHoc;
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::{Deserialize, json};
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+use tracing::Instrument;
+
+use crate::api::{API_V1, api_mount};
+use crate::cache::response_cache;
+use crate::clock::Timekeeper;
+use crate::crypto::content_cipher;
+use crate::csrf::*;
+use crate::db::*;
+use crate::error::ApiError;
+use crate::handlers::posts::{ForeignPostPolicy, post_access};
+use crate::signing::*;
+use crate::timeout::timeout_routes;
+use crate::util::*;
+
+/// Number of posts in a feed, the most recently updated.
+const FEED_ENTRIES_MAX: i64 = 50;
+/// How long feed readers and proxies may reuse a feed before revalidating it.
+const FEED_CACHE_CONTROL: &str = "public, max-age=300";
+
+/// Returns the public link of a shared post, a page of the app.
+fn share_url(token: &str) -> String {
+    format!("{}/shared/{}", app_url(), token)
+}
+
+fn feed_url(feed_id: &str) -> String {
+    format!("{}{}/shared/feeds/{}", app_url(), API_V1, feed_id)
+}
+
+/// Returns the ID of the user's feed, creating it as `id` at `now` with their first share.
+async fn feed_id_get_or_create(
+    db: &mut sqlx::SqliteConnection,
+    user_id: i64,
+    id: String,
+    now: DateTime<Utc>,
+) -> String {
+    sqlx::query!(
+        "INSERT INTO share_feeds (user_id, id, created_at) VALUES (?, ?, ?) ON CONFLICT (user_id) DO NOTHING",
+        user_id,
+        id,
+        now
+    )
+    .execute(&mut *db)
+    .instrument(query_span("share_feeds.insert"))
+    .await
+    .expect("Failed to create share feed");
+    sqlx::query_scalar!("SELECT id FROM share_feeds WHERE user_id = ?", user_id)
+        .fetch_one(db)
+        .instrument(query_span("share_feeds.id"))
+        .await
+        .expect("Failed to fetch share feed")
+}
+
+/// Shares a post through a public link, which anyone holding it may read, and adds it to the
+/// user's feed. Sharing an already shared post returns its existing link. Only verified accounts
+/// may share.
+#[put("/<id>/share")]
+async fn share(
+    mut db: Connection<Db>,
+    user: VerifiedUserCtx,
+    access: &State<ForeignPostPolicy>,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    ids: &State<IdSource>,
+    id: String,
+) -> Result<(Status, json::Value), ApiError> {
+    post_access(&mut db, **access, user.id, &id).await?;
+
+    let token = ids.id();
+    let now = clock.now();
+    let inserted = sqlx::query!(
+        "INSERT INTO post_shares (token, post_id, user_id, created_at) VALUES (?, ?, ?, ?) \
+        ON CONFLICT (post_id) DO NOTHING",
+        token,
+        id,
+        user.id,
+        now
+    )
+    .execute(&mut **db)
+    .instrument(query_span("post_shares.insert"))
+    .await
+    .expect("Failed to share post")
+    .rows_affected();
+    response_cache().invalidate_user(user.id);
+    let token = sqlx::query_scalar!("SELECT token FROM post_shares WHERE post_id = ?", id)
+        .fetch_one(&mut **db)
+        .instrument(query_span("post_shares.token"))
+        .await
+        .expect("Failed to fetch share");
+    let feed_id = feed_id_get_or_create(&mut db, user.id, ids.id(), now).await;
+
+    let status = match inserted {
+        0 => Status::Ok,
+        _ => Status::Created,
+    };
+    Ok((
+        status,
+        json::json!({ "token": token, "url": share_url(&token), "feedUrl": feed_url(&feed_id) }),
+    ))
+}
+
+/// Stops sharing a post. Its link stops working and it leaves the feed.
+#[delete("/<id>/share")]
+async fn unshare(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    id: String,
+) -> Result<(Status, json::Value), ApiError> {
+    let deleted = sqlx::query!("DELETE FROM post_shares WHERE post_id = ? AND user_id = ?", id, user.id)
+        .execute(&mut **db)
+        .instrument(query_span("post_shares.delete"))
+        .await
+        .expect("Failed to unshare post")
+        .rows_affected();
+    response_cache().invalidate_user(user.id);
+    if deleted == 0 {
+        return Err(ApiError::not_found("Share not found"));
+    }
+    Ok((Status::Ok, json::json!({ "message": "success" })))
+}
+
+/// A shared post, as the public sees it.
+struct SharedPost {
+    token: String,
+    content: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+/// Returns the title of a post: its first line.
+fn title(content: &str) -> String {
+    let title = content.lines().find(|line| !line.trim().is_empty()).unwrap_or_default();
+    title.trim().chars().take(80).collect()
+}
+
+/// Returns the public view of a post, with decrypted content.
+fn public_json(content: &str, created_at: DateTime<Utc>, updated_at: DateTime<Utc>) -> json::Value {
+    json::json!({
+        "title": title(content),
+        "content": content,
+        "createdAt": created_at,
+        "updatedAt": updated_at,
+    })
+}
+
+/// Reads a shared post, without signing in.
+#[get("/<token>")]
+async fn shared_read(mut db: Connection<Db>, token: &str) -> Result<(Status, json::Value), ApiError> {
+    let post = sqlx::query_as!(
+        SharedPost,
+        "SELECT s.token, p.content, p.created_at AS \"created_at: DateTime<Utc>\", \
+        p.updated_at AS \"updated_at: DateTime<Utc>\" FROM post_shares s JOIN posts p ON p.id = s.post_id \
+        JOIN users u ON u.id = s.user_id WHERE s.token = ? AND u.disabled_at IS NULL",
+        token
+    )
+    .fetch_optional(&mut **db)
+    .instrument(query_span("post_shares.read"))
+    .await
+    .expect("Failed to fetch shared post")
+    .ok_or_else(|| ApiError::not_found("Shared post not found"))?;
+    let content = content_cipher()
+        .decrypt(&post.content)
+        .expect("Failed to decrypt post content");
+
+    Ok((Status::Ok, public_json(&content, post.created_at, post.updated_at)))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+struct SignedUrlRequestBody {
+    #[serde(default = "ttl_default")]
+    ttl_secs: i64,
+}
+
+fn ttl_default() -> i64 {
+    3600
+}
+
+/// Returns a URL reading the post without signing in, which works until it expires. Unlike share
+/// links these can't be revoked one by one, only all together by rotating the signing key. Only
+/// verified accounts may sign URLs.
+#[post("/<id>/signed-url", data = "<body>")]
+async fn signed_url_create(
+    mut db: Connection<Db>,
+    user: VerifiedUserCtx,
+    access: &State<ForeignPostPolicy>,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    id: String,
+    body: json::Json<SignedUrlRequestBody>,
+) -> Result<(Status, json::Value), ApiError> {
+    if !(1..=SIGNED_URL_TTL_MAX_SECS).contains(&body.ttl_secs) {
+        return Err(ApiError::validation(format!(
+            "ttlSecs must be between 1 and {}",
+            SIGNED_URL_TTL_MAX_SECS
+        )));
+    }
+    post_access(&mut db, **access, user.id, &id).await?;
+
+    let expires_at = clock.now() + chrono::TimeDelta::seconds(body.ttl_secs);
+    let url = signed_url(&format!("/shared/posts/{}", id), expires_at);
+    Ok((Status::Ok, json::json!({ "url": url, "expiresAt": expires_at })))
+}
+
+/// Reads a post through a URL made by `signed_url_create`.
+#[get("/posts/<id>")]
+async fn signed_read(mut db: Connection<Db>, _signed: SignedUrl, id: &str) -> Result<(Status, json::Value), ApiError> {
+    let post = sqlx::query!(
+        "SELECT p.content, p.created_at AS \"created_at: DateTime<Utc>\", p.updated_at AS \"updated_at: DateTime<Utc>\" \
+        FROM posts p JOIN users u ON u.id = p.user_id WHERE p.id = ? AND u.disabled_at IS NULL",
+        id
+    )
+    .fetch_optional(&mut **db)
+    .instrument(query_span("posts.signed_read"))
+    .await
+    .expect("Failed to fetch post")
+    .ok_or_else(|| ApiError::not_found("Post not found"))?;
+    let content = content_cipher()
+        .decrypt(&post.content)
+        .expect("Failed to decrypt post content");
+
+    Ok((Status::Ok, public_json(&content, post.created_at, post.updated_at)))
+}
+
+/// The `If-None-Match` header of a request.
+pub struct IfNoneMatch<'r>(Option<&'r str>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(IfNoneMatch(request.headers().get_one("If-None-Match")))
+    }
+}
+
+/// An Atom document with its validators, or `304 Not Modified`.
+pub struct AtomFeed {
+    body: Option<String>,
+    etag: String,
+    last_modified: DateTime<Utc>,
+}
+
+impl<'r> Responder<'r, 'static> for AtomFeed {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response
+            .header(ContentType::new("application", "atom+xml").with_params(("charset", "utf-8")))
+            .header(Header::new("Cache-Control", FEED_CACHE_CONTROL))
+            .header(Header::new("ETag", self.etag))
+            .header(Header::new(
+                "Last-Modified",
+                self.last_modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+            ));
+        match self.body {
+            Some(body) => response.sized_body(body.len(), Cursor::new(body)),
+            None => response.status(Status::NotModified),
+        };
+        response.ok()
+    }
+}
+
+/// Escapes text for XML content and attribute values.
+fn xml_escape(text: &str) -> String {
+    text.chars()
+        .filter(|c| matches!(c, '\t' | '\n' | '\r') || !c.is_control())
+        .fold(String::with_capacity(text.len()), |mut escaped, c| {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&apos;"),
+                c => escaped.push(c),
+            }
+            escaped
+        })
+}
+
+/// Renders a feed of shared posts (with decrypted content), most recently updated first.
+fn atom_render(feed_id: &str, updated: DateTime<Utc>, posts: &[SharedPost]) -> String {
+    let timestamp = |at: DateTime<Utc>| at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let mut atom = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+        <title>Shared posts</title>\n\
+        <id>{url}</id>\n\
+        <link rel=\"self\" href=\"{url}\"/>\n\
+        <updated>{updated}</updated>\n\
+        <author><name>Anonymous</name></author>\n",
+        url = xml_escape(&feed_url(feed_id)),
+        updated = timestamp(updated),
+    );
+    for post in posts {
+        let url = xml_escape(&share_url(&post.token));
+        atom.push_str(&format!(
+            "<entry>\n\
+            <title>{}</title>\n\
+            <id>{url}</id>\n\
+            <link href=\"{url}\"/>\n\
+            <published>{}</published>\n\
+            <updated>{}</updated>\n\
+            <content type=\"text\">{}</content>\n\
+            </entry>\n",
+            xml_escape(&title(&post.content)),
+            timestamp(post.created_at),
+            timestamp(post.updated_at),
+            xml_escape(&post.content),
+            url = url,
+        ));
+    }
+    atom.push_str("</feed>\n");
+    atom
+}
+
+/// Serves the Atom feed of a user's shared posts. The feed is only rendered again when a shared
+/// post or the set of shares changed: otherwise a reader sending the last `ETag` gets a 304.
+#[get("/feeds/<id>")]
+async fn feed(mut db: Connection<Db>, id: &str, if_none_match: IfNoneMatch<'_>) -> Result<AtomFeed, ApiError> {
+    let user_id = sqlx::query_scalar!(
+        "SELECT f.user_id FROM share_feeds f JOIN users u ON u.id = f.user_id \
+        WHERE f.id = ? AND u.disabled_at IS NULL",
+        id
+    )
+    .fetch_optional(&mut **db)
+    .instrument(query_span("share_feeds.user_id"))
+    .await
+    .expect("Failed to fetch share feed")
+    .ok_or_else(|| ApiError::not_found("Feed not found"))?;
+
+    // Any write bumps a post's `seq`, and shares only come and go, so these change with the feed
+    let state = sqlx::query!(
+        "SELECT COUNT(*) AS \"count!: i64\", COALESCE(MAX(p.seq), 0) AS \"seq!: i64\", \
+        MAX(p.updated_at) AS \"updated_at: DateTime<Utc>\", MAX(s.created_at) AS \"shared_at: DateTime<Utc>\" \
+        FROM post_shares s JOIN posts p ON p.id = s.post_id WHERE s.user_id = ?",
+        user_id
+    )
+    .fetch_one(&mut **db)
+    .instrument(query_span("post_shares.feed_state"))
+    .await
+    .expect("Failed to fetch share feed state");
+    let shared_at = state.shared_at.unwrap_or(DateTime::UNIX_EPOCH);
+    let last_modified = state.updated_at.unwrap_or(DateTime::UNIX_EPOCH).max(shared_at);
+    let hash = format!(
+        "{:x}",
+        Sha256::digest(format!(
+            "{}:{}:{}",
+            state.count,
+            state.seq,
+            shared_at.timestamp_micros()
+        ))
+    );
+    let etag = format!("\"{}\"", &hash[..16]);
+    if if_none_match.0 == Some(etag.as_str()) {
+        return Ok(AtomFeed {
+            body: None,
+            etag,
+            last_modified,
+        });
+    }
+
+    let posts = sqlx::query_as!(
+        SharedPost,
+        "SELECT s.token, p.content, p.created_at AS \"created_at: DateTime<Utc>\", \
+        p.updated_at AS \"updated_at: DateTime<Utc>\" FROM post_shares s JOIN posts p ON p.id = s.post_id \
+        WHERE s.user_id = ? ORDER BY p.updated_at DESC LIMIT ?",
+        user_id,
+        FEED_ENTRIES_MAX
+    )
+    .fetch_all(&mut **db)
+    .instrument(query_span("post_shares.feed"))
+    .await
+    .expect("Failed to fetch shared posts")
+    .into_iter()
+    .map(|post| SharedPost {
+        content: content_cipher()
+            .decrypt(&post.content)
+            .expect("Failed to decrypt post content"),
+        ..post
+    })
+    .collect::<Vec<_>>();
+
+    Ok(AtomFeed {
+        body: Some(atom_render(id, last_modified, &posts)),
+        etag,
+        last_modified,
+    })
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Shares stage", |rocket| async {
+        let rocket = api_mount(
+            rocket,
+            "/posts",
+            timeout_routes("/posts", routes![share, unshare, signed_url_create]),
+        );
+        api_mount(
+            rocket,
+            "/shared",
+            timeout_routes("/shared", routes![shared_read, signed_read, feed]),
+        )
+    })
+}
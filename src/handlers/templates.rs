@@ -0,0 +1,273 @@
+use rocket::State;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, json};
+use tracing::Instrument;
+
+use crate::api::api_mount;
+use crate::clock::Timekeeper;
+use crate::cron::Cron;
+use crate::crypto::content_cipher;
+use crate::csrf::*;
+use crate::db::*;
+use crate::error::ApiError;
+use crate::handlers::posts::{content_size_check, post_owned};
+use crate::recurring::{PostTemplate, template_instantiate, template_next_run};
+use crate::timeout::timeout_routes;
+use crate::util::*;
+use crate::variants::variants;
+
+/// Maximum length of a template name.
+const TEMPLATE_NAME_MAX_LEN: usize = 100;
+/// Maximum number of templates a user can have.
+const TEMPLATES_MAX_COUNT: i64 = 50;
+
+async fn template_get(db: &mut sqlx::SqliteConnection, user_id: i64, id: &str) -> Option<PostTemplate> {
+    sqlx::query_as!(
+        PostTemplate,
+        "SELECT id, user_id, name, content, variant, parent_id, schedule, enabled AS \"enabled: bool\", \
+        next_run_at AS \"next_run_at: DateTime<Utc>\", last_run_at AS \"last_run_at: DateTime<Utc>\", \
+        created_at AS \"created_at: DateTime<Utc>\", updated_at AS \"updated_at: DateTime<Utc>\" \
+        FROM post_templates WHERE id = ? AND user_id = ?",
+        id,
+        user_id
+    )
+    .fetch_optional(db)
+    .instrument(query_span("post_templates.get"))
+    .await
+    .expect("Failed to fetch template")
+    .map(PostTemplate::content_decrypt)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+struct TemplateRequestBody {
+    name: String,
+    content: String,
+    variant: String,
+    #[serde(default)]
+    parent_id: Option<String>,
+    schedule: String,
+    #[serde(default = "enabled_default")]
+    enabled: bool,
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+/// Checks a template the way a post write would be checked, and parses its schedule.
+async fn template_validate(
+    db: &mut sqlx::SqliteConnection,
+    user_id: i64,
+    body: &TemplateRequestBody,
+) -> Result<Cron, ApiError> {
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > TEMPLATE_NAME_MAX_LEN {
+        return Err(ApiError::validation(format!(
+            "name must be 1 to {} characters",
+            TEMPLATE_NAME_MAX_LEN
+        )));
+    }
+    content_size_check(&body.content)?;
+    variants()
+        .check(&body.variant, &body.content)
+        .map_err(ApiError::validation)?;
+    let schedule = Cron::parse(&body.schedule).map_err(|e| ApiError::validation(format!("schedule: {}", e)))?;
+    match &body.parent_id {
+        Some(parent_id) if !post_owned(db, user_id, parent_id).await => {
+            Err(ApiError::validation(format!("Parent post {} not found", parent_id)))
+        }
+        _ => Ok(schedule),
+    }
+}
+
+/// Lists the user's templates by name.
+#[get("/")]
+async fn list(mut db: Connection<Db>, user: UserCtx) -> (Status, json::Value) {
+    let templates = sqlx::query_as!(
+        PostTemplate,
+        "SELECT id, user_id, name, content, variant, parent_id, schedule, enabled AS \"enabled: bool\", \
+        next_run_at AS \"next_run_at: DateTime<Utc>\", last_run_at AS \"last_run_at: DateTime<Utc>\", \
+        created_at AS \"created_at: DateTime<Utc>\", updated_at AS \"updated_at: DateTime<Utc>\" \
+        FROM post_templates WHERE user_id = ? ORDER BY name, id",
+        user.id
+    )
+    .fetch_all(&mut **db)
+    .instrument(query_span("post_templates.list"))
+    .await
+    .expect("Failed to fetch templates")
+    .into_iter()
+    .map(PostTemplate::content_decrypt)
+    .collect::<Vec<_>>();
+    (Status::Ok, json::json!({ "items": templates, "hasMore": false }))
+}
+
+/// Creates a template. Its first post comes at the next time its schedule matches.
+#[post("/", data = "<body>")]
+async fn create(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    ids: &State<IdSource>,
+    body: json::Json<TemplateRequestBody>,
+) -> Result<(Status, json::Value), ApiError> {
+    let schedule = template_validate(&mut db, user.id, &body).await?;
+    let count = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!: i64" FROM post_templates WHERE user_id = ?"#,
+        user.id
+    )
+    .fetch_one(&mut **db)
+    .instrument(query_span("post_templates.count"))
+    .await
+    .expect("Failed to count templates");
+    if count >= TEMPLATES_MAX_COUNT {
+        return Err(ApiError::validation(format!(
+            "At most {} templates can be stored",
+            TEMPLATES_MAX_COUNT
+        )));
+    }
+
+    let now = clock.now();
+    let template = PostTemplate {
+        id: ids.id(),
+        user_id: user.id,
+        name: body.name.trim().to_owned(),
+        content: body.content.clone(),
+        variant: body.variant.clone(),
+        parent_id: body.parent_id.clone(),
+        schedule: body.schedule.trim().to_owned(),
+        enabled: body.enabled,
+        next_run_at: template_next_run(&schedule, body.enabled, now),
+        last_run_at: None,
+        created_at: now,
+        updated_at: now,
+    };
+    let content = content_cipher().encrypt(&template.content);
+    sqlx::query!(
+        "INSERT INTO post_templates (id, user_id, name, content, variant, parent_id, schedule, enabled, next_run_at, \
+        created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        template.id,
+        template.user_id,
+        template.name,
+        content,
+        template.variant,
+        template.parent_id,
+        template.schedule,
+        template.enabled,
+        template.next_run_at,
+        template.created_at,
+        template.updated_at
+    )
+    .execute(&mut **db)
+    .instrument(query_span("post_templates.insert"))
+    .await
+    .expect("Failed to insert template");
+
+    Ok((Status::Created, json::json!(template)))
+}
+
+#[get("/<id>")]
+async fn read(mut db: Connection<Db>, user: UserCtx, id: String) -> Result<(Status, json::Value), ApiError> {
+    let template = template_get(&mut db, user.id, &id)
+        .await
+        .ok_or_else(|| ApiError::not_found("Template not found"))?;
+    Ok((Status::Ok, json::json!(template)))
+}
+
+/// Replaces a template. Its next run is worked out again from now, so changing the schedule or
+/// re-enabling it never fires runs that were missed.
+#[put("/<id>", data = "<body>")]
+async fn update(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    id: String,
+    body: json::Json<TemplateRequestBody>,
+) -> Result<(Status, json::Value), ApiError> {
+    let mut template = template_get(&mut db, user.id, &id)
+        .await
+        .ok_or_else(|| ApiError::not_found("Template not found"))?;
+    let schedule = template_validate(&mut db, user.id, &body).await?;
+
+    let now = clock.now();
+    template.name = body.name.trim().to_owned();
+    template.content = body.content.clone();
+    template.variant = body.variant.clone();
+    template.parent_id = body.parent_id.clone();
+    template.schedule = body.schedule.trim().to_owned();
+    template.enabled = body.enabled;
+    template.next_run_at = template_next_run(&schedule, body.enabled, now);
+    template.updated_at = now;
+    let content = content_cipher().encrypt(&template.content);
+    sqlx::query!(
+        "UPDATE post_templates SET name = ?, content = ?, variant = ?, parent_id = ?, schedule = ?, enabled = ?, \
+        next_run_at = ?, updated_at = ? WHERE id = ? AND user_id = ?",
+        template.name,
+        content,
+        template.variant,
+        template.parent_id,
+        template.schedule,
+        template.enabled,
+        template.next_run_at,
+        template.updated_at,
+        id,
+        user.id
+    )
+    .execute(&mut **db)
+    .instrument(query_span("post_templates.update"))
+    .await
+    .expect("Failed to update template");
+
+    Ok((Status::Ok, json::json!(template)))
+}
+
+/// Deletes a template. The posts made from it stay.
+#[delete("/<id>")]
+async fn delete(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    id: String,
+) -> Result<(Status, json::Value), ApiError> {
+    let deleted = sqlx::query!("DELETE FROM post_templates WHERE id = ? AND user_id = ?", id, user.id)
+        .execute(&mut **db)
+        .instrument(query_span("post_templates.delete"))
+        .await
+        .expect("Failed to delete template")
+        .rows_affected();
+    if deleted == 0 {
+        return Err(ApiError::not_found("Template not found"));
+    }
+    Ok((Status::Ok, json::json!({ "message": "success" })))
+}
+
+/// Creates a post from the template right away. The schedule is left as it is.
+#[post("/<id>/run")]
+async fn run(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    ids: &State<IdSource>,
+    id: String,
+) -> Result<(Status, json::Value), ApiError> {
+    let template = template_get(&mut db, user.id, &id)
+        .await
+        .ok_or_else(|| ApiError::not_found("Template not found"))?;
+    let post_id = template_instantiate(&mut db, &template, ids.id(), clock.now_stored()).await?;
+    Ok((Status::Created, json::json!({ "postId": post_id })))
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Templates stage", |rocket| async {
+        api_mount(
+            rocket,
+            "/templates",
+            timeout_routes("/templates", routes![list, create, read, update, delete, run]),
+        )
+    })
+}
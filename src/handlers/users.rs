@@ -0,0 +1,520 @@
+use chrono::Duration;
+use rocket::State;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize, json};
+use tracing::Instrument;
+
+use crate::api::api_mount;
+use crate::authz::*;
+use crate::clock::Timekeeper;
+use crate::csrf::*;
+use crate::db::*;
+use crate::error::{ApiError, ErrorCode};
+use crate::handlers::admin::users_merge;
+use crate::handlers::session::{code_consume, hash_saturated};
+use crate::impersonation::Impersonation;
+use crate::otp::OtpChannelKind;
+use crate::scope::Scope;
+use crate::sms::{Sms, phone_is_valid};
+use crate::timeout::timeout_routes;
+use crate::util::*;
+
+/// Maximum length of a preference key.
+const PREFERENCE_KEY_MAX_LEN: usize = 64;
+/// Maximum size of a single serialized preference value.
+const PREFERENCE_VALUE_MAX_BYTES: usize = 16 * 1024;
+/// Maximum number of preferences a user can store.
+const PREFERENCES_MAX_COUNT: i64 = 100;
+
+/// Loads all preferences of a user as a single JSON object.
+async fn preferences_get(db: &mut sqlx::SqliteConnection, scope: Scope) -> json::Value {
+    let rows = scope.preferences(db).await.expect("Failed to fetch preferences");
+
+    let map = rows
+        .into_iter()
+        .map(|(key, value)| {
+            let value = json::from_str(&value).unwrap_or(json::Value::Null);
+            (key, value)
+        })
+        .collect::<json::serde_json::Map<_, _>>();
+    json::Value::Object(map)
+}
+
+/// Minutes a phone verification code can be used for, and minutes before another can be texted.
+const PHONE_CODE_TTL_MINS: i64 = 10;
+const PHONE_CODE_RESEND_MINS: i64 = 2;
+
+/// The user's phone number and where their login codes are sent.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+struct PhoneSettings {
+    phone: Option<String>,
+    phone_verified_at: Option<DateTime<Utc>>,
+    otp_channel: String,
+}
+
+async fn phone_settings(db: &mut sqlx::SqliteConnection, user_id: i64) -> PhoneSettings {
+    sqlx::query_as!(
+        PhoneSettings,
+        "SELECT phone, phone_verified_at AS \"phone_verified_at: DateTime<Utc>\", otp_channel FROM users WHERE id = ?",
+        user_id
+    )
+    .fetch_one(db)
+    .instrument(query_span("users.phone_get"))
+    .await
+    .expect("Failed to fetch phone settings")
+}
+
+fn sms_unavailable() -> ApiError {
+    ApiError::new(
+        Status::ServiceUnavailable,
+        ErrorCode::Unavailable,
+        "Text messages are not available",
+    )
+}
+
+/// Returns the profile of the current user.
+#[get("/me")]
+async fn me(mut db: Connection<Db>, user: UserCtx) -> Result<(Status, json::Value), ApiError> {
+    let user = Scope::from(&user)
+        .user_read(&mut db)
+        .await
+        .expect("Failed to fetch user");
+
+    match user {
+        Some(user) => {
+            let phone = phone_settings(&mut db, user.id).await;
+            Ok((
+                Status::Ok,
+                json::json!({
+                    "id": user.id,
+                    "createdAt": user.created_at,
+                    "email": user.email,
+                    "emailVerifiedAt": user.email_verified_at,
+                    "phone": phone.phone,
+                    "phoneVerifiedAt": phone.phone_verified_at,
+                    "otpChannel": phone.otp_channel,
+                }),
+            ))
+        }
+        None => Err(ApiError::not_found("User not found")),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct PhoneRequestBody<'r> {
+    phone: &'r str,
+}
+
+/// Sets the user's phone number, in E.164 format, and texts it a code to verify it with
+/// `POST /me/phone/verify`. Until then, login codes are emailed.
+#[put("/me/phone", data = "<body>")]
+async fn phone_set(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    sms: &State<Sms>,
+    clock: &State<Timekeeper>,
+    body: json::Json<PhoneRequestBody<'_>>,
+) -> Result<(Status, json::Value), ApiError> {
+    let Some(sender) = &sms.0 else {
+        return Err(sms_unavailable());
+    };
+    let phone = body.phone.trim();
+    if !phone_is_valid(phone) {
+        return Err(ApiError::validation("phone must be in E.164 format, e.g. +14155550100"));
+    }
+
+    let last_code_at = sqlx::query_scalar!(
+        "SELECT phone_code_created_at AS \"phone_code_created_at: DateTime<Utc>\" FROM users WHERE id = ?",
+        user.id
+    )
+    .fetch_one(&mut **db)
+    .instrument(query_span("users.phone_code_created_at"))
+    .await
+    .expect("Failed to fetch phone code");
+    let now = clock.now();
+    if last_code_at.is_some_and(|at| at > now - Duration::minutes(PHONE_CODE_RESEND_MINS)) {
+        return Err(ApiError::new(
+            Status::TooManyRequests,
+            ErrorCode::RateLimited,
+            format!(
+                "Wait {} minutes after requesting a code to try again.",
+                PHONE_CODE_RESEND_MINS
+            ),
+        ));
+    }
+
+    let code = code_gen();
+    let code_hash = match hash_code(&code).await {
+        Ok(hash) => hash,
+        Err(HashError::Saturated) => return Err(hash_saturated()),
+        Err(HashError::Failed(e)) => {
+            tracing::error!("users:phone-hash-failed: {}", e);
+            return Err(ApiError::internal());
+        }
+    };
+    sqlx::query!(
+        "UPDATE users SET phone = ?, phone_verified_at = NULL, phone_code_hash = ?, phone_code_attempts = 0, \
+        phone_code_created_at = ?, otp_channel = 'email' WHERE id = ?",
+        phone,
+        code_hash,
+        now,
+        user.id
+    )
+    .execute(&mut **db)
+    .instrument(query_span("users.phone_set"))
+    .await
+    .expect("Failed to set phone");
+
+    let message = format!(
+        "Your verification code is: {}. It will expire in {} minutes.",
+        code, PHONE_CODE_TTL_MINS
+    );
+    if let Err(e) = sender.send(phone, &message).await {
+        tracing::warn!("users:phone-code-failed:{}: {}", user.id, e);
+        return Err(sms_unavailable());
+    }
+    tracing::info!("users:phone-set:{}", user.id);
+    Ok((Status::Ok, json::json!(phone_settings(&mut db, user.id).await)))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct PhoneVerifyRequestBody<'r> {
+    code: &'r str,
+}
+
+/// Verifies the user's phone number with the code texted by `PUT /me/phone`, after which it can be
+/// picked as the channel of login codes.
+#[post("/me/phone/verify", data = "<body>")]
+async fn phone_verify(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    body: json::Json<PhoneVerifyRequestBody<'_>>,
+) -> Result<(Status, json::Value), ApiError> {
+    let invalid = || ApiError::validation("The code is invalid or expired");
+    if !code_is_valid(body.code) {
+        return Err(invalid());
+    }
+    let record = sqlx::query!(
+        "SELECT phone_code_hash, phone_code_attempts, \
+        phone_code_created_at AS \"phone_code_created_at: DateTime<Utc>\" FROM users WHERE id = ?",
+        user.id
+    )
+    .fetch_one(&mut **db)
+    .instrument(query_span("users.phone_code_get"))
+    .await
+    .expect("Failed to fetch phone code");
+    let (Some(code_hash), Some(created_at)) = (record.phone_code_hash, record.phone_code_created_at) else {
+        return Err(invalid());
+    };
+    let attempts = record.phone_code_attempts.unwrap_or(0);
+    let now = clock.now();
+    if attempts > 2 || created_at < now - Duration::minutes(PHONE_CODE_TTL_MINS) {
+        return Err(invalid());
+    }
+
+    let verified = match hash_code_verify(&code_hash, body.code).await {
+        Ok(verified) => verified,
+        // Don't count a shed request as a failed attempt
+        Err(HashError::Saturated) => return Err(hash_saturated()),
+        Err(HashError::Failed(_)) => false,
+    };
+    if !verified {
+        let attempts = attempts + 1;
+        sqlx::query!(
+            "UPDATE users SET phone_code_attempts = ? WHERE id = ?",
+            attempts,
+            user.id
+        )
+        .execute(&mut **db)
+        .instrument(query_span("users.phone_code_attempts_increment"))
+        .await
+        .expect("Failed to increment phone code attempts");
+        return Err(invalid());
+    }
+
+    sqlx::query!(
+        "UPDATE users SET phone_verified_at = ?, phone_code_hash = NULL, phone_code_attempts = NULL, \
+        phone_code_created_at = NULL WHERE id = ?",
+        now,
+        user.id
+    )
+    .execute(&mut **db)
+    .instrument(query_span("users.phone_verify"))
+    .await
+    .expect("Failed to verify phone");
+    tracing::info!("users:phone-verified:{}", user.id);
+    Ok((Status::Ok, json::json!(phone_settings(&mut db, user.id).await)))
+}
+
+/// Removes the user's phone number. Login codes are emailed again.
+#[delete("/me/phone")]
+async fn phone_remove(mut db: Connection<Db>, user: UserCtx, _csrf: CsrfVerified) -> (Status, json::Value) {
+    sqlx::query!(
+        "UPDATE users SET phone = NULL, phone_verified_at = NULL, phone_code_hash = NULL, \
+        phone_code_attempts = NULL, phone_code_created_at = NULL, otp_channel = 'email' WHERE id = ?",
+        user.id
+    )
+    .execute(&mut **db)
+    .instrument(query_span("users.phone_remove"))
+    .await
+    .expect("Failed to remove phone");
+    (Status::Ok, json::json!(phone_settings(&mut db, user.id).await))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct OtpChannelRequestBody<'r> {
+    channel: &'r str,
+}
+
+/// Picks where the user's login codes are sent: `email`, or `sms` to their verified phone number.
+#[put("/me/otp-channel", data = "<body>")]
+async fn otp_channel_set(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    sms: &State<Sms>,
+    body: json::Json<OtpChannelRequestBody<'_>>,
+) -> Result<(Status, json::Value), ApiError> {
+    let Some(channel) = OtpChannelKind::parse(body.channel) else {
+        return Err(ApiError::validation("channel must be email or sms"));
+    };
+    if channel == OtpChannelKind::Sms {
+        if sms.0.is_none() {
+            return Err(sms_unavailable());
+        }
+        if phone_settings(&mut db, user.id).await.phone_verified_at.is_none() {
+            return Err(ApiError::validation("Verify a phone number first"));
+        }
+    }
+    let channel = channel.as_str();
+    sqlx::query!("UPDATE users SET otp_channel = ? WHERE id = ?", channel, user.id)
+        .execute(&mut **db)
+        .instrument(query_span("users.otp_channel_set"))
+        .await
+        .expect("Failed to set OTP channel");
+    Ok((Status::Ok, json::json!(phone_settings(&mut db, user.id).await)))
+}
+
+#[get("/me/preferences")]
+async fn preferences_read(mut db: Connection<Db>, user: UserCtx) -> (Status, json::Value) {
+    (Status::Ok, preferences_get(&mut db, Scope::from(&user)).await)
+}
+
+/// Merges the given preferences into the stored ones. Keys set to `null` are removed, keys that
+/// are not mentioned are left untouched, so devices can update settings independently.
+#[put("/me/preferences", data = "<body>")]
+async fn preferences_update(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    body: json::Json<json::serde_json::Map<String, json::Value>>,
+) -> Result<(Status, json::Value), ApiError> {
+    let mut values = Vec::with_capacity(body.len());
+    for (key, value) in body.iter() {
+        if key.is_empty() || key.len() > PREFERENCE_KEY_MAX_LEN {
+            return Err(ApiError::validation(format!(
+                "Preference keys must be 1-{} characters",
+                PREFERENCE_KEY_MAX_LEN
+            ))
+            .details(json::json!({ "key": key })));
+        }
+        let value = (!value.is_null()).then(|| value.to_string());
+        if value.as_ref().is_some_and(|v| v.len() > PREFERENCE_VALUE_MAX_BYTES) {
+            return Err(ApiError::validation(format!(
+                "Preference '{}' exceeds {} bytes",
+                key, PREFERENCE_VALUE_MAX_BYTES
+            ))
+            .details(json::json!({ "key": key })));
+        }
+        values.push((key.as_str(), value));
+    }
+
+    let scope = Scope::from(&user);
+    let now = clock.now();
+    let mut tx = sqlx::Connection::begin(&mut **db)
+        .await
+        .expect("Failed to begin transaction");
+    for (key, value) in values {
+        match value {
+            Some(value) => scope
+                .preference_set(&mut tx, key, &value, now)
+                .await
+                .expect("Failed to upsert preference"),
+            None => scope
+                .preference_delete(&mut tx, key)
+                .await
+                .expect("Failed to delete preference"),
+        }
+    }
+
+    let count = scope
+        .preferences_count(&mut tx)
+        .await
+        .expect("Failed to count preferences");
+    if count > PREFERENCES_MAX_COUNT {
+        tx.rollback().await.expect("Failed to roll back transaction");
+        return Err(ApiError::validation(format!(
+            "At most {} preferences can be stored",
+            PREFERENCES_MAX_COUNT
+        )));
+    }
+    tx.commit().await.expect("Failed to commit preferences");
+
+    Ok((Status::Ok, preferences_get(&mut db, scope).await))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct MergeRequestBody<'r> {
+    email: &'r str,
+    code: &'r str,
+}
+
+/// Merges another account into the user's, e.g. one they signed up to with another email. A login
+/// code sent to that email, which is then consumed, proves they own it. The other account is
+/// disabled and its sessions are signed out.
+#[post("/me/merge", data = "<body>")]
+async fn merge(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    body: json::Json<MergeRequestBody<'_>>,
+) -> Result<(Status, json::Value), ApiError> {
+    let source = code_consume(&mut db, body.email, body.code, clock.now()).await?;
+    let counts = users_merge(&mut db, source, user.id, clock.now())
+        .await
+        .expect("Failed to merge users")
+        .ok_or_else(|| ApiError::validation("Cannot merge an account into itself"))?;
+    tracing::info!("users:merge:{}:{}", source, user.id);
+    Ok((Status::Ok, json::json!({ "moved": counts })))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+struct ApiTokenRequestBody<'r> {
+    name: &'r str,
+    /// Space-separated, e.g. `posts:read account:read`.
+    scopes: &'r str,
+    /// Omitted for a token that lasts until deleted.
+    expires_in_days: Option<i64>,
+}
+
+/// Lists the user's personal access tokens. Their secrets can't be shown again.
+#[get("/me/tokens")]
+async fn api_tokens_read(mut db: Connection<Db>, user: UserCtx) -> (Status, json::Value) {
+    let tokens = api_tokens_list(&mut db, user.id)
+        .await
+        .expect("Failed to list API tokens");
+    (Status::Ok, json::json!({ "items": tokens }))
+}
+
+/// Creates a personal access token limited to `scopes`, returning its secret once. Only sessions
+/// manage tokens, so that a token can't mint itself broader ones, and not impersonations, whose
+/// tokens would outlive them.
+#[post("/me/tokens", data = "<body>")]
+#[allow(clippy::too_many_arguments)]
+async fn api_token_add(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    authz: Authz,
+    impersonation: Option<Impersonation>,
+    _csrf: CsrfVerified,
+    clock: &State<Timekeeper>,
+    ids: &State<IdSource>,
+    body: json::Json<ApiTokenRequestBody<'_>>,
+) -> Result<(Status, json::Value), ApiError> {
+    authz.session_required()?;
+    if impersonation.is_some() {
+        return Err(ApiError::new(
+            Status::Forbidden,
+            ErrorCode::Forbidden,
+            "Tokens can't be created while impersonating",
+        ));
+    }
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Err(ApiError::validation("name must be 1 to 100 characters"));
+    }
+    let scopes = scopes_parse(body.scopes)
+        .ok_or_else(|| ApiError::validation("scopes must be <resource>:read or <resource>:write, e.g. posts:read"))?;
+    if body
+        .expires_in_days
+        .is_some_and(|days| !(1..=API_TOKEN_TTL_MAX_DAYS).contains(&days))
+    {
+        return Err(ApiError::validation(format!(
+            "expiresInDays must be 1 to {}",
+            API_TOKEN_TTL_MAX_DAYS
+        )));
+    }
+    let (token, secret) = api_token_create(
+        &mut db,
+        user.id,
+        name,
+        &scopes,
+        body.expires_in_days,
+        ids.id(),
+        clock.now(),
+    )
+    .await
+    .expect("Failed to create API token");
+    tracing::info!("users:api-token-create:{}:{}", user.id, token.id);
+    let mut body = json::json!(token);
+    body["token"] = json::json!(secret);
+    Ok((Status::Created, body))
+}
+
+#[delete("/me/tokens/<id>")]
+async fn api_token_remove(
+    mut db: Connection<Db>,
+    user: UserCtx,
+    authz: Authz,
+    _csrf: CsrfVerified,
+    id: &str,
+) -> Result<(Status, json::Value), ApiError> {
+    authz.session_required()?;
+    if !api_token_delete(&mut db, user.id, id)
+        .await
+        .expect("Failed to delete API token")
+    {
+        return Err(ApiError::not_found("Token not found"));
+    }
+    tracing::info!("users:api-token-delete:{}:{}", user.id, id);
+    Ok((Status::Ok, json::json!({ "id": id })))
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Users stage", |rocket| async {
+        api_mount(
+            rocket,
+            "/users",
+            timeout_routes(
+                "/users",
+                routes![
+                    me,
+                    phone_set,
+                    phone_verify,
+                    phone_remove,
+                    otp_channel_set,
+                    preferences_read,
+                    preferences_update,
+                    merge,
+                    api_tokens_read,
+                    api_token_add,
+                    api_token_remove
+                ],
+            ),
+        )
+    })
+}
@@ -0,0 +1,45 @@
+use rocket::State;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::json;
+
+use crate::db::*;
+use crate::email::{EmailHealth, EmailStatus};
+use crate::replication::{ReplicationHealth, ReplicationMonitor, replication_config};
+use crate::util::manage_default;
+
+/// Readiness probe for load balancers and orchestrators: 503 when the database can't be reached.
+/// Broken email delivery is reported as `degraded` only, as the rest of the app still works. So is
+/// a replication sidecar that stopped shipping the WAL, unless `REPLICATION_REQUIRED` is set.
+#[get("/")]
+async fn readyz(db: &Db, email: &State<EmailStatus>, replication: &State<ReplicationMonitor>) -> (Status, json::Value) {
+    let database = pool_probe(db, db_pool_config()).await;
+    let email = match email.get() {
+        EmailHealth::Unknown => json::json!({ "status": "unknown" }),
+        EmailHealth::Ok => json::json!({ "status": "ok" }),
+        EmailHealth::Broken(reason) => json::json!({ "status": "broken", "reason": reason }),
+    };
+    let replicating = replication.check().await;
+    let replication_down = replicating
+        .as_ref()
+        .is_some_and(|health| *health != ReplicationHealth::Ok);
+    let (status, summary) = match (database, email["status"] == "broken", replication_down) {
+        (false, _, _) => (Status::ServiceUnavailable, "unavailable"),
+        (true, _, true) if replication.required() => (Status::ServiceUnavailable, "unavailable"),
+        (true, false, false) => (Status::Ok, "ready"),
+        (true, _, _) => (Status::Ok, "degraded"),
+    };
+    let database = if database { "ok" } else { "failed" };
+    let mut checks = json::json!({ "database": { "status": database }, "email": email });
+    if let Some(health) = replicating {
+        checks["replication"] = health.to_json();
+    }
+    (status, json::json!({ "status": summary, "checks": checks }))
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Health stage", |rocket| async {
+        let rocket = manage_default(rocket, |_| ReplicationMonitor::new(replication_config().clone()));
+        rocket.mount("/readyz", routes![readyz])
+    })
+}
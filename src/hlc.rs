@@ -0,0 +1,154 @@
+use rocket::serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+
+use crate::clock::clock_config;
+use crate::db::id_gen;
+use crate::util::*;
+
+/// A hybrid logical clock stamp: wall-clock milliseconds, a counter ordering the stamps within a
+/// millisecond, and the node that issued it as the tie-breaker. Unlike wall-clock timestamps, the
+/// stamps of a node only ever grow, and every stamp issued after one was seen is greater than it.
+///
+/// Written as `<millis>-<counter>-<node>` with zero-padded numbers, e.g.
+/// `001706702400000-00003-web1`, so that the text sorts in the same order as the stamps.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+#[serde(crate = "rocket::serde")]
+pub struct Hlc {
+    pub millis: i64,
+    pub counter: u16,
+    pub node: String,
+}
+
+impl Hlc {
+    /// The stamp of a write that only came with a wall-clock `updated_at`. It sorts before every
+    /// stamp a node issues in the same millisecond.
+    pub fn from_wall(at: DateTime<Utc>) -> Self {
+        Self {
+            millis: at.timestamp_millis(),
+            counter: 0,
+            node: String::new(),
+        }
+    }
+}
+
+impl fmt::Display for Hlc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:015}-{:05}-{}", self.millis, self.counter, self.node)
+    }
+}
+
+impl FromStr for Hlc {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Version {} is not a <millis>-<counter>-<node> clock stamp", text);
+        let mut parts = text.splitn(3, '-');
+        let (Some(millis), Some(counter), Some(node)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        let digits = |part: &str, len| part.len() == len && part.bytes().all(|b| b.is_ascii_digit());
+        let node_valid = node.len() <= 64
+            && node
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if !digits(millis, 15) || !digits(counter, 5) || !node_valid {
+            return Err(invalid());
+        }
+        Ok(Self {
+            millis: millis.parse().map_err(|_| invalid())?,
+            counter: counter.parse().map_err(|_| invalid())?,
+            node: node.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for Hlc {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl From<Hlc> for String {
+    fn from(hlc: Hlc) -> Self {
+        hlc.to_string()
+    }
+}
+
+/// Issues the stamps of this server. Its node is `HLC_NODE_ID`, or a random ID per process.
+pub struct HlcClock {
+    node: String,
+    last: Mutex<(i64, u16)>,
+}
+
+impl HlcClock {
+    pub fn new(node: impl Into<String>) -> Self {
+        Self {
+            node: node.into(),
+            last: Mutex::new((0, 0)),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(config_var("HLC_NODE_ID").unwrap_or_else(|| id_gen()[..8].to_string()))
+    }
+
+    /// Advances the clock past its last stamp, `seen` (if any) and the wall clock, returning the
+    /// new stamp.
+    fn advance(&self, seen: Option<&Hlc>) -> Hlc {
+        let wall = Utc::now().timestamp_millis();
+        let mut last = self.last.lock().unwrap();
+        let (mut millis, mut counter) = *last;
+        if let Some(seen) = seen.filter(|seen| (seen.millis, seen.counter) > (millis, counter)) {
+            (millis, counter) = (seen.millis, seen.counter);
+        }
+        (millis, counter) = match wall > millis {
+            true => (wall, 0),
+            // The counter ran out, borrow the next millisecond
+            false if counter == u16::MAX => (millis + 1, 0),
+            false => (millis, counter + 1),
+        };
+        *last = (millis, counter);
+        Hlc {
+            millis,
+            counter,
+            node: self.node.clone(),
+        }
+    }
+
+    /// Returns a new stamp for a write made on this server.
+    pub fn now(&self) -> Hlc {
+        self.advance(None)
+    }
+
+    /// Returns a new stamp greater than both the clock's last one and `seen`, a client's stamp.
+    pub fn update(&self, seen: &Hlc) -> Hlc {
+        self.advance(Some(seen))
+    }
+
+    /// Returns the stamp to store for a client-supplied `version`, or why it was refused. Stamps
+    /// further ahead of the server time than `CLOCK_SKEW_MAX_SECS` are refused whatever the
+    /// `CLOCK_SKEW_POLICY`, since clamping one would reorder it against the client's other writes.
+    pub fn accept(&self, version: Hlc, now: DateTime<Utc>) -> Result<Hlc, String> {
+        if version.millis > (now + clock_config().max_skew).timestamp_millis() {
+            return Err(format!(
+                "Version {} is ahead of the server time {}, check the device clock",
+                version,
+                now.to_rfc3339()
+            ));
+        }
+        // Later stamps of this server must sort after the client's
+        self.update(&version);
+        Ok(version)
+    }
+}
+
+/// Returns the process-wide `HlcClock`.
+pub fn hlc_clock() -> &'static HlcClock {
+    static CLOCK: OnceLock<HlcClock> = OnceLock::new();
+    CLOCK.get_or_init(HlcClock::from_env)
+}
@@ -0,0 +1,137 @@
+use rocket::fairing::AdHoc;
+use rocket::http::{self, Header, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::serde::Serialize;
+use tracing::Instrument;
+
+use crate::authz::{session_record, session_record_cached};
+use crate::client_info::ip_locate;
+use crate::db::*;
+use crate::util::*;
+
+/// Name of the private cookie marking the caller as support staff acting as the user. It holds the
+/// ID of the impersonation session.
+pub const IMPERSONATION_COOKIE: &str = "impersonation";
+
+/// Response header carrying who is impersonating the user, so that clients can show a banner.
+pub const IMPERSONATION_HEADER: &str = "X-Impersonated-By";
+
+/// Lifetime of an impersonation, from `IMPERSONATION_TTL_MINS` (30). It is not extended by use.
+pub fn impersonation_ttl_mins() -> i64 {
+    app_config().auth.impersonation_ttl_mins
+}
+
+/// A session opened by support staff through `POST /admin/impersonate/<user_id>`, to act as the
+/// user while reproducing an issue.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct Impersonation {
+    pub session_id: String,
+    pub user_id: i64,
+    /// Who opened it, as given by the staff member.
+    pub impersonator: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Impersonation {
+    /// The auth, session and impersonation cookies, living from `now` until the impersonation expires.
+    pub fn cookies(&self, now: DateTime<Utc>) -> [http::Cookie<'static>; 3] {
+        let max_age = rocket::time::Duration::seconds((self.expires_at - now).num_seconds().max(0));
+        [
+            http::Cookie::build(("user_id", self.user_id.to_string()))
+                .http_only(false)
+                .max_age(max_age)
+                .build(),
+            http::Cookie::build((SESSION_COOKIE, self.session_id.clone()))
+                .http_only(true)
+                .max_age(max_age)
+                .build(),
+            http::Cookie::build((IMPERSONATION_COOKIE, self.session_id.clone()))
+                .http_only(true)
+                .max_age(max_age)
+                .build(),
+        ]
+    }
+}
+
+/// Opens an impersonation of the user by `impersonator`, for `reason`, as session `session_id`
+/// starting at `now`. Returns `None` when there is no such user, or the account is disabled. The session row is kept after the impersonation ends,
+/// as its audit record.
+pub async fn impersonation_start(
+    db: &mut sqlx::SqliteConnection,
+    user_id: i64,
+    impersonator: &str,
+    reason: &str,
+    meta: &RequestMeta,
+    session_id: String,
+    now: DateTime<Utc>,
+) -> Result<Option<Impersonation>, sqlx::Error> {
+    let user = sqlx::query!("SELECT id FROM users WHERE id = ? AND disabled_at IS NULL", user_id)
+        .fetch_optional(&mut *db)
+        .instrument(query_span("users.impersonate"))
+        .await?;
+    if user.is_none() {
+        return Ok(None);
+    }
+
+    let impersonation = Impersonation {
+        session_id,
+        user_id,
+        impersonator: impersonator.to_owned(),
+        expires_at: now + chrono::TimeDelta::minutes(impersonation_ttl_mins()),
+    };
+    let location = meta.ip.as_deref().and_then(ip_locate);
+    sqlx::query!(
+        "INSERT INTO sessions (id, user_id, created_at, expires_at, ip, user_agent, location, impersonator, \
+        impersonation_reason, auth_method) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, 'impersonation')",
+        impersonation.session_id,
+        user_id,
+        now,
+        impersonation.expires_at,
+        meta.ip,
+        meta.user_agent,
+        location,
+        impersonation.impersonator,
+        reason
+    )
+    .execute(&mut *db)
+    .instrument(query_span("sessions.impersonate"))
+    .await?;
+    Ok(Some(impersonation))
+}
+
+/// Request guard for the impersonation the caller is in: the session of the request is active and
+/// was opened by support staff, see `session_record`. Forwards otherwise, so that an impersonation
+/// ends with its session row, whatever the cookies' max-age.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Impersonation {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match session_record(request).await {
+            Ok(Some(session)) => match &session.impersonator {
+                Some(impersonator) => request::Outcome::Success(Impersonation {
+                    session_id: session.id.clone(),
+                    user_id: session.user_id,
+                    impersonator: impersonator.clone(),
+                    expires_at: session.expires_at,
+                }),
+                None => request::Outcome::Forward(Status::Unauthorized),
+            },
+            Ok(None) => request::Outcome::Forward(Status::Unauthorized),
+            Err(()) => request::Outcome::Error((Status::ServiceUnavailable, "database unavailable")),
+        }
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_response("Impersonation header", |request, response| {
+        Box::pin(async move {
+            // Set for requests that authenticated, as `Authz` looks the session up
+            if let Some(impersonator) = session_record_cached(request).and_then(|s| s.impersonator.clone()) {
+                response.set_header(Header::new(IMPERSONATION_HEADER, impersonator));
+            }
+        })
+    })
+}
@@ -0,0 +1,207 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL;
+use ring::signature::{ED25519, Ed25519KeyPair, KeyPair, UnparsedPublicKey};
+use rocket::fairing::AdHoc;
+use rocket::http::Header;
+use rocket::serde::{Deserialize, Serialize, json};
+use std::sync::OnceLock;
+
+use crate::util::*;
+
+/// How long verifiers may cache the published keys.
+const JWKS_CACHE_CONTROL: &str = "public, max-age=300";
+
+/// Why a token was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenError {
+    /// Not three base64 segments of an EdDSA JWT with the expected claims.
+    Malformed,
+    Expired,
+    /// Signed with a key that is no longer configured, i.e. revoked by rotation.
+    UnknownKey(String),
+    Invalid,
+}
+
+impl std::fmt::Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "token is malformed"),
+            Self::Expired => write!(f, "token has expired"),
+            Self::UnknownKey(id) => write!(f, "token uses unknown key {}", id),
+            Self::Invalid => write!(f, "token signature is invalid"),
+        }
+    }
+}
+
+/// The staff member acting as the user, for tokens issued while impersonating.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TokenActor {
+    pub sub: String,
+}
+
+/// Claims of the tokens handed to sibling services.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TokenClaims {
+    /// The `APP_URL` of the issuing instance.
+    pub iss: String,
+    /// The user id.
+    pub sub: String,
+    pub email: String,
+    pub iat: i64,
+    pub exp: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<TokenActor>,
+}
+
+/// Signs JWTs with Ed25519 (`EdDSA`) so that sibling services can authorize users offline, checking
+/// tokens against the keys published at `/.well-known/jwks.json`.
+///
+/// Keys are loaded from the environment as `<id>:<base64 32-byte seed>` pairs: `JWT_SIGNING_KEY`
+/// signs new tokens and `JWT_SIGNING_KEYS_OLD` (comma-separated) lists retired keys that are still
+/// published until their tokens expire. Without `JWT_SIGNING_KEY` a random key is used, so tokens
+/// stop verifying when the process restarts and each instance publishes its own key.
+pub struct TokenSigner {
+    active: String,
+    keys: Vec<(String, Ed25519KeyPair)>,
+}
+
+fn key_parse(entry: &str) -> (String, Ed25519KeyPair) {
+    let (id, seed) = entry
+        .split_once(':')
+        .unwrap_or_else(|| panic!("JWT signing key must be <id>:<base64 seed>, got {}", entry));
+    let key = BASE64
+        .decode(seed.trim())
+        .ok()
+        .filter(|seed| seed.len() == 32)
+        .and_then(|seed| Ed25519KeyPair::from_seed_unchecked(&seed).ok())
+        .unwrap_or_else(|| panic!("JWT signing key {} must be 32 bytes of base64", id));
+    (id.trim().to_string(), key)
+}
+
+impl TokenSigner {
+    pub fn new(active: &str, old: &[&str]) -> Self {
+        let (active, key) = key_parse(active);
+        let mut keys = vec![(active.clone(), key)];
+        keys.extend(old.iter().map(|entry| key_parse(entry)));
+        Self { active, keys }
+    }
+
+    pub fn from_env() -> Self {
+        // Not `env_list`, which lowercases and would change the seeds
+        let old = config_var("JWT_SIGNING_KEYS_OLD").unwrap_or_default();
+        let old = old
+            .split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+            .collect::<Vec<_>>();
+        match config_var("JWT_SIGNING_KEY") {
+            Some(active) => Self::new(&active, &old),
+            None => {
+                tracing::warn!("JWT_SIGNING_KEY is not set, tokens will not verify after a restart");
+                let seed = BASE64.encode(rand::random::<[u8; 32]>());
+                Self::new(&format!("ephemeral-{}:{}", nanoid::nanoid!(8), seed), &old)
+            }
+        }
+    }
+
+    fn key(&self, key_id: &str) -> Option<&Ed25519KeyPair> {
+        self.keys.iter().find(|(id, _)| id == key_id).map(|(_, key)| key)
+    }
+
+    /// Returns the compact JWT of `claims`, signed with the active key.
+    pub fn sign(&self, claims: &TokenClaims) -> String {
+        let header = json::json!({ "alg": "EdDSA", "typ": "JWT", "kid": self.active });
+        let signing_input = format!(
+            "{}.{}",
+            BASE64_URL.encode(header.to_string()),
+            BASE64_URL.encode(json::to_string(claims).expect("Failed to serialize claims"))
+        );
+        let signature = self
+            .key(&self.active)
+            .expect("active key")
+            .sign(signing_input.as_bytes());
+        format!("{}.{}", signing_input, BASE64_URL.encode(signature))
+    }
+
+    /// Checks a token made by `sign`, as of `now`, the way a sibling service would with the JWKS.
+    pub fn verify(&self, token: &str, now: DateTime<Utc>) -> Result<TokenClaims, TokenError> {
+        let mut segments = token.split('.');
+        let (Some(header), Some(claims), Some(signature), None) =
+            (segments.next(), segments.next(), segments.next(), segments.next())
+        else {
+            return Err(TokenError::Malformed);
+        };
+        let decode = |segment: &str| BASE64_URL.decode(segment).map_err(|_| TokenError::Malformed);
+        let header = json::from_slice::<json::Value>(&decode(header)?).map_err(|_| TokenError::Malformed)?;
+        let key_id = match (header["alg"].as_str(), header["kid"].as_str()) {
+            (Some("EdDSA"), Some(key_id)) => key_id,
+            _ => return Err(TokenError::Malformed),
+        };
+        let key = self
+            .key(key_id)
+            .ok_or_else(|| TokenError::UnknownKey(key_id.to_string()))?;
+        let signing_input = &token[..token.len() - signature.len() - 1];
+        UnparsedPublicKey::new(&ED25519, key.public_key().as_ref())
+            .verify(signing_input.as_bytes(), &decode(signature)?)
+            .map_err(|_| TokenError::Invalid)?;
+        let claims = json::from_slice::<TokenClaims>(&decode(claims)?).map_err(|_| TokenError::Malformed)?;
+        match now.timestamp() < claims.exp {
+            true => Ok(claims),
+            false => Err(TokenError::Expired),
+        }
+    }
+
+    /// The public keys of every configured key, as a JSON Web Key Set.
+    pub fn jwks(&self) -> json::Value {
+        let keys = self
+            .keys
+            .iter()
+            .map(|(id, key)| {
+                json::json!({
+                    "kty": "OKP",
+                    "crv": "Ed25519",
+                    "alg": "EdDSA",
+                    "use": "sig",
+                    "kid": id,
+                    "x": BASE64_URL.encode(key.public_key().as_ref()),
+                })
+            })
+            .collect::<Vec<_>>();
+        json::json!({ "keys": keys })
+    }
+}
+
+/// Returns the process-wide `TokenSigner`.
+pub fn token_signer() -> &'static TokenSigner {
+    static SIGNER: OnceLock<TokenSigner> = OnceLock::new();
+    SIGNER.get_or_init(TokenSigner::from_env)
+}
+
+/// Lifetime of issued tokens, from `JWT_TTL_SECS` (default 5 minutes).
+pub fn token_ttl() -> i64 {
+    app_config().auth.jwt_ttl_secs
+}
+
+#[derive(Responder)]
+#[response(content_type = "json")]
+struct Jwks(String, Header<'static>);
+
+/// Publishes the keys tokens are verified with.
+#[get("/jwks.json")]
+fn jwks() -> Jwks {
+    Jwks(
+        token_signer().jwks().to_string(),
+        Header::new("Cache-Control", JWKS_CACHE_CONTROL),
+    )
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("JWT stage", |rocket| async {
+        // Loads the keys now, so that a malformed one fails startup
+        token_signer();
+        rocket.mount("/.well-known", routes![jwks])
+    })
+}
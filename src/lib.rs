@@ -1,9 +1,63 @@
 #[macro_use]
 extern crate rocket;
 
+pub mod api;
+#[cfg(feature = "embed")]
+pub mod assets;
+pub mod authz;
+pub mod blobs;
+#[cfg(feature = "redis")]
+pub mod bus;
+pub mod cache;
+pub mod challenge;
+pub mod chaos;
+pub mod client_info;
+pub mod clock;
+pub mod coalesce;
+pub mod config;
+pub mod cron;
+pub mod crypto;
+pub mod csrf;
 pub mod db;
+pub mod email;
+pub mod error;
+pub mod events;
+pub mod filter;
+pub mod flags;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod handlers;
+pub mod health;
+pub mod hlc;
+pub mod impersonation;
+pub mod jwt;
+pub mod maintenance;
+pub mod merge;
+pub mod metrics;
+pub mod oauth;
+pub mod otp;
+pub mod panics;
+pub mod payload;
+pub mod previews;
+pub mod ratelimit;
+pub mod recurring;
+pub mod reload;
+pub mod reminders;
+pub mod replication;
+pub mod retry;
+pub mod scan;
+pub mod scope;
+#[cfg(feature = "secrets")]
+pub mod secrets;
+pub mod signing;
+pub mod slowquery;
+pub mod sms;
+pub mod summary;
+pub mod telemetry;
+pub mod timeout;
+pub mod tls;
 pub mod util;
+pub mod variants;
 
 #[cfg(test)]
 pub mod tests;
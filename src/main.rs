@@ -3,55 +3,82 @@ extern crate rocket;
 
 use chrono::{DateTime, Utc};
 use rocket::fairing::{Fairing, Info, Kind};
-use rocket::http::Status;
-use rocket::serde::json;
+use rocket::http::Header;
 use rocket::{Data, Request, Response};
-use rocket_sqlx::{db, handlers, util::*};
+use rocket_sqlx::client_info::ClientIp;
+use rocket_sqlx::telemetry::{
+    self, REQUEST_LOG_TARGET, RequestId, RequestLog, RequestLogFormat, RequestSpan, RequestUser, request_log_format,
+};
+use rocket_sqlx::{
+    api, blobs, clock, coalesce, config, db, email, error, events, flags, handlers, health, impersonation, jwt,
+    maintenance, metrics, oauth, panics, previews, ratelimit, recurring, reload, reminders, scan, sms, tls,
+};
 
 #[launch]
 fn rocket() -> _ {
     dotenv::dotenv().expect("Failed to load .env file");
-    env_get(); // asserts all are there
+    #[cfg(feature = "secrets")]
+    if let Err(e) = rocket_sqlx::secrets::secrets_load() {
+        eprintln!("Failed to load secrets: {}", e);
+        std::process::exit(1);
+    }
+    let env = config::env_check(); // exits with a report of every missing or invalid variable
+    telemetry::tracing_init();
+    panics::panic_hook_install();
 
-    rocket::build()
+    // The key may come from a _FILE or a secrets store, which Rocket doesn't read itself
+    let figment = rocket::Config::figment().merge(("secret_key", env.rocket_secret_key.as_str()));
+    let rocket = rocket::custom(figment)
         .attach(RequestLogger)
-        .register("/", catchers![c401, c404, c422, c500])
+        .register("/", error::catchers())
+        .attach(config::stage())
+        .attach(api::stage())
+        .attach(blobs::stage())
+        .attach(clock::stage())
+        .attach(coalesce::stage())
         .attach(db::stage())
+        .attach(email::stage())
+        .attach(events::stage())
+        .attach(flags::stage())
+        .attach(impersonation::stage())
+        .attach(jwt::stage())
+        .attach(maintenance::stage())
+        .attach(handlers::admin::stage())
+        .attach(handlers::attachments::stage())
         .attach(handlers::posts::stage())
+        .attach(handlers::calendar::stage())
+        .attach(handlers::comments::stage())
+        .attach(handlers::dev::stage())
+        .attach(handlers::orgs::stage())
         .attach(handlers::session::stage())
-}
-
-#[catch(401)]
-fn c401() -> (Status, json::Value) {
-    (Status::Unauthorized, json::json!({ "message": "Unauthorized" }))
-}
+        .attach(handlers::shares::stage())
+        .attach(handlers::templates::stage())
+        .attach(handlers::users::stage())
+        .attach(health::stage())
+        .attach(metrics::stage())
+        .attach(oauth::stage())
+        .attach(previews::stage())
+        .attach(ratelimit::stage())
+        .attach(recurring::stage())
+        .attach(reload::stage())
+        .attach(reminders::stage())
+        .attach(scan::stage())
+        .attach(sms::stage())
+        .attach(tls::stage());
 
-#[catch(404)]
-fn c404() -> (Status, json::Value) {
-    (Status::NotFound, json::json!({ "message": "Not found" }))
-}
+    #[cfg(feature = "graphql")]
+    let rocket = rocket.attach(handlers::graphql::stage());
+    #[cfg(feature = "grpc")]
+    let rocket = rocket.attach(rocket_sqlx::grpc::stage());
+    #[cfg(feature = "embed")]
+    let rocket = rocket.attach(rocket_sqlx::assets::stage());
 
-#[catch(422)]
-fn c422() -> (Status, json::Value) {
-    (
-        Status::UnprocessableEntity,
-        json::json!({ "message": "Inputs are invalid" }),
-    )
-}
-
-#[catch(500)]
-fn c500() -> (Status, json::Value) {
-    (
-        Status::InternalServerError,
-        json::json!({ "message": "Internal Server Error" }),
-    )
+    rocket
 }
 
 struct RequestLogger;
 struct RequestLoggerCache {
     start: DateTime<Utc>,
-    method: String,
-    uri: String,
 }
 #[rocket::async_trait]
 impl Fairing for RequestLogger {
@@ -63,27 +90,54 @@ impl Fairing for RequestLogger {
     }
 
     async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
-        let method = request.method().to_string();
-        let uri = request.uri().to_string();
+        let span = tracing::info_span!(
+            "request",
+            method = %request.method(),
+            uri = %request.uri(),
+            request_id = RequestId::of(request),
+            client_ip = ClientIp::of(request).map(|ip| ip.to_string()),
+            user_id = tracing::field::Empty,
+            route = tracing::field::Empty,
+        );
         let start = Utc::now();
-        request.local_cache(|| RequestLoggerCache { start, method, uri });
+        request.local_cache(|| RequestSpan(span));
+        request.local_cache(|| RequestLoggerCache { start });
     }
 
     async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
-        let local_cache = request.local_cache(|| RequestLoggerCache {
-            start: Utc::now(),
-            method: "UNKNOWN".to_string(),
-            uri: "UNKNOWN".to_string(),
-        });
-        let duration = (Utc::now() - local_cache.start).num_milliseconds();
+        let local_cache = request.local_cache(|| RequestLoggerCache { start: Utc::now() });
+        let now = Utc::now();
+        response.set_header(Header::new(RequestId::HEADER, RequestId::of(request).to_owned()));
 
-        println!(
-            "{} {} {} {} {}ms",
-            local_cache.start.to_rfc3339(),
-            local_cache.method,
-            local_cache.uri,
-            response.status().code,
-            duration
-        );
+        if let Some(name) = request.route().and_then(|route| route.name.as_deref()) {
+            RequestSpan::of(request).record("route", name);
+        }
+        let headers = request.headers();
+        let log = RequestLog {
+            at: now,
+            request_id: RequestId::of(request).to_owned(),
+            method: request.method().to_string(),
+            uri: request.uri().to_string(),
+            status: response.status().code,
+            bytes: response.body_mut().size().await,
+            duration_ms: (now - local_cache.start).num_milliseconds(),
+            user_id: RequestUser::of(request),
+            client_ip: ClientIp::of(request),
+            user_agent: headers.get_one("User-Agent").map(str::to_owned),
+            referer: headers.get_one("Referer").map(str::to_owned),
+        };
+        match request_log_format() {
+            RequestLogFormat::Plain => RequestSpan::of(request).in_scope(|| {
+                tracing::info!(
+                    status = log.status,
+                    duration_ms = log.duration_ms,
+                    bytes = log.bytes,
+                    user_agent = log.user_agent,
+                    "request completed"
+                );
+            }),
+            RequestLogFormat::Json => tracing::info!(target: REQUEST_LOG_TARGET, "{}", log.to_json()),
+            RequestLogFormat::Common => tracing::info!(target: REQUEST_LOG_TARGET, "{}", log.to_common()),
+        }
     }
 }
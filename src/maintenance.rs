@@ -0,0 +1,117 @@
+use rocket::fairing::AdHoc;
+use rocket::http::{Method, Status};
+use rocket::route::{self, Handler, Route};
+use rocket::{Data, Request};
+use std::sync::{Arc, RwLock};
+
+use crate::api::{API_LEGACY, API_V1, request_reads};
+use crate::error::{ApiError, ErrorCode};
+use crate::util::*;
+
+/// Maintenance mode, e.g. while a migration or a restore runs: API writes answer a 503 with
+/// `Retry-After`, while reads keep working. The admin API is left open so that the mode can be
+/// turned off again by a config reload.
+///
+/// - `MAINTENANCE_MODE`: on or off (off)
+/// - `MAINTENANCE_MESSAGE`: the message of the 503s
+/// - `MAINTENANCE_RETRY_AFTER_SECS`: sent in `Retry-After` (300)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaintenanceConfig {
+    pub enabled: bool,
+    pub message: Option<String>,
+    pub retry_after_secs: u64,
+}
+
+impl MaintenanceConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_parse_or("MAINTENANCE_MODE", false),
+            message: config_var("MAINTENANCE_MESSAGE").filter(|message| !message.trim().is_empty()),
+            retry_after_secs: env_parse_or("MAINTENANCE_RETRY_AFTER_SECS", 300),
+        }
+    }
+}
+
+/// Managed state holding the current `MaintenanceConfig`. Clones share it.
+#[derive(Debug, Clone)]
+pub struct Maintenance(Arc<RwLock<MaintenanceConfig>>);
+
+impl Maintenance {
+    pub fn new(config: MaintenanceConfig) -> Self {
+        Self(Arc::new(RwLock::new(config)))
+    }
+
+    pub fn config(&self) -> MaintenanceConfig {
+        self.0.read().expect("maintenance lock poisoned").clone()
+    }
+
+    pub fn reconfigure(&self, config: MaintenanceConfig) {
+        *self.0.write().expect("maintenance lock poisoned") = config;
+    }
+
+    /// The error refusing a request to `path` with `method`, when maintenance mode is on and it
+    /// is a write outside the admin API.
+    pub fn refusal(&self, method: Method, path: &str) -> Option<ApiError> {
+        let path = path
+            .strip_prefix(API_V1)
+            .or_else(|| path.strip_prefix(API_LEGACY))
+            .unwrap_or(path);
+        if request_reads(method, path) || path == "/admin" || path.starts_with("/admin/") {
+            return None;
+        }
+        self.write_refusal()
+    }
+
+    /// The error refusing a write when maintenance mode is on, for writes that can't be told by
+    /// their route, e.g. GraphQL mutations and gRPC pushes.
+    pub fn write_refusal(&self) -> Option<ApiError> {
+        let config = self.config();
+        if !config.enabled {
+            return None;
+        }
+        let message = config
+            .message
+            .unwrap_or_else(|| "Down for maintenance, changes can't be saved for now.".into());
+        Some(
+            ApiError::new(Status::ServiceUnavailable, ErrorCode::Maintenance, message)
+                .retry_after(config.retry_after_secs),
+        )
+    }
+}
+
+/// Handler wrapper answering with the maintenance 503 while the mode is on.
+#[derive(Clone)]
+struct MaintenanceHandler {
+    inner: Box<dyn Handler>,
+}
+
+#[rocket::async_trait]
+impl Handler for MaintenanceHandler {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        let refusal = request
+            .rocket()
+            .state::<Maintenance>()
+            .and_then(|maintenance| maintenance.refusal(request.method(), request.uri().path().as_str()));
+        match refusal {
+            Some(error) => route::Outcome::from(request, error),
+            None => self.inner.handle(request, data).await,
+        }
+    }
+}
+
+/// Wraps the handlers of `routes` with the maintenance mode check.
+pub fn maintenance_routes(routes: Vec<Route>) -> Vec<Route> {
+    routes
+        .into_iter()
+        .map(|mut route| {
+            route.handler = Box::new(MaintenanceHandler { inner: route.handler });
+            route
+        })
+        .collect()
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Maintenance stage", |rocket| async {
+        manage_default(rocket, |_| Maintenance::new(MaintenanceConfig::from_env()))
+    })
+}
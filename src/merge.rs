@@ -0,0 +1,81 @@
+/// Merges two edits of the same text line by line, diff3-style: lines changed on one side only take
+/// that side's change, lines changed the same way on both sides are kept once. Returns `None` when
+/// both sides changed the same lines differently.
+pub fn text_merge(base: &str, ours: &str, theirs: &str) -> Option<String> {
+    let base = base.split_inclusive('\n').collect::<Vec<_>>();
+    let ours = ours.split_inclusive('\n').collect::<Vec<_>>();
+    let theirs = theirs.split_inclusive('\n').collect::<Vec<_>>();
+    let to_ours = lines_match(&base, &ours);
+    let to_theirs = lines_match(&base, &theirs);
+
+    let mut merged = String::new();
+    let (mut i, mut a, mut b) = (0, 0, 0);
+    loop {
+        // The chunk runs up to the next base line that both sides kept
+        let stable = (i..base.len()).find_map(|k| Some((k, to_ours[k]?, to_theirs[k]?)));
+        let (k, ka, kb) = stable.unwrap_or((base.len(), ours.len(), theirs.len()));
+        let chunk = chunk_merge(&base[i..k], &ours[a..ka], &theirs[b..kb])?;
+        merged.extend(chunk.iter().copied());
+        if stable.is_none() {
+            return Some(merged);
+        }
+        merged.push_str(base[k]);
+        (i, a, b) = (k + 1, ka + 1, kb + 1);
+    }
+}
+
+/// Resolves a chunk of lines that differs from `base` on at least one side.
+fn chunk_merge<'a, 'l>(base: &[&'l str], ours: &'a [&'l str], theirs: &'a [&'l str]) -> Option<&'a [&'l str]> {
+    if ours == base {
+        Some(theirs)
+    } else if theirs == base || ours == theirs {
+        Some(ours)
+    } else {
+        None
+    }
+}
+
+/// Returns, for each line of `a`, the line of `b` it is paired with in a longest common
+/// subsequence, if any.
+fn lines_match(a: &[&str], b: &[&str]) -> Vec<Option<usize>> {
+    // Edits are mostly local, so pair the common head and tail off before the quadratic part
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let mut matched = vec![None; a.len()];
+    for (i, line) in matched.iter_mut().enumerate().take(prefix) {
+        *line = Some(i);
+    }
+    for k in 1..=suffix {
+        matched[a.len() - k] = Some(b.len() - k);
+    }
+
+    let a_mid = &a[prefix..a.len() - suffix];
+    let b_mid = &b[prefix..b.len() - suffix];
+    // lengths[i][j] is the length of a longest common subsequence of a_mid[i..] and b_mid[j..]
+    let mut lengths = vec![vec![0u32; b_mid.len() + 1]; a_mid.len() + 1];
+    for i in (0..a_mid.len()).rev() {
+        for j in (0..b_mid.len()).rev() {
+            lengths[i][j] = match a_mid[i] == b_mid[j] {
+                true => lengths[i + 1][j + 1] + 1,
+                false => lengths[i + 1][j].max(lengths[i][j + 1]),
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < a_mid.len() && j < b_mid.len() {
+        if a_mid[i] == b_mid[j] {
+            matched[prefix + i] = Some(prefix + j);
+            (i, j) = (i + 1, j + 1);
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    matched
+}
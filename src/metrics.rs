@@ -0,0 +1,172 @@
+use rocket::fairing::AdHoc;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+
+use crate::handlers::admin::AdminCtx;
+
+/// Upper bounds (in seconds) of the buckets used by every histogram.
+const HISTOGRAM_BUCKETS: [f64; 10] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0, 5.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    buckets: [u64; HISTOGRAM_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Number(f64),
+    Histogram(Histogram),
+}
+
+#[derive(Debug)]
+struct Family {
+    kind: Kind,
+    help: &'static str,
+    series: BTreeMap<String, Value>,
+}
+
+/// A minimal in-process metrics registry rendered in the Prometheus text format by `GET /metrics`.
+/// Series are identified by a metric name plus label pairs.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    families: Mutex<BTreeMap<&'static str, Family>>,
+}
+
+/// Renders label pairs as `{a="1",b="2"}`, or an empty string when there are none.
+fn labels_render(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let pairs = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect::<Vec<_>>();
+    format!("{{{}}}", pairs.join(","))
+}
+
+impl Metrics {
+    fn update(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        kind: Kind,
+        labels: &[(&str, &str)],
+        f: impl FnOnce(&mut Value),
+    ) {
+        let mut families = self.families.lock().expect("metrics lock poisoned");
+        let family = families.entry(name).or_insert_with(|| Family {
+            kind,
+            help,
+            series: BTreeMap::new(),
+        });
+        debug_assert_eq!(family.kind, kind, "metric {} registered with two kinds", name);
+        let value = family
+            .series
+            .entry(labels_render(labels))
+            .or_insert_with(|| match kind {
+                Kind::Histogram => Value::Histogram(Histogram::default()),
+                _ => Value::Number(0.0),
+            });
+        f(value);
+    }
+
+    /// Adds `by` to a counter.
+    pub fn counter_add(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)], by: u64) {
+        self.update(name, help, Kind::Counter, labels, |value| {
+            if let Value::Number(n) = value {
+                *n += by as f64;
+            }
+        });
+    }
+
+    /// Increments a counter by one.
+    pub fn counter_inc(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)]) {
+        self.counter_add(name, help, labels, 1);
+    }
+
+    /// Sets a gauge to the given value.
+    pub fn gauge_set(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)], to: f64) {
+        self.update(name, help, Kind::Gauge, labels, |value| {
+            if let Value::Number(n) = value {
+                *n = to;
+            }
+        });
+    }
+
+    /// Records an observation, in seconds, in a histogram.
+    pub fn histogram_observe(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)], secs: f64) {
+        self.update(name, help, Kind::Histogram, labels, |value| {
+            if let Value::Histogram(h) = value {
+                for (bucket, bound) in h.buckets.iter_mut().zip(HISTOGRAM_BUCKETS) {
+                    if secs <= bound {
+                        *bucket += 1;
+                    }
+                }
+                h.sum += secs;
+                h.count += 1;
+            }
+        });
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let families = self.families.lock().expect("metrics lock poisoned");
+        let mut out = String::new();
+        for (name, family) in families.iter() {
+            let kind = match family.kind {
+                Kind::Counter => "counter",
+                Kind::Gauge => "gauge",
+                Kind::Histogram => "histogram",
+            };
+            let _ = writeln!(out, "# HELP {} {}", name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, value) in &family.series {
+                match value {
+                    Value::Number(n) => {
+                        let _ = writeln!(out, "{}{} {}", name, labels, n);
+                    }
+                    Value::Histogram(h) => {
+                        // Bucket series need an extra `le` label merged into the existing ones
+                        let inner = labels.trim_start_matches('{').trim_end_matches('}');
+                        let sep = if inner.is_empty() { "" } else { "," };
+                        for (count, bound) in h.buckets.iter().zip(HISTOGRAM_BUCKETS) {
+                            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, inner, sep, bound, count);
+                        }
+                        let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, inner, sep, h.count);
+                        let _ = writeln!(out, "{}_sum{} {}", name, labels, h.sum);
+                        let _ = writeln!(out, "{}_count{} {}", name, labels, h.count);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Returns the process-wide metrics registry.
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::default)
+}
+
+/// Renders the registry. Requires the admin token, like the admin API.
+#[get("/")]
+fn index(_admin: AdminCtx) -> String {
+    metrics().render()
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Metrics stage", |rocket| async {
+        rocket.mount("/metrics", routes![index])
+    })
+}
@@ -0,0 +1,550 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL;
+use chrono::TimeDelta;
+use nanoid::nanoid;
+use regex::Regex;
+use rocket::fairing::AdHoc;
+use rocket::form::{Form, FromForm};
+use rocket::http::uri::Origin;
+use rocket::http::{Header, Status};
+use rocket::response::Redirect;
+use rocket::serde::{Serialize, json};
+use rocket::{Route, State};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+use tracing::Instrument;
+
+use crate::authz::{Credential, TokenGrant, scopes_parse};
+use crate::clock::Timekeeper;
+use crate::db::*;
+use crate::error::{ApiError, ErrorCode};
+use crate::impersonation::Impersonation;
+use crate::panics::panic_routes;
+use crate::ratelimit::rate_limit_routes;
+use crate::timeout::timeout_routes;
+use crate::util::*;
+
+/// Lifetime of an authorization code, which the app exchanges right after the redirect.
+pub const OAUTH_CODE_TTL_SECS: i64 = 120;
+/// Lifetime of an access token.
+pub const OAUTH_ACCESS_TTL_SECS: i64 = 3600;
+/// Lifetime of a refresh token. Each refresh issues a new one, so apps in use stay signed in.
+pub const OAUTH_REFRESH_TTL_DAYS: i64 = 30;
+
+fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Checks a PKCE verifier against the `S256` challenge it was made for.
+pub fn pkce_verify(verifier: &str, challenge: &str) -> bool {
+    let valid = (43..=128).contains(&verifier.len())
+        && verifier
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._~".contains(&b));
+    valid && BASE64_URL.encode(Sha256::digest(verifier.as_bytes())) == challenge
+}
+
+/// Percent-encodes a query parameter value, keeping only unreserved characters.
+fn query_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            true => (b as char).to_string(),
+            false => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Appends `params` to the query of `uri`.
+fn uri_with_query(uri: &str, params: &[(&str, Option<&str>)]) -> String {
+    let query = params
+        .iter()
+        .filter_map(|(name, value)| value.map(|value| format!("{}={}", name, query_encode(value))))
+        .collect::<Vec<_>>()
+        .join("&");
+    let separator = if uri.contains('?') { '&' } else { '?' };
+    format!("{}{}{}", uri, separator, query)
+}
+
+/// Whether `uri` can be registered as a redirect URI: absolute, e.g. `https://app.example.com/cb`
+/// or a custom scheme of a mobile app (`com.example.notes:/oauth`), without a fragment.
+pub fn redirect_uri_is_valid(uri: &str) -> bool {
+    static REDIRECT_URI_RE: OnceLock<Regex> = OnceLock::new();
+    let regex = REDIRECT_URI_RE.get_or_init(|| {
+        Regex::new(r"^[A-Za-z][A-Za-z0-9+.-]*:[^\s#]+$").expect("failed to compile redirect URI regex")
+    });
+    uri.len() <= 2048 && regex.is_match(uri)
+}
+
+/// A first-party app allowed to get tokens.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct OAuthClient {
+    pub id: String,
+    pub name: String,
+    /// Where users may be sent back to with a code, compared exactly.
+    pub redirect_uris: Vec<String>,
+    /// The most a client may ask for.
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl OAuthClient {
+    fn from_row(
+        id: String,
+        name: String,
+        redirect_uris: String,
+        scopes: String,
+        created_at: DateTime<Utc>,
+        revoked_at: Option<DateTime<Utc>>,
+    ) -> Self {
+        let split = |text: String| text.split_whitespace().map(str::to_owned).collect();
+        Self {
+            id,
+            name,
+            redirect_uris: split(redirect_uris),
+            scopes: split(scopes),
+            created_at,
+            revoked_at,
+        }
+    }
+}
+
+/// Registers client `id` at `now`. The caller validates `redirect_uris` and `scopes`.
+pub async fn client_create(
+    db: &mut sqlx::SqliteConnection,
+    name: &str,
+    redirect_uris: &[String],
+    scopes: &[String],
+    id: String,
+    now: DateTime<Utc>,
+) -> Result<OAuthClient, sqlx::Error> {
+    let client = OAuthClient {
+        id,
+        name: name.to_owned(),
+        redirect_uris: redirect_uris.to_vec(),
+        scopes: scopes.to_vec(),
+        created_at: now,
+        revoked_at: None,
+    };
+    let (redirect_uris, scopes) = (client.redirect_uris.join(" "), client.scopes.join(" "));
+    sqlx::query!(
+        "INSERT INTO oauth_clients (id, name, redirect_uris, scopes, created_at) VALUES (?, ?, ?, ?, ?)",
+        client.id,
+        client.name,
+        redirect_uris,
+        scopes,
+        client.created_at
+    )
+    .execute(db)
+    .instrument(query_span("oauth_clients.insert"))
+    .await?;
+    Ok(client)
+}
+
+/// Lists every client, revoked ones included, newest first.
+pub async fn clients_list(db: &mut sqlx::SqliteConnection) -> Result<Vec<OAuthClient>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT id, name, redirect_uris, scopes, created_at AS \"created_at: DateTime<Utc>\", \
+        revoked_at AS \"revoked_at: DateTime<Utc>\" FROM oauth_clients ORDER BY created_at DESC"
+    )
+    .fetch_all(db)
+    .instrument(query_span("oauth_clients.list"))
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| OAuthClient::from_row(r.id, r.name, r.redirect_uris, r.scopes, r.created_at, r.revoked_at))
+        .collect())
+}
+
+/// Returns a client that is not revoked.
+pub async fn client_get(db: &mut sqlx::SqliteConnection, id: &str) -> Result<Option<OAuthClient>, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT id, name, redirect_uris, scopes, created_at AS \"created_at: DateTime<Utc>\", \
+        revoked_at AS \"revoked_at: DateTime<Utc>\" FROM oauth_clients WHERE id = ? AND revoked_at IS NULL",
+        id
+    )
+    .fetch_optional(db)
+    .instrument(query_span("oauth_clients.get"))
+    .await?;
+    Ok(row.map(|r| OAuthClient::from_row(r.id, r.name, r.redirect_uris, r.scopes, r.created_at, r.revoked_at)))
+}
+
+/// Revokes a client at `now`, with every token and pending code it was issued. Returns `false`
+/// when there is no such client or it was already revoked.
+pub async fn client_revoke(db: &mut sqlx::SqliteConnection, id: &str, now: DateTime<Utc>) -> Result<bool, sqlx::Error> {
+    let revoked = sqlx::query!(
+        "UPDATE oauth_clients SET revoked_at = ? WHERE id = ? AND revoked_at IS NULL",
+        now,
+        id
+    )
+    .execute(&mut *db)
+    .instrument(query_span("oauth_clients.revoke"))
+    .await?
+    .rows_affected();
+    if revoked == 0 {
+        return Ok(false);
+    }
+    sqlx::query!(
+        "UPDATE oauth_tokens SET revoked_at = ? WHERE client_id = ? AND revoked_at IS NULL",
+        now,
+        id
+    )
+    .execute(&mut *db)
+    .instrument(query_span("oauth_tokens.revoke_client"))
+    .await?;
+    sqlx::query!("DELETE FROM oauth_codes WHERE client_id = ?", id)
+        .execute(&mut *db)
+        .instrument(query_span("oauth_codes.delete_client"))
+        .await?;
+    Ok(true)
+}
+
+/// Returns the grant of an access token that has not expired by `now`, of a client that was not
+/// revoked.
+pub async fn oauth_access_lookup(
+    db: &sqlx::SqlitePool,
+    token: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<TokenGrant>, sqlx::Error> {
+    let hash = token_hash(token);
+    let row = sqlx::query!(
+        "SELECT t.user_id, t.client_id, t.scope FROM oauth_tokens t JOIN oauth_clients c ON c.id = t.client_id \
+        WHERE t.access_hash = ? AND t.access_expires_at > ? AND t.revoked_at IS NULL AND c.revoked_at IS NULL",
+        hash,
+        now
+    )
+    .fetch_optional(db)
+    .instrument(query_span("oauth_tokens.by_access"))
+    .await?;
+    Ok(row.map(|row| TokenGrant {
+        user_id: row.user_id,
+        credential: Credential::OAuth {
+            client_id: row.client_id,
+        },
+        scopes: row.scope.split_whitespace().map(str::to_owned).collect(),
+    }))
+}
+
+/// A fresh access and refresh token pair issued at `now`, and the body of the token response it
+/// makes.
+struct TokenPair {
+    access: String,
+    refresh: String,
+    now: DateTime<Utc>,
+}
+
+impl TokenPair {
+    fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            access: nanoid!(43),
+            refresh: nanoid!(43),
+            now,
+        }
+    }
+
+    fn access_expires_at(&self) -> DateTime<Utc> {
+        self.now + TimeDelta::seconds(OAUTH_ACCESS_TTL_SECS)
+    }
+
+    fn refresh_expires_at(&self) -> DateTime<Utc> {
+        self.now + TimeDelta::days(OAUTH_REFRESH_TTL_DAYS)
+    }
+
+    fn body(&self, scope: &str) -> json::Value {
+        json::json!({
+            "access_token": self.access,
+            "token_type": "Bearer",
+            "expires_in": OAUTH_ACCESS_TTL_SECS,
+            "refresh_token": self.refresh,
+            "scope": scope,
+        })
+    }
+}
+
+/// A response of the token endpoint, which must not be cached.
+#[derive(Responder)]
+pub struct TokenResponse {
+    inner: (Status, json::Value),
+    cache_control: Header<'static>,
+}
+
+impl TokenResponse {
+    fn new(status: Status, body: json::Value) -> Self {
+        Self {
+            inner: (status, body),
+            cache_control: Header::new("Cache-Control", "no-store"),
+        }
+    }
+
+    /// An error in the format of RFC 6749, e.g. `invalid_grant`.
+    fn error(error: &str, description: &str) -> Self {
+        let status = match error {
+            "invalid_client" => Status::Unauthorized,
+            _ => Status::BadRequest,
+        };
+        Self::new(
+            status,
+            json::json!({ "error": error, "error_description": description }),
+        )
+    }
+}
+
+#[derive(FromForm)]
+struct AuthorizeParams<'r> {
+    response_type: Option<&'r str>,
+    client_id: Option<&'r str>,
+    redirect_uri: Option<&'r str>,
+    scope: Option<&'r str>,
+    state: Option<&'r str>,
+    code_challenge: Option<&'r str>,
+    code_challenge_method: Option<&'r str>,
+}
+
+/// Sends the user back to the client with a code for the requested scopes. Apps are first-party, so
+/// signed-in users are not asked to consent; others are sent to the web app's login first, which
+/// returns them here. Errors about the client or its redirect URI are answered with a 400, as the
+/// user can't be sent back to an unverified app; the others are sent back to the app, including
+/// `access_denied` while impersonating, as the app's tokens would outlive the impersonation.
+#[get("/authorize?<params..>")]
+async fn authorize(
+    mut db: Connection<Db>,
+    user: Option<UserCtx>,
+    impersonation: Option<Impersonation>,
+    clock: &State<Timekeeper>,
+    origin: &Origin<'_>,
+    params: AuthorizeParams<'_>,
+) -> Result<Redirect, ApiError> {
+    let AuthorizeParams {
+        response_type,
+        client_id,
+        redirect_uri,
+        scope,
+        state,
+        code_challenge,
+        code_challenge_method,
+    } = params;
+    let bad_request = |message| ApiError::new(Status::BadRequest, ErrorCode::BadRequest, message);
+    let client = match client_id {
+        Some(client_id) => client_get(&mut db, client_id)
+            .await
+            .expect("Failed to fetch OAuth client"),
+        None => None,
+    };
+    let client = client.ok_or_else(|| bad_request("Unknown client_id"))?;
+    let redirect_uri = redirect_uri
+        .filter(|uri| client.redirect_uris.iter().any(|allowed| allowed == uri))
+        .ok_or_else(|| bad_request("redirect_uri is not registered for the client"))?;
+    let refuse = |error: &str| {
+        Ok(Redirect::found(uri_with_query(
+            redirect_uri,
+            &[("error", Some(error)), ("state", state)],
+        )))
+    };
+
+    if response_type != Some("code") {
+        return refuse("unsupported_response_type");
+    }
+    let Some(code_challenge) = code_challenge.filter(|_| code_challenge_method == Some("S256")) else {
+        return refuse("invalid_request");
+    };
+    let scopes = match scope {
+        Some(scope) => scopes_parse(scope),
+        None => Some(client.scopes.clone()),
+    };
+    let Some(scopes) = scopes.filter(|scopes| scopes.iter().all(|scope| client.scopes.contains(scope))) else {
+        return refuse("invalid_scope");
+    };
+    let Some(user) = user else {
+        let login = format!("{}/login", app_url());
+        return Ok(Redirect::found(uri_with_query(
+            &login,
+            &[("next", Some(&origin.to_string()))],
+        )));
+    };
+    if impersonation.is_some() {
+        return refuse("access_denied");
+    }
+
+    let code = nanoid!(32);
+    let (hash, scope) = (token_hash(&code), scopes.join(" "));
+    let expires_at = clock.now() + TimeDelta::seconds(OAUTH_CODE_TTL_SECS);
+    sqlx::query!(
+        "INSERT INTO oauth_codes (code_hash, client_id, user_id, redirect_uri, scope, code_challenge, expires_at) \
+        VALUES (?, ?, ?, ?, ?, ?, ?)",
+        hash,
+        client.id,
+        user.id,
+        redirect_uri,
+        scope,
+        code_challenge,
+        expires_at
+    )
+    .execute(&mut **db)
+    .instrument(query_span("oauth_codes.insert"))
+    .await
+    .expect("Failed to insert OAuth code");
+    tracing::info!("oauth:authorize:{}:{}", client.id, user.id);
+    Ok(Redirect::found(uri_with_query(
+        redirect_uri,
+        &[("code", Some(&code)), ("state", state)],
+    )))
+}
+
+#[derive(FromForm)]
+struct TokenRequestForm<'r> {
+    grant_type: Option<&'r str>,
+    client_id: Option<&'r str>,
+    code: Option<&'r str>,
+    redirect_uri: Option<&'r str>,
+    code_verifier: Option<&'r str>,
+    refresh_token: Option<&'r str>,
+}
+
+/// Exchanges an authorization code and its PKCE verifier, or a refresh token, for an access and
+/// refresh token pair. A refresh token is single use: refreshing replaces both tokens.
+#[post("/token", data = "<form>")]
+async fn token(
+    mut db: Connection<Db>,
+    clock: &State<Timekeeper>,
+    ids: &State<IdSource>,
+    form: Form<TokenRequestForm<'_>>,
+) -> TokenResponse {
+    let Some(client_id) = form.client_id else {
+        return TokenResponse::error("invalid_request", "client_id is required");
+    };
+    let client = client_get(&mut db, client_id)
+        .await
+        .expect("Failed to fetch OAuth client");
+    let Some(client) = client else {
+        return TokenResponse::error("invalid_client", "Unknown client");
+    };
+    let pair = TokenPair::new(clock.now());
+    let (access_hash, refresh_hash) = (token_hash(&pair.access), token_hash(&pair.refresh));
+    let (access_expires_at, refresh_expires_at) = (pair.access_expires_at(), pair.refresh_expires_at());
+
+    match form.grant_type {
+        Some("authorization_code") => {
+            let (Some(code), Some(redirect_uri), Some(verifier)) = (form.code, form.redirect_uri, form.code_verifier)
+            else {
+                return TokenResponse::error("invalid_request", "code, redirect_uri and code_verifier are required");
+            };
+            let hash = token_hash(code);
+            // Deleted whatever happens next, so that a code can't be tried twice
+            let grant = sqlx::query!(
+                "DELETE FROM oauth_codes WHERE code_hash = ? \
+                RETURNING client_id, user_id, redirect_uri, scope, code_challenge, \
+                expires_at AS \"expires_at: DateTime<Utc>\"",
+                hash
+            )
+            .fetch_optional(&mut **db)
+            .instrument(query_span("oauth_codes.consume"))
+            .await
+            .expect("Failed to consume OAuth code");
+            let grant = grant.filter(|grant| {
+                grant.client_id == client.id
+                    && grant.redirect_uri == redirect_uri
+                    && grant.expires_at > pair.now
+                    && pkce_verify(verifier, &grant.code_challenge)
+            });
+            let Some(grant) = grant else {
+                return TokenResponse::error("invalid_grant", "The code is invalid, expired or was already used");
+            };
+            let id = ids.id();
+            sqlx::query!(
+                "INSERT INTO oauth_tokens (id, client_id, user_id, scope, access_hash, access_expires_at, \
+                refresh_hash, refresh_expires_at, created_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                id,
+                client.id,
+                grant.user_id,
+                grant.scope,
+                access_hash,
+                access_expires_at,
+                refresh_hash,
+                refresh_expires_at,
+                pair.now
+            )
+            .execute(&mut **db)
+            .instrument(query_span("oauth_tokens.insert"))
+            .await
+            .expect("Failed to insert OAuth tokens");
+            tracing::info!("oauth:token:{}:{}", client.id, grant.user_id);
+            TokenResponse::new(Status::Ok, pair.body(&grant.scope))
+        }
+        Some("refresh_token") => {
+            let Some(refresh_token) = form.refresh_token else {
+                return TokenResponse::error("invalid_request", "refresh_token is required");
+            };
+            let hash = token_hash(refresh_token);
+            let grant = sqlx::query!(
+                "UPDATE oauth_tokens SET access_hash = ?, access_expires_at = ?, refresh_hash = ?, \
+                refresh_expires_at = ? WHERE refresh_hash = ? AND client_id = ? AND refresh_expires_at > ? \
+                AND revoked_at IS NULL AND user_id IN (SELECT id FROM users WHERE disabled_at IS NULL) \
+                RETURNING user_id, scope",
+                access_hash,
+                access_expires_at,
+                refresh_hash,
+                refresh_expires_at,
+                hash,
+                client.id,
+                pair.now
+            )
+            .fetch_optional(&mut **db)
+            .instrument(query_span("oauth_tokens.refresh"))
+            .await
+            .expect("Failed to refresh OAuth tokens");
+            let Some(grant) = grant else {
+                return TokenResponse::error("invalid_grant", "The refresh token is invalid, expired or revoked");
+            };
+            tracing::info!("oauth:refresh:{}:{}", client.id, grant.user_id);
+            TokenResponse::new(Status::Ok, pair.body(&grant.scope))
+        }
+        _ => TokenResponse::error(
+            "unsupported_grant_type",
+            "grant_type must be authorization_code or refresh_token",
+        ),
+    }
+}
+
+#[derive(FromForm)]
+struct RevokeRequestForm<'r> {
+    token: Option<&'r str>,
+    client_id: Option<&'r str>,
+}
+
+/// Revokes the grant of an access or refresh token of the client, e.g. when the user signs out of
+/// the app. Unknown tokens are not an error, as in RFC 7009.
+#[post("/revoke", data = "<form>")]
+async fn revoke(mut db: Connection<Db>, clock: &State<Timekeeper>, form: Form<RevokeRequestForm<'_>>) -> TokenResponse {
+    let (Some(token), Some(client_id)) = (form.token, form.client_id) else {
+        return TokenResponse::error("invalid_request", "token and client_id are required");
+    };
+    let (hash, now) = (token_hash(token), clock.now());
+    sqlx::query!(
+        "UPDATE oauth_tokens SET revoked_at = ? WHERE (access_hash = ? OR refresh_hash = ?) AND client_id = ? \
+        AND revoked_at IS NULL",
+        now,
+        hash,
+        hash,
+        client_id
+    )
+    .execute(&mut **db)
+    .instrument(query_span("oauth_tokens.revoke"))
+    .await
+    .expect("Failed to revoke OAuth tokens");
+    TokenResponse::new(Status::Ok, json::json!({}))
+}
+
+/// The `/oauth` routes, rate limited like the session routes.
+fn routes() -> Vec<Route> {
+    rate_limit_routes(panic_routes(timeout_routes(
+        "/oauth",
+        routes![authorize, token, revoke],
+    )))
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("OAuth stage", |rocket| async { rocket.mount("/oauth", routes()) })
+}
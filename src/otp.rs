@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use crate::db::sqlx;
+use crate::email::{EmailTemplate, Mailer};
+use crate::sms::SmsSender;
+
+/// Where a user's login codes are sent, from `users.otp_channel`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpChannelKind {
+    Email,
+    /// Texted to the user's verified phone number.
+    Sms,
+}
+
+impl OtpChannelKind {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "email" => Some(Self::Email),
+            "sms" => Some(Self::Sms),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::Sms => "sms",
+        }
+    }
+}
+
+/// Delivers one-time codes, such as login codes, to an address of the channel.
+#[rocket::async_trait]
+pub trait OtpChannel: Send + Sync {
+    async fn code_send(&self, db: &mut sqlx::SqliteConnection, to: &str, code: &str) -> Result<(), String>;
+}
+
+/// The text of a login code, the same whatever the channel.
+fn code_message(code: &str) -> String {
+    format!("Your login code is: {}. It will expire in 5 minutes.", code)
+}
+
+#[rocket::async_trait]
+impl OtpChannel for Mailer {
+    // Never fails: failed emails are recorded by `Mailer::send` instead
+    async fn code_send(&self, db: &mut sqlx::SqliteConnection, to: &str, code: &str) -> Result<(), String> {
+        self.send(db, EmailTemplate::LoginCode, to, "", &code_message(code))
+            .await;
+        Ok(())
+    }
+}
+
+#[rocket::async_trait]
+impl OtpChannel for Arc<dyn SmsSender> {
+    async fn code_send(&self, _: &mut sqlx::SqliteConnection, to: &str, code: &str) -> Result<(), String> {
+        self.send(to, &code_message(code)).await
+    }
+}
@@ -0,0 +1,59 @@
+use rocket::futures::FutureExt;
+use rocket::http::Status;
+use rocket::route::{self, Handler, Route};
+use rocket::{Data, Request};
+use std::backtrace::Backtrace;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+use tracing::Instrument;
+
+use crate::error::ApiError;
+use crate::telemetry::{RequestId, RequestSpan};
+
+/// Replaces the default panic hook, which prints to stderr, with one that logs the panic and a
+/// stack trace through tracing. Panics inside handlers wrapped by `panic_routes` are logged within
+/// the request span, so the record carries the request ID.
+pub fn panic_hook_install() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        panic::set_hook(Box::new(|info| {
+            let backtrace = Backtrace::force_capture();
+            tracing::error!(backtrace = %backtrace, "panic: {}", info);
+        }));
+    });
+}
+
+/// Handler wrapper that turns a panic in the inner handler into the standard JSON 500 response.
+#[derive(Clone)]
+struct PanicHandler {
+    inner: Box<dyn Handler>,
+}
+
+#[rocket::async_trait]
+impl Handler for PanicHandler {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        let span = RequestSpan::of(request).clone();
+        let handled = AssertUnwindSafe(self.inner.handle(request, data).instrument(span))
+            .catch_unwind()
+            .await;
+        match handled {
+            Ok(outcome) => outcome,
+            Err(_) => {
+                tracing::error!(uri = %request.uri(), request_id = RequestId::of(request), "handler panicked");
+                route::Outcome::from(request, ApiError::from_status(Status::InternalServerError))
+            }
+        }
+    }
+}
+
+/// Wraps the handlers of `routes` so that panics become JSON 500 responses instead of reaching
+/// Rocket.
+pub fn panic_routes(routes: Vec<Route>) -> Vec<Route> {
+    routes
+        .into_iter()
+        .map(|mut route| {
+            route.handler = Box::new(PanicHandler { inner: route.handler });
+            route
+        })
+        .collect()
+}
@@ -0,0 +1,91 @@
+use rocket::Request;
+use rocket::data::{self, Data, FromData};
+use rocket::http::{ContentType, MediaType};
+use rocket::route::{self, Handler};
+use rocket::serde::{Deserialize, json, msgpack};
+use std::io::Cursor;
+use std::ops::Deref;
+
+/// A request body in JSON, or in MessagePack when sent with `Content-Type: application/msgpack`.
+/// Both decode into the same serde types, MessagePack being the smaller and faster to parse for
+/// clients syncing many posts at once.
+#[derive(Debug)]
+pub struct Payload<T>(pub T);
+
+impl<T> Payload<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Payload<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// Why a `Payload` could not be decoded.
+#[derive(Debug)]
+pub enum PayloadError<'r> {
+    Json(json::Error<'r>),
+    MsgPack(msgpack::Error),
+}
+
+#[rocket::async_trait]
+impl<'r, T: Deserialize<'r>> FromData<'r> for Payload<T> {
+    type Error = PayloadError<'r>;
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        match request.content_type() == Some(&ContentType::MsgPack) {
+            true => msgpack::MsgPack::<T>::from_data(request, data)
+                .await
+                .map(|body| Payload(body.into_inner()))
+                .map_error(|(status, e)| (status, PayloadError::MsgPack(e))),
+            false => json::Json::<T>::from_data(request, data)
+                .await
+                .map(|body| Payload(body.into_inner()))
+                .map_error(|(status, e)| (status, PayloadError::Json(e))),
+        }
+    }
+}
+
+/// Whether the client prefers MessagePack responses, per its `Accept` header.
+fn msgpack_accepted(request: &Request<'_>) -> bool {
+    request
+        .accept()
+        .is_some_and(|accept| accept.preferred().media_type() == &MediaType::MsgPack)
+}
+
+/// Handler wrapper that re-encodes the JSON responses of the inner handler as MessagePack for
+/// clients that ask for it. Errors raised before a handler runs stay JSON.
+#[derive(Clone)]
+struct MsgPackHandler {
+    inner: Box<dyn Handler>,
+}
+
+#[rocket::async_trait]
+impl Handler for MsgPackHandler {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        let mut response = match self.inner.handle(request, data).await {
+            route::Outcome::Success(response) => response,
+            outcome => return outcome,
+        };
+        if !msgpack_accepted(request) || response.content_type() != Some(ContentType::JSON) {
+            return route::Outcome::Success(response);
+        }
+
+        let body = response.body_mut().to_string().await.unwrap_or_default();
+        let body = json::from_str::<json::Value>(&body).unwrap_or(json::Value::Null);
+        let body = msgpack::to_vec(&body).expect("JSON values serialize to MessagePack");
+        response.set_header(ContentType::MsgPack);
+        response.set_sized_body(body.len(), Cursor::new(body));
+        route::Outcome::Success(response)
+    }
+}
+
+/// Wraps `inner` to answer in MessagePack when the client accepts it.
+pub fn msgpack_handler(inner: Box<dyn Handler>) -> Box<dyn Handler> {
+    Box::new(MsgPackHandler { inner })
+}
@@ -0,0 +1,513 @@
+use regex::Regex;
+use reqwest::Url;
+use reqwest::header::{ACCEPT, CONTENT_TYPE, LOCATION};
+use reqwest::redirect::Policy;
+use rocket::fairing::AdHoc;
+use rocket::serde::{Serialize, json};
+use rocket::tokio::time;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::Instrument;
+
+use crate::cache::response_cache;
+use crate::crypto::content_cipher;
+use crate::db::*;
+use crate::metrics::metrics;
+use crate::util::*;
+
+/// Link previews are fetched in the background when `LINK_PREVIEWS` is set. Every
+/// `LINK_PREVIEWS_POLL_INTERVAL_MS` (5000) the job scans the posts written since its last round for
+/// URLs, then fetches up to `LINK_PREVIEWS_BATCH` (20) new ones, each within
+/// `LINK_PREVIEWS_TIMEOUT_MS` (5000) and reading at most `LINK_PREVIEWS_MAX_BYTES` (256 KiB) of the
+/// page.
+#[derive(Debug, Clone)]
+pub struct PreviewsConfig {
+    pub enabled: bool,
+    pub poll_interval: Duration,
+    pub batch: i64,
+    pub timeout: Duration,
+    pub max_bytes: usize,
+}
+
+impl PreviewsConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_parse_or("LINK_PREVIEWS", false),
+            poll_interval: Duration::from_millis(env_parse_or("LINK_PREVIEWS_POLL_INTERVAL_MS", 5000)),
+            batch: env_parse_or("LINK_PREVIEWS_BATCH", 20),
+            timeout: Duration::from_millis(env_parse_or("LINK_PREVIEWS_TIMEOUT_MS", 5000)),
+            max_bytes: env_parse_or("LINK_PREVIEWS_MAX_BYTES", 256 * 1024),
+        }
+    }
+}
+
+/// Returns the process-wide `PreviewsConfig`.
+pub fn previews_config() -> &'static PreviewsConfig {
+    static CONFIG: OnceLock<PreviewsConfig> = OnceLock::new();
+    CONFIG.get_or_init(PreviewsConfig::from_env)
+}
+
+/// URLs previewed per post; the rest are ignored.
+pub const POST_URLS_MAX: usize = 10;
+const URL_MAX_LEN: usize = 2048;
+const REDIRECTS_MAX: usize = 3;
+
+/// The OpenGraph metadata of a page, or what stands in for it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct LinkPreview {
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image_url: Option<String>,
+    pub site_name: Option<String>,
+}
+
+/// Returns the distinct http(s) URLs of a text, in order, without the punctuation that usually
+/// follows a link in prose.
+pub fn urls_extract(content: &str) -> Vec<String> {
+    static URL_RE: OnceLock<Regex> = OnceLock::new();
+    let re = URL_RE.get_or_init(|| Regex::new(r#"(?i)https?://[^\s<>"'()\[\]{}`]+"#).expect("valid URL regex"));
+
+    let mut urls = Vec::<String>::new();
+    for found in re.find_iter(content) {
+        let url = found
+            .as_str()
+            .trim_end_matches(['.', ',', ';', ':', '!', '?', '*', '_', '~']);
+        if url.len() > URL_MAX_LEN || urls.iter().any(|seen| seen == url) || Url::parse(url).is_err() {
+            continue;
+        }
+        urls.push(url.to_string());
+        if urls.len() == POST_URLS_MAX {
+            break;
+        }
+    }
+    urls
+}
+
+/// Whether an address is on the public internet. Fetching anything else would let a post author
+/// probe the network the server runs in.
+pub fn ip_is_public(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || a >= 240
+                // Carrier-grade NAT, IETF protocol assignments and benchmarking
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0 && c == 0)
+                || (a == 198 && (b == 18 || b == 19)))
+        }
+        IpAddr::V6(ip) => {
+            let [first, second, ..] = ip.segments();
+            // Global unicast only, less documentation and the ranges that tunnel to IPv4
+            (first & 0xe000) == 0x2000 && !(first == 0x2001 && (second == 0 || second == 0x0db8)) && first != 0x2002
+        }
+    }
+}
+
+/// Refuses URLs the fetcher must not follow: other schemes, credentials, and ports other than
+/// the standard web ones.
+fn url_check(url: &Url) -> Result<(), String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("unsupported scheme {}", url.scheme()));
+    }
+    if !url.username().is_empty() || url.password().is_some() {
+        return Err("URL with credentials".into());
+    }
+    match url.port_or_known_default() {
+        Some(80 | 443) => Ok(()),
+        port => Err(format!("unsupported port {:?}", port)),
+    }
+}
+
+/// Resolves the host of a URL, failing unless every address it has is public.
+async fn host_resolve(url: &Url) -> Result<SocketAddr, String> {
+    let port = url.port_or_known_default().ok_or("URL without a port")?;
+    let host = url.host_str().ok_or("URL without a host")?;
+    let addrs = match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => rocket::tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|e| e.to_string())?
+            .collect::<Vec<_>>(),
+    };
+    if let Some(addr) = addrs.iter().find(|addr| !ip_is_public(addr.ip())) {
+        return Err(format!("{} is not a public address", addr.ip()));
+    }
+    addrs.first().copied().ok_or_else(|| "host has no address".into())
+}
+
+/// Sends a GET to a URL, connecting to the address that was checked rather than resolving the
+/// host again, which could now answer with another one.
+async fn page_get(url: &Url, config: &PreviewsConfig) -> Result<reqwest::Response, String> {
+    url_check(url)?;
+    let addr = host_resolve(url).await?;
+    let mut client = reqwest::Client::builder()
+        .redirect(Policy::none())
+        .no_proxy()
+        .timeout(config.timeout)
+        .user_agent(concat!("rocket-sqlx-previews/", env!("CARGO_PKG_VERSION")));
+    if let Some(domain) = url.domain() {
+        client = client.resolve(domain, addr);
+    }
+    client
+        .build()
+        .map_err(|e| e.to_string())?
+        .get(url.clone())
+        .header(ACCEPT, "text/html,application/xhtml+xml")
+        .send()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Reads up to `max` bytes of a body. The metadata is in the page's head, so the rest is dropped.
+async fn body_read(mut response: reqwest::Response, max: usize) -> Result<String, String> {
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+        let room = max - body.len();
+        body.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        if body.len() == max {
+            break;
+        }
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Fetches the preview of an HTML page, following a few redirects, each checked like the URL
+/// itself.
+pub async fn preview_fetch(url: &str, config: &PreviewsConfig) -> Result<LinkPreview, String> {
+    let mut target = Url::parse(url).map_err(|e| e.to_string())?;
+    for _ in 0..=REDIRECTS_MAX {
+        let response = page_get(&target, config).await?;
+        let status = response.status();
+        if status.is_redirection() {
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .ok_or("redirect without a location")?;
+            target = target.join(location).map_err(|e| e.to_string())?;
+            continue;
+        }
+        if !status.is_success() {
+            return Err(format!("page responded {}", status));
+        }
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if !content_type.starts_with("text/html") && !content_type.starts_with("application/xhtml+xml") {
+            return Err(format!("not an HTML page: {}", content_type));
+        }
+        let html = body_read(response, config.max_bytes).await?;
+        return Ok(LinkPreview {
+            url: url.to_string(),
+            ..og_parse(&html, &target)
+        });
+    }
+    Err("too many redirects".into())
+}
+
+/// Decodes the HTML entities of a text, collapses its whitespace and cuts it to `max` characters.
+fn text_clean(text: &str, max: usize) -> String {
+    static ENTITY_RE: OnceLock<Regex> = OnceLock::new();
+    let re =
+        ENTITY_RE.get_or_init(|| Regex::new(r"&(#[0-9]{1,7}|#[xX][0-9a-fA-F]{1,6}|[a-zA-Z]+);").expect("valid regex"));
+
+    let decoded = re.replace_all(text, |captures: &regex::Captures| {
+        let entity = &captures[1];
+        let code = match entity.strip_prefix('#') {
+            Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16).ok(),
+            Some(decimal) => decimal.parse().ok(),
+            None => None,
+        };
+        match (code.and_then(char::from_u32), entity) {
+            (Some(c), _) => c.to_string(),
+            (None, "amp") => "&".into(),
+            (None, "lt") => "<".into(),
+            (None, "gt") => ">".into(),
+            (None, "quot") => "\"".into(),
+            (None, "apos") => "'".into(),
+            (None, "nbsp") => " ".into(),
+            _ => captures[0].to_string(),
+        }
+    });
+    decoded
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(max)
+        .collect()
+}
+
+/// Extracts the preview of a page from its OpenGraph tags, falling back on Twitter cards, the
+/// `<title>` and the meta description. Relative image URLs are resolved against `base`.
+pub fn og_parse(html: &str, base: &Url) -> LinkPreview {
+    static META_RE: OnceLock<Regex> = OnceLock::new();
+    static ATTR_RE: OnceLock<Regex> = OnceLock::new();
+    static TITLE_RE: OnceLock<Regex> = OnceLock::new();
+    let meta_re = META_RE.get_or_init(|| Regex::new(r"(?is)<meta\s[^>]*>").expect("valid regex"));
+    let attr_re =
+        ATTR_RE.get_or_init(|| Regex::new(r#"(?s)([a-zA-Z_:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).expect("valid regex"));
+    let title_re = TITLE_RE.get_or_init(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").expect("valid regex"));
+
+    // The first of each property wins, as with the crawlers of the big sites
+    let mut meta = HashMap::<String, &str>::new();
+    for tag in meta_re.find_iter(html) {
+        let attrs = attr_re
+            .captures_iter(tag.as_str())
+            .map(|captures| {
+                let value = captures.get(2).or(captures.get(3)).map(|value| value.as_str());
+                (captures[1].to_ascii_lowercase(), value.unwrap_or_default())
+            })
+            .collect::<HashMap<_, _>>();
+        let key = attrs.get("property").or(attrs.get("name"));
+        if let (Some(key), Some(content)) = (key, attrs.get("content")) {
+            meta.entry(key.to_ascii_lowercase()).or_insert(content);
+        }
+    }
+    let field = |keys: &[&str], max| {
+        keys.iter()
+            .find_map(|key| meta.get(*key))
+            .map(|value| text_clean(value, max))
+            .filter(|value| !value.is_empty())
+    };
+
+    let title = field(&["og:title", "twitter:title"], 300).or_else(|| {
+        title_re
+            .captures(html)
+            .map(|captures| text_clean(&captures[1], 300))
+            .filter(|title| !title.is_empty())
+    });
+    let image_url = field(&["og:image", "og:image:url", "twitter:image"], URL_MAX_LEN)
+        .and_then(|image| base.join(&image).ok())
+        .filter(|image| matches!(image.scheme(), "http" | "https"))
+        .map(String::from);
+    LinkPreview {
+        url: base.to_string(),
+        title,
+        description: field(&["og:description", "twitter:description", "description"], 1000),
+        image_url,
+        site_name: field(&["og:site_name"], 100),
+    }
+}
+
+/// Records the URLs of the posts written since the last scan, queueing the ones not seen before
+/// for a fetch. Returns how many posts were scanned.
+pub async fn links_scan(db: &mut sqlx::SqliteConnection, batch: i64) -> Result<usize, sqlx::Error> {
+    let cursor = sqlx::query_scalar!("SELECT seq FROM link_scan_cursor")
+        .fetch_one(&mut *db)
+        .instrument(query_span("link_scan_cursor.read"))
+        .await?;
+    let posts = sqlx::query!(
+        "SELECT id, user_id, content, seq FROM posts WHERE seq > ? ORDER BY seq LIMIT ?",
+        cursor,
+        batch
+    )
+    .fetch_all(&mut *db)
+    .instrument(query_span("posts.since_seq"))
+    .await?;
+    let Some(last) = posts.last().map(|post| post.seq) else {
+        return Ok(0);
+    };
+
+    let mut tx = sqlx::Connection::begin(&mut *db).await?;
+    for post in &posts {
+        let urls = match content_cipher().decrypt(&post.content) {
+            Ok(content) => urls_extract(&content),
+            Err(e) => {
+                tracing::warn!(post_id = post.id, "skipping links of undecryptable post: {}", e);
+                continue;
+            }
+        };
+        sqlx::query!("DELETE FROM post_links WHERE post_id = ?", post.id)
+            .execute(&mut *tx)
+            .instrument(query_span("post_links.delete"))
+            .await?;
+        for (position, url) in urls.iter().enumerate() {
+            let position = position as i64;
+            sqlx::query!(
+                "INSERT INTO post_links (post_id, url, position) VALUES (?, ?, ?)",
+                post.id,
+                url,
+                position
+            )
+            .execute(&mut *tx)
+            .instrument(query_span("post_links.insert"))
+            .await?;
+            sqlx::query!("INSERT OR IGNORE INTO link_previews (url) VALUES (?)", url)
+                .execute(&mut *tx)
+                .instrument(query_span("link_previews.insert"))
+                .await?;
+        }
+    }
+    sqlx::query!("DELETE FROM link_previews WHERE url NOT IN (SELECT url FROM post_links)")
+        .execute(&mut *tx)
+        .instrument(query_span("link_previews.prune"))
+        .await?;
+    sqlx::query!("UPDATE link_scan_cursor SET seq = ?", last)
+        .execute(&mut *tx)
+        .instrument(query_span("link_scan_cursor.update"))
+        .await?;
+    tx.commit().await?;
+    // Responses cached between the write and the scan show the old links' previews
+    for user_id in posts.iter().map(|post| post.user_id).collect::<HashSet<_>>() {
+        response_cache().invalidate_user(user_id);
+    }
+    Ok(posts.len())
+}
+
+/// Stores the outcome of a fetch, and drops the cached responses of the users whose posts link to
+/// the URL. Failures are kept, so that a broken link is not fetched over and over.
+pub async fn preview_store(
+    db: &sqlx::SqlitePool,
+    url: &str,
+    result: Result<LinkPreview, String>,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    let status = match result {
+        Ok(preview) => {
+            sqlx::query!(
+                "UPDATE link_previews SET status = 'ok', title = ?, description = ?, image_url = ?, site_name = ?, \
+                fetched_at = ? WHERE url = ?",
+                preview.title,
+                preview.description,
+                preview.image_url,
+                preview.site_name,
+                now,
+                url
+            )
+            .execute(db)
+            .instrument(query_span("link_previews.update"))
+            .await?;
+            "ok"
+        }
+        Err(e) => {
+            tracing::debug!(url, "link preview fetch failed: {}", e);
+            sqlx::query!(
+                "UPDATE link_previews SET status = 'failed', fetched_at = ? WHERE url = ?",
+                now,
+                url
+            )
+            .execute(db)
+            .instrument(query_span("link_previews.update"))
+            .await?;
+            "failed"
+        }
+    };
+    metrics().counter_inc(
+        "link_previews_fetched_total",
+        "Link preview fetches.",
+        &[("status", status)],
+    );
+
+    let user_ids = sqlx::query_scalar!(
+        "SELECT DISTINCT p.user_id FROM post_links l JOIN posts p ON p.id = l.post_id WHERE l.url = ?",
+        url
+    )
+    .fetch_all(db)
+    .instrument(query_span("post_links.users"))
+    .await?;
+    user_ids
+        .into_iter()
+        .for_each(|user_id| response_cache().invalidate_user(user_id));
+    Ok(())
+}
+
+/// Fetches a batch of the queued URLs. Returns how many were fetched.
+pub async fn previews_fetch_pending(db: &sqlx::SqlitePool, config: &PreviewsConfig) -> Result<usize, sqlx::Error> {
+    let urls = sqlx::query_scalar!(
+        "SELECT url FROM link_previews WHERE status = 'pending' LIMIT ?",
+        config.batch
+    )
+    .fetch_all(db)
+    .instrument(query_span("link_previews.pending"))
+    .await?;
+    for url in &urls {
+        let result = preview_fetch(url, config).await;
+        preview_store(db, url, result).await?;
+    }
+    Ok(urls.len())
+}
+
+/// Serializes posts with the previews fetched so far of the links in them, under `previews`.
+pub async fn posts_with_previews(
+    db: &mut sqlx::SqliteConnection,
+    posts: Vec<Post>,
+) -> Result<Vec<json::Value>, sqlx::Error> {
+    let ids = json::to_string(&posts.iter().map(|post| post.id.as_str()).collect::<Vec<_>>())
+        .expect("post IDs serialize to JSON");
+    let rows = sqlx::query!(
+        "SELECT l.post_id, v.url, v.title, v.description, v.image_url, v.site_name FROM post_links l \
+        JOIN link_previews v ON v.url = l.url \
+        WHERE l.post_id IN (SELECT value FROM json_each(?)) AND v.status = 'ok' ORDER BY l.position",
+        ids
+    )
+    .fetch_all(db)
+    .instrument(query_span("link_previews.of_posts"))
+    .await?;
+
+    let mut previews = HashMap::<String, Vec<LinkPreview>>::new();
+    for row in rows {
+        previews.entry(row.post_id).or_default().push(LinkPreview {
+            url: row.url,
+            title: row.title,
+            description: row.description,
+            image_url: row.image_url,
+            site_name: row.site_name,
+        });
+    }
+    Ok(posts
+        .into_iter()
+        .map(|post| {
+            let post_previews = previews.remove(&post.id).unwrap_or_default();
+            let mut value = json::json!(post);
+            value["previews"] = json::json!(post_previews);
+            value
+        })
+        .collect())
+}
+
+/// Scans new posts and fetches their previews forever. A full batch is followed by the next one
+/// straight away.
+async fn enricher(pool: sqlx::SqlitePool, config: &'static PreviewsConfig) {
+    loop {
+        let scanned = match pool.acquire().await {
+            Ok(mut db) => links_scan(&mut db, config.batch).await,
+            Err(e) => Err(e),
+        };
+        let fetched = previews_fetch_pending(&pool, config).await;
+        match (scanned, fetched) {
+            (Ok(scanned), Ok(fetched)) if scanned as i64 == config.batch || fetched as i64 == config.batch => continue,
+            (Err(e), _) | (_, Err(e)) => tracing::warn!("link previews round failed: {}", e),
+            _ => {}
+        }
+        time::sleep(config.poll_interval).await;
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_liftoff("Link previews", |rocket| {
+        Box::pin(async move {
+            let config = previews_config();
+            let Some(db) = Db::fetch(rocket).filter(|_| config.enabled) else {
+                return;
+            };
+            rocket::tokio::spawn(enricher((**db).clone(), config));
+        })
+    })
+}
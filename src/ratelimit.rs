@@ -0,0 +1,247 @@
+use chrono::TimeDelta;
+use rocket::fairing::AdHoc;
+use rocket::http::{Header, Method, Status};
+use rocket::route::{self, Handler, Route};
+use rocket::{Data, Request};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::api::{API_LEGACY, API_V1, request_reads};
+use crate::client_info::ClientIp;
+use crate::error::{ApiError, ErrorCode};
+use crate::metrics::metrics;
+use crate::util::*;
+
+/// Number of tracked windows above which expired ones are dropped.
+const WINDOWS_PRUNE_AT: usize = 10_000;
+
+/// The quota a route counts against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteClass {
+    /// `GET`, `HEAD` and `OPTIONS` requests, and the `POST` routes that only read, see
+    /// `request_reads`.
+    Read,
+    /// Every other request.
+    Write,
+    /// Anything under `/session` and `/oauth`, whatever the method, as it is where codes are sent and
+    /// guessed.
+    Auth,
+}
+
+impl RouteClass {
+    pub fn of(method: Method, path: &str) -> Self {
+        let path = path
+            .strip_prefix(API_V1)
+            .or_else(|| path.strip_prefix(API_LEGACY))
+            .unwrap_or(path);
+        if path == "/session" || path.starts_with("/session/") || path.starts_with("/oauth/") {
+            Self::Auth
+        } else if request_reads(method, path) {
+            Self::Read
+        } else {
+            Self::Write
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Auth => "auth",
+        }
+    }
+}
+
+/// Requests allowed per user and route class within `RATE_LIMIT_WINDOW_SECS` (60):
+/// `RATE_LIMIT_READS` (600), `RATE_LIMIT_WRITES` (120) and `RATE_LIMIT_AUTH` (20). A quota of 0
+/// lifts the limit of its class.
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub window_secs: i64,
+    pub reads: u32,
+    pub writes: u32,
+    pub auth: u32,
+}
+
+impl RateLimitConfig {
+    pub fn from_env() -> Self {
+        Self {
+            window_secs: env_parse_or("RATE_LIMIT_WINDOW_SECS", 60),
+            reads: env_parse_or("RATE_LIMIT_READS", 600),
+            writes: env_parse_or("RATE_LIMIT_WRITES", 120),
+            auth: env_parse_or("RATE_LIMIT_AUTH", 20),
+        }
+    }
+
+    pub fn quota(&self, class: RouteClass) -> u32 {
+        match class {
+            RouteClass::Read => self.reads,
+            RouteClass::Write => self.writes,
+            RouteClass::Auth => self.auth,
+        }
+    }
+}
+
+/// Where a caller stands against the quota of a route class, as sent in the `X-RateLimit-*`
+/// headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub limit: u32,
+    pub remaining: u32,
+    /// When the window ends and the quota is restored.
+    pub reset_at: DateTime<Utc>,
+    /// Whether this request went over the quota and is refused.
+    pub exceeded: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    started_at: DateTime<Utc>,
+    count: u32,
+}
+
+/// Counts requests per caller and route class in fixed windows. Callers are keyed by user when
+/// signed in, so that scripts get the same quota wherever they run from, and by IP address
+/// otherwise. Counts are kept in memory, so each instance enforces its own quotas. Clones share
+/// the counts and the quotas.
+#[derive(Clone)]
+pub struct RateLimiter {
+    config: Arc<RwLock<RateLimitConfig>>,
+    windows: Arc<Mutex<HashMap<(String, RouteClass), Window>>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config: Arc::new(RwLock::new(config)),
+            windows: Arc::default(),
+        }
+    }
+
+    pub fn config(&self) -> RateLimitConfig {
+        self.config.read().expect("rate limiter lock poisoned").clone()
+    }
+
+    /// Swaps the quotas, e.g. on a config reload. Counts in the current windows are kept.
+    pub fn reconfigure(&self, config: RateLimitConfig) {
+        *self.config.write().expect("rate limiter lock poisoned") = config;
+    }
+
+    /// Counts a request of `key` against the quota of `class` at `now`. Returns `None` when the
+    /// class has no limit.
+    pub fn hit(&self, key: &str, class: RouteClass, now: DateTime<Utc>) -> Option<RateLimit> {
+        let config = self.config();
+        let limit = config.quota(class);
+        if limit == 0 {
+            return None;
+        }
+        let length = TimeDelta::seconds(config.window_secs);
+        let mut windows = self.windows.lock().expect("rate limiter lock poisoned");
+        if windows.len() >= WINDOWS_PRUNE_AT {
+            windows.retain(|_, window| window.started_at + length > now);
+        }
+
+        let window = windows.entry((key.to_owned(), class)).or_insert(Window {
+            started_at: now,
+            count: 0,
+        });
+        if window.started_at + length <= now {
+            *window = Window {
+                started_at: now,
+                count: 0,
+            };
+        }
+        let exceeded = window.count >= limit;
+        if !exceeded {
+            window.count += 1;
+        }
+        Some(RateLimit {
+            limit,
+            remaining: limit - window.count,
+            reset_at: window.started_at + length,
+            exceeded,
+        })
+    }
+}
+
+/// The caller a request counts against: `user:<id>` with a session cookie, else `ip:<address>`.
+pub fn rate_limit_key(request: &Request<'_>) -> String {
+    let user_id = request
+        .cookies()
+        .get_private("user_id")
+        .and_then(|cookie| cookie.value().parse::<i64>().ok());
+    match (user_id, ClientIp::of(request)) {
+        (Some(id), _) => format!("user:{}", id),
+        (None, Some(ip)) => format!("ip:{}", ip),
+        (None, None) => "ip:unknown".to_owned(),
+    }
+}
+
+/// The rate limit of the current request, counted once however many routes it is tried against.
+struct RateLimitState(Option<RateLimit>);
+
+/// Handler wrapper that counts the request against its caller's quota and answers with a 429 once
+/// the quota is used up.
+#[derive(Clone)]
+struct RateLimitHandler {
+    inner: Box<dyn Handler>,
+}
+
+#[rocket::async_trait]
+impl Handler for RateLimitHandler {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        let state = request.local_cache(|| {
+            let limit = request.rocket().state::<RateLimiter>().and_then(|limiter| {
+                let class = RouteClass::of(request.method(), request.uri().path().as_str());
+                let limit = limiter.hit(&rate_limit_key(request), class, Utc::now())?;
+                if limit.exceeded {
+                    metrics().counter_inc(
+                        "rate_limited_requests_total",
+                        "Requests refused for exceeding a rate limit.",
+                        &[("class", class.as_str())],
+                    );
+                }
+                Some(limit)
+            });
+            RateLimitState(limit)
+        });
+        match state.0 {
+            Some(limit) if limit.exceeded => {
+                let retry_after = (limit.reset_at - Utc::now()).num_seconds().max(1) as u64;
+                let error = ApiError::new(
+                    Status::TooManyRequests,
+                    ErrorCode::RateLimited,
+                    format!("Rate limit exceeded, retry in {} seconds.", retry_after),
+                );
+                route::Outcome::from(request, error.retry_after(retry_after))
+            }
+            _ => self.inner.handle(request, data).await,
+        }
+    }
+}
+
+/// Wraps the handlers of `routes` with the caller's rate limit.
+pub fn rate_limit_routes(routes: Vec<Route>) -> Vec<Route> {
+    routes
+        .into_iter()
+        .map(|mut route| {
+            route.handler = Box::new(RateLimitHandler { inner: route.handler });
+            route
+        })
+        .collect()
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Rate limit stage", |rocket| async {
+        let rocket = manage_default(rocket, |_| RateLimiter::new(RateLimitConfig::from_env()));
+        rocket.attach(AdHoc::on_response("Rate limit headers", |request, response| {
+            Box::pin(async move {
+                if let Some(limit) = request.local_cache(|| RateLimitState(None)).0 {
+                    response.set_header(Header::new("X-RateLimit-Limit", limit.limit.to_string()));
+                    response.set_header(Header::new("X-RateLimit-Remaining", limit.remaining.to_string()));
+                    response.set_header(Header::new("X-RateLimit-Reset", limit.reset_at.timestamp().to_string()));
+                }
+            })
+        }))
+    })
+}
@@ -0,0 +1,205 @@
+use rocket::fairing::AdHoc;
+use rocket::serde::Serialize;
+use rocket::tokio::time;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::Instrument;
+
+use crate::clock::Timekeeper;
+use crate::cron::Cron;
+use crate::crypto::content_cipher;
+use crate::db::*;
+use crate::events::events_wake;
+use crate::handlers::posts::{PostWriteError, UpsertPostPayload, posts_upsert_many, version_default};
+use crate::metrics::metrics;
+use crate::util::*;
+
+/// The scheduler looks for due templates every `TEMPLATES_POLL_INTERVAL_MS` (30000) and
+/// instantiates up to `TEMPLATES_BATCH` (100) at a time, unless `TEMPLATES_SCHEDULER` is `false`.
+#[derive(Debug, Clone)]
+pub struct TemplatesConfig {
+    pub enabled: bool,
+    pub poll_interval: Duration,
+    pub batch: i64,
+}
+
+impl TemplatesConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_parse_or("TEMPLATES_SCHEDULER", true),
+            poll_interval: Duration::from_millis(env_parse_or("TEMPLATES_POLL_INTERVAL_MS", 30_000)),
+            batch: env_parse_or("TEMPLATES_BATCH", 100),
+        }
+    }
+}
+
+/// Returns the process-wide `TemplatesConfig`.
+pub fn templates_config() -> &'static TemplatesConfig {
+    static CONFIG: OnceLock<TemplatesConfig> = OnceLock::new();
+    CONFIG.get_or_init(TemplatesConfig::from_env)
+}
+
+/// A post created again and again on a cron schedule, such as a daily journal entry.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(crate = "rocket::serde")]
+pub struct PostTemplate {
+    pub id: String,
+    #[serde(skip)]
+    pub user_id: i64,
+    pub name: String,
+    /// The content of each post, where `{{date}}` and `{{time}}` stand for when it is created.
+    pub content: String,
+    pub variant: String,
+    /// The post new posts are nested under.
+    pub parent_id: Option<String>,
+    /// When to create posts, see `Cron`.
+    pub schedule: String,
+    pub enabled: bool,
+    /// `None` while disabled, or when the schedule never matches again.
+    pub next_run_at: Option<DateTime<Utc>>,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl PostTemplate {
+    /// Replaces the stored (possibly encrypted) content with its plain text.
+    pub fn content_decrypt(mut self) -> Self {
+        self.content = content_cipher()
+            .decrypt(&self.content)
+            .expect("Failed to decrypt template content");
+        self
+    }
+}
+
+/// Returns the next run of an enabled template, from `now`.
+pub fn template_next_run(schedule: &Cron, enabled: bool, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    enabled.then(|| schedule.next_after(now)).flatten()
+}
+
+/// Fills in the placeholders of a template's content: `{{date}}` (`2024-01-31`) and `{{time}}`
+/// (`08:00`), in UTC.
+pub fn template_render(content: &str, at: DateTime<Utc>) -> String {
+    content
+        .replace("{{date}}", &at.format("%Y-%m-%d").to_string())
+        .replace("{{time}}", &at.format("%H:%M").to_string())
+}
+
+/// Creates post `id` from a decrypted template at `now`, like `POST /api/posts` would. Returns the
+/// new post's ID.
+pub async fn template_instantiate(
+    db: &mut sqlx::SqliteConnection,
+    template: &PostTemplate,
+    id: String,
+    now: DateTime<Utc>,
+) -> Result<String, PostWriteError> {
+    let post = UpsertPostPayload {
+        id,
+        parent_id: template.parent_id.clone(),
+        created_at: now,
+        content: template_render(&template.content, now),
+        updated_at: now,
+        version: version_default(None, None),
+        variant: template.variant.clone(),
+        position: None,
+        org_id: None,
+        remind_at: None,
+    };
+    posts_upsert_many(db, template.user_id, std::slice::from_ref(&post), now).await?;
+    Ok(post.id)
+}
+
+/// Instantiates the templates that are due at `now`, with post IDs from `ids`, and moves each to
+/// its next run. A run missed while the server was down happens once, late, rather than once per
+/// missed occurrence. Returns how many templates were due.
+pub async fn templates_run_due(
+    db: &mut sqlx::SqliteConnection,
+    batch: i64,
+    now: DateTime<Utc>,
+    ids: &IdSource,
+) -> Result<usize, sqlx::Error> {
+    let due = sqlx::query_as!(
+        PostTemplate,
+        "SELECT t.id, t.user_id, t.name, t.content, t.variant, t.parent_id, t.schedule, t.enabled AS \"enabled: bool\", \
+        t.next_run_at AS \"next_run_at: DateTime<Utc>\", t.last_run_at AS \"last_run_at: DateTime<Utc>\", \
+        t.created_at AS \"created_at: DateTime<Utc>\", t.updated_at AS \"updated_at: DateTime<Utc>\" \
+        FROM post_templates t JOIN users u ON u.id = t.user_id \
+        WHERE t.next_run_at <= ? AND u.disabled_at IS NULL ORDER BY t.next_run_at LIMIT ?",
+        now,
+        batch
+    )
+    .fetch_all(&mut *db)
+    .instrument(query_span("post_templates.due"))
+    .await?
+    .into_iter()
+    .map(PostTemplate::content_decrypt)
+    .collect::<Vec<_>>();
+
+    for template in &due {
+        let next_run_at = Cron::parse(&template.schedule)
+            .ok()
+            .and_then(|schedule| template_next_run(&schedule, template.enabled, now));
+        let mut tx = sqlx::Connection::begin(&mut *db).await?;
+        sqlx::query!(
+            "UPDATE post_templates SET next_run_at = ?, last_run_at = ? WHERE id = ?",
+            next_run_at,
+            now,
+            template.id
+        )
+        .execute(&mut *tx)
+        .instrument(query_span("post_templates.advance"))
+        .await?;
+        // A template that can't be instantiated (e.g. over the size limit) still moves on
+        let status = match template_instantiate(&mut tx, template, ids.id(), now).await {
+            Ok(_) => "ok",
+            Err(PostWriteError::Db(e)) => return Err(e),
+            Err(e) => {
+                tracing::warn!(template_id = template.id, "recurring post skipped: {}", e);
+                "failed"
+            }
+        };
+        tx.commit().await?;
+        metrics().counter_inc(
+            "templates_instantiated_total",
+            "Posts created from recurring templates.",
+            &[("status", status)],
+        );
+    }
+    if !due.is_empty() {
+        events_wake();
+    }
+    Ok(due.len())
+}
+
+/// Runs due templates forever. A full batch is followed by the next one straight away.
+async fn scheduler(pool: sqlx::SqlitePool, clock: Timekeeper, ids: IdSource, config: &'static TemplatesConfig) {
+    loop {
+        let ran = match pool.acquire().await {
+            Ok(mut db) => templates_run_due(&mut db, config.batch, clock.now_stored(), &ids).await,
+            Err(e) => Err(e),
+        };
+        match ran {
+            Ok(ran) if ran as i64 == config.batch => continue,
+            Ok(_) => {}
+            Err(e) => tracing::warn!("recurring posts round failed: {}", e),
+        }
+        time::sleep(config.poll_interval).await;
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_liftoff("Recurring posts scheduler", |rocket| {
+        Box::pin(async move {
+            let config = templates_config();
+            let (Some(db), Some(clock), Some(ids)) = (
+                Db::fetch(rocket).filter(|_| config.enabled),
+                rocket.state::<Timekeeper>(),
+                rocket.state::<IdSource>(),
+            ) else {
+                return;
+            };
+            rocket::tokio::spawn(scheduler((**db).clone(), clock.clone(), ids.clone(), config));
+        })
+    })
+}
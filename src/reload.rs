@@ -0,0 +1,142 @@
+use rocket::fairing::AdHoc;
+use rocket::request::{self, FromRequest, Request};
+use rocket::serde::json;
+use rocket::{Phase, Rocket};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+
+use crate::config::config_file_reload;
+use crate::flags::{FlagCache, flags_refresh};
+use crate::maintenance::{Maintenance, MaintenanceConfig};
+use crate::metrics::metrics;
+use crate::ratelimit::{RateLimitConfig, RateLimiter};
+use crate::telemetry::log_filter_reload;
+
+/// The settings that may change without a restart, so without dropping the event streams: the
+/// rate limits, the log filter, how often feature flags are re-read (their snapshot is dropped too)
+/// and maintenance mode. A reload is triggered by `SIGHUP` or `POST /admin/config/reload`, and
+/// re-reads `Rocket.toml`; the environment of a running process can't change, so the settings to
+/// tune at runtime belong in the file. Every other setting still needs a restart.
+///
+/// Clones of the managed state the settings apply to.
+#[derive(Clone)]
+pub struct Reloadable {
+    limiter: Option<RateLimiter>,
+    flags: Option<FlagCache>,
+    maintenance: Option<Maintenance>,
+}
+
+impl Reloadable {
+    pub fn of<P: Phase>(rocket: &Rocket<P>) -> Self {
+        Self {
+            limiter: rocket.state::<RateLimiter>().cloned(),
+            flags: rocket.state::<FlagCache>().cloned(),
+            maintenance: rocket.state::<Maintenance>().cloned(),
+        }
+    }
+
+    /// Re-reads the settings and applies them. Every setting is read before any is applied, so a
+    /// reload with an invalid value applies none of them. Returns the settings now in force.
+    pub fn reload(&self) -> Result<json::Value, String> {
+        // One reload at a time, so that their settings don't interleave
+        static RELOADING: Mutex<()> = Mutex::new(());
+        let _reloading = RELOADING.lock().unwrap_or_else(|e| e.into_inner());
+
+        let reloaded = config_file_reload().and_then(|()| {
+            panic::catch_unwind(AssertUnwindSafe(|| {
+                (
+                    RateLimitConfig::from_env(),
+                    flags_refresh(),
+                    MaintenanceConfig::from_env(),
+                )
+            }))
+            .map_err(|panic| match panic.downcast::<String>() {
+                Ok(message) => *message,
+                Err(panic) => panic.downcast_ref::<&str>().unwrap_or(&"invalid setting").to_string(),
+            })
+        });
+        let (limits, refresh, maintenance) = match reloaded {
+            Ok(settings) => settings,
+            Err(e) => {
+                metrics().counter_inc(
+                    "config_reloads_total",
+                    "Reloads of the runtime settings.",
+                    &[("result", "error")],
+                );
+                return Err(e);
+            }
+        };
+        let log_filter = log_filter_reload()?;
+
+        if let Some(limiter) = &self.limiter {
+            limiter.reconfigure(limits.clone());
+        }
+        if let Some(flags) = &self.flags {
+            flags.reconfigure(refresh);
+        }
+        if let Some(state) = &self.maintenance {
+            state.reconfigure(maintenance.clone());
+        }
+        metrics().counter_inc(
+            "config_reloads_total",
+            "Reloads of the runtime settings.",
+            &[("result", "ok")],
+        );
+        tracing::info!(maintenance = maintenance.enabled, "config reloaded");
+
+        Ok(json::json!({
+            "rateLimits": {
+                "windowSecs": limits.window_secs,
+                "reads": limits.reads,
+                "writes": limits.writes,
+                "auth": limits.auth,
+            },
+            "logFilter": log_filter,
+            "flagsRefreshSecs": refresh.as_secs(),
+            "maintenance": {
+                "enabled": maintenance.enabled,
+                "message": maintenance.message,
+                "retryAfterSecs": maintenance.retry_after_secs,
+            },
+        }))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Reloadable {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(Self::of(request.rocket()))
+    }
+}
+
+/// Reloads on every `SIGHUP` until shutdown.
+#[cfg(unix)]
+async fn sighup_listen(reloadable: Reloadable) {
+    use rocket::tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            tracing::error!("config reload: can't listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        if let Err(e) = reloadable.reload() {
+            tracing::error!("config reload failed, keeping the current settings: {}", e);
+        }
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_liftoff("Config reload on SIGHUP", |rocket| {
+        Box::pin(async move {
+            #[cfg(unix)]
+            rocket::tokio::spawn(sighup_listen(Reloadable::of(rocket)));
+            #[cfg(not(unix))]
+            let _ = rocket;
+        })
+    })
+}
@@ -0,0 +1,182 @@
+use rocket::fairing::AdHoc;
+use rocket::tokio::time;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::Instrument;
+
+use crate::clock::Timekeeper;
+use crate::crypto::content_cipher;
+use crate::db::*;
+use crate::email::{EmailTemplate, Mailer};
+use crate::events::{event_record, events_wake};
+use crate::handlers::posts::PostChangeKind;
+use crate::metrics::metrics;
+use crate::util::*;
+
+/// The scheduler looks for due reminders every `REMINDERS_POLL_INTERVAL_MS` (30000) and delivers
+/// up to `REMINDERS_BATCH` (100) at a time, unless `REMINDERS` is `false`. Each is recorded as a
+/// `reminded` event, which reaches live subscribers and the webhook, and emailed to the user unless
+/// `REMINDERS_EMAIL` is `false`.
+#[derive(Debug, Clone)]
+pub struct RemindersConfig {
+    pub enabled: bool,
+    pub poll_interval: Duration,
+    pub batch: i64,
+    pub email: bool,
+}
+
+impl RemindersConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_parse_or("REMINDERS", true),
+            poll_interval: Duration::from_millis(env_parse_or("REMINDERS_POLL_INTERVAL_MS", 30_000)),
+            batch: env_parse_or("REMINDERS_BATCH", 100),
+            email: env_parse_or("REMINDERS_EMAIL", true),
+        }
+    }
+}
+
+/// Returns the process-wide `RemindersConfig`.
+pub fn reminders_config() -> &'static RemindersConfig {
+    static CONFIG: OnceLock<RemindersConfig> = OnceLock::new();
+    CONFIG.get_or_init(RemindersConfig::from_env)
+}
+
+/// A reminder whose time has come, with what is needed to deliver it.
+#[derive(Debug, Clone)]
+pub struct DueReminder {
+    pub post_id: String,
+    pub user_id: i64,
+    pub email: String,
+    /// The post's stored, possibly encrypted, content.
+    pub content: String,
+    pub remind_at: DateTime<Utc>,
+}
+
+/// Lists the reminders due at `now` that were not delivered yet, oldest first. Those of disabled
+/// accounts are left alone.
+pub async fn reminders_due(
+    db: &mut sqlx::SqliteConnection,
+    now: DateTime<Utc>,
+    batch: i64,
+) -> Result<Vec<DueReminder>, sqlx::Error> {
+    sqlx::query_as!(
+        DueReminder,
+        "SELECT p.id AS post_id, p.user_id, u.email, p.content, p.remind_at AS \"remind_at!: DateTime<Utc>\" \
+        FROM posts p JOIN users u ON u.id = p.user_id \
+        LEFT JOIN reminder_deliveries d ON d.post_id = p.id \
+        WHERE p.remind_at IS NOT NULL AND p.remind_at <= ? AND (d.remind_at IS NULL OR d.remind_at <> p.remind_at) \
+        AND u.disabled_at IS NULL ORDER BY p.remind_at LIMIT ?",
+        now,
+        batch
+    )
+    .fetch_all(db)
+    .instrument(query_span("posts.reminders_due"))
+    .await
+}
+
+/// Emails a reminder, titled with the first line of the post.
+async fn reminder_email(db: &mut sqlx::SqliteConnection, mailer: &Mailer, reminder: &DueReminder) {
+    let content = match content_cipher().decrypt(&reminder.content) {
+        Ok(content) => content,
+        Err(e) => {
+            tracing::warn!(post_id = reminder.post_id, "reminder email skipped: {}", e);
+            return;
+        }
+    };
+    let title = content.lines().find(|line| !line.trim().is_empty()).unwrap_or_default();
+    let title = title.trim().chars().take(80).collect::<String>();
+    let body = format!(
+        "You asked to be reminded of this post:\r\n\r\n\
+        {}\r\n\r\n\
+        Open it at {}/posts/{}",
+        content.chars().take(1000).collect::<String>(),
+        app_url(),
+        reminder.post_id,
+    );
+    mailer
+        .send(db, EmailTemplate::Reminder, &reminder.email, &title, &body)
+        .await;
+}
+
+/// Delivers the reminders due at `now`: records each as delivered along with a `reminded` event,
+/// then emails it. Recording first means a crash between the two loses the email rather than
+/// sending it twice. Returns how many were delivered.
+pub async fn reminders_deliver(
+    db: &mut sqlx::SqliteConnection,
+    mailer: Option<&Mailer>,
+    batch: i64,
+    now: DateTime<Utc>,
+) -> Result<usize, sqlx::Error> {
+    let due = reminders_due(&mut *db, now, batch).await?;
+    for reminder in &due {
+        let mut tx = sqlx::Connection::begin(&mut *db).await?;
+        sqlx::query!(
+            "INSERT INTO reminder_deliveries (post_id, remind_at, delivered_at) VALUES (?, ?, ?) \
+            ON CONFLICT (post_id) DO UPDATE SET remind_at = excluded.remind_at, delivered_at = excluded.delivered_at",
+            reminder.post_id,
+            reminder.remind_at,
+            now
+        )
+        .execute(&mut *tx)
+        .instrument(query_span("reminder_deliveries.upsert"))
+        .await?;
+        event_record(
+            &mut tx,
+            reminder.user_id,
+            PostChangeKind::Reminded,
+            Some(&reminder.post_id),
+            now,
+        )
+        .await?;
+        tx.commit().await?;
+
+        if let Some(mailer) = mailer {
+            reminder_email(&mut *db, mailer, reminder).await;
+        }
+        metrics().counter_inc("reminders_delivered_total", "Post reminders delivered.", &[]);
+    }
+    if !due.is_empty() {
+        events_wake();
+    }
+    Ok(due.len())
+}
+
+/// Delivers due reminders forever. A full batch is followed by the next one straight away.
+async fn scheduler(
+    pool: sqlx::SqlitePool,
+    clock: Timekeeper,
+    mailer: Option<Mailer>,
+    config: &'static RemindersConfig,
+) {
+    loop {
+        let delivered = match pool.acquire().await {
+            Ok(mut db) => reminders_deliver(&mut db, mailer.as_ref(), config.batch, clock.now()).await,
+            Err(e) => Err(e),
+        };
+        match delivered {
+            Ok(delivered) if delivered as i64 == config.batch => continue,
+            Ok(_) => {}
+            Err(e) => tracing::warn!("reminder delivery failed: {}", e),
+        }
+        time::sleep(config.poll_interval).await;
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_liftoff("Reminders scheduler", |rocket| {
+        Box::pin(async move {
+            let config = reminders_config();
+            let (Some(db), Some(clock)) = (
+                Db::fetch(rocket).filter(|_| config.enabled),
+                rocket.state::<Timekeeper>(),
+            ) else {
+                return;
+            };
+            let mailer = config
+                .email
+                .then(|| rocket.state::<Mailer>().cloned().unwrap_or_else(Mailer::from_env));
+            rocket::tokio::spawn(scheduler((**db).clone(), clock.clone(), mailer, config));
+        })
+    })
+}
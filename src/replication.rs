@@ -0,0 +1,247 @@
+use rocket::fairing::{self, AdHoc};
+use rocket::serde::json;
+use rocket::tokio::time::{Duration, Instant};
+use rocket::{Build, Rocket};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::util::*;
+
+/// Hooks for a streaming replication sidecar such as Litestream, which ships the SQLite WAL to
+/// object storage.
+///
+/// - `REPLICATION_HEALTH_URL`: the sidecar's metrics endpoint (Litestream's `addr`), checked by
+///   `/readyz`; unset leaves replication out of the readiness checks
+/// - `REPLICATION_SYNC_METRIC`: a counter the sidecar bumps on every sync (`litestream_sync_count`);
+///   the WAL counts as shipped while it keeps growing. Empty only checks the endpoint answers
+/// - `REPLICATION_MAX_LAG_SECS`: how long the counter may stand still before replication is `stale`
+///   (60)
+/// - `REPLICATION_REQUIRED`: answer `/readyz` with a 503 rather than `degraded` when replication is
+///   not ok (off)
+/// - `REPLICATION_RESTORE_COMMAND`: when the database file is missing or empty at startup, this
+///   shell command is run before migrations to restore the latest snapshot, with `{db}` replaced by
+///   the database path, e.g. `litestream restore -if-replica-exists -o {db} s3://bucket/db`
+#[derive(Debug, Clone)]
+pub struct ReplicationConfig {
+    pub health_url: Option<String>,
+    pub sync_metric: Option<String>,
+    pub max_lag: Duration,
+    pub required: bool,
+    pub restore_command: Option<String>,
+}
+
+impl ReplicationConfig {
+    pub fn from_env() -> Self {
+        let non_empty = |name| config_var(name).filter(|value: &String| !value.trim().is_empty());
+        Self {
+            health_url: non_empty("REPLICATION_HEALTH_URL"),
+            sync_metric: match config_var("REPLICATION_SYNC_METRIC") {
+                Some(metric) => Some(metric.trim().to_owned()).filter(|metric| !metric.is_empty()),
+                None => Some("litestream_sync_count".into()),
+            },
+            max_lag: Duration::from_secs(env_parse_or("REPLICATION_MAX_LAG_SECS", 60)),
+            required: env_parse_or("REPLICATION_REQUIRED", false),
+            restore_command: non_empty("REPLICATION_RESTORE_COMMAND"),
+        }
+    }
+}
+
+/// Returns the process-wide `ReplicationConfig`.
+pub fn replication_config() -> &'static ReplicationConfig {
+    static CONFIG: OnceLock<ReplicationConfig> = OnceLock::new();
+    CONFIG.get_or_init(ReplicationConfig::from_env)
+}
+
+/// Whether the WAL is being shipped, as last found by `ReplicationMonitor::check`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplicationHealth {
+    Ok,
+    /// The sidecar answers, but has not synced for longer than the allowed lag.
+    Stale {
+        lag: Duration,
+    },
+    /// The sidecar can't be reached or its metrics can't be read, for the given reason.
+    Failed(String),
+}
+
+impl ReplicationHealth {
+    pub fn to_json(&self) -> json::Value {
+        match self {
+            Self::Ok => json::json!({ "status": "ok" }),
+            Self::Stale { lag } => json::json!({ "status": "stale", "lagSecs": lag.as_secs() }),
+            Self::Failed(reason) => json::json!({ "status": "failed", "reason": reason }),
+        }
+    }
+}
+
+/// The last sync count read from the sidecar, and when it was last seen growing.
+#[derive(Debug, Clone, Copy)]
+struct SyncProgress {
+    count: f64,
+    advanced_at: Instant,
+}
+
+/// Managed state checking the replication sidecar for `/readyz`. It remembers the sync count across
+/// checks, so a sidecar that answers but stopped shipping shows up as `stale`.
+#[derive(Debug, Clone)]
+pub struct ReplicationMonitor {
+    config: ReplicationConfig,
+    progress: Arc<Mutex<Option<SyncProgress>>>,
+}
+
+impl ReplicationMonitor {
+    pub fn new(config: ReplicationConfig) -> Self {
+        Self {
+            config,
+            progress: Arc::default(),
+        }
+    }
+
+    pub fn required(&self) -> bool {
+        self.config.required
+    }
+
+    /// Scrapes the sidecar. Returns `None` when no sidecar is configured.
+    pub async fn check(&self) -> Option<ReplicationHealth> {
+        let url = self.config.health_url.as_ref()?;
+        let scrape = async {
+            let response = http_client().get(url).send().await.map_err(|e| e.to_string())?;
+            if !response.status().is_success() {
+                return Err(format!("sidecar answered {}", response.status()));
+            }
+            response.text().await.map_err(|e| e.to_string())
+        };
+        Some(self.observe(scrape.await, Instant::now()))
+    }
+
+    /// Judges a scrape of the sidecar's metrics made at `now`.
+    pub fn observe(&self, scrape: Result<String, String>, now: Instant) -> ReplicationHealth {
+        let body = match scrape {
+            Ok(body) => body,
+            Err(reason) => return ReplicationHealth::Failed(reason),
+        };
+        let Some(metric) = &self.config.sync_metric else {
+            return ReplicationHealth::Ok;
+        };
+        let Some(count) = metric_sum(&body, metric) else {
+            return ReplicationHealth::Failed(format!("sidecar does not report {}", metric));
+        };
+
+        let mut progress = self.progress.lock().unwrap();
+        let advanced_at = match *progress {
+            Some(last) if count == last.count => last.advanced_at,
+            // The first scrape and any growth, or a reset by a sidecar restart
+            _ => now,
+        };
+        *progress = Some(SyncProgress { count, advanced_at });
+        let lag = now.saturating_duration_since(advanced_at);
+        match lag > self.config.max_lag {
+            true => ReplicationHealth::Stale { lag },
+            false => ReplicationHealth::Ok,
+        }
+    }
+}
+
+fn http_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(2))
+            .build()
+            .expect("failed to build HTTP client")
+    })
+}
+
+/// Sums the samples of a metric in the Prometheus text format, across its labels.
+pub fn metric_sum(body: &str, metric: &str) -> Option<f64> {
+    let mut sum = None;
+    for line in body.lines().filter(|line| !line.starts_with('#')) {
+        let Some(rest) = line.strip_prefix(metric) else {
+            continue;
+        };
+        let value = match rest.chars().next() {
+            Some('{') => rest.rsplit_once('}').map(|(_, value)| value),
+            Some(' ') => Some(rest),
+            _ => None,
+        };
+        let Some(value) = value.and_then(|value| value.split_whitespace().next()?.parse::<f64>().ok()) else {
+            continue;
+        };
+        *sum.get_or_insert(0.0) += value;
+    }
+    sum
+}
+
+/// The file of a SQLite database URL, or `None` for in-memory databases.
+pub fn database_path(url: &str) -> Option<PathBuf> {
+    let path = url
+        .strip_prefix("sqlite://")
+        .or_else(|| url.strip_prefix("sqlite:"))
+        .unwrap_or(url);
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let memory = path.is_empty() || path == ":memory:" || query.split('&').any(|param| param == "mode=memory");
+    (!memory).then(|| PathBuf::from(path))
+}
+
+/// Runs the restore command when the database at `path` is missing or empty. Returns whether it
+/// ran; the command failing is an error, as starting on an empty database would then replicate it
+/// over the good copy.
+pub async fn restore_if_empty(command: &str, path: &Path) -> Result<bool, String> {
+    let empty = match std::fs::metadata(path) {
+        Ok(metadata) => metadata.len() == 0,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+        Err(e) => return Err(format!("failed to inspect {}: {}", path.display(), e)),
+    };
+    if !empty {
+        return Ok(false);
+    }
+
+    let command = command.replace("{db}", &path.to_string_lossy());
+    let output =
+        rocket::tokio::task::spawn_blocking(move || std::process::Command::new("sh").arg("-c").arg(&command).output())
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| format!("failed to run the restore command: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("restore command failed ({}): {}", output.status, stderr.trim()));
+    }
+    Ok(true)
+}
+
+/// Restores the database from its replica before the pool opens it, when configured and empty.
+async fn restore_run(rocket: Rocket<Build>) -> fairing::Result {
+    let Some(command) = &replication_config().restore_command else {
+        return Ok(rocket);
+    };
+    let url = match rocket.figment().extract_inner::<String>("databases.sqlx.url") {
+        Ok(url) => url,
+        Err(e) => {
+            tracing::error!("replica restore: no database URL: {}", e);
+            return Err(rocket);
+        }
+    };
+    let Some(path) = database_path(&url) else {
+        return Ok(rocket);
+    };
+    match restore_if_empty(command, &path).await {
+        Ok(false) => Ok(rocket),
+        Ok(true) if path.exists() => {
+            tracing::info!(path = %path.display(), "database restored from its replica");
+            Ok(rocket)
+        }
+        Ok(true) => {
+            tracing::warn!(path = %path.display(), "no replica to restore, starting on an empty database");
+            Ok(rocket)
+        }
+        Err(e) => {
+            tracing::error!(path = %path.display(), "replica restore failed: {}", e);
+            Err(rocket)
+        }
+    }
+}
+
+/// The restore step, which the SQLx stage attaches ahead of opening the pool and migrating.
+pub fn restore_stage() -> AdHoc {
+    AdHoc::try_on_ignite("Replica restore", restore_run)
+}
@@ -0,0 +1,181 @@
+use rocket::http::Status;
+use rocket::tokio::time::{Duration, sleep};
+use std::sync::OnceLock;
+
+use crate::db::{is_statement_timeout, sqlx};
+use crate::error::{ApiError, ErrorCode};
+use crate::metrics::metrics;
+use crate::util::*;
+
+/// How writes that find the database locked by another connection are retried. `DB_WRITE_RETRIES`
+/// (4) retries at most, the n-th after a random delay of up to `DB_WRITE_RETRY_BASE_MS` (10) times
+/// 2^n, capped at `DB_WRITE_RETRY_MAX_MS` (250). The jitter spreads out the retries of devices
+/// syncing in a burst, which would otherwise collide again.
+#[derive(Debug, Clone)]
+pub struct WriteRetryConfig {
+    pub retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl WriteRetryConfig {
+    pub fn from_env() -> Self {
+        Self {
+            retries: env_parse_or("DB_WRITE_RETRIES", 4),
+            base_delay: Duration::from_millis(env_parse_or("DB_WRITE_RETRY_BASE_MS", 10)),
+            max_delay: Duration::from_millis(env_parse_or("DB_WRITE_RETRY_MAX_MS", 250)),
+        }
+    }
+
+    /// The random delay before retry number `retry`, counted from 0.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let ceiling = self.base_delay.saturating_mul(1 << retry.min(16)).min(self.max_delay);
+        ceiling.mul_f64(rand::random::<f64>())
+    }
+}
+
+/// Returns the process-wide `WriteRetryConfig`.
+pub fn write_retry_config() -> &'static WriteRetryConfig {
+    static CONFIG: OnceLock<WriteRetryConfig> = OnceLock::new();
+    CONFIG.get_or_init(WriteRetryConfig::from_env)
+}
+
+/// Errors that may come from the database being locked, which a retry may get past.
+pub trait Busy {
+    fn is_busy(&self) -> bool;
+}
+
+impl Busy for sqlx::Error {
+    /// `SQLITE_BUSY` and `SQLITE_LOCKED`, with their extended codes.
+    fn is_busy(&self) -> bool {
+        let sqlx::Error::Database(e) = self else {
+            return false;
+        };
+        let primary = e
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .map(|code| code & 0xff);
+        matches!(primary, Some(5 | 6)) || e.message().contains("database is locked")
+    }
+}
+
+/// Runs `write`, an `async` block making a whole transaction or a single statement, again while it
+/// fails with a `Busy` error, per `write_retry_config`. `write` must not have effects outside the
+/// database.
+///
+/// A macro rather than a function taking an async closure: the futures of handlers awaiting an
+/// async closure can't be proven `Send`.
+macro_rules! write_retry {
+    ($op:expr, $write:expr) => {
+        $crate::retry::write_retry_with!($crate::retry::write_retry_config(), $op, $write)
+    };
+}
+
+/// `write_retry!` with the given config.
+macro_rules! write_retry_with {
+    ($config:expr, $op:expr, $write:expr) => {{
+        let mut retry = $crate::retry::WriteRetry::new($config, $op);
+        loop {
+            let result = $write.await;
+            match &result {
+                Err(e) if retry.again(e).await => {}
+                _ => break result,
+            }
+        }
+    }};
+}
+
+pub(crate) use {write_retry, write_retry_with};
+
+/// The retries made so far by a `write_retry!`.
+pub struct WriteRetry {
+    config: WriteRetryConfig,
+    op: &'static str,
+    retry: u32,
+}
+
+impl WriteRetry {
+    pub fn new(config: &WriteRetryConfig, op: &'static str) -> Self {
+        Self {
+            config: config.clone(),
+            op,
+            retry: 0,
+        }
+    }
+
+    /// Whether to retry after `e`, after waiting for the delay of this retry when so.
+    pub async fn again<E: Busy>(&mut self, e: &E) -> bool {
+        if !e.is_busy() || self.retry >= self.config.retries {
+            return false;
+        }
+        metrics().counter_inc(
+            "db_write_retries_total",
+            "Writes retried after finding the database locked.",
+            &[("op", self.op)],
+        );
+        tracing::debug!(op = self.op, retry = self.retry, "database locked, retrying write");
+        sleep(self.config.delay(self.retry)).await;
+        self.retry += 1;
+        true
+    }
+}
+
+/// The error response of a write that failed on the database: a 503 asking to retry when the
+/// database stayed locked through the retries or a statement timed out, or else a 500.
+pub fn write_error(e: sqlx::Error) -> ApiError {
+    if e.is_busy() {
+        tracing::warn!("db:write-busy: {}", e);
+        return ApiError::new(
+            Status::ServiceUnavailable,
+            ErrorCode::Unavailable,
+            "The database is busy, try again shortly.",
+        )
+        .retry_after(1);
+    }
+    if is_statement_timeout(&e) {
+        tracing::warn!("db:write-timeout: {}", e);
+        return ApiError::new(
+            Status::ServiceUnavailable,
+            ErrorCode::Unavailable,
+            "The database is slow to answer, try again shortly.",
+        )
+        .retry_after(1);
+    }
+    tracing::error!("db:write-error: {}", e);
+    ApiError::internal()
+}
+
+/// The error of a retried write made of several steps: a database error, which is retried when
+/// `Busy`, or the response to give up with, which rolls the transaction back.
+#[derive(Debug)]
+pub enum WriteError {
+    Db(sqlx::Error),
+    Api(ApiError),
+}
+
+impl Busy for WriteError {
+    fn is_busy(&self) -> bool {
+        matches!(self, Self::Db(e) if e.is_busy())
+    }
+}
+
+impl From<sqlx::Error> for WriteError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Db(e)
+    }
+}
+
+impl From<ApiError> for WriteError {
+    fn from(e: ApiError) -> Self {
+        Self::Api(e)
+    }
+}
+
+impl From<WriteError> for ApiError {
+    fn from(e: WriteError) -> Self {
+        match e {
+            WriteError::Db(e) => write_error(e),
+            WriteError::Api(e) => e,
+        }
+    }
+}
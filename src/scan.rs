@@ -0,0 +1,122 @@
+use rocket::fairing::AdHoc;
+use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::TcpStream;
+use rocket::tokio::time;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::util::*;
+
+/// What a scanner found in some content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanVerdict {
+    Clean,
+    /// Flagged, with the name of the signature that matched.
+    Infected(String),
+}
+
+/// Scans uploaded content, such as attachments, before it may be downloaded by anyone.
+#[rocket::async_trait]
+pub trait ContentScanner: Send + Sync {
+    /// Returns the verdict on `content`, or `Err` if the scanner could not be reached or failed.
+    async fn scan(&self, content: &[u8]) -> Result<ScanVerdict, String>;
+}
+
+/// Accepts everything, for when no scanner is configured.
+pub struct NoopScanner;
+
+#[rocket::async_trait]
+impl ContentScanner for NoopScanner {
+    async fn scan(&self, _content: &[u8]) -> Result<ScanVerdict, String> {
+        Ok(ScanVerdict::Clean)
+    }
+}
+
+/// Size of the chunks content is streamed to clamd in, under its default `StreamMaxLength`.
+const CLAMD_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Scans with a ClamAV daemon over TCP, with its `INSTREAM` command.
+pub struct Clamd {
+    pub addr: String,
+    pub timeout: Duration,
+}
+
+impl Clamd {
+    async fn instream(&self, content: &[u8]) -> Result<String, String> {
+        let mut stream = TcpStream::connect(&self.addr).await.map_err(|e| e.to_string())?;
+        stream.write_all(b"zINSTREAM\0").await.map_err(|e| e.to_string())?;
+        for chunk in content.chunks(CLAMD_CHUNK_BYTES) {
+            stream
+                .write_all(&(chunk.len() as u32).to_be_bytes())
+                .await
+                .map_err(|e| e.to_string())?;
+            stream.write_all(chunk).await.map_err(|e| e.to_string())?;
+        }
+        stream.write_all(&0u32.to_be_bytes()).await.map_err(|e| e.to_string())?;
+
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await.map_err(|e| e.to_string())?;
+        Ok(String::from_utf8_lossy(&reply)
+            .trim_end_matches(['\0', '\n'])
+            .to_string())
+    }
+}
+
+/// Reads a clamd reply: `stream: OK`, `stream: <signature> FOUND` or `<message> ERROR`.
+pub fn clamd_reply_parse(reply: &str) -> Result<ScanVerdict, String> {
+    let reply = reply.strip_prefix("stream: ").unwrap_or(reply);
+    if reply == "OK" {
+        Ok(ScanVerdict::Clean)
+    } else if let Some(signature) = reply.strip_suffix(" FOUND") {
+        Ok(ScanVerdict::Infected(signature.to_string()))
+    } else {
+        Err(format!("clamd: {}", reply))
+    }
+}
+
+#[rocket::async_trait]
+impl ContentScanner for Clamd {
+    async fn scan(&self, content: &[u8]) -> Result<ScanVerdict, String> {
+        let reply = time::timeout(self.timeout, self.instream(content))
+            .await
+            .map_err(|_| "clamd: timed out".to_string())??;
+        clamd_reply_parse(&reply)
+    }
+}
+
+/// Managed state holding the scanner uploads go through. Content is only downloadable once its
+/// verdict is `Clean`: flagged content is quarantined or rejected, and so is content the scanner
+/// failed on, so that an outage of the scanner doesn't let files through.
+#[derive(Clone)]
+pub struct Scanner(pub Arc<dyn ContentScanner>);
+
+impl Scanner {
+    /// Builds the scanner from `CONTENT_SCANNER` (`none` or `clamd`). clamd is reached at
+    /// `CLAMD_ADDR` (`127.0.0.1:3310`) within `CLAMD_TIMEOUT_MS` (30000).
+    pub fn from_env() -> Self {
+        let scanner: Arc<dyn ContentScanner> = match config_var("CONTENT_SCANNER").unwrap_or_default().as_str() {
+            "" | "none" => Arc::new(NoopScanner),
+            "clamd" => Arc::new(Clamd {
+                addr: config_var("CLAMD_ADDR").unwrap_or_else(|| "127.0.0.1:3310".into()),
+                timeout: Duration::from_millis(env_parse_or("CLAMD_TIMEOUT_MS", 30_000)),
+            }),
+            other => panic!("CONTENT_SCANNER has an invalid value: {}", other),
+        };
+        Self(scanner)
+    }
+
+    /// Scans content, logging failures. Callers must treat an `Err` like a flagged verdict.
+    pub async fn scan(&self, content: &[u8]) -> Result<ScanVerdict, String> {
+        let verdict = self.0.scan(content).await;
+        if let Err(e) = &verdict {
+            tracing::warn!("content scan failed: {}", e);
+        }
+        verdict
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Content scanning stage", |rocket| async {
+        manage_default(rocket, |_| Scanner::from_env())
+    })
+}
@@ -0,0 +1,970 @@
+use rocket::serde::json;
+
+use crate::cache::response_cache;
+use crate::crypto::content_cipher;
+use crate::db::{Post, Statement, User, sqlx};
+use crate::filter::{FilterExpr, JsonPathFilter};
+use crate::hlc::{Hlc, hlc_clock};
+use crate::summary::summary_of;
+use crate::util::*;
+
+/// The rows a single user may access. Every query against a user's posts and preferences goes
+/// through a `Scope`, which binds the owner predicate itself, so that a handler cannot forget
+/// `AND user_id = ?`. Its writes drop the user's cached responses. Only the admin CLI reads these
+/// tables unscoped; login and the auth guards look users up before there is a scope to speak of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scope {
+    user_id: i64,
+}
+
+impl From<&UserCtx> for Scope {
+    fn from(user: &UserCtx) -> Self {
+        Self { user_id: user.id }
+    }
+}
+
+/// Filters of `Scope::posts_list`.
+#[derive(Debug, Clone, Default)]
+pub struct PostFilter {
+    /// Only posts that are (`true`) or are not (`false`) favorited.
+    pub favorited: Option<bool>,
+    /// Only the children of `Some(id)`, or top-level posts for `Some(None)`.
+    pub parent: Option<Option<String>>,
+    /// Only posts updated strictly before this time.
+    pub before: Option<DateTime<Utc>>,
+    /// Sort by `position` first.
+    pub manual: bool,
+    /// A client-supplied `?filter=` expression.
+    pub expr: Option<FilterExpr>,
+    /// Client-supplied `?json_path=` filters, all of which must match.
+    pub json_paths: Vec<JsonPathFilter>,
+}
+
+/// Every column of a post, as read by the queries built at runtime.
+const POST_COLUMNS_ALL: &str = "p.id, p.parent_id, p.content, p.created_at, p.updated_at, p.version, p.user_id, p.variant, \
+    p.position, p.seq, p.org_id, p.remind_at, p.title, p.excerpt, r.post_id IS NOT NULL AS favorited";
+
+/// The fields of a post a client may select with `?fields=`, named as in the JSON of a post, and
+/// the SQL each is read from. The selected columns only ever come from this list.
+const POST_FIELDS: [(&str, &str); 14] = [
+    ("id", "p.id"),
+    ("parentId", "p.parent_id"),
+    ("content", "p.content"),
+    ("createdAt", "p.created_at"),
+    ("updatedAt", "p.updated_at"),
+    ("version", "p.version"),
+    ("variant", "p.variant"),
+    ("position", "p.position"),
+    ("seq", "p.seq"),
+    ("orgId", "p.org_id"),
+    ("remindAt", "p.remind_at"),
+    ("favorited", "r.post_id IS NOT NULL AS favorited"),
+    ("title", "p.title"),
+    ("excerpt", "p.excerpt"),
+];
+
+/// A `?fields=` selection, such as `id,updatedAt,excerpt`. The `id` is always selected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PostFields(Vec<&'static str>);
+
+impl PostFields {
+    /// Parses a comma-separated list of fields, refusing any not in the allow-list.
+    pub fn parse(list: &str) -> Result<Self, String> {
+        let mut fields = vec!["id"];
+        for name in list.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let (field, _) = POST_FIELDS
+                .iter()
+                .find(|(field, _)| *field == name)
+                .ok_or_else(|| format!("Unknown field '{}' in fields", name))?;
+            if !fields.contains(field) {
+                fields.push(field);
+            }
+        }
+        Ok(Self(fields))
+    }
+
+    /// The SELECT list. A title or excerpt that isn't stored is derived from the content, see
+    /// `Post::content_decrypt`, so the content and both are read with either.
+    fn columns(&self) -> String {
+        let mut fields = self.0.clone();
+        if fields.iter().any(|field| matches!(*field, "title" | "excerpt")) {
+            for needed in ["content", "title", "excerpt"] {
+                if !fields.contains(&needed) {
+                    fields.push(needed);
+                }
+            }
+        }
+        fields
+            .iter()
+            .filter_map(|field| POST_FIELDS.iter().find(|(name, _)| name == field))
+            .map(|(_, column)| *column)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Keeps the selected fields of a serialized post, and its `previews` when it has them.
+    pub fn pick(&self, post: json::Value) -> json::Value {
+        match post {
+            json::Value::Object(mut object) => {
+                object.retain(|key, _| key == "previews" || self.0.contains(&key.as_str()));
+                json::Value::Object(object)
+            }
+            other => other,
+        }
+    }
+}
+
+/// When a post was written, by the wall clock and by the hybrid logical clock.
+#[derive(Debug, Clone)]
+pub struct WriteStamp {
+    pub updated_at: DateTime<Utc>,
+    pub version: Hlc,
+}
+
+impl WriteStamp {
+    /// The stamp of a write the server makes itself at `updated_at`.
+    pub fn at(updated_at: DateTime<Utc>) -> Self {
+        Self {
+            updated_at,
+            version: hlc_clock().now(),
+        }
+    }
+}
+
+/// A post content as written: encrypted when encryption is on, with its duplicate detection hash
+/// and the title and excerpt of `summary_of`.
+#[derive(Debug, Clone)]
+pub struct StoredContent {
+    pub content: String,
+    pub content_hash: String,
+    /// `None` with encryption on, as they would give the content away; `Post::content_decrypt`
+    /// then derives them from the decrypted content.
+    pub title: Option<String>,
+    pub excerpt: Option<String>,
+}
+
+impl StoredContent {
+    pub fn of(plaintext: &str) -> Self {
+        let cipher = content_cipher();
+        let (title, excerpt) = match cipher.encrypts() {
+            true => (None, None),
+            false => {
+                let (title, excerpt) = summary_of(plaintext);
+                (Some(title), Some(excerpt))
+            }
+        };
+        Self {
+            content: cipher.encrypt(plaintext),
+            content_hash: cipher.content_hash(plaintext),
+            title,
+            excerpt,
+        }
+    }
+}
+
+/// A post as written by `Scope::posts_upsert`, with its content already encrypted.
+#[derive(Debug)]
+pub struct PostWrite<'a> {
+    pub id: &'a str,
+    pub parent_id: Option<&'a str>,
+    pub content: StoredContent,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub version: Hlc,
+    pub variant: &'a str,
+    pub position: Option<f64>,
+    pub org_id: Option<&'a str>,
+    pub remind_at: Option<DateTime<Utc>>,
+}
+
+impl Scope {
+    /// The scope of the given user, for callers that authenticate without a `UserCtx` (gRPC, jobs).
+    pub fn user(user_id: i64) -> Self {
+        Self { user_id }
+    }
+
+    pub fn user_id(self) -> i64 {
+        self.user_id
+    }
+
+    /// Lists the user's posts, newest first or in manual order, starting at `after` when given.
+    /// Fetches up to `limit` posts.
+    pub async fn posts_list(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        filter: &PostFilter,
+        after: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<Post>, sqlx::Error> {
+        if filter.expr.is_some() || !filter.json_paths.is_empty() {
+            return self.posts_list_built(db, filter, None, after, limit).await;
+        }
+        let parent_filter = filter.parent.is_some();
+        let parent = filter.parent.clone().flatten();
+        match after {
+            Some(after) => {
+                sqlx::query_as!(
+                    Post,
+                    "SELECT p.id, p.parent_id, p.content, p.created_at AS \"created_at: DateTime<Utc>\", \
+                    p.updated_at AS \"updated_at: DateTime<Utc>\", p.version, p.user_id, p.variant, p.position, p.seq, p.org_id, \
+                    p.remind_at AS \"remind_at: DateTime<Utc>\", p.title, p.excerpt, r.post_id IS NOT NULL AS \"favorited!: bool\" FROM posts p \
+                    LEFT JOIN post_reactions r ON r.post_id = p.id AND r.user_id = p.user_id AND r.kind = 'favorite' \
+                    WHERE p.user_id = ? AND p.updated_at >= ? AND (? IS NULL OR p.updated_at < ?) \
+                    AND (? IS NULL OR (r.post_id IS NOT NULL) = ?) AND (? = 0 OR p.parent_id IS ?) \
+                    ORDER BY CASE WHEN ? THEN p.position END ASC NULLS LAST, p.updated_at DESC LIMIT ?",
+                    self.user_id,
+                    after,
+                    filter.before,
+                    filter.before,
+                    filter.favorited,
+                    filter.favorited,
+                    parent_filter,
+                    parent,
+                    filter.manual,
+                    limit
+                )
+                .fetch_all(db)
+                .statement("posts.list_after")
+                .await
+            }
+            None => {
+                sqlx::query_as!(
+                    Post,
+                    "SELECT p.id, p.parent_id, p.content, p.created_at AS \"created_at: DateTime<Utc>\", \
+                    p.updated_at AS \"updated_at: DateTime<Utc>\", p.version, p.user_id, p.variant, p.position, p.seq, p.org_id, \
+                    p.remind_at AS \"remind_at: DateTime<Utc>\", p.title, p.excerpt, r.post_id IS NOT NULL AS \"favorited!: bool\" FROM posts p \
+                    LEFT JOIN post_reactions r ON r.post_id = p.id AND r.user_id = p.user_id AND r.kind = 'favorite' \
+                    WHERE p.user_id = ? AND (? IS NULL OR p.updated_at < ?) \
+                    AND (? IS NULL OR (r.post_id IS NOT NULL) = ?) AND (? = 0 OR p.parent_id IS ?) \
+                    ORDER BY CASE WHEN ? THEN p.position END ASC NULLS LAST, p.updated_at DESC LIMIT ?",
+                    self.user_id,
+                    filter.before,
+                    filter.before,
+                    filter.favorited,
+                    filter.favorited,
+                    parent_filter,
+                    parent,
+                    filter.manual,
+                    limit
+                )
+                .fetch_all(db)
+                .statement("posts.list")
+                .await
+            }
+        }
+    }
+
+    /// `posts_list` reading only the given fields of the posts; the others are left at their
+    /// defaults.
+    pub async fn posts_list_fields(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        filter: &PostFilter,
+        fields: &PostFields,
+        after: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<Post>, sqlx::Error> {
+        self.posts_list_built(db, filter, Some(fields), after, limit).await
+    }
+
+    /// `posts_list` with a filter expression, JSON path filters or a field selection, which need the
+    /// query built at runtime.
+    async fn posts_list_built(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        filter: &PostFilter,
+        fields: Option<&PostFields>,
+        after: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<Post>, sqlx::Error> {
+        let columns = fields.map_or_else(|| POST_COLUMNS_ALL.to_string(), PostFields::columns);
+        let mut builder = sqlx::QueryBuilder::new(format!(
+            "SELECT {} FROM posts p \
+            LEFT JOIN post_reactions r ON r.post_id = p.id AND r.user_id = p.user_id AND r.kind = 'favorite' \
+            WHERE p.user_id = ",
+            columns
+        ));
+        builder.push_bind(self.user_id);
+        if let Some(after) = after {
+            builder.push(" AND p.updated_at >= ").push_bind(after);
+        }
+        if let Some(before) = filter.before {
+            builder.push(" AND p.updated_at < ").push_bind(before);
+        }
+        if let Some(favorited) = filter.favorited {
+            builder.push(" AND (r.post_id IS NOT NULL) = ").push_bind(favorited);
+        }
+        if let Some(parent) = &filter.parent {
+            builder.push(" AND p.parent_id IS ").push_bind(parent.clone());
+        }
+        if let Some(expr) = &filter.expr {
+            builder.push(" AND ");
+            expr.push_sql(&mut builder);
+        }
+        for json_path in &filter.json_paths {
+            builder.push(" AND ");
+            json_path.push_sql(&mut builder);
+        }
+        if filter.manual {
+            builder.push(" ORDER BY p.position ASC NULLS LAST, p.updated_at DESC");
+        } else {
+            builder.push(" ORDER BY p.updated_at DESC");
+        }
+        builder.push(" LIMIT ").push_bind(limit);
+
+        let name = match fields {
+            Some(_) => "posts.list_fields",
+            None => "posts.list_filter",
+        };
+        builder.build_query_as::<Post>().fetch_all(db).statement(name).await
+    }
+
+    /// Lists the user's posts, newest first, optionally updated at or after `after` and of one
+    /// variant. Fetches up to `limit` posts.
+    pub async fn posts_list_variant(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        after: Option<DateTime<Utc>>,
+        variant: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Post>, sqlx::Error> {
+        sqlx::query_as!(
+            Post,
+            "SELECT p.id, p.parent_id, p.content, p.created_at AS \"created_at: DateTime<Utc>\", \
+            p.updated_at AS \"updated_at: DateTime<Utc>\", p.version, p.user_id, p.variant, p.position, p.seq, p.org_id, \
+            p.remind_at AS \"remind_at: DateTime<Utc>\", p.title, p.excerpt, r.post_id IS NOT NULL AS \"favorited!: bool\" FROM posts p \
+            LEFT JOIN post_reactions r ON r.post_id = p.id AND r.user_id = p.user_id AND r.kind = 'favorite' \
+            WHERE p.user_id = ? AND (? IS NULL OR p.updated_at >= ?) \
+            AND (? IS NULL OR p.variant = ?) ORDER BY p.updated_at DESC LIMIT ?",
+            self.user_id,
+            after,
+            after,
+            variant,
+            variant,
+            limit
+        )
+        .fetch_all(db)
+        .statement("posts.list_variant")
+        .await
+    }
+
+    /// Lists the user's posts updated at or after `since`, oldest first.
+    pub async fn posts_updated_since(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<Post>, sqlx::Error> {
+        sqlx::query_as!(
+            Post,
+            "SELECT p.id, p.parent_id, p.content, p.created_at AS \"created_at: DateTime<Utc>\", \
+            p.updated_at AS \"updated_at: DateTime<Utc>\", p.version, p.user_id, p.variant, p.position, p.seq, p.org_id, \
+            p.remind_at AS \"remind_at: DateTime<Utc>\", p.title, p.excerpt, r.post_id IS NOT NULL AS \"favorited!: bool\" FROM posts p \
+            LEFT JOIN post_reactions r ON r.post_id = p.id AND r.user_id = p.user_id AND r.kind = 'favorite' \
+            WHERE p.user_id = ? AND p.updated_at >= ? ORDER BY p.updated_at",
+            self.user_id,
+            since
+        )
+        .fetch_all(db)
+        .statement("posts.updated_since")
+        .await
+    }
+
+    /// Lists the user's posts written after `since_seq`, in write order. Fetches up to `limit` posts.
+    pub async fn posts_since_seq(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        since_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<Post>, sqlx::Error> {
+        sqlx::query_as!(
+            Post,
+            "SELECT p.id, p.parent_id, p.content, p.created_at AS \"created_at: DateTime<Utc>\", \
+            p.updated_at AS \"updated_at: DateTime<Utc>\", p.version, p.user_id, p.variant, p.position, p.seq, p.org_id, \
+            p.remind_at AS \"remind_at: DateTime<Utc>\", p.title, p.excerpt, r.post_id IS NOT NULL AS \"favorited!: bool\" FROM posts p \
+            LEFT JOIN post_reactions r ON r.post_id = p.id AND r.user_id = p.user_id AND r.kind = 'favorite' \
+            WHERE p.user_id = ? AND p.seq > ? ORDER BY p.seq LIMIT ?",
+            self.user_id,
+            since_seq,
+            limit
+        )
+        .fetch_all(db)
+        .statement("posts.list_since_seq")
+        .await
+    }
+
+    /// Returns the IDs of the user's posts deleted with a `seq` in `(since_seq, until_seq]`.
+    pub async fn posts_deleted_between(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        since_seq: i64,
+        until_seq: i64,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar!(
+            "SELECT post_id FROM post_tombstones WHERE user_id = ? AND seq > ? AND seq <= ? ORDER BY seq",
+            self.user_id,
+            since_seq,
+            until_seq
+        )
+        .fetch_all(db)
+        .statement("posts.tombstones")
+        .await
+    }
+
+    pub async fn post_read(self, db: &mut sqlx::SqliteConnection, id: &str) -> Result<Option<Post>, sqlx::Error> {
+        sqlx::query_as!(
+            Post,
+            "SELECT p.id, p.parent_id, p.content, p.created_at AS \"created_at: DateTime<Utc>\", \
+            p.updated_at AS \"updated_at: DateTime<Utc>\", p.version, p.user_id, p.variant, p.position, p.seq, p.org_id, \
+            p.remind_at AS \"remind_at: DateTime<Utc>\", p.title, p.excerpt, r.post_id IS NOT NULL AS \"favorited!: bool\" FROM posts p \
+            LEFT JOIN post_reactions r ON r.post_id = p.id AND r.user_id = p.user_id AND r.kind = 'favorite' \
+            WHERE p.id = ? AND p.user_id = ?",
+            id,
+            self.user_id
+        )
+        .fetch_optional(db)
+        .statement("posts.read")
+        .await
+    }
+
+    /// `post_read` reading only the given fields of the post; the others are left at their
+    /// defaults.
+    pub async fn post_read_fields(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        id: &str,
+        fields: &PostFields,
+    ) -> Result<Option<Post>, sqlx::Error> {
+        let mut builder = sqlx::QueryBuilder::new(format!(
+            "SELECT {} FROM posts p \
+            LEFT JOIN post_reactions r ON r.post_id = p.id AND r.user_id = p.user_id AND r.kind = 'favorite' \
+            WHERE p.id = ",
+            fields.columns()
+        ));
+        builder.push_bind(id).push(" AND p.user_id = ").push_bind(self.user_id);
+        builder
+            .build_query_as::<Post>()
+            .fetch_optional(db)
+            .statement("posts.read_fields")
+            .await
+    }
+
+    /// Reads those of the posts with `ids` the user has, in no particular order.
+    pub async fn posts_read_many(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        ids: &[&str],
+    ) -> Result<Vec<Post>, sqlx::Error> {
+        let ids = rocket::serde::json::to_string(&ids).expect("IDs serialize");
+        sqlx::query_as!(
+            Post,
+            "SELECT p.id, p.parent_id, p.content, p.created_at AS \"created_at: DateTime<Utc>\", \
+            p.updated_at AS \"updated_at: DateTime<Utc>\", p.version, p.user_id, p.variant, p.position, p.seq, p.org_id, \
+            p.remind_at AS \"remind_at: DateTime<Utc>\", p.title, p.excerpt, r.post_id IS NOT NULL AS \"favorited!: bool\" FROM posts p \
+            LEFT JOIN post_reactions r ON r.post_id = p.id AND r.user_id = p.user_id AND r.kind = 'favorite' \
+            WHERE p.user_id = ? AND p.id IN (SELECT value FROM json_each(?))",
+            self.user_id,
+            ids
+        )
+        .fetch_all(db)
+        .statement("posts.read_many")
+        .await
+    }
+
+    /// Returns the (possibly encrypted) content of the post at `version`, while it is among the
+    /// revisions kept.
+    pub async fn post_revision(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        id: &str,
+        version: &Hlc,
+    ) -> Result<Option<String>, sqlx::Error> {
+        let version = version.to_string();
+        sqlx::query_scalar!(
+            "SELECT r.content FROM post_revisions r JOIN posts p ON p.id = r.post_id \
+            WHERE r.post_id = ? AND r.version = ? AND p.user_id = ?",
+            id,
+            version,
+            self.user_id
+        )
+        .fetch_optional(db)
+        .statement("posts.revision")
+        .await
+    }
+
+    /// Whether the post exists and belongs to the user.
+    pub async fn post_exists(self, db: &mut sqlx::SqliteConnection, id: &str) -> Result<bool, sqlx::Error> {
+        let post = sqlx::query!("SELECT id FROM posts WHERE id = ? AND user_id = ?", id, self.user_id)
+            .fetch_optional(db)
+            .statement("posts.owned")
+            .await?;
+        Ok(post.is_some())
+    }
+
+    /// Whether a post with this ID exists but belongs to another user, which is all a scope tells
+    /// about other users' posts.
+    pub async fn post_foreign(self, db: &mut sqlx::SqliteConnection, id: &str) -> Result<bool, sqlx::Error> {
+        let post = sqlx::query!("SELECT id FROM posts WHERE id = ? AND user_id != ?", id, self.user_id)
+            .fetch_optional(db)
+            .statement("posts.foreign")
+            .await?;
+        Ok(post.is_some())
+    }
+
+    /// Returns the `parent_id` of the post, or `None` when the user has no such post.
+    pub async fn post_parent_id(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        id: &str,
+    ) -> Result<Option<Option<String>>, sqlx::Error> {
+        let post = sqlx::query!(
+            "SELECT parent_id FROM posts WHERE id = ? AND user_id = ?",
+            id,
+            self.user_id
+        )
+        .fetch_optional(db)
+        .statement("posts.parent")
+        .await?;
+        Ok(post.map(|post| post.parent_id))
+    }
+
+    /// Whether another of the user's posts than `id` has the given content hash.
+    pub async fn post_duplicate_exists(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        content_hash: &str,
+        id: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let post = sqlx::query!(
+            "SELECT id FROM posts WHERE user_id = ? AND content_hash = ? AND id != ? LIMIT 1",
+            self.user_id,
+            content_hash,
+            id
+        )
+        .fetch_optional(db)
+        .statement("posts.dedupe")
+        .await?;
+        Ok(post.is_some())
+    }
+
+    /// Returns those of `ids` that are taken by posts of other users. Post IDs are unique across
+    /// users, and the upsert leaves such posts alone.
+    pub async fn post_ids_taken(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        ids: &[&str],
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let ids = rocket::serde::json::to_string(&ids).expect("IDs serialize");
+        sqlx::query_scalar!(
+            "SELECT id FROM posts WHERE user_id != ? AND id IN (SELECT value FROM json_each(?)) ORDER BY id",
+            self.user_id,
+            ids
+        )
+        .fetch_all(db)
+        .statement("posts.ids_taken")
+        .await
+    }
+
+    /// Inserts the posts, or overwrites those the user already has with an older `version`.
+    /// Posts of other users with the same ID are left alone, see `post_ids_taken`.
+    pub async fn posts_upsert(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        posts: &[PostWrite<'_>],
+    ) -> Result<(), sqlx::Error> {
+        if posts.is_empty() {
+            return Ok(());
+        }
+
+        let mut builder = sqlx::QueryBuilder::new(
+            "INSERT INTO posts (created_at, id, parent_id, content, content_hash, title, excerpt, updated_at, version, \
+            user_id, variant, position, org_id, remind_at) ",
+        );
+        builder.push_values(posts, |mut row, post| {
+            row.push_bind(post.created_at)
+                .push_bind(post.id)
+                .push_bind(post.parent_id)
+                .push_bind(&post.content.content)
+                .push_bind(&post.content.content_hash)
+                .push_bind(&post.content.title)
+                .push_bind(&post.content.excerpt)
+                .push_bind(post.updated_at)
+                .push_bind(post.version.to_string())
+                .push_bind(self.user_id)
+                .push_bind(post.variant)
+                .push_bind(post.position)
+                .push_bind(post.org_id)
+                .push_bind(post.remind_at);
+        });
+        builder.push(
+            " ON CONFLICT(id) DO UPDATE SET parent_id = excluded.parent_id, content = excluded.content, \
+            content_hash = excluded.content_hash, title = excluded.title, excerpt = excluded.excerpt, \
+            variant = excluded.variant, position = excluded.position, \
+            org_id = excluded.org_id, remind_at = excluded.remind_at, updated_at = excluded.updated_at, \
+            version = excluded.version",
+        );
+        builder.push(" WHERE posts.version < excluded.version AND posts.user_id = excluded.user_id");
+
+        builder.build().execute(db).statement("posts.upsert_many").await?;
+        response_cache().invalidate_user(self.user_id);
+        Ok(())
+    }
+
+    /// Updates the content (and the position, when given) of a post whose `version` is older.
+    /// Returns whether it was updated.
+    pub async fn post_update(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        id: &str,
+        content: &StoredContent,
+        position: Option<f64>,
+        stamp: &WriteStamp,
+    ) -> Result<bool, sqlx::Error> {
+        let version = stamp.version.to_string();
+        let result = sqlx::query!(
+            "UPDATE posts SET content = ?, content_hash = ?, title = ?, excerpt = ?, position = COALESCE(?, position), \
+            updated_at = ?, version = ? WHERE id = ? AND user_id = ? AND version < ?",
+            content.content,
+            content.content_hash,
+            content.title,
+            content.excerpt,
+            position,
+            stamp.updated_at,
+            version,
+            id,
+            self.user_id,
+            version,
+        )
+        .execute(db)
+        .statement("posts.update")
+        .await?;
+        response_cache().invalidate_user(self.user_id);
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns the `position` of the post, or `None` when the user has no such post.
+    pub async fn post_position(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        id: &str,
+    ) -> Result<Option<Option<f64>>, sqlx::Error> {
+        let post = sqlx::query!(
+            "SELECT position FROM posts WHERE id = ? AND user_id = ?",
+            id,
+            self.user_id
+        )
+        .fetch_optional(db)
+        .statement("posts.position")
+        .await?;
+        Ok(post.map(|post| post.position))
+    }
+
+    /// Returns the stored version of the user's post, or `None` when the user has no such post.
+    pub async fn post_version(self, db: &mut sqlx::SqliteConnection, id: &str) -> Result<Option<String>, sqlx::Error> {
+        let post = sqlx::query!(
+            "SELECT version FROM posts WHERE id = ? AND user_id = ?",
+            id,
+            self.user_id
+        )
+        .fetch_optional(db)
+        .statement("posts.version")
+        .await?;
+        Ok(post.map(|post| post.version))
+    }
+
+    /// Returns the variant of the user's post, or `None` when the user has no such post.
+    pub async fn post_variant(self, db: &mut sqlx::SqliteConnection, id: &str) -> Result<Option<String>, sqlx::Error> {
+        let post = sqlx::query!(
+            "SELECT variant FROM posts WHERE id = ? AND user_id = ?",
+            id,
+            self.user_id
+        )
+        .fetch_optional(db)
+        .statement("posts.variant")
+        .await?;
+        Ok(post.map(|post| post.variant))
+    }
+
+    pub async fn posts_position_max(self, db: &mut sqlx::SqliteConnection) -> Result<Option<f64>, sqlx::Error> {
+        sqlx::query_scalar!("SELECT MAX(position) FROM posts WHERE user_id = ?", self.user_id)
+            .fetch_one(db)
+            .statement("posts.position_max")
+            .await
+    }
+
+    /// Spreads the positions of the user's positioned posts out to 1, 2, 3... keeping their order.
+    /// Returns the IDs of the renumbered posts.
+    pub async fn posts_renumber(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        stamp: &WriteStamp,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let version = stamp.version.to_string();
+        let renumbered = sqlx::query_scalar!(
+            "UPDATE posts SET position = ranked.rank, updated_at = ?, version = ? \
+            FROM (SELECT id, ROW_NUMBER() OVER (ORDER BY position, updated_at DESC) AS rank \
+            FROM posts WHERE user_id = ? AND position IS NOT NULL) AS ranked \
+            WHERE posts.id = ranked.id RETURNING posts.id",
+            stamp.updated_at,
+            version,
+            self.user_id
+        )
+        .fetch_all(db)
+        .statement("posts.renumber")
+        .await?;
+        response_cache().invalidate_user(self.user_id);
+        Ok(renumbered)
+    }
+
+    /// Moves a post to `position`. Returns whether the user has such a post.
+    pub async fn post_position_set(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        id: &str,
+        position: f64,
+        stamp: &WriteStamp,
+    ) -> Result<bool, sqlx::Error> {
+        let version = stamp.version.to_string();
+        let result = sqlx::query!(
+            "UPDATE posts SET position = ?, updated_at = ?, version = ? WHERE id = ? AND user_id = ?",
+            position,
+            stamp.updated_at,
+            version,
+            id,
+            self.user_id
+        )
+        .execute(db)
+        .statement("posts.move")
+        .await?;
+        response_cache().invalidate_user(self.user_id);
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Sets or clears when to remind the user of a post. Returns whether the user has such a post.
+    pub async fn post_remind_at_set(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        id: &str,
+        remind_at: Option<DateTime<Utc>>,
+        stamp: &WriteStamp,
+    ) -> Result<bool, sqlx::Error> {
+        let version = stamp.version.to_string();
+        let result = sqlx::query!(
+            "UPDATE posts SET remind_at = ?, updated_at = ?, version = ? WHERE id = ? AND user_id = ?",
+            remind_at,
+            stamp.updated_at,
+            version,
+            id,
+            self.user_id
+        )
+        .execute(db)
+        .statement("posts.remind_at")
+        .await?;
+        response_cache().invalidate_user(self.user_id);
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Moves the children of a post up to its parent. Returns the IDs of the moved posts.
+    pub async fn posts_reparent_children(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        id: &str,
+        stamp: &WriteStamp,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let version = stamp.version.to_string();
+        let moved = sqlx::query_scalar!(
+            "UPDATE posts SET parent_id = (SELECT parent_id FROM posts WHERE id = ? AND user_id = ?), \
+            updated_at = ?, version = ? WHERE parent_id = ? AND user_id = ? RETURNING id",
+            id,
+            self.user_id,
+            stamp.updated_at,
+            version,
+            id,
+            self.user_id
+        )
+        .fetch_all(db)
+        .statement("posts.reparent")
+        .await?;
+        response_cache().invalidate_user(self.user_id);
+        Ok(moved)
+    }
+
+    /// Returns the IDs of all descendants of a post.
+    pub async fn posts_descendants(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        id: &str,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar!(
+            "WITH RECURSIVE descendants(id) AS ( \
+            SELECT id FROM posts WHERE parent_id = ? AND user_id = ? \
+            UNION SELECT p.id FROM posts p JOIN descendants d ON p.parent_id = d.id AND p.user_id = ?) \
+            SELECT id AS \"id!\" FROM descendants",
+            id,
+            self.user_id,
+            self.user_id
+        )
+        .fetch_all(db)
+        .statement("posts.descendants")
+        .await
+    }
+
+    /// Deletes a single post row; children are handled by the foreign key. Returns whether the user
+    /// had such a post.
+    pub async fn post_remove(self, db: &mut sqlx::SqliteConnection, id: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!("DELETE FROM posts WHERE id = ? AND user_id = ?", id, self.user_id)
+            .execute(db)
+            .statement("posts.delete")
+            .await?;
+        response_cache().invalidate_user(self.user_id);
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn posts_remove_all(self, db: &mut sqlx::SqliteConnection) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM posts WHERE user_id = ?", self.user_id)
+            .execute(db)
+            .statement("posts.delete_all")
+            .await?;
+        response_cache().invalidate_user(self.user_id);
+        Ok(())
+    }
+
+    /// Adds the user's reaction of `kind` to a post at `now`. The caller checks that the post is
+    /// theirs.
+    pub async fn post_reaction_add(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        id: &str,
+        kind: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "INSERT OR IGNORE INTO post_reactions (post_id, user_id, kind, created_at) VALUES (?, ?, ?, ?)",
+            id,
+            self.user_id,
+            kind,
+            now
+        )
+        .execute(db)
+        .statement("posts.favorite")
+        .await?;
+        response_cache().invalidate_user(self.user_id);
+        Ok(())
+    }
+
+    pub async fn post_reaction_remove(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        id: &str,
+        kind: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "DELETE FROM post_reactions WHERE post_id = ? AND user_id = ? AND kind = ?",
+            id,
+            self.user_id,
+            kind
+        )
+        .execute(db)
+        .statement("posts.unfavorite")
+        .await?;
+        response_cache().invalidate_user(self.user_id);
+        Ok(())
+    }
+
+    /// Lists the posts shared with an organization the user is a member of, newest first, with
+    /// the user's own stars. Empty for other organizations. Fetches up to `limit` posts.
+    pub async fn org_posts(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        org_id: &str,
+        after: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<Post>, sqlx::Error> {
+        sqlx::query_as!(
+            Post,
+            "SELECT p.id, p.parent_id, p.content, p.created_at AS \"created_at: DateTime<Utc>\", \
+            p.updated_at AS \"updated_at: DateTime<Utc>\", p.version, p.user_id, p.variant, p.position, p.seq, p.org_id, \
+            p.remind_at AS \"remind_at: DateTime<Utc>\", p.title, p.excerpt, r.post_id IS NOT NULL AS \"favorited!: bool\" FROM posts p \
+            JOIN organization_members m ON m.org_id = p.org_id AND m.user_id = ? \
+            LEFT JOIN post_reactions r ON r.post_id = p.id AND r.user_id = m.user_id AND r.kind = 'favorite' \
+            WHERE p.org_id = ? AND (? IS NULL OR p.updated_at >= ?) ORDER BY p.updated_at DESC LIMIT ?",
+            self.user_id,
+            org_id,
+            after,
+            after,
+            limit
+        )
+        .fetch_all(db)
+        .statement("organizations.posts")
+        .await
+    }
+
+    /// The user's own row. `None` once the account was deleted.
+    pub async fn user_read(self, db: &mut sqlx::SqliteConnection) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as!(
+            User,
+            "SELECT id, created_at AS \"created_at: DateTime<Utc>\", email, code_hash, code_attempts, \
+            code_created_at AS \"code_created_at: DateTime<Utc>\", \
+            email_verified_at AS \"email_verified_at: DateTime<Utc>\", \
+            disabled_at AS \"disabled_at: DateTime<Utc>\" FROM users WHERE id = ?",
+            self.user_id
+        )
+        .fetch_optional(db)
+        .statement("users.by_id")
+        .await
+    }
+
+    /// The user's preferences as `(key, JSON value)` pairs, sorted by key.
+    pub async fn preferences(self, db: &mut sqlx::SqliteConnection) -> Result<Vec<(String, String)>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT key, value FROM user_preferences WHERE user_id = ? ORDER BY key",
+            self.user_id
+        )
+        .fetch_all(db)
+        .statement("user_preferences.list")
+        .await?;
+        Ok(rows.into_iter().map(|row| (row.key, row.value)).collect())
+    }
+
+    pub async fn preference_set(
+        self,
+        db: &mut sqlx::SqliteConnection,
+        key: &str,
+        value: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "INSERT INTO user_preferences (user_id, key, value, updated_at) VALUES (?, ?, ?, ?) \
+            ON CONFLICT(user_id, key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
+            self.user_id,
+            key,
+            value,
+            now,
+        )
+        .execute(db)
+        .statement("user_preferences.upsert")
+        .await?;
+        Ok(())
+    }
+
+    pub async fn preference_delete(self, db: &mut sqlx::SqliteConnection, key: &str) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "DELETE FROM user_preferences WHERE user_id = ? AND key = ?",
+            self.user_id,
+            key
+        )
+        .execute(db)
+        .statement("user_preferences.delete")
+        .await?;
+        Ok(())
+    }
+
+    pub async fn preferences_count(self, db: &mut sqlx::SqliteConnection) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!: i64" FROM user_preferences WHERE user_id = ?"#,
+            self.user_id
+        )
+        .fetch_one(db)
+        .statement("user_preferences.count")
+        .await
+    }
+}
@@ -0,0 +1,129 @@
+use rocket::serde::json;
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::config::config_lookup;
+
+/// Where `secrets_load` takes secrets from, per `SECRETS_SOURCE`. The keys of the secret are setting
+/// names, e.g. `DKIM_KEY_PRIVATE` and `ROCKET_SECRET_KEY`, which then need not be in the
+/// environment at all.
+///
+/// - `vault`: the KV secret at `VAULT_SECRET_PATH` (e.g. `secret/data/rocket-sqlx` for a KV v2
+///   engine) on the Vault server at `VAULT_ADDR`, read with `VAULT_TOKEN` (or `VAULT_TOKEN_FILE`)
+/// - `sops`: the SOPS-encrypted file at `SOPS_SECRETS_FILE`, decrypted by the `sops` binary with the
+///   keys it finds in its environment (age, KMS...)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretsSource {
+    Vault { addr: String, token: String, path: String },
+    Sops { file: String },
+}
+
+impl SecretsSource {
+    /// Returns `None` when `SECRETS_SOURCE` is unset.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let required = |name: &str| match config_lookup(name)? {
+            Some(value) if !value.trim().is_empty() => Ok(value.trim().to_string()),
+            _ => Err(format!("{} must be set when SECRETS_SOURCE is", name)),
+        };
+        match config_lookup("SECRETS_SOURCE")?.unwrap_or_default().as_str() {
+            "" => Ok(None),
+            "vault" => Ok(Some(Self::Vault {
+                addr: required("VAULT_ADDR")?.trim_end_matches('/').to_string(),
+                token: required("VAULT_TOKEN")?,
+                path: required("VAULT_SECRET_PATH")?.trim_matches('/').to_string(),
+            })),
+            "sops" => Ok(Some(Self::Sops {
+                file: required("SOPS_SECRETS_FILE")?,
+            })),
+            other => Err(format!("SECRETS_SOURCE has an invalid value: {}", other)),
+        }
+    }
+
+    /// Fetches the secrets. Blocking: it runs before Rocket's runtime starts.
+    pub fn fetch(&self) -> Result<HashMap<String, String>, String> {
+        match self {
+            Self::Vault { addr, token, path } => {
+                let url = format!("{}/v1/{}", addr, path);
+                let runtime = rocket::tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(|e| e.to_string())?;
+                let body = runtime.block_on(async {
+                    let client = reqwest::Client::builder()
+                        .timeout(Duration::from_secs(10))
+                        .build()
+                        .map_err(|e| e.to_string())?;
+                    let response = client
+                        .get(&url)
+                        .header("X-Vault-Token", token)
+                        .send()
+                        .await
+                        .map_err(|e| format!("Vault request failed: {}", e))?;
+                    if !response.status().is_success() {
+                        return Err(format!("Vault answered {} for {}", response.status(), path));
+                    }
+                    response.json::<json::Value>().await.map_err(|e| e.to_string())
+                })?;
+                // KV v2 nests the secret in data.data, KV v1 has it in data
+                let data = &body["data"];
+                let secret = match data.get("data").filter(|data| data.is_object()) {
+                    Some(secret) => secret,
+                    None => data,
+                };
+                secrets_from_json(secret)
+            }
+            Self::Sops { file } => {
+                let output = Command::new("sops")
+                    .args(["--decrypt", "--output-type", "json", file])
+                    .output()
+                    .map_err(|e| format!("failed to run sops: {}", e))?;
+                if !output.status.success() {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    return Err(format!("sops failed ({}): {}", output.status, stderr.trim()));
+                }
+                let secret = json::from_slice::<json::Value>(&output.stdout).map_err(|e| e.to_string())?;
+                secrets_from_json(&secret)
+            }
+        }
+    }
+}
+
+/// The settings in a JSON object of secrets. Values that are not strings are kept as their JSON,
+/// e.g. `true` or `3`.
+pub fn secrets_from_json(secret: &json::Value) -> Result<HashMap<String, String>, String> {
+    let secret = secret.as_object().ok_or("the secret is not an object of settings")?;
+    Ok(secret
+        .iter()
+        .filter(|(_, value)| !value.is_null())
+        .map(|(name, value)| {
+            let value = match value {
+                json::Value::String(value) => value.clone(),
+                other => other.to_string(),
+            };
+            (name.clone(), value)
+        })
+        .collect())
+}
+
+static SECRETS: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// A secret loaded by `secrets_load`.
+pub fn secret_get(name: &str) -> Option<&'static str> {
+    SECRETS.get()?.get(name).map(String::as_str)
+}
+
+/// Loads the secrets of `SECRETS_SOURCE`, which `config_var` then finds. Call it first thing at
+/// startup, before any setting is read. Returns how many secrets were loaded.
+pub fn secrets_load() -> Result<usize, String> {
+    let Some(source) = SecretsSource::from_env()? else {
+        return Ok(0);
+    };
+    let secrets = source.fetch()?;
+    let count = secrets.len();
+    SECRETS
+        .set(secrets)
+        .map_err(|_| "secrets are already loaded".to_string())?;
+    Ok(count)
+}
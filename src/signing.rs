@@ -0,0 +1,174 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL;
+use hmac::{Hmac, Mac};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::api::{API_LEGACY, API_V1};
+use crate::util::*;
+
+/// Longest lifetime of a signed URL.
+pub const SIGNED_URL_TTL_MAX_SECS: i64 = 7 * 24 * 3600;
+
+/// Why a signed URL was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    /// `expires`, `kid` or `sig` is missing or malformed.
+    Malformed,
+    Expired,
+    /// Signed with a key that is no longer configured, i.e. revoked by rotation.
+    UnknownKey(String),
+    Invalid,
+}
+
+impl std::fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "signed URL is malformed"),
+            Self::Expired => write!(f, "signed URL has expired"),
+            Self::UnknownKey(id) => write!(f, "signed URL uses unknown key {}", id),
+            Self::Invalid => write!(f, "signed URL signature is invalid"),
+        }
+    }
+}
+
+/// Signs URLs with HMAC-SHA256 so that resources can be handed to third parties without a session,
+/// until the URL expires.
+///
+/// Keys are loaded from the environment as `<id>:<secret>` pairs: `URL_SIGNING_KEY` signs new URLs
+/// and `URL_SIGNING_KEYS_OLD` (comma-separated) lists retired keys whose URLs are still honored.
+/// Dropping a key revokes every URL signed with it. Without `URL_SIGNING_KEY` a random key is used,
+/// so signed URLs only last until the process restarts and only work on the instance that signed
+/// them.
+pub struct UrlSigner {
+    active: String,
+    keys: HashMap<String, Vec<u8>>,
+}
+
+fn key_parse(entry: &str) -> (String, Vec<u8>) {
+    let (id, secret) = entry
+        .split_once(':')
+        .unwrap_or_else(|| panic!("URL signing key must be <id>:<secret>, got {}", entry));
+    let secret = secret.trim();
+    if secret.len() < 32 {
+        panic!("URL signing key {} must be at least 32 characters", id);
+    }
+    (id.trim().to_string(), secret.as_bytes().to_vec())
+}
+
+impl UrlSigner {
+    pub fn new(active: &str, old: &[&str]) -> Self {
+        let mut keys = old.iter().map(|entry| key_parse(entry)).collect::<HashMap<_, _>>();
+        let (active, key) = key_parse(active);
+        keys.insert(active.clone(), key);
+        Self { active, keys }
+    }
+
+    pub fn from_env() -> Self {
+        // Not `env_list`, which lowercases and would change the secrets
+        let old = config_var("URL_SIGNING_KEYS_OLD").unwrap_or_default();
+        let old = old
+            .split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+            .collect::<Vec<_>>();
+        match config_var("URL_SIGNING_KEY") {
+            Some(active) => Self::new(&active, &old),
+            None => {
+                tracing::warn!("URL_SIGNING_KEY is not set, signed URLs will not survive a restart");
+                Self::new(&format!("ephemeral:{}", nanoid::nanoid!(43)), &old)
+            }
+        }
+    }
+
+    fn mac(key: &[u8], path: &str, expires: i64, key_id: &str) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(format!("{}\n{}\n{}", path, expires, key_id).as_bytes());
+        mac
+    }
+
+    /// Returns the query string granting access to `path` (without its `/api` prefix) until
+    /// `expires_at`.
+    pub fn sign(&self, path: &str, expires_at: DateTime<Utc>) -> String {
+        let expires = expires_at.timestamp();
+        let mac = Self::mac(&self.keys[&self.active], path, expires, &self.active);
+        let signature = BASE64_URL.encode(mac.finalize().into_bytes());
+        format!("expires={}&kid={}&sig={}", expires, self.active, signature)
+    }
+
+    /// Checks a signature of `path` made by `sign`, as of `now`.
+    pub fn verify(
+        &self,
+        path: &str,
+        expires: i64,
+        key_id: &str,
+        signature: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(), SignatureError> {
+        let key = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| SignatureError::UnknownKey(key_id.to_string()))?;
+        let signature = BASE64_URL.decode(signature).map_err(|_| SignatureError::Malformed)?;
+        Self::mac(key, path, expires, key_id)
+            .verify_slice(&signature)
+            .map_err(|_| SignatureError::Invalid)?;
+        match now.timestamp() < expires {
+            true => Ok(()),
+            false => Err(SignatureError::Expired),
+        }
+    }
+}
+
+/// Returns the process-wide `UrlSigner`.
+pub fn url_signer() -> &'static UrlSigner {
+    static SIGNER: OnceLock<UrlSigner> = OnceLock::new();
+    SIGNER.get_or_init(UrlSigner::from_env)
+}
+
+/// Returns an absolute URL of the `/api/v1` resource at `path` (e.g. `/shared/posts/<id>`), signed
+/// until `expires_at`.
+pub fn signed_url(path: &str, expires_at: DateTime<Utc>) -> String {
+    format!(
+        "{}{}{}?{}",
+        app_url(),
+        API_V1,
+        path,
+        url_signer().sign(path, expires_at)
+    )
+}
+
+/// Request guard admitting requests to a URL made by `signed_url`, under either API prefix.
+/// Refused ones get a 403.
+pub struct SignedUrl;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SignedUrl {
+    type Error = SignatureError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let path = request.uri().path().as_str();
+        let path = path
+            .strip_prefix(API_V1)
+            .or_else(|| path.strip_prefix(API_LEGACY))
+            .unwrap_or(path);
+        let param = |name| request.query_value::<&str>(name).and_then(Result::ok);
+        let verified = match (
+            param("expires").and_then(|e| e.parse().ok()),
+            param("kid"),
+            param("sig"),
+        ) {
+            (Some(expires), Some(key_id), Some(signature)) => {
+                url_signer().verify(path, expires, key_id, signature, Utc::now())
+            }
+            _ => Err(SignatureError::Malformed),
+        };
+        match verified {
+            Ok(()) => request::Outcome::Success(SignedUrl),
+            Err(e) => request::Outcome::Error((Status::Forbidden, e)),
+        }
+    }
+}
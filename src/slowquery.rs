@@ -0,0 +1,237 @@
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+use tracing_subscriber::Layer;
+use tracing_subscriber::filter::{FilterFn, filter_fn};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::metrics::metrics;
+use crate::util::*;
+
+/// Timing of the database queries, from the `db_query` spans of `query_span` and the statement
+/// events sqlx logs inside them:
+///
+/// - every query is observed in `db_query_duration_seconds`, by query name
+/// - queries slower than `DB_SLOW_QUERY_MS` (250, 0 turns it off) are logged with their statement
+///   and counted in `db_slow_queries_total`. Parameters are bound, so never part of the statement,
+///   and literals written into it are redacted
+/// - the time each request spent in queries is observed in `db_request_duration_seconds`, by route
+#[derive(Debug, Clone)]
+pub struct QueryTimer {
+    slow: Option<Duration>,
+}
+
+impl QueryTimer {
+    pub fn new(slow: Option<Duration>) -> Self {
+        Self { slow }
+    }
+
+    pub fn from_env() -> Self {
+        let slow = env_parse_or("DB_SLOW_QUERY_MS", 250u64);
+        Self::new((slow > 0).then(|| Duration::from_millis(slow)))
+    }
+
+    /// The layer, with a filter of its own, so that it sees the queries whatever the log filter.
+    pub fn layer<S>(self) -> tracing_subscriber::filter::Filtered<Self, FilterFn, S>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        self.with_filter(filter_fn(query_timer_wants as fn(&Metadata<'_>) -> bool))
+    }
+}
+
+fn query_timer_wants(metadata: &Metadata<'_>) -> bool {
+    match metadata.is_span() {
+        true => matches!(metadata.name(), "db_query" | "request"),
+        false => metadata.target() == "sqlx::query",
+    }
+}
+
+/// Kept in the extensions of a `db_query` span.
+struct QueryTiming {
+    name: String,
+    started_at: Instant,
+    statement: Option<String>,
+}
+
+/// Kept in the extensions of a `request` span.
+#[derive(Default)]
+struct RequestTiming {
+    route: Option<String>,
+    queries: u32,
+    spent: Duration,
+}
+
+/// Reads the string fields this layer needs.
+#[derive(Default)]
+struct FieldsVisit {
+    query: Option<String>,
+    route: Option<String>,
+    summary: Option<String>,
+    statement: Option<String>,
+}
+
+impl Visit for FieldsVisit {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let slot = match field.name() {
+            "query" => &mut self.query,
+            "route" => &mut self.route,
+            "summary" => &mut self.summary,
+            "db.statement" => &mut self.statement,
+            _ => return,
+        };
+        *slot = Some(value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.record_str(field, format!("{:?}", value).trim_matches('"'));
+    }
+}
+
+impl<S> Layer<S> for QueryTimer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = FieldsVisit::default();
+        attrs.record(&mut fields);
+        let mut extensions = span.extensions_mut();
+        match span.name() {
+            "db_query" => extensions.insert(QueryTiming {
+                name: fields.query.unwrap_or_else(|| "unknown".into()),
+                started_at: Instant::now(),
+                statement: None,
+            }),
+            _ => extensions.insert(RequestTiming {
+                route: fields.route,
+                ..RequestTiming::default()
+            }),
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = FieldsVisit::default();
+        values.record(&mut fields);
+        if let (Some(route), Some(timing)) = (fields.route, span.extensions_mut().get_mut::<RequestTiming>()) {
+            timing.route = Some(route);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.event_span(event) else {
+            return;
+        };
+        let mut fields = FieldsVisit::default();
+        event.record(&mut fields);
+        // sqlx leaves the statement empty when the summary is all of it
+        let statement = fields
+            .statement
+            .filter(|statement| !statement.trim().is_empty())
+            .or(fields.summary);
+        if let (Some(statement), Some(timing)) = (statement, span.extensions_mut().get_mut::<QueryTiming>()) {
+            timing.statement = Some(statement);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let timing = span.extensions_mut().remove::<QueryTiming>();
+        let Some(timing) = timing else {
+            let request = span.extensions_mut().remove::<RequestTiming>();
+            if let Some(request) = request {
+                request_observe(request);
+            }
+            return;
+        };
+        let elapsed = timing.started_at.elapsed();
+        metrics().histogram_observe(
+            "db_query_duration_seconds",
+            "Time spent in database queries, by query.",
+            &[("query", &timing.name)],
+            elapsed.as_secs_f64(),
+        );
+        let request = span.scope().skip(1).find(|parent| parent.name() == "request");
+        let mut extensions = request.as_ref().map(|request| request.extensions_mut());
+        if let Some(request) = extensions.as_mut().and_then(|e| e.get_mut::<RequestTiming>()) {
+            request.queries += 1;
+            request.spent += elapsed;
+        }
+
+        if self.slow.is_some_and(|slow| elapsed >= slow) {
+            metrics().counter_inc(
+                "db_slow_queries_total",
+                "Database queries slower than DB_SLOW_QUERY_MS, by query.",
+                &[("query", &timing.name)],
+            );
+            let statement = timing.statement.as_deref().map(statement_redact);
+            tracing::warn!(
+                query = timing.name,
+                duration_ms = elapsed.as_millis() as u64,
+                statement,
+                "slow query"
+            );
+        }
+    }
+}
+
+fn request_observe(timing: RequestTiming) {
+    if timing.queries == 0 {
+        return;
+    }
+    metrics().histogram_observe(
+        "db_request_duration_seconds",
+        "Time each request spent in database queries, by route.",
+        &[("route", timing.route.as_deref().unwrap_or("unknown"))],
+        timing.spent.as_secs_f64(),
+    );
+}
+
+/// A statement on one line, with its string and number literals replaced by `?`, so that no value
+/// written into it reaches the logs.
+pub fn statement_redact(statement: &str) -> String {
+    let mut redacted = String::with_capacity(statement.len());
+    let mut chars = statement.chars().peekable();
+    // Whether a digit would belong to the previous word, e.g. the 2 in `posts_v2`
+    let mut in_word = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                // '' escapes a quote inside a string
+                while let Some(c) = chars.next() {
+                    if c == '\'' && chars.next_if_eq(&'\'').is_none() {
+                        break;
+                    }
+                }
+                redacted.push('?');
+                in_word = false;
+            }
+            '0'..='9' if !in_word => {
+                while chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '.').is_some() {}
+                redacted.push('?');
+            }
+            c if c.is_whitespace() => {
+                if !redacted.is_empty() && !redacted.ends_with(' ') {
+                    redacted.push(' ');
+                }
+                in_word = false;
+            }
+            c => {
+                redacted.push(c);
+                // Numbered parameters (`?1`, `$1`) are kept too
+                in_word = c.is_alphanumeric() || matches!(c, '_' | '"' | '`' | '?' | '$');
+            }
+        }
+    }
+    redacted.trim_end().to_string()
+}
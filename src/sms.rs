@@ -0,0 +1,108 @@
+use regex::Regex;
+use rocket::fairing::AdHoc;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use crate::config::config_var;
+use crate::util::manage_default;
+
+/// Sends text messages.
+#[rocket::async_trait]
+pub trait SmsSender: Send + Sync {
+    /// Texts `body` to `to`, an E.164 phone number, returning why it failed.
+    async fn send(&self, to: &str, body: &str) -> Result<(), String>;
+}
+
+/// Managed state holding the sender of text messages, such as login codes. When no provider is
+/// configured, users can't add a phone number.
+#[derive(Clone, Default)]
+pub struct Sms(pub Option<Arc<dyn SmsSender>>);
+
+impl Sms {
+    /// Builds the sender from `SMS_PROVIDER`: `twilio`, configured by `TWILIO_ACCOUNT_SID`,
+    /// `TWILIO_AUTH_TOKEN`, `SMS_FROM` and `TWILIO_API_URL` (for Twilio-compatible APIs), or `log`,
+    /// which only logs texts, for development.
+    pub fn from_env() -> Self {
+        let required = |name| config_var(name).unwrap_or_else(|| panic!("{} must be set when SMS_PROVIDER is", name));
+        let sender: Option<Arc<dyn SmsSender>> = match config_var("SMS_PROVIDER").unwrap_or_default().as_str() {
+            "" => None,
+            "twilio" => Some(Arc::new(Twilio {
+                api_url: config_var("TWILIO_API_URL")
+                    .unwrap_or_else(|| "https://api.twilio.com".into())
+                    .trim_end_matches('/')
+                    .to_string(),
+                account_sid: required("TWILIO_ACCOUNT_SID"),
+                auth_token: required("TWILIO_AUTH_TOKEN"),
+                from: required("SMS_FROM"),
+            })),
+            "log" => Some(Arc::new(LoggedSms)),
+            other => panic!("SMS_PROVIDER has an invalid value: {}", other),
+        };
+        Self(sender)
+    }
+}
+
+/// Validates a phone number in E.164 format, e.g. `+14155550100`.
+pub fn phone_is_valid(phone: &str) -> bool {
+    static PHONE_RE: OnceLock<Regex> = OnceLock::new();
+    let regex = PHONE_RE.get_or_init(|| Regex::new(r"^\+[1-9][0-9]{7,14}$").expect("failed to compile phone regex"));
+    regex.is_match(phone)
+}
+
+/// Returns the shared HTTP client used to call SMS providers.
+fn http_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("failed to build HTTP client")
+    })
+}
+
+/// Sends texts through the Messages API of Twilio, or of a provider speaking the same protocol.
+pub struct Twilio {
+    pub api_url: String,
+    pub account_sid: String,
+    pub auth_token: String,
+    /// The number, or messaging service, texts are sent from.
+    pub from: String,
+}
+
+#[rocket::async_trait]
+impl SmsSender for Twilio {
+    async fn send(&self, to: &str, body: &str) -> Result<(), String> {
+        let url = format!(
+            "{}/2010-04-01/Accounts/{}/Messages.json",
+            self.api_url, self.account_sid
+        );
+        http_client()
+            .post(url)
+            .basic_auth(&self.account_sid, Some(&self.auth_token))
+            .form(&[("To", to), ("From", self.from.as_str()), ("Body", body)])
+            .send()
+            .await
+            .map_err(|e| e.to_string())?
+            .error_for_status()
+            .map_err(|e| e.to_string())?;
+        tracing::info!("sms sent");
+        Ok(())
+    }
+}
+
+/// Logs texts instead of sending them.
+pub struct LoggedSms;
+
+#[rocket::async_trait]
+impl SmsSender for LoggedSms {
+    async fn send(&self, to: &str, body: &str) -> Result<(), String> {
+        tracing::info!(to, body, "sms send simulated");
+        Ok(())
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("SMS stage", |rocket| async {
+        manage_default(rocket, |_| Sms::from_env())
+    })
+}
@@ -0,0 +1,116 @@
+use rocket::serde::json;
+
+/// Longest title, in characters.
+pub const TITLE_MAX_CHARS: usize = 120;
+/// Longest excerpt, in characters.
+pub const EXCERPT_MAX_CHARS: usize = 280;
+
+/// The title and excerpt of a post content, for list views: the title is its first line with text
+/// and the excerpt the text after it on one line, both with the Markdown stripped and cut to
+/// `TITLE_MAX_CHARS` and `EXCERPT_MAX_CHARS`. A JSON object content with a string `title` (e.g. a
+/// todo) is titled by it instead, with no excerpt.
+pub fn summary_of(content: &str) -> (String, String) {
+    if let Ok(json::Value::Object(object)) = json::from_str::<json::Value>(content) {
+        let title = object.get("title").and_then(json::Value::as_str).unwrap_or_default();
+        return (chars_cut(title.trim(), TITLE_MAX_CHARS), String::new());
+    }
+
+    let mut lines = content.lines().map(markdown_strip).filter(|line| !line.is_empty());
+    let title = lines.next().unwrap_or_default();
+    let mut excerpt = String::new();
+    for line in lines {
+        if excerpt.chars().count() > EXCERPT_MAX_CHARS {
+            break;
+        }
+        if !excerpt.is_empty() {
+            excerpt.push(' ');
+        }
+        excerpt.push_str(&line);
+    }
+    (
+        chars_cut(&title, TITLE_MAX_CHARS),
+        chars_cut(&excerpt, EXCERPT_MAX_CHARS),
+    )
+}
+
+/// `text` cut to `max` characters, ending with an ellipsis when it was longer.
+fn chars_cut(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut cut = text.chars().take(max - 1).collect::<String>().trim_end().to_string();
+    cut.push('…');
+    cut
+}
+
+/// The text of a Markdown line: without heading, quote, list and task markers, fences, rules and
+/// inline markup, links and images keeping their text.
+pub fn markdown_strip(line: &str) -> String {
+    let mut line = line.trim();
+    if line.starts_with("```") || line.starts_with("~~~") {
+        return String::new();
+    }
+    // A rule is three or more of the same mark, spaces allowed
+    let marks = line.chars().filter(|c| !c.is_whitespace()).collect::<Vec<_>>();
+    if marks.len() >= 3 && matches!(marks[0], '-' | '*' | '_') && marks.iter().all(|c| *c == marks[0]) {
+        return String::new();
+    }
+    loop {
+        let stripped = line
+            .strip_prefix('>')
+            .or_else(|| {
+                let hashes = line.len() - line.trim_start_matches('#').len();
+                (1..=6)
+                    .contains(&hashes)
+                    .then(|| line[hashes..].strip_prefix(' '))
+                    .flatten()
+            })
+            .or_else(|| ["- ", "* ", "+ "].iter().find_map(|marker| line.strip_prefix(marker)))
+            .or_else(|| {
+                ["[ ] ", "[x] ", "[X] "]
+                    .iter()
+                    .find_map(|marker| line.strip_prefix(marker))
+            })
+            .or_else(|| {
+                let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                (digits > 0)
+                    .then(|| {
+                        line[digits..]
+                            .strip_prefix(". ")
+                            .or_else(|| line[digits..].strip_prefix(") "))
+                    })
+                    .flatten()
+            });
+        match stripped {
+            Some(rest) => line = rest.trim_start(),
+            None => break,
+        }
+    }
+
+    let mut text = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            // `![alt](url)` and `[text](url)` keep their text
+            '!' if chars.peek() == Some(&'[') => {}
+            '[' => {}
+            ']' if chars.peek() == Some(&'(') => {
+                for c in chars.by_ref() {
+                    if c == ')' {
+                        break;
+                    }
+                }
+            }
+            ']' => {}
+            '*' | '`' => {}
+            '~' if chars.peek() == Some(&'~') => {
+                chars.next();
+            }
+            '_' if chars.peek() == Some(&'_') => {
+                chars.next();
+            }
+            c => text.push(c),
+        }
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
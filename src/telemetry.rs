@@ -0,0 +1,251 @@
+use chrono::{DateTime, Utc};
+use rocket::Request;
+use rocket::serde::json;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use tracing::{Event, Subscriber};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::fmt::format::{FormatEvent, FormatFields, Writer};
+use tracing_subscriber::fmt::{FmtContext, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry, fmt, reload};
+
+use crate::db::id_gen;
+use crate::slowquery::QueryTimer;
+use crate::util::{app_mode, config_var};
+
+/// The span covering a single request, cached on the request so that guards can record fields
+/// (e.g. `user_id`) on it once they are known.
+pub struct RequestSpan(pub tracing::Span);
+
+impl RequestSpan {
+    /// Returns the span cached for this request, or a disabled span if none was created.
+    pub fn of<'r>(request: &'r Request<'_>) -> &'r tracing::Span {
+        &request.local_cache(|| RequestSpan(tracing::Span::none())).0
+    }
+}
+
+/// The user a request was made by, once the `UserCtx` guard has authenticated it.
+pub struct RequestUser(pub Option<i64>);
+
+impl RequestUser {
+    pub fn of(request: &Request<'_>) -> Option<i64> {
+        request.local_cache(|| RequestUser(None)).0
+    }
+
+    /// Records the user on the request and its span.
+    pub fn record(request: &Request<'_>, user_id: i64) {
+        request.local_cache(|| RequestUser(Some(user_id)));
+        RequestSpan::of(request).record("user_id", user_id);
+    }
+}
+
+/// Correlation ID of a request: the caller's `X-Request-Id` header when it looks sane, otherwise a
+/// freshly generated one. Returned in the `X-Request-Id` response header and in error bodies.
+pub struct RequestId(pub String);
+
+impl RequestId {
+    pub const HEADER: &'static str = "X-Request-Id";
+
+    /// Returns the ID of this request, assigning one on first use.
+    pub fn of<'r>(request: &'r Request<'_>) -> &'r str {
+        &request
+            .local_cache(|| {
+                let id = request
+                    .headers()
+                    .get_one(Self::HEADER)
+                    .filter(|id| {
+                        !id.is_empty()
+                            && id.len() <= 64
+                            && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+                    })
+                    .map(str::to_owned)
+                    .unwrap_or_else(id_gen);
+                RequestId(id)
+            })
+            .0
+    }
+}
+
+/// The `EnvFilter` directives of the subscriber: `LOG_FILTER`, falling back to `RUST_LOG`, then
+/// `info`. Errors on directives that don't parse.
+pub fn log_filter() -> Result<(String, EnvFilter), String> {
+    let directives = config_var("LOG_FILTER")
+        .or_else(|| config_var("RUST_LOG"))
+        .unwrap_or_else(|| "info".into());
+    let filter = EnvFilter::try_new(&directives).map_err(|e| format!("LOG_FILTER is invalid: {}", e))?;
+    Ok((directives, filter))
+}
+
+/// Swaps the filter of the subscriber installed by `tracing_init`.
+static LOG_FILTER_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Installs the global tracing subscriber.
+///
+/// The filter is read by `log_filter` using `EnvFilter` directives, e.g.
+/// `LOG_FILTER=info,rocket_sqlx=debug,sqlx=warn`, and may be changed at runtime by
+/// `log_filter_reload`. `LOG_FORMAT` selects `pretty` or `json` output and defaults to pretty in
+/// debug mode and JSON in production. Records emitted through the `log` crate by Rocket and sqlx
+/// are forwarded to the subscriber too.
+pub fn tracing_init() {
+    let filter = log_filter().map(|(_, filter)| filter).unwrap_or_else(|e| {
+        eprintln!("{}, logging at info", e);
+        EnvFilter::new("info")
+    });
+    let (filter, handle) = reload::Layer::new(filter);
+
+    let format = config_var("LOG_FORMAT").unwrap_or_else(|| match app_mode() {
+        "debug" => "pretty".into(),
+        _ => "json".into(),
+    });
+    // The filter is the output's own, as the query timer needs the queries whatever it lets through
+    let output = match format.as_str() {
+        "json" => fmt::layer().json().with_filter(filter).boxed(),
+        _ => fmt::layer().pretty().with_filter(filter).boxed(),
+    };
+    let output = output.with_filter(filter_fn(|metadata| metadata.target() != REQUEST_LOG_TARGET));
+
+    let subscriber = tracing_subscriber::registry()
+        .with(output)
+        .with(request_log_layer(std::io::stdout))
+        .with(QueryTimer::from_env().layer());
+    match subscriber.try_init() {
+        Ok(()) => {
+            let _ = LOG_FILTER_HANDLE.set(handle);
+        }
+        // A subscriber may already be installed, e.g. when several test clients are built
+        Err(e) => tracing::debug!("tracing subscriber already installed: {}", e),
+    }
+}
+
+/// Re-reads the log filter and applies it to the running subscriber. Returns its directives, or
+/// `None` when `tracing_init` did not install the subscriber.
+pub fn log_filter_reload() -> Result<Option<String>, String> {
+    let (directives, filter) = log_filter()?;
+    let Some(handle) = LOG_FILTER_HANDLE.get() else {
+        return Ok(None);
+    };
+    handle.reload(filter).map_err(|e| e.to_string())?;
+    Ok(Some(directives))
+}
+
+/// Target of the events carrying the preformatted `json` and `common` request log lines.
+pub const REQUEST_LOG_TARGET: &str = "request_log";
+
+/// Writes the events of `REQUEST_LOG_TARGET`, and only those, to `writer` as bare lines. They are
+/// left out of the regular output and its filter, so that `LOG_FILTER` can't drop the access log.
+pub fn request_log_layer<S, W>(writer: W) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    fmt::layer()
+        .with_ansi(false)
+        .with_writer(writer)
+        .event_format(MessageOnly)
+        .with_filter(filter_fn(|metadata| metadata.target() == REQUEST_LOG_TARGET))
+}
+
+/// Formats an event as its message alone.
+struct MessageOnly;
+
+impl<S, N> FormatEvent<S, N> for MessageOnly
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> std::fmt::Result {
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+/// How the request logger writes the line of each request, from `REQUEST_LOG_FORMAT`:
+///
+/// - `plain` (default): a `request completed` event in the request span, so written by the
+///   subscriber, pretty or JSON per `LOG_FORMAT`
+/// - `json`: one JSON object per request on stdout, through `request_log_layer`
+/// - `common`: the combined log format of Apache and nginx on stdout (the common log format
+///   followed by the referer and user agent), with the user ID as the remote user, for the usual
+///   log analyzers, through `request_log_layer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestLogFormat {
+    Plain,
+    Json,
+    Common,
+}
+
+impl RequestLogFormat {
+    pub fn from_env() -> Self {
+        match config_var("REQUEST_LOG_FORMAT").unwrap_or_default().as_str() {
+            "" | "plain" => Self::Plain,
+            "json" => Self::Json,
+            "common" => Self::Common,
+            other => panic!("REQUEST_LOG_FORMAT has an invalid value: {}", other),
+        }
+    }
+}
+
+/// Returns the process-wide `RequestLogFormat`.
+pub fn request_log_format() -> RequestLogFormat {
+    static FORMAT: OnceLock<RequestLogFormat> = OnceLock::new();
+    *FORMAT.get_or_init(RequestLogFormat::from_env)
+}
+
+/// What is logged of a request once it is answered.
+#[derive(Debug, Clone)]
+pub struct RequestLog {
+    pub at: DateTime<Utc>,
+    pub request_id: String,
+    pub method: String,
+    pub uri: String,
+    pub status: u16,
+    /// Size of the response body, when known without reading a streamed one.
+    pub bytes: Option<usize>,
+    pub duration_ms: i64,
+    pub user_id: Option<i64>,
+    pub client_ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    pub referer: Option<String>,
+}
+
+impl RequestLog {
+    pub fn to_json(&self) -> json::Value {
+        json::json!({
+            "timestamp": self.at.to_rfc3339(),
+            "requestId": self.request_id,
+            "method": self.method,
+            "uri": self.uri,
+            "status": self.status,
+            "bytes": self.bytes,
+            "durationMs": self.duration_ms,
+            "userId": self.user_id,
+            "clientIp": self.client_ip.map(|ip| ip.to_string()),
+            "userAgent": self.user_agent,
+            "referer": self.referer,
+        })
+    }
+
+    /// The line in the combined log format. The protocol is left out of the request line, as
+    /// Rocket doesn't expose it.
+    pub fn to_common(&self) -> String {
+        let quoted = |value: &Option<String>| match value {
+            Some(value) => format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+            None => "\"-\"".to_string(),
+        };
+        format!(
+            "{} - {} [{}] \"{} {}\" {} {} {} {}",
+            self.client_ip.map_or("-".to_string(), |ip| ip.to_string()),
+            self.user_id.map_or("-".to_string(), |id| id.to_string()),
+            self.at.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.method,
+            self.uri,
+            self.status,
+            self.bytes.map_or("-".to_string(), |bytes| bytes.to_string()),
+            quoted(&self.referer),
+            quoted(&self.user_agent),
+        )
+    }
+}
@@ -0,0 +1,87 @@
+use crate::tests::util::*;
+
+use rocket::http::{ContentType, Method, Status};
+use rocket::local::blocking::Client;
+use rocket::serde::json;
+
+use crate::handlers::posts::ForeignPostPolicy;
+
+/// Requests naming a post of another user, across the post, comment, attachment and share routes.
+const FOREIGN_REQUESTS: &[(Method, &str, &str)] = &[
+    (Method::Get, "/api/posts/theirs", ""),
+    (Method::Put, "/api/posts/theirs", r#"{"content":"Mine now"}"#),
+    (Method::Delete, "/api/posts/theirs", ""),
+    (Method::Put, "/api/posts/theirs/favorite", ""),
+    (Method::Delete, "/api/posts/theirs/reminder", ""),
+    (Method::Get, "/api/posts/theirs/comments", ""),
+    (Method::Post, "/api/posts/theirs/comments", r#"{"content":"Hi"}"#),
+    (Method::Get, "/api/posts/theirs/attachments", ""),
+    (Method::Put, "/api/posts/theirs/share", "{}"),
+];
+
+/// Returns the client, a user and the owner of the post `theirs`.
+fn client_with_foreign_post(policy: ForeignPostPolicy) -> (Client, i64, i64) {
+    let client = client_tracked_build(|rocket| rocket.manage(policy));
+    let owner_id = seed_user(&client, &email_for_session());
+    let user_id = seed_user(&client, &email_for_session());
+    let pool = pool_cloned_get(&client);
+    block_on(async move {
+        sqlx::query("INSERT INTO posts (id, user_id, content, variant) VALUES ('theirs', ?, 'Hello', 'note')")
+            .bind(owner_id)
+            .execute(&pool)
+            .await
+            .expect("insert post");
+        // Sharing takes a verified email
+        sqlx::query("UPDATE users SET email_verified_at = CURRENT_TIMESTAMP WHERE id = ?")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .expect("verify user");
+    });
+    (client, user_id, owner_id)
+}
+
+fn dispatch(client: &Client, user_id: i64, method: Method, uri: &'static str, body: &str) -> (Status, json::Value) {
+    let request = with_csrf(signed_in(client.req(method, uri), user_id));
+    let request = match body.is_empty() {
+        true => request,
+        false => request.header(ContentType::JSON).body(body),
+    };
+    let response = request.dispatch();
+    (response.status(), response.into_json().unwrap_or_default())
+}
+
+#[test]
+fn access_to_foreign_posts_is_hidden_by_default() {
+    let (client, user_id, _) = client_with_foreign_post(ForeignPostPolicy::Hide);
+    for &(method, uri, body) in FOREIGN_REQUESTS {
+        let (status, body) = dispatch(&client, user_id, method, uri, body);
+        assert_eq!(status, Status::NotFound, "{} {}", method, uri);
+        assert_eq!(body["code"], "not_found", "{} {}", method, uri);
+    }
+}
+
+#[test]
+fn access_to_foreign_posts_is_forbidden_in_strict_mode() {
+    let (client, user_id, owner_id) = client_with_foreign_post(ForeignPostPolicy::Forbid);
+    for &(method, uri, body) in FOREIGN_REQUESTS {
+        let (status, body) = dispatch(&client, user_id, method, uri, body);
+        assert_eq!(status, Status::Forbidden, "{} {}", method, uri);
+        assert_eq!(body["code"], "not_owner", "{} {}", method, uri);
+    }
+    let (status, body) = dispatch(
+        &client,
+        user_id,
+        Method::Post,
+        "/api/posts/delete-many",
+        r#"["theirs"]"#,
+    );
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(body["code"], "not_owner");
+
+    // Missing posts are still missing, and the owner is unaffected
+    let (status, _) = dispatch(&client, user_id, Method::Get, "/api/posts/missing", "");
+    assert_eq!(status, Status::NotFound);
+    let (status, _) = dispatch(&client, owner_id, Method::Get, "/api/posts/theirs", "");
+    assert_eq!(status, Status::Ok);
+}
@@ -0,0 +1,174 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+use rocket::serde::json;
+
+use crate::handlers::admin::users_merge;
+
+#[test]
+fn admin_routes_require_the_token() {
+    let client = client_tracked_get();
+    let user_id = seed_user(&client, &email_for_session());
+    let uri = format!("/api/admin/users/{}/suspend", user_id);
+
+    // Not configured
+    let response = client.post(uri.as_str()).header(admin_header()).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    let client = client_with_admin();
+    let user_id = seed_user(&client, &email_for_session());
+    let uri = format!("/api/admin/users/{}/suspend", user_id);
+    let response = client.post(uri.as_str()).header(bearer("forged")).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let response = client
+        .post("/api/admin/users/999999/suspend")
+        .header(admin_header())
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn admin_suspend_and_reactivate_user() {
+    let client = client_with_admin();
+    let email = email_for_session();
+    let user_id = seed_user(&client, &email);
+
+    let response = signed_in(client.get("/api/v1/session/"), user_id).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client
+        .post(format!("/api/admin/users/{}/suspend", user_id))
+        .header(admin_header())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = signed_in(client.get("/api/v1/session/"), user_id).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let response = client
+        .post(format!("/api/admin/users/{}/reactivate", user_id))
+        .header(admin_header())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    // Suspending revoked the sessions, so the user signs in again
+    let response = signed_in(client.get("/api/v1/session/"), user_id).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    seed_code(&client, &email, CODE_EXAMPLE);
+    let response = client
+        .post("/api/v1/session/login")
+        .json(&json::json!({ "email": email, "code": CODE_EXAMPLE }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = client.get("/api/v1/session/").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn admin_merge_keeps_the_target_on_conflicts() {
+    let app = TestApp::new().start().await;
+    let pool = app.pool();
+    let source = app.user_seed(&email_for_session()).await;
+    let target = app.user_seed(&email_for_session()).await;
+    for statement in [
+        format!("INSERT INTO posts (id, user_id, content, variant) VALUES ('source-post', {source}, 'Hello', 'note')"),
+        format!("INSERT INTO user_preferences (user_id, key, value) VALUES ({source}, 'theme', '\"dark\"')"),
+        format!("INSERT INTO user_preferences (user_id, key, value) VALUES ({source}, 'lang', '\"fr\"')"),
+        format!("INSERT INTO user_preferences (user_id, key, value) VALUES ({target}, 'theme', '\"light\"')"),
+        "INSERT INTO organizations (id, name) VALUES ('org', 'Org')".to_string(),
+        format!("INSERT INTO organization_members (org_id, user_id, role) VALUES ('org', {source}, 'owner')"),
+        format!("INSERT INTO organization_members (org_id, user_id, role) VALUES ('org', {target}, 'member')"),
+        format!("INSERT INTO sessions (id, user_id, expires_at) VALUES ('source-session', {source}, '2999-01-01')"),
+    ] {
+        sqlx::query(&statement).execute(&pool).await.unwrap();
+    }
+
+    let (mut db, now) = (pool.acquire().await.unwrap(), Utc::now());
+    assert!(users_merge(&mut db, source, source, now).await.unwrap().is_none());
+    let counts = users_merge(&mut db, source, target, now).await.unwrap().unwrap();
+    assert_eq!((counts.posts, counts.sessions, counts.preferences), (1, 1, 1));
+    // Merged accounts can't be merged again
+    assert!(users_merge(&mut db, source, target, now).await.unwrap().is_none());
+
+    let preferences = sqlx::query_as::<_, (String, String)>(
+        "SELECT key, value FROM user_preferences WHERE user_id IN (?, ?) ORDER BY key",
+    )
+    .bind(source)
+    .bind(target)
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    let expected = [("lang", "\"fr\""), ("theme", "\"light\"")].map(|(k, v)| (k.to_string(), v.to_string()));
+    assert_eq!(preferences, expected);
+    let role = sqlx::query_scalar::<_, String>("SELECT role FROM organization_members WHERE user_id = ?")
+        .bind(target)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(role, "owner");
+    let revoked = sqlx::query_scalar::<_, bool>(
+        "SELECT revoked_at IS NOT NULL FROM sessions WHERE id = 'source-session' AND user_id = ?",
+    )
+    .bind(target)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert!(revoked);
+}
+
+#[test]
+fn admin_users_lists_aggregates_by_page() {
+    let client = client_with_admin();
+    let heavy = seed_user(&client, "heavy_1@example.com");
+    let dormant = seed_user(&client, "heavyx1@example.com");
+    let pool = pool_cloned_get(&client);
+    block_on(async move {
+        for statement in [
+            format!(
+                "INSERT INTO posts (id, user_id, content, variant, updated_at) \
+                VALUES ('one', {heavy}, 'Hello', 'note', '2026-01-01T00:00:00Z')"
+            ),
+            format!(
+                "INSERT INTO posts (id, user_id, content, variant, updated_at) \
+                VALUES ('two', {heavy}, 'World!', 'note', '2026-02-01T00:00:00Z')"
+            ),
+            "INSERT INTO blobs (key, sha256, size, created_at) VALUES ('blob', 'sha', 100, '2026-01-01')".to_string(),
+            format!(
+                "INSERT INTO attachments (id, post_id, user_id, blob_key, filename, content_type, created_at) \
+                VALUES ('file', 'one', {heavy}, 'blob', 'a.png', 'image/png', '2026-01-01')"
+            ),
+        ] {
+            sqlx::query(&statement).execute(&pool).await.unwrap();
+        }
+    });
+
+    let response = client.get("/api/admin/users").header(admin_header()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(
+        (body["total"].as_i64(), body["hasMore"].as_bool()),
+        (Some(2), Some(false))
+    );
+    let items = body["items"].as_array().unwrap();
+    assert_eq!(items[0]["id"], heavy);
+    assert_eq!(items[0]["postCount"], 2);
+    assert_eq!(items[0]["storageBytes"], 5 + 6 + 100);
+    assert!(items[0]["lastActivityAt"].as_str().unwrap().starts_with("2026-02-01"));
+    assert_eq!(items[1]["id"], dormant);
+    assert_eq!(items[1]["postCount"], 0);
+    assert_eq!(items[1]["storageBytes"], 0);
+    assert_eq!(items[1]["lastActivityAt"], json::Value::Null);
+
+    // `_` matches itself only, not any character
+    let response = client.get("/api/admin/users?q=Y_1").header(admin_header()).dispatch();
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["items"][0]["id"], heavy);
+
+    let response = client.get("/api/admin/users?page=2").header(admin_header()).dispatch();
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["items"], json::json!([]));
+    let response = client.get("/api/admin/users?page=0").header(admin_header()).dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+}
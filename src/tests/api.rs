@@ -0,0 +1,89 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+use rocket::serde::json;
+
+use crate::api::*;
+use crate::metrics::metrics;
+
+#[test]
+fn api_v1_wraps_responses_in_envelope() {
+    let client = ClientAuthenticated::new();
+    let response = client.post_json(
+        "/api/v1/posts",
+        &json::json!({ "id": "envelope-a", "content": "hello", "variant": "note" }),
+    );
+    assert_eq!(response.status(), Status::Created);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body, json::json!({ "data": null, "error": null, "meta": null }));
+
+    let response = client.get("/api/v1/posts?limit=1");
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["data"][0]["id"], json::json!("envelope-a"));
+    assert_eq!(body["meta"], json::json!({ "pagination": { "hasMore": false } }));
+
+    let response = client.get("/api/v1/posts/missing");
+    assert_eq!(response.status(), Status::NotFound);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["data"], json::Value::Null);
+    assert_eq!(body["error"]["code"], json::json!("not_found"));
+    assert_eq!(body["error"]["message"], json::json!("Post not found"));
+}
+
+#[test]
+fn api_v1_guard_failures_use_envelope() {
+    let client = client_tracked_get();
+    let response = client.get("/api/v1/posts").dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["error"]["code"], json::json!("unauthorized"));
+    assert_eq!(body["error"]["message"], json::json!("Unauthorized"));
+}
+
+#[test]
+fn api_legacy_paths_are_deprecated_aliases() {
+    let client = ClientAuthenticated::new();
+    let response = client.get("/api/posts?limit=1");
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("Deprecation"), Some("true"));
+    assert_eq!(
+        response.headers().get_one("Link"),
+        Some("</api/v1/posts>; rel=\"successor-version\"")
+    );
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body, json::json!({ "items": [], "hasMore": false }));
+}
+
+#[get("/old")]
+fn old() -> json::Value {
+    json::json!({ "message": "success" })
+}
+
+#[test]
+fn api_deprecated_routes_announce_their_sunset() {
+    let deprecation = Deprecation {
+        sunset: Some("2027-01-01T00:00:00Z".parse().unwrap()),
+        successor: Some("/api/v1/new".into()),
+    };
+    let client =
+        client_tracked_build(|rocket| api_mount(rocket, "/test", deprecated_routes(routes![old], deprecation)));
+
+    // The route's own deprecation also applies to its unversioned alias
+    for uri in ["/api/v1/test/old", "/api/test/old"] {
+        let response = client.get(uri).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Deprecation"), Some("true"));
+        assert_eq!(
+            response.headers().get_one("Sunset"),
+            Some("Fri, 01 Jan 2027 00:00:00 GMT")
+        );
+        assert_eq!(
+            response.headers().get_one("Link"),
+            Some("</api/v1/new>; rel=\"successor-version\"")
+        );
+    }
+
+    let metrics = metrics().render();
+    assert!(metrics.contains("deprecated_requests_total{route=\"old\",api=\"v1\"}"));
+}
@@ -0,0 +1,34 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+
+use crate::assets::*;
+
+#[test]
+fn assets_resolve_files_and_fall_back_to_the_app() {
+    let files = ["index.html", "assets/app-1a2b3c.js", "favicon.ico"];
+    let exists = |path: &str| files.contains(&path);
+    assert_eq!(asset_resolve("", exists).as_deref(), Some("index.html"));
+    assert_eq!(asset_resolve("favicon.ico", exists).as_deref(), Some("favicon.ico"));
+    // Client-side routes get the app, missing files don't
+    assert_eq!(asset_resolve("notes/42", exists).as_deref(), Some("index.html"));
+    assert_eq!(asset_resolve("assets/app-old.js", exists), None);
+}
+
+#[test]
+fn assets_cache_hashed_files_forever() {
+    assert_eq!(
+        asset_cache_control("assets/app-1a2b3c.js"),
+        "public, max-age=31536000, immutable"
+    );
+    assert_eq!(asset_cache_control("index.html"), "no-cache");
+    assert_eq!(asset_cache_control("favicon.ico"), "public, max-age=3600");
+}
+
+#[test]
+fn assets_leave_api_paths_to_the_api() {
+    let client = ClientAuthenticated::new();
+    let response = client.get("/api/no-such-endpoint");
+    assert_eq!(response.status(), Status::NotFound);
+    assert_eq!(response.content_type(), Some(rocket::http::ContentType::JSON));
+}
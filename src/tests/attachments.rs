@@ -0,0 +1,113 @@
+use crate::tests::util::*;
+
+use rocket::http::{ContentType, Status};
+use rocket::local::blocking::{Client, LocalResponse};
+use rocket::serde::json;
+
+use crate::blobs::{BlobStore, blobs_gc};
+
+fn client_with_store() -> (Client, BlobStore) {
+    let dir = std::env::temp_dir().join(format!("rocket-sqlx-blobs-{}-{}", std::process::id(), next_sequence()));
+    let store = BlobStore::new(dir);
+    let client = client_tracked_build(|rocket| rocket.manage(store.clone()));
+    (client, store)
+}
+
+fn post_seed(client: &Client, user_id: i64, id: &str) {
+    let pool = pool_cloned_get(client);
+    let id = id.to_owned();
+    block_on(async move {
+        sqlx::query("INSERT INTO posts (id, user_id, content, variant) VALUES (?, ?, 'Note', 'note')")
+            .bind(id)
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .expect("insert post")
+    });
+}
+
+fn upload<'c>(client: &'c Client, user_id: i64, post_id: &str, filename: &str, bytes: &[u8]) -> LocalResponse<'c> {
+    let uri = format!("/api/posts/{}/attachments?filename={}", post_id, filename);
+    with_csrf(signed_in(client.post(uri), user_id))
+        .header(ContentType::PNG)
+        .body(bytes)
+        .dispatch()
+}
+
+fn blob_refs(client: &Client) -> Vec<(i64, bool)> {
+    let pool = pool_cloned_get(client);
+    block_on(async move {
+        sqlx::query_as::<_, (i64, bool)>("SELECT ref_count, unreferenced_at IS NOT NULL FROM blobs ORDER BY key")
+            .fetch_all(&pool)
+            .await
+            .expect("fetch blobs")
+    })
+}
+
+#[test]
+fn attachments_share_blobs_until_collected() {
+    let (client, store) = client_with_store();
+    let user_id = seed_user(&client, &email_for_session());
+    post_seed(&client, user_id, "first");
+    post_seed(&client, user_id, "second");
+
+    let response = upload(&client, user_id, "first", "photo.png", b"\x89PNG same bytes");
+    assert_eq!(response.status(), Status::Created);
+    let first = response.into_json::<json::Value>().unwrap();
+    assert_eq!(first["size"], json::json!(15));
+    let response = upload(&client, user_id, "second", "copy.png", b"\x89PNG same bytes");
+    assert_eq!(response.status(), Status::Created);
+    let second = response.into_json::<json::Value>().unwrap();
+    assert_eq!(blob_refs(&client), vec![(2, false)]);
+
+    let uri = format!("/api/posts/first/attachments/{}", first["id"].as_str().unwrap());
+    let response = signed_in(client.get(uri.as_str()), user_id).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::PNG));
+    assert_eq!(
+        response.headers().get_one("Content-Disposition"),
+        Some("attachment; filename=\"photo.png\"")
+    );
+    assert_eq!(response.into_bytes().unwrap(), b"\x89PNG same bytes");
+
+    let response = with_csrf(signed_in(client.delete(uri.as_str()), user_id)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    // Deleting the post drops its attachment too
+    let response = with_csrf(signed_in(client.delete("/api/posts/second"), user_id)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(blob_refs(&client), vec![(0, true)]);
+
+    let pool = pool_cloned_get(&client);
+    let collected = block_on(async move {
+        let mut db = pool.acquire().await.unwrap();
+        (
+            blobs_gc(&mut db, &store, 3600, 10).await.unwrap(),
+            blobs_gc(&mut db, &store, 0, 10).await.unwrap(),
+        )
+    });
+    assert_eq!(collected, (0, 1));
+    assert!(blob_refs(&client).is_empty());
+    let uri = format!("/api/posts/second/attachments/{}", second["id"].as_str().unwrap());
+    let response = signed_in(client.get(uri.as_str()), user_id).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn attachments_require_the_post_and_a_filename() {
+    let (client, _) = client_with_store();
+    let user_id = seed_user(&client, &email_for_session());
+    let other_id = seed_user(&client, &email_for_session());
+    post_seed(&client, other_id, "theirs");
+
+    assert_eq!(
+        upload(&client, user_id, "theirs", "a.png", b"bytes").status(),
+        Status::NotFound
+    );
+    post_seed(&client, user_id, "mine");
+    assert_eq!(
+        upload(&client, user_id, "mine", "..%2Fescape", b"bytes").status(),
+        Status::UnprocessableEntity
+    );
+    let response = signed_in(client.get("/api/posts/theirs/attachments"), user_id).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
@@ -0,0 +1,114 @@
+use crate::tests::util::*;
+
+use rocket::http::{Method, Status};
+use rocket::local::blocking::Client;
+use rocket::serde::json;
+
+use crate::authz::{API_TOKEN_PREFIX, scope_required, scopes_parse};
+
+const TOKENS_URI: &str = "/api/users/me/tokens";
+
+fn token_status(client: &Client, method: Method, uri: &str, token: &str) -> (Status, json::Value) {
+    let response = client
+        .req(method, uri)
+        .header(bearer(token))
+        .json(&json::json!({ "content": "from a script", "variant": "note", "name": "x", "scopes": "posts:write" }))
+        .dispatch();
+    (response.status(), response.into_json().unwrap_or_default())
+}
+
+#[test]
+fn authz_scopes_are_parsed_and_derived_from_routes() {
+    assert_eq!(
+        scopes_parse("posts:write posts:read posts:read"),
+        Some(vec!["posts:read".to_string(), "posts:write".to_string()])
+    );
+    assert_eq!(scopes_parse(""), None);
+    assert_eq!(scopes_parse("posts:read admin:read"), None);
+    assert_eq!(scopes_parse("posts:delete"), None);
+    assert_eq!(
+        scope_required(Method::Get, "/api/v1/posts/p-1"),
+        Some("posts:read".into())
+    );
+    assert_eq!(
+        scope_required(Method::Delete, "/api/posts/p-1"),
+        Some("posts:write".into())
+    );
+    assert_eq!(
+        scope_required(Method::Post, "/api/v1/posts/fetch-many"),
+        Some("posts:read".into())
+    );
+    assert_eq!(
+        scope_required(Method::Get, "/api/users/me"),
+        Some("account:read".into())
+    );
+    assert_eq!(
+        scope_required(Method::Get, "/api/session/"),
+        Some("account:read".into())
+    );
+    assert_eq!(scope_required(Method::Get, "/api/admin/users"), None);
+    assert_eq!(scope_required(Method::Get, "/oauth/authorize"), None);
+}
+
+#[test]
+fn authz_api_tokens_are_limited_to_their_scopes() {
+    let client = client_tracked_get();
+    let user_id = seed_user(&client, &email_for_session());
+    let session = |method: Method, uri: &str, body: json::Value| {
+        let request = signed_in(client.req(method, uri.to_owned()), user_id);
+        let response = with_csrf(request).json(&body).dispatch();
+        (
+            response.status(),
+            response.into_json::<json::Value>().unwrap_or_default(),
+        )
+    };
+
+    let (status, _) = session(
+        Method::Post,
+        TOKENS_URI,
+        json::json!({ "name": "dashboard", "scopes": "posts:delete" }),
+    );
+    assert_eq!(status, Status::UnprocessableEntity);
+    let (status, body) = session(
+        Method::Post,
+        TOKENS_URI,
+        json::json!({ "name": "dashboard", "scopes": "posts:read account:write", "expiresInDays": 30 }),
+    );
+    assert_eq!(status, Status::Created);
+    assert_eq!(body["scopes"], json::json!(["account:write", "posts:read"]));
+    let token = body["token"].as_str().unwrap().to_owned();
+    let token_id = body["id"].as_str().unwrap().to_owned();
+    assert!(token.starts_with(API_TOKEN_PREFIX));
+
+    assert_eq!(token_status(&client, Method::Get, "/api/posts", &token).0, Status::Ok);
+    // Fetching by IDs is a read, whatever its method
+    let response = client
+        .post("/api/posts/fetch-many")
+        .header(bearer(&token))
+        .json(&json::json!([{ "id": "missing" }]))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let (status, body) = token_status(&client, Method::Post, "/api/posts", &token);
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(body["details"]["scope"], "posts:write");
+    // Tokens can't mint tokens, whatever their scopes
+    assert_eq!(
+        token_status(&client, Method::Post, TOKENS_URI, &token).0,
+        Status::Forbidden
+    );
+    assert_eq!(
+        token_status(&client, Method::Get, "/api/posts", "pat_unknown").0,
+        Status::Unauthorized
+    );
+
+    let (status, body) = session(Method::Get, TOKENS_URI, json::json!({}));
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["items"].as_array().unwrap().len(), 1);
+    assert!(body["items"][0].get("token").is_none());
+    let uri = format!("{}/{}", TOKENS_URI, token_id);
+    assert_eq!(session(Method::Delete, &uri, json::json!({})).0, Status::Ok);
+    assert_eq!(
+        token_status(&client, Method::Get, "/api/posts", &token).0,
+        Status::Unauthorized
+    );
+}
@@ -0,0 +1,97 @@
+use crate::tests::util::*;
+
+use std::time::Duration;
+
+use chrono::Utc;
+use rocket::http::Status;
+use rocket::serde::json;
+
+use crate::cache::*;
+use crate::handlers::admin::users_merge;
+use crate::metrics::metrics;
+
+#[rocket::async_test]
+async fn cache_invalidates_only_the_writing_user() {
+    let cache = ResponseCache::new(&CacheConfig {
+        ttl: Duration::from_secs(60),
+        max_entries: 100,
+    });
+    cache.insert(1, "read/a".into(), json::json!({ "id": "a" })).await;
+    cache.insert(2, "read/a".into(), json::json!({ "id": "b" })).await;
+    assert_eq!(cache.get(1, "read", "read/a").await, Some(json::json!({ "id": "a" })));
+
+    cache.invalidate_user(1);
+    assert_eq!(cache.get(1, "read", "read/a").await, None);
+    assert_eq!(cache.get(2, "read", "read/a").await, Some(json::json!({ "id": "b" })));
+}
+
+#[rocket::async_test]
+async fn cache_disabled_by_zero_ttl() {
+    let cache = ResponseCache::new(&CacheConfig {
+        ttl: Duration::ZERO,
+        max_entries: 100,
+    });
+    cache.insert(1, "read/a".into(), json::json!({})).await;
+    assert_eq!(cache.get(1, "read", "read/a").await, None);
+}
+
+#[rocket::async_test]
+async fn cache_serves_repeated_reads_and_drops_them_on_write() {
+    let app = TestApp::new().with_posts(1).start().await;
+    let read = || async {
+        let response = app.get("/api/posts/post-0").await;
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json::<json::Value>().await.unwrap()["content"].clone()
+    };
+    assert_eq!(read().await, "Post 0");
+    assert_eq!(read().await, "Post 0");
+    let metrics = metrics().render();
+    assert!(metrics.contains("response_cache_hits_total{endpoint=\"read\"}"));
+
+    let update = json::json!({ "content": "After", "updatedAt": Utc::now().to_rfc3339() });
+    assert_eq!(app.put_json("/api/posts/post-0", &update).await.status(), Status::Ok);
+    assert_eq!(read().await, "After");
+    let listed = app.get("/api/posts").await.into_json::<json::Value>().await.unwrap();
+    assert_eq!(listed["items"][0]["content"], "After");
+}
+
+#[rocket::async_test]
+async fn cache_keys_lists_by_their_parsed_parameters() {
+    let app = TestApp::new().with_posts(1).start().await;
+    let count = |uri: &'static str| async {
+        let listed = app.get(uri).await.into_json::<json::Value>().await.unwrap();
+        listed["items"].as_array().unwrap().len()
+    };
+    assert_eq!(count("/api/posts?limit=5&content=false").await, 1);
+
+    // Written behind the cache's back, so only a differently keyed list sees it
+    let statement = format!(
+        "INSERT INTO posts (id, user_id, content, variant) VALUES ('unseen', {}, 'Hi', 'note')",
+        app.user_id()
+    );
+    sqlx::query(&statement).execute(&app.pool()).await.unwrap();
+    assert_eq!(count("/api/posts?content=false&limit=%35").await, 1);
+    assert_eq!(count("/api/posts?content=false&limit=6").await, 2);
+}
+
+#[rocket::async_test]
+async fn cache_drops_the_target_list_on_merge() {
+    let app = TestApp::new().with_posts(1).start().await;
+    let count = || async {
+        let listed = app.get("/api/posts").await.into_json::<json::Value>().await.unwrap();
+        listed["items"].as_array().unwrap().len()
+    };
+    assert_eq!(count().await, 1);
+
+    let pool = app.pool();
+    let source = app.user_seed(&email_for_session()).await;
+    let statement =
+        format!("INSERT INTO posts (id, user_id, content, variant) VALUES ('source-post', {source}, 'Hello', 'note')");
+    sqlx::query(&statement).execute(&pool).await.unwrap();
+    let mut db = pool.acquire().await.unwrap();
+    users_merge(&mut db, source, app.user_id(), Utc::now())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(count().await, 2);
+}
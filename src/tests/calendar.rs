@@ -0,0 +1,78 @@
+use crate::tests::util::*;
+
+use rocket::http::{ContentType, Status};
+use rocket::serde::json;
+
+use crate::db::Post;
+use crate::handlers::calendar::*;
+
+#[test]
+fn calendar_post_date_prefers_the_reminder() {
+    let post = |content: &str, remind_at: Option<&str>| -> Post {
+        json::from_value(json::json!({
+            "id": "post-0",
+            "parentId": null,
+            "content": content,
+            "createdAt": "2024-01-31T08:00:00Z",
+            "updatedAt": "2024-01-31T08:00:00Z",
+            "variant": "note",
+            "position": null,
+            "remindAt": remind_at,
+        }))
+        .unwrap()
+    };
+    let cases = [
+        (
+            post("Dentist 2024-03-05", None),
+            Some(PostDate::Day("2024-03-05".parse().unwrap())),
+        ),
+        (
+            post("Dentist 2024-03-05 14:30, then 2024-03-06", None),
+            Some(PostDate::At("2024-03-05T14:30:00Z".parse().unwrap())),
+        ),
+        (
+            post("Dentist 2024-03-05", Some("2024-03-04T09:00:00Z")),
+            Some(PostDate::At("2024-03-04T09:00:00Z".parse().unwrap())),
+        ),
+        (post("Not a date 2024-13-45", None), None),
+        (post("No date", None), None),
+    ];
+    for (post, date) in cases {
+        assert_eq!(post_date(&post), date, "{}", post.content);
+    }
+}
+
+#[rocket::async_test]
+async fn calendar_feed_lists_dated_posts_by_token() {
+    let app = TestApp::new().with_user().start().await;
+
+    for (id, content) in [
+        ("dated", "Dentist, 2024-03-05\nBring the x-rays"),
+        ("undated", "Groceries"),
+    ] {
+        let post = json::json!({ "id": id, "content": content, "variant": "note" });
+        assert_eq!(app.post_json("/api/posts", &post).await.status(), Status::Created);
+    }
+
+    let response = app.post_json("/api/users/me/calendar-feed", &json::json!({})).await;
+    assert_eq!(response.status(), Status::Created);
+    let body = response.into_json::<json::Value>().await.unwrap();
+    let token = body["token"].as_str().unwrap().to_owned();
+    assert!(body["url"].as_str().unwrap().ends_with(&token));
+
+    let uri = format!("/api/posts/calendar.ics?token={}", token);
+    let response = app.get(&uri).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::Calendar));
+    let ics = response.into_string().await.unwrap();
+    assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+    assert!(ics.contains("UID:dated@rocket-sqlx\r\n"));
+    assert!(ics.contains("DTSTART;VALUE=DATE:20240305\r\n"));
+    assert!(ics.contains("SUMMARY:Dentist\\, 2024-03-05\r\n"));
+    assert!(!ics.contains("undated"));
+
+    let wrong = app.get("/api/posts/calendar.ics?token=wrong").await;
+    assert_eq!(wrong.status(), Status::NotFound);
+    assert_eq!(app.delete("/api/users/me/calendar-feed").await.status(), Status::Ok);
+    assert_eq!(app.get(&uri).await.status(), Status::NotFound);
+}
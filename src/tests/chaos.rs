@@ -0,0 +1,141 @@
+use crate::tests::util::*;
+
+use std::sync::Arc;
+
+use crate::chaos::{Fault, FaultConfig, FaultInjector, faults_scoped};
+use crate::handlers::posts::{ChildrenOnDelete, PostWriteError, UpsertPostPayload, post_delete, posts_upsert_many};
+
+fn injector(config: FaultConfig) -> Arc<FaultInjector> {
+    Arc::new(FaultInjector::new(config))
+}
+
+fn post(id: &str, now: DateTime<Utc>) -> UpsertPostPayload {
+    UpsertPostPayload {
+        id: id.into(),
+        parent_id: None,
+        created_at: now,
+        content: format!("content of {}", id),
+        updated_at: now,
+        version: None,
+        variant: "note".into(),
+        position: None,
+        org_id: None,
+        remind_at: None,
+    }
+}
+
+async fn post_count(pool: &sqlx::SqlitePool, id: &str) -> (i64, i64) {
+    let posts = sqlx::query_scalar("SELECT COUNT(*) FROM posts WHERE id = ?")
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    let events = sqlx::query_scalar("SELECT COUNT(*) FROM events WHERE post_id = ?")
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    (posts, events)
+}
+
+#[test]
+fn chaos_faults_drawn_per_config() {
+    assert_eq!(FaultInjector::default().roll(), None);
+    let busy = FaultInjector::new(FaultConfig {
+        busy_rate: 1.0,
+        ..FaultConfig::default()
+    });
+    assert_eq!(busy.roll(), Some(Fault::Busy));
+    let dropped = FaultInjector::new(FaultConfig {
+        drop_rate: 1.0,
+        ..FaultConfig::default()
+    });
+    assert_eq!(dropped.roll(), Some(Fault::Dropped));
+
+    let error = Fault::Busy.error();
+    let database = error.as_database_error().expect("a database error");
+    assert_eq!(database.code().as_deref(), Some("5"));
+    assert!(matches!(Fault::Timeout.error(), sqlx::Error::PoolTimedOut));
+}
+
+#[rocket::async_test]
+async fn chaos_failed_upsert_writes_nothing() {
+    let app = TestApp::new().with_user().start().await;
+    let pool = app.pool();
+    let now = Utc::now();
+
+    for fault in [
+        FaultConfig {
+            busy_rate: 1.0,
+            ..FaultConfig::default()
+        },
+        FaultConfig {
+            timeout_rate: 1.0,
+            ..FaultConfig::default()
+        },
+    ] {
+        let mut conn = pool.acquire().await.unwrap();
+        let posts = [post("chaos-1", now)];
+        let result = faults_scoped(
+            injector(fault),
+            posts_upsert_many(&mut conn, app.user_id(), &posts, now),
+        )
+        .await;
+        assert!(matches!(result, Err(PostWriteError::Db(_))));
+        assert_eq!(post_count(&pool, "chaos-1").await, (0, 0));
+    }
+
+    // The connection is still usable once the faults stop
+    let mut conn = pool.acquire().await.unwrap();
+    posts_upsert_many(&mut conn, app.user_id(), &[post("chaos-1", now)], now)
+        .await
+        .unwrap();
+    assert_eq!(post_count(&pool, "chaos-1").await, (1, 1));
+}
+
+#[rocket::async_test]
+async fn chaos_writes_are_all_or_nothing_under_random_faults() {
+    let app = TestApp::new().with_user().start().await;
+    let pool = app.pool();
+    let now = Utc::now();
+    let faults = injector(FaultConfig {
+        busy_rate: 0.2,
+        timeout_rate: 0.1,
+        drop_rate: 0.1,
+    });
+
+    for i in 0..40 {
+        let id = format!("chaos-random-{}", i);
+        let mut conn = pool.acquire().await.unwrap();
+        let posts = [post(&id, now)];
+        let result = faults_scoped(faults.clone(), posts_upsert_many(&mut conn, app.user_id(), &posts, now)).await;
+        let expected = if result.is_ok() { (1, 1) } else { (0, 0) };
+        assert_eq!(post_count(&pool, &id).await, expected, "post {}", id);
+    }
+}
+
+#[rocket::async_test]
+async fn chaos_failed_delete_keeps_the_post_and_its_children() {
+    let app = TestApp::new().with_user().start().await;
+    let pool = app.pool();
+    let now = Utc::now();
+    let mut child = post("chaos-child", now);
+    child.parent_id = Some("chaos-parent".into());
+    let mut conn = pool.acquire().await.unwrap();
+    posts_upsert_many(&mut conn, app.user_id(), &[post("chaos-parent", now), child], now)
+        .await
+        .unwrap();
+
+    let faults = injector(FaultConfig {
+        drop_rate: 1.0,
+        ..FaultConfig::default()
+    });
+    let result = faults_scoped(
+        faults,
+        post_delete(&mut conn, app.user_id(), "chaos-parent", ChildrenOnDelete::Cascade, now),
+    )
+    .await;
+    assert!(result.is_err());
+    assert_eq!(post_count(&pool, "chaos-parent").await.0, 1);
+    assert_eq!(post_count(&pool, "chaos-child").await.0, 1);
+}
@@ -0,0 +1,103 @@
+use crate::tests::util::*;
+
+use rocket::http::Header;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::client_info::*;
+
+#[test]
+fn client_info_parses_common_user_agents() {
+    let chrome_mac = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) \
+        Chrome/126.0.0.0 Safari/537.36";
+    assert_eq!(
+        user_agent_parse(chrome_mac),
+        UserAgentInfo {
+            browser: Some("Chrome 126".into()),
+            os: Some("macOS".into()),
+            device: "desktop",
+        }
+    );
+
+    let safari_iphone = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 \
+        (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1";
+    assert_eq!(
+        user_agent_parse(safari_iphone),
+        UserAgentInfo {
+            browser: Some("Safari 17".into()),
+            os: Some("iOS".into()),
+            device: "mobile",
+        }
+    );
+
+    let edge_windows = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) \
+        Chrome/126.0.0.0 Safari/537.36 Edg/126.0.2592.87";
+    assert_eq!(user_agent_parse(edge_windows).browser.as_deref(), Some("Edge 126"));
+
+    let bot = "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)";
+    assert_eq!(user_agent_parse(bot).device, "bot");
+
+    assert_eq!(
+        user_agent_parse("curl/8.4.0"),
+        UserAgentInfo {
+            browser: None,
+            os: None,
+            device: "desktop",
+        }
+    );
+}
+
+#[test]
+fn client_info_forwarded_chains_parse() {
+    let ip = |text: &str| Some(text.parse::<IpAddr>().unwrap());
+    assert_eq!(
+        forwarded_for(
+            Some("for=192.0.2.60;proto=http, for=\"[2001:db8:cafe::17]:4711\""),
+            None
+        ),
+        [ip("192.0.2.60"), ip("2001:db8:cafe::17")]
+    );
+    assert_eq!(
+        forwarded_for(None, Some("203.0.113.9, unknown, 10.0.0.2:8080")),
+        [ip("203.0.113.9"), None, ip("10.0.0.2")]
+    );
+    assert_eq!(forwarded_for(None, None), []);
+}
+
+#[test]
+fn client_info_client_ip_skips_trusted_proxies_only() {
+    let ip = |text: &str| text.parse::<IpAddr>().unwrap();
+    let proxies = TrustedProxies(vec!["10.0.0.0/8".parse().unwrap()]);
+    let chain = [Some(ip("198.51.100.7")), Some(ip("203.0.113.9")), Some(ip("10.0.0.2"))];
+    // 203.0.113.9 is not a proxy of ours, so whatever it claims is ignored
+    assert_eq!(client_ip_resolve(ip("10.0.0.1"), &chain, &proxies), ip("203.0.113.9"));
+    // Headers from clients connecting directly are ignored
+    assert_eq!(
+        client_ip_resolve(ip("203.0.113.9"), &chain, &proxies),
+        ip("203.0.113.9")
+    );
+    assert_eq!(
+        client_ip_resolve(ip("::ffff:10.0.0.1"), &[None], &proxies),
+        ip("10.0.0.1")
+    );
+}
+
+#[get("/ip")]
+fn ip(client_ip: ClientIp) -> String {
+    client_ip.0.map(|ip| ip.to_string()).unwrap_or_default()
+}
+
+#[test]
+fn client_info_client_ip_guard_believes_trusted_proxies() {
+    let proxies = TrustedProxies(vec!["10.0.0.0/8".parse().unwrap()]);
+    let client = client_tracked_build(|rocket| rocket.manage(proxies).mount("/test", routes![ip]));
+    let request = |peer: &str| {
+        client
+            .get("/test/ip")
+            .remote(peer.parse::<SocketAddr>().unwrap())
+            .header(Header::new("X-Forwarded-For", "203.0.113.9"))
+            .dispatch()
+            .into_string()
+    };
+    assert_eq!(request("10.0.0.1:5000").as_deref(), Some("203.0.113.9"));
+    assert_eq!(request("198.51.100.7:5000").as_deref(), Some("198.51.100.7"));
+}
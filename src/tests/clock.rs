@@ -0,0 +1,114 @@
+use crate::tests::util::*;
+
+use chrono::{DateTime, TimeDelta, Timelike, Utc};
+use rocket::http::Status;
+use rocket::serde::json;
+use std::sync::Arc;
+
+use crate::clock::*;
+
+#[test]
+fn clock_time_endpoint_returns_server_time() {
+    let client = ClientAuthenticated::new();
+    let response = client.get("/api/v1/time");
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().expect("time response");
+    let now_ms = body["data"]["nowMs"].as_i64().expect("nowMs");
+    assert!((Utc::now().timestamp_millis() - now_ms).abs() < 5_000);
+}
+
+#[test]
+fn clock_rejects_timestamps_from_the_future() {
+    let client = ClientAuthenticated::new();
+    let ahead = Utc::now() + TimeDelta::days(365);
+    let payload = json::json!({ "content": "From the future", "updatedAt": ahead, "variant": "note" });
+    let response = client.post_json("/api/posts", &payload);
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+
+    let slightly_ahead = Utc::now() + TimeDelta::seconds(10);
+    let payload = json::json!({ "content": "Fast clock", "updatedAt": slightly_ahead, "variant": "note" });
+    assert_success(client.post_json("/api/posts", &payload), Status::Created);
+}
+
+#[test]
+fn clock_clamps_when_configured() {
+    let config = ClockConfig {
+        max_skew: TimeDelta::seconds(60),
+        policy: SkewPolicy::Clamp,
+        precision: TimestampPrecision::Seconds,
+    };
+    let now = Utc::now().with_nanosecond(0).unwrap();
+    assert_eq!(
+        config.accept(now + TimeDelta::seconds(30), now),
+        Ok(now + TimeDelta::seconds(30))
+    );
+    assert_eq!(config.accept(now + TimeDelta::days(1), now), Ok(now));
+}
+
+#[test]
+fn clock_truncates_to_the_configured_precision() {
+    let at = DateTime::parse_from_rfc3339("2024-01-31T12:00:00.123456789Z")
+        .unwrap()
+        .to_utc();
+    let truncated = |precision: TimestampPrecision| precision.truncate(at).to_rfc3339();
+    assert_eq!(truncated(TimestampPrecision::Seconds), "2024-01-31T12:00:00+00:00");
+    assert_eq!(truncated(TimestampPrecision::Millis), "2024-01-31T12:00:00.123+00:00");
+    assert_eq!(
+        truncated(TimestampPrecision::Micros),
+        "2024-01-31T12:00:00.123456+00:00"
+    );
+}
+
+#[test]
+fn clock_orders_edits_within_the_same_second() {
+    let client = ClientAuthenticated::new();
+    let second = Utc::now().with_nanosecond(0).unwrap() - TimeDelta::minutes(1);
+    let first = second + TimeDelta::milliseconds(100);
+    let later = second + TimeDelta::milliseconds(400);
+    let payload = json::json!({ "id": "ms-post", "content": "First", "updatedAt": first, "variant": "note" });
+    assert_success(client.post_json("/api/posts", &payload), Status::Created);
+
+    let payload = json::json!({ "content": "Later", "updatedAt": later });
+    assert_success(client.put_json("/api/posts/ms-post", &payload), Status::Ok);
+    // Stale within the same second, so it loses
+    let payload = json::json!({ "content": "Stale", "updatedAt": first + TimeDelta::milliseconds(1) });
+    assert_eq!(
+        client.put_json("/api/posts/ms-post", &payload).status(),
+        Status::NotFound
+    );
+
+    let response = client.get("/api/posts/ms-post");
+    let body = response.into_json::<json::Value>().expect("post response");
+    assert_eq!(body["content"], "Later");
+    assert_eq!(
+        json::from_value::<DateTime<Utc>>(body["updatedAt"].clone()).unwrap(),
+        later
+    );
+}
+
+#[test]
+fn clock_handlers_take_time_and_ids_from_managed_state() {
+    let start = DateTime::parse_from_rfc3339("2030-01-01T00:00:00Z").unwrap().to_utc();
+    let clock = Arc::new(TestClock::new(start));
+    let client = client_tracked_build(|rocket| rocket.manage(Timekeeper(clock.clone())));
+    let user_id = seed_user(&client, &email_for_session());
+
+    let request = signed_in(client.post("/api/orgs"), user_id);
+    let response = with_csrf(request).json(&json::json!({ "name": "Team" })).dispatch();
+    assert_eq!(response.status(), Status::Created);
+    let org = response.into_json::<json::Value>().unwrap();
+    assert_eq!(org["id"], "id-1");
+    // Each read moves the clock a millisecond, and background tasks read it too
+    let created_at = json::from_value::<DateTime<Utc>>(org["createdAt"].clone()).unwrap();
+    assert!((start..start + TimeDelta::seconds(1)).contains(&created_at));
+
+    clock.advance(TimeDelta::days(1));
+    let body = client
+        .get("/api/v1/time")
+        .dispatch()
+        .into_json::<json::Value>()
+        .unwrap();
+    let now_ms = body["data"]["nowMs"].as_i64().unwrap();
+    let later = start + TimeDelta::days(1);
+    assert!((later.timestamp_millis()..(later + TimeDelta::seconds(1)).timestamp_millis()).contains(&now_ms));
+}
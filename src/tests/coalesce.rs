@@ -0,0 +1,92 @@
+use crate::tests::util::*;
+
+use rocket::http::{ContentType, Status};
+use rocket::local::blocking::Client;
+use rocket::serde::json;
+use rocket::tokio::time::Duration;
+
+use crate::clock::Clock;
+use crate::coalesce::UpdateCoalescer;
+
+/// Returns a client whose coalescer only writes when flushed, and a user owning the post `typed`.
+fn client_coalescing() -> (Client, UpdateCoalescer, i64) {
+    let coalescer = UpdateCoalescer::new(Some(Duration::from_secs(3600)));
+    let client = client_tracked_build(|rocket| rocket.manage(coalescer.clone()));
+    let user_id = seed_user(&client, &email_for_session());
+    let pool = pool_cloned_get(&client);
+    block_on(async move {
+        sqlx::query("INSERT INTO posts (id, user_id, content, variant) VALUES ('typed', ?, 'H', 'note')")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .expect("insert post")
+    });
+    (client, coalescer, user_id)
+}
+
+/// Sends an update of `id` stamped `seconds_ago` before the server time.
+fn update(client: &Client, user_id: i64, id: &str, content: &str, seconds_ago: i64) -> Status {
+    let updated_at = test_clock(client.rocket()).now() - chrono::Duration::seconds(seconds_ago);
+    let body = json::json!({ "content": content, "updatedAt": updated_at.to_rfc3339() });
+    let request = signed_in(client.put(format!("/api/posts/{}", id)), user_id);
+    with_csrf(request)
+        .header(ContentType::JSON)
+        .body(body.to_string())
+        .dispatch()
+        .status()
+}
+
+fn content(client: &Client, user_id: i64) -> String {
+    let response = signed_in(client.get("/api/posts/typed"), user_id).dispatch();
+    let body: json::Value = response.into_json().expect("post");
+    body["content"].as_str().expect("content").to_owned()
+}
+
+fn flush(client: &Client, coalescer: &UpdateCoalescer) -> usize {
+    let pool = pool_cloned_get(client);
+    let coalescer = coalescer.clone();
+    let now = test_clock(client.rocket()).now();
+    block_on(async move {
+        let mut db = pool.acquire().await.unwrap();
+        coalescer.flush(&mut db, now).await.expect("flush")
+    })
+}
+
+fn events(client: &Client) -> i64 {
+    let pool = pool_cloned_get(client);
+    block_on(async move {
+        sqlx::query_scalar("SELECT COUNT(*) FROM events WHERE post_id = 'typed'")
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+    })
+}
+
+#[test]
+fn coalesce_writes_only_the_newest_update() {
+    let (client, coalescer, user_id) = client_coalescing();
+    assert_eq!(update(&client, user_id, "typed", "He", 30), Status::Ok);
+    assert_eq!(update(&client, user_id, "typed", "Hel", 20), Status::Ok);
+    assert_eq!(update(&client, user_id, "typed", "Hello", 10), Status::Ok);
+    assert_eq!(coalescer.pending_len(), 1);
+    assert_eq!(content(&client, user_id), "H");
+
+    assert_eq!(flush(&client, &coalescer), 1);
+    assert_eq!(content(&client, user_id), "Hello");
+    assert_eq!(events(&client), 1);
+    assert_eq!(flush(&client, &coalescer), 0);
+}
+
+#[test]
+fn coalesce_refuses_outdated_updates() {
+    let (client, coalescer, user_id) = client_coalescing();
+    assert_eq!(update(&client, user_id, "typed", "Hello", 10), Status::Ok);
+    // Older than the queued update
+    assert_eq!(update(&client, user_id, "typed", "Hel", 20), Status::NotFound);
+    assert_eq!(flush(&client, &coalescer), 1);
+    // Older than the stored version
+    assert_eq!(update(&client, user_id, "typed", "He", 30), Status::NotFound);
+    assert_eq!(update(&client, user_id, "missing", "Hi", 5), Status::NotFound);
+    assert_eq!(coalescer.pending_len(), 0);
+    assert_eq!(content(&client, user_id), "Hello");
+}
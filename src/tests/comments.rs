@@ -0,0 +1,85 @@
+use crate::tests::util::*;
+
+use chrono::Duration;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, json};
+
+use crate::db;
+
+const POSTS_BASE: &str = "/api/posts";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "rocket::serde")]
+struct CommentListResponse {
+    items: Vec<db::Comment>,
+    has_more: bool,
+}
+
+fn post_seed(client: &ClientAuthenticated, id: &str) -> String {
+    let payload = json::json!({ "id": id, "content": "Commented post", "variant": "note" });
+    assert_success(client.post_json(POSTS_BASE, &payload), Status::Created);
+    format!("{}/{}/comments", POSTS_BASE, id)
+}
+
+fn fetch_comments(client: &ClientAuthenticated, uri: &str) -> CommentListResponse {
+    let response = client.get(uri);
+    assert_eq!(response.status(), Status::Ok);
+    response.into_json::<CommentListResponse>().expect("comments response")
+}
+
+#[test]
+fn comments_crud_and_threads() {
+    let client = ClientAuthenticated::new();
+    let base = post_seed(&client, "commented");
+    let now = client.now();
+
+    let root = json::json!({ "id": "c-root", "content": "Root", "createdAt": now, "updatedAt": now });
+    assert_success(client.post_json(&base, &root), Status::Created);
+    let reply = json::json!({ "id": "c-reply", "parentId": "c-root", "content": "Reply" });
+    assert_success(client.post_json(&base, &reply), Status::Created);
+
+    let orphan = json::json!({ "parentId": "missing", "content": "Orphan" });
+    // Replies must point to a comment on the same post
+    assert_eq!(client.post_json(&base, &orphan).status(), Status::UnprocessableEntity);
+
+    let list = fetch_comments(&client, &base);
+    assert_eq!(list.items.len(), 2);
+    assert!(!list.has_more);
+    let paged = fetch_comments(&client, &format!("{}?limit=1", base));
+    assert_eq!(paged.items.len(), 1);
+    assert!(paged.has_more);
+
+    let root_uri = format!("{}/c-root", base);
+    let stale = json::json!({ "content": "Stale", "updatedAt": now - Duration::seconds(30) });
+    // Last write wins: older updates are rejected
+    assert_eq!(client.put_json(&root_uri, &stale).status(), Status::NotFound);
+    let newer = json::json!({ "content": "Edited", "updatedAt": now + Duration::seconds(30) });
+    assert_success(client.put_json(&root_uri, &newer), Status::Ok);
+
+    let response = client.get(&root_uri);
+    assert_eq!(response.status(), Status::Ok);
+    let edited = response.into_json::<db::Comment>().expect("comment response");
+    assert_eq!(edited.content, "Edited");
+    assert_eq!(edited.post_id, "commented");
+
+    // Deleting a comment deletes its replies
+    assert_success(client.delete(&root_uri), Status::Ok);
+    assert!(fetch_comments(&client, &base).items.is_empty());
+}
+
+#[test]
+fn comments_are_scoped_to_the_post_owner() {
+    let owner = ClientAuthenticated::new();
+    let base = post_seed(&owner, "owned-commented");
+    let comment = json::json!({ "id": "c-owned", "content": "Private" });
+    assert_success(owner.post_json(&base, &comment), Status::Created);
+
+    let other = ClientAuthenticated::new();
+    assert_eq!(other.get(&base).status(), Status::NotFound);
+    assert_eq!(other.post_json(&base, &comment).status(), Status::NotFound);
+    assert_eq!(other.get(&format!("{}/c-owned", base)).status(), Status::NotFound);
+
+    // Deleting the post deletes its comments
+    assert_success(owner.delete(&format!("{}/owned-commented", POSTS_BASE)), Status::Ok);
+    assert_eq!(owner.get(&format!("{}/c-owned", base)).status(), Status::NotFound);
+}
@@ -0,0 +1,88 @@
+use crate::tests::util::*;
+
+use crate::config::{
+    AppConfig, AuthConfig, ConfigReport, EnvVars, config_lookup, rocket_databases_check, secret_key_check,
+};
+use crate::handlers::posts::ForeignPostPolicy;
+
+#[test]
+fn config_test_environment_is_valid() {
+    let _ = client_tracked_get(); // sets the environment of the tests
+    assert!(EnvVars::from_env().is_ok());
+}
+
+#[test]
+fn config_database_urls_must_be_sqlite() {
+    assert_eq!(rocket_databases_check("{sqlx={url=\"db.sqlite\"}}"), Ok(()));
+    assert_eq!(rocket_databases_check("{sqlx={url=\"sqlite::memory:\"}}"), Ok(()));
+    assert_eq!(
+        rocket_databases_check("{sqlx={url=\"sqlite://data/db.sqlite\"}}"),
+        Ok(())
+    );
+    assert!(rocket_databases_check("db.sqlite").is_err());
+    assert!(rocket_databases_check("{sqlx={max_connections=4}}").is_err());
+    let postgres = rocket_databases_check("{sqlx={url=\"postgres://localhost/app\"}}").unwrap_err();
+    assert!(postgres.contains("postgres"));
+}
+
+#[test]
+fn config_secret_keys_must_be_32_or_64_bytes_of_base64() {
+    assert_eq!(secret_key_check("5Z4RZccfO6oVLQj86VXLxCaX/xyGq5wixH4hWsLve0s="), Ok(()));
+    assert!(secret_key_check("c2hvcnQ=").unwrap_err().contains("found 5"));
+    assert!(secret_key_check("not base64!").is_err());
+}
+
+#[test]
+fn config_report_lists_every_problem() {
+    let mut report = ConfigReport::default();
+    report.problem("DATABASE_URL", "must be set");
+    report.problem("ROCKET_SECRET_KEY", "must be base64");
+    assert_eq!(
+        report.to_string(),
+        "Invalid configuration (2 problems):\n  DATABASE_URL: must be set\n  ROCKET_SECRET_KEY: must be base64\n\
+        See .env.example for every setting."
+    );
+}
+
+#[test]
+fn config_environment_overrides_the_config_file() {
+    assert_eq!(config_var("CONFIG_TEST_UNSET"), None);
+    unsafe { std::env::set_var("CONFIG_TEST_SETTING", "42") };
+    assert_eq!(config_var("CONFIG_TEST_SETTING").as_deref(), Some("42"));
+    assert_eq!(env_parse_or("CONFIG_TEST_SETTING", 0), 42);
+}
+
+#[test]
+fn config_settings_read_from_files() {
+    let path = std::env::temp_dir().join(format!("rocket-sqlx-secret-{}-{}", std::process::id(), next_sequence()));
+    std::fs::write(&path, "s3cr3t\n").unwrap();
+    unsafe { std::env::set_var("CONFIG_TEST_SECRET_FILE", &path) };
+    assert_eq!(config_var("CONFIG_TEST_SECRET").as_deref(), Some("s3cr3t"));
+
+    // The variable itself wins over its file
+    unsafe { std::env::set_var("CONFIG_TEST_SECRET", "plain") };
+    assert_eq!(config_var("CONFIG_TEST_SECRET").as_deref(), Some("plain"));
+
+    std::fs::remove_file(&path).unwrap();
+    unsafe { std::env::set_var("CONFIG_TEST_MISSING_FILE", &path) };
+    let error = config_lookup("CONFIG_TEST_MISSING").unwrap_err();
+    assert!(error.starts_with("CONFIG_TEST_MISSING_FILE: failed to read"));
+}
+
+#[test]
+fn config_managed_app_config_drives_the_stages() {
+    let base = AppConfig::load();
+    let config = AppConfig {
+        auth: AuthConfig {
+            admin_token: Some("from-config".into()),
+            post_access_policy: ForeignPostPolicy::Forbid,
+            ..base.auth.clone()
+        },
+        ..base
+    };
+    let client = client_tracked_build(|rocket| rocket.manage(config));
+    let rocket = client.rocket();
+    assert_eq!(rocket.state::<ForeignPostPolicy>(), Some(&ForeignPostPolicy::Forbid));
+    let token = rocket.state::<crate::handlers::admin::AdminToken>().unwrap();
+    assert_eq!(token.0.as_deref(), Some("from-config"));
+}
@@ -0,0 +1,52 @@
+use crate::tests::util::*;
+
+use crate::cron::*;
+
+fn at(text: &str) -> DateTime<Utc> {
+    text.parse().unwrap()
+}
+
+#[test]
+fn cron_next_after_finds_the_next_match() {
+    let cases = [
+        ("* * * * *", "2024-01-31T08:00:30Z", "2024-01-31T08:01:00Z"),
+        ("@daily", "2024-01-31T08:00:00Z", "2024-02-01T00:00:00Z"),
+        ("30 7 * * 1-5", "2024-02-02T08:00:00Z", "2024-02-05T07:30:00Z"),
+        ("*/15 9-17/4 * * *", "2024-01-31T13:50:00Z", "2024-01-31T17:00:00Z"),
+        ("0 0 29 2 *", "2024-03-01T00:00:00Z", "2028-02-29T00:00:00Z"),
+        ("0 12 * * 7", "2024-01-31T00:00:00Z", "2024-02-04T12:00:00Z"),
+        // Both days restricted: either matches
+        ("0 0 15 * 1", "2024-01-31T00:00:00Z", "2024-02-05T00:00:00Z"),
+        ("@yearly", "2024-12-31T23:59:59Z", "2025-01-01T00:00:00Z"),
+    ];
+    for (expression, after, next) in cases {
+        let cron = Cron::parse(expression).unwrap();
+        assert_eq!(cron.next_after(at(after)), Some(at(next)), "{}", expression);
+    }
+    assert_eq!(
+        Cron::parse("0 0 30 2 *")
+            .unwrap()
+            .next_after(at("2024-01-01T00:00:00Z")),
+        None
+    );
+}
+
+#[test]
+fn cron_parse_rejects_invalid_expressions() {
+    for invalid in [
+        "",
+        "* * * *",
+        "* * * * * *",
+        "60 * * * *",
+        "* 24 * * *",
+        "* * 0 * *",
+        "* * * 13 *",
+        "* * * * 8",
+        "5-1 * * * *",
+        "*/0 * * * *",
+        "a * * * *",
+        "@reboot",
+    ] {
+        assert!(Cron::parse(invalid).is_err(), "{}", invalid);
+    }
+}
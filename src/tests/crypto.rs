@@ -0,0 +1,59 @@
+use crate::crypto::*;
+
+const KEY_1: &str = "k1:AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+const KEY_2: &str = "k2:ICEiIyQlJicoKSorLC0uLzAxMjM0NTY3ODk6Ozw9Pj8=";
+
+#[test]
+fn crypto_round_trips_with_active_key() {
+    let cipher = ContentCipher::new(Some(KEY_1), &[]);
+    let stored = cipher.encrypt("Hello World");
+    assert!(stored.starts_with("enc:v1:k1:"));
+    assert_ne!(stored, cipher.encrypt("Hello World"), "nonces must be random");
+    assert_eq!(cipher.decrypt(&stored).unwrap(), "Hello World");
+    assert!(!cipher.needs_rotation(&stored));
+}
+
+#[test]
+fn crypto_passes_plain_text_through() {
+    let disabled = ContentCipher::new(None, &[]);
+    assert_eq!(disabled.encrypt("Hello World"), "Hello World");
+    assert_eq!(disabled.decrypt("Hello World").unwrap(), "Hello World");
+
+    let enabled = ContentCipher::new(Some(KEY_1), &[]);
+    assert_eq!(enabled.decrypt("Hello World").unwrap(), "Hello World");
+    assert!(enabled.needs_rotation("Hello World"));
+}
+
+#[test]
+fn crypto_decrypts_with_retired_keys() {
+    let stored = ContentCipher::new(Some(KEY_1), &[]).encrypt("Hello World");
+
+    let rotated = ContentCipher::new(Some(KEY_2), &[KEY_1]);
+    assert_eq!(rotated.decrypt(&stored).unwrap(), "Hello World");
+    assert!(rotated.needs_rotation(&stored));
+
+    let forgotten = ContentCipher::new(Some(KEY_2), &[]);
+    assert_eq!(forgotten.decrypt(&stored), Err(CryptoError::UnknownKey("k1".into())));
+}
+
+#[test]
+fn crypto_rejects_tampered_content() {
+    let cipher = ContentCipher::new(Some(KEY_1), &[]);
+    let mut stored = cipher.encrypt("Hello World");
+    let last = stored.pop();
+    stored.push(if last == Some('A') { 'B' } else { 'A' });
+    assert_eq!(cipher.decrypt(&stored), Err(CryptoError::Corrupt));
+}
+
+#[test]
+fn crypto_content_hash_is_keyed_when_encrypting() {
+    let disabled = ContentCipher::new(None, &[]);
+    let enabled = ContentCipher::new(Some(KEY_1), &[]);
+    let rotated = ContentCipher::new(Some(KEY_2), &[KEY_1]);
+
+    assert_eq!(disabled.content_hash("Hello World"), disabled.content_hash("Hello World"));
+    assert_ne!(disabled.content_hash("Hello World"), disabled.content_hash("Hello World!"));
+    // The plain digest would let anyone confirm a guess of the encrypted content
+    assert_ne!(enabled.content_hash("Hello World"), disabled.content_hash("Hello World"));
+    assert_ne!(enabled.content_hash("Hello World"), rotated.content_hash("Hello World"));
+}
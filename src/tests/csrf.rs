@@ -0,0 +1,65 @@
+use crate::tests::util::*;
+
+use rocket::http::{Cookie, Header, Status};
+use rocket::serde::json;
+
+use crate::csrf::{CSRF_COOKIE, CSRF_HEADER};
+
+#[test]
+fn csrf_login_issues_token_cookie() {
+    let client = client_tracked_get();
+    let email = email_for_session();
+    seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(0), Utc::now());
+
+    let response = client
+        .post("/api/session/login")
+        .json(&json::json!({ "email": email, "code": CODE_EXAMPLE }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let cookie = response
+        .cookies()
+        .get(CSRF_COOKIE)
+        .map(|c| c.value().to_string())
+        .expect("csrf cookie");
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["csrfToken"], json::json!(cookie));
+}
+
+#[test]
+fn csrf_mutation_requires_matching_header() {
+    let client = client_tracked_get();
+    let user_id = seed_user(&client, &email_for_session());
+    let payload = json::json!({ "content": "csrf", "variant": "note" });
+
+    // No token at all
+    let response = signed_in(client.post("/api/posts"), user_id).json(&payload).dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+
+    // Header does not match the cookie
+    let response = signed_in(client.post("/api/posts"), user_id)
+        .cookie(Cookie::new(CSRF_COOKIE, CSRF_TOKEN_EXAMPLE))
+        .header(Header::new(CSRF_HEADER, "forged"))
+        .json(&payload)
+        .dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let response = with_csrf(signed_in(client.post("/api/posts"), user_id))
+        .json(&payload)
+        .dispatch();
+    assert_eq!(response.status(), Status::Created);
+}
+
+#[test]
+fn csrf_safe_methods_and_bearer_requests_are_exempt() {
+    let client = client_tracked_get();
+    let user_id = seed_user(&client, &email_for_session());
+
+    let response = signed_in(client.get("/api/posts"), user_id).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = signed_in(client.post("/api/posts"), user_id)
+        .header(bearer("some-token"))
+        .json(&json::json!({ "content": "bearer", "variant": "note" }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Created);
+}
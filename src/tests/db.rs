@@ -0,0 +1,65 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+use rocket::tokio::time::Duration;
+
+use crate::db::{is_statement_timeout, pool_warm_up, posts_oversized_report, statement_run};
+use crate::retry::write_error;
+
+#[rocket::async_test]
+async fn db_warm_up_leaves_no_rows_behind() {
+    let app = TestApp::new().start().await;
+    let pool = app.pool();
+
+    assert_eq!(pool_warm_up(&pool, 2).await.unwrap(), 2);
+    let posts: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM posts WHERE user_id = 0")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(posts, 0);
+}
+
+#[rocket::async_test]
+async fn db_oversized_posts_are_reported() {
+    let app = TestApp::new().with_posts(3).start().await;
+    let pool = app.pool();
+
+    // Written before the limit, straight to the table
+    sqlx::query("UPDATE posts SET content = ? WHERE id = 'post-1'")
+        .bind("x".repeat(2048))
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert_eq!(posts_oversized_report(&pool, 1024).await.unwrap(), 1);
+    assert_eq!(posts_oversized_report(&pool, 4096).await.unwrap(), 0);
+}
+
+#[rocket::async_test]
+async fn db_statements_time_out() {
+    let app = TestApp::new().start().await;
+    let pool = app.pool();
+
+    let deadline = Some(Duration::from_millis(20));
+    let count = statement_run(
+        "posts.count",
+        deadline,
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM posts").fetch_one(&pool),
+    )
+    .await;
+    assert!(count.is_ok());
+
+    let stuck = statement_run(
+        "posts.stuck",
+        deadline,
+        std::future::pending::<Result<(), sqlx::Error>>(),
+    )
+    .await;
+    let error = stuck.unwrap_err();
+    assert!(is_statement_timeout(&error));
+    assert_eq!(
+        error.to_string(),
+        "error communicating with database: posts.stuck timed out after 20ms"
+    );
+    assert_eq!(write_error(error).status, Status::ServiceUnavailable);
+    assert!(!is_statement_timeout(&sqlx::Error::RowNotFound));
+}
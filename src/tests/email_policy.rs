@@ -0,0 +1,28 @@
+use crate::tests::util::*;
+
+#[test]
+fn email_policy_deny_list_and_disposable_domains() {
+    let policy = EmailDomainPolicy {
+        deny: vec!["blocked.example".into()],
+        deny_disposable: true,
+        ..Default::default()
+    };
+
+    assert!(policy.check_lists("example.com").is_ok());
+    assert!(policy.check_lists("blocked.example").is_err());
+    assert!(policy.check_lists("mail.blocked.example").is_err());
+    assert!(policy.check_lists("notblocked.example").is_ok());
+    assert!(policy.check_lists("Mailinator.com").is_err());
+}
+
+#[test]
+fn email_policy_allow_list_restricts_domains() {
+    let policy = EmailDomainPolicy {
+        allow: vec!["corp.example".into()],
+        ..Default::default()
+    };
+
+    assert!(policy.check_lists("corp.example").is_ok());
+    assert!(policy.check_lists("eu.corp.example").is_ok());
+    assert!(policy.check_lists("example.com").is_err());
+}
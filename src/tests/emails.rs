@@ -0,0 +1,152 @@
+use crate::tests::util::*;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rocket::http::Status;
+use rocket::serde::json;
+
+use crate::email::{DeliveryError, Email, EmailIdentity, EmailSender, EmailTemplate, Mailer};
+use crate::handlers::admin::AdminToken;
+
+/// Refuses every email the way a server refuses an unknown mailbox.
+#[derive(Default)]
+struct BouncingSender {
+    attempts: AtomicUsize,
+}
+
+#[rocket::async_trait]
+impl EmailSender for BouncingSender {
+    async fn send(&self, _: &Email) -> Result<Option<String>, DeliveryError> {
+        self.attempts.fetch_add(1, Ordering::SeqCst);
+        Err(DeliveryError {
+            message: "550 5.1.1 User unknown".into(),
+            permanent: true,
+        })
+    }
+}
+
+#[test]
+fn emails_hard_bounces_suppress_the_address() {
+    let sender = Arc::new(BouncingSender::default());
+    let mailer = Mailer(sender.clone());
+    let client = client_tracked_build(|rocket| {
+        rocket
+            .manage(mailer.clone())
+            .manage(AdminToken(Some(ADMIN_TOKEN_EXAMPLE.into())))
+    });
+    let email = email_for_session();
+
+    let response = client
+        .post("/api/session/send-code")
+        .json(&json::json!({ "email": email }))
+        .dispatch();
+    assert_success(response, Status::Ok);
+    assert_eq!(sender.attempts.load(Ordering::SeqCst), 1);
+
+    // The next email to the address is recorded without being sent
+    let pool = pool_cloned_get(&client);
+    let to = email.to_uppercase();
+    block_on(async move {
+        let mut db = pool.acquire().await.unwrap();
+        mailer.send(&mut db, EmailTemplate::NewDevice, &to, "", "Hello").await
+    });
+    assert_eq!(sender.attempts.load(Ordering::SeqCst), 1);
+
+    let response = client
+        .get("/api/admin/emails/failures")
+        .header(admin_header())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().unwrap();
+    let items = body["items"].as_array().unwrap();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0]["template"], "new_device");
+    assert_eq!(items[0]["status"], "suppressed");
+    assert_eq!(items[1]["template"], "login_code");
+    assert_eq!(items[1]["status"], "failed");
+    assert_eq!(items[1]["error"], "550 5.1.1 User unknown");
+    assert_eq!(items[1]["recipient"], email.to_lowercase());
+
+    let uri = format!("/api/admin/emails/suppressions/{}", email);
+    let response = client.delete(uri.as_str()).header(admin_header()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = client.delete(uri.as_str()).header(admin_header()).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn emails_identity_is_checked() {
+    let identity = EmailIdentity {
+        from: "notes@example.com".into(),
+        from_name: Some("Notes".into()),
+        reply_to: None,
+        subject_prefix: "[Notes]".into(),
+        subjects: vec![(EmailTemplate::Reminder, "About {title}".into())],
+        dkim_domain: "example.com".into(),
+        dkim_selector: "mail2026".into(),
+    };
+    assert_eq!(identity.check(), Ok(()));
+    assert_eq!(
+        identity.subject(EmailTemplate::Reminder, "Plumber"),
+        "[Notes] About Plumber"
+    );
+    assert_eq!(
+        identity.subject(EmailTemplate::LoginCode, ""),
+        "[Notes] Your login code"
+    );
+    let email = identity.email(EmailTemplate::LoginCode, "user@example.com", "", "Hi");
+    assert_eq!(email.from, "notes@example.com");
+    assert_eq!(email.from_name.as_deref(), Some("Notes"));
+
+    let invalid = [
+        EmailIdentity {
+            from: "notes".into(),
+            ..identity.clone()
+        },
+        EmailIdentity {
+            reply_to: Some("help at example.com".into()),
+            ..identity.clone()
+        },
+        EmailIdentity {
+            from_name: Some("Notes\"\r\nBcc: everyone@example.com".into()),
+            ..identity.clone()
+        },
+        EmailIdentity {
+            subjects: vec![(EmailTemplate::LoginCode, "Code\r\nBcc: everyone@example.com".into())],
+            ..identity.clone()
+        },
+        EmailIdentity {
+            dkim_selector: "mail_2026".into(),
+            ..identity.clone()
+        },
+        EmailIdentity {
+            dkim_domain: "localhost".into(),
+            ..identity.clone()
+        },
+    ];
+    for identity in invalid {
+        assert!(identity.check().is_err(), "{:?}", identity);
+    }
+}
+
+#[test]
+fn emails_simulated_in_debug_mode_are_listed() {
+    let client = client_tracked_get();
+    let email = email_for_session();
+    let response = client
+        .post("/api/session/send-code")
+        .json(&json::json!({ "email": email }))
+        .dispatch();
+    assert_success(response, Status::Ok);
+
+    let uri = format!("/api/dev/emails?to={}", email.replace('+', "%2B"));
+    let response = client.get(uri.as_str()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().unwrap();
+    let items = body["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["to"], email);
+    assert_eq!(items[0]["subject"], "[ROCKET] Your login code");
+    assert!(items[0]["body"].as_str().unwrap().contains("Your login code is: "));
+}
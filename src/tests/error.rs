@@ -0,0 +1,45 @@
+use crate::tests::util::*;
+
+use rocket::http::{Header, Status};
+use rocket::serde::json;
+
+#[test]
+fn error_bodies_share_one_schema() {
+    let client = ClientAuthenticated::new();
+
+    // From a handler
+    let response = client.get("/api/posts/missing");
+    assert_eq!(response.status(), Status::NotFound);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["code"], json::json!("not_found"));
+    assert_eq!(body["message"], json::json!("Post not found"));
+    assert_eq!(body["details"], json::Value::Null);
+    assert!(body["requestId"].as_str().is_some_and(|id| !id.is_empty()));
+
+    // From a catcher
+    let anonymous = client_tracked_get();
+    let response = anonymous.get("/api/posts").dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["code"], json::json!("unauthorized"));
+    assert_eq!(body["message"], json::json!("Unauthorized"));
+
+    // Validation errors carry details
+    let long_key = "k".repeat(200);
+    let response = client.put_json("/api/users/me/preferences", &json::json!({ long_key.clone(): true }));
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["code"], json::json!("validation_failed"));
+    assert_eq!(body["details"], json::json!({ "key": long_key }));
+}
+
+#[test]
+fn error_bodies_echo_the_callers_request_id() {
+    let client = client_tracked_get();
+    let response = client
+        .get("/api/posts")
+        .header(Header::new("X-Request-Id", "req-123"))
+        .dispatch();
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["requestId"], json::json!("req-123"));
+}
@@ -0,0 +1,97 @@
+use crate::tests::util::*;
+
+use std::sync::{Arc, Mutex};
+
+use rocket::http::Status;
+use rocket::serde::json;
+
+use crate::events::*;
+
+/// Records what it is handed, or refuses everything when `failing`.
+struct RecordingSink {
+    failing: bool,
+    delivered: Arc<Mutex<Vec<Event>>>,
+}
+
+#[rocket::async_trait]
+impl EventSink for RecordingSink {
+    fn name(&self) -> &'static str {
+        "test"
+    }
+
+    async fn deliver(&self, events: &[Event]) -> Result<(), String> {
+        if self.failing {
+            return Err("unavailable".into());
+        }
+        self.delivered.lock().unwrap().extend_from_slice(events);
+        Ok(())
+    }
+}
+
+#[rocket::async_test]
+async fn events_are_recorded_with_writes_and_delivered_at_least_once() {
+    let app = TestApp::new().with_user().start().await;
+    let user_id = app.user_id();
+    let mut db = app.pool().acquire().await.unwrap();
+    let delivered = Arc::new(Mutex::new(Vec::new()));
+    let sink = RecordingSink {
+        failing: false,
+        delivered: delivered.clone(),
+    };
+    let failing = RecordingSink {
+        failing: true,
+        delivered: delivered.clone(),
+    };
+
+    // A new consumer starts after the events that already exist
+    assert_eq!(events_dispatch(&mut db, &sink, 10, app.now()).await, Ok(0));
+
+    let post = json::json!({ "id": "event-post", "content": "Hello", "variant": "note" });
+    assert_eq!(app.post_json("/api/posts", &post).await.status(), Status::Created);
+    // A rejected update records nothing
+    let stale = json::json!({ "content": "Stale", "updatedAt": "2000-01-01T00:00:00Z" });
+    let response = app.put_json("/api/posts/event-post", &stale).await;
+    assert_eq!(response.status(), Status::NotFound);
+    assert_eq!(app.delete("/api/posts/event-post").await.status(), Status::Ok);
+
+    // A failed delivery leaves the cursor in place, so the same events are offered again
+    assert!(events_dispatch(&mut db, &failing, 10, app.now()).await.is_err());
+    assert_eq!(events_dispatch(&mut db, &sink, 1, app.now()).await, Ok(1));
+    assert_eq!(events_dispatch(&mut db, &sink, 10, app.now()).await, Ok(1));
+    assert_eq!(events_dispatch(&mut db, &sink, 10, app.now()).await, Ok(0));
+
+    let delivered = delivered.lock().unwrap();
+    let kinds = delivered
+        .iter()
+        .map(|event| (event.user_id, event.kind.as_str(), event.post_id.as_deref()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        [
+            (user_id, "upserted", Some("event-post")),
+            (user_id, "deleted", Some("event-post"))
+        ]
+    );
+    assert!(delivered[0].id < delivered[1].id);
+}
+
+#[test]
+fn events_webhook_signature_is_hmac_sha256_of_the_body() {
+    assert_eq!(
+        webhook_signature("secret", br#"{"events":[]}"#),
+        "sha256=a642b59553c93e227ec0f2f38910fbf71231a2197c00899833c00478cec86f34"
+    );
+}
+
+#[test]
+fn events_survive_a_json_round_trip_between_instances() {
+    let event = Event {
+        id: 7,
+        user_id: 3,
+        kind: "upserted".into(),
+        post_id: Some("bus-post".into()),
+        created_at: Utc::now(),
+    };
+    let payload = json::to_string(&event).unwrap();
+    assert_eq!(json::from_str::<Event>(&payload).unwrap(), event);
+}
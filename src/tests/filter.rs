@@ -0,0 +1,106 @@
+use crate::db::sqlx::{QueryBuilder, Sqlite};
+use crate::filter::{FilterExpr, JsonPathFilter};
+
+fn sql(filter: &str) -> String {
+    let expr = FilterExpr::parse(filter).expect("valid filter");
+    let mut builder = QueryBuilder::<Sqlite>::new("");
+    expr.push_sql(&mut builder);
+    builder.sql().to_owned()
+}
+
+#[test]
+fn filter_compiles_to_parameterized_sql() {
+    assert_eq!(
+        sql(r#"variant eq "todo" and updatedAt gt "2024-01-01""#),
+        "((p.variant IS ?) AND (p.updated_at > ?))"
+    );
+    // `and` binds tighter than `or`, and `not` tighter than both
+    assert_eq!(
+        sql(r#"not favorited eq true or parentId eq null and position ge -1.5"#),
+        "((NOT ((r.post_id IS NOT NULL) IS ?)) OR ((p.parent_id IS NULL) AND (p.position >= ?)))"
+    );
+    assert_eq!(
+        sql(r#"(orgId ne null or id eq "a \"quoted\" id")"#),
+        "((p.org_id IS NOT NULL) OR (p.id IS ?))"
+    );
+    // Values never reach the SQL, whatever they contain
+    assert_eq!(sql(r#"variant eq "x') OR 1=1 --""#), "(p.variant IS ?)");
+}
+
+#[test]
+fn filter_rejects_invalid_expressions() {
+    for filter in [
+        "",
+        "variant",
+        r#"variant eq"#,
+        r#"variant like "todo""#,
+        r#"content eq "secret""#,
+        r#"variant eq "todo" and"#,
+        r#"(variant eq "todo""#,
+        r#"variant eq "todo")"#,
+        r#"variant eq "unterminated"#,
+        "variant eq 1",
+        "variant eq null",
+        "position gt null",
+        r#"createdAt gt "yesterday""#,
+        r#"favorited gt true"#,
+        "user_id eq 1",
+        "variant eq todo",
+        r#"variant eq "a"; DROP TABLE posts"#,
+    ] {
+        assert!(FilterExpr::parse(filter).is_err(), "{:?} should not parse", filter);
+    }
+
+    let terms = vec![r#"variant eq "todo""#; 21].join(" or ");
+    assert!(FilterExpr::parse(&terms).is_err());
+    let nested = format!("{}{}{}", "(".repeat(9), r#"variant eq "todo""#, ")".repeat(9));
+    assert!(FilterExpr::parse(&nested).is_err());
+    assert!(FilterExpr::parse(&"not ".repeat(9)).is_err());
+}
+
+fn json_path_sql(filter: &str) -> String {
+    let filter = JsonPathFilter::parse(filter).expect("valid json_path");
+    let mut builder = QueryBuilder::<Sqlite>::new("");
+    filter.push_sql(&mut builder);
+    builder.sql().to_owned()
+}
+
+#[test]
+fn json_path_compiles_to_parameterized_sql() {
+    assert_eq!(
+        json_path_sql("$.done == false"),
+        "(json_type(p.content_json, ?) = 'false')"
+    );
+    assert_eq!(
+        json_path_sql("$.items[2].weight>=1.5"),
+        "(json_type(p.content_json, ?) IN ('integer', 'real') AND json_extract(p.content_json, ?) >= ?)"
+    );
+    assert_eq!(
+        json_path_sql(r#"$.tag != "x') OR 1=1 --""#),
+        "(p.content_json IS NOT NULL AND NOT COALESCE((json_type(p.content_json, ?) = 'text' \
+        AND json_extract(p.content_json, ?) IS ?), 0))"
+    );
+}
+
+#[test]
+fn json_path_rejects_invalid_filters() {
+    for filter in [
+        "",
+        "done == false",
+        "$.done",
+        "$.done = false",
+        "$.done == no",
+        "$.done < true",
+        "$.due > null",
+        "$.tags[] == 1",
+        "$.tags[x] == 1",
+        r#"$."quoted" == 1"#,
+        "$.a-b == 1",
+        "$.done == [false]",
+        r#"$.done == {"a": 1}"#,
+    ] {
+        assert!(JsonPathFilter::parse(filter).is_err(), "{:?} should not parse", filter);
+    }
+    let deep = format!("${} == 1", ".a".repeat(17));
+    assert!(JsonPathFilter::parse(&deep).is_err());
+}
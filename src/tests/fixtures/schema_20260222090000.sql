@@ -0,0 +1,178 @@
+PRAGMA foreign_keys=OFF;
+BEGIN TRANSACTION;
+CREATE TABLE _sqlx_migrations (
+    version BIGINT PRIMARY KEY,
+    description TEXT NOT NULL,
+    installed_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    success BOOLEAN NOT NULL,
+    checksum BLOB NOT NULL,
+    execution_time BIGINT NOT NULL
+);
+INSERT INTO _sqlx_migrations VALUES(20260202071257,'initial','2026-02-22 09:30:00',1,X'b457d7818ce4738f4a5fb2e9b1868645494fd52138b23891b29c0336c1c83383d803f3146a3959c4c7f5f7b4e9021dca',1200000);
+INSERT INTO _sqlx_migrations VALUES(20260210090000,'user preferences','2026-02-22 09:30:00',1,X'83a3cb2c80b7dbcdb497e3b3e20a010216863307deb8367041a603e6385df7c892e27e7d69ececabb58977f6cb81cfb2',1200000);
+INSERT INTO _sqlx_migrations VALUES(20260211090000,'users email verified at','2026-02-22 09:30:00',1,X'3b14d0c79f34b17431b7250e910309eba6c0d64f5278c230c5fc8298dd8bb50ab01bbf336fd2ddc2a73a2416059d2697',1200000);
+INSERT INTO _sqlx_migrations VALUES(20260212090000,'sessions','2026-02-22 09:30:00',1,X'9671300890fb17d9deed09238db43c8751e719f2310f69f83ab809c318249f98e4096eb27bb9bbc42c057ad4bc4c2fd2',1200000);
+INSERT INTO _sqlx_migrations VALUES(20260213090000,'sessions location','2026-02-22 09:30:00',1,X'9e116ebb5f82b17f4311d100c0446ddd648c906ba0fbd8850a56a0aade283ec0806c32f5c81c47cd259d9d59f24419f8',1200000);
+INSERT INTO _sqlx_migrations VALUES(20260214090000,'users disabled at','2026-02-22 09:30:00',1,X'26089e6d506b841aa3aba1c4ad3dd454debe84646e8c05a8868391ba174985825fdf74cd06659e693ef8b8b91ff0f01c',1200000);
+INSERT INTO _sqlx_migrations VALUES(20260215090000,'post reactions','2026-02-22 09:30:00',1,X'37f4cc7ac5d9a5f5b532f8355bb5f8321157d4b4dc5b94b5652f884a08251cfb0e4543ce83b10f5f9d730bcfb12d2fdb',1200000);
+INSERT INTO _sqlx_migrations VALUES(20260216090000,'comments','2026-02-22 09:30:00',1,X'be12417fb54927201c4d738150bfce3964c49a1482433d47a642392828eef3cac39927f18eb5ff8aa6e91784bcd15a84',1200000);
+INSERT INTO _sqlx_migrations VALUES(20260217090000,'posts parent id','2026-02-22 09:30:00',1,X'5279ceffa62f03154b779cacfb8a503d1260171aa088d9c6719453b38cf7b7ebafbb4ad0b0b890db19b440de8fef2ab3',1200000);
+INSERT INTO _sqlx_migrations VALUES(20260218090000,'posts position','2026-02-22 09:30:00',1,X'474d9255ddcdffe3594939a7f81f20e794d668d60747b85acb8205dee12541d0c8b0c776e23aaba7a860fadceafd4ace',1200000);
+INSERT INTO _sqlx_migrations VALUES(20260219090000,'posts content hash','2026-02-22 09:30:00',1,X'0c86d58d82a07183e41323a09e3dfeabe25429debf9e1e9121370b6e5d6d3f97b52398812e1a931f270820786cb4ad5d',1200000);
+INSERT INTO _sqlx_migrations VALUES(20260220090000,'posts seq','2026-02-22 09:30:00',1,X'10f170e1f48b3277857dfa04c05c2eaa597c79a063a62d8d40ccd6f2e2bc077c62e89f3f47926bd183499f149389772e',1200000);
+INSERT INTO _sqlx_migrations VALUES(20260221090000,'organizations','2026-02-22 09:30:00',1,X'a93d8f95f6edfacdc009f916aa274960fd1cfc0d8290c73f55f21f0eeef5805a03634a14413e844a8c29b2b489ff8d65',1200000);
+INSERT INTO _sqlx_migrations VALUES(20260222090000,'events','2026-02-22 09:30:00',1,X'6aca3f221c5add34af342004248e13708c854f3669f5d5d57b1ee07f9d7165b2650ba498b107daf669238066167b5a9f',1200000);
+CREATE TABLE posts (
+  id TEXT PRIMARY KEY NOT NULL,
+  content TEXT NOT NULL,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  user_id INTEGER NOT NULL,
+  variant TEXT NOT NULL, parent_id TEXT REFERENCES posts(id) ON DELETE CASCADE, position REAL, content_hash TEXT, seq INTEGER NOT NULL DEFAULT 0, org_id TEXT REFERENCES organizations(id) ON DELETE SET NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+INSERT INTO posts VALUES('post-a','Groceries','2026-02-05 08:00:00','2026-02-05 08:30:00',1,'todo','post-b',1.0,NULL,3,NULL);
+INSERT INTO posts VALUES('post-b','Ideas','2026-02-06 09:00:00','2026-02-06 09:00:00',1,'note',NULL,NULL,NULL,4,NULL);
+CREATE TABLE users (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  code_attempts INTEGER DEFAULT 0,
+  code_created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+  code_hash TEXT,
+  email TEXT UNIQUE NOT NULL
+, email_verified_at DATETIME, disabled_at DATETIME);
+INSERT INTO users VALUES(1,'2026-02-03 10:00:00',0,'2026-02-03 10:00:00',NULL,'ada@example.com','2026-02-03 10:01:00',NULL);
+INSERT INTO users VALUES(2,'2026-02-04 11:00:00',0,'2026-02-04 11:00:00',NULL,'grace@example.com',NULL,NULL);
+CREATE TABLE user_preferences (
+  user_id INTEGER NOT NULL,
+  key TEXT NOT NULL,
+  value TEXT NOT NULL,
+  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  PRIMARY KEY (user_id, key),
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+INSERT INTO user_preferences VALUES(1,'theme','"dark"','2026-02-09 14:00:00');
+CREATE TABLE sessions (
+  id TEXT PRIMARY KEY NOT NULL,
+  user_id INTEGER NOT NULL,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  expires_at DATETIME NOT NULL,
+  revoked_at DATETIME,
+  ip TEXT,
+  user_agent TEXT, location TEXT,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+INSERT INTO sessions VALUES('session-a',1,'2026-02-10 15:00:00','2026-02-11 15:00:00',NULL,'203.0.113.7','Mozilla/5.0',NULL);
+CREATE TABLE post_reactions (
+  post_id TEXT NOT NULL,
+  user_id INTEGER NOT NULL,
+  kind TEXT NOT NULL,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  PRIMARY KEY (post_id, user_id, kind),
+  FOREIGN KEY (post_id) REFERENCES posts(id) ON DELETE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+INSERT INTO post_reactions VALUES('post-b',1,'favorite','2026-02-07 12:00:00');
+CREATE TABLE comments (
+  id TEXT PRIMARY KEY NOT NULL,
+  post_id TEXT NOT NULL,
+  parent_id TEXT,
+  user_id INTEGER NOT NULL,
+  content TEXT NOT NULL,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  FOREIGN KEY (post_id) REFERENCES posts(id) ON DELETE CASCADE,
+  FOREIGN KEY (parent_id) REFERENCES comments(id) ON DELETE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+INSERT INTO comments VALUES('comment-a','post-a',NULL,1,'Oat milk too','2026-02-08 13:00:00','2026-02-08 13:00:00');
+CREATE TABLE sync_counter (
+  id INTEGER PRIMARY KEY CHECK (id = 1),
+  seq INTEGER NOT NULL
+);
+INSERT INTO sync_counter VALUES(1,4);
+CREATE TABLE post_tombstones (
+  post_id TEXT NOT NULL,
+  user_id INTEGER NOT NULL,
+  seq INTEGER NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+CREATE TABLE organizations (
+  id TEXT PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+INSERT INTO organizations VALUES('org-a','Acme','2026-02-12 16:00:00');
+CREATE TABLE organization_members (
+  org_id TEXT NOT NULL,
+  user_id INTEGER NOT NULL,
+  role TEXT NOT NULL CHECK (role IN ('owner', 'admin', 'member')),
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  PRIMARY KEY (org_id, user_id),
+  FOREIGN KEY (org_id) REFERENCES organizations(id) ON DELETE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+INSERT INTO organization_members VALUES('org-a',1,'owner','2026-02-12 16:00:00');
+INSERT INTO organization_members VALUES('org-a',2,'member','2026-02-13 17:00:00');
+CREATE TABLE events (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  user_id INTEGER NOT NULL,
+  kind TEXT NOT NULL CHECK (kind IN ('upserted', 'deleted', 'cleared')),
+  post_id TEXT,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+INSERT INTO events VALUES(1,1,'upserted','post-a','2026-02-14 18:00:00');
+CREATE TABLE event_cursors (
+  consumer TEXT PRIMARY KEY NOT NULL,
+  event_id INTEGER NOT NULL,
+  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+INSERT INTO event_cursors VALUES('webhooks',1,'2026-02-14 18:00:01');
+PRAGMA writable_schema=ON;
+CREATE TABLE IF NOT EXISTS sqlite_sequence(name,seq);
+DELETE FROM sqlite_sequence;
+INSERT INTO sqlite_sequence VALUES('users',2);
+INSERT INTO sqlite_sequence VALUES('events',1);
+CREATE TRIGGER posts_seq_insert AFTER INSERT ON posts
+BEGIN
+  UPDATE sync_counter SET seq = seq + 1;
+  UPDATE posts SET seq = (SELECT seq FROM sync_counter) WHERE id = NEW.id;
+  DELETE FROM post_tombstones WHERE post_id = NEW.id AND user_id = NEW.user_id;
+END;
+CREATE TRIGGER posts_seq_update AFTER UPDATE ON posts WHEN NEW.seq = OLD.seq
+BEGIN
+  UPDATE sync_counter SET seq = seq + 1;
+  UPDATE posts SET seq = (SELECT seq FROM sync_counter) WHERE id = NEW.id;
+END;
+CREATE TRIGGER posts_seq_delete AFTER DELETE ON posts
+BEGIN
+  UPDATE sync_counter SET seq = seq + 1;
+  INSERT INTO post_tombstones (post_id, user_id, seq) VALUES (OLD.id, OLD.user_id, (SELECT seq FROM sync_counter));
+END;
+CREATE TRIGGER post_reactions_seq_insert AFTER INSERT ON post_reactions
+BEGIN
+  UPDATE sync_counter SET seq = seq + 1;
+  UPDATE posts SET seq = (SELECT seq FROM sync_counter) WHERE id = NEW.post_id;
+END;
+CREATE TRIGGER post_reactions_seq_delete AFTER DELETE ON post_reactions
+BEGIN
+  UPDATE sync_counter SET seq = seq + 1;
+  UPDATE posts SET seq = (SELECT seq FROM sync_counter) WHERE id = OLD.post_id;
+END;
+CREATE INDEX idx_posts_updated_at ON posts (updated_at);
+CREATE INDEX idx_posts_user_id ON posts (user_id);
+CREATE INDEX idx_users_email ON users (email);
+CREATE INDEX idx_sessions_user_id ON sessions (user_id);
+CREATE INDEX idx_post_reactions_user_id ON post_reactions (user_id, kind);
+CREATE INDEX idx_comments_post_id_updated_at ON comments (post_id, updated_at);
+CREATE INDEX idx_posts_parent_id ON posts (parent_id);
+CREATE INDEX idx_posts_user_id_position ON posts (user_id, position);
+CREATE INDEX idx_posts_user_id_content_hash ON posts (user_id, content_hash);
+CREATE INDEX idx_posts_user_id_seq ON posts (user_id, seq);
+CREATE INDEX idx_post_tombstones_user_id_seq ON post_tombstones (user_id, seq);
+CREATE INDEX idx_organization_members_user_id ON organization_members (user_id);
+CREATE INDEX idx_posts_org_id_updated_at ON posts (org_id, updated_at);
+CREATE INDEX idx_events_created_at ON events (created_at);
+PRAGMA writable_schema=OFF;
+COMMIT;
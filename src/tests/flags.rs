@@ -0,0 +1,86 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+use rocket::serde::json;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::flags::*;
+
+fn flag_example(enabled: bool, rollout_percent: i64, users: &[(i64, bool)]) -> FlagSet {
+    let flag = FeatureFlag {
+        name: "sync_v2".into(),
+        enabled,
+        rollout_percent,
+        users: users.iter().copied().collect::<BTreeMap<_, _>>(),
+        updated_at: Utc::now(),
+    };
+    FlagSet(HashMap::from([(flag.name.clone(), flag)]))
+}
+
+#[test]
+fn flags_apply_overrides_then_rollout() {
+    let set = flag_example(false, 0, &[(7, true)]);
+    assert!(set.enabled("sync_v2", Some(7)));
+    assert!(!set.enabled("sync_v2", Some(8)));
+    assert!(!set.enabled("sync_v2", None));
+    assert!(!set.enabled("unknown", Some(7)));
+
+    let set = flag_example(true, 0, &[(7, false)]);
+    assert!(!set.enabled("sync_v2", Some(7)));
+    assert!(set.enabled("sync_v2", Some(8)));
+    assert!(set.enabled("sync_v2", None));
+
+    // A rollout reaches about its share of the users, and only signed-in ones
+    let set = flag_example(false, 30, &[]);
+    let reached = (1..=1000).filter(|id| set.enabled("sync_v2", Some(*id))).count();
+    assert!((250..350).contains(&reached), "{} users reached", reached);
+    assert!(!set.enabled("sync_v2", None));
+    assert_eq!(flag_bucket("sync_v2", 42), flag_bucket("sync_v2", 42));
+}
+
+#[test]
+fn flags_toggle_through_the_admin_api() {
+    let client = client_with_admin();
+    let user_id = seed_user(&client, &email_for_session());
+    let flags_of_user = || {
+        let response = signed_in(client.get("/api/flags"), user_id).dispatch();
+        response.into_json::<json::Value>().expect("flags response")["flags"].clone()
+    };
+    assert_eq!(flags_of_user(), json::json!([]));
+
+    let response = client
+        .put("/api/admin/flags/sync_v2")
+        .header(admin_header())
+        .json(&json::json!({ "enabled": false }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = client
+        .put(format!("/api/admin/flags/sync_v2/users/{}", user_id))
+        .header(admin_header())
+        .json(&json::json!({ "enabled": true }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(flags_of_user(), json::json!(["sync_v2"]));
+    // Not for anyone else
+    let response = client.get("/api/flags").dispatch();
+    assert_eq!(response.into_json::<json::Value>().unwrap()["flags"], json::json!([]));
+
+    let response = client.get("/api/admin/flags").header(admin_header()).dispatch();
+    let body = response.into_json::<json::Value>().expect("flags list");
+    assert_eq!(body["items"][0]["name"], "sync_v2");
+    assert_eq!(body["items"][0]["users"][user_id.to_string()], true);
+
+    let response = client
+        .delete("/api/admin/flags/sync_v2")
+        .header(admin_header())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(flags_of_user(), json::json!([]));
+
+    let response = client
+        .put("/api/admin/flags/Not%20Valid")
+        .header(admin_header())
+        .json(&json::json!({ "enabled": true }))
+        .dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+}
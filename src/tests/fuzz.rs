@@ -0,0 +1,114 @@
+use crate::tests::util::*;
+
+use proptest::prelude::*;
+use rocket::serde::{Deserialize, json};
+
+use crate::handlers::posts::{CreateRequestBody, UpsertPostPayload};
+use crate::handlers::session::{LoginRequestBody, SendCodeRequestBody};
+
+/// Field names of the request bodies, so that generated objects mostly hit real fields.
+const FIELDS: &[&str] = &[
+    "id",
+    "parentId",
+    "createdAt",
+    "content",
+    "updatedAt",
+    "variant",
+    "position",
+    "orgId",
+    "code",
+    "email",
+    "rememberMe",
+    "captchaToken",
+    "website",
+];
+
+/// Strings a parser is likely to trip over: near-miss timestamps, escapes, huge numbers.
+fn tricky_string() -> impl Strategy<Value = String> {
+    prop_oneof![
+        any::<String>(),
+        "[0-9]{4}-[0-9]{2}-[0-9]{2}T[0-9]{2}:[0-9]{2}:[0-9]{2}(\\.[0-9]{1,12})?(Z|[+-][0-9]{2}:[0-9]{2})?",
+        "[0-9]{1,24}",
+        Just("2024-02-30T00:00:00Z".to_string()),
+        Just("+275760-09-13T00:00:00Z".to_string()),
+        Just("\\ud800".to_string()),
+    ]
+}
+
+fn json_value() -> impl Strategy<Value = json::Value> {
+    let leaf = prop_oneof![
+        Just(json::Value::Null),
+        any::<bool>().prop_map(json::Value::from),
+        any::<i64>().prop_map(json::Value::from),
+        any::<f64>().prop_map(json::Value::from),
+        tricky_string().prop_map(json::Value::from),
+    ];
+    leaf.prop_recursive(3, 32, 8, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..4).prop_map(json::Value::from),
+            prop::collection::btree_map(prop::sample::select(FIELDS), inner, 0..FIELDS.len()).prop_map(|fields| {
+                json::Value::Object(
+                    fields
+                        .into_iter()
+                        .map(|(key, value)| (key.to_string(), value))
+                        .collect(),
+                )
+            }),
+        ]
+    })
+}
+
+/// Parses a body the way the `Json` guard does, from text; reaching the end without panicking is
+/// the property.
+fn body_parse(text: &str) {
+    let _ = json::from_str::<CreateRequestBody>(text);
+    let _ = json::from_str::<Vec<UpsertPostPayload>>(text);
+    let _ = json::from_str::<LoginRequestBody<'_>>(text);
+    let _ = json::from_str::<SendCodeRequestBody<'_>>(text);
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Stamped {
+    at: DateTime<Utc>,
+    #[serde(default)]
+    until: Option<DateTime<Utc>>,
+}
+
+proptest! {
+    #[test]
+    fn fuzz_request_bodies_from_arbitrary_text(text in any::<String>()) {
+        body_parse(&text);
+    }
+
+    #[test]
+    fn fuzz_request_bodies_from_structured_json(value in json_value()) {
+        body_parse(&value.to_string());
+        body_parse(&json::json!([value]).to_string());
+    }
+
+    #[test]
+    fn fuzz_timestamps_accept_rfc3339(text in tricky_string()) {
+        let parsed = json::from_value::<Stamped>(json::json!({ "at": text, "until": text }));
+        if let Ok(at) = DateTime::parse_from_rfc3339(&text) {
+            let parsed = parsed.unwrap();
+            prop_assert_eq!(parsed.at, at);
+            prop_assert_eq!(parsed.until, Some(at.to_utc()));
+        }
+    }
+
+    #[test]
+    fn fuzz_timestamp_params_parse_or_fail_validation(text in tricky_string()) {
+        if let Err(e) = timestamp_param_parse("after", &text) {
+            prop_assert_eq!(e.status, rocket::http::Status::UnprocessableEntity);
+        }
+    }
+
+    #[test]
+    fn fuzz_timestamps_round_trip(seconds in 0i64..253_402_300_799, nanos in 0u32..1_000_000_000) {
+        let at = DateTime::from_timestamp(seconds, nanos).unwrap();
+        let parsed = json::from_value::<Stamped>(json::json!({ "at": at })).unwrap();
+        prop_assert_eq!(parsed.at, at);
+        prop_assert_eq!(parsed.until, None);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+use rocket::serde::json;
+
+use crate::maintenance::{Maintenance, MaintenanceConfig};
+use crate::ratelimit::{RateLimitConfig, RateLimiter};
+
+const GRAPHQL_BASE: &str = "/api/graphql";
+
+fn graphql(client: &ClientAuthenticated, query: &str) -> json::Value {
+    let response = client.post_json(GRAPHQL_BASE, &json::json!({ "query": query }));
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert!(body.get("errors").is_none(), "unexpected errors: {}", body);
+    body["data"].clone()
+}
+
+#[test]
+fn graphql_upsert_and_list_posts() {
+    let client = ClientAuthenticated::new();
+
+    let data = graphql(
+        &client,
+        r#"mutation {
+            upsertPosts(posts: [
+                { id: "gql-a", content: "first", variant: "note", updatedAt: "2026-01-01T00:00:00Z" },
+                { id: "gql-b", content: "second", variant: "todo", updatedAt: "2026-01-02T00:00:00Z" }
+            ])
+        }"#,
+    );
+    assert_eq!(data["upsertPosts"], json::json!(true));
+
+    // An older update must not overwrite the stored post
+    graphql(
+        &client,
+        r#"mutation {
+            upsertPosts(posts: [{ id: "gql-a", content: "stale", variant: "note", updatedAt: "2025-01-01T00:00:00Z" }])
+        }"#,
+    );
+
+    let data = graphql(&client, r#"{ posts(limit: 1) { items { id content } hasMore } }"#);
+    assert_eq!(
+        data["posts"]["items"],
+        json::json!([{ "id": "gql-b", "content": "second" }])
+    );
+    assert_eq!(data["posts"]["hasMore"], json::json!(true));
+
+    let data = graphql(
+        &client,
+        r#"{ posts(variant: "note") { items { id content } hasMore } }"#,
+    );
+    assert_eq!(
+        data["posts"]["items"],
+        json::json!([{ "id": "gql-a", "content": "first" }])
+    );
+    assert_eq!(data["posts"]["hasMore"], json::json!(false));
+
+    let data = graphql(&client, r#"mutation { deletePost(id: "gql-a") }"#);
+    assert_eq!(data["deletePost"], json::json!(true));
+    let data = graphql(&client, r#"{ post(id: "gql-a") { id } }"#);
+    assert_eq!(data["post"], json::Value::Null);
+}
+
+#[test]
+fn graphql_stream_rejects_non_subscriptions() {
+    let client = ClientAuthenticated::new();
+    let response = client.get("/api/graphql/stream?query=mutation%20%7B%20deletePost(id%3A%20%22x%22)%20%7D");
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+}
+
+#[test]
+fn graphql_mutations_count_against_the_write_rate_limit() {
+    let limiter = RateLimiter::new(RateLimitConfig {
+        window_secs: 60,
+        reads: 0,
+        writes: 1,
+        auth: 0,
+    });
+    let client = client_tracked_build(|rocket| rocket.manage(limiter));
+    let user_id = seed_user(&client, &email_for_session());
+    let mutation = json::json!({ "query": r#"mutation { deletePost(id: "missing") }"# });
+    let execute = || {
+        with_csrf(signed_in(client.post(GRAPHQL_BASE), user_id))
+            .json(&mutation)
+            .dispatch()
+    };
+
+    let response = execute();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("X-RateLimit-Remaining"), Some("0"));
+    assert_eq!(execute().status(), Status::TooManyRequests);
+}
+
+#[test]
+fn graphql_mutations_are_refused_in_maintenance_mode() {
+    let maintenance = Maintenance::new(MaintenanceConfig {
+        enabled: true,
+        message: None,
+        retry_after_secs: 60,
+    });
+    let client = client_tracked_build(|rocket| rocket.manage(maintenance));
+    let user_id = seed_user(&client, &email_for_session());
+    let execute = |query: &str| {
+        with_csrf(signed_in(client.post(GRAPHQL_BASE), user_id))
+            .json(&json::json!({ "query": query }))
+            .dispatch()
+    };
+
+    let response = execute(r#"{ posts { hasMore } }"#);
+    assert_eq!(response.status(), Status::Ok);
+    let response = execute(r#"mutation { deletePost(id: "missing") }"#);
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+    assert_eq!(response.headers().get_one("Retry-After"), Some("60"));
+}
@@ -0,0 +1,59 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+use rocket::local::blocking::Client;
+use rocket::serde::json;
+
+use crate::grpc::{cookie_key, user_id_get};
+
+/// Resolves the user of a `cookie` metadata entry, as gRPC calls do.
+fn grpc_user_id(client: &Client, header: String) -> Result<i64, tonic::Code> {
+    let pool = pool_cloned_get(client);
+    block_on(async move {
+        let mut request = tonic::Request::new(());
+        request.metadata_mut().insert("cookie", header.parse().unwrap());
+        user_id_get(&request, &cookie_key(), &pool).await.map_err(|e| e.code())
+    })
+}
+
+#[test]
+fn grpc_authenticates_with_the_rest_session_cookie() {
+    let client = client_tracked_get();
+    let email = email_for_session();
+    let (user_id, _) = seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(0), Utc::now());
+
+    let response = client
+        .post("/api/session/login")
+        .json(&json::json!({ "email": email, "code": CODE_EXAMPLE }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let sealed = |name| response.cookies().get(name).expect("cookie").value().to_string();
+    let header = format!(
+        "csrf_token=abc; user_id={}; {}={}",
+        sealed("user_id"),
+        SESSION_COOKIE,
+        sealed(SESSION_COOKIE)
+    );
+    assert_eq!(grpc_user_id(&client, header.clone()), Ok(user_id));
+
+    // Without its session, the auth cookie is not enough
+    let without_session = format!("user_id={}", sealed("user_id"));
+    assert_eq!(
+        grpc_user_id(&client, without_session),
+        Err(tonic::Code::Unauthenticated)
+    );
+    assert_eq!(
+        grpc_user_id(&client, "user_id=1".into()),
+        Err(tonic::Code::Unauthenticated)
+    );
+
+    let pool = pool_cloned_get(&client);
+    block_on(async move {
+        sqlx::query("UPDATE users SET disabled_at = CURRENT_TIMESTAMP WHERE id = ?")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .expect("disable user");
+    });
+    assert_eq!(grpc_user_id(&client, header), Err(tonic::Code::Unauthenticated));
+}
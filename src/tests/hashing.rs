@@ -0,0 +1,99 @@
+use crate::tests::util::*;
+
+use argon2::PasswordHasher;
+use argon2::password_hash::{SaltString, rand_core::OsRng};
+use rocket::http::Status;
+use rocket::serde::json;
+use rocket::tokio::time::Duration;
+
+#[test]
+fn hashing_limiter_sheds_when_queue_is_full() {
+    block_on(async {
+        let limiter = HashLimiter::new(1, 0, Duration::from_secs(5));
+        let _held = limiter.acquire().await.expect("first permit");
+        assert_eq!(limiter.acquire().await.err(), Some(HashError::Saturated));
+    });
+}
+
+#[test]
+fn hashing_limiter_sheds_after_queue_timeout() {
+    block_on(async {
+        let limiter = HashLimiter::new(1, 4, Duration::from_millis(20));
+        let held = limiter.acquire().await.expect("first permit");
+        assert_eq!(limiter.acquire().await.err(), Some(HashError::Saturated));
+
+        drop(held);
+        assert!(limiter.acquire().await.is_ok());
+    });
+}
+
+/// Hashes `CODE_EXAMPLE` with a lower memory cost than the current `HashConfig`.
+fn outdated_hash() -> String {
+    let outdated = HashConfig {
+        memory_kib: hash_config().memory_kib / 2,
+        ..hash_config().clone()
+    };
+    let salt = SaltString::generate(&mut OsRng);
+    outdated
+        .argon2()
+        .hash_password(CODE_EXAMPLE.as_bytes(), &salt)
+        .expect("hash")
+        .to_string()
+}
+
+#[test]
+fn hashing_rehashes_codes_hashed_with_outdated_settings() {
+    let outdated_hash = outdated_hash();
+    assert!(hash_needs_rehash(&outdated_hash));
+
+    let verification =
+        block_on(async move { hash_code_verify_rehash(&outdated_hash, CODE_EXAMPLE).await }).expect("verify");
+    assert!(verification.verified);
+    let rehash = verification.rehash.expect("rehash");
+    assert!(!hash_needs_rehash(&rehash));
+
+    let verification = block_on(async move { hash_code_verify_rehash(&rehash, CODE_EXAMPLE).await }).expect("verify");
+    assert_eq!(
+        verification,
+        HashVerification {
+            verified: true,
+            rehash: None
+        }
+    );
+}
+
+#[test]
+fn hashing_login_rehashes_the_pending_code() {
+    let client = client_tracked_get();
+    let email = email_for_session();
+    let (user_id, _) = seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(0), Utc::now());
+    let pool = pool_cloned_get(&client);
+    let stored_hash = move || {
+        let pool = pool.clone();
+        block_on(async move {
+            sqlx::query_scalar::<_, String>("SELECT code_hash FROM users WHERE id = ?")
+                .bind(user_id)
+                .fetch_one(&pool)
+                .await
+                .expect("code hash")
+        })
+    };
+    let pool = pool_cloned_get(&client);
+    block_on(async move {
+        // A suspended account keeps its code, so the re-hash is what gets stored
+        sqlx::query("UPDATE users SET code_hash = ?, disabled_at = CURRENT_TIMESTAMP WHERE id = ?")
+            .bind(outdated_hash())
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .expect("outdate code hash");
+    });
+    assert!(hash_needs_rehash(&stored_hash()));
+
+    let response = client
+        .post("/api/session/login")
+        .json(&json::json!({ "email": email, "code": CODE_EXAMPLE }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert!(!hash_needs_rehash(&stored_hash()));
+}
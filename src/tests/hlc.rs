@@ -0,0 +1,115 @@
+use crate::tests::util::*;
+
+use chrono::TimeDelta;
+use rocket::http::Status;
+use rocket::serde::json;
+
+use crate::hlc::*;
+
+fn stamp(millis: i64, counter: u16, node: &str) -> Hlc {
+    Hlc {
+        millis,
+        counter,
+        node: node.into(),
+    }
+}
+
+#[test]
+fn hlc_text_sorts_like_the_stamps() {
+    let stamps = [
+        stamp(1_706_702_400_000, 0, ""),
+        stamp(1_706_702_400_000, 0, "web1"),
+        stamp(1_706_702_400_000, 2, "a"),
+        stamp(1_706_702_400_000, 10, "a"),
+        stamp(1_706_702_400_001, 0, "a"),
+    ];
+    for pair in stamps.windows(2) {
+        assert!(pair[0] < pair[1]);
+        assert!(pair[0].to_string() < pair[1].to_string());
+    }
+    assert_eq!(stamps[3].to_string(), "001706702400000-00010-a");
+    assert_eq!("001706702400000-00010-a".parse::<Hlc>(), Ok(stamps[3].clone()));
+    assert_eq!(
+        "001706702400000-00000-web-1".parse::<Hlc>(),
+        Ok(stamp(1_706_702_400_000, 0, "web-1"))
+    );
+
+    for invalid in [
+        "",
+        "1706702400000-0-a",
+        "001706702400000-00000",
+        "001706702400000-99999-a",
+        "x",
+    ] {
+        assert!(invalid.parse::<Hlc>().is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn hlc_clock_stamps_grow_past_what_it_has_seen() {
+    let clock = HlcClock::new("test");
+    let first = clock.now();
+    let second = clock.now();
+    assert!(first < second);
+
+    // A client a minute ahead pulls the clock along, and the counter orders the stamps after it
+    let ahead = stamp((Utc::now() + TimeDelta::minutes(1)).timestamp_millis(), 7, "client");
+    let updated = clock.update(&ahead);
+    assert!(updated > ahead);
+    assert_eq!((updated.millis, updated.counter), (ahead.millis, 8));
+    assert!(clock.now() > updated);
+
+    let far_ahead = Hlc::from_wall(Utc::now() + TimeDelta::days(1));
+    assert!(clock.accept(far_ahead, Utc::now()).is_err());
+}
+
+#[test]
+fn hlc_versions_decide_conflicts_over_updated_at() {
+    let client = ClientAuthenticated::new();
+    let now = Utc::now();
+    let version = |millis: i64, node: &str| stamp(now.timestamp_millis() + millis, 0, node);
+
+    let payload =
+        json::json!({ "id": "hlc-post", "content": "First", "version": version(-1000, "a"), "variant": "note" });
+    assert_success(client.post_json("/api/posts", &payload), Status::Created);
+
+    // An edit from a device whose wall clock runs behind still wins with a greater version
+    let behind = now - TimeDelta::hours(1);
+    let payload = json::json!({ "content": "Second", "updatedAt": behind, "version": version(-500, "b") });
+    assert_success(client.put_json("/api/posts/hlc-post", &payload), Status::Ok);
+
+    // ...and a newer wall-clock time does not beat it with a smaller version
+    let payload = json::json!({ "content": "Stale", "updatedAt": now, "version": version(-1000, "c") });
+    assert_eq!(
+        client.put_json("/api/posts/hlc-post", &payload).status(),
+        Status::NotFound
+    );
+
+    let response = client.get("/api/posts/hlc-post");
+    let body = response.into_json::<json::Value>().expect("post response");
+    assert_eq!(body["content"], "Second");
+    assert_eq!(body["version"], version(-500, "b").to_string());
+
+    // Without a version or a timestamp, the server stamps the write after everything it has seen
+    let payload = json::json!({ "content": "Third" });
+    assert_success(client.put_json("/api/posts/hlc-post", &payload), Status::Ok);
+
+    let payload = json::json!({ "content": "Ahead", "version": version(86_400_000, "d") });
+    let response = client.put_json("/api/posts/hlc-post", &payload);
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+}
+
+#[test]
+fn hlc_sync_clock_returns_a_stamp_after_the_clients() {
+    let client = ClientAuthenticated::new();
+    let seen = stamp(Utc::now().timestamp_millis() + 30_000, 5, "client");
+
+    let response = client.get(&format!("/api/sync/clock?version={}", seen));
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().expect("clock response");
+    let version = json::from_value::<Hlc>(body["version"].clone()).expect("version");
+    assert!(version > seen);
+
+    let response = client.get("/api/sync/clock?version=yesterday");
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+}
@@ -0,0 +1,142 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+use rocket::local::blocking::Client;
+use rocket::serde::json;
+
+use crate::impersonation::{IMPERSONATION_COOKIE, IMPERSONATION_HEADER};
+
+fn impersonate(client: &Client, user_id: i64, body: &json::Value) -> Status {
+    client
+        .post(format!("/api/admin/impersonate/{}", user_id))
+        .header(admin_header())
+        .json(body)
+        .dispatch()
+        .status()
+}
+
+#[test]
+fn impersonation_is_marked_and_ends_when_stopped() {
+    let client = client_with_admin();
+    let user_id = seed_user(&client, &email_for_session());
+    let body = json::json!({ "staff": "alice", "reason": "ticket 42" });
+    assert_eq!(
+        impersonate(&client, user_id, &json::json!({ "staff": "alice", "reason": " " })),
+        Status::UnprocessableEntity
+    );
+    assert_eq!(impersonate(&client, 999999, &body), Status::NotFound);
+    assert_eq!(impersonate(&client, user_id, &body), Status::Ok);
+
+    let response = client.get("/api/session/").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one(IMPERSONATION_HEADER), Some("alice"));
+    let session = response.into_json::<json::Value>().unwrap();
+    assert_eq!(session["id"], user_id);
+    assert_eq!(session["impersonation"]["impersonator"], "alice");
+
+    let cookies = ["user_id", SESSION_COOKIE].map(|name| client.cookies().get_private(name).unwrap());
+    let response = with_csrf(client.post("/api/session/stop-impersonating")).dispatch();
+    assert_success(response, Status::Ok);
+    assert!(client.cookies().get_private(IMPERSONATION_COOKIE).is_none());
+    assert_eq!(client.get("/api/session/").dispatch().status(), Status::Unauthorized);
+    // Cookies kept from before the stop are refused too
+    let [user_cookie, session_cookie] = cookies;
+    let response = client
+        .get("/api/posts")
+        .private_cookie(user_cookie)
+        .private_cookie(session_cookie)
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    let response = with_csrf(client.post("/api/session/stop-impersonating")).dispatch();
+    assert_eq!(response.status(), Status::Conflict);
+
+    // The session stays as the audit record
+    let pool = pool_cloned_get(&client);
+    let audit = block_on(async move {
+        sqlx::query_as::<_, (String, String, bool)>(
+            "SELECT impersonator, impersonation_reason, revoked_at IS NOT NULL FROM sessions WHERE user_id = ?",
+        )
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+    });
+    assert_eq!(audit, ("alice".into(), "ticket 42".into(), true));
+}
+
+#[test]
+fn impersonation_expires_whatever_the_cookies_say() {
+    let client = client_with_admin();
+    let user_id = seed_user(&client, &email_for_session());
+    let body = json::json!({ "staff": "bob", "reason": "ticket 43" });
+    assert_eq!(impersonate(&client, user_id, &body), Status::Ok);
+    assert_eq!(client.get("/api/session/").dispatch().status(), Status::Ok);
+
+    let pool = pool_cloned_get(&client);
+    block_on(async move {
+        sqlx::query("UPDATE sessions SET expires_at = '2000-01-01T00:00:00Z' WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .unwrap()
+    });
+    let response = client.get("/api/session/").dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert!(response.headers().get_one(IMPERSONATION_HEADER).is_none());
+
+    // Nor is any other route open to the user's cookies
+    assert_eq!(client.get("/api/posts").dispatch().status(), Status::Unauthorized);
+    let response = with_csrf(client.post("/api/posts"))
+        .json(&json::json!({ "content": "after", "variant": "note" }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[test]
+fn impersonation_cannot_mint_tokens() {
+    let client = client_with_admin();
+    let user_id = seed_user(&client, &email_for_session());
+    let body = json::json!({ "staff": "carol", "reason": "ticket 44" });
+    assert_eq!(impersonate(&client, user_id, &body), Status::Ok);
+
+    let response = with_csrf(client.post("/api/users/me/tokens"))
+        .json(&json::json!({ "name": "cli", "scopes": "posts:read" }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let redirect_uri = "com.example.notes:/oauth";
+    let response = client
+        .post("/api/admin/oauth/clients")
+        .header(admin_header())
+        .json(&json::json!({ "name": "Notes", "redirectUris": [redirect_uri], "scopes": "posts:read" }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Created);
+    let client_id = response.into_json::<json::Value>().unwrap()["id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let response = client
+        .get(format!(
+            "/oauth/authorize?response_type=code&client_id={}&redirect_uri={}&code_challenge=challenge\
+            &code_challenge_method=S256",
+            client_id, redirect_uri
+        ))
+        .dispatch();
+    assert_eq!(response.status(), Status::Found);
+    let location = response.headers().get_one("Location").unwrap();
+    assert_eq!(location, format!("{}?error=access_denied", redirect_uri));
+
+    let pool = pool_cloned_get(&client);
+    let grants = block_on(async move {
+        sqlx::query_scalar::<_, i64>(
+            "SELECT (SELECT COUNT(*) FROM api_tokens WHERE user_id = ?) + \
+            (SELECT COUNT(*) FROM oauth_codes WHERE user_id = ?)",
+        )
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+    });
+    assert_eq!(grants, 0);
+}
@@ -0,0 +1,91 @@
+use crate::tests::util::*;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL;
+use chrono::TimeDelta;
+use rocket::http::Status;
+use rocket::serde::json;
+
+use crate::jwt::*;
+
+const KEY_A: &str = "a:AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+const KEY_B: &str = "b:ICEiIyQlJicoKSorLC0uLzAxMjM0NTY3ODk6Ozw9Pj8=";
+
+fn claims_example(now: DateTime<Utc>) -> TokenClaims {
+    TokenClaims {
+        iss: "https://notes.example.com".into(),
+        sub: "42".into(),
+        email: "user@example.com".into(),
+        iat: now.timestamp(),
+        exp: (now + TimeDelta::minutes(5)).timestamp(),
+        act: None,
+    }
+}
+
+#[test]
+fn jwt_tokens_expire_and_are_revoked_by_rotation() {
+    let now = Utc::now();
+    let signer = TokenSigner::new(KEY_A, &[]);
+    let token = signer.sign(&claims_example(now));
+    assert_eq!(signer.verify(&token, now), Ok(claims_example(now)));
+    assert_eq!(
+        signer.verify(&token, now + TimeDelta::minutes(6)),
+        Err(TokenError::Expired)
+    );
+
+    // Tampered claims break the signature
+    let mut segments = token.split('.').map(str::to_owned).collect::<Vec<_>>();
+    let forged = TokenClaims {
+        sub: "1".into(),
+        ..claims_example(now)
+    };
+    segments[1] = BASE64_URL.encode(json::to_string(&forged).unwrap());
+    assert_eq!(signer.verify(&segments.join("."), now), Err(TokenError::Invalid));
+    assert_eq!(signer.verify("not.a-token", now), Err(TokenError::Malformed));
+
+    // Retired keys are still published until dropped
+    let rotated = TokenSigner::new(KEY_B, &[KEY_A]);
+    assert_eq!(rotated.verify(&token, now), Ok(claims_example(now)));
+    let kids = rotated.jwks()["keys"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|key| key["kid"].as_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(kids, ["b", "a"]);
+    let dropped = TokenSigner::new(KEY_B, &[]);
+    assert_eq!(dropped.verify(&token, now), Err(TokenError::UnknownKey("a".into())));
+}
+
+#[test]
+fn jwt_session_token_is_verifiable_with_the_published_keys() {
+    let client = client_tracked_get();
+    let email = email_for_session();
+    let user_id = seed_user(&client, &email);
+
+    let response = client.post("/api/session/token").dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    let request = signed_in(client.post("/api/session/token"), user_id);
+    let response = with_csrf(request).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().unwrap();
+    let token = body["token"].as_str().unwrap();
+    let claims = token_signer().verify(token, Utc::now()).unwrap();
+    assert_eq!(claims.sub, user_id.to_string());
+    assert_eq!(claims.email, email);
+    assert_eq!(claims.exp - claims.iat, token_ttl());
+    assert_eq!(claims.act, None);
+
+    let response = client.get("/.well-known/jwks.json").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("Cache-Control"), Some("public, max-age=300"));
+    let jwks = response.into_json::<json::Value>().unwrap();
+    let header = token.split('.').next().unwrap();
+    let header = json::from_slice::<json::Value>(&BASE64_URL.decode(header).unwrap()).unwrap();
+    let key = &jwks["keys"][0];
+    assert_eq!(key["kid"], header["kid"]);
+    assert_eq!(
+        (key["kty"].as_str(), key["crv"].as_str()),
+        (Some("OKP"), Some("Ed25519"))
+    );
+}
@@ -0,0 +1,80 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+use rocket::serde::json;
+
+use crate::handlers::admin::AdminToken;
+use crate::maintenance::{Maintenance, MaintenanceConfig};
+use crate::ratelimit::{RateLimitConfig, RateLimiter};
+
+#[test]
+fn maintenance_refuses_writes_only() {
+    let maintenance = Maintenance::new(MaintenanceConfig {
+        enabled: true,
+        message: Some("Upgrading the database".into()),
+        retry_after_secs: 120,
+    });
+    let client = client_tracked_build({
+        let maintenance = maintenance.clone();
+        |rocket| {
+            rocket
+                .manage(maintenance)
+                .manage(AdminToken(Some(ADMIN_TOKEN_EXAMPLE.into())))
+        }
+    });
+    let user_id = seed_user(&client, &email_for_session());
+
+    let response = signed_in(client.get("/api/v1/posts"), user_id).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let request = signed_in(client.post("/api/v1/posts"), user_id);
+    let response = with_csrf(request)
+        .json(&json::json!({ "content": "held", "variant": "note" }))
+        .dispatch();
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+    assert_eq!(response.headers().get_one("Retry-After"), Some("120"));
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["error"]["code"], "maintenance");
+    assert_eq!(body["error"]["message"], "Upgrading the database");
+    // Writes that can't be told by their route, e.g. gRPC pushes, are refused alike
+    let refusal = maintenance.write_refusal().expect("refusal");
+    assert_eq!(
+        (refusal.status, refusal.message.as_str()),
+        (Status::ServiceUnavailable, "Upgrading the database")
+    );
+
+    // The admin API stays open, e.g. to reload the settings that turn it off
+    let response = client
+        .post(format!("/api/admin/users/{}/suspend", user_id))
+        .header(admin_header())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn maintenance_config_reload_applies_the_settings() {
+    let limiter = RateLimiter::new(RateLimitConfig {
+        window_secs: 60,
+        reads: 1,
+        writes: 0,
+        auth: 0,
+    });
+    let client = client_tracked_build(|rocket| {
+        rocket
+            .manage(limiter)
+            .manage(AdminToken(Some(ADMIN_TOKEN_EXAMPLE.into())))
+    });
+    assert_eq!(client.get("/api/v1/flags").dispatch().status(), Status::Ok);
+    assert_eq!(client.get("/api/v1/flags").dispatch().status(), Status::TooManyRequests);
+
+    let response = client
+        .post("/api/admin/config/reload")
+        .header(admin_header())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().unwrap();
+    // The test environment lifts the limits
+    assert_eq!(body["rateLimits"]["reads"], 0);
+    assert_eq!(body["maintenance"]["enabled"], false);
+    assert_eq!(client.get("/api/v1/flags").dispatch().status(), Status::Ok);
+}
@@ -0,0 +1,76 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+use rocket::serde::json;
+
+use crate::merge::*;
+
+#[test]
+fn merge_takes_changes_to_different_lines_from_both_sides() {
+    let base = "title\none\ntwo\nthree\n";
+    let ours = "title\nONE\ntwo\nthree\n";
+    let theirs = "title\none\ntwo\nthree\nfour\n";
+    assert_eq!(
+        text_merge(base, ours, theirs).as_deref(),
+        Some("title\nONE\ntwo\nthree\nfour\n")
+    );
+    // The same change on both sides is kept once
+    assert_eq!(text_merge(base, ours, ours).as_deref(), Some(ours));
+    assert_eq!(text_merge(base, base, theirs).as_deref(), Some(theirs));
+    assert_eq!(text_merge("", "a\n", "").as_deref(), Some("a\n"));
+}
+
+#[test]
+fn merge_refuses_different_changes_to_the_same_lines() {
+    let base = "title\none\ntwo\n";
+    assert_eq!(text_merge(base, "title\nuno\ntwo\n", "title\neins\ntwo\n"), None);
+    // Appending different lines at the same place conflicts too
+    assert_eq!(
+        text_merge(base, "title\none\ntwo\nthree\n", "title\none\ntwo\ndrei\n"),
+        None
+    );
+}
+
+#[test]
+fn merge_mode_merges_concurrent_updates() {
+    let client = ClientAuthenticated::new();
+    let base = "Shopping\n- eggs\n- milk\n- bread\n";
+    let payload = json::json!({ "id": "merge-post", "content": base, "variant": "note" });
+    assert_success(client.post_json("/api/posts", &payload), Status::Created);
+    let response = client.get("/api/posts/merge-post");
+    let stored = response.into_json::<json::Value>().expect("post response");
+    let base_version = stored["version"].clone();
+
+    // Another device edits first, then this one edits the same base
+    let other = json::json!({ "content": "Shopping\n- eggs\n- oat milk\n- bread\n" });
+    assert_success(client.put_json("/api/posts/merge-post", &other), Status::Ok);
+    let stale = json::json!({
+        "content": "Shopping\n- eggs\n- milk\n- bread\n- jam\n",
+        "version": base_version,
+        "baseVersion": base_version,
+    });
+    let response = client.put_json("/api/posts/merge-post", &stale);
+    assert_eq!(response.status(), Status::NotFound);
+
+    let response = client.put_json("/api/posts/merge-post?merge=true", &stale);
+    assert_eq!(response.status(), Status::Ok);
+    let merged = "Shopping\n- eggs\n- oat milk\n- bread\n- jam\n";
+    let body = response.into_json::<json::Value>().expect("merge response");
+    assert_eq!(body["content"], merged);
+    let response = client.get("/api/posts/merge-post");
+    let stored = response.into_json::<json::Value>().expect("post response");
+    assert_eq!(stored["content"], merged);
+
+    // An overlapping edit comes back with both versions
+    let overlapping = json::json!({
+        "content": "Shopping\n- eggs\n- soy milk\n- bread\n",
+        "version": base_version,
+        "baseVersion": base_version,
+    });
+    let response = client.put_json("/api/posts/merge-post?merge=true", &overlapping);
+    assert_eq!(response.status(), Status::Conflict);
+    let body = response.into_json::<json::Value>().expect("conflict response");
+    assert_eq!(body["code"], "conflict");
+    assert_eq!(body["details"]["current"]["content"], merged);
+    assert_eq!(body["details"]["yours"]["content"], overlapping["content"]);
+}
@@ -0,0 +1,51 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+
+use crate::db::{DbPoolConfig, pool_probe};
+use crate::metrics::Metrics;
+
+#[test]
+fn metrics_render_prometheus_text() {
+    let m = Metrics::default();
+    m.counter_inc("requests_total", "Requests.", &[("route", "list")]);
+    m.counter_add("requests_total", "Requests.", &[("route", "list")], 2);
+    m.gauge_set("pool_size", "Pool size.", &[], 4.0);
+    m.histogram_observe("wait_seconds", "Wait.", &[], 0.003);
+
+    let text = m.render();
+    assert!(text.contains("# TYPE requests_total counter\n"));
+    assert!(text.contains("requests_total{route=\"list\"} 3\n"));
+    assert!(text.contains("pool_size 4\n"));
+    assert!(text.contains("wait_seconds_bucket{le=\"0.0025\"} 0\n"));
+    assert!(text.contains("wait_seconds_bucket{le=\"0.005\"} 1\n"));
+    assert!(text.contains("wait_seconds_count 1\n"));
+}
+
+#[test]
+fn metrics_endpoint_exposes_pool_stats() {
+    let client = client_with_admin();
+    let pool = pool_cloned_get(&client);
+    assert!(block_on(
+        async move { pool_probe(&pool, &DbPoolConfig::from_env()).await }
+    ));
+
+    let response = client.get("/metrics").dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    let response = client.get("/metrics").header(bearer("forged")).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let response = client.get("/metrics").header(admin_header()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let text = response.into_string().unwrap();
+    assert!(text.contains("# TYPE db_pool_size gauge\n"));
+    assert!(text.contains("# TYPE db_pool_idle gauge\n"));
+    assert!(text.contains("db_pool_acquire_seconds_count"));
+}
+
+#[test]
+fn metrics_endpoint_is_off_without_an_admin_token() {
+    let client = client_tracked_get();
+    let response = client.get("/metrics").header(admin_header()).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
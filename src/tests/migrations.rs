@@ -0,0 +1,68 @@
+use sqlx::{Connection, Executor, SqliteConnection};
+
+use crate::db::MIGRATOR;
+
+/// A `sqlite3 .dump` of a deployment migrated up to `20260222090000_events`, the last migration
+/// before timestamps were rewritten as RFC 3339, with a few rows in each table. Add a newer dump
+/// next to it when a migration rewrites existing rows.
+const DUMP_LEGACY: &str = include_str!("fixtures/schema_20260222090000.sql");
+
+/// The tables, indexes and triggers, apart from SQLite's and the migrator's own.
+async fn schema_of(db: &mut SqliteConnection) -> Vec<(String, String, String, String)> {
+    sqlx::query_as(
+        "SELECT type, name, tbl_name, sql FROM sqlite_master \
+        WHERE name NOT LIKE 'sqlite_%' AND name != '_sqlx_migrations' ORDER BY type, name",
+    )
+    .fetch_all(db)
+    .await
+    .unwrap()
+}
+
+async fn versions_of(db: &mut SqliteConnection) -> Vec<i64> {
+    sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success ORDER BY version")
+        .fetch_all(db)
+        .await
+        .unwrap()
+}
+
+#[rocket::async_test]
+async fn migrations_bring_legacy_databases_to_the_fresh_schema() {
+    let mut fresh = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+    MIGRATOR.run(&mut fresh).await.unwrap();
+
+    let mut legacy = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+    legacy.execute(DUMP_LEGACY).await.unwrap();
+    // The dump turns them off to load its rows in any order
+    legacy.execute("PRAGMA foreign_keys = ON").await.unwrap();
+    // Fails when a migration the dump recorded as applied has been edited since
+    MIGRATOR.run(&mut legacy).await.unwrap();
+
+    assert_eq!(schema_of(&mut legacy).await, schema_of(&mut fresh).await);
+    assert_eq!(versions_of(&mut legacy).await, versions_of(&mut fresh).await);
+    let violations = sqlx::query("PRAGMA foreign_key_check")
+        .fetch_all(&mut legacy)
+        .await
+        .unwrap();
+    assert!(violations.is_empty());
+
+    // Rows written before the data migrations were rewritten by them
+    let posts: Vec<(String, String, String)> = sqlx::query_as("SELECT id, updated_at, version FROM posts ORDER BY id")
+        .fetch_all(&mut legacy)
+        .await
+        .unwrap();
+    assert_eq!(
+        posts,
+        [
+            (
+                "post-a".to_string(),
+                "2026-02-05T08:30:00+00:00".to_string(),
+                "001770280200000-00000-".to_string()
+            ),
+            (
+                "post-b".to_string(),
+                "2026-02-06T09:00:00+00:00".to_string(),
+                "001770368400000-00000-".to_string()
+            ),
+        ]
+    );
+}
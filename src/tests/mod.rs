@@ -1,3 +1,68 @@
+pub mod access;
+pub mod admin;
+pub mod api;
+#[cfg(feature = "embed")]
+pub mod assets;
+pub mod attachments;
+pub mod authz;
+pub mod cache;
+pub mod calendar;
+pub mod chaos;
+pub mod client_info;
+pub mod clock;
+pub mod coalesce;
+pub mod comments;
+pub mod config;
+pub mod cron;
+pub mod crypto;
+pub mod csrf;
+pub mod db;
+pub mod email_policy;
+pub mod emails;
+pub mod error;
+pub mod events;
+pub mod filter;
+pub mod flags;
+pub mod fuzz;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hashing;
+pub mod health;
+pub mod hlc;
+pub mod impersonation;
+pub mod jwt;
+pub mod maintenance;
+pub mod merge;
+pub mod metrics;
+pub mod migrations;
+pub mod oauth;
+pub mod orgs;
+pub mod panics;
+pub mod payload;
 pub mod posts;
+pub mod posts_lww;
+pub mod previews;
+pub mod ratelimit;
+pub mod reminders;
+pub mod replication;
+pub mod retry;
+pub mod scan;
+pub mod scope;
+#[cfg(feature = "secrets")]
+pub mod secrets;
 pub mod session;
+pub mod shares;
+pub mod signing;
+pub mod slowquery;
+pub mod sms;
+pub mod snapshots;
+pub mod summary;
+pub mod telemetry;
+pub mod templates;
+pub mod timeout;
+pub mod tls;
+pub mod users;
 pub mod util;
+pub mod variants;
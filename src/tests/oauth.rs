@@ -0,0 +1,166 @@
+use crate::tests::util::*;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL;
+use rocket::http::{ContentType, Method, Status};
+use rocket::local::blocking::Client;
+use rocket::serde::json;
+use sha2::{Digest, Sha256};
+
+use crate::oauth::{pkce_verify, redirect_uri_is_valid};
+
+const REDIRECT_URI: &str = "com.example.notes:/oauth";
+const VERIFIER: &str = "0123456789abcdefghijklmnopqrstuvwxyz-._~0123456789";
+
+fn challenge() -> String {
+    BASE64_URL.encode(Sha256::digest(VERIFIER.as_bytes()))
+}
+
+/// Registers a client and returns its id.
+fn oauth_client_create(client: &Client, scopes: &str) -> String {
+    let response = client
+        .post("/api/admin/oauth/clients")
+        .header(admin_header())
+        .json(&json::json!({ "name": "Notes for iOS", "redirectUris": [REDIRECT_URI], "scopes": scopes }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Created);
+    let body = response.into_json::<json::Value>().unwrap();
+    body["id"].as_str().unwrap().to_owned()
+}
+
+/// Authorizes the client as the user and returns the `Location` it redirects to.
+fn authorize(client: &Client, user_id: Option<i64>, client_id: &str, scope: &str) -> String {
+    let uri = format!(
+        "/oauth/authorize?response_type=code&client_id={}&redirect_uri={}&scope={}&state=xyz\
+        &code_challenge={}&code_challenge_method=S256",
+        client_id,
+        REDIRECT_URI,
+        scope.replace(' ', "+"),
+        challenge()
+    );
+    let request = client.get(uri);
+    let request = match user_id {
+        Some(user_id) => signed_in(request, user_id),
+        None => request,
+    };
+    let response = request.dispatch();
+    assert_eq!(response.status(), Status::Found);
+    response.headers().get_one("Location").unwrap().to_owned()
+}
+
+fn code_of(location: &str) -> String {
+    let query = location.split_once('?').unwrap().1;
+    let code = query.split('&').find_map(|pair| pair.strip_prefix("code="));
+    code.expect("code in redirect").to_owned()
+}
+
+fn token_request(client: &Client, form: &str) -> (Status, json::Value) {
+    let response = client
+        .post("/oauth/token")
+        .header(ContentType::Form)
+        .body(form)
+        .dispatch();
+    assert_eq!(response.headers().get_one("Cache-Control"), Some("no-store"));
+    (response.status(), response.into_json().unwrap_or_default())
+}
+
+fn api_status(client: &Client, method: Method, uri: &str, token: &str) -> Status {
+    client
+        .req(method, uri)
+        .header(bearer(token))
+        .json(&json::json!({ "content": "from the app", "variant": "note" }))
+        .dispatch()
+        .status()
+}
+
+#[test]
+fn oauth_redirect_uris_and_pkce_are_validated() {
+    assert!(redirect_uri_is_valid("https://app.example.com/callback"));
+    assert!(redirect_uri_is_valid(REDIRECT_URI));
+    assert!(!redirect_uri_is_valid("/callback"));
+    assert!(!redirect_uri_is_valid("https://app.example.com/#callback"));
+    assert!(pkce_verify(VERIFIER, &challenge()));
+    assert!(!pkce_verify("short", &BASE64_URL.encode(Sha256::digest(b"short"))));
+}
+
+#[test]
+fn oauth_authorization_code_flow_issues_scoped_tokens() {
+    let client = client_with_admin();
+    let user_id = seed_user(&client, &email_for_session());
+    let client_id = oauth_client_create(&client, "posts:read account:read");
+
+    // Users that aren't signed in go through the login of the web app first
+    let location = authorize(&client, None, &client_id, "posts:read");
+    assert!(location.contains("/login?next=%2Foauth%2Fauthorize%3F"), "{}", location);
+    // Scopes beyond the client's are refused back to the app
+    let location = authorize(&client, Some(user_id), &client_id, "posts:write");
+    assert_eq!(location, format!("{}?error=invalid_scope&state=xyz", REDIRECT_URI));
+
+    let location = authorize(&client, Some(user_id), &client_id, "posts:read account:read");
+    assert!(location.starts_with(REDIRECT_URI) && location.ends_with("&state=xyz"));
+    let code = code_of(&location);
+    let exchange = |code: &str, verifier: &str| {
+        let form = format!(
+            "grant_type=authorization_code&client_id={}&code={}&redirect_uri={}&code_verifier={}",
+            client_id, code, REDIRECT_URI, verifier
+        );
+        token_request(&client, &form)
+    };
+    let (status, body) = exchange(&code, &VERIFIER.replace('0', "1"));
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(body["error"], "invalid_grant");
+    // The code was spent by the failed attempt
+    let (status, _) = exchange(&code, VERIFIER);
+    assert_eq!(status, Status::BadRequest);
+
+    let code = code_of(&authorize(
+        &client,
+        Some(user_id),
+        &client_id,
+        "posts:read account:read",
+    ));
+    let (status, body) = exchange(&code, VERIFIER);
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["token_type"], "Bearer");
+    assert_eq!(body["scope"], "account:read posts:read");
+    let access = body["access_token"].as_str().unwrap().to_owned();
+    let refresh = body["refresh_token"].as_str().unwrap().to_owned();
+
+    assert_eq!(api_status(&client, Method::Get, "/api/posts", &access), Status::Ok);
+    assert_eq!(
+        api_status(&client, Method::Post, "/api/posts", &access),
+        Status::Forbidden
+    );
+    assert_eq!(api_status(&client, Method::Get, "/api/users/me", &access), Status::Ok);
+    assert_eq!(
+        api_status(&client, Method::Put, "/api/users/me/preferences", &access),
+        Status::Forbidden
+    );
+    let response = client.get("/api/session/").header(bearer(&access)).dispatch();
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["id"], user_id);
+
+    // Refreshing replaces both tokens
+    let form = format!(
+        "grant_type=refresh_token&client_id={}&refresh_token={}",
+        client_id, refresh
+    );
+    let (status, body) = token_request(&client, &form);
+    assert_eq!(status, Status::Ok);
+    let access_new = body["access_token"].as_str().unwrap().to_owned();
+    assert_eq!(
+        api_status(&client, Method::Get, "/api/posts", &access),
+        Status::Unauthorized
+    );
+    assert_eq!(api_status(&client, Method::Get, "/api/posts", &access_new), Status::Ok);
+    let (status, _) = token_request(&client, &form);
+    assert_eq!(status, Status::BadRequest);
+
+    let uri = format!("/api/admin/oauth/clients/{}", client_id);
+    let response = client.delete(uri).header(admin_header()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        api_status(&client, Method::Get, "/api/posts", &access_new),
+        Status::Unauthorized
+    );
+}
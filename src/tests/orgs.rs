@@ -0,0 +1,92 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+use rocket::local::blocking::{Client, LocalResponse};
+use rocket::serde::json;
+
+fn post_as<'c>(client: &'c Client, user_id: i64, uri: &'c str, body: &json::Value) -> LocalResponse<'c> {
+    with_csrf(signed_in(client.post(uri), user_id)).json(body).dispatch()
+}
+
+fn put_as<'c>(client: &'c Client, user_id: i64, uri: &'c str, body: &json::Value) -> LocalResponse<'c> {
+    with_csrf(signed_in(client.put(uri), user_id)).json(body).dispatch()
+}
+
+fn get_as<'c>(client: &'c Client, user_id: i64, uri: &'c str) -> LocalResponse<'c> {
+    signed_in(client.get(uri), user_id).dispatch()
+}
+
+#[test]
+fn orgs_share_posts_with_members() {
+    let client = client_tracked_get();
+    let owner = seed_user(&client, &email_for_session());
+    let member_email = email_for_session();
+    let member = seed_user(&client, &member_email);
+    let outsider = seed_user(&client, &email_for_session());
+
+    let response = post_as(&client, owner, "/api/orgs", &json::json!({ "name": "Team" }));
+    assert_eq!(response.status(), Status::Created);
+    let org_id = response.into_json::<json::Value>().unwrap()["id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let members_uri = format!("/api/orgs/{}/members", org_id);
+    let posts_uri = format!("/api/orgs/{}/posts", org_id);
+
+    // Only members can share posts with the organization or list them
+    let shared = json::json!({ "id": "org-post", "content": "Shared", "variant": "note", "orgId": org_id });
+    let response = post_as(&client, outsider, "/api/posts", &shared);
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    assert_eq!(get_as(&client, outsider, &posts_uri).status(), Status::NotFound);
+
+    let response = post_as(&client, owner, "/api/posts", &shared);
+    assert_eq!(response.status(), Status::Created);
+    let private = json::json!({ "id": "own-post", "content": "Private", "variant": "note" });
+    let response = post_as(&client, owner, "/api/posts", &private);
+    assert_eq!(response.status(), Status::Created);
+
+    let response = put_as(
+        &client,
+        owner,
+        &members_uri,
+        &json::json!({ "email": member_email, "role": "member" }),
+    );
+    assert_eq!(response.status(), Status::Created);
+
+    let response = get_as(&client, member, &members_uri);
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().unwrap();
+    let roles = body["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| (m["userId"].as_i64().unwrap(), m["role"].as_str().unwrap().to_owned()))
+        .collect::<Vec<_>>();
+    assert_eq!(roles, vec![(owner, "owner".to_owned()), (member, "member".to_owned())]);
+
+    let response = get_as(&client, member, &posts_uri);
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().unwrap();
+    let items = body["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["id"], json::json!("org-post"));
+    assert_eq!(items[0]["content"], json::json!("Shared"));
+    assert_eq!(items[0]["orgId"], json::json!(org_id));
+
+    // Plain members can't manage the organization, and the last owner can't leave
+    let response = put_as(
+        &client,
+        member,
+        &members_uri,
+        &json::json!({ "email": member_email, "role": "admin" }),
+    );
+    assert_eq!(response.status(), Status::Forbidden);
+    let owner_uri = format!("{}/{}", members_uri, owner);
+    let response = with_csrf(signed_in(client.delete(owner_uri.as_str()), owner)).dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+
+    let member_uri = format!("{}/{}", members_uri, member);
+    let response = with_csrf(signed_in(client.delete(member_uri.as_str()), member)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(get_as(&client, member, &posts_uri).status(), Status::NotFound);
+}
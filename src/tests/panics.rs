@@ -0,0 +1,23 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+use rocket::serde::json;
+
+use crate::panics::panic_routes;
+
+#[get("/boom")]
+fn boom() -> &'static str {
+    panic!("boom");
+}
+
+#[test]
+fn panics_become_json_500s() {
+    let client = client_tracked_build(|rocket| rocket.mount("/test", panic_routes(routes![boom])));
+
+    let response = client.get("/test/boom").dispatch();
+    assert_eq!(response.status(), Status::InternalServerError);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["code"], json::json!("internal"));
+    assert_eq!(body["message"], json::json!("Internal Server Error"));
+    assert!(body["requestId"].as_str().is_some_and(|id| !id.is_empty()));
+}
@@ -0,0 +1,43 @@
+use crate::tests::util::*;
+
+use rocket::http::{ContentType, Status};
+use rocket::serde::json;
+
+#[test]
+fn payload_posts_sync_in_msgpack() {
+    let client = ClientAuthenticated::new();
+    let now = Utc::now();
+    let posts = json::json!([
+        { "id": "msgpack-a", "createdAt": now, "content": "First", "updatedAt": now, "variant": "note" },
+        { "id": "msgpack-b", "createdAt": now, "content": "Second", "updatedAt": now, "variant": "note" },
+    ]);
+    let response = client.post_msgpack("/api/v1/posts/upsert-many", &posts);
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::MsgPack));
+
+    let response = client.get_msgpack("/api/v1/posts?limit=10");
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::MsgPack));
+    let body = response.into_msgpack::<json::Value>().expect("msgpack response");
+    let mut contents = body["data"]
+        .as_array()
+        .expect("posts")
+        .iter()
+        .map(|post| post["content"].as_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    contents.sort();
+    assert_eq!(contents, ["First", "Second"]);
+    assert_eq!(body["meta"]["pagination"]["hasMore"], false);
+
+    // Clients that don't ask for MessagePack keep getting JSON
+    let response = client.get("/api/v1/posts/msgpack-a");
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+}
+
+#[test]
+fn payload_invalid_msgpack_is_refused() {
+    let client = ClientAuthenticated::new();
+    // A MessagePack string where a post list is expected
+    let response = client.post_msgpack("/api/posts/upsert-many", &"not a list");
+    assert_eq!(response.status(), Status::BadRequest);
+}
@@ -1,9 +1,10 @@
 use crate::tests::util::*;
 
-use chrono::{DateTime, Duration, Timelike, Utc};
+use chrono::{DateTime, Duration, Utc};
 use rocket::http::Status;
-use rocket::serde::{Deserialize, Serialize};
+use rocket::serde::{Deserialize, Serialize, json};
 
+use crate::clock::Clock;
 use crate::db;
 
 const POSTS_BASE: &str = "/api/posts";
@@ -52,7 +53,7 @@ fn posts_list_filter_after() {
     // Ensure the list is initially empty
     assert!(fetch_posts(&client, POSTS_BASE).items.is_empty());
 
-    let start = Utc::now().with_nanosecond(0).unwrap();
+    let start = client.now();
     let mut timestamps = Vec::new();
 
     for offset in 0..3 {
@@ -66,7 +67,7 @@ fn posts_list_filter_after() {
         };
         // Insert posts with different timestamps
         assert_success(client.post_json(POSTS_BASE, &payload), Status::Created);
-        timestamps.push(stamp.naive_utc());
+        timestamps.push(stamp);
     }
 
     // Verify all posts are listed
@@ -76,7 +77,7 @@ fn posts_list_filter_after() {
     assert!(!list.has_more);
 
     let threshold = timestamps[1];
-    let filtered_uri = format!("{}?after={}", POSTS_BASE, threshold.to_rfc3339());
+    let filtered_uri = format!("{}?after={}", POSTS_BASE, threshold.format("%Y-%m-%dT%H:%M:%SZ"));
     let filtered = fetch_posts(&client, &filtered_uri);
     let expected = timestamps.iter().filter(|&&ts| ts >= threshold).count();
     // Ensure the filtered list contains only posts after the threshold
@@ -85,10 +86,73 @@ fn posts_list_filter_after() {
     assert!(filtered.items.iter().all(|post| post.updated_at >= threshold));
 }
 
+#[test]
+fn posts_list_time_range() {
+    let client = ClientAuthenticated::new();
+    for day in 1..=3 {
+        let stamp = format!("2024-01-0{}T00:00:00Z", day).parse::<DateTime<Utc>>().unwrap();
+        let payload = CreatePostPayload {
+            id: Some(format!("range-{}", day)),
+            created_at: Some(stamp),
+            content: format!("Day {}", day),
+            updated_at: Some(stamp),
+            variant: "note".into(),
+        };
+        assert_success(client.post_json(POSTS_BASE, &payload), Status::Created);
+    }
+    let count = |query: &str| fetch_posts(&client, &format!("{}?{}", POSTS_BASE, query)).items.len();
+
+    // `after` is inclusive and accepts dates and epoch milliseconds; `before` is exclusive
+    assert_eq!(count("after=2024-01-02"), 2);
+    assert_eq!(count("after=1704153600000"), 2);
+    assert_eq!(count("before=2024-01-02T00:00:00Z"), 1);
+    assert_eq!(count("after=2024-01-01&before=2024-01-03"), 2);
+
+    for query in [
+        "after=yesterday",
+        "after=2024-13-01",
+        "before=",
+        "after=2024-01-03&before=2024-01-01",
+    ] {
+        let response = client.get(&format!("{}?{}", POSTS_BASE, query));
+        assert_eq!(response.status(), Status::UnprocessableEntity, "{}", query);
+    }
+}
+
+#[test]
+fn posts_list_pages_newest_first_with_before() {
+    let client = ClientAuthenticated::new();
+    let start = "2024-01-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+    // Created out of order, so that insertion order can't pass for the newest-first order
+    for offset in [2, 0, 4, 1, 3] {
+        let stamp = start + Duration::hours(offset);
+        let payload = CreatePostPayload {
+            id: Some(format!("page-{}", offset)),
+            created_at: Some(stamp),
+            content: format!("Page post {}", offset),
+            updated_at: Some(stamp),
+            variant: "note".into(),
+        };
+        assert_success(client.post_json(POSTS_BASE, &payload), Status::Created);
+    }
+
+    let mut ids = Vec::new();
+    let mut uri = format!("{}?limit=2", POSTS_BASE);
+    loop {
+        let page = fetch_posts(&client, &uri);
+        let Some(last) = page.items.last() else {
+            break;
+        };
+        uri = format!("{}?limit=2&before={}", POSTS_BASE, last.updated_at.format("%Y-%m-%dT%H:%M:%SZ"));
+        ids.extend(page.items.into_iter().map(|post| post.id));
+    }
+    assert_eq!(ids, ["page-4", "page-3", "page-2", "page-1", "page-0"]);
+}
+
 #[test]
 fn posts_read_by_id() {
     let client = ClientAuthenticated::new();
-    let now = Utc::now().with_nanosecond(0).unwrap();
+    let now = client.now();
 
     let payload = CreatePostPayload {
         id: Some("read-test".into()),
@@ -115,7 +179,7 @@ fn posts_read_by_id() {
 #[test]
 fn posts_create_upsert() {
     let client = ClientAuthenticated::new();
-    let now = Utc::now().with_nanosecond(0).unwrap();
+    let now = client.now();
 
     let id = "create-upsert";
     let initial_payload = CreatePostPayload {
@@ -161,13 +225,13 @@ fn posts_create_upsert() {
     let updated_post = fetch_post(&client, &read_uri);
     // Ensure the post was updated with newer content
     assert_eq!(updated_post.content, "Updated content");
-    assert_eq!(updated_post.updated_at, (now + Duration::seconds(30)).naive_utc());
+    assert_eq!(updated_post.updated_at, now + Duration::seconds(30));
 }
 
 #[test]
 fn posts_update_by_id() {
     let client = ClientAuthenticated::new();
-    let now = Utc::now().with_nanosecond(0).unwrap();
+    let now = client.now();
     let id = "update-me";
 
     let payload = CreatePostPayload {
@@ -193,7 +257,7 @@ fn posts_update_by_id() {
     let updated = fetch_post(&client, &update_uri);
     // Ensure the post was updated with the new content and timestamp
     assert_eq!(updated.content, "After update");
-    assert_eq!(updated.updated_at, update_at.naive_utc());
+    assert_eq!(updated.updated_at, update_at);
 
     let stale_payload = UpdatePostPayload {
         content: "Stale".into(),
@@ -209,10 +273,39 @@ fn posts_update_by_id() {
     assert_eq!(response.status(), Status::NotFound);
 }
 
+#[test]
+fn posts_content_over_limit_is_refused() {
+    let client = ClientAuthenticated::new();
+    let payload = CreatePostPayload {
+        id: Some("too-large".into()),
+        created_at: None,
+        content: "x".repeat(65537),
+        updated_at: None,
+        variant: "note".into(),
+    };
+    let response = client.post_json(POSTS_BASE, &payload);
+    assert_eq!(response.status(), Status::PayloadTooLarge);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["code"], "payload_too_large");
+    assert_eq!(body["details"], json::json!({ "size": 65537, "maxBytes": 65536 }));
+
+    let payload = CreatePostPayload {
+        content: "x".repeat(65536),
+        ..payload
+    };
+    assert_success(client.post_json(POSTS_BASE, &payload), Status::Created);
+    let update = UpdatePostPayload {
+        content: "x".repeat(65537),
+        updated_at: None,
+    };
+    let response = client.put_json(&format!("{}/too-large", POSTS_BASE), &update);
+    assert_eq!(response.status(), Status::PayloadTooLarge);
+}
+
 #[test]
 fn posts_delete_all() {
     let client = ClientAuthenticated::new();
-    let now = Utc::now().with_nanosecond(0).unwrap();
+    let now = client.now();
 
     for offset in 0..2 {
         let payload = CreatePostPayload {
@@ -235,7 +328,7 @@ fn posts_delete_all() {
 #[test]
 fn posts_delete_by_id() {
     let client = ClientAuthenticated::new();
-    let now = Utc::now().with_nanosecond(0).unwrap();
+    let now = client.now();
 
     let payload = CreatePostPayload {
         id: Some("delete-one".into()),
@@ -259,7 +352,7 @@ fn posts_delete_by_id() {
 #[test]
 fn posts_upsert_many() {
     let client = ClientAuthenticated::new();
-    let now = Utc::now().with_nanosecond(0).unwrap();
+    let now = client.now();
 
     let upsert_uri = format!("{}/upsert-many", POSTS_BASE);
     // Initial bulk insert for two posts
@@ -287,7 +380,7 @@ fn posts_upsert_many() {
     assert_eq!(list.items.len(), 2);
 
     let mut updated_payloads = payloads.clone();
-    let newer = (now + Duration::seconds(30)).with_nanosecond(0).unwrap();
+    let newer = now + Duration::seconds(30);
     updated_payloads[0].content = "bulk one updated".into();
     updated_payloads[0].updated_at = newer;
 
@@ -297,9 +390,9 @@ fn posts_upsert_many() {
     let updated = fetch_post(&client, &format!("{}/{}", POSTS_BASE, updated_payloads[0].id));
     // Ensure the post was updated with newer content
     assert_eq!(updated.content, "bulk one updated");
-    assert_eq!(updated.updated_at, newer.naive_utc());
+    assert_eq!(updated.updated_at, newer);
 
-    let older = (now - Duration::seconds(30)).with_nanosecond(0).unwrap();
+    let older = now - Duration::seconds(30);
     let stale_payloads = vec![UpsertPostPayload {
         id: updated_payloads[0].id.clone(),
         created_at: now,
@@ -313,7 +406,494 @@ fn posts_upsert_many() {
     let skipped = fetch_post(&client, &format!("{}/{}", POSTS_BASE, updated_payloads[0].id));
     // Ensure the post was not updated with older content
     assert_eq!(skipped.content, "bulk one updated");
-    assert_eq!(skipped.updated_at, newer.naive_utc());
+    assert_eq!(skipped.updated_at, newer);
+}
+
+#[test]
+fn posts_favorite_and_filter() {
+    let client = ClientAuthenticated::new();
+    let now = client.now();
+
+    for id in ["fav-starred", "fav-plain"] {
+        let payload = CreatePostPayload {
+            id: Some(id.into()),
+            created_at: Some(now),
+            content: format!("Favorite {}", id),
+            updated_at: Some(now),
+            variant: "note".into(),
+        };
+        assert_success(client.post_json(POSTS_BASE, &payload), Status::Created);
+    }
+
+    let favorite_uri = format!("{}/{}/favorite", POSTS_BASE, "fav-starred");
+    // Favoriting is idempotent
+    assert_success(client.put_json(&favorite_uri, &()), Status::Ok);
+    assert_success(client.put_json(&favorite_uri, &()), Status::Ok);
+
+    let starred = fetch_post(&client, &format!("{}/{}", POSTS_BASE, "fav-starred"));
+    assert!(starred.favorited);
+    // Favoriting does not count as an edit
+    assert_eq!(starred.updated_at, now);
+
+    let favorited = fetch_posts(&client, &format!("{}?favorited=true", POSTS_BASE));
+    assert_eq!(favorited.items.len(), 1);
+    assert_eq!(favorited.items[0].id, "fav-starred");
+
+    let others = fetch_posts(&client, &format!("{}?favorited=false", POSTS_BASE));
+    assert_eq!(others.items.len(), 1);
+    assert_eq!(others.items[0].id, "fav-plain");
+    assert!(!others.items[0].favorited);
+
+    assert_success(client.delete(&favorite_uri), Status::Ok);
+    assert!(fetch_posts(&client, &format!("{}?favorited=true", POSTS_BASE)).items.is_empty());
+
+    let missing_uri = format!("{}/{}/favorite", POSTS_BASE, "missing-favorite");
+    // Ensure favoriting a non-existent post returns 404
+    assert_eq!(client.put_json(&missing_uri, &()).status(), Status::NotFound);
+}
+
+#[test]
+fn posts_list_filter_expression() {
+    let client = ClientAuthenticated::new();
+    let old = client.now() - Duration::days(30);
+    for (id, variant, updated_at) in [
+        ("expr-old-todo", "todo", old),
+        ("expr-new-todo", "todo", old + Duration::days(20)),
+        ("expr-new-note", "note", old + Duration::days(20)),
+    ] {
+        let payload = CreatePostPayload {
+            id: Some(id.into()),
+            created_at: Some(old),
+            content: id.into(),
+            updated_at: Some(updated_at),
+            variant: variant.into(),
+        };
+        assert_success(client.post_json(POSTS_BASE, &payload), Status::Created);
+    }
+    let uri = |filter: &str| {
+        format!(
+            "{}?filter={}",
+            POSTS_BASE,
+            filter.replace(' ', "%20").replace('"', "%22")
+        )
+    };
+
+    let since = (old + Duration::days(10)).format("%Y-%m-%d");
+    let posts = fetch_posts(
+        &client,
+        &uri(&format!(r#"variant eq "todo" and updatedAt gt "{}""#, since)),
+    );
+    assert_eq!(
+        posts.items.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(),
+        ["expr-new-todo"]
+    );
+
+    let posts = fetch_posts(&client, &uri(r#"not (variant eq "todo") or id eq "expr-old-todo""#));
+    let mut ids = posts.items.iter().map(|p| p.id.as_str()).collect::<Vec<_>>();
+    ids.sort();
+    assert_eq!(ids, ["expr-new-note", "expr-old-todo"]);
+
+    // Unknown fields and values of the wrong type are rejected instead of ignored
+    for filter in [r#"content eq "x""#, "variant eq 1", r#"variant eq "todo" or"#] {
+        assert_eq!(client.get(&uri(filter)).status(), Status::UnprocessableEntity);
+    }
+}
+
+#[test]
+fn posts_list_json_path() {
+    let client = ClientAuthenticated::new();
+    for (id, content) in [
+        ("json-open", r#"{"done": false, "weight": 2, "tags": ["home"]}"#),
+        ("json-done", r#"{"done": true, "weight": 5}"#),
+        ("json-zero", r#"{"done": 0}"#),
+        ("json-text", "done: false"),
+    ] {
+        let payload = json::json!({ "id": id, "content": content, "variant": "todo" });
+        assert_success(client.post_json(POSTS_BASE, &payload), Status::Created);
+    }
+    let encode = |json_path: &str| {
+        json_path
+            .bytes()
+            .map(|b| match b.is_ascii_alphanumeric() || b == b'.' || b == b'_' {
+                true => (b as char).to_string(),
+                false => format!("%{:02X}", b),
+            })
+            .collect::<String>()
+    };
+    let ids = |json_paths: &[&str]| {
+        let query = json_paths
+            .iter()
+            .map(|json_path| format!("json_path={}", encode(json_path)))
+            .collect::<Vec<_>>()
+            .join("&");
+        let posts = fetch_posts(&client, &format!("{}?{}", POSTS_BASE, query));
+        let mut ids = posts.items.into_iter().map(|p| p.id).collect::<Vec<_>>();
+        ids.sort();
+        ids
+    };
+
+    // Only JSON booleans match, not 0 or text that looks like JSON
+    assert_eq!(ids(&["$.done == false"]), ["json-open"]);
+    assert_eq!(ids(&["$.done != false"]), ["json-done", "json-zero"]);
+    assert_eq!(ids(&["$.weight > 1", "$.weight < 5"]), ["json-open"]);
+    assert_eq!(ids(&[r#"$.tags[0] == "home""#]), ["json-open"]);
+    assert!(ids(&["$.missing == null"]).is_empty());
+
+    let response = client.get(&format!("{}?json_path={}", POSTS_BASE, encode("$.done")));
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+}
+
+#[test]
+fn posts_list_titles_without_content() {
+    let client = ClientAuthenticated::new();
+    let payload = json::json!({
+        "id": "titled",
+        "content": "# Trip to *Lisbon*\nFlights booked.\n\n- Pack [charger](https://example.com)",
+        "variant": "note",
+    });
+    assert_success(client.post_json(POSTS_BASE, &payload), Status::Created);
+
+    let post = fetch_post(&client, &format!("{}/titled", POSTS_BASE));
+    assert_eq!(post.title.as_deref(), Some("Trip to Lisbon"));
+    assert_eq!(post.excerpt.as_deref(), Some("Flights booked. Pack charger"));
+
+    let response = client.get(&format!("{}?content=false", POSTS_BASE));
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().unwrap();
+    let item = &body["items"][0];
+    assert_eq!(item["title"], "Trip to Lisbon");
+    assert!(item.get("content").is_none());
+
+    // Updates keep them in step with the content
+    let update = json::json!({ "content": "Trip to Porto" });
+    assert_success(client.put_json(&format!("{}/titled", POSTS_BASE), &update), Status::Ok);
+    let post = fetch_post(&client, &format!("{}/titled", POSTS_BASE));
+    assert_eq!(post.title.as_deref(), Some("Trip to Porto"));
+    assert_eq!(post.excerpt.as_deref(), Some(""));
+}
+
+#[test]
+fn posts_sparse_fieldsets() {
+    let client = ClientAuthenticated::new();
+    let payload = json::json!({ "id": "sparse", "content": "Plan\nBook the venue", "variant": "note" });
+    assert_success(client.post_json(POSTS_BASE, &payload), Status::Created);
+    let keys = |body: &json::Value| {
+        let mut keys = body.as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+        keys
+    };
+
+    let response = client.get(&format!("{}?fields=updatedAt,excerpt", POSTS_BASE));
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().unwrap();
+    let item = &body["items"][0];
+    assert_eq!(keys(item), ["excerpt", "id", "updatedAt"]);
+    assert_eq!(item["excerpt"], "Book the venue");
+    assert!(item["updatedAt"].is_string());
+
+    let response = client.get(&format!("{}/sparse?fields=title,favorited", POSTS_BASE));
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body, json::json!({ "id": "sparse", "title": "Plan", "favorited": false }));
+
+    for uri in [
+        format!("{}?fields=id,userId", POSTS_BASE),
+        format!("{}/sparse?fields=content;DROP", POSTS_BASE),
+    ] {
+        assert_eq!(client.get(&uri).status(), Status::UnprocessableEntity);
+    }
+}
+
+#[test]
+fn posts_hierarchy() {
+    let client = ClientAuthenticated::new();
+    let create = |id: &str, parent_id: Option<&str>| {
+        let payload = json::json!({ "id": id, "parentId": parent_id, "content": id, "variant": "note" });
+        client.post_json(POSTS_BASE, &payload)
+    };
+
+    assert_success(create("folder", None), Status::Created);
+    assert_success(create("sub", Some("folder")), Status::Created);
+    assert_success(create("leaf", Some("sub")), Status::Created);
+    // Ensure parents must exist
+    assert_eq!(create("orphan", Some("missing")).status(), Status::UnprocessableEntity);
+    // Ensure a post cannot be moved under its own descendant
+    assert_eq!(create("folder", Some("leaf")).status(), Status::UnprocessableEntity);
+
+    let children = fetch_posts(&client, &format!("{}?parent=folder", POSTS_BASE));
+    assert_eq!(children.items.len(), 1);
+    assert_eq!(children.items[0].id, "sub");
+    let top_level = fetch_posts(&client, &format!("{}?parent=", POSTS_BASE));
+    assert_eq!(top_level.items.len(), 1);
+    assert_eq!(top_level.items[0].id, "folder");
+
+    // Deleting re-parents the children by default
+    assert_success(client.delete(&format!("{}/sub", POSTS_BASE)), Status::Ok);
+    let leaf = fetch_post(&client, &format!("{}/leaf", POSTS_BASE));
+    assert_eq!(leaf.parent_id.as_deref(), Some("folder"));
+
+    // Cascading deletes the whole subtree
+    assert_success(client.delete(&format!("{}/folder?children=cascade", POSTS_BASE)), Status::Ok);
+    assert!(fetch_posts(&client, POSTS_BASE).items.is_empty());
+}
+
+#[test]
+fn posts_manual_order() {
+    let client = ClientAuthenticated::new();
+    for (id, position) in [("first", 1.0), ("second", 2.0), ("third", 3.0)] {
+        let payload = json::json!({ "id": id, "content": id, "variant": "task", "position": position });
+        assert_success(client.post_json(POSTS_BASE, &payload), Status::Created);
+    }
+    let order = |client: &ClientAuthenticated| {
+        let list = fetch_posts(client, &format!("{}?sort=manual", POSTS_BASE));
+        list.items.into_iter().map(|post| post.id).collect::<Vec<_>>()
+    };
+    assert_eq!(order(&client), ["first", "second", "third"]);
+
+    let move_uri = format!("{}/third/move", POSTS_BASE);
+    let between = json::json!({ "after": "first", "before": "second" });
+    assert_success(client.post_json(&move_uri, &between), Status::Ok);
+    assert_eq!(order(&client), ["first", "third", "second"]);
+
+    // Keeps ordering correctly once repeated moves exhaust the room between two posts
+    for _ in 0..60 {
+        let between = json::json!({ "after": "first", "before": "third" });
+        assert_success(client.post_json(&format!("{}/second/move", POSTS_BASE), &between), Status::Ok);
+        let between = json::json!({ "after": "first", "before": "second" });
+        assert_success(client.post_json(&move_uri, &between), Status::Ok);
+    }
+    assert_eq!(order(&client), ["first", "third", "second"]);
+
+    // Moving without neighbors puts the post last
+    assert_success(client.post_json(&format!("{}/first/move", POSTS_BASE), &json::json!({})), Status::Ok);
+    assert_eq!(order(&client), ["third", "second", "first"]);
+
+    let inverted = json::json!({ "after": "first", "before": "third" });
+    assert_eq!(client.post_json(&move_uri, &inverted).status(), Status::UnprocessableEntity);
+}
+
+#[test]
+fn posts_upsert_many_dedupe() {
+    let client = ClientAuthenticated::new();
+    let now = client.now();
+    let post = |id: &str, content: &str| UpsertPostPayload {
+        id: id.into(),
+        created_at: now,
+        content: content.into(),
+        updated_at: now,
+        variant: "note".into(),
+    };
+    let upsert_uri = format!("{}/upsert-many?dedupe=true", POSTS_BASE);
+
+    let first_import = vec![post("import-1", "Alpha"), post("import-2", "Beta")];
+    assert_success(client.post_json(&upsert_uri, &first_import), Status::Ok);
+
+    // Re-running the import with fresh IDs skips the existing content and in-batch repeats
+    let second_import = vec![
+        post("import-3", "Alpha"),
+        post("import-4", "Gamma"),
+        post("import-5", "Gamma"),
+    ];
+    let response = client.post_json(&upsert_uri, &second_import);
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().expect("upsert response");
+    assert_eq!(body["skipped"], json::json!(["import-3", "import-5"]));
+
+    let ids = fetch_posts(&client, POSTS_BASE)
+        .items
+        .into_iter()
+        .map(|post| post.id)
+        .collect::<std::collections::HashSet<_>>();
+    assert_eq!(ids, ["import-1", "import-2", "import-4"].map(String::from).into());
+
+    // Updating a post with its own content is not a duplicate
+    let mut update = post("import-1", "Alpha");
+    update.updated_at = now + Duration::seconds(30);
+    let response = client.post_json(&upsert_uri, &vec![update]);
+    let body = response.into_json::<json::Value>().expect("upsert response");
+    assert_eq!(body["skipped"], json::json!([]));
+}
+
+#[test]
+fn posts_batches_with_partial_success() {
+    let client = ClientAuthenticated::new();
+    let payloads = json::json!([
+        { "id": "batch-ok", "createdAt": Utc::now(), "content": "Fine", "updatedAt": Utc::now(), "variant": "note" },
+        {
+            "id": "batch-orphan",
+            "parentId": "missing",
+            "createdAt": Utc::now(),
+            "content": "Orphan",
+            "updatedAt": Utc::now(),
+            "variant": "note"
+        },
+    ]);
+
+    // Without partial success the invalid post fails the whole batch
+    let response = client.post_json(&format!("{}/upsert-many", POSTS_BASE), &payloads);
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    assert!(fetch_posts(&client, POSTS_BASE).items.is_empty());
+
+    let response = client.post_json(&format!("{}/upsert-many?partial=true", POSTS_BASE), &payloads);
+    assert_eq!(response.status(), Status::MultiStatus);
+    let body = response.into_json::<json::Value>().expect("batch response");
+    assert_eq!(body["results"][0]["status"], 200);
+    assert_eq!(body["results"][1]["status"], 422);
+    assert_eq!(body["results"][1]["error"]["code"], "validation_failed");
+    assert_eq!(fetch_posts(&client, POSTS_BASE).items.len(), 1);
+
+    let delete_uri = format!("{}/delete-many", POSTS_BASE);
+    let ids = json::json!(["batch-ok", "missing"]);
+    // All or nothing by default
+    assert_eq!(client.post_json(&delete_uri, &ids).status(), Status::NotFound);
+    assert_eq!(fetch_posts(&client, POSTS_BASE).items.len(), 1);
+
+    let response = client.post_json(&format!("{}?partial=true", delete_uri), &ids);
+    assert_eq!(response.status(), Status::MultiStatus);
+    let body = response.into_json::<json::Value>().expect("batch response");
+    assert_eq!(body["results"][0]["status"], 200);
+    assert_eq!(body["results"][1]["status"], 404);
+    assert!(fetch_posts(&client, POSTS_BASE).items.is_empty());
+}
+
+#[test]
+fn posts_batches_are_applied_once() {
+    let client = ClientAuthenticated::new();
+    let now = client.now();
+    let post = UpsertPostPayload {
+        id: "batched".into(),
+        created_at: now,
+        content: "Once".into(),
+        updated_at: now,
+        variant: "note".into(),
+    };
+    let upsert_uri = format!("{}/upsert-many", POSTS_BASE);
+    let batch = json::json!({ "batchId": "batch-1", "posts": [post] });
+    assert_success(client.post_json(&upsert_uri, &batch), Status::Ok);
+
+    // The retry gets the same response without writing the post again
+    let post_uri = format!("{}/batched", POSTS_BASE);
+    assert_eq!(client.delete(&post_uri).status(), Status::Ok);
+    assert_success(client.post_json(&upsert_uri, &batch), Status::Ok);
+    assert_eq!(client.get(&post_uri).status(), Status::NotFound);
+
+    let reused = json::json!({ "batchId": "batch-1", "posts": [] });
+    let response = client.post_json(&upsert_uri, &reused);
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let response = client.post_json(&format!("{}?partial=true", upsert_uri), &batch);
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+}
+
+#[test]
+fn posts_fetch_many_returns_changed() {
+    let client = ClientAuthenticated::new();
+    let now = client.now();
+    let posts = ["known", "stale", "new"].map(|id| UpsertPostPayload {
+        id: id.into(),
+        created_at: now,
+        content: format!("Content of {}", id),
+        updated_at: now,
+        variant: "note".into(),
+    });
+    assert_success(
+        client.post_json(&format!("{}/upsert-many", POSTS_BASE), &posts),
+        Status::Ok,
+    );
+    let known = fetch_post(&client, &format!("{}/known", POSTS_BASE));
+
+    let fetch_uri = format!("{}/fetch-many", POSTS_BASE);
+    let known_set = json::json!([
+        { "id": "known", "updatedAt": known.updated_at },
+        { "id": "stale", "updatedAt": known.updated_at - Duration::minutes(1) },
+        { "id": "new" },
+        { "id": "gone", "updatedAt": known.updated_at },
+        { "id": "known", "updatedAt": known.updated_at },
+    ]);
+    let response = client.post_json(&fetch_uri, &known_set);
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().expect("fetch-many response");
+    let ids = body["items"]
+        .as_array()
+        .expect("items")
+        .iter()
+        .map(|post| post["id"].as_str().expect("id"))
+        .collect::<Vec<_>>();
+    assert_eq!(ids, ["stale", "new"]);
+    assert_eq!(body["items"][0]["content"], "Content of stale");
+    assert_eq!(body["missing"], json::json!(["gone"]));
+
+    let too_many = (0..1001).map(|i| json::json!({ "id": i.to_string() })).collect::<Vec<_>>();
+    let response = client.post_json(&fetch_uri, &too_many);
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+}
+
+#[test]
+fn posts_ids_of_other_users_are_refused() {
+    let client = client_tracked_get();
+    let owner_id = seed_user(&client, &email_for_session());
+    let user_id = seed_user(&client, &email_for_session());
+    let now = test_clock(client.rocket()).now();
+    let post = |id: &str| UpsertPostPayload {
+        id: id.into(),
+        created_at: now,
+        content: "Mine".into(),
+        updated_at: now,
+        variant: "note".into(),
+    };
+    let upsert = |user_id, posts: &[UpsertPostPayload]| {
+        let uri = format!("{}/upsert-many", POSTS_BASE);
+        with_csrf(signed_in(client.post(uri).json(&posts), user_id)).dispatch()
+    };
+    assert_success(upsert(owner_id, &[post("shared-id")]), Status::Ok);
+
+    // The whole batch is refused, not only the post of the other user
+    let response = upsert(user_id, &[post("fresh-id"), post("shared-id")]);
+    assert_eq!(response.status(), Status::Conflict);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["details"], json::json!({ "id": "shared-id" }));
+    let response = signed_in(client.get("/api/posts"), user_id).dispatch();
+    let posts = response.into_json::<PostListResponse>().unwrap();
+    assert!(posts.items.is_empty());
+
+    // The owner can still write to it
+    assert_success(upsert(owner_id, &[post("shared-id")]), Status::Ok);
+}
+
+#[test]
+fn posts_sync_by_seq() {
+    let client = ClientAuthenticated::new();
+    let pull = |since_seq: i64, limit: i64| {
+        let uri = format!("{}?since_seq={}&limit={}", POSTS_BASE, since_seq, limit);
+        let response = client.get(&uri);
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json::<json::Value>().expect("sync response")
+    };
+    let ids = |page: &json::Value| {
+        let items = page["items"].as_array().expect("items");
+        items.iter().map(|post| post["id"].as_str().unwrap().to_owned()).collect::<Vec<_>>()
+    };
+
+    for id in ["seq-a", "seq-b"] {
+        let payload = json::json!({ "id": id, "content": id, "variant": "note" });
+        assert_success(client.post_json(POSTS_BASE, &payload), Status::Created);
+    }
+
+    // A cut-off page hands back the seq of its last post
+    let first = pull(0, 1);
+    assert_eq!(ids(&first), ["seq-a"]);
+    assert_eq!(first["hasMore"], true);
+    let rest = pull(first["seq"].as_i64().unwrap(), 10);
+    assert_eq!(ids(&rest), ["seq-b"]);
+    let caught_up = rest["seq"].as_i64().unwrap();
+
+    let update = json::json!({ "content": "edited" });
+    assert_success(client.put_json(&format!("{}/seq-a", POSTS_BASE), &update), Status::Ok);
+    assert_success(client.delete(&format!("{}/seq-b", POSTS_BASE)), Status::Ok);
+
+    let changes = pull(caught_up, 10);
+    assert_eq!(ids(&changes), ["seq-a"]);
+    assert_eq!(changes["deleted"], json::json!(["seq-b"]));
+    assert!(changes["seq"].as_i64().unwrap() > caught_up);
 }
 
 fn fetch_posts(client: &ClientAuthenticated, uri: &str) -> PostListResponse {
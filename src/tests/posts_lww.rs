@@ -0,0 +1,138 @@
+use crate::tests::util::*;
+
+use std::cell::Cell;
+use std::collections::HashMap;
+
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestRunner};
+use rocket::http::Status;
+use rocket::serde::json;
+
+/// Post IDs and timestamps are drawn from small ranges so that sequences revisit the same posts
+/// and often write them with equal timestamps.
+const IDS: usize = 4;
+const SECONDS: u32 = 8;
+
+#[derive(Debug, Clone)]
+struct Write {
+    id: usize,
+    second: u32,
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Create(Write),
+    Update(Write),
+    UpsertMany(Vec<Write>),
+}
+
+fn write() -> impl Strategy<Value = Write> {
+    (0..IDS, 0..SECONDS).prop_map(|(id, second)| Write { id, second })
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    // A batch holds each ID once, in any order
+    let batch = prop::collection::btree_map(0..IDS, 0..SECONDS, 1..=IDS)
+        .prop_map(|writes| {
+            writes
+                .into_iter()
+                .map(|(id, second)| Write { id, second })
+                .collect::<Vec<_>>()
+        })
+        .prop_shuffle();
+    prop_oneof![
+        write().prop_map(Op::Create),
+        write().prop_map(Op::Update),
+        batch.prop_map(Op::UpsertMany),
+    ]
+}
+
+/// The last-write-wins rule: a write lands when the post is new or its stored `updated_at` is
+/// strictly older. Returns whether it landed.
+fn model_apply(model: &mut HashMap<String, (String, u32)>, id: String, content: String, second: u32) -> bool {
+    match model.get(&id) {
+        Some((_, stored)) if *stored >= second => false,
+        _ => {
+            model.insert(id, (content, second));
+            true
+        }
+    }
+}
+
+fn timestamp(second: u32) -> String {
+    format!("2024-01-01T00:00:{:02}Z", second)
+}
+
+#[test]
+fn posts_upserts_match_last_write_wins_model() {
+    let client = ClientAuthenticated::new();
+    let case = Cell::new(0);
+    let mut runner = TestRunner::new(Config {
+        cases: 48,
+        ..Config::default()
+    });
+
+    let result = runner.run(&prop::collection::vec(op(), 1..12), |ops| {
+        // Post IDs are global, so every case writes posts of its own
+        let prefix = format!("lww{}-", case.replace(case.get() + 1));
+        let post_id = |write: &Write| format!("{}{}", prefix, write.id);
+        let mut model = HashMap::new();
+
+        for (n, op) in ops.iter().enumerate() {
+            let content = |write: &Write| format!("op{}-post{}", n, write.id);
+            let body = |write: &Write| {
+                json::json!({
+                    "id": post_id(write),
+                    "content": content(write),
+                    "variant": "note",
+                    "createdAt": timestamp(write.second),
+                    "updatedAt": timestamp(write.second),
+                })
+            };
+            match op {
+                Op::Create(write) => {
+                    let response = client.post_json("/api/posts", &body(write));
+                    prop_assert_eq!(response.status(), Status::Created);
+                    model_apply(&mut model, post_id(write), content(write), write.second);
+                }
+                Op::Update(write) => {
+                    // Unlike an upsert, an update of a missing post is refused
+                    let landed = model.contains_key(&post_id(write))
+                        && model_apply(&mut model, post_id(write), content(write), write.second);
+                    let update = json::json!({ "content": content(write), "updatedAt": timestamp(write.second) });
+                    let response = client.put_json(&format!("/api/posts/{}", post_id(write)), &update);
+                    let expected = if landed { Status::Ok } else { Status::NotFound };
+                    prop_assert_eq!(response.status(), expected);
+                }
+                Op::UpsertMany(writes) => {
+                    let batch = writes.iter().map(body).collect::<Vec<_>>();
+                    let response = client.post_json("/api/posts/upsert-many", &batch);
+                    prop_assert_eq!(response.status(), Status::Ok);
+                    for write in writes {
+                        model_apply(&mut model, post_id(write), content(write), write.second);
+                    }
+                }
+            }
+        }
+
+        let listed = client.get("/api/posts?limit=1000").into_json::<json::Value>().unwrap();
+        let stored = listed["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|post| post["id"].as_str().unwrap().starts_with(&prefix))
+            .map(|post| {
+                let id = post["id"].as_str().unwrap().to_owned();
+                let content = post["content"].as_str().unwrap().to_owned();
+                let updated_at = post["updatedAt"].as_str().unwrap();
+                let second = (0..SECONDS).find(|second| timestamp(*second) == updated_at);
+                (id, (content, second.expect("a generated timestamp")))
+            })
+            .collect::<HashMap<_, _>>();
+        prop_assert_eq!(stored, model);
+        Ok(())
+    });
+    if let Err(e) = result {
+        panic!("{}", e);
+    }
+}
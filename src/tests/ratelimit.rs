@@ -0,0 +1,84 @@
+use crate::tests::util::*;
+
+use chrono::TimeDelta;
+use rocket::http::{Method, Status};
+use rocket::serde::json;
+
+use crate::ratelimit::*;
+
+fn limiter() -> RateLimiter {
+    RateLimiter::new(RateLimitConfig {
+        window_secs: 60,
+        reads: 2,
+        writes: 1,
+        auth: 0,
+    })
+}
+
+#[test]
+fn ratelimit_route_classes() {
+    assert_eq!(RouteClass::of(Method::Get, "/api/v1/posts"), RouteClass::Read);
+    assert_eq!(RouteClass::of(Method::Put, "/api/posts/post-0"), RouteClass::Write);
+    assert_eq!(RouteClass::of(Method::Post, "/api/v1/session/login"), RouteClass::Auth);
+    assert_eq!(RouteClass::of(Method::Get, "/api/session/"), RouteClass::Auth);
+    assert_eq!(RouteClass::of(Method::Get, "/api/sessions"), RouteClass::Read);
+    assert_eq!(
+        RouteClass::of(Method::Post, "/api/v1/posts/fetch-many"),
+        RouteClass::Read
+    );
+    assert_eq!(
+        RouteClass::of(Method::Post, "/api/v1/posts/delete-many"),
+        RouteClass::Write
+    );
+}
+
+#[test]
+fn ratelimit_counts_per_key_and_class_in_windows() {
+    let limiter = limiter();
+    let now = Utc::now();
+
+    let first = limiter.hit("user:1", RouteClass::Read, now).unwrap();
+    assert_eq!((first.limit, first.remaining, first.exceeded), (2, 1, false));
+    assert_eq!(first.reset_at, now + TimeDelta::seconds(60));
+    let second = limiter.hit("user:1", RouteClass::Read, now).unwrap();
+    assert_eq!((second.remaining, second.exceeded), (0, false));
+    let third = limiter
+        .hit("user:1", RouteClass::Read, now + TimeDelta::seconds(30))
+        .unwrap();
+    assert_eq!((third.remaining, third.exceeded), (0, true));
+
+    // Other callers and classes have their own quotas, and classes without one are not limited
+    assert!(!limiter.hit("user:2", RouteClass::Read, now).unwrap().exceeded);
+    assert!(!limiter.hit("user:1", RouteClass::Write, now).unwrap().exceeded);
+    assert_eq!(limiter.hit("user:1", RouteClass::Auth, now), None);
+
+    let next = limiter
+        .hit("user:1", RouteClass::Read, now + TimeDelta::seconds(60))
+        .unwrap();
+    assert_eq!((next.remaining, next.exceeded), (1, false));
+}
+
+#[test]
+fn ratelimit_headers_and_429_per_user() {
+    let client = client_tracked_build(|rocket| rocket.manage(limiter()));
+    let user_id = seed_user(&client, &email_for_session());
+    let other_id = seed_user(&client, &email_for_session());
+    let get = |user_id| signed_in(client.get("/api/users/me/preferences"), user_id).dispatch();
+
+    for remaining in ["1", "0"] {
+        let response = get(user_id);
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("X-RateLimit-Limit"), Some("2"));
+        assert_eq!(response.headers().get_one("X-RateLimit-Remaining"), Some(remaining));
+        assert!(response.headers().get_one("X-RateLimit-Reset").is_some());
+    }
+
+    let response = get(user_id);
+    assert_eq!(response.status(), Status::TooManyRequests);
+    assert_eq!(response.headers().get_one("X-RateLimit-Remaining"), Some("0"));
+    assert!(response.headers().get_one("Retry-After").is_some());
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["code"], json::json!("rate_limited"));
+
+    assert_eq!(get(other_id).status(), Status::Ok);
+}
@@ -0,0 +1,111 @@
+use crate::tests::util::*;
+
+use std::sync::Arc;
+
+use chrono::TimeDelta;
+use rocket::http::Status;
+use rocket::serde::json;
+
+use crate::email::Mailer;
+use crate::reminders::*;
+
+#[rocket::async_test]
+async fn reminders_due_posts_are_delivered_once() {
+    let app = TestApp::new().with_user().start().await;
+    let pool = app.pool();
+    let sender = Arc::new(MockSender::default());
+    let mailer = Mailer(sender.clone());
+    let now = app.now();
+
+    for (id, remind_at) in [
+        ("due", now - TimeDelta::minutes(1)),
+        ("later", now + TimeDelta::hours(1)),
+    ] {
+        let post = json::json!({
+            "id": id,
+            "content": format!("Call the {} plumber\nBefore noon", id),
+            "variant": "todo",
+            "remindAt": remind_at,
+        });
+        assert_eq!(app.post_json("/api/posts", &post).await.status(), Status::Created);
+    }
+    let body = app
+        .get("/api/posts/due")
+        .await
+        .into_json::<json::Value>()
+        .await
+        .unwrap();
+    assert!(body["remindAt"].is_string());
+
+    let mut db = pool.acquire().await.unwrap();
+    assert_eq!(
+        reminders_deliver(&mut db, Some(&mailer), 10, app.now()).await.unwrap(),
+        1
+    );
+    assert_eq!(
+        reminders_deliver(&mut db, Some(&mailer), 10, app.now()).await.unwrap(),
+        0
+    );
+
+    let sent = sender.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].subject, "[ROCKET] Reminder: Call the due plumber");
+    assert!(sent[0].body.contains("/posts/due"));
+    let reminded: Vec<String> = sqlx::query_scalar("SELECT post_id FROM events WHERE kind = 'reminded'")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(reminded, ["due"]);
+}
+
+#[rocket::async_test]
+async fn reminders_snooze_and_dismiss() {
+    let app = TestApp::new().with_posts(1).start().await;
+    let pool = app.pool();
+    let mut db = pool.acquire().await.unwrap();
+
+    let response = app
+        .post_json("/api/posts/post-0/reminder/snooze", &json::json!({ "minutes": 30 }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().await.unwrap();
+    let post = app
+        .get("/api/posts/post-0")
+        .await
+        .into_json::<json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(post["remindAt"], body["remindAt"]);
+    assert_eq!(reminders_deliver(&mut db, None, 10, app.now()).await.unwrap(), 0);
+
+    // Once delivered, a snoozed reminder is due again when its new time comes
+    for _ in 0..2 {
+        sqlx::query("UPDATE posts SET remind_at = ? WHERE id = 'post-0'")
+            .bind(app.now() - TimeDelta::seconds(1))
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(reminders_deliver(&mut db, None, 10, app.now()).await.unwrap(), 1);
+    }
+
+    let past = json::json!({ "until": app.now() - TimeDelta::minutes(1) });
+    let response = app.post_json("/api/posts/post-0/reminder/snooze", &past).await;
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let response = app
+        .post_json("/api/posts/post-0/reminder/snooze", &json::json!({}))
+        .await;
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+
+    assert_eq!(app.delete("/api/posts/post-0/reminder").await.status(), Status::Ok);
+    let post = app
+        .get("/api/posts/post-0")
+        .await
+        .into_json::<json::Value>()
+        .await
+        .unwrap();
+    assert!(post["remindAt"].is_null());
+    assert_eq!(
+        app.delete("/api/posts/missing/reminder").await.status(),
+        Status::NotFound
+    );
+}
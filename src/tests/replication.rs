@@ -0,0 +1,135 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+use rocket::serde::json;
+use rocket::tokio::time::{Duration, Instant};
+use std::path::PathBuf;
+
+use crate::replication::{
+    ReplicationConfig, ReplicationHealth, ReplicationMonitor, database_path, metric_sum, restore_if_empty,
+};
+
+const METRICS: &str = "# HELP litestream_sync_count Number of sync operations performed\n\
+# TYPE litestream_sync_count counter\n\
+litestream_sync_count{db=\"/data/db.sqlite\"} 41\n\
+litestream_sync_count{db=\"/data/other.sqlite\"} 1\n\
+litestream_sync_error_count{db=\"/data/db.sqlite\"} 3\n";
+
+fn config(health_url: &str) -> ReplicationConfig {
+    ReplicationConfig {
+        health_url: Some(health_url.into()),
+        sync_metric: Some("litestream_sync_count".into()),
+        max_lag: Duration::from_secs(60),
+        required: false,
+        restore_command: None,
+    }
+}
+
+#[test]
+fn replication_metrics_summed_across_labels() {
+    assert_eq!(metric_sum(METRICS, "litestream_sync_count"), Some(42.0));
+    assert_eq!(metric_sum(METRICS, "litestream_sync_error_count"), Some(3.0));
+    assert_eq!(metric_sum("up 1\n", "up"), Some(1.0));
+    assert_eq!(metric_sum(METRICS, "litestream_sync"), None);
+}
+
+#[test]
+fn replication_database_paths() {
+    assert_eq!(database_path("db.sqlite"), Some(PathBuf::from("db.sqlite")));
+    assert_eq!(
+        database_path("sqlite://data/db.sqlite"),
+        Some(PathBuf::from("data/db.sqlite"))
+    );
+    assert_eq!(
+        database_path("sqlite:db.sqlite?mode=rwc"),
+        Some(PathBuf::from("db.sqlite"))
+    );
+    assert_eq!(database_path("sqlite::memory:"), None);
+    assert_eq!(database_path("sqlite:shared?mode=memory&cache=shared"), None);
+}
+
+#[test]
+fn replication_goes_stale_when_syncs_stop() {
+    let monitor = ReplicationMonitor::new(config("http://sidecar/metrics"));
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+    let scrape = |count: u32| Ok(format!("litestream_sync_count {}\n", count));
+
+    assert_eq!(monitor.observe(scrape(1), at(0)), ReplicationHealth::Ok);
+    assert_eq!(monitor.observe(scrape(1), at(30)), ReplicationHealth::Ok);
+    assert_eq!(monitor.observe(scrape(5), at(50)), ReplicationHealth::Ok);
+    assert_eq!(monitor.observe(scrape(5), at(100)), ReplicationHealth::Ok);
+    assert_eq!(
+        monitor.observe(scrape(5), at(120)),
+        ReplicationHealth::Stale {
+            lag: Duration::from_secs(70)
+        }
+    );
+    // A restarted sidecar counts from 0 again
+    assert_eq!(monitor.observe(scrape(0), at(130)), ReplicationHealth::Ok);
+
+    let failed = monitor.observe(Err("connection refused".into()), at(140));
+    assert_eq!(failed, ReplicationHealth::Failed("connection refused".into()));
+    assert!(matches!(
+        monitor.observe(Ok("up 1\n".into()), at(150)),
+        ReplicationHealth::Failed(_)
+    ));
+}
+
+#[test]
+fn replication_down_degrades_readyz() {
+    // Nothing listens on port 1
+    let monitor = ReplicationMonitor::new(config("http://127.0.0.1:1/metrics"));
+    let client = client_tracked_build(|rocket| rocket.manage(monitor));
+    let response = client.get("/readyz").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["checks"]["replication"]["status"], "failed");
+
+    let required = ReplicationMonitor::new(ReplicationConfig {
+        required: true,
+        ..config("http://127.0.0.1:1/metrics")
+    });
+    let client = client_tracked_build(|rocket| rocket.manage(required));
+    let response = client.get("/readyz").dispatch();
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+}
+
+#[test]
+fn replication_restores_only_empty_databases() {
+    let dir = std::env::temp_dir().join(format!(
+        "rocket-sqlx-restore-{}-{}",
+        std::process::id(),
+        next_sequence()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("db.sqlite");
+    let command = "printf restored > {db}";
+
+    let restored = block_on({
+        let path = path.clone();
+        async move { restore_if_empty(command, &path).await }
+    });
+    assert_eq!(restored, Ok(true));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "restored");
+
+    // A database with data is left alone
+    std::fs::write(&path, "live").unwrap();
+    let restored = block_on({
+        let path = path.clone();
+        async move { restore_if_empty(command, &path).await }
+    });
+    assert_eq!(restored, Ok(false));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "live");
+
+    // A failed restore stops the startup
+    std::fs::write(&path, "").unwrap();
+    let restored = block_on({
+        let path = path.clone();
+        async move { restore_if_empty("echo no replica >&2; exit 1", &path).await }
+    });
+    assert!(restored.unwrap_err().contains("no replica"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
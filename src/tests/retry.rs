@@ -0,0 +1,109 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+use rocket::tokio::time::Duration;
+use std::sync::Arc;
+
+use crate::chaos::{Fault, FaultConfig, FaultInjector, faults_scoped};
+use crate::handlers::posts::{UpsertPostPayload, posts_upsert_many};
+use crate::retry::{Busy, WriteRetryConfig, write_error, write_retry_with};
+
+fn config(retries: u32) -> WriteRetryConfig {
+    WriteRetryConfig {
+        retries,
+        base_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(2),
+    }
+}
+
+#[rocket::async_test]
+async fn retry_busy_writes_until_they_pass() {
+    let mut calls = 0;
+    let result = write_retry_with!(&config(4), "test", async {
+        calls += 1;
+        match calls {
+            1 | 2 => Err(Fault::Busy.error()),
+            _ => Ok(calls),
+        }
+    });
+    assert_eq!(result.unwrap(), 3);
+}
+
+#[rocket::async_test]
+async fn retry_gives_up_with_a_503() {
+    let mut calls = 0;
+    let result: Result<(), _> = write_retry_with!(&config(2), "test", async {
+        calls += 1;
+        Err(Fault::Busy.error())
+    });
+    assert_eq!(calls, 3);
+    let error = write_error(result.unwrap_err());
+    assert_eq!(error.status, Status::ServiceUnavailable);
+    assert_eq!(error.retry_after, Some(1));
+}
+
+#[rocket::async_test]
+async fn retry_leaves_other_errors_alone() {
+    let mut calls = 0;
+    let result: Result<(), _> = write_retry_with!(&config(4), "test", async {
+        calls += 1;
+        Err(Fault::Dropped.error())
+    });
+    assert_eq!(calls, 1);
+    let error = result.unwrap_err();
+    assert!(!error.is_busy());
+    assert_eq!(write_error(error).status, Status::InternalServerError);
+}
+
+#[test]
+fn retry_delays_grow_up_to_the_cap() {
+    let config = WriteRetryConfig {
+        retries: 10,
+        base_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(50),
+    };
+    for _ in 0..100 {
+        assert!(config.delay(0) <= Duration::from_millis(10));
+        assert!(config.delay(2) <= Duration::from_millis(40));
+        assert!(config.delay(9) <= Duration::from_millis(50));
+    }
+}
+
+#[rocket::async_test]
+async fn retry_upserts_through_a_flaky_database() {
+    let app = TestApp::new().with_user().start().await;
+    let pool = app.pool();
+    let now = Utc::now();
+    let faults = Arc::new(FaultInjector::new(FaultConfig {
+        busy_rate: 0.5,
+        ..FaultConfig::default()
+    }));
+
+    for i in 0..10 {
+        let post = UpsertPostPayload {
+            id: format!("retry-{}", i),
+            parent_id: None,
+            created_at: now,
+            content: format!("Post {}", i),
+            updated_at: now,
+            version: None,
+            variant: "note".into(),
+            position: None,
+            org_id: None,
+            remind_at: None,
+        };
+        let mut conn = pool.acquire().await.unwrap();
+        let write = async {
+            write_retry_with!(&config(30), "test", async {
+                posts_upsert_many(&mut conn, app.user_id(), std::slice::from_ref(&post), now).await
+            })
+        };
+        faults_scoped(faults.clone(), write).await.unwrap();
+    }
+
+    let posts: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM posts WHERE id LIKE 'retry-%'")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(posts, 10);
+}
@@ -0,0 +1,68 @@
+use crate::tests::util::*;
+
+use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::TcpListener;
+use std::time::Duration;
+
+use crate::scan::*;
+
+#[test]
+fn scan_clamd_replies_are_parsed() {
+    assert_eq!(clamd_reply_parse("stream: OK"), Ok(ScanVerdict::Clean));
+    assert_eq!(
+        clamd_reply_parse("stream: Eicar-Signature FOUND"),
+        Ok(ScanVerdict::Infected("Eicar-Signature".into()))
+    );
+    assert!(clamd_reply_parse("INSTREAM size limit exceeded. ERROR").is_err());
+}
+
+/// Answers one `INSTREAM` like clamd, flagging content that contains `EICAR`.
+async fn clamd_fake(listener: TcpListener) {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut command = [0u8; 10];
+    stream.read_exact(&mut command).await.unwrap();
+    assert_eq!(&command, b"zINSTREAM\0");
+    let mut content = Vec::new();
+    loop {
+        let len = stream.read_u32().await.unwrap() as usize;
+        if len == 0 {
+            break;
+        }
+        let mut chunk = vec![0; len];
+        stream.read_exact(&mut chunk).await.unwrap();
+        content.extend(chunk);
+    }
+    let reply: &[u8] = match content.windows(5).any(|window| window == b"EICAR") {
+        true => b"stream: Eicar-Signature FOUND\0",
+        false => b"stream: OK\0",
+    };
+    stream.write_all(reply).await.unwrap();
+}
+
+#[test]
+fn scan_clamd_streams_content() {
+    block_on(async {
+        for (content, verdict) in [
+            (vec![b'a'; 200_000], ScanVerdict::Clean),
+            (
+                b"X5O!P%@AP EICAR-TEST".to_vec(),
+                ScanVerdict::Infected("Eicar-Signature".into()),
+            ),
+        ] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let clamd = Clamd {
+                addr: listener.local_addr().unwrap().to_string(),
+                timeout: Duration::from_secs(5),
+            };
+            let server = rocket::tokio::spawn(clamd_fake(listener));
+            assert_eq!(clamd.scan(&content).await, Ok(verdict));
+            server.await.unwrap();
+        }
+
+        let unreachable = Clamd {
+            addr: "127.0.0.1:1".into(),
+            timeout: Duration::from_secs(5),
+        };
+        assert!(unreachable.scan(b"anything").await.is_err());
+    });
+}
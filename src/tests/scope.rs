@@ -0,0 +1,181 @@
+use crate::tests::util::*;
+
+use chrono::Duration;
+
+use crate::hlc::Hlc;
+use crate::scope::*;
+
+#[test]
+fn post_fields_are_allow_listed() {
+    assert_eq!(
+        PostFields::parse(" updatedAt ,excerpt,updatedAt,"),
+        PostFields::parse("id,updatedAt,excerpt")
+    );
+    for fields in [
+        "user_id",
+        "userId",
+        "p.content",
+        "content_hash",
+        "*",
+        "id) FROM users --",
+    ] {
+        assert!(PostFields::parse(fields).is_err(), "{:?} should not parse", fields);
+    }
+}
+
+#[rocket::async_test]
+async fn scope_hides_posts_of_other_users() {
+    let app = TestApp::new().start().await;
+    let owner = Scope::user(app.user_seed(&email_for_session()).await);
+    let other = Scope::user(app.user_seed(&email_for_session()).await);
+
+    let mut db = app.pool().acquire().await.expect("acquire connection");
+    let now = Utc::now();
+    let post = |id, parent_id, content: &str| PostWrite {
+        id,
+        parent_id,
+        content: StoredContent::of(content),
+        created_at: now,
+        updated_at: now,
+        version: Hlc::from_wall(now),
+        variant: "note",
+        position: Some(1.0),
+        org_id: None,
+        remind_at: None,
+    };
+    owner
+        .posts_upsert(
+            &mut db,
+            &[
+                post("scoped-parent", None, "a"),
+                post("scoped-child", Some("scoped-parent"), "b"),
+            ],
+        )
+        .await
+        .expect("upsert posts");
+
+    // The same ID written by another user must not overwrite the owner's post
+    let later = PostWrite {
+        updated_at: now + Duration::minutes(1),
+        version: Hlc::from_wall(now + Duration::minutes(1)),
+        ..post("scoped-parent", None, "hijacked")
+    };
+    other.posts_upsert(&mut db, &[later]).await.expect("upsert post");
+    let stored = owner.post_read(&mut db, "scoped-parent").await.expect("read post");
+    assert_eq!(stored.map(|p| p.content), Some("a".to_owned()));
+
+    let later = WriteStamp {
+        updated_at: now + Duration::minutes(2),
+        version: Hlc::from_wall(now + Duration::minutes(2)),
+    };
+    assert!(other.post_read(&mut db, "scoped-parent").await.unwrap().is_none());
+    assert!(!other.post_exists(&mut db, "scoped-parent").await.unwrap());
+    assert!(other.post_parent_id(&mut db, "scoped-child").await.unwrap().is_none());
+    assert!(other.post_position(&mut db, "scoped-parent").await.unwrap().is_none());
+    assert!(
+        !other
+            .post_update(&mut db, "scoped-parent", &StoredContent::of("x"), None, &later)
+            .await
+            .unwrap()
+    );
+    assert!(
+        !other
+            .post_position_set(&mut db, "scoped-parent", 5.0, &later)
+            .await
+            .unwrap()
+    );
+    assert!(other.posts_renumber(&mut db, &later).await.unwrap().is_empty());
+    assert!(
+        other
+            .posts_reparent_children(&mut db, "scoped-parent", &later)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(
+        other
+            .posts_descendants(&mut db, "scoped-parent")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(!other.post_remove(&mut db, "scoped-parent").await.unwrap());
+    other.posts_remove_all(&mut db).await.unwrap();
+    let listed = other
+        .posts_list(&mut db, &PostFilter::default(), None, 100)
+        .await
+        .unwrap();
+    assert!(listed.is_empty());
+    assert!(
+        other
+            .posts_list_variant(&mut db, None, None, 100)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(other.posts_updated_since(&mut db, now).await.unwrap().is_empty());
+    assert!(other.posts_since_seq(&mut db, 0, 100).await.unwrap().is_empty());
+
+    // A child of another user under the owner's post, e.g. left by a merge, is not the owner's
+    other
+        .posts_upsert(&mut db, &[post("foreign-child", Some("scoped-child"), "c")])
+        .await
+        .expect("upsert post");
+
+    let listed = owner
+        .posts_list(&mut db, &PostFilter::default(), None, 100)
+        .await
+        .unwrap();
+    assert_eq!(listed.len(), 2);
+    assert_eq!(
+        owner.posts_descendants(&mut db, "scoped-parent").await.unwrap(),
+        vec!["scoped-child"]
+    );
+}
+
+#[rocket::async_test]
+async fn scope_hides_preferences_of_other_users() {
+    let app = TestApp::new().start().await;
+    let owner = Scope::user(app.user_seed(&email_for_session()).await);
+    let other = Scope::user(app.user_seed(&email_for_session()).await);
+
+    let mut db = app.pool().acquire().await.expect("acquire connection");
+    owner
+        .preference_set(&mut db, "theme", "\"dark\"", Utc::now())
+        .await
+        .unwrap();
+    other.preference_delete(&mut db, "theme").await.unwrap();
+
+    assert!(other.preferences(&mut db).await.unwrap().is_empty());
+    assert_eq!(other.preferences_count(&mut db).await.unwrap(), 0);
+    assert_eq!(
+        owner.preferences(&mut db).await.unwrap(),
+        vec![("theme".to_owned(), "\"dark\"".to_owned())]
+    );
+    assert_eq!(other.user_read(&mut db).await.unwrap().map(|u| u.id), Some(other.user_id()));
+}
+
+/// Handlers must not bypass the scope with SQL of their own against the scoped tables.
+#[test]
+fn handlers_query_scoped_tables_only_through_scope() {
+    let sources = [
+        ("handlers/comments.rs", include_str!("../handlers/comments.rs")),
+        ("handlers/orgs.rs", include_str!("../handlers/orgs.rs")),
+        ("handlers/posts.rs", include_str!("../handlers/posts.rs")),
+        ("handlers/session.rs", include_str!("../handlers/session.rs")),
+        ("handlers/users.rs", include_str!("../handlers/users.rs")),
+        ("handlers/graphql.rs", include_str!("../handlers/graphql.rs")),
+        ("grpc.rs", include_str!("../grpc.rs")),
+    ];
+    let patterns = ["FROM posts", "INTO posts", "UPDATE posts", "user_preferences"];
+    for (file, source) in sources {
+        for pattern in patterns {
+            assert!(
+                !source.contains(pattern),
+                "{} queries `{}` outside of Scope",
+                file,
+                pattern
+            );
+        }
+    }
+}
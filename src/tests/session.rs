@@ -1,9 +1,14 @@
 use crate::tests::util::*;
 
+use std::sync::Arc;
+
 use chrono::Duration;
-use rocket::http::Status;
+use rocket::http::{Header, Status};
+use rocket::local::blocking::Client;
 use rocket::serde::json;
 
+use crate::challenge::{ChallengeGate, ChallengeVerifier};
+
 #[test]
 fn session_index_requires_auth() {
     let client = client_tracked_get();
@@ -12,13 +17,46 @@ fn session_index_requires_auth() {
 
     let email = email_for_session();
     let user_id = seed_user(&client, &email);
+    let response = signed_in(client.get("/api/session/"), user_id).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let mut body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["session"]["id"], json::json!(session_id_for(user_id)));
+    body.as_object_mut().unwrap().remove("session");
+    assert_eq!(
+        body,
+        json::json!({
+            "id": user_id,
+            "email": email,
+            "emailVerifiedAt": null,
+            "otpChannel": "email",
+        })
+    );
+}
+
+#[test]
+fn session_index_describes_the_current_session() {
+    let client = client_tracked_get();
+    let email = email_for_session();
+    seed_code(&client, &email, CODE_EXAMPLE);
     let response = client
-        .get("/api/session/")
-        .private_cookie(auth_cookie(user_id))
+        .post("/api/session/login")
+        .json(&json::json!({ "email": email, "code": CODE_EXAMPLE, "rememberMe": true }))
         .dispatch();
     assert_eq!(response.status(), Status::Ok);
-    let body = response.into_json::<json::Value>().unwrap();
-    assert_eq!(body, json::json!({ "id": user_id }));
+
+    let body = client
+        .get("/api/session/")
+        .dispatch()
+        .into_json::<json::Value>()
+        .unwrap();
+    assert_eq!(body["email"], email);
+    assert!(body["emailVerifiedAt"].is_string());
+    let session = &body["session"];
+    assert_eq!(session["authMethod"], "email_code");
+    assert_eq!(session["twoFactorEnabled"], false);
+    let created_at = session["createdAt"].as_str().unwrap().parse::<DateTime<Utc>>().unwrap();
+    let expires_at = session["expiresAt"].as_str().unwrap().parse::<DateTime<Utc>>().unwrap();
+    assert_eq!((expires_at - created_at).num_days(), SESSION_TTL_REMEMBERED_DAYS);
 }
 
 #[test]
@@ -26,7 +64,7 @@ fn session_login_success_sets_cookie_and_clears_metadata() {
     let client = client_tracked_get();
     let email = email_for_session();
     let code = CODE_EXAMPLE; // Use shared constant
-    let created_at = NaiveDateTime::now();
+    let created_at = Utc::now();
     let (user_id, _) = seed_user_with_code(&client, &email, code, Some(0), created_at);
 
     let response = client
@@ -44,11 +82,42 @@ fn session_login_success_sets_cookie_and_clears_metadata() {
     assert!(user.code_attempts.is_none());
 }
 
+#[test]
+fn session_login_remember_me_extends_cookie_lifetime() {
+    let client = client_tracked_get();
+
+    let email = email_for_session();
+    seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(0), Utc::now());
+    let response = client
+        .post("/api/session/login")
+        .json(&json::json!({ "email": email, "code": CODE_EXAMPLE }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let cookie = response.cookies().get_private("user_id").expect("auth cookie");
+    assert_eq!(
+        cookie.max_age(),
+        Some(rocket::time::Duration::hours(SESSION_TTL_SHORT_HOURS))
+    );
+
+    let email = email_for_session();
+    seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(0), Utc::now());
+    let response = client
+        .post("/api/session/login")
+        .json(&json::json!({ "email": email, "code": CODE_EXAMPLE, "rememberMe": true }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let cookie = response.cookies().get_private("user_id").expect("auth cookie");
+    assert_eq!(
+        cookie.max_age(),
+        Some(rocket::time::Duration::days(SESSION_TTL_REMEMBERED_DAYS))
+    );
+}
+
 #[test]
 fn session_login_rejects_invalid_code_format() {
     let client = client_tracked_get();
     let email = email_for_session();
-    seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(0), NaiveDateTime::now()); // Use shared constant
+    seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(0), Utc::now()); // Use shared constant
 
     let response = client
         .post("/api/session/login")
@@ -61,7 +130,7 @@ fn session_login_rejects_invalid_code_format() {
 fn session_login_rejects_expired_code() {
     let client = client_tracked_get();
     let email = email_for_session();
-    let expired_at = NaiveDateTime::now() - Duration::minutes(11);
+    let expired_at = Utc::now() - Duration::minutes(11);
     seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(0), expired_at); // Use shared constant
 
     let response = client
@@ -75,7 +144,7 @@ fn session_login_rejects_expired_code() {
 fn session_login_increments_attempts_on_failure() {
     let client = client_tracked_get();
     let email = email_for_session();
-    seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(0), NaiveDateTime::now()); // Use shared constant
+    seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(0), Utc::now()); // Use shared constant
 
     let response = client
         .post("/api/session/login")
@@ -91,7 +160,7 @@ fn session_login_increments_attempts_on_failure() {
 fn session_login_rejects_exhausted_attempts() {
     let client = client_tracked_get();
     let email = email_for_session();
-    seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(3), NaiveDateTime::now()); // Use shared constant
+    seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(3), Utc::now()); // Use shared constant
 
     let response = client
         .post("/api/session/login")
@@ -100,6 +169,56 @@ fn session_login_rejects_exhausted_attempts() {
     assert_eq!(response.status(), Status::Unauthorized);
 }
 
+#[test]
+fn session_login_rejects_disabled_users() {
+    let client = client_tracked_get();
+    let email = email_for_session();
+    let (user_id, _) = seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(0), Utc::now());
+    let pool = pool_cloned_get(&client);
+    block_on(async move {
+        sqlx::query("UPDATE users SET disabled_at = CURRENT_TIMESTAMP WHERE id = ?")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .expect("disable user");
+    });
+
+    let response = client
+        .post("/api/session/login")
+        .json(&json::json!({ "email": email, "code": CODE_EXAMPLE }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["code"], json::json!("account_disabled"));
+}
+
+#[test]
+fn session_cookie_of_disabled_or_deleted_user_is_rejected() {
+    let client = client_tracked_get();
+    let disabled_id = seed_user(&client, &email_for_session());
+    let deleted_id = seed_user(&client, &email_for_session());
+    let pool = pool_cloned_get(&client);
+    block_on(async move {
+        session_seed(pool.clone(), disabled_id).await;
+        session_seed(pool.clone(), deleted_id).await;
+        sqlx::query("UPDATE users SET disabled_at = CURRENT_TIMESTAMP WHERE id = ?")
+            .bind(disabled_id)
+            .execute(&pool)
+            .await
+            .expect("disable user");
+        sqlx::query("DELETE FROM users WHERE id = ?")
+            .bind(deleted_id)
+            .execute(&pool)
+            .await
+            .expect("delete user");
+    });
+
+    for user_id in [disabled_id, deleted_id] {
+        let response = signed_in(client.get("/api/session/"), user_id).dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+}
+
 #[test]
 fn session_logout_clears_cookie() {
     let client = client_tracked_get();
@@ -107,7 +226,7 @@ fn session_logout_clears_cookie() {
     let user_id = seed_user(&client, &email);
     client.cookies().add_private(auth_cookie(user_id));
 
-    let response = client.post("/api/session/logout").dispatch();
+    let response = with_csrf(client.post("/api/session/logout")).dispatch();
     assert_success(response, Status::Ok);
     assert!(client.cookies().get_private("user_id").is_none());
 
@@ -117,10 +236,10 @@ fn session_logout_clears_cookie() {
 
 #[test]
 fn session_send_code_updates_existing_user() {
-    let client = client_tracked_get();
+    let (client, sender) = client_with_mailer();
     let email = email_for_session();
-    let old_time = NaiveDateTime::now() - Duration::minutes(5);
-    let (_, old_hash) = seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(1), old_time); // Use shared constant
+    let old_time = Utc::now() - Duration::minutes(5);
+    seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(1), old_time); // Use shared constant
 
     let response = client
         .post("/api/session/send-code")
@@ -132,15 +251,26 @@ fn session_send_code_updates_existing_user() {
     assert_eq!(user.code_attempts, Some(0));
     let updated_at = user.code_created_at.expect("code_created_at");
     assert!(updated_at > old_time);
-    let new_hash = user.code_hash.expect("code_hash");
-    assert_ne!(new_hash, old_hash);
+
+    // The emailed code replaces the old one
+    let code = email_code_extract(&sender.sent()[0]).expect("code in email");
+    assert_ne!(code, CODE_EXAMPLE);
+    let login = |code: &str| {
+        client
+            .post("/api/session/login")
+            .json(&json::json!({ "email": email, "code": code }))
+            .dispatch()
+            .status()
+    };
+    assert_eq!(login(CODE_EXAMPLE), Status::Unauthorized);
+    assert_eq!(login(&code), Status::Ok);
 }
 
 #[test]
 fn session_send_code_rate_limits_recent_requests() {
     let client = client_tracked_get();
     let email = email_for_session();
-    let recent = NaiveDateTime::now();
+    let recent = Utc::now();
     seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(0), recent); // Use shared constant
 
     let response = client
@@ -155,7 +285,7 @@ fn session_send_code_rate_limits_recent_requests() {
 
 #[test]
 fn session_send_code_creates_user() {
-    let client = client_tracked_get();
+    let (client, sender) = client_with_mailer();
     let email = email_for_session();
 
     let response = client
@@ -167,5 +297,227 @@ fn session_send_code_creates_user() {
     let user = fetch_user_by_email(&client, &email);
     assert_eq!(user.email, email);
     assert_eq!(user.code_attempts, Some(0));
-    assert!(user.code_hash.is_some());
+
+    let sent = sender.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].to, email);
+    assert_eq!(sent[0].subject, "[ROCKET] Your login code");
+    let code = email_code_extract(&sent[0]).expect("code in email");
+
+    let response = client
+        .post("/api/session/login")
+        .json(&json::json!({ "email": email, "code": code }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn session_send_code_sends_no_email_when_refused() {
+    let (client, sender) = client_with_mailer();
+    let email = email_for_session();
+    seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(0), Utc::now());
+
+    // Rate limited
+    let response = client
+        .post("/api/session/send-code")
+        .json(&json::json!({ "email": email }))
+        .dispatch();
+    assert_eq!(response.status(), Status::TooManyRequests);
+    // Caught by the honeypot
+    let response = client
+        .post("/api/session/send-code")
+        .json(&json::json!({ "email": email_for_session(), "website": "http://spam.example" }))
+        .dispatch();
+    assert_success(response, Status::Ok);
+
+    assert!(sender.sent().is_empty());
+}
+
+#[test]
+fn session_login_records_sessions_that_can_be_revoked() {
+    let client = client_tracked_get();
+    let email = email_for_session();
+
+    for user_agent in ["Device A", "Device B"] {
+        seed_code(&client, &email, CODE_EXAMPLE);
+        let response = client
+            .post("/api/session/login")
+            .header(Header::new("User-Agent", user_agent))
+            .json(&json::json!({ "email": email, "code": CODE_EXAMPLE }))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    let response = client.get("/api/session/sessions").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().unwrap();
+    let items = body["items"].as_array().expect("items");
+    assert_eq!(items.len(), 2);
+    // Newest first, and that's the one the client is using
+    assert_eq!(items[0]["userAgent"], json::json!("Device B"));
+    assert_eq!(items[0]["current"], json::json!(true));
+    assert_eq!(items[1]["current"], json::json!(false));
+
+    let other_id = items[1]["id"].as_str().expect("id").to_string();
+    let response = with_csrf(client.delete(format!("/api/session/sessions/{}", other_id))).dispatch();
+    assert_success(response, Status::Ok);
+    let response = with_csrf(client.delete(format!("/api/session/sessions/{}", other_id))).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    let body = client
+        .get("/api/session/sessions")
+        .dispatch()
+        .into_json::<json::Value>()
+        .unwrap();
+    assert_eq!(body["items"].as_array().map(Vec::len), Some(1));
+}
+
+#[test]
+fn session_cookie_of_revoked_session_is_rejected() {
+    let client = client_tracked_get();
+    let user_id = seed_user(&client, &email_for_session());
+    let response = signed_in(client.get("/api/session/"), user_id).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let uri = format!("/api/session/sessions/{}", session_id_for(user_id));
+    let response = with_csrf(signed_in(client.delete(uri), user_id)).dispatch();
+    assert_success(response, Status::Ok);
+
+    // The auth cookie is still valid, its session is not
+    let response = signed_in(client.get("/api/session/"), user_id).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+struct FakeVerifier;
+
+#[rocket::async_trait]
+impl ChallengeVerifier for FakeVerifier {
+    async fn verify(&self, token: &str, _remote_ip: Option<&str>) -> Result<bool, String> {
+        Ok(token == "human")
+    }
+}
+
+#[test]
+fn session_send_code_requires_valid_captcha_when_configured() {
+    let client = client_tracked_build(|rocket| rocket.manage(ChallengeGate(Some(Arc::new(FakeVerifier)))));
+    let email = email_for_session();
+
+    let response = client
+        .post("/api/session/send-code")
+        .json(&json::json!({ "email": email }))
+        .dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+
+    let response = client
+        .post("/api/session/send-code")
+        .json(&json::json!({ "email": email, "captchaToken": "bot" }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let response = client
+        .post("/api/session/send-code")
+        .json(&json::json!({ "email": email, "captchaToken": "human" }))
+        .dispatch();
+    assert_success(response, Status::Ok);
+}
+
+#[test]
+fn session_send_code_ignores_honeypot_submissions() {
+    let client = client_tracked_get();
+    let email = email_for_session();
+
+    let response = client
+        .post("/api/session/send-code")
+        .json(&json::json!({ "email": email, "website": "http://spam.example" }))
+        .dispatch();
+    assert_success(response, Status::Ok);
+
+    let pool = pool_cloned_get(&client);
+    let exists = block_on(async move {
+        sqlx::query("SELECT id FROM users WHERE email = ?")
+            .bind(email)
+            .fetch_optional(&pool)
+            .await
+            .expect("query user")
+            .is_some()
+    });
+    assert!(!exists);
+}
+
+/// Logs in as a new user and returns the expiry of the session, moved to `expires_at`.
+fn session_expiring_at(client: &Client, expires_at: DateTime<Utc>) -> String {
+    let email = email_for_session();
+    seed_code(client, &email, CODE_EXAMPLE);
+    let response = client
+        .post("/api/session/login")
+        .json(&json::json!({ "email": email, "code": CODE_EXAMPLE }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let session_id = client.cookies().get_private(SESSION_COOKIE).unwrap().value().to_owned();
+    let pool = pool_cloned_get(client);
+    let id = session_id.clone();
+    block_on(async move {
+        sqlx::query("UPDATE sessions SET expires_at = ? WHERE id = ?")
+            .bind(expires_at)
+            .bind(id)
+            .execute(&pool)
+            .await
+            .expect("move session expiry")
+    });
+    session_id
+}
+
+fn session_expiry(client: &Client, session_id: &str) -> DateTime<Utc> {
+    let pool = pool_cloned_get(client);
+    let id = session_id.to_owned();
+    block_on(async move {
+        sqlx::query_scalar("SELECT expires_at FROM sessions WHERE id = ?")
+            .bind(id)
+            .fetch_one(&pool)
+            .await
+            .expect("fetch session expiry")
+    })
+}
+
+#[test]
+fn session_refresh_extends_the_session() {
+    let client = client_tracked_get();
+    let response = with_csrf(client.post("/api/session/refresh"))
+        .private_cookie(auth_cookie(seed_user(&client, &email_for_session())))
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let session_id = session_expiring_at(&client, Utc::now() + Duration::hours(20));
+    let response = with_csrf(client.post("/api/session/refresh")).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let expires_at = session_expiry(&client, &session_id);
+    assert!(expires_at > Utc::now() + Duration::hours(SESSION_TTL_SHORT_HOURS) - Duration::minutes(1));
+}
+
+#[test]
+fn session_cookie_of_expired_session_is_rejected() {
+    let client = client_tracked_get();
+    let session_id = session_expiring_at(&client, Utc::now() - Duration::minutes(1));
+    assert_eq!(client.get("/api/session/").dispatch().status(), Status::Unauthorized);
+    let response = with_csrf(client.post("/api/session/refresh")).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    // Neither request brought the session back
+    assert!(session_expiry(&client, &session_id) < Utc::now());
+}
+
+#[test]
+fn session_activity_extends_sessions_past_half_their_lifetime() {
+    let client = client_tracked_get();
+    let fresh = Utc::now() + Duration::hours(20);
+    let session_id = session_expiring_at(&client, fresh);
+    assert_eq!(client.get("/api/session/").dispatch().status(), Status::Ok);
+    assert_eq!(session_expiry(&client, &session_id), fresh);
+
+    let due = Utc::now() + Duration::hours(2);
+    let session_id = session_expiring_at(&client, due);
+    // Only requests the session signs in slide it
+    assert_eq!(client.get("/api/v1/time").dispatch().status(), Status::Ok);
+    assert_eq!(session_expiry(&client, &session_id), due);
+    assert_eq!(client.get("/api/session/").dispatch().status(), Status::Ok);
+    assert!(session_expiry(&client, &session_id) > Utc::now() + Duration::hours(SESSION_TTL_SHORT_HOURS - 1));
 }
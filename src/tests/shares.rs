@@ -0,0 +1,108 @@
+use crate::tests::util::*;
+
+use rocket::http::{Header, Status};
+use rocket::serde::json;
+
+#[rocket::async_test]
+async fn shares_links_read_posts_without_signing_in() {
+    let app = TestApp::new().with_verified_user().with_posts(2).start().await;
+
+    let response = app.put_json("/api/posts/post-0/share", &json::json!({})).await;
+    assert_eq!(response.status(), Status::Created);
+    let body = response.into_json::<json::Value>().await.unwrap();
+    let token = body["token"].as_str().unwrap().to_owned();
+    assert!(body["url"].as_str().unwrap().ends_with(&token));
+    // Sharing again keeps the link
+    let again = app
+        .put_json("/api/posts/post-0/share", &json::json!({}))
+        .await
+        .into_json::<json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(again["token"], body["token"]);
+    assert_eq!(
+        app.put_json("/api/posts/missing/share", &json::json!({}))
+            .await
+            .status(),
+        Status::NotFound
+    );
+
+    let uri = format!("/api/shared/{}", token);
+    let response = app.anonymous().get(uri.clone()).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let shared = response.into_json::<json::Value>().await.unwrap();
+    assert!(shared["content"].is_string());
+    assert!(shared["updatedAt"].is_string());
+
+    assert_eq!(app.delete("/api/posts/post-0/share").await.status(), Status::Ok);
+    let response = app.anonymous().get(uri).dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn shares_feed_lists_shared_posts_and_revalidates() {
+    let app = TestApp::new().with_verified_user().start().await;
+
+    for (id, content) in [("shared", "Hello <world> & all\nMore"), ("private", "Secret")] {
+        let post = json::json!({ "id": id, "content": content, "variant": "note" });
+        assert_eq!(app.post_json("/api/posts", &post).await.status(), Status::Created);
+    }
+    let body = app
+        .put_json("/api/posts/shared/share", &json::json!({}))
+        .await
+        .into_json::<json::Value>()
+        .await
+        .unwrap();
+    let feed_url = body["feedUrl"].as_str().unwrap();
+    let uri = &feed_url[feed_url.find("/api/v1/").unwrap()..];
+
+    let response = app.anonymous().get(uri.to_owned()).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        response.headers().get_one("Content-Type"),
+        Some("application/atom+xml; charset=utf-8")
+    );
+    assert_eq!(response.headers().get_one("Cache-Control"), Some("public, max-age=300"));
+    let etag = response.headers().get_one("ETag").unwrap().to_owned();
+    let atom = response.into_string().await.unwrap();
+    assert!(atom.contains("<title>Hello &lt;world&gt; &amp; all</title>"));
+    assert!(!atom.contains("Secret"));
+
+    let revalidate = || {
+        app.anonymous()
+            .get(uri.to_owned())
+            .header(Header::new("If-None-Match", etag.clone()))
+            .dispatch()
+    };
+    assert_eq!(revalidate().await.status(), Status::NotModified);
+
+    // Editing a shared post changes the feed
+    let post = json::json!({ "content": "Hello again" });
+    assert_eq!(app.put_json("/api/posts/shared", &post).await.status(), Status::Ok);
+    let response = revalidate().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(
+        response
+            .into_string()
+            .await
+            .unwrap()
+            .contains("<title>Hello again</title>")
+    );
+
+    let response = app.anonymous().get("/api/v1/shared/feeds/missing").dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn shares_require_a_verified_email() {
+    let app = TestApp::new().with_posts(1).start().await;
+
+    let response = app.put_json("/api/posts/post-0/share", &json::json!({})).await;
+    assert_eq!(response.status(), Status::Forbidden);
+    let body = response.into_json::<json::Value>().await.unwrap();
+    assert_eq!(body["message"], "This requires a verified email address");
+    let response = app
+        .post_json("/api/posts/post-0/signed-url", &json::json!({ "ttlSecs": 60 }))
+        .await;
+    assert_eq!(response.status(), Status::Forbidden);
+}
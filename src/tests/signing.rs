@@ -0,0 +1,83 @@
+use crate::tests::util::*;
+
+use chrono::TimeDelta;
+use rocket::http::Status;
+use rocket::serde::json;
+
+use crate::signing::*;
+
+const KEY_A: &str = "a:0123456789abcdef0123456789abcdef";
+const KEY_B: &str = "b:fedcba9876543210fedcba9876543210";
+
+/// Splits a query made by `UrlSigner::sign` into `expires`, `kid` and `sig`.
+fn query_parse(query: &str) -> (i64, String, String) {
+    let param = |name: &str| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(&format!("{}=", name)))
+            .unwrap()
+            .to_string()
+    };
+    (param("expires").parse().unwrap(), param("kid"), param("sig"))
+}
+
+#[test]
+fn signing_urls_expire_and_are_revoked_by_rotation() {
+    let now = Utc::now();
+    let signer = UrlSigner::new(KEY_A, &[]);
+    let (expires, kid, sig) = query_parse(&signer.sign("/shared/posts/post-0", now + TimeDelta::hours(1)));
+    assert_eq!(kid, "a");
+    assert_eq!(signer.verify("/shared/posts/post-0", expires, &kid, &sig, now), Ok(()));
+    assert_eq!(
+        signer.verify("/shared/posts/post-1", expires, &kid, &sig, now),
+        Err(SignatureError::Invalid)
+    );
+    assert_eq!(
+        signer.verify("/shared/posts/post-0", expires + 3600, &kid, &sig, now),
+        Err(SignatureError::Invalid)
+    );
+    assert_eq!(
+        signer.verify("/shared/posts/post-0", expires, &kid, &sig, now + TimeDelta::hours(2)),
+        Err(SignatureError::Expired)
+    );
+
+    // Rotated out but kept: still honored. Dropped: revoked.
+    let rotated = UrlSigner::new(KEY_B, &[KEY_A]);
+    assert_eq!(rotated.verify("/shared/posts/post-0", expires, &kid, &sig, now), Ok(()));
+    let revoked = UrlSigner::new(KEY_B, &[]);
+    assert_eq!(
+        revoked.verify("/shared/posts/post-0", expires, &kid, &sig, now),
+        Err(SignatureError::UnknownKey("a".into()))
+    );
+}
+
+#[rocket::async_test]
+async fn signing_urls_read_posts_without_signing_in() {
+    let app = TestApp::new().with_verified_user().with_posts(1).start().await;
+
+    let response = app
+        .post_json("/api/posts/post-0/signed-url", &json::json!({ "ttlSecs": 60 }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().await.unwrap();
+    let url = body["url"].as_str().unwrap();
+    let uri = &url[url.find("/api/v1/").unwrap()..];
+
+    let response = app.anonymous().get(uri.to_owned()).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response.into_json::<json::Value>().await.unwrap()["data"]["content"].is_string());
+    // The same signature works under the legacy prefix, but not for another post
+    let legacy = uri.replacen("/api/v1/", "/api/", 1);
+    assert_eq!(app.anonymous().get(legacy).dispatch().await.status(), Status::Ok);
+    let other = uri.replacen("post-0", "post-1", 1);
+    assert_eq!(app.anonymous().get(other).dispatch().await.status(), Status::Forbidden);
+    let unsigned = "/api/v1/shared/posts/post-0";
+    assert_eq!(
+        app.anonymous().get(unsigned).dispatch().await.status(),
+        Status::Forbidden
+    );
+
+    let too_long = json::json!({ "ttlSecs": SIGNED_URL_TTL_MAX_SECS + 1 });
+    let response = app.post_json("/api/posts/post-0/signed-url", &too_long).await;
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+}
@@ -0,0 +1,45 @@
+use std::time::Duration;
+use tracing_subscriber::layer::SubscriberExt;
+
+use crate::db::query_span;
+use crate::metrics::metrics;
+use crate::slowquery::{QueryTimer, statement_redact};
+
+#[test]
+fn slowquery_statements_redacted() {
+    assert_eq!(
+        statement_redact("\n\nSELECT id, content\nFROM posts\nWHERE user_id = ? AND id = 'post-0'\n"),
+        "SELECT id, content FROM posts WHERE user_id = ? AND id = ?"
+    );
+    assert_eq!(
+        statement_redact("UPDATE posts_v2 SET content = 'it''s secret', position = 1.5 WHERE id = ?1 LIMIT 10"),
+        "UPDATE posts_v2 SET content = ?, position = ? WHERE id = ?1 LIMIT ?"
+    );
+    assert_eq!(statement_redact("SELECT -42, x'CAFE'"), "SELECT -?, x?");
+}
+
+#[test]
+fn slowquery_queries_timed_and_logged() {
+    let subscriber = tracing_subscriber::registry().with(QueryTimer::new(Some(Duration::ZERO)).layer());
+    tracing::subscriber::with_default(subscriber, || {
+        let request = tracing::info_span!("request", route = tracing::field::Empty);
+        request.in_scope(|| {
+            for _ in 0..2 {
+                let query = query_span("slowquery.test");
+                query.in_scope(|| {
+                    tracing::debug!(
+                        target: "sqlx::query",
+                        summary = "select * from posts …",
+                        db.statement = "\n\nSELECT * FROM posts WHERE id = 'post-0'\n"
+                    );
+                });
+            }
+        });
+        request.record("route", "slowquery_test");
+    });
+
+    let text = metrics().render();
+    assert!(text.contains("db_query_duration_seconds_count{query=\"slowquery.test\"} 2\n"));
+    assert!(text.contains("db_slow_queries_total{query=\"slowquery.test\"} 2\n"));
+    assert!(text.contains("db_request_duration_seconds_count{route=\"slowquery_test\"} 1\n"));
+}
@@ -0,0 +1,116 @@
+use crate::tests::util::*;
+
+use std::sync::{Arc, Mutex};
+
+use chrono::TimeDelta;
+use rocket::http::{Method, Status};
+use rocket::local::blocking::Client;
+use rocket::serde::json;
+
+use crate::email::Mailer;
+use crate::sms::{Sms, SmsSender, phone_is_valid};
+
+const PHONE_EXAMPLE: &str = "+14155550100";
+const PHONE_URI: &str = "/api/users/me/phone";
+const CHANNEL_URI: &str = "/api/users/me/otp-channel";
+
+/// Records the texts it is handed instead of sending them.
+#[derive(Default)]
+struct MockSms {
+    sent: Mutex<Vec<(String, String)>>,
+}
+
+impl MockSms {
+    /// Returns the number and the 8-digit code of the latest text.
+    fn last_code(&self) -> (String, String) {
+        let sent = self.sent.lock().unwrap();
+        let (to, body) = sent.last().expect("a text was sent");
+        let code = body.split(|c: char| !c.is_ascii_digit()).find(|word| word.len() == 8);
+        (to.clone(), code.expect("code in text").to_owned())
+    }
+}
+
+#[rocket::async_trait]
+impl SmsSender for MockSms {
+    async fn send(&self, to: &str, body: &str) -> Result<(), String> {
+        self.sent.lock().unwrap().push((to.into(), body.into()));
+        Ok(())
+    }
+}
+
+fn dispatch(client: &Client, user_id: i64, method: Method, uri: &str, body: json::Value) -> (Status, json::Value) {
+    let request = with_csrf(signed_in(client.req(method, uri), user_id));
+    let response = request.json(&body).dispatch();
+    (response.status(), response.into_json().unwrap_or_default())
+}
+
+#[test]
+fn sms_phone_numbers_must_be_e164() {
+    assert!(phone_is_valid(PHONE_EXAMPLE));
+    assert!(!phone_is_valid("4155550100"));
+    assert!(!phone_is_valid("+1 415 555 0100"));
+    assert!(!phone_is_valid("+0155550100"));
+}
+
+#[test]
+fn sms_login_codes_are_texted_once_the_phone_is_verified() {
+    let texts = Arc::new(MockSms::default());
+    let emails = Arc::new(MockSender::default());
+    let (sms, mailer) = (Sms(Some(texts.clone())), Mailer(emails.clone()));
+    let client = client_tracked_build(|rocket| rocket.manage(sms).manage(mailer));
+    let email = email_for_session();
+    let (user_id, _) = seed_user_with_code(&client, &email, "12345678", None, Utc::now() - TimeDelta::hours(1));
+
+    let (status, _) = dispatch(
+        &client,
+        user_id,
+        Method::Put,
+        PHONE_URI,
+        json::json!({ "phone": "555" }),
+    );
+    assert_eq!(status, Status::UnprocessableEntity);
+    let phone = json::json!({ "phone": PHONE_EXAMPLE });
+    let (status, body) = dispatch(&client, user_id, Method::Put, PHONE_URI, phone.clone());
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["phoneVerifiedAt"], json::Value::Null);
+    let (status, _) = dispatch(&client, user_id, Method::Put, PHONE_URI, phone);
+    assert_eq!(status, Status::TooManyRequests);
+
+    // Unverified numbers can't receive login codes
+    let sms_channel = json::json!({ "channel": "sms" });
+    let (status, _) = dispatch(&client, user_id, Method::Put, CHANNEL_URI, sms_channel.clone());
+    assert_eq!(status, Status::UnprocessableEntity);
+
+    let (to, code) = texts.last_code();
+    assert_eq!(to, PHONE_EXAMPLE);
+    let wrong = if code == "00000000" { "11111111" } else { "00000000" };
+    let uri = "/api/users/me/phone/verify";
+    let (status, _) = dispatch(&client, user_id, Method::Post, uri, json::json!({ "code": wrong }));
+    assert_eq!(status, Status::UnprocessableEntity);
+    let (status, body) = dispatch(&client, user_id, Method::Post, uri, json::json!({ "code": code }));
+    assert_eq!(status, Status::Ok);
+    assert!(body["phoneVerifiedAt"].is_string());
+    let (status, body) = dispatch(&client, user_id, Method::Put, CHANNEL_URI, sms_channel);
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["otpChannel"], "sms");
+
+    let response = client
+        .post("/api/session/send-code")
+        .json(&json::json!({ "email": email }))
+        .dispatch();
+    assert_success(response, Status::Ok);
+    assert!(emails.sent().is_empty());
+    let (to, code) = texts.last_code();
+    assert_eq!(to, PHONE_EXAMPLE);
+    let response = client
+        .post("/api/session/login")
+        .json(&json::json!({ "email": email, "code": code }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = client
+        .get("/api/session/")
+        .dispatch()
+        .into_json::<json::Value>()
+        .unwrap();
+    assert_eq!(body["session"]["authMethod"], "sms_code");
+}
@@ -0,0 +1,400 @@
+use crate::tests::util::*;
+
+use insta::assert_json_snapshot;
+use rocket::http::Status;
+use rocket::local::blocking::LocalResponse;
+use rocket::serde::json;
+
+/// Fields that differ from run to run, replaced by their name in brackets.
+const FIELDS_DYNAMIC: &[&str] = &[
+    "createdAt",
+    "email",
+    "expiresAt",
+    "nextRunAt",
+    "now",
+    "nowMs",
+    "requestId",
+    "seq",
+    "updatedAt",
+    "version",
+];
+
+/// Snapshots sort object keys, so that only renamed, added or removed fields show up in diffs.
+fn settings() -> insta::Settings {
+    let mut settings = insta::Settings::clone_current();
+    settings.set_sort_maps(true);
+    for field in FIELDS_DYNAMIC {
+        settings.add_redaction(&format!(".**.{}", field), format!("[{}]", field));
+    }
+    settings
+}
+
+fn body_of(response: LocalResponse, expected: Status) -> json::Value {
+    assert_eq!(response.status(), expected);
+    response.into_json::<json::Value>().unwrap()
+}
+
+#[test]
+fn snapshots_of_error_bodies() {
+    let client = client_tracked_get();
+    let user_id = seed_user(&client, &email_for_session());
+
+    let unauthorized = body_of(client.get("/api/v1/posts").dispatch(), Status::Unauthorized);
+    let request = signed_in(client.post("/api/v1/posts"), user_id);
+    let forbidden = body_of(
+        request
+            .json(&json::json!({ "content": "hello", "variant": "note" }))
+            .dispatch(),
+        Status::Forbidden,
+    );
+    let request = signed_in(client.get("/api/v1/posts/missing"), user_id);
+    let not_found = body_of(request.dispatch(), Status::NotFound);
+    let request = signed_in(client.get("/api/posts/missing"), user_id);
+    let not_found_legacy = body_of(request.dispatch(), Status::NotFound);
+    let request = signed_in(client.put("/api/v1/users/me/preferences"), user_id);
+    let invalid = body_of(
+        with_csrf(request).json(&json::json!({ "": true })).dispatch(),
+        Status::UnprocessableEntity,
+    );
+
+    settings().bind(|| {
+        assert_json_snapshot!(unauthorized, @r#"
+        {
+          "data": null,
+          "error": {
+            "code": "unauthorized",
+            "details": null,
+            "message": "Unauthorized",
+            "requestId": "[requestId]"
+          },
+          "meta": null
+        }
+        "#);
+        assert_json_snapshot!(forbidden, @r#"
+        {
+          "data": null,
+          "error": {
+            "code": "forbidden",
+            "details": null,
+            "message": "Forbidden",
+            "requestId": "[requestId]"
+          },
+          "meta": null
+        }
+        "#);
+        assert_json_snapshot!(not_found, @r#"
+        {
+          "data": null,
+          "error": {
+            "code": "not_found",
+            "details": null,
+            "message": "Post not found",
+            "requestId": "[requestId]"
+          },
+          "meta": null
+        }
+        "#);
+        assert_json_snapshot!(not_found_legacy, @r#"
+        {
+          "code": "not_found",
+          "details": null,
+          "message": "Post not found",
+          "requestId": "[requestId]"
+        }
+        "#);
+        assert_json_snapshot!(invalid, @r#"
+        {
+          "data": null,
+          "error": {
+            "code": "validation_failed",
+            "details": {
+              "key": ""
+            },
+            "message": "Preference keys must be 1-64 characters",
+            "requestId": "[requestId]"
+          },
+          "meta": null
+        }
+        "#);
+    });
+}
+
+#[test]
+fn snapshots_of_posts_and_comments() {
+    let client = ClientAuthenticated::new();
+    let created = body_of(
+        client.post_json(
+            "/api/v1/posts",
+            &json::json!({ "id": "p-1", "content": "hello", "variant": "note" }),
+        ),
+        Status::Created,
+    );
+    let read = body_of(client.get("/api/v1/posts/p-1"), Status::Ok);
+    let list = body_of(client.get("/api/v1/posts?limit=1"), Status::Ok);
+    let list_legacy = body_of(client.get("/api/posts?limit=1"), Status::Ok);
+    let comment_created = body_of(
+        client.post_json(
+            "/api/v1/posts/p-1/comments",
+            &json::json!({ "id": "c-1", "content": "first" }),
+        ),
+        Status::Created,
+    );
+    let comment = body_of(client.get("/api/v1/posts/p-1/comments/c-1"), Status::Ok);
+
+    settings().bind(|| {
+        assert_json_snapshot!(created, @r#"
+        {
+          "data": null,
+          "error": null,
+          "meta": null
+        }
+        "#);
+        assert_json_snapshot!(read, @r#"
+        {
+          "data": {
+            "content": "hello",
+            "createdAt": "[createdAt]",
+            "excerpt": "",
+            "favorited": false,
+            "id": "p-1",
+            "orgId": null,
+            "parentId": null,
+            "position": null,
+            "previews": [],
+            "remindAt": null,
+            "seq": "[seq]",
+            "title": "hello",
+            "updatedAt": "[updatedAt]",
+            "variant": "note",
+            "version": "[version]"
+          },
+          "error": null,
+          "meta": null
+        }
+        "#);
+        assert_json_snapshot!(list, @r#"
+        {
+          "data": [
+            {
+              "content": "hello",
+              "createdAt": "[createdAt]",
+              "excerpt": "",
+              "favorited": false,
+              "id": "p-1",
+              "orgId": null,
+              "parentId": null,
+              "position": null,
+              "remindAt": null,
+              "seq": "[seq]",
+              "title": "hello",
+              "updatedAt": "[updatedAt]",
+              "variant": "note",
+              "version": "[version]"
+            }
+          ],
+          "error": null,
+          "meta": {
+            "pagination": {
+              "hasMore": false
+            }
+          }
+        }
+        "#);
+        assert_json_snapshot!(list_legacy, @r#"
+        {
+          "hasMore": false,
+          "items": [
+            {
+              "content": "hello",
+              "createdAt": "[createdAt]",
+              "excerpt": "",
+              "favorited": false,
+              "id": "p-1",
+              "orgId": null,
+              "parentId": null,
+              "position": null,
+              "remindAt": null,
+              "seq": "[seq]",
+              "title": "hello",
+              "updatedAt": "[updatedAt]",
+              "variant": "note",
+              "version": "[version]"
+            }
+          ]
+        }
+        "#);
+        assert_json_snapshot!(comment_created, @r#"
+        {
+          "data": {
+            "id": "c-1",
+            "message": "success"
+          },
+          "error": null,
+          "meta": null
+        }
+        "#);
+        assert_json_snapshot!(comment, @r#"
+        {
+          "data": {
+            "content": "first",
+            "createdAt": "[createdAt]",
+            "id": "c-1",
+            "parentId": null,
+            "postId": "p-1",
+            "updatedAt": "[updatedAt]"
+          },
+          "error": null,
+          "meta": null
+        }
+        "#);
+    });
+}
+
+#[test]
+fn snapshots_of_orgs_and_templates() {
+    let client = ClientAuthenticated::new();
+    let org = body_of(
+        client.post_json("/api/v1/orgs", &json::json!({ "name": "Acme" })),
+        Status::Created,
+    );
+    let orgs = body_of(client.get("/api/v1/orgs"), Status::Ok);
+    let template = body_of(
+        client.post_json(
+            "/api/v1/templates",
+            &json::json!({ "name": "Journal", "content": "Today", "variant": "note", "schedule": "0 21 * * *" }),
+        ),
+        Status::Created,
+    );
+
+    settings().bind(|| {
+        assert_json_snapshot!(org, @r#"
+        {
+          "data": {
+            "createdAt": "[createdAt]",
+            "id": "id-1",
+            "name": "Acme"
+          },
+          "error": null,
+          "meta": null
+        }
+        "#);
+        assert_json_snapshot!(orgs, @r#"
+        {
+          "data": [
+            {
+              "createdAt": "[createdAt]",
+              "id": "id-1",
+              "name": "Acme",
+              "role": "owner"
+            }
+          ],
+          "error": null,
+          "meta": {
+            "pagination": {
+              "hasMore": false
+            }
+          }
+        }
+        "#);
+        assert_json_snapshot!(template, @r#"
+        {
+          "data": {
+            "content": "Today",
+            "createdAt": "[createdAt]",
+            "enabled": true,
+            "id": "id-2",
+            "lastRunAt": null,
+            "name": "Journal",
+            "nextRunAt": "[nextRunAt]",
+            "parentId": null,
+            "schedule": "0 21 * * *",
+            "updatedAt": "[updatedAt]",
+            "variant": "note"
+          },
+          "error": null,
+          "meta": null
+        }
+        "#);
+    });
+}
+
+#[test]
+fn snapshots_of_account_and_time() {
+    let client = ClientAuthenticated::new();
+    let session = body_of(client.get("/api/v1/session/"), Status::Ok);
+    let me = body_of(client.get("/api/v1/users/me"), Status::Ok);
+    let preferences = body_of(
+        client.put_json(
+            "/api/v1/users/me/preferences",
+            &json::json!({ "theme": "dark", "fontSize": 14 }),
+        ),
+        Status::Ok,
+    );
+    let time = body_of(client.get("/api/v1/time"), Status::Ok);
+
+    let mut settings = settings();
+    // Each instance numbers its users past those of the instances built before it
+    settings.add_redaction(".data.id", "[id]");
+    settings.add_redaction(".data.session.id", "[id]");
+    settings.bind(|| {
+        assert_json_snapshot!(session, @r#"
+        {
+          "data": {
+            "email": "[email]",
+            "emailVerifiedAt": null,
+            "id": "[id]",
+            "otpChannel": "email",
+            "session": {
+              "authMethod": "email_code",
+              "createdAt": "[createdAt]",
+              "expiresAt": "[expiresAt]",
+              "id": "[id]",
+              "ip": null,
+              "location": null,
+              "revokedAt": null,
+              "twoFactorEnabled": false,
+              "userAgent": null
+            }
+          },
+          "error": null,
+          "meta": null
+        }
+        "#);
+        assert_json_snapshot!(me, @r#"
+        {
+          "data": {
+            "createdAt": "[createdAt]",
+            "email": "[email]",
+            "emailVerifiedAt": null,
+            "id": "[id]",
+            "otpChannel": "email",
+            "phone": null,
+            "phoneVerifiedAt": null
+          },
+          "error": null,
+          "meta": null
+        }
+        "#);
+        assert_json_snapshot!(preferences, @r#"
+        {
+          "data": {
+            "fontSize": 14,
+            "theme": "dark"
+          },
+          "error": null,
+          "meta": null
+        }
+        "#);
+        assert_json_snapshot!(time, @r#"
+        {
+          "data": {
+            "maxSkewSecs": 300,
+            "now": "[now]",
+            "nowMs": "[nowMs]"
+          },
+          "error": null,
+          "meta": null
+        }
+        "#);
+    });
+}
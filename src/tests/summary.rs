@@ -0,0 +1,35 @@
+use crate::summary::*;
+
+#[test]
+fn summary_strips_markdown() {
+    assert_eq!(
+        summary_of("# Groceries\n\n- [ ] **Milk**\n- [x] [Bread](https://example.com/bread)\n> `2` eggs"),
+        ("Groceries".to_string(), "Milk Bread 2 eggs".to_string())
+    );
+    assert_eq!(
+        summary_of("\n---\n1. First ~~step~~\n```rust\nlet x = 1;\n```\n![A cat](cat.png)"),
+        ("First step".to_string(), "let x = 1; A cat".to_string())
+    );
+    assert_eq!(summary_of(""), (String::new(), String::new()));
+    assert_eq!(markdown_strip("## snake_case and __bold__"), "snake_case and bold");
+}
+
+#[test]
+fn summary_cuts_long_text() {
+    let (title, excerpt) = summary_of(&format!("{}\n{}", "t".repeat(500), "word ".repeat(200)));
+    assert_eq!(title.chars().count(), TITLE_MAX_CHARS);
+    assert!(title.ends_with('…'));
+    assert!(excerpt.chars().count() <= EXCERPT_MAX_CHARS);
+    assert!(excerpt.ends_with('…'));
+
+    assert_eq!(summary_of("Short\nbody"), ("Short".to_string(), "body".to_string()));
+}
+
+#[test]
+fn summary_of_structured_content() {
+    assert_eq!(
+        summary_of(r#"{"title": " Call Bob ", "done": false}"#),
+        ("Call Bob".to_string(), String::new())
+    );
+    assert_eq!(summary_of(r#"{"done": false}"#), (String::new(), String::new()));
+}
@@ -0,0 +1,83 @@
+use crate::tests::util::*;
+
+use std::sync::{Arc, Mutex};
+
+use chrono::TimeZone;
+use tracing_subscriber::layer::SubscriberExt;
+
+use crate::telemetry::{REQUEST_LOG_TARGET, RequestLog, request_log_layer};
+
+fn request_log() -> RequestLog {
+    RequestLog {
+        at: Utc.with_ymd_and_hms(2026, 3, 9, 13, 55, 36).unwrap(),
+        request_id: "req-1".into(),
+        method: "GET".into(),
+        uri: "/api/v1/posts?limit=10".into(),
+        status: 200,
+        bytes: Some(2326),
+        duration_ms: 12,
+        user_id: Some(42),
+        client_ip: Some("203.0.113.7".parse().unwrap()),
+        user_agent: Some("Notes/1.2 (\"beta\")".into()),
+        referer: None,
+    }
+}
+
+#[test]
+fn telemetry_request_log_in_the_combined_log_format() {
+    assert_eq!(
+        request_log().to_common(),
+        "203.0.113.7 - 42 [09/Mar/2026:13:55:36 +0000] \"GET /api/v1/posts?limit=10\" 200 2326 \"-\" \
+        \"Notes/1.2 (\\\"beta\\\")\""
+    );
+    let anonymous = RequestLog {
+        user_id: None,
+        client_ip: None,
+        bytes: None,
+        ..request_log()
+    };
+    assert!(anonymous.to_common().starts_with("- - - [09/Mar/2026:13:55:36 +0000]"));
+    assert!(anonymous.to_common().contains(" 200 - "));
+}
+
+#[test]
+fn telemetry_request_log_as_json() {
+    let log = request_log().to_json();
+    assert_eq!(log["userId"], 42);
+    assert_eq!(log["bytes"], 2326);
+    assert_eq!(log["clientIp"], "203.0.113.7");
+    assert_eq!(log["userAgent"], "Notes/1.2 (\"beta\")");
+    assert_eq!(log["timestamp"], "2026-03-09T13:55:36+00:00");
+}
+
+#[test]
+fn telemetry_request_log_lines_are_written_bare() {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let writer = {
+        let lines = lines.clone();
+        move || LinesWriter(lines.clone())
+    };
+    let subscriber = tracing_subscriber::registry().with(request_log_layer(writer));
+    tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::info_span!("request", request_id = "req-1");
+        span.in_scope(|| {
+            tracing::info!(target: REQUEST_LOG_TARGET, "{}", request_log().to_common());
+            tracing::info!("not a request line");
+        });
+    });
+    let written = String::from_utf8(lines.lock().unwrap().clone()).unwrap();
+    assert_eq!(written, format!("{}\n", request_log().to_common()));
+}
+
+struct LinesWriter(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for LinesWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+use rocket::serde::json;
+
+use crate::recurring::*;
+
+const TEMPLATES_BASE: &str = "/api/templates";
+
+#[rocket::async_test]
+async fn templates_crud() {
+    let app = TestApp::new().with_posts(1).start().await;
+
+    let template = json::json!({
+        "name": "Journal",
+        "content": "Journal {{date}}",
+        "variant": "note",
+        "parentId": "post-0",
+        "schedule": "0 21 * * *",
+    });
+    let response = app.post_json(TEMPLATES_BASE, &template).await;
+    assert_eq!(response.status(), Status::Created);
+    let created = response.into_json::<json::Value>().await.unwrap();
+    let id = created["id"].as_str().unwrap().to_owned();
+    assert_eq!(created["content"], "Journal {{date}}");
+    assert!(created["nextRunAt"].as_str().unwrap().contains("T21:00:00"));
+
+    let body = app.get(TEMPLATES_BASE).await.into_json::<json::Value>().await.unwrap();
+    assert_eq!(body["items"].as_array().unwrap().len(), 1);
+
+    let disabled = json::json!({
+        "name": "Journal",
+        "content": "Journal {{date}}",
+        "variant": "note",
+        "schedule": "@weekly",
+        "enabled": false,
+    });
+    let uri = format!("{}/{}", TEMPLATES_BASE, id);
+    assert_eq!(app.put_json(&uri, &disabled).await.status(), Status::Ok);
+    let body = app.get(&uri).await.into_json::<json::Value>().await.unwrap();
+    assert_eq!(body["schedule"], "@weekly");
+    assert!(body["nextRunAt"].is_null());
+    assert!(body["parentId"].is_null());
+
+    for invalid in [
+        json::json!({ "name": "", "content": "", "variant": "note", "schedule": "@daily" }),
+        json::json!({ "name": "Journal", "content": "", "variant": "note", "schedule": "0 25 * * *" }),
+        json::json!({ "name": "Journal", "content": "", "variant": "note", "schedule": "@daily", "parentId": "missing" }),
+    ] {
+        let response = app.post_json(TEMPLATES_BASE, &invalid).await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+    }
+
+    assert_eq!(app.delete(&uri).await.status(), Status::Ok);
+    assert_eq!(app.get(&uri).await.status(), Status::NotFound);
+    assert_eq!(app.delete(&uri).await.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn templates_due_are_instantiated_once() {
+    let app = TestApp::new().with_user().start().await;
+    let pool = app.pool();
+
+    let template = json::json!({
+        "name": "Journal",
+        "content": "Journal {{date}}",
+        "variant": "note",
+        "schedule": "@hourly",
+    });
+    let created = app
+        .post_json(TEMPLATES_BASE, &template)
+        .await
+        .into_json::<json::Value>()
+        .await
+        .unwrap();
+    let id = created["id"].as_str().unwrap();
+
+    let mut db = pool.acquire().await.unwrap();
+    assert_eq!(templates_run_due(&mut db, 10, app.now(), app.ids()).await.unwrap(), 0);
+    sqlx::query("UPDATE post_templates SET next_run_at = ? WHERE id = ?")
+        .bind(Utc::now() - chrono::TimeDelta::days(3))
+        .bind(id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    // Missed runs are made up for once
+    assert_eq!(templates_run_due(&mut db, 10, app.now(), app.ids()).await.unwrap(), 1);
+    assert_eq!(templates_run_due(&mut db, 10, app.now(), app.ids()).await.unwrap(), 0);
+
+    let body = app.get("/api/posts").await.into_json::<json::Value>().await.unwrap();
+    let posts = body["items"].as_array().unwrap();
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0]["content"], format!("Journal {}", app.now().format("%Y-%m-%d")));
+    let body = app
+        .get(&format!("{}/{}", TEMPLATES_BASE, id))
+        .await
+        .into_json::<json::Value>()
+        .await
+        .unwrap();
+    assert!(body["lastRunAt"].is_string());
+    assert!(body["nextRunAt"].as_str().unwrap() > body["lastRunAt"].as_str().unwrap());
+
+    let response = app
+        .post_json(&format!("{}/{}/run", TEMPLATES_BASE, id), &json::json!({}))
+        .await;
+    assert_eq!(response.status(), Status::Created);
+}
@@ -0,0 +1,62 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+use rocket::serde::json;
+use rocket::tokio::time::{Duration, sleep};
+use std::collections::HashMap;
+
+use crate::timeout::{TimeoutConfig, route_key, routes_with_deadline};
+
+#[get("/slow")]
+async fn slow() -> &'static str {
+    sleep(Duration::from_millis(500)).await;
+    "done"
+}
+
+#[get("/fast")]
+async fn fast() -> &'static str {
+    "done"
+}
+
+#[test]
+fn timeout_aborts_slow_handlers_with_504() {
+    let client = client_tracked_build(|rocket| {
+        rocket.mount(
+            "/test",
+            routes_with_deadline(routes![slow, fast], Duration::from_millis(50)),
+        )
+    });
+
+    let response = client.get("/test/slow").dispatch();
+    assert_eq!(response.status(), Status::GatewayTimeout);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["code"], json::json!("timeout"));
+    assert_eq!(body["message"], json::json!("Request timed out"));
+
+    let response = client.get("/test/fast").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_string().as_deref(), Some("done"));
+}
+
+#[get("/")]
+async fn list() -> &'static str {
+    "done"
+}
+
+#[test]
+fn timeout_overrides_are_keyed_by_method_and_path() {
+    let keys: Vec<_> = routes![list, slow]
+        .iter()
+        .map(|route| route_key("/posts", route))
+        .collect();
+    assert_eq!(keys, ["get /posts", "get /posts/slow"]);
+    assert_eq!(route_key("", &routes![slow][0]), "get /slow");
+
+    let config = TimeoutConfig {
+        default: Duration::from_secs(30),
+        overrides: HashMap::from([("get /posts/slow".to_string(), Duration::from_secs(120))]),
+    };
+    assert_eq!(config.deadline("GET /posts/slow"), Duration::from_secs(120));
+    // The same handler mounted elsewhere keeps the default
+    assert_eq!(config.deadline("GET /comments/slow"), Duration::from_secs(30));
+}
@@ -0,0 +1,71 @@
+use crate::tests::util::*;
+
+use rocket::http::uri::Host;
+use rocket::http::{Header, Status};
+
+use crate::handlers::admin::AdminToken;
+use crate::tls::*;
+
+#[test]
+fn tls_forwarded_proto_trusts_the_nearest_proxy() {
+    assert_eq!(
+        forwarded_proto(Some("for=192.0.2.60;proto=https;by=203.0.113.43"), None),
+        Some("https")
+    );
+    // A client can prepend its own element, the proxy's comes last
+    assert_eq!(
+        forwarded_proto(Some("proto=https, for=192.0.2.60;proto=http"), None),
+        Some("http")
+    );
+    assert_eq!(forwarded_proto(None, Some("https, http")), Some("http"));
+    assert_eq!(forwarded_proto(Some("for=192.0.2.60"), Some("https")), Some("https"));
+    assert_eq!(forwarded_proto(None, None), None);
+}
+
+#[test]
+fn tls_https_url_keeps_non_default_ports() {
+    assert_eq!(
+        https_url("example.com", Some(443), "/a?b=1"),
+        "https://example.com/a?b=1"
+    );
+    assert_eq!(https_url("example.com", Some(8443), "/"), "https://example.com:8443/");
+    assert_eq!(https_url("example.com", None, "/a"), "https://example.com/a");
+}
+
+#[test]
+fn tls_redirect_sends_plain_http_to_https() {
+    let client = client_tracked_build(|rocket| {
+        rocket
+            .manage(AdminToken(Some(ADMIN_TOKEN_EXAMPLE.into())))
+            .attach(HttpsRedirect {
+                trust_proxy: true,
+                https_port: None,
+            })
+    });
+    // Local requests don't take their host from the Host header
+    let mut request = client
+        .post("/api/posts?merge=true")
+        .header(Header::new("X-Forwarded-Proto", "http"));
+    request.inner_mut().set_host(Host::parse("notes.example.com").unwrap());
+    let response = request.dispatch();
+    assert_eq!(response.status(), Status::MovedPermanently);
+    assert_eq!(
+        response.headers().get_one("Location"),
+        Some("https://notes.example.com/api/posts?merge=true")
+    );
+
+    let response = client
+        .get("/api/time")
+        .header(Header::new("Host", "notes.example.com"))
+        .header(Header::new("X-Forwarded-Proto", "https"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    // Metrics are scraped over plain HTTP
+    let response = client
+        .get("/metrics")
+        .header(Header::new("Host", "10.0.0.5"))
+        .header(admin_header())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
@@ -0,0 +1,123 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+use rocket::serde::json;
+
+const PREFERENCES_URI: &str = "/api/users/me/preferences";
+
+#[test]
+fn users_preferences_merge_and_delete() {
+    let client = ClientAuthenticated::new();
+
+    let response = client.get(PREFERENCES_URI);
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_json::<json::Value>().unwrap(), json::json!({}));
+
+    let response = client.put_json(
+        PREFERENCES_URI,
+        &json::json!({ "theme": "dark", "syncInterval": 30, "defaultVariant": "note" }),
+    );
+    assert_eq!(response.status(), Status::Ok);
+
+    // Keys not mentioned are kept, null removes a key
+    let response = client.put_json(
+        PREFERENCES_URI,
+        &json::json!({ "theme": "light", "syncInterval": null }),
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let expected = json::json!({ "theme": "light", "defaultVariant": "note" });
+    assert_eq!(response.into_json::<json::Value>().unwrap(), expected);
+
+    let response = client.get(PREFERENCES_URI);
+    assert_eq!(response.into_json::<json::Value>().unwrap(), expected);
+}
+
+#[test]
+fn users_preferences_rejects_invalid_keys() {
+    let client = ClientAuthenticated::new();
+    let long_key = "k".repeat(65);
+
+    let response = client.put_json(PREFERENCES_URI, &json::json!({ long_key: true }));
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+}
+
+#[test]
+fn users_preferences_requires_auth() {
+    let client = client_tracked_get();
+    let response = client.get(PREFERENCES_URI).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[test]
+fn users_me_reports_email_verification() {
+    let client = client_tracked_get();
+    let email = email_for_session();
+    let (user_id, _) = seed_user_with_code(&client, &email, CODE_EXAMPLE, Some(0), Utc::now());
+
+    let response = signed_in(client.get("/api/users/me"), user_id).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["email"], json::json!(email));
+    assert!(body["emailVerifiedAt"].is_null());
+
+    let response = client
+        .post("/api/session/login")
+        .json(&json::json!({ "email": email, "code": CODE_EXAMPLE }))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let user = fetch_user_by_email(&client, &email);
+    let verified_at = user.email_verified_at.expect("email_verified_at");
+    let response = client.get("/api/users/me").dispatch();
+    let body = response.into_json::<json::Value>().unwrap();
+    assert_eq!(body["emailVerifiedAt"], json::json!(verified_at));
+}
+
+#[rocket::async_test]
+async fn users_merge_requires_a_code_for_the_other_account() {
+    let app = TestApp::new().with_user().start().await;
+    let pool = app.pool();
+    let email = format!("other+{}@example.com", next_sequence());
+    let hash = hash_code(CODE_EXAMPLE).await.expect("hash code");
+    let other = sqlx::query("INSERT INTO users (email, code_attempts, code_created_at, code_hash) VALUES (?, 0, ?, ?)")
+        .bind(&email)
+        .bind(Utc::now())
+        .bind(&hash)
+        .execute(&pool)
+        .await
+        .unwrap()
+        .last_insert_rowid();
+    sqlx::query("INSERT INTO posts (id, user_id, content, variant) VALUES ('theirs', ?, 'Hello', 'note')")
+        .bind(other)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_json(
+            "/api/users/me/merge",
+            &json::json!({ "email": email, "code": "87654321" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let response = app
+        .post_json(
+            "/api/users/me/merge",
+            &json::json!({ "email": email, "code": CODE_EXAMPLE }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_json::<json::Value>().await.unwrap();
+    assert_eq!(body["moved"]["posts"], json::json!(1));
+    assert_eq!(app.get("/api/posts/theirs").await.status(), Status::Ok);
+
+    let (merged_into, disabled) =
+        sqlx::query_as::<_, (Option<i64>, bool)>("SELECT merged_into, disabled_at IS NOT NULL FROM users WHERE id = ?")
+            .bind(other)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(merged_into, Some(app.user_id()));
+    assert!(disabled);
+}
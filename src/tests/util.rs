@@ -1,20 +1,45 @@
 use std::env;
-use std::fs;
 use std::future::Future;
-use std::sync::Mutex;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Once, OnceLock};
 
-use rocket::http::Status;
+use chrono::{SubsecRound, TimeDelta};
+use rocket::http::{Accept, Cookie, Header, Status};
+use rocket::local::asynchronous::{
+    Client as AsyncClient, LocalRequest as AsyncRequest, LocalResponse as AsyncResponse,
+};
 use rocket::local::blocking::{Client, LocalRequest, LocalResponse};
-use rocket::serde::Serialize;
+use rocket::serde::{Serialize, json};
 use rocket::tokio::runtime::Runtime;
+use rocket::{Build, Orbit, Rocket};
 use rocket_db_pools::Database;
 
-use crate::db;
+use crate::api;
+use crate::blobs;
+use crate::clock::{self, Clock, Timekeeper};
+use crate::coalesce;
+use crate::config;
+use crate::csrf::{CSRF_COOKIE, CSRF_HEADER};
+use crate::db::{self, IdGenerator, IdSource};
+use crate::email::{self, DeliveryError, Email, EmailSender, Mailer};
+use crate::error;
+use crate::events;
+use crate::flags;
 use crate::handlers;
+use crate::health;
+use crate::impersonation;
+use crate::jwt;
+use crate::maintenance;
+use crate::metrics;
+use crate::oauth;
+use crate::previews;
+use crate::ratelimit;
+use crate::recurring;
+use crate::reminders;
+use crate::scan;
+use crate::sms;
 pub use crate::util::*;
 
-static DB_ENV_MUTEX: Mutex<()> = Mutex::new(());
 static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
 
 pub(super) struct ClientAuthenticated {
@@ -27,66 +52,396 @@ impl ClientAuthenticated {
         let client = client_tracked_get();
         let email = format!("user+{}@example.com", next_sequence());
         let user_id = seed_user(&client, &email);
+        block_on(session_seed(pool_cloned_get(&client), user_id));
         Self { inner: client, user_id }
     }
 
-    pub(super) fn get<'c>(&'c self, uri: &'c str) -> LocalResponse<'c> {
-        self.with_auth(self.inner.get(uri)).dispatch()
+    pub(super) fn get<'c>(&'c self, uri: &str) -> LocalResponse<'c> {
+        self.with_auth(self.inner.get(uri.to_owned())).dispatch()
     }
 
-    pub(super) fn post_json<'c, T>(&'c self, uri: &'c str, body: &T) -> LocalResponse<'c>
+    pub(super) fn post_json<'c, T>(&'c self, uri: &str, body: &T) -> LocalResponse<'c>
     where
         T: Serialize,
     {
-        self.with_auth(self.inner.post(uri).json(body)).dispatch()
+        self.with_auth(self.inner.post(uri.to_owned()).json(body)).dispatch()
     }
 
-    pub(super) fn put_json<'c, T>(&'c self, uri: &'c str, body: &T) -> LocalResponse<'c>
+    pub(super) fn put_json<'c, T>(&'c self, uri: &str, body: &T) -> LocalResponse<'c>
     where
         T: Serialize,
     {
-        self.with_auth(self.inner.put(uri).json(body)).dispatch()
+        self.with_auth(self.inner.put(uri.to_owned()).json(body)).dispatch()
     }
 
-    pub(super) fn delete<'c>(&'c self, uri: &'c str) -> LocalResponse<'c> {
-        self.with_auth(self.inner.delete(uri)).dispatch()
+    pub(super) fn delete<'c>(&'c self, uri: &str) -> LocalResponse<'c> {
+        self.with_auth(self.inner.delete(uri.to_owned())).dispatch()
+    }
+
+    /// Sends the body as MessagePack and asks for a MessagePack response.
+    pub(super) fn post_msgpack<'c, T>(&'c self, uri: &str, body: &T) -> LocalResponse<'c>
+    where
+        T: Serialize,
+    {
+        self.with_auth(self.inner.post(uri.to_owned()).msgpack(body).header(Accept::MsgPack))
+            .dispatch()
+    }
+
+    pub(super) fn get_msgpack<'c>(&'c self, uri: &str) -> LocalResponse<'c> {
+        self.with_auth(self.inner.get(uri.to_owned()).header(Accept::MsgPack))
+            .dispatch()
+    }
+
+    /// Reads the instance's `TestClock`.
+    pub(super) fn now(&self) -> DateTime<Utc> {
+        test_clock(self.inner.rocket()).now()
     }
 
     fn with_auth<'c>(&'c self, request: LocalRequest<'c>) -> LocalRequest<'c> {
-        request.private_cookie(auth_cookie(self.user_id))
+        with_csrf(signed_in(request, self.user_id))
     }
 }
 
-pub(super) fn client_tracked_get() -> Client {
-    // setup env
-    let lock = DB_ENV_MUTEX.lock().unwrap();
-    let seq = next_sequence();
-    let db_path = format!("/tmp/test_db_{}.sqlite", seq);
-
-    // Clean up if file exists from previous run
-    let _ = fs::remove_file(&db_path);
-    let db_config = format!("{{sqlx={{url=\"sqlite://{}\"}}}}", db_path);
-    unsafe {
-        env::set_var("DATABASE_URL", format!("sqlite://{}", db_path));
-        env::set_var("ROCKET_DATABASES", &db_config);
+pub(super) const CSRF_TOKEN_EXAMPLE: &str = "test-csrf-token";
+
+/// Attaches a matching double-submit CSRF cookie and header to the request.
+pub(super) fn with_csrf(request: LocalRequest<'_>) -> LocalRequest<'_> {
+    request
+        .cookie(Cookie::new(CSRF_COOKIE, CSRF_TOKEN_EXAMPLE))
+        .header(Header::new(CSRF_HEADER, CSRF_TOKEN_EXAMPLE))
+}
+
+/// Sets the process-wide environment the app reads at startup. It is the same for every test and
+/// written once, before the first instance is built; each instance gets its own database through
+/// its figment instead.
+fn env_init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| unsafe {
+        env::set_var("DATABASE_URL", "sqlite::memory:");
+        env::set_var("ROCKET_DATABASES", "{sqlx={url=\"sqlite::memory:\"}}");
         env::set_var("ROCKET_PROFILE", "debug");
         env::set_var("ROCKET_SECRET_KEY", "5Z4RZccfO6oVLQj86VXLxCaX/xyGq5wixH4hWsLve0s=");
         env::set_var("DKIM_KEY_PRIVATE", "test_key");
         env::set_var("DKIM_KEY_PUBLIC", "test_public_key");
         env::set_var("EMAIL_FROM", "test@example.com");
-    }
+        // Under Rocket's 1 MiB body limit, so that requests over it reach the handlers
+        env::set_var("POST_CONTENT_MAX_BYTES", "65536");
+        // Tests deliver due reminders themselves, see `reminders_deliver`
+        env::set_var("REMINDERS", "false");
+        // Same for recurring posts, see `templates_run_due`
+        env::set_var("TEMPLATES_SCHEDULER", "false");
+        // And for unreferenced blobs, see `blobs_gc`
+        env::set_var("ATTACHMENTS_GC", "false");
+        // Tests of rate limiting manage a limiter with their own quotas
+        env::set_var("RATE_LIMIT_READS", "0");
+        env::set_var("RATE_LIMIT_WRITES", "0");
+        env::set_var("RATE_LIMIT_AUTH", "0");
+        // The variants the tests use, and one whose content has a schema
+        env::set_var(
+            "POST_VARIANTS",
+            r#"{"note": null, "todo": null, "task": null, "checklist": {"type": "object", "required": ["done"], "properties": {"done": {"type": "boolean"}}}}"#,
+        );
+    });
     env_get(); // asserts all are there
+}
+
+/// The clock of test instances. It starts on a whole second and moves a millisecond each time it is
+/// read, so that timestamps round-trip exactly and successive writes still sort in order. It
+/// starts at the current time because some expiries are still checked against the system clock.
+pub(super) struct TestClock(Mutex<DateTime<Utc>>);
+
+impl TestClock {
+    pub(super) fn new(start: DateTime<Utc>) -> Self {
+        Self(Mutex::new(start))
+    }
+
+    /// Moves the clock forward, e.g. past an expiry.
+    pub(super) fn advance(&self, by: TimeDelta) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> DateTime<Utc> {
+        let mut at = self.0.lock().unwrap();
+        *at += TimeDelta::milliseconds(1);
+        *at
+    }
+}
+
+/// The ID source of test instances: `id-1`, `id-2`... in the order they are taken.
+pub(super) struct SequentialIds(AtomicUsize);
+
+impl IdGenerator for SequentialIds {
+    fn id(&self) -> String {
+        format!("id-{}", self.0.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+}
+
+/// The `TestClock` of an instance built by `rocket_build`, unless the test managed its own
+/// `Timekeeper`.
+pub(super) fn test_clock(rocket: &Rocket<Orbit>) -> &TestClock {
+    rocket.state::<Arc<TestClock>>().expect("test clock is managed")
+}
 
-    // env ready
-    let rocket = rocket::build()
+/// Builds an instance on a fresh in-memory database, shared by the connections of its pool and
+/// gone with the pool, with a `TestClock` and `SequentialIds`. `customize` runs before the stages
+/// are attached (e.g. to manage state).
+fn rocket_build(customize: impl FnOnce(Rocket<Build>) -> Rocket<Build>) -> Rocket<Build> {
+    env_init();
+    let url = format!("sqlite:file:test-{}?mode=memory&cache=shared", next_sequence());
+    let figment = rocket::Config::figment()
+        .merge(("databases.sqlx.url", url))
+        // An in-memory database is dropped when its last connection closes
+        .merge(("databases.sqlx.min_connections", 1));
+
+    let rocket = customize(rocket::custom(figment));
+    let rocket = if rocket.state::<Timekeeper>().is_some() {
+        rocket
+    } else {
+        let clock = Arc::new(TestClock::new(Utc::now().trunc_subsecs(0)));
+        rocket.manage(Timekeeper(clock.clone())).manage(clock)
+    };
+    let rocket = manage_default(rocket, |_| IdSource(Arc::new(SequentialIds(AtomicUsize::new(0)))));
+    let rocket = rocket
+        .register("/", error::catchers())
+        .attach(config::stage())
+        .attach(api::stage())
+        .attach(blobs::stage())
+        .attach(clock::stage())
+        .attach(coalesce::stage())
         .attach(db::stage())
+        .attach(email::stage())
+        .attach(events::stage())
+        .attach(flags::stage())
+        .attach(impersonation::stage())
+        .attach(jwt::stage())
+        .attach(maintenance::stage())
+        .attach(handlers::admin::stage())
+        .attach(handlers::attachments::stage())
         .attach(handlers::posts::stage())
-        .attach(handlers::session::stage());
-    let client = Client::tracked(rocket).expect("valid rocket instance");
-    drop(lock);
+        .attach(handlers::calendar::stage())
+        .attach(handlers::comments::stage())
+        .attach(handlers::dev::stage())
+        .attach(handlers::orgs::stage())
+        .attach(handlers::session::stage())
+        .attach(handlers::shares::stage())
+        .attach(handlers::templates::stage())
+        .attach(handlers::users::stage())
+        .attach(health::stage())
+        .attach(metrics::stage())
+        .attach(oauth::stage())
+        .attach(previews::stage())
+        .attach(ratelimit::stage())
+        .attach(recurring::stage())
+        .attach(reminders::stage())
+        .attach(scan::stage())
+        .attach(sms::stage());
+    #[cfg(feature = "graphql")]
+    let rocket = rocket.attach(handlers::graphql::stage());
+    #[cfg(feature = "embed")]
+    let rocket = rocket.attach(crate::assets::stage());
+    rocket
+}
+
+pub(super) fn client_tracked_get() -> Client {
+    client_tracked_build(|rocket| rocket)
+}
+
+/// Like `client_tracked_get`, but lets the test customize the instance (e.g. manage state) before
+/// the stages are attached.
+pub(super) fn client_tracked_build(customize: impl FnOnce(Rocket<Build>) -> Rocket<Build>) -> Client {
+    let client = Client::tracked(rocket_build(customize)).expect("valid rocket instance");
+    let pool = pool_cloned_get(&client);
+    block_on(async move { user_ids_offset(&pool).await });
     client
 }
 
+/// Starts the instance's user IDs past those any other instance hands out. Post responses are
+/// cached process-wide by user ID, so instances must not share IDs.
+async fn user_ids_offset(pool: &sqlx::SqlitePool) {
+    let start = next_sequence() as i64 * 1_000_000;
+    sqlx::query("INSERT INTO sqlite_sequence (name, seq) VALUES ('users', ?)")
+        .bind(start)
+        .execute(pool)
+        .await
+        .expect("offset user IDs");
+}
+
+/// Builder of an app for async tests, driven by `rocket::local::asynchronous::Client`:
+///
+/// ```ignore
+/// let app = TestApp::new().with_user().with_posts(3).start().await;
+/// let response = app.get("/api/posts").await;
+/// ```
+pub(super) struct TestApp {
+    user: bool,
+    verified: bool,
+    posts: usize,
+}
+
+impl TestApp {
+    pub(super) fn new() -> Self {
+        Self {
+            user: false,
+            verified: false,
+            posts: 0,
+        }
+    }
+
+    /// Seeds a user whose session the requests of the app carry.
+    pub(super) fn with_user(mut self) -> Self {
+        self.user = true;
+        self
+    }
+
+    /// Like `with_user`, with the user's email address verified.
+    pub(super) fn with_verified_user(mut self) -> Self {
+        self.user = true;
+        self.verified = true;
+        self
+    }
+
+    /// Seeds `count` notes for the user (implies `with_user`), with IDs `post-0`, `post-1`...
+    /// updated a second apart, newest last.
+    pub(super) fn with_posts(mut self, count: usize) -> Self {
+        self.user = true;
+        self.posts = count;
+        self
+    }
+
+    pub(super) async fn start(self) -> TestClient {
+        let client = AsyncClient::tracked(rocket_build(|rocket| rocket))
+            .await
+            .expect("valid rocket instance");
+        let mut app = TestClient { client, user_id: None };
+        user_ids_offset(&app.pool()).await;
+        if self.user {
+            let email = format!("user+{}@example.com", next_sequence());
+            let user_id = app.user_seed(&email).await;
+            session_seed(app.pool(), user_id).await;
+            if self.verified {
+                sqlx::query("UPDATE users SET email_verified_at = CURRENT_TIMESTAMP WHERE id = ?")
+                    .bind(user_id)
+                    .execute(&app.pool())
+                    .await
+                    .expect("verify user");
+            }
+            app.user_id = Some(user_id);
+        }
+        if self.posts > 0 {
+            let now = test_clock(app.client.rocket()).now();
+            let posts = (0..self.posts)
+                .map(|i| {
+                    let at = (now - chrono::Duration::seconds((self.posts - i) as i64)).to_rfc3339();
+                    json::json!({
+                        "id": format!("post-{}", i),
+                        "content": format!("Post {}", i),
+                        "variant": "note",
+                        "createdAt": at,
+                        "updatedAt": at,
+                    })
+                })
+                .collect::<Vec<_>>();
+            let response = app.post_json("/api/posts/upsert-many", &posts).await;
+            assert_eq!(response.status(), Status::Ok);
+        }
+        app
+    }
+}
+
+/// A started `TestApp`. Requests carry the seeded user's session and a CSRF token, if any.
+pub(super) struct TestClient {
+    client: AsyncClient,
+    user_id: Option<i64>,
+}
+
+impl TestClient {
+    /// The seeded user. Panics unless the app was built `with_user`.
+    pub(super) fn user_id(&self) -> i64 {
+        self.user_id.expect("TestApp built without a user")
+    }
+
+    /// The underlying client, for requests made without the seeded user's session.
+    pub(super) fn anonymous(&self) -> &AsyncClient {
+        &self.client
+    }
+
+    pub(super) fn pool(&self) -> sqlx::SqlitePool {
+        let pool = db::Db::fetch(self.client.rocket()).expect("database pool");
+        (**pool).clone()
+    }
+
+    /// Reads the instance's `TestClock`.
+    pub(super) fn now(&self) -> DateTime<Utc> {
+        test_clock(self.client.rocket()).now()
+    }
+
+    /// The instance's `SequentialIds`, for the background tasks tests run themselves.
+    pub(super) fn ids(&self) -> &IdSource {
+        self.client.rocket().state::<IdSource>().expect("ID source is managed")
+    }
+
+    /// Inserts another user, returning their ID.
+    pub(super) async fn user_seed(&self, email: &str) -> i64 {
+        sqlx::query("INSERT INTO users (email) VALUES (?)")
+            .bind(email)
+            .execute(&self.pool())
+            .await
+            .expect("insert user")
+            .last_insert_rowid()
+    }
+
+    pub(super) async fn get(&self, uri: &str) -> AsyncResponse<'_> {
+        self.with_auth(self.client.get(uri.to_owned())).dispatch().await
+    }
+
+    pub(super) async fn post_json<T: Serialize>(&self, uri: &str, body: &T) -> AsyncResponse<'_> {
+        self.with_auth(self.client.post(uri.to_owned()).json(body))
+            .dispatch()
+            .await
+    }
+
+    pub(super) async fn put_json<T: Serialize>(&self, uri: &str, body: &T) -> AsyncResponse<'_> {
+        self.with_auth(self.client.put(uri.to_owned()).json(body))
+            .dispatch()
+            .await
+    }
+
+    pub(super) async fn delete(&self, uri: &str) -> AsyncResponse<'_> {
+        self.with_auth(self.client.delete(uri.to_owned())).dispatch().await
+    }
+
+    fn with_auth<'c>(&self, request: AsyncRequest<'c>) -> AsyncRequest<'c> {
+        let request = request
+            .cookie(Cookie::new(CSRF_COOKIE, CSRF_TOKEN_EXAMPLE))
+            .header(Header::new(CSRF_HEADER, CSRF_TOKEN_EXAMPLE));
+        match self.user_id {
+            Some(user_id) => request
+                .private_cookie(auth_cookie(user_id))
+                .private_cookie(session_cookie(session_id_for(user_id), true)),
+            None => request,
+        }
+    }
+}
+
+pub(super) const ADMIN_TOKEN_EXAMPLE: &str = "test-admin-token";
+
+/// Like `client_tracked_get`, but with the admin API enabled for `admin_header`.
+pub(super) fn client_with_admin() -> Client {
+    client_tracked_build(|rocket| rocket.manage(handlers::admin::AdminToken(Some(ADMIN_TOKEN_EXAMPLE.into()))))
+}
+
+/// The `Authorization` header of the admin API, see `client_with_admin`.
+pub(super) fn admin_header() -> Header<'static> {
+    bearer(ADMIN_TOKEN_EXAMPLE)
+}
+
+/// An `Authorization: Bearer` header carrying `token`.
+pub(super) fn bearer(token: &str) -> Header<'static> {
+    Header::new("Authorization", format!("Bearer {}", token))
+}
+
 pub(super) const CODE_EXAMPLE: &str = "12345678"; // Updated to 8 digits
 
 pub(super) fn email_for_session() -> String {
@@ -97,10 +452,17 @@ pub(super) fn fetch_user_by_email(client: &Client, email: &str) -> db::User {
     let pool = pool_cloned_get(client);
     let email_owned = email.to_owned();
     block_on(async move {
-        sqlx::query_as!(db::User, "SELECT * FROM users WHERE email = ?", email_owned)
-            .fetch_one(&pool)
-            .await
-            .expect("fetch user by email")
+        sqlx::query_as!(
+            db::User,
+            "SELECT id, created_at AS \"created_at: DateTime<Utc>\", email, code_hash, code_attempts, \
+            code_created_at AS \"code_created_at: DateTime<Utc>\", \
+            email_verified_at AS \"email_verified_at: DateTime<Utc>\", \
+            disabled_at AS \"disabled_at: DateTime<Utc>\" FROM users WHERE email = ?",
+            email_owned
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("fetch user by email")
     })
 }
 
@@ -109,10 +471,43 @@ pub(super) fn next_sequence() -> usize {
 }
 
 pub(super) fn pool_cloned_get(client: &Client) -> sqlx::SqlitePool {
-    let pool = db::Db::fetch(client.rocket()).expect("database pool");
+    pool_cloned_get_orbit(client.rocket())
+}
+
+fn pool_cloned_get_orbit(rocket: &Rocket<Orbit>) -> sqlx::SqlitePool {
+    let pool = db::Db::fetch(rocket).expect("database pool");
     (**pool).clone()
 }
 
+/// ID of the session `session_seed` opens for the user.
+pub(super) fn session_id_for(user_id: i64) -> String {
+    format!("session-{}", user_id)
+}
+
+/// Opens a remembered session for the user, unless it is already open, as logging in would. It
+/// expires in a thousand years, past any instance clock a test sets.
+pub(super) async fn session_seed(pool: sqlx::SqlitePool, user_id: i64) {
+    sqlx::query(
+        "INSERT OR IGNORE INTO sessions (id, user_id, expires_at) SELECT ?, id, ? FROM users WHERE id = ?",
+    )
+    .bind(session_id_for(user_id))
+    .bind(Utc::now() + chrono::Duration::days(365 * 1000))
+    .bind(user_id)
+    .execute(&pool)
+    .await
+    .expect("insert session");
+}
+
+/// Signs the request in as the user, with the auth and session cookies of a session opened by
+/// `session_seed`.
+pub(super) fn signed_in(request: LocalRequest<'_>, user_id: i64) -> LocalRequest<'_> {
+    let pool = pool_cloned_get_orbit(request.inner().rocket());
+    block_on(session_seed(pool, user_id));
+    request
+        .private_cookie(auth_cookie(user_id))
+        .private_cookie(session_cookie(session_id_for(user_id), true))
+}
+
 pub(super) fn seed_user(client: &Client, email: &str) -> i64 {
     let pool = pool_cloned_get(client);
     let email_owned = email.to_owned();
@@ -131,7 +526,7 @@ pub(super) fn seed_user_with_code(
     email: &str,
     code: &str,
     attempts: Option<i64>,
-    code_created_at: NaiveDateTime,
+    code_created_at: DateTime<Utc>,
 ) -> (i64, String) {
     let pool = pool_cloned_get(client);
     let email_owned = email.to_owned();
@@ -152,6 +547,63 @@ pub(super) fn seed_user_with_code(
     })
 }
 
+/// Gives the user (created if missing) a fresh login code.
+pub(super) fn seed_code(client: &Client, email: &str, code: &str) {
+    let pool = pool_cloned_get(client);
+    let email_owned = email.to_owned();
+    let code_owned = code.to_owned();
+    block_on(async move {
+        let hash = hash_code(&code_owned).await.expect("hash code");
+        sqlx::query(
+            "INSERT INTO users (email, code_attempts, code_created_at, code_hash) VALUES (?, 0, ?, ?) \
+            ON CONFLICT(email) DO UPDATE SET code_attempts = 0, code_created_at = excluded.code_created_at, \
+            code_hash = excluded.code_hash",
+        )
+        .bind(email_owned)
+        .bind(Utc::now())
+        .bind(&hash)
+        .execute(&pool)
+        .await
+        .expect("seed code");
+    })
+}
+
+/// Records the emails it is handed instead of sending them.
+#[derive(Default)]
+pub(super) struct MockSender {
+    sent: Mutex<Vec<Email>>,
+}
+
+impl MockSender {
+    pub(super) fn sent(&self) -> Vec<Email> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[rocket::async_trait]
+impl EmailSender for MockSender {
+    async fn send(&self, email: &Email) -> Result<Option<String>, DeliveryError> {
+        self.sent.lock().unwrap().push(email.clone());
+        Ok(None)
+    }
+}
+
+/// Like `client_tracked_get`, but with emails captured by the returned `MockSender`.
+pub(super) fn client_with_mailer() -> (Client, Arc<MockSender>) {
+    let sender = Arc::new(MockSender::default());
+    let mailer = Mailer(sender.clone());
+    (client_tracked_build(|rocket| rocket.manage(mailer)), sender)
+}
+
+/// Returns the login code in the body of a code email.
+pub(super) fn email_code_extract(email: &Email) -> Option<String> {
+    email
+        .body
+        .split(|c: char| !c.is_ascii_digit())
+        .find(|word| word.len() == 8)
+        .map(str::to_owned)
+}
+
 pub(super) fn assert_success(response: LocalResponse, expected: Status) {
     assert_eq!(response.status(), expected);
     if expected == Status::Ok || expected == Status::Created {
@@ -160,10 +612,14 @@ pub(super) fn assert_success(response: LocalResponse, expected: Status) {
     }
 }
 
+/// Runs a future to completion on a runtime shared by the blocking tests.
 pub(super) fn block_on<F, T>(future: F) -> T
 where
     F: Future<Output = T> + Send + 'static,
     T: Send + 'static,
 {
-    Runtime::new().expect("tokio runtime").block_on(future)
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME
+        .get_or_init(|| Runtime::new().expect("tokio runtime"))
+        .block_on(future)
 }
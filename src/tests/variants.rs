@@ -0,0 +1,109 @@
+use crate::tests::util::*;
+
+use rocket::http::Status;
+use rocket::serde::json;
+
+use crate::variants::*;
+
+const POSTS_BASE: &str = "/api/posts";
+
+const CHECKLIST: &str =
+    r#"{"todo": {"type": "object", "required": ["done"], "properties": {"done": {"type": "boolean"}}}}"#;
+
+#[test]
+fn variants_parse() {
+    let any = VariantRegistry::parse("").unwrap();
+    assert!(any.check("anything", "at all").is_ok());
+    assert!(!any.checks_content());
+
+    let names = VariantRegistry::parse("note, todo").unwrap();
+    assert!(names.check("todo", "free-form").is_ok());
+    assert!(names.check("Todo", "").unwrap_err().contains("note, todo"));
+    assert!(!names.checks_content());
+
+    let registry = VariantRegistry::parse(CHECKLIST).unwrap();
+    assert!(registry.checks_content());
+    for invalid in [
+        "[1]",
+        r#"{"todo": 1}"#,
+        r#"{"todo": {"type": "date"}}"#,
+        r#"{"todo": {"pattern": "^a"}}"#,
+        r#"{"todo": {"properties": {"done": {"required": "done"}}}}"#,
+    ] {
+        assert!(VariantRegistry::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn variants_check_content() {
+    let registry = VariantRegistry::parse(CHECKLIST).unwrap();
+    assert!(registry.check("todo", r#"{"done": false, "title": "Milk"}"#).is_ok());
+    for (content, error) in [
+        ("Buy milk", "must be JSON"),
+        ("[]", "content must be of type object"),
+        (r#"{"title": "Milk"}"#, "content.done is required"),
+        (r#"{"done": "no"}"#, "content.done must be of type boolean"),
+    ] {
+        let e = registry.check("todo", content).unwrap_err();
+        assert!(e.contains(error), "{}: {}", content, e);
+    }
+
+    let schema = json::json!({
+        "type": "array",
+        "items": { "type": "object", "additionalProperties": false, "properties": {
+            "step": { "type": "string", "minLength": 1 },
+            "state": { "enum": ["open", "closed"] },
+        } },
+    });
+    let schema = ContentSchema::parse(&schema).unwrap();
+    assert!(
+        schema
+            .check(&json::json!([{ "step": "a", "state": "open" }]), "content")
+            .is_ok()
+    );
+    assert_eq!(
+        schema.check(&json::json!([{ "step": "" }]), "content").unwrap_err(),
+        "content[0].step is too short"
+    );
+    assert!(schema.check(&json::json!([{ "state": "done" }]), "content").is_err());
+    assert_eq!(
+        schema.check(&json::json!([{ "extra": 1 }]), "content").unwrap_err(),
+        "content[0].extra is not allowed"
+    );
+}
+
+// The test environment allows note, todo, task and checklist, whose content needs a boolean `done`
+#[test]
+fn variants_enforced_on_writes() {
+    let client = ClientAuthenticated::new();
+    let post =
+        |id: &str, variant: &str, content: &str| json::json!({ "id": id, "variant": variant, "content": content });
+
+    let response = client.post_json(POSTS_BASE, &post("variant-unknown", "Note", "Hello"));
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let response = client.post_json(POSTS_BASE, &post("variant-bad", "checklist", r#"{"done": 1}"#));
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let body = response.into_json::<json::Value>().unwrap();
+    assert!(body["message"].as_str().unwrap().contains("content.done"));
+
+    let response = client.post_json(POSTS_BASE, &post("variant-ok", "checklist", r#"{"done": false}"#));
+    assert_eq!(response.status(), Status::Created);
+
+    let uri = format!("{}/upsert-many", POSTS_BASE);
+    let batch = vec![
+        post("variant-batch-ok", "note", "Fine"),
+        post("variant-batch-bad", "checklist", "not JSON"),
+    ];
+    assert_eq!(client.post_json(&uri, &batch).status(), Status::UnprocessableEntity);
+    assert_eq!(
+        client.get(&format!("{}/variant-batch-ok", POSTS_BASE)).status(),
+        Status::NotFound
+    );
+
+    // Updates are checked against the variant of the stored post
+    let uri = format!("{}/variant-ok", POSTS_BASE);
+    let response = client.put_json(&uri, &json::json!({ "content": "done" }));
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let response = client.put_json(&uri, &json::json!({ "content": r#"{"done": true}"# }));
+    assert_eq!(response.status(), Status::Ok);
+}
@@ -0,0 +1,118 @@
+use rocket::http::Status;
+use rocket::route::{self, Handler, Route};
+use rocket::tokio::time::{Duration, timeout};
+use rocket::{Data, Request};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::error::ApiError;
+use crate::util::*;
+
+/// Deadlines for request handlers. `REQUEST_TIMEOUT_SECS` sets the default (30s) and
+/// `REQUEST_TIMEOUT_OVERRIDES` overrides it per route, keyed by method and path (see `route_key`),
+/// e.g. `POST /posts/upsert-many=120,POST /session/send-code=45`. Handler names aren't unique
+/// across modules, so they can't be used.
+#[derive(Debug, Clone)]
+pub struct TimeoutConfig {
+    pub default: Duration,
+    pub overrides: HashMap<String, Duration>,
+}
+
+impl TimeoutConfig {
+    pub fn from_env() -> Self {
+        let overrides = env_list("REQUEST_TIMEOUT_OVERRIDES")
+            .into_iter()
+            .map(|item| {
+                let (name, secs) = item
+                    .split_once('=')
+                    .and_then(|(name, secs)| Some((name.trim().to_string(), secs.trim().parse().ok()?)))
+                    .unwrap_or_else(|| panic!("REQUEST_TIMEOUT_OVERRIDES has an invalid entry: {}", item));
+                (name, Duration::from_secs(secs))
+            })
+            .collect();
+
+        Self {
+            default: Duration::from_secs(env_parse_or("REQUEST_TIMEOUT_SECS", 30)),
+            overrides,
+        }
+    }
+
+    /// Returns the deadline for the route with the given `route_key`.
+    pub fn deadline(&self, key: &str) -> Duration {
+        self.overrides
+            .get(&key.to_ascii_lowercase())
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// Returns the process-wide `TimeoutConfig`.
+pub fn timeout_config() -> &'static TimeoutConfig {
+    static CONFIG: OnceLock<TimeoutConfig> = OnceLock::new();
+    CONFIG.get_or_init(TimeoutConfig::from_env)
+}
+
+/// Handler wrapper that aborts the inner handler once its deadline passes.
+#[derive(Clone)]
+struct TimeoutHandler {
+    inner: Box<dyn Handler>,
+    deadline: Duration,
+}
+
+#[rocket::async_trait]
+impl Handler for TimeoutHandler {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        match timeout(self.deadline, self.inner.handle(request, data)).await {
+            Ok(outcome) => outcome,
+            Err(_) => {
+                tracing::warn!(
+                    uri = %request.uri(),
+                    deadline_ms = self.deadline.as_millis() as u64,
+                    "request timed out"
+                );
+                route::Outcome::from(request, ApiError::from_status(Status::GatewayTimeout))
+            }
+        }
+    }
+}
+
+/// Wraps the handlers of `routes` so that each is aborted with a 504 JSON response once it runs
+/// longer than `deadline`. Dropping the handler future cancels any query or SMTP call in flight.
+pub fn routes_with_deadline(routes: Vec<Route>, deadline: Duration) -> Vec<Route> {
+    routes
+        .into_iter()
+        .map(|mut route| {
+            route.handler = Box::new(TimeoutHandler {
+                inner: route.handler,
+                deadline,
+            });
+            route
+        })
+        .collect()
+}
+
+/// Identifies `route` in `REQUEST_TIMEOUT_OVERRIDES`: its method and its path under `base`, without
+/// the query, e.g. `POST /posts/upsert-many`. `base` is the path given to `api_mount`, or the mount
+/// point of routes mounted outside the API.
+pub fn route_key(base: &str, route: &Route) -> String {
+    let path = route.uri.path();
+    let path = match (base.trim_end_matches('/'), path) {
+        ("", path) => path.to_string(),
+        (base, "/") => base.to_string(),
+        (base, path) => format!("{}{}", base, path),
+    };
+    format!("{} {}", route.method, path).to_ascii_lowercase()
+}
+
+/// Wraps the handlers of `routes`, to be mounted under `base`, with the deadlines from
+/// `TimeoutConfig`.
+pub fn timeout_routes(base: &str, routes: Vec<Route>) -> Vec<Route> {
+    let config = timeout_config();
+    routes
+        .into_iter()
+        .flat_map(|route| {
+            let deadline = config.deadline(&route_key(base, &route));
+            routes_with_deadline(vec![route], deadline)
+        })
+        .collect()
+}
@@ -0,0 +1,403 @@
+use rocket::fairing::{self, AdHoc, Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Method, Status};
+use rocket::request::{self, FromRequest};
+use rocket::response::Redirect;
+use rocket::{Build, Data, Request, Rocket};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::error::ApiError;
+use crate::util::*;
+
+/// HTTPS settings, for deployments without a reverse proxy terminating TLS in front of the app.
+///
+/// - `TLS_CERT_PATH` / `TLS_KEY_PATH`: PEM certificate chain and private key Rocket serves HTTPS
+///   with (requires the `tls` feature)
+/// - `ACME_DOMAINS`: domains to get a certificate for from Let's Encrypt instead, kept in
+///   `ACME_CACHE_DIR` (`acme`) with the account, as `ACME_EMAIL`; `ACME_STAGING=true` uses the
+///   staging directory (requires the `acme` feature)
+/// - `HTTPS_REDIRECT`: answer plain-HTTP requests with a 301 to HTTPS. When the app terminates
+///   TLS, a second listener on `HTTP_REDIRECT_PORT` (80) does the redirecting.
+/// - `TRUST_PROXY`: take the scheme of requests from the `Forwarded` or `X-Forwarded-Proto` header
+///   set by a proxy in front of the app. Only enable it when clients can't reach the app directly.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+    pub acme_domains: Vec<String>,
+    pub acme_email: Option<String>,
+    pub acme_dir: PathBuf,
+    pub acme_staging: bool,
+    pub redirect: bool,
+    pub redirect_port: u16,
+    pub trust_proxy: bool,
+}
+
+impl TlsConfig {
+    pub fn from_env() -> Self {
+        let config = Self {
+            cert_path: env_parse_opt("TLS_CERT_PATH"),
+            key_path: env_parse_opt("TLS_KEY_PATH"),
+            acme_domains: env_list("ACME_DOMAINS"),
+            acme_email: env_parse_opt("ACME_EMAIL"),
+            acme_dir: env_parse_or("ACME_CACHE_DIR", PathBuf::from("acme")),
+            acme_staging: env_parse_or("ACME_STAGING", false),
+            redirect: env_parse_or("HTTPS_REDIRECT", false),
+            redirect_port: env_parse_or("HTTP_REDIRECT_PORT", 80),
+            trust_proxy: env_parse_or("TRUST_PROXY", false),
+        };
+        if config.cert_path.is_some() != config.key_path.is_some() {
+            panic!("TLS_CERT_PATH and TLS_KEY_PATH must be set together");
+        }
+        if config.redirect && config.cert_files().is_none() && !config.trust_proxy {
+            // Every request would look like plain HTTP and be redirected
+            panic!("HTTPS_REDIRECT needs TLS_CERT_PATH, ACME_DOMAINS or TRUST_PROXY=true");
+        }
+        config
+    }
+
+    /// Returns the certificate chain and key files to serve HTTPS with, if the app terminates TLS.
+    pub fn cert_files(&self) -> Option<(PathBuf, PathBuf)> {
+        if !self.acme_domains.is_empty() {
+            return Some((self.acme_dir.join("cert.pem"), self.acme_dir.join("key.pem")));
+        }
+        Some((self.cert_path.clone()?, self.key_path.clone()?))
+    }
+}
+
+/// Returns the process-wide `TlsConfig`.
+pub fn tls_config() -> &'static TlsConfig {
+    static CONFIG: OnceLock<TlsConfig> = OnceLock::new();
+    CONFIG.get_or_init(TlsConfig::from_env)
+}
+
+/// Returns the scheme a proxy reports the client used: the `proto` of the last `Forwarded`
+/// element, else the last `X-Forwarded-Proto` value. The last ones are those added by the proxy
+/// next to the app; earlier ones may come from the client.
+pub fn forwarded_proto<'h>(forwarded: Option<&'h str>, x_forwarded_proto: Option<&'h str>) -> Option<&'h str> {
+    let proto = forwarded.and_then(|forwarded| {
+        forwarded
+            .rsplit(',')
+            .next()?
+            .split(';')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case("proto"))
+            .map(|(_, value)| value.trim().trim_matches('"'))
+    });
+    proto
+        .or_else(|| x_forwarded_proto?.rsplit(',').next().map(str::trim))
+        .filter(|proto| !proto.is_empty())
+}
+
+/// Whether the request reached the app over HTTPS, directly or, when `trust_proxy` is set,
+/// through a proxy.
+pub fn request_is_secure(request: &Request<'_>, trust_proxy: bool) -> bool {
+    if request.rocket().config().tls_enabled() {
+        return true;
+    }
+    let headers = request.headers();
+    trust_proxy
+        && forwarded_proto(headers.get_one("Forwarded"), headers.get_one("X-Forwarded-Proto"))
+            .is_some_and(|proto| proto.eq_ignore_ascii_case("https"))
+}
+
+/// Builds the HTTPS URL of a request, leaving the port out when it is the default one.
+pub fn https_url(domain: &str, port: Option<u16>, uri: &str) -> String {
+    match port {
+        Some(port) if port != 443 => format!("https://{}:{}{}", domain, port, uri),
+        _ => format!("https://{}{}", domain, uri),
+    }
+}
+
+/// Paths served over plain HTTP too: metrics are scraped from inside the network, and ACME
+/// validation starts over HTTP.
+const REDIRECT_EXEMPT: &[&str] = &["/metrics", "/.well-known/acme-challenge/"];
+
+/// Internal route plain-HTTP requests are rerouted to, so that no other handler runs for them.
+const REDIRECT_ROUTE: &str = "/__https-redirect";
+
+/// Where the redirect route sends the request, set by the `HttpsRedirect` fairing. `None` when
+/// the request had no `Host` to build the URL from.
+struct HttpsTarget(Option<String>);
+
+/// Fairing answering plain-HTTP requests with a 301 to the same URL over HTTPS.
+pub struct HttpsRedirect {
+    /// Whether to believe the scheme reported by a proxy.
+    pub trust_proxy: bool,
+    /// The port HTTPS is served on, when not the one the request came in on.
+    pub https_port: Option<u16>,
+}
+
+#[rocket::async_trait]
+impl Fairing for HttpsRedirect {
+    fn info(&self) -> Info {
+        Info {
+            name: "HTTPS Redirect",
+            kind: Kind::Ignite | Kind::Request,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        Ok(rocket.mount(REDIRECT_ROUTE, routes![https_redirect]))
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let path = request.uri().path();
+        if request_is_secure(request, self.trust_proxy) || REDIRECT_EXEMPT.iter().any(|exempt| path.starts_with(exempt))
+        {
+            return;
+        }
+        let target = request
+            .host()
+            .map(|host| https_url(host.domain().as_str(), self.https_port, &request.uri().to_string()));
+        request.local_cache(|| HttpsTarget(target));
+        request.set_method(Method::Get);
+        request.set_uri(Origin::parse(REDIRECT_ROUTE).expect("valid redirect route"));
+    }
+}
+
+/// Guard reading the target the `HttpsRedirect` fairing set for the request.
+struct HttpsTargetGuard<'r>(&'r Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for HttpsTargetGuard<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(HttpsTargetGuard(&request.local_cache(|| HttpsTarget(None)).0))
+    }
+}
+
+#[get("/")]
+fn https_redirect(target: HttpsTargetGuard<'_>) -> Result<Redirect, ApiError> {
+    match target.0 {
+        Some(url) => Ok(Redirect::moved(url.clone())),
+        None => Err(ApiError::from_status(Status::BadRequest)),
+    }
+}
+
+/// Builds the plain-HTTP listener that redirects to the app on `https_port`, and with the `acme`
+/// feature answers ACME HTTP-01 challenges.
+fn redirector_build(config: &TlsConfig, main: &rocket::Config, https_port: u16) -> Rocket<Build> {
+    let rocket = rocket::custom(rocket::Config {
+        port: config.redirect_port,
+        #[cfg(feature = "tls")]
+        tls: None,
+        ..main.clone()
+    })
+    .attach(HttpsRedirect {
+        trust_proxy: false,
+        https_port: Some(https_port),
+    });
+    #[cfg(feature = "acme")]
+    let rocket = rocket
+        .manage(acme::challenges().clone())
+        .mount("/.well-known/acme-challenge", routes![acme::challenge]);
+    rocket
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("TLS stage", |rocket| async move {
+        let config = tls_config();
+        let Some((cert, key)) = config.cert_files() else {
+            return match config.redirect {
+                true => Ok(rocket.attach(HttpsRedirect {
+                    trust_proxy: config.trust_proxy,
+                    https_port: None,
+                })),
+                false => Ok(rocket),
+            };
+        };
+        if cfg!(not(feature = "tls")) {
+            tracing::error!("TLS_CERT_PATH and ACME_DOMAINS require the `tls` feature");
+            return Err(rocket);
+        }
+        if !config.acme_domains.is_empty() && cfg!(not(feature = "acme")) {
+            tracing::error!("ACME_DOMAINS requires the `acme` feature");
+            return Err(rocket);
+        }
+
+        if config.redirect || !config.acme_domains.is_empty() {
+            let main = match rocket.figment().extract::<rocket::Config>() {
+                Ok(main) => main,
+                Err(e) => {
+                    tracing::error!("Invalid Rocket config: {}", e);
+                    return Err(rocket);
+                }
+            };
+            let redirector = redirector_build(config, &main, main.port);
+            rocket::tokio::spawn(async move {
+                tracing::info!(port = config.redirect_port, "http redirect listening");
+                if let Err(e) = redirector.launch().await {
+                    tracing::error!("http redirect listener failed: {}", e);
+                }
+            });
+        }
+        #[cfg(feature = "acme")]
+        if !config.acme_domains.is_empty() {
+            if let Err(e) = acme::certificate_ensure(config).await {
+                tracing::error!("ACME certificate request failed: {}", e);
+                return Err(rocket);
+            }
+        }
+
+        let figment = rocket
+            .figment()
+            .clone()
+            .merge(("tls.certs", cert))
+            .merge(("tls.key", key));
+        Ok(rocket.configure(figment))
+    })
+}
+
+/// Let's Encrypt certificates over the ACME HTTP-01 challenge.
+///
+/// The certificate is requested at startup when there is none yet or it is older than
+/// `RENEW_AFTER_DAYS`. Rocket loads certificates once, at launch, so restart the app at least
+/// monthly (e.g. from a timer) to keep it renewed.
+#[cfg(feature = "acme")]
+mod acme {
+    use instant_acme::{
+        Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, LetsEncrypt, NewAccount, NewOrder,
+        OrderStatus,
+    };
+    use rcgen::{CertificateParams, DistinguishedName, KeyPair};
+    use rocket::State;
+    use rocket::tokio::time::{Duration, sleep};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::sync::{Arc, Mutex, OnceLock};
+    use std::time::SystemTime;
+
+    use super::TlsConfig;
+
+    /// Let's Encrypt certificates last 90 days.
+    const RENEW_AFTER_DAYS: u64 = 60;
+
+    /// Key authorizations of the pending challenges, by token.
+    pub type Challenges = Arc<Mutex<HashMap<String, String>>>;
+
+    /// Returns the challenges shared with the plain-HTTP listener.
+    pub fn challenges() -> &'static Challenges {
+        static CHALLENGES: OnceLock<Challenges> = OnceLock::new();
+        CHALLENGES.get_or_init(Challenges::default)
+    }
+
+    #[get("/<token>")]
+    pub fn challenge(token: &str, challenges: &State<Challenges>) -> Option<String> {
+        challenges.lock().unwrap().get(token).cloned()
+    }
+
+    /// Requests a certificate for the configured domains unless a fresh one is cached.
+    pub async fn certificate_ensure(config: &TlsConfig) -> Result<(), String> {
+        let (cert_path, key_path) = config.cert_files().expect("ACME domains are configured");
+        let age = fs::metadata(&cert_path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+        if age.is_some_and(|age| age.as_secs() < RENEW_AFTER_DAYS * 24 * 3600) {
+            return Ok(());
+        }
+        fs::create_dir_all(&config.acme_dir).map_err(|e| e.to_string())?;
+        tracing::info!(domains = ?config.acme_domains, "requesting ACME certificate");
+
+        let account = account_load(config).await?;
+        let identifiers = config
+            .acme_domains
+            .iter()
+            .map(|domain| Identifier::Dns(domain.clone()))
+            .collect::<Vec<_>>();
+        let mut order = account
+            .new_order(&NewOrder {
+                identifiers: &identifiers,
+            })
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut ready = Vec::new();
+        for authz in order.authorizations().await.map_err(|e| e.to_string())? {
+            if matches!(authz.status, AuthorizationStatus::Valid) {
+                continue;
+            }
+            let challenge = authz
+                .challenges
+                .iter()
+                .find(|challenge| challenge.r#type == ChallengeType::Http01)
+                .ok_or("no HTTP-01 challenge offered")?;
+            let key_authorization = order.key_authorization(challenge);
+            challenges()
+                .lock()
+                .unwrap()
+                .insert(challenge.token.clone(), key_authorization.as_str().to_string());
+            ready.push(challenge.url.clone());
+        }
+        for url in &ready {
+            order.set_challenge_ready(url).await.map_err(|e| e.to_string())?;
+        }
+
+        let mut delay = Duration::from_millis(250);
+        loop {
+            sleep(delay).await;
+            let state = order.refresh().await.map_err(|e| e.to_string())?;
+            match state.status {
+                OrderStatus::Ready => break,
+                OrderStatus::Invalid => return Err("order was refused by the ACME server".into()),
+                _ if delay > Duration::from_secs(30) => return Err("order was not validated in time".into()),
+                _ => delay *= 2,
+            }
+        }
+        challenges().lock().unwrap().clear();
+
+        let key = KeyPair::generate().map_err(|e| e.to_string())?;
+        let mut params = CertificateParams::new(config.acme_domains.clone()).map_err(|e| e.to_string())?;
+        params.distinguished_name = DistinguishedName::new();
+        let csr = params.serialize_request(&key).map_err(|e| e.to_string())?;
+        order.finalize(csr.der()).await.map_err(|e| e.to_string())?;
+        let chain = loop {
+            match order.certificate().await.map_err(|e| e.to_string())? {
+                Some(chain) => break chain,
+                None => sleep(Duration::from_secs(1)).await,
+            }
+        };
+
+        fs::write(&key_path, key.serialize_pem()).map_err(|e| e.to_string())?;
+        fs::write(&cert_path, chain).map_err(|e| e.to_string())?;
+        tracing::info!("ACME certificate issued");
+        Ok(())
+    }
+
+    /// Loads the ACME account from the cache directory, registering it on first use.
+    async fn account_load(config: &TlsConfig) -> Result<Account, String> {
+        let path = config.acme_dir.join("account.json");
+        if let Ok(saved) = fs::read_to_string(&path) {
+            let credentials: AccountCredentials = rocket::serde::json::from_str(&saved).map_err(|e| e.to_string())?;
+            return Account::from_credentials(credentials).await.map_err(|e| e.to_string());
+        }
+
+        let contact = config
+            .acme_email
+            .iter()
+            .map(|email| format!("mailto:{}", email))
+            .collect::<Vec<_>>();
+        let contact = contact.iter().map(String::as_str).collect::<Vec<_>>();
+        let directory = match config.acme_staging {
+            true => LetsEncrypt::Staging.url(),
+            false => LetsEncrypt::Production.url(),
+        };
+        let (account, credentials) = Account::create(
+            &NewAccount {
+                contact: &contact,
+                terms_of_service_agreed: true,
+                only_return_existing: false,
+            },
+            directory,
+            None,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+        let saved = rocket::serde::json::to_string(&credentials).map_err(|e| e.to_string())?;
+        fs::write(&path, saved).map_err(|e| e.to_string())?;
+        Ok(account)
+    }
+}
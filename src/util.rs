@@ -1,21 +1,27 @@
 use argon2::password_hash::{PasswordHash, SaltString, rand_core::OsRng};
 use argon2::{Argon2, PasswordHasher, PasswordVerifier};
-pub use chrono::NaiveDateTime;
 pub use chrono::{DateTime, Utc};
 pub use futures::{future::TryFutureExt, stream::TryStreamExt};
-use mail_struct::Mail;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use rocket::http;
-use rocket::outcome::IntoOutcome;
 use rocket::request;
 use rocket::serde::{self, Deserialize, Serialize};
-use rocket::tokio::sync::Semaphore;
+use rocket::tokio::sync::{Semaphore, SemaphorePermit};
 use rocket::tokio::task::spawn_blocking;
 use rocket::tokio::time::{Duration, timeout};
-use rocket::{Request, futures};
-use smtp_send::Send;
-use std::{env, sync::OnceLock};
+use rocket::{Build, Request, Rocket, futures};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::Instrument;
+
+use crate::authz::Authz;
+use crate::client_info::ClientIp;
+pub use crate::config::{AppConfig, EnvVars, app_config, app_config_managed, config_var, env_get};
+use crate::db::{Database, Db, query_span, sqlx};
+use crate::error::{ApiError, ErrorCode, guard_error_set};
+use crate::handlers::session::session_slide;
+use crate::telemetry::RequestUser;
 
 /// Returns the application mode as a string: "debug" if the profile is "debug", otherwise "production".
 pub fn app_mode() -> &'static str {
@@ -26,12 +32,50 @@ pub fn app_mode() -> &'static str {
     })
 }
 
+/// How long a login session lasts when the user does not ask to be remembered.
+pub const SESSION_TTL_SHORT_HOURS: i64 = 24;
+/// How long a login session lasts when the user asks to be remembered.
+pub const SESSION_TTL_REMEMBERED_DAYS: i64 = 30;
+
+/// Builds the auth cookie for a short-lived session.
 pub fn auth_cookie(user_id: i64) -> http::Cookie<'static> {
+    auth_cookie_with_lifetime(user_id, false)
+}
+
+/// Builds the auth cookie, persisting it for `SESSION_TTL_REMEMBERED_DAYS` when `remember_me`
+/// is set and for `SESSION_TTL_SHORT_HOURS` otherwise.
+pub fn auth_cookie_with_lifetime(user_id: i64, remember_me: bool) -> http::Cookie<'static> {
     http::Cookie::build(("user_id", user_id.to_string()))
         .http_only(false)
+        .max_age(session_max_age(remember_me))
+        .build()
+}
+
+/// Name of the private cookie referencing the current row in the `sessions` table.
+pub const SESSION_COOKIE: &str = "session_id";
+
+/// Builds the cookie referencing a row in the `sessions` table, living as long as the auth cookie.
+pub fn session_cookie(session_id: String, remember_me: bool) -> http::Cookie<'static> {
+    http::Cookie::build((SESSION_COOKIE, session_id))
+        .http_only(true)
+        .max_age(session_max_age(remember_me))
         .build()
 }
 
+/// Returns the cookie max-age for a session, depending on whether the user asked to be remembered.
+pub fn session_max_age(remember_me: bool) -> rocket::time::Duration {
+    if remember_me {
+        rocket::time::Duration::days(SESSION_TTL_REMEMBERED_DAYS)
+    } else {
+        rocket::time::Duration::hours(SESSION_TTL_SHORT_HOURS)
+    }
+}
+
+/// Public base URL of the app, used to build links in emails. Read from `APP_URL`.
+pub fn app_url() -> &'static str {
+    &app_config().app_url
+}
+
 /// Validates if the given email is in a valid format.
 pub fn email_is_valid(email: &str) -> bool {
     static EMAIL_RE: OnceLock<Regex> = OnceLock::new();
@@ -41,121 +85,385 @@ pub fn email_is_valid(email: &str) -> bool {
     regex.is_match(email)
 }
 
-/// Struct to hold required environment variables.
-#[derive(Debug)]
-pub struct EnvVars {
-    pub database_url: String,
-    pub rocket_databases: String,
-    pub dkim_key_public: String,
-    pub dkim_key_private: String,
-    pub rocket_secret_key: String,
-}
-
-/// Loads and validates required environment variables into an `EnvVars` struct.
-/// Throws an error if any required variable is missing.
-pub fn env_get() -> &'static EnvVars {
-    static ENV_VARS: OnceLock<EnvVars> = OnceLock::new();
-
-    ENV_VARS.get_or_init(|| EnvVars {
-        database_url: env::var("DATABASE_URL").expect("DATABASE_URL must be set"),
-        rocket_databases: env::var("ROCKET_DATABASES").expect("ROCKET_DATABASES must be set"),
-        dkim_key_public: env::var("DKIM_KEY_PUBLIC")
-            .expect("DKIM_KEY_PUBLIC must be set")
-            .replace("\\n", "\n"),
-        dkim_key_private: env::var("DKIM_KEY_PRIVATE")
-            .expect("DKIM_KEY_PRIVATE must be set")
-            .replace("\\n", "\n"),
-        rocket_secret_key: env::var("ROCKET_SECRET_KEY").expect("ROCKET_SECRET_KEY must be set"),
+/// Well-known disposable/throwaway email providers rejected when `EMAIL_DENY_DISPOSABLE` is set.
+const DISPOSABLE_EMAIL_DOMAINS: &[&str] = &[
+    "10minutemail.com",
+    "dispostable.com",
+    "fakeinbox.com",
+    "getnada.com",
+    "guerrillamail.com",
+    "maildrop.cc",
+    "mailinator.com",
+    "mintemail.com",
+    "mohmal.com",
+    "sharklasers.com",
+    "temp-mail.org",
+    "tempmail.com",
+    "throwawaymail.com",
+    "trashmail.com",
+    "yopmail.com",
+];
+
+/// Decides which email domains may sign up. Loaded from the environment:
+/// `EMAIL_DOMAIN_ALLOWLIST` and `EMAIL_DOMAIN_DENYLIST` (comma-separated domains, subdomains
+/// included), `EMAIL_DENY_DISPOSABLE` and `EMAIL_MX_CHECK` (booleans).
+#[derive(Debug, Clone, Default)]
+pub struct EmailDomainPolicy {
+    /// When non-empty, only these domains may sign up.
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    pub deny_disposable: bool,
+    /// Require the domain to publish at least one MX record.
+    pub mx_check: bool,
+}
+
+impl EmailDomainPolicy {
+    pub fn from_env() -> Self {
+        Self {
+            allow: env_list("EMAIL_DOMAIN_ALLOWLIST"),
+            deny: env_list("EMAIL_DOMAIN_DENYLIST"),
+            deny_disposable: env_parse_or("EMAIL_DENY_DISPOSABLE", false),
+            mx_check: env_parse_or("EMAIL_MX_CHECK", false),
+        }
+    }
+
+    /// Checks the domain against the allow/deny lists, returning the reason when it is rejected.
+    pub fn check_lists(&self, domain: &str) -> Result<(), &'static str> {
+        let domain = domain.to_ascii_lowercase();
+        if !self.allow.is_empty() && !self.allow.iter().any(|d| domain_matches(&domain, d)) {
+            return Err("email domain is not allowed");
+        }
+        if self.deny.iter().any(|d| domain_matches(&domain, d)) {
+            return Err("email domain is not allowed");
+        }
+        if self.deny_disposable && DISPOSABLE_EMAIL_DOMAINS.iter().any(|d| domain_matches(&domain, d)) {
+            return Err("disposable email addresses are not allowed");
+        }
+        Ok(())
+    }
+}
+
+/// Returns `true` if `domain` is `pattern` or one of its subdomains.
+fn domain_matches(domain: &str, pattern: &str) -> bool {
+    domain == pattern || domain.strip_suffix(pattern).is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+/// Returns the process-wide `EmailDomainPolicy`.
+pub fn email_domain_policy() -> &'static EmailDomainPolicy {
+    static POLICY: OnceLock<EmailDomainPolicy> = OnceLock::new();
+    POLICY.get_or_init(EmailDomainPolicy::from_env)
+}
+
+/// Checks whether the given (already syntactically valid) email may be used to sign up, applying
+/// the domain lists and, when enabled, the MX-record check.
+pub async fn email_domain_check(email: &str) -> Result<(), &'static str> {
+    let policy = email_domain_policy();
+    let domain = email.rsplit_once('@').map(|(_, domain)| domain).unwrap_or_default();
+    policy.check_lists(domain)?;
+
+    if policy.mx_check && !email_domain_has_mx(domain).await {
+        return Err("email domain does not accept mail");
+    }
+    Ok(())
+}
+
+/// Looks up the MX records of a domain. Fails open when the resolver itself is unavailable so that
+/// a DNS outage doesn't block every signup.
+async fn email_domain_has_mx(domain: &str) -> bool {
+    use hickory_resolver::TokioAsyncResolver;
+    use hickory_resolver::error::ResolveErrorKind;
+
+    static RESOLVER: OnceLock<Option<TokioAsyncResolver>> = OnceLock::new();
+    let resolver = RESOLVER.get_or_init(|| {
+        TokioAsyncResolver::tokio_from_system_conf()
+            .inspect_err(|e| tracing::warn!("MX check disabled, failed to build DNS resolver: {}", e))
+            .ok()
+    });
+    let Some(resolver) = resolver else {
+        return true;
+    };
+
+    match resolver.mx_lookup(format!("{}.", domain)).await {
+        Ok(lookup) => lookup.iter().next().is_some(),
+        Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => false,
+        Err(e) => {
+            tracing::warn!(domain, "MX lookup failed, allowing signup: {}", e);
+            true
+        }
+    }
+}
+
+/// Manages the state built by `default` unless `rocket` already manages a `T`, so that embedders
+/// and tests can manage their own before ignition.
+pub fn manage_default<T: Send + Sync + 'static>(
+    rocket: Rocket<Build>,
+    default: impl FnOnce(&Rocket<Build>) -> T,
+) -> Rocket<Build> {
+    if rocket.state::<T>().is_some() {
+        return rocket;
+    }
+    let state = default(&rocket);
+    rocket.manage(state)
+}
+
+/// Parses an optional comma-separated setting (or list in `Rocket.toml`) into a lowercased list.
+pub fn env_list(name: &str) -> Vec<String> {
+    config_var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(|item| item.trim().to_ascii_lowercase())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Parses an optional setting (see `config_var`), falling back to `default` when it is unset.
+/// Panics if the setting is set but cannot be parsed.
+pub fn env_parse_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match config_var(name) {
+        Some(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{} has an invalid value: {}", name, value)),
+        None => default,
+    }
+}
+
+/// Parses an optional setting (see `config_var`), returning `None` when it is unset.
+/// Panics if the setting is set but cannot be parsed.
+pub fn env_parse_opt<T: std::str::FromStr>(name: &str) -> Option<T> {
+    config_var(name).map(|value| {
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("{} has an invalid value: {}", name, value))
     })
 }
 
+/// Generates a random 8-digit login code.
+pub fn code_gen() -> String {
+    (0..8)
+        .map(|_| rand::random::<u8>() % 10)
+        .map(|digit| digit.to_string())
+        .collect()
+}
+
 /// Validates if the given code is a 8-digit numeric string.
 pub fn code_is_valid(code: &str) -> bool {
     code.len() == 8 && code.chars().all(|c| c.is_ascii_digit())
 }
 
-/// Sends an email using the `smtp_send` crate with DKIM signing.
-pub async fn email_send(from: &str, to: &str, subject: &str, body: &str) {
-    if app_mode() == "debug" {
-        info!(
-            "Email send simulated (debug mode): from={}, to={}, subject={}, body={}",
-            from, to, subject, body
-        );
-        return;
+/// Error returned by the hashing helpers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashError {
+    /// Hashing capacity is saturated; the caller should shed the request and ask the client to retry.
+    Saturated,
+    /// Hashing itself failed or timed out.
+    Failed(&'static str),
+}
+
+/// Bounds the number of concurrent hashing operations and the number of callers allowed to wait for
+/// one, so that a flood of requests is rejected early instead of queueing indefinitely.
+pub(crate) struct HashLimiter {
+    semaphore: Semaphore,
+    waiting: AtomicUsize,
+    queue_max: usize,
+    queue_timeout: Duration,
+}
+
+impl HashLimiter {
+    pub(crate) fn new(concurrency: usize, queue_max: usize, queue_timeout: Duration) -> Self {
+        Self {
+            semaphore: Semaphore::new(concurrency),
+            waiting: AtomicUsize::new(0),
+            queue_max,
+            queue_timeout,
+        }
     }
 
-    let sk = env_get().dkim_key_private.as_bytes().to_vec();
+    /// Acquires a hashing permit, waiting at most `queue_timeout` and only if fewer than `queue_max`
+    /// callers are already waiting.
+    pub(crate) async fn acquire(&self) -> Result<SemaphorePermit<'_>, HashError> {
+        if let Ok(permit) = self.semaphore.try_acquire() {
+            return Ok(permit);
+        }
 
-    // Create sender with DKIM selector
-    let sender = Send::new("default", &sk);
+        if self.waiting.fetch_add(1, Ordering::SeqCst) >= self.queue_max {
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+            return Err(HashError::Saturated);
+        }
+        let result = timeout(self.queue_timeout, self.semaphore.acquire()).await;
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
 
-    // Build email
-    let mut mail = Mail::new(
-        from,
-        [to],
-        // b"Subject: Test\r\n\r\nHello".to_vec(),
-        format!("Subject: {}\r\n\r\n{}", subject, body).into_bytes(),
-    )
-    .unwrap();
+        match result {
+            Ok(Ok(permit)) => Ok(permit),
+            Ok(Err(_)) => Err(HashError::Failed("semaphore closed")),
+            Err(_) => Err(HashError::Saturated),
+        }
+    }
 
-    // Send email
-    let result = sender.send(&mut mail).await;
+    /// Seconds a shed client should wait before retrying.
+    pub(crate) fn retry_after_secs(&self) -> u64 {
+        self.queue_timeout.as_secs().max(1)
+    }
+}
+
+/// Returns the shared limiter for hashing operations, configured from `HashConfig`.
+pub(crate) fn hash_limiter() -> &'static HashLimiter {
+    static LIMITER: OnceLock<HashLimiter> = OnceLock::new();
+    LIMITER.get_or_init(|| {
+        let config = hash_config();
+        HashLimiter::new(
+            config.concurrency,
+            config.queue_max,
+            Duration::from_millis(config.queue_timeout_ms),
+        )
+    })
+}
 
-    println!("sent: {}, errors: {}", result.success, result.error_li.len());
+/// Seconds a client shed because of hashing saturation should wait before retrying.
+pub fn hash_retry_after_secs() -> u64 {
+    hash_limiter().retry_after_secs()
 }
 
-/// Returns a static semaphore for limiting concurrent hashing operations.
-fn hash_semaphore() -> &'static Semaphore {
-    static SEMAPHORE: OnceLock<Semaphore> = OnceLock::new();
-    SEMAPHORE.get_or_init(|| Semaphore::const_new(8))
+/// Argon2 settings shared by `hash_code` and `hash_code_verify`, plus the limits of the hashing
+/// worker pool. Every field can be overridden with the matching `HASH_*` environment variable.
+#[derive(Debug, Clone)]
+pub struct HashConfig {
+    pub algorithm: argon2::Algorithm,
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    /// Maximum number of concurrent hashing operations.
+    pub concurrency: usize,
+    /// Maximum number of requests allowed to wait for a hashing slot before new ones are shed.
+    pub queue_max: usize,
+    /// How long a request may wait for a hashing slot before it is shed.
+    pub queue_timeout_ms: u64,
+}
+
+impl HashConfig {
+    /// Loads the config from the environment. The defaults use a much lower memory cost than
+    /// Argon2's, which is plenty for a short-lived login code on a small server.
+    pub fn from_env() -> Self {
+        Self {
+            algorithm: env_parse_or("HASH_ALGORITHM", argon2::Algorithm::Argon2i),
+            memory_kib: env_parse_or("HASH_MEMORY_KIB", 3000),
+            iterations: env_parse_or("HASH_ITERATIONS", 3),
+            parallelism: env_parse_or("HASH_PARALLELISM", 4),
+            concurrency: env_parse_or("HASH_CONCURRENCY", 8),
+            queue_max: env_parse_or("HASH_QUEUE_MAX", 32),
+            queue_timeout_ms: env_parse_or("HASH_QUEUE_TIMEOUT_MS", 2000),
+        }
+    }
+
+    /// Builds an Argon2 hasher for these settings.
+    pub fn argon2(&self) -> Argon2<'static> {
+        let params = argon2::Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .expect("invalid argon2 params in HashConfig");
+        Argon2::new(self.algorithm, argon2::Version::V0x13, params)
+    }
+
+    /// Returns `true` if the hash was produced with exactly these settings.
+    pub fn is_current(&self, hash: &PasswordHash) -> bool {
+        let Ok(params) = argon2::Params::try_from(hash) else {
+            return false;
+        };
+        hash.algorithm == self.algorithm.ident()
+            && hash.version == Some(argon2::Version::V0x13.into())
+            && params.m_cost() == self.memory_kib
+            && params.t_cost() == self.iterations
+            && params.p_cost() == self.parallelism
+    }
+}
+
+/// Returns the process-wide `HashConfig`.
+pub fn hash_config() -> &'static HashConfig {
+    static CONFIG: OnceLock<HashConfig> = OnceLock::new();
+    CONFIG.get_or_init(HashConfig::from_env)
+}
+
+/// Returns `true` if the stored hash was produced with settings other than the current `HashConfig`.
+pub fn hash_needs_rehash(hash: &str) -> bool {
+    !PasswordHash::new(hash).is_ok_and(|parsed| hash_config().is_current(&parsed))
 }
 
 /// Hashes the given code using the Argon2 algorithm.
-/// Returns the hashed code as a `String` or an error message.
-pub async fn hash_code(code: &str) -> Result<String, &'static str> {
-    let _permit = hash_semaphore().acquire().await.map_err(|_| "semaphore closed")?;
+/// Returns the hashed code as a `String` or an error.
+pub async fn hash_code(code: &str) -> Result<String, HashError> {
+    let _permit = hash_limiter().acquire().await?;
     let salt = SaltString::generate(&mut OsRng);
-    // Here we reduce memory cost because the default is much higher than we need for a temporal code
-    // and we don't have a big server
-    let params = argon2::Params::new(3000, 3, 4, None).unwrap();
-    let argon2 = Argon2::new(argon2::Algorithm::Argon2i, argon2::Version::V0x13, params);
-    // let argon2 = Argon2::default(); // defaults, which are more resource intensive
+    let argon2 = hash_config().argon2();
     let code = code.as_bytes().to_vec();
     let result = timeout(
         Duration::from_secs(5),
         spawn_blocking(move || argon2.hash_password(&code, &salt).map(|hash| hash.to_string())),
     )
     .await
-    .map_err(|_| "hash timeout")?;
+    .map_err(|_| HashError::Failed("hash timeout"))?;
 
-    result.map_err(|_| "hash join error")?.map_err(|_| "hash error")
+    result
+        .map_err(|_| HashError::Failed("hash join error"))?
+        .map_err(|_| HashError::Failed("hash error"))
 }
 
 /// Verifies if the given code matches the provided hash using the Argon2 algorithm.
 /// Returns `true` if the code matches, otherwise `false`.
-pub async fn hash_code_verify(hash: &str, code: &str) -> Result<bool, &'static str> {
-    let _permit = hash_semaphore().acquire().await.map_err(|_| "semaphore closed")?;
-    // Here we reduce memory cost because the default is much higher than we need for a temporal code
-    // and we don't have a big server
-    let params = argon2::Params::new(3000, 3, 4, None).unwrap();
-    let argon2 = Argon2::new(argon2::Algorithm::Argon2i, argon2::Version::V0x13, params);
-    // let argon2 = Argon2::default(); // defaults, which are more resource intensive
+pub async fn hash_code_verify(hash: &str, code: &str) -> Result<bool, HashError> {
+    hash_code_verify_inner(hash, code, false)
+        .await
+        .map(|verification| verification.verified)
+}
+
+/// Outcome of `hash_code_verify_rehash`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashVerification {
+    pub verified: bool,
+    /// A fresh hash of the code under the current `HashConfig`, present when the code verified
+    /// against a hash produced with outdated settings. Callers should persist it.
+    pub rehash: Option<String>,
+}
+
+/// Like `hash_code_verify`, but transparently re-hashes the code when it verifies against a hash
+/// produced with settings other than the current `HashConfig`.
+pub async fn hash_code_verify_rehash(hash: &str, code: &str) -> Result<HashVerification, HashError> {
+    hash_code_verify_inner(hash, code, true).await
+}
+
+async fn hash_code_verify_inner(hash: &str, code: &str, rehash: bool) -> Result<HashVerification, HashError> {
+    let _permit = hash_limiter().acquire().await?;
+    // The hasher only supplies the settings for a re-hash; verification uses those encoded in the hash
+    let config = hash_config();
+    let argon2 = config.argon2();
     let hash = hash.to_owned();
     let code = code.as_bytes().to_vec();
     let result = timeout(
         Duration::from_secs(5),
         spawn_blocking(move || {
+            let unverified = HashVerification {
+                verified: false,
+                rehash: None,
+            };
             let parsed_hash = match PasswordHash::new(&hash) {
                 Ok(h) => h,
-                Err(_) => return Ok(false),
+                Err(_) => return Ok(unverified),
             };
-            Ok(argon2.verify_password(&code, &parsed_hash).is_ok())
+            if argon2.verify_password(&code, &parsed_hash).is_err() {
+                return Ok(unverified);
+            }
+            let rehash = if rehash && !config.is_current(&parsed_hash) {
+                let salt = SaltString::generate(&mut OsRng);
+                let new_hash = argon2
+                    .hash_password(&code, &salt)
+                    .map_err(|_| HashError::Failed("hash error"))?;
+                Some(new_hash.to_string())
+            } else {
+                None
+            };
+            Ok(HashVerification { verified: true, rehash })
         }),
     )
     .await
-    .map_err(|_| "verify timeout")?;
+    .map_err(|_| HashError::Failed("verify timeout"))?;
 
-    result.map_err(|_| "verify join error")?
+    result.map_err(|_| HashError::Failed("verify join error"))?
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -171,121 +479,183 @@ pub static MESSAGE_RESPONSE_SUCCESS: Lazy<MessageResponse> = Lazy::new(|| Messag
 // MessageResponse { message: "success".into() }
 // do teh above as a static var
 
-/// Extension trait for `NaiveDateTime` providing additional utility methods.
-pub trait NaiveDateTimeExt {
-    fn now() -> NaiveDateTime;
-    fn parse_from_rfc3339(timestamp: String) -> NaiveDateTime;
-    fn serializer<S>(ndt: &NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer;
-    fn deserializer<'de, D>(deserializer: D) -> Result<NaiveDateTime, D::Error>
-    where
-        D: serde::Deserializer<'de>;
-    fn serializer_option<S>(ndt: &Option<NaiveDateTime>, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer;
-    fn deserializer_option<'de, D>(deserializer: D) -> Result<Option<NaiveDateTime>, D::Error>
-    where
-        D: serde::Deserializer<'de>;
-    fn to_datetime(self) -> DateTime<Utc>;
-    fn to_rfc3339(self) -> String;
-}
-
-impl NaiveDateTimeExt for NaiveDateTime {
-    /// Returns the current UTC time as a `NaiveDateTime`.
-    fn now() -> NaiveDateTime {
-        let now: DateTime<Utc> = chrono::Utc::now();
-        now.naive_utc()
-    }
+/// Parses the timestamp query parameter `name`: an RFC3339 timestamp, a `YYYY-MM-DD` date (midnight
+/// UTC) or milliseconds since the Unix epoch. Anything else is a validation error.
+pub fn timestamp_param_parse(name: &str, text: &str) -> Result<DateTime<Utc>, ApiError> {
+    let parsed = if !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit()) {
+        text.parse().ok().and_then(DateTime::from_timestamp_millis)
+    } else if let Ok(date) = chrono::NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        date.and_hms_opt(0, 0, 0).map(|at| at.and_utc())
+    } else {
+        DateTime::parse_from_rfc3339(text).ok().map(|at| at.to_utc())
+    };
+    parsed.ok_or_else(|| {
+        ApiError::validation(format!(
+            "`{}` must be an RFC3339 timestamp (2024-01-31T12:00:00Z), a date (2024-01-31) or epoch milliseconds",
+            name
+        ))
+    })
+}
 
-    /// Parses a timestamp in RFC3339 format into a `NaiveDateTime`.
-    fn parse_from_rfc3339(timestamp: String) -> NaiveDateTime {
-        let dt = DateTime::parse_from_rfc3339(&timestamp)
-            .unwrap_or_else(|_| panic!("Invalid timestamp format: {}", timestamp));
-        dt.naive_utc()
-    }
+/// Represents the user context extracted from request cookies.
+#[derive(Debug, serde::Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UserCtx {
+    pub id: i64,
+}
 
-    /// Converts a `NaiveDateTime` to a `DateTime<Utc>`.
-    fn to_datetime(self) -> DateTime<Utc> {
-        self.and_utc()
-    }
+/// Information about the caller's client, for session tracking and audit purposes.
+#[derive(Debug, Clone, Default)]
+pub struct RequestMeta {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
 
-    /// Converts a `NaiveDateTime` to an RFC3339 formatted string.
-    fn to_rfc3339(self) -> String {
-        let str = self.to_datetime().to_rfc3339();
-        // replace +00:00 with Z for UTC timezone
-        str.replace("+00:00", "Z")
-    }
+#[rocket::async_trait]
+impl<'r> request::FromRequest<'r> for RequestMeta {
+    type Error = std::convert::Infallible;
 
-    /// Serializes a `NaiveDateTime` into a string using RFC3339 format.
-    fn serializer<S>(ndt: &NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let s = ndt.to_rfc3339();
-        serializer.serialize_str(&s)
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<RequestMeta, Self::Error> {
+        request::Outcome::Success(RequestMeta {
+            ip: ClientIp::of(request).map(|ip| ip.to_string()),
+            user_agent: request.headers().get_one("User-Agent").map(str::to_owned),
+        })
     }
+}
 
-    /// deserializes a `NaiveDateTime` from a string in RFC3339 format.
-    /// The standard deserializer for NaiveDateTime does not support timezone
-    /// '+08:00' or 'Z' suffix for UTC timezone, use the DateTime parser instead
-    ///  and convert to NaiveDateTime
-    fn deserializer<'de, D>(deserializer: D) -> Result<NaiveDateTime, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let s: String = serde::Deserialize::deserialize(deserializer)?;
-        let dt = DateTime::parse_from_rfc3339(&s)
-            .map_err(|_| serde::de::Error::custom(format!("Invalid timestamp format: {}", s)))?;
-        Ok(dt.naive_utc())
-    }
+/// Whether sessions slide, from `SESSION_SLIDING` (default on): requests made with a session in the
+/// second half of its lifetime extend it to a full lifetime, so that clients in use stay signed in
+/// while abandoned sessions still expire.
+pub fn session_sliding() -> bool {
+    app_config().auth.session_sliding
+}
 
-    fn serializer_option<S>(ndt: &Option<NaiveDateTime>, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        match ndt {
-            Some(value) => {
-                let s = value.to_rfc3339();
-                serializer.serialize_some(&s)
-            }
-            None => serializer.serialize_none(),
-        }
-    }
+/// Whether `UserCtx` checks that the user of the cookie still exists and is not disabled, from
+/// `AUTH_USER_CHECK` (default on). Turning it off saves a query per request, at the cost of deleted
+/// and disabled users keeping access until their cookie expires.
+pub fn auth_user_check() -> bool {
+    app_config().auth.auth_user_check
+}
 
-    fn deserializer_option<'de, D>(deserializer: D) -> Result<Option<NaiveDateTime>, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let value: Option<String> = serde::Deserialize::deserialize(deserializer)?;
-        value
-            .map(|s| {
-                DateTime::parse_from_rfc3339(&s)
-                    .map(|dt| dt.naive_utc())
-                    .map_err(|_| serde::de::Error::custom(format!("Invalid timestamp format: {}", s)))
-            })
-            .transpose()
+/// The columns of the caller's `users` row that the auth guards look at.
+#[derive(Debug, Clone)]
+struct UserRecord {
+    disabled_at: Option<DateTime<Utc>>,
+    email_verified_at: Option<DateTime<Utc>>,
+}
+
+/// `UserRecord` lookup cached on the request. `Err` when the database could not be queried.
+struct UserRecordCache(Result<Option<UserRecord>, ()>);
+
+/// Loads the `users` row of `user_id` once per request, so that stacked guards (`UserCtx`,
+/// `VerifiedUserCtx`) share a single query and connection checkout instead of each taking a
+/// `Connection<Db>` from the pool.
+async fn user_record<'r>(request: &'r Request<'_>, user_id: i64) -> &'r Result<Option<UserRecord>, ()> {
+    let cache = request
+        .local_cache_async(async {
+            let Some(db) = Db::fetch(request.rocket()) else {
+                return UserRecordCache(Err(()));
+            };
+            let record = user_record_fetch(db, user_id)
+                .await
+                .map_err(|e| tracing::error!("auth:user-lookup-error: {}", e));
+            UserRecordCache(record)
+        })
+        .await;
+    &cache.0
+}
+
+async fn user_record_fetch(db: &sqlx::SqlitePool, user_id: i64) -> Result<Option<UserRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        UserRecord,
+        "SELECT disabled_at AS \"disabled_at: DateTime<Utc>\", \
+        email_verified_at AS \"email_verified_at: DateTime<Utc>\" FROM users WHERE id = ?",
+        user_id
+    )
+    .fetch_optional(db)
+    .instrument(query_span("users.auth"))
+    .await
+}
+
+/// Whether `UserCtx` would let the user in: unless `auth_user_check` is off, they were neither
+/// deleted nor disabled. For callers outside Rocket, e.g. gRPC.
+#[cfg(feature = "grpc")]
+pub(crate) async fn user_is_active(db: &sqlx::SqlitePool, user_id: i64) -> Result<bool, sqlx::Error> {
+    if !auth_user_check() {
+        return Ok(true);
     }
+    let record = user_record_fetch(db, user_id).await?;
+    Ok(record.is_some_and(|record| record.disabled_at.is_none()))
 }
 
-/// Represents the user context extracted from request cookies.
+/// User context for features that require a verified email address, such as making posts readable
+/// without signing in. Fails with 403 when the account has not confirmed its email yet.
 #[derive(Debug, serde::Serialize)]
 #[serde(crate = "rocket::serde")]
-pub struct UserCtx {
+pub struct VerifiedUserCtx {
     pub id: i64,
 }
 
-/// Extracts the user context from the request cookies for convenient access.
+#[rocket::async_trait]
+impl<'r> request::FromRequest<'r> for VerifiedUserCtx {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<VerifiedUserCtx, Self::Error> {
+        let user = match request.guard::<UserCtx>().await {
+            request::Outcome::Success(user) => user,
+            request::Outcome::Forward(status) => return request::Outcome::Forward(status),
+            request::Outcome::Error(error) => return request::Outcome::Error(error),
+        };
+
+        match user_record(request, user.id).await {
+            Ok(Some(record)) if record.email_verified_at.is_some() => {
+                request::Outcome::Success(VerifiedUserCtx { id: user.id })
+            }
+            Ok(_) => {
+                guard_error_set(
+                    request,
+                    ApiError::new(
+                        http::Status::Forbidden,
+                        ErrorCode::Forbidden,
+                        "This requires a verified email address",
+                    ),
+                );
+                request::Outcome::Error((http::Status::Forbidden, "email not verified"))
+            }
+            Err(()) => request::Outcome::Error((http::Status::ServiceUnavailable, "database unavailable")),
+        }
+    }
+}
+
+/// Extracts the user context from the request cookies for convenient access, or from a token within
+/// its scopes when there are none, see `Authz`. Unless `auth_user_check` is off, fails with 401 when
+/// the user was deleted or disabled since the cookie or token was issued.
 #[rocket::async_trait]
 impl<'r> request::FromRequest<'r> for UserCtx {
-    type Error = std::convert::Infallible;
+    type Error = &'static str;
 
     async fn from_request(request: &'r Request<'_>) -> request::Outcome<UserCtx, Self::Error> {
-        request
-            .cookies()
-            .get_private("user_id")
-            .and_then(|cookie| cookie.value().parse().ok())
-            .map(|id| UserCtx { id })
-            .or_forward(http::Status::Unauthorized)
+        let id = match request.guard::<Authz>().await {
+            request::Outcome::Success(authz) => authz.user_id,
+            request::Outcome::Forward(status) => return request::Outcome::Forward(status),
+            request::Outcome::Error(error) => return request::Outcome::Error(error),
+        };
+        RequestUser::record(request, id);
+
+        if auth_user_check() {
+            match user_record(request, id).await {
+                Ok(Some(record)) if record.disabled_at.is_none() => {}
+                Ok(Some(_)) => {
+                    guard_error_set(request, ApiError::account_disabled());
+                    return request::Outcome::Error((http::Status::Unauthorized, "account disabled"));
+                }
+                Ok(None) => return request::Outcome::Error((http::Status::Unauthorized, "account deleted")),
+                Err(()) => return request::Outcome::Error((http::Status::ServiceUnavailable, "database unavailable")),
+            }
+        }
+        if session_sliding() {
+            session_slide(request, id).await;
+        }
+        request::Outcome::Success(UserCtx { id })
     }
 }
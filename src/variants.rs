@@ -0,0 +1,247 @@
+use rocket::serde::json;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use crate::util::*;
+
+/// The post variants clients may use, from `POST_VARIANTS`, so that the variant names of the
+/// different clients don't drift apart. Either a comma-separated list of names, or a JSON object of
+/// names to the schema their content must match, `null` for free-form content, e.g.
+/// `{"note": null, "todo": {"type": "object", "required": ["done"], "properties": {"done": {"type": "boolean"}}}}`.
+///
+/// Unset, any variant is accepted with any content, as before the registry.
+#[derive(Debug, Clone, Default)]
+pub struct VariantRegistry {
+    variants: Option<BTreeMap<String, Option<ContentSchema>>>,
+}
+
+impl VariantRegistry {
+    /// Parses a `POST_VARIANTS` value.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(Self::default());
+        }
+        let variants = match value.starts_with(['{', '[']) {
+            true => {
+                let value = json::from_str::<json::Value>(value).map_err(|e| format!("not JSON: {}", e))?;
+                let object = value.as_object().ok_or("not an object of variants")?;
+                object
+                    .iter()
+                    .map(|(name, schema)| {
+                        let schema = match schema {
+                            json::Value::Null => None,
+                            schema => Some(ContentSchema::parse(schema).map_err(|e| format!("{}: {}", name, e))?),
+                        };
+                        Ok((name.clone(), schema))
+                    })
+                    .collect::<Result<BTreeMap<_, _>, String>>()?
+            }
+            false => value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| (name.to_string(), None))
+                .collect(),
+        };
+        Ok(Self {
+            variants: Some(variants),
+        })
+    }
+
+    pub fn from_env() -> Self {
+        let value = config_var("POST_VARIANTS").unwrap_or_default();
+        Self::parse(&value).unwrap_or_else(|e| panic!("POST_VARIANTS has an invalid value: {}", e))
+    }
+
+    /// Whether the content of some variant has a schema, so that updates need the variant of the
+    /// post to be checked.
+    pub fn checks_content(&self) -> bool {
+        self.variants.iter().flat_map(BTreeMap::values).any(Option::is_some)
+    }
+
+    /// Checks that `variant` is allowed and that `content` matches its schema.
+    pub fn check(&self, variant: &str, content: &str) -> Result<(), String> {
+        let Some(variants) = &self.variants else {
+            return Ok(());
+        };
+        let Some(schema) = variants.get(variant) else {
+            let names = variants.keys().map(String::as_str).collect::<Vec<_>>();
+            return Err(format!(
+                "Unknown variant {:?}, expected one of: {}",
+                variant,
+                names.join(", ")
+            ));
+        };
+        let Some(schema) = schema else {
+            return Ok(());
+        };
+        let content = json::from_str::<json::Value>(content)
+            .map_err(|e| format!("Content of a {} post must be JSON: {}", variant, e))?;
+        schema
+            .check(&content, "content")
+            .map_err(|e| format!("Content of a {} post is invalid: {}", variant, e))
+    }
+}
+
+/// Returns the process-wide `VariantRegistry`.
+pub fn variants() -> &'static VariantRegistry {
+    static REGISTRY: OnceLock<VariantRegistry> = OnceLock::new();
+    REGISTRY.get_or_init(VariantRegistry::from_env)
+}
+
+/// The subset of JSON Schema that post contents are checked against: `type` (a name or a list of
+/// them), `enum`, `required`, `properties`, `additionalProperties: false`, `items`, `minLength` and
+/// `maxLength`. Other keywords are refused rather than ignored, so that a schema never checks less
+/// than it says.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentSchema(json::Value);
+
+const SCHEMA_KEYWORDS: &[&str] = &[
+    "type",
+    "enum",
+    "required",
+    "properties",
+    "additionalProperties",
+    "items",
+    "minLength",
+    "maxLength",
+    "title",
+    "description",
+];
+const SCHEMA_TYPES: &[&str] = &["object", "array", "string", "number", "integer", "boolean", "null"];
+
+impl ContentSchema {
+    pub fn parse(schema: &json::Value) -> Result<Self, String> {
+        schema_validate(schema)?;
+        Ok(Self(schema.clone()))
+    }
+
+    /// Checks `value`, naming what fails by its `path`, e.g. `content.done`.
+    pub fn check(&self, value: &json::Value, path: &str) -> Result<(), String> {
+        schema_check(&self.0, value, path)
+    }
+}
+
+fn schema_validate(schema: &json::Value) -> Result<(), String> {
+    let schema = schema.as_object().ok_or("a schema must be an object")?;
+    if let Some(keyword) = schema
+        .keys()
+        .find(|keyword| !SCHEMA_KEYWORDS.contains(&keyword.as_str()))
+    {
+        return Err(format!("unsupported schema keyword {}", keyword));
+    }
+    if let Some(types) = schema.get("type") {
+        let types = match types {
+            json::Value::Array(types) => types.iter().collect(),
+            single => vec![single],
+        };
+        for name in types {
+            if !name.as_str().is_some_and(|name| SCHEMA_TYPES.contains(&name)) {
+                return Err(format!("unknown type {}", name));
+            }
+        }
+    }
+    if schema.get("enum").is_some_and(|values| !values.is_array()) {
+        return Err("enum must be an array".into());
+    }
+    let required = schema.get("required").map(json::Value::as_array);
+    if required.is_some_and(|array| !array.is_some_and(|names| names.iter().all(json::Value::is_string))) {
+        return Err("required must be an array of names".into());
+    }
+    if let Some(properties) = schema.get("properties") {
+        let properties = properties.as_object().ok_or("properties must be an object")?;
+        for (name, property) in properties {
+            schema_validate(property).map_err(|e| format!("{}: {}", name, e))?;
+        }
+    }
+    if schema
+        .get("additionalProperties")
+        .is_some_and(|additional| !additional.is_boolean())
+    {
+        return Err("additionalProperties must be a boolean".into());
+    }
+    if let Some(items) = schema.get("items") {
+        schema_validate(items).map_err(|e| format!("items: {}", e))?;
+    }
+    for bound in ["minLength", "maxLength"] {
+        if schema.get(bound).is_some_and(|length| !length.is_u64()) {
+            return Err(format!("{} must be a non-negative integer", bound));
+        }
+    }
+    Ok(())
+}
+
+fn type_matches(name: &str, value: &json::Value) -> bool {
+    match name {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => false,
+    }
+}
+
+fn schema_check(schema: &json::Value, value: &json::Value, path: &str) -> Result<(), String> {
+    if let Some(types) = schema.get("type") {
+        let types = match types {
+            json::Value::Array(types) => types.iter().filter_map(json::Value::as_str).collect(),
+            single => single.as_str().into_iter().collect::<Vec<_>>(),
+        };
+        if !types.iter().any(|name| type_matches(name, value)) {
+            return Err(format!("{} must be of type {}", path, types.join(" or ")));
+        }
+    }
+    let values = schema.get("enum").and_then(json::Value::as_array);
+    if let Some(values) = values.filter(|values| !values.contains(value)) {
+        return Err(format!(
+            "{} must be one of {}",
+            path,
+            json::Value::Array(values.clone())
+        ));
+    }
+    if let Some(text) = value.as_str() {
+        let length = text.chars().count() as u64;
+        if schema
+            .get("minLength")
+            .and_then(json::Value::as_u64)
+            .is_some_and(|min| length < min)
+        {
+            return Err(format!("{} is too short", path));
+        }
+        if schema
+            .get("maxLength")
+            .and_then(json::Value::as_u64)
+            .is_some_and(|max| length > max)
+        {
+            return Err(format!("{} is too long", path));
+        }
+    }
+    if let Some(object) = value.as_object() {
+        let required = schema.get("required").and_then(json::Value::as_array);
+        for name in required.into_iter().flatten().filter_map(json::Value::as_str) {
+            if !object.contains_key(name) {
+                return Err(format!("{}.{} is required", path, name));
+            }
+        }
+        let properties = schema.get("properties").and_then(json::Value::as_object);
+        for (name, field) in object {
+            match properties.and_then(|properties| properties.get(name)) {
+                Some(property) => schema_check(property, field, &format!("{}.{}", path, name))?,
+                None if schema.get("additionalProperties") == Some(&json::Value::Bool(false)) => {
+                    return Err(format!("{}.{} is not allowed", path, name));
+                }
+                None => {}
+            }
+        }
+    }
+    if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+        for (index, item) in array.iter().enumerate() {
+            schema_check(items, item, &format!("{}[{}]", path, index))?;
+        }
+    }
+    Ok(())
+}